@@ -438,6 +438,7 @@ class MessageRole(StrEnum):
     """
 
     System = "System"
+    Developer = "Developer"
     User = "User"
     Model = "Model"
 
@@ -1089,6 +1090,9 @@ class Suggestion(Entity):
     execution_ended: Timestamp | None = None
     """The timestamp when the generation ended."""
 
+    model_invocation: ModelInvocation | None = None
+    """The invocation of the model which generated the suggestion."""
+
     feedback: str | None = None
     """Feedback on the suggestion"""
 
@@ -2169,6 +2173,30 @@ class InstructionAttachment(Entity):
     file: File
     """The file to attach."""
 
+    description: str | None = None
+    """A description of the attachment, provided by the user, to help the model understand it."""
+
+    pages: str | None = None
+    """The pages of a PDF attachment to include e.g. `3-5,9`."""
+
+    byte_range: str | None = None
+    """The range of bytes of the attachment to include e.g. `0-4095`."""
+
+    sheet: str | None = None
+    """The sheet of a spreadsheet attachment to include, by name or number."""
+
+    checksum: str | None = None
+    """The SHA-256 hash of the content of the attachment provided to the model, hex encoded."""
+
+    size_bytes: UnsignedInteger | None = None
+    """The size, in bytes, of the content of the attachment provided to the model."""
+
+    source_url: str | None = None
+    """The URL that the file of the attachment was retrieved from."""
+
+    generated_by: str | None = None
+    """The workflow, or step of a workflow, which generated the file of the attachment."""
+
 
 @dataclass(kw_only=True, repr=False)
 class InstructionBlock(Instruction):
@@ -2214,6 +2242,9 @@ class InstructionMessage(Entity):
     role: MessageRole | None = None
     """The role of the message in the conversation."""
 
+    name: str | None = None
+    """The name of the participant who authored the message."""
+
     parts: list[MessagePart]
     """Parts of the message."""
 
@@ -2353,6 +2384,51 @@ class MathInline(Math):
     type: Literal["MathInline"] = "MathInline"
 
 
+@dataclass(kw_only=True, repr=False)
+class ModelInvocation(Entity):
+    """
+    A record of the invocation of a generative model to create content.
+    """
+
+    type: Literal["ModelInvocation"] = "ModelInvocation"
+
+    model_id: str
+    """The id of the model that was invoked e.g. `openai/gpt-4o`."""
+
+    model_version: str | None = None
+    """The version of the model reported by the provider e.g. `gpt-4o-2024-08-06`."""
+
+    provider: str | None = None
+    """The provider of the model e.g. `OpenAI`."""
+
+    model_parameters: ModelParameters | None = None
+    """The parameters the model was invoked with."""
+
+    prompt_tokens: UnsignedInteger | None = None
+    """The number of tokens in the prompt sent to the model."""
+
+    output_tokens: UnsignedInteger | None = None
+    """The number of tokens in the output generated by the model."""
+
+    cached_tokens: UnsignedInteger | None = None
+    """The number of prompt tokens read from the provider's prompt cache."""
+
+    cost: float | None = None
+    """The estimated cost of the invocation in US dollars."""
+
+    start_time: Timestamp | None = None
+    """The time the invocation started."""
+
+    end_time: Timestamp | None = None
+    """The time the invocation ended."""
+
+    task_fingerprint: str | None = None
+    """The fingerprint of the task the model was invoked for."""
+
+    output_hash: str | None = None
+    """The SHA-256 hash of the content generated by the model."""
+
+
 @dataclass(kw_only=True, repr=False)
 class ModelParameters(Entity):
     """
@@ -3223,6 +3299,24 @@ class TimestampValidator(Entity):
     """The inclusive upper limit for a timestamp."""
 
 
+@dataclass(kw_only=True, repr=False)
+class ToolResult(Entity):
+    """
+    The result of a call to a tool made by a model.
+    """
+
+    type: Literal["ToolResult"] = "ToolResult"
+
+    call_id: str
+    """The identifier of the tool call that this is the result of."""
+
+    name: str | None = None
+    """The name of the tool that was called."""
+
+    content: str
+    """The content of the result."""
+
+
 @dataclass(kw_only=True, repr=False)
 class TupleValidator(Entity):
     """
@@ -3514,6 +3608,8 @@ MessagePart = Union[
     ImageObject,
     AudioObject,
     VideoObject,
+    ToolResult,
+    File,
 ]
 """
 A union type for a part of a message.
@@ -3603,6 +3699,7 @@ Node = Union[
     MathBlock,
     MathInline,
     MediaObject,
+    ModelInvocation,
     ModelParameters,
     MonetaryGrant,
     Note,
@@ -3650,6 +3747,7 @@ Node = Union[
     TimeValidator,
     Timestamp,
     TimestampValidator,
+    ToolResult,
     TupleValidator,
     Underline,
     Unknown,
@@ -3813,6 +3911,7 @@ TYPES = [
     ListItem,
     MathBlock,
     MathInline,
+    ModelInvocation,
     ModelParameters,
     MonetaryGrant,
     Note,
@@ -3857,6 +3956,7 @@ TYPES = [
     TimeValidator,
     Timestamp,
     TimestampValidator,
+    ToolResult,
     TupleValidator,
     Underline,
     Unknown,
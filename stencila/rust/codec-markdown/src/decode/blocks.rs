@@ -783,6 +783,7 @@ fn chat_message(input: &mut Located<&str>) -> ModalResult<Block> {
             Caseless("msg/"),
             alt((
                 Caseless("system"),
+                Caseless("developer"),
                 Caseless("user"),
                 Caseless("model"),
                 Caseless("group"),
@@ -1,6 +1,6 @@
 //! Parsing functions shared by `inlines.rs` and `blocks.rs`
 
-use std::str::FromStr;
+use std::{path::Path, str::FromStr};
 
 use markdown::mdast;
 use winnow::{
@@ -14,9 +14,13 @@ use winnow::{
     token::{none_of, take_until, take_while},
 };
 
-use codec::schema::{
-    Date, DateTime, Duration, ExecutionBounds, ExecutionMode, ImageObject, InstructionMessage,
-    InstructionType, MessagePart, ModelParameters, Node, RelativePosition, Time, Timestamp,
+use codec::{
+    format::Format,
+    schema::{
+        Date, DateTime, Duration, ExecutionBounds, ExecutionMode, File, ImageObject,
+        InstructionMessage, InstructionType, MessagePart, ModelParameters, Node, RelativePosition,
+        Time, Timestamp,
+    },
 };
 use codec_json5_trait::Json5Codec;
 use codec_text_trait::TextCodec;
@@ -408,8 +412,8 @@ pub fn node_to_option_duration(node: Node) -> Option<Duration> {
 
 /// Parse a string into an [`InstructionMessage`]
 ///
-/// Parses the string as Markdown and splits images into separate
-/// message parts.
+/// Parses the string as Markdown and splits images, and other files
+/// (e.g. `![](data.csv)`), into separate message parts.
 pub fn string_to_instruction_message(md: &str) -> InstructionMessage {
     use markdown::{ParseOptions, to_mdast};
     use mdast::Node;
@@ -432,6 +436,19 @@ pub fn string_to_instruction_message(md: &str) -> InstructionMessage {
                 if !text.is_empty() {
                     parts.push(MessagePart::from(text.drain(..)))
                 }
+
+                // Images of files with known, non-media, formats (e.g. `![](data.csv)`) are files
+                if is_file(&image.url) {
+                    let name = image
+                        .url
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or(&image.url)
+                        .to_string();
+                    parts.push(MessagePart::File(File::new(name, image.url)));
+                    continue;
+                }
+
                 let content_url = if image.url.starts_with("file://")
                     || image.url.starts_with("https://")
                     || image.url.starts_with("http://")
@@ -465,6 +482,22 @@ pub fn string_to_instruction_message(md: &str) -> InstructionMessage {
     }
 }
 
+/// Whether a URL is of a file with a known format that is neither an image, audio nor video
+///
+/// Returns `false` for URLs without an extension, or with an unknown one, so that
+/// these continue to be treated as images.
+fn is_file(url: &str) -> bool {
+    if url.starts_with("data:") || Path::new(url).extension().is_none() {
+        return false;
+    }
+
+    let format = Format::from_url(url);
+    !(matches!(format, Format::Other(..) | Format::Unknown)
+        || format.is_image()
+        || format.is_audio()
+        || format.is_video())
+}
+
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
@@ -624,6 +657,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_string_to_instruction_message() {
+        let message = string_to_instruction_message(
+            "Compare ![](plot.png) with ![](transects.csv) and ![](https://example.org/map)",
+        );
+        let kinds = message
+            .parts
+            .iter()
+            .map(|part| match part {
+                MessagePart::Text(..) => "text",
+                MessagePart::ImageObject(..) => "image",
+                MessagePart::File(..) => "file",
+                _ => "other",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec!["text", "image", "text", "file", "text", "image"]
+        );
+
+        let Some(MessagePart::File(file)) = message.parts.get(3) else {
+            unreachable!()
+        };
+        assert_eq!(file.name, "transects.csv");
+        assert_eq!(file.path, "transects.csv");
+    }
+
     #[test]
     fn test_take_until_unbalanced() {
         assert_eq!(
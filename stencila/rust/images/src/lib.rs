@@ -261,10 +261,69 @@ pub fn convert(from: &Path, to: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Assemble images into a contact sheet
+///
+/// Each image is scaled to fit within a square cell of `cell_size` pixels and the
+/// cells are arranged in a grid with as many columns as rows (or one more), separated
+/// by a white gutter. Returns the PNG encoded bytes of the contact sheet.
+pub fn contact_sheet(images: &[Vec<u8>], cell_size: u32) -> Result<Vec<u8>> {
+    if images.is_empty() {
+        bail!("No images to assemble into a contact sheet")
+    }
+
+    let gutter = (cell_size / 32).max(1);
+    let columns = (images.len() as f64).sqrt().ceil() as u32;
+    let rows = (images.len() as u32).div_ceil(columns);
+
+    let width = columns * cell_size + (columns + 1) * gutter;
+    let height = rows * cell_size + (rows + 1) * gutter;
+    let mut sheet = ImageBuffer::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+
+    for (index, bytes) in images.iter().enumerate() {
+        let image = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .decode()?
+            .thumbnail(cell_size, cell_size);
+
+        let index = index as u32;
+        let (column, row) = (index % columns, index / columns);
+        let x = gutter + column * (cell_size + gutter) + (cell_size - image.width()) / 2;
+        let y = gutter + row * (cell_size + gutter) + (cell_size - image.height()) / 2;
+        sheet.copy_from(&image.to_rgba8(), x, y)?;
+    }
+
+    let mut bytes = Vec::new();
+    sheet.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_contact_sheet() -> Result<()> {
+        let image = |color: Rgba<u8>| -> Result<Vec<u8>> {
+            let mut bytes = Vec::new();
+            ImageBuffer::from_pixel(40, 20, color)
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+            Ok(bytes)
+        };
+        let images = [
+            image(Rgba([255, 0, 0, 255]))?,
+            image(Rgba([0, 255, 0, 255]))?,
+            image(Rgba([0, 0, 255, 255]))?,
+        ];
+
+        let sheet = image::load_from_memory(&contact_sheet(&images, 32)?)?;
+        assert_eq!(sheet.dimensions(), (2 * 32 + 3, 2 * 32 + 3));
+        assert_eq!(sheet.get_pixel(1 + 16, 1 + 16), Rgba([255, 0, 0, 255]));
+        assert_eq!(sheet.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
+
+        Ok(())
+    }
+
     #[test]
     fn test_img_srcs_transform() {
         let html = r#"
@@ -0,0 +1,151 @@
+use common::serde::{Deserialize, Serialize};
+
+use crate::{CatalogConfig, ModelTask};
+
+/// A named alias for a model, with default parameters
+///
+/// Aliases (e.g. `fast`, `accurate`, `vision`, `cheap`) are defined in the
+/// `[catalog.aliases]` table of the models config and can be used anywhere a model id
+/// is accepted so that documents can reference a stable intent rather than a
+/// version string:
+///
+/// ```toml
+/// [catalog.aliases.accurate]
+/// model = "openai/gpt-4.1"
+/// temperature = 0
+///
+/// [catalog.aliases.cheap]
+/// model = "openai/gpt-4o-mini"
+/// max-tokens = 1000
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub struct ModelAlias {
+    /// The id of the model the alias resolves to
+    pub model: String,
+
+    /// The temperature used for tasks using the alias which do not specify one
+    pub temperature: Option<f32>,
+
+    /// The maximum number of tokens used for tasks using the alias which do not specify one
+    pub max_tokens: Option<u16>,
+
+    /// A description of the intent of the alias
+    pub description: Option<String>,
+}
+
+impl CatalogConfig {
+    /// Resolve a model id which may be an alias
+    ///
+    /// Returns the id unchanged if it is not an alias.
+    pub fn resolve_model_id(&self, id: &str) -> String {
+        self.aliases
+            .get(id)
+            .map(|alias| alias.model.clone())
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    /// Resolve aliases in the model ids of a task
+    ///
+    /// Resolves the `model_id` and `model_parameters.model_ids` of the task and, for the
+    /// first alias resolved, applies its default parameters to the task if not already set.
+    /// Returns the name of that alias, if any.
+    pub fn apply_aliases(&self, task: &mut ModelTask) -> Option<String> {
+        if self.aliases.is_empty() {
+            return None;
+        }
+
+        let mut applied: Option<String> = None;
+        let mut resolve = |id: &mut String| {
+            if let Some(alias) = self.aliases.get(id.as_str()) {
+                applied.get_or_insert_with(|| id.clone());
+                *id = alias.model.clone();
+            }
+        };
+
+        if let Some(id) = task.model_id.as_mut() {
+            resolve(id);
+        }
+        if let Some(ids) = task
+            .model_parameters
+            .as_mut()
+            .and_then(|pars| pars.model_ids.as_mut())
+        {
+            for id in ids {
+                resolve(id);
+            }
+        }
+        if let Some(compression) = task.compression.as_mut() {
+            resolve(&mut compression.model);
+        }
+        if let Some(memory) = task.memory.as_mut() {
+            resolve(&mut memory.model);
+        }
+
+        let name = applied?;
+        if let Some(alias) = self.aliases.get(&name) {
+            if task.temperature.is_none() {
+                task.temperature = alias.temperature;
+            }
+            if task.max_tokens.is_none() {
+                task.max_tokens = alias.max_tokens;
+            }
+        }
+
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::Result, toml};
+    use schema::ModelParameters;
+
+    use crate::ModelsConfig;
+
+    use super::*;
+
+    #[test]
+    fn resolves_and_applies() -> Result<()> {
+        let config: ModelsConfig = toml::from_str(
+            r#"
+[catalog.aliases.accurate]
+model = "openai/gpt-4.1"
+temperature = 0
+
+[catalog.aliases.cheap]
+model = "openai/gpt-4o-mini"
+max-tokens = 1000
+"#,
+        )?;
+        let config = config.catalog;
+
+        assert_eq!(config.resolve_model_id("cheap"), "openai/gpt-4o-mini");
+        assert_eq!(config.resolve_model_id("openai/o3"), "openai/o3");
+
+        let mut task = ModelTask {
+            model_parameters: Some(ModelParameters {
+                model_ids: Some(vec!["accurate".into(), "anthropic".into()]),
+                ..Default::default()
+            }),
+            max_tokens: Some(200),
+            ..Default::default()
+        };
+        assert_eq!(config.apply_aliases(&mut task).as_deref(), Some("accurate"));
+        assert_eq!(
+            task.model_parameters.and_then(|pars| pars.model_ids),
+            Some(vec!["openai/gpt-4.1".into(), "anthropic".into()])
+        );
+        assert_eq!(task.temperature, Some(0.0));
+        assert_eq!(task.max_tokens, Some(200));
+
+        let mut task = ModelTask {
+            model_id: Some("openai/o3".into()),
+            ..Default::default()
+        };
+        assert_eq!(config.apply_aliases(&mut task), None);
+        assert_eq!(task.model_id.as_deref(), Some("openai/o3"));
+
+        Ok(())
+    }
+}
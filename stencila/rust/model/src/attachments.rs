@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, create_dir_all, metadata, read_dir},
+    io::{BufReader, Cursor, Read, Seek, copy},
+    path::{Component, Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::{Result, bail},
+    glob::{Pattern, glob},
+    serde::{Deserialize, Serialize},
+    tar::Archive,
+    tempfile::tempdir,
+};
+use flate2::read::GzDecoder;
+use format::Format;
+use schema::{File, InstructionAttachment};
+use zip::ZipArchive;
+
+use crate::models_config;
+
+/// Limits on the files that an attachment pointing at a directory or glob expands into
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct AttachmentExpansion {
+    /// The maximum number of files an attachment may expand into
+    pub max_files: usize,
+
+    /// The maximum total size, in bytes, of the files an attachment may expand into
+    pub max_total_bytes: u64,
+
+    /// Glob patterns for the members of archives to attach
+    ///
+    /// Matched against the path of each member within the archive e.g. `outputs/*.csv`.
+    /// If empty, all members are attached.
+    pub include: Vec<String>,
+
+    /// Glob patterns for the members of archives not to attach
+    ///
+    /// Applied after `include`.
+    pub exclude: Vec<String>,
+}
+
+impl Default for AttachmentExpansion {
+    fn default() -> Self {
+        Self {
+            max_files: 20,
+            max_total_bytes: 20 * 1024 * 1024,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl AttachmentExpansion {
+    /// Whether a member of an archive should be attached
+    fn selects(&self, member: &Path) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|pattern| {
+                Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path(member))
+            })
+        };
+
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+/// The kind of an archive attachment
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Determine the kind of archive from the media type or name of a file
+    fn of(file: &File) -> Option<Self> {
+        match file.media_type.as_deref() {
+            Some("application/zip" | "application/x-zip-compressed") => return Some(Self::Zip),
+            Some("application/x-tar") => return Some(Self::Tar),
+            Some("application/gzip" | "application/x-gzip")
+                if file.path.ends_with(".tar.gz") || file.name.ends_with(".tar.gz") =>
+            {
+                return Some(Self::TarGz);
+            }
+            _ => {}
+        }
+
+        let name = file.path.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a path is a glob pattern
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Expand an attachment pointing at a directory, glob or archive into attachments for each file
+///
+/// Returns `None` if the attachment points at a single file (or has content) and so
+/// does not need expanding. Relative paths are resolved against `base`. Hidden files,
+/// and subdirectories, are ignored. Files are sorted by path and aliased using the
+/// alias of the attachment and the file's stem (e.g. `figures-transect-1`) or, if the
+/// alias is itself a pattern (e.g. when no alias was given for `outputs/*.png`), by file
+/// name. Errors if the files exceed the limits in the `[attachments.expansion]` table
+/// of the models config.
+///
+/// Attachments which are zip or tar archives are expanded into their members
+/// using [`expand_archive`].
+pub fn expand_attachment(
+    attachment: &InstructionAttachment,
+    base: Option<&Path>,
+) -> Result<Option<Vec<InstructionAttachment>>> {
+    let limits = &models_config().attachments.expansion;
+    if ArchiveKind::of(&attachment.file).is_some() {
+        expand_archive(attachment, base, limits)
+    } else {
+        expand_with(attachment, base, limits)
+    }
+}
+
+fn expand_with(
+    attachment: &InstructionAttachment,
+    base: Option<&Path>,
+    limits: &AttachmentExpansion,
+) -> Result<Option<Vec<InstructionAttachment>>> {
+    if attachment.file.content.is_some() {
+        return Ok(None);
+    }
+
+    let path = attachment.file.path.trim().trim_start_matches("file://");
+    if path.is_empty() || path.starts_with("http://") || path.starts_with("https://") {
+        return Ok(None);
+    }
+
+    let resolved = match base {
+        Some(base) if !Path::new(path).is_absolute() => base.join(path),
+        _ => PathBuf::from(path),
+    };
+
+    let mut paths: Vec<PathBuf> = if is_pattern(path) {
+        glob(&resolved.to_string_lossy())?
+            .flatten()
+            .filter(|path| path.is_file())
+            .collect()
+    } else if resolved.is_dir() {
+        read_dir(&resolved)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect()
+    } else {
+        return Ok(None);
+    };
+    paths.retain(|path| {
+        !path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'))
+    });
+    paths.sort();
+
+    if paths.is_empty() {
+        bail!(
+            "Attachment `{}` at `{path}` does not match any files",
+            attachment.alias
+        );
+    }
+    if paths.len() > limits.max_files {
+        bail!(
+            "Attachment `{}` at `{path}` matches {} files which exceeds the limit of {}",
+            attachment.alias,
+            paths.len(),
+            limits.max_files
+        );
+    }
+
+    let total: u64 = paths
+        .iter()
+        .filter_map(|path| metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if total > limits.max_total_bytes {
+        bail!(
+            "Attachment `{}` at `{path}` matches files totalling {total} bytes which exceeds the limit of {} bytes",
+            attachment.alias,
+            limits.max_total_bytes
+        );
+    }
+
+    let mut aliases = HashSet::new();
+    let attachments = paths
+        .into_iter()
+        .map(|path| {
+            let (alias, name) = member_alias(&attachment.alias, &path, &mut aliases);
+
+            let mut file = File::new(name, path.to_string_lossy().to_string());
+            file.media_type = attachment.file.media_type.clone();
+
+            InstructionAttachment::new(alias, file)
+        })
+        .collect();
+
+    Ok(Some(attachments))
+}
+
+/// Create a unique alias, and get the file name, for a file expanded from an attachment
+fn member_alias(alias: &str, path: &Path, aliases: &mut HashSet<String>) -> (String, String) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| name.clone());
+
+    let alias = if alias.is_empty() || is_pattern(alias) {
+        name.clone()
+    } else {
+        format!("{alias}-{stem}")
+    };
+    let mut unique = alias.clone();
+    let mut index = 1;
+    while !aliases.insert(unique.clone()) {
+        index += 1;
+        unique = format!("{alias}-{index}");
+    }
+
+    (unique, name)
+}
+
+/// The content of an archive, in memory or on disk
+trait ArchiveSource: Read + Seek {}
+
+impl<T: Read + Seek> ArchiveSource for T {}
+
+/// Expand a zip or tar archive attachment into attachments for each selected member
+///
+/// Selected members are unpacked into a temporary directory, which is removed
+/// before returning, and their content embedded in the returned attachments.
+/// Members with paths that are absolute, or which would escape the directory
+/// (e.g. `../../.bashrc`), are skipped, as are links, directories and hidden files.
+/// The `max_files` and `max_total_bytes` limits apply to the selected members
+/// (using their uncompressed size) and are checked before anything is unpacked.
+/// Errors if no members are selected.
+pub fn expand_archive(
+    attachment: &InstructionAttachment,
+    base: Option<&Path>,
+    limits: &AttachmentExpansion,
+) -> Result<Option<Vec<InstructionAttachment>>> {
+    let Some(kind) = ArchiveKind::of(&attachment.file) else {
+        return Ok(None);
+    };
+
+    // Archives on disk are read as they are expanded, rather than all at once,
+    // so that only the members that are selected (and checked) are loaded
+    let source: Box<dyn ArchiveSource + '_> = match &attachment.file.content {
+        Some(content) if attachment.file.options.transfer_encoding.as_deref() == Some("base64") => {
+            Box::new(Cursor::new(BASE64.decode(content.trim())?))
+        }
+        Some(content) => Box::new(Cursor::new(content.as_bytes())),
+        None => {
+            let path = attachment.file.path.trim().trim_start_matches("file://");
+            let resolved = match base {
+                Some(base) if !Path::new(path).is_absolute() => base.join(path),
+                _ => PathBuf::from(path),
+            };
+            Box::new(BufReader::new(fs::File::open(resolved)?))
+        }
+    };
+
+    // Leading `./` components (e.g. from `tar -cf run.tar .`) are removed before
+    // members are checked, selected and unpacked
+    let normalize = |member: &Path| -> PathBuf {
+        member
+            .components()
+            .skip_while(|component| matches!(component, Component::CurDir))
+            .collect()
+    };
+    let is_safe = |member: &Path| {
+        member.components().next().is_some()
+            && member
+                .components()
+                .all(|component| matches!(component, Component::Normal(..)))
+            && !member
+                .components()
+                .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+    };
+
+    // Members which appear more than once (e.g. in appended tar archives) are
+    // attached once, with the content and size of the last
+    let dir = tempdir()?;
+    let mut members: Vec<PathBuf> = Vec::new();
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let mut total = 0u64;
+    let mut check = |member: &Path, size: u64| -> Result<()> {
+        match sizes.insert(member.to_path_buf(), size) {
+            Some(previous) => total -= previous,
+            None => members.push(member.to_path_buf()),
+        }
+        total += size;
+        if members.len() > limits.max_files {
+            bail!(
+                "Archive attachment `{}` has more than {} selected members",
+                attachment.alias,
+                limits.max_files
+            );
+        }
+        if total > limits.max_total_bytes {
+            bail!(
+                "Archive attachment `{}` has selected members totalling more than {} bytes",
+                attachment.alias,
+                limits.max_total_bytes
+            );
+        }
+        Ok(())
+    };
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(source)?;
+
+            let mut selected = Vec::new();
+            for index in 0..archive.len() {
+                let file = archive.by_index(index)?;
+                let Some(member) = file.enclosed_name().map(|member| normalize(&member)) else {
+                    continue;
+                };
+                if file.is_file() && is_safe(&member) && limits.selects(&member) {
+                    check(&member, file.size())?;
+                    selected.push((index, member, file.size()));
+                }
+            }
+
+            // The declared sizes, which were checked against the limits, are not
+            // trusted when decompressing: a member larger than declared is an error
+            for (index, member, size) in selected {
+                let dest = dir.path().join(&member);
+                if let Some(parent) = dest.parent() {
+                    create_dir_all(parent)?;
+                }
+                let mut reader = archive.by_index(index)?.take(size.saturating_add(1));
+                if copy(&mut reader, &mut fs::File::create(dest)?)? > size {
+                    bail!(
+                        "Member `{}` of archive attachment `{}` is larger than its declared size",
+                        member.display(),
+                        attachment.alias
+                    );
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let reader: Box<dyn Read + '_> = match kind {
+                ArchiveKind::TarGz => Box::new(GzDecoder::new(source)),
+                _ => Box::new(source),
+            };
+
+            // Entries are streamed so each is checked, and then unpacked, in turn
+            let mut archive = Archive::new(reader);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let member = normalize(&entry.path()?);
+                if entry.header().entry_type().is_file()
+                    && is_safe(&member)
+                    && limits.selects(&member)
+                {
+                    check(&member, entry.size())?;
+                    entry.unpack_in(dir.path())?;
+                }
+            }
+        }
+    }
+
+    if members.is_empty() {
+        bail!(
+            "Archive attachment `{}` does not have any members to attach",
+            attachment.alias
+        );
+    }
+
+    let mut aliases = HashSet::new();
+    let mut attachments = Vec::new();
+    for member in members {
+        let data = fs::read(dir.path().join(&member))?;
+        let (alias, name) = member_alias(&attachment.alias, &member, &mut aliases);
+
+        let mut file = File::new(
+            name,
+            format!("{}/{}", attachment.file.path, member.to_string_lossy()),
+        );
+        file.media_type = Some(Format::from_path(&member).media_type());
+        file.size = Some(data.len() as u64);
+        match String::from_utf8(data) {
+            Ok(text) => file.content = Some(text),
+            Err(error) => {
+                file.content = Some(BASE64.encode(error.into_bytes()));
+                file.options.transfer_encoding = Some("base64".into());
+            }
+        }
+
+        attachments.push(InstructionAttachment::new(alias, file));
+    }
+
+    Ok(Some(attachments))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    fn attachment(alias: &str, path: &str) -> InstructionAttachment {
+        InstructionAttachment::new(alias.into(), File::new(String::new(), path.into()))
+    }
+
+    fn aliases(attachments: Option<Vec<InstructionAttachment>>) -> Vec<String> {
+        attachments
+            .unwrap_or_default()
+            .into_iter()
+            .map(|attachment| attachment.alias)
+            .collect()
+    }
+
+    #[test]
+    fn expands_directories_and_globs() -> Result<()> {
+        let dir = tempdir()?;
+        let outputs = dir.path().join("outputs");
+        create_dir(&outputs)?;
+        write(outputs.join("transect-2.png"), "png")?;
+        write(outputs.join("transect-1.png"), "png")?;
+        write(outputs.join("summary.csv"), "a,b")?;
+        write(outputs.join(".hidden.png"), "png")?;
+
+        let limits = AttachmentExpansion::default();
+        let base = Some(dir.path());
+
+        assert_eq!(
+            aliases(expand_with(
+                &attachment("*.png", "outputs/*.png"),
+                base,
+                &limits
+            )?),
+            vec!["transect-1.png", "transect-2.png"]
+        );
+        assert_eq!(
+            aliases(expand_with(
+                &attachment("figures", "outputs"),
+                base,
+                &limits
+            )?),
+            vec![
+                "figures-summary",
+                "figures-transect-1",
+                "figures-transect-2"
+            ]
+        );
+        assert!(expand_with(&attachment("csv", "outputs/summary.csv"), base, &limits)?.is_none());
+
+        let limits = AttachmentExpansion {
+            max_files: 1,
+            ..Default::default()
+        };
+        assert!(expand_with(&attachment("figures", "outputs"), base, &limits).is_err());
+
+        let limits = AttachmentExpansion {
+            max_total_bytes: 4,
+            ..Default::default()
+        };
+        assert!(expand_with(&attachment("figures", "outputs"), base, &limits).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn expands_archives() -> Result<()> {
+        let dir = tempdir()?;
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in [
+            ("run/outputs/transects.csv", b"id,rate\n1,-0.5".as_slice()),
+            ("run/outputs/map.png", &[0x89, 0x50, 0x4e, 0x47, 0xff]),
+            ("run/.cache/state", b"x"),
+            ("run/log.txt", b"done"),
+            ("../escape.txt", b"oops"),
+        ] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())?;
+            std::io::Write::write_all(&mut zip, content)?;
+        }
+        write(dir.path().join("run.zip"), zip.finish()?.into_inner())?;
+
+        let limits = AttachmentExpansion {
+            exclude: vec!["*.txt".into()],
+            ..Default::default()
+        };
+        let attachments = expand_archive(&attachment("run", "run.zip"), Some(dir.path()), &limits)?
+            .unwrap_or_default();
+        let summary: Vec<_> = attachments
+            .iter()
+            .map(|attachment| {
+                (
+                    attachment.alias.as_str(),
+                    attachment.file.media_type.as_deref().unwrap_or_default(),
+                    attachment.file.options.transfer_encoding.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("run-transects", "text/csv", None),
+                ("run-map", "image/png", Some("base64")),
+            ]
+        );
+        assert_eq!(
+            attachments[0].file.content.as_deref(),
+            Some("id,rate\n1,-0.5")
+        );
+
+        let limits = AttachmentExpansion {
+            include: vec!["run/outputs/*".into()],
+            max_files: 1,
+            ..Default::default()
+        };
+        assert!(expand_archive(&attachment("run", "run.zip"), Some(dir.path()), &limits).is_err());
+
+        let mut tar = common::tar::Builder::new(Vec::new());
+        let mut header = common::tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "log.txt", b"done".as_slice())?;
+        write(dir.path().join("run.tar"), tar.into_inner()?)?;
+
+        let attachments = expand_archive(
+            &attachment("*", "run.tar"),
+            Some(dir.path()),
+            &AttachmentExpansion::default(),
+        )?
+        .unwrap_or_default();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].alias, "log.txt");
+        assert_eq!(attachments[0].file.content.as_deref(), Some("done"));
+
+        // Leading `./` components are ignored and repeated members are attached once,
+        // with the content of the last. The name is written directly into the header
+        // because `set_path` would normalize it.
+        let mut tar = common::tar::Builder::new(Vec::new());
+        for (name, content) in [
+            ("./outputs/transects.csv", b"id,rate".as_slice()),
+            ("./outputs/log.txt", b"started"),
+            ("./outputs/log.txt", b"done"),
+        ] {
+            let mut header = common::tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, content)?;
+        }
+        write(dir.path().join("outputs.tar"), tar.into_inner()?)?;
+
+        let limits = AttachmentExpansion {
+            include: vec!["outputs/*".into()],
+            ..Default::default()
+        };
+        let attachments =
+            expand_archive(&attachment("*", "outputs.tar"), Some(dir.path()), &limits)?
+                .unwrap_or_default();
+        let summary: Vec<_> = attachments
+            .iter()
+            .map(|attachment| {
+                (
+                    attachment.alias.as_str(),
+                    attachment.file.content.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("transects.csv", "id,rate"), ("log.txt", "done")]
+        );
+
+        // Archives without any selected members are an error
+        let limits = AttachmentExpansion {
+            include: vec!["figures/*".into()],
+            ..Default::default()
+        };
+        assert!(
+            expand_archive(&attachment("*", "outputs.tar"), Some(dir.path()), &limits)
+                .is_err_and(|error| error.to_string().contains("does not have any members"))
+        );
+
+        // Members larger than their declared size are rejected
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(
+            "transects.csv",
+            zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored),
+        )?;
+        std::io::Write::write_all(&mut zip, b"id,rate\n1,-0.5\n2,0.1")?;
+        let mut bytes = zip.finish()?.into_inner();
+        for (signature, offset) in [(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let Some(start) = bytes.windows(4).position(|window| window == signature) else {
+                bail!("expected zip header")
+            };
+            bytes[start + offset..start + offset + 4].copy_from_slice(&4u32.to_le_bytes());
+        }
+        write(dir.path().join("bomb.zip"), bytes)?;
+        assert!(
+            expand_archive(
+                &attachment("bomb", "bomb.zip"),
+                Some(dir.path()),
+                &AttachmentExpansion::default()
+            )
+            .is_err_and(|error| error.to_string().contains("larger than its declared size"))
+        );
+
+        assert!(
+            expand_archive(
+                &attachment("csv", "data.csv"),
+                None,
+                &AttachmentExpansion::default()
+            )?
+            .is_none()
+        );
+
+        Ok(())
+    }
+}
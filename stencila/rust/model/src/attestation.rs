@@ -0,0 +1,285 @@
+use std::{
+    fs::{OpenOptions, create_dir_all, read},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    chrono::Utc,
+    eyre::{Result, bail, eyre},
+    serde::{Deserialize, Serialize},
+    serde_json,
+};
+use dirs::{DirType, get_app_dir};
+use ring::{
+    digest::{SHA256, digest},
+    rand::SystemRandom,
+    signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey},
+};
+
+use crate::models_config;
+
+/// The name of the file that the signing key is stored in, within the config directory
+const KEY_FILE: &str = "attestation-key.pk8";
+
+/// Options for attesting to the outputs of models
+///
+/// ```toml
+/// [records.attestation]
+/// enabled = true
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct AttestationConfig {
+    /// Whether to sign an attestation for each output
+    pub enabled: bool,
+
+    /// The path of the PKCS#8 file containing the Ed25519 signing key
+    ///
+    /// A key is generated, and written to this path, if the file does not exist.
+    /// Defaults to `attestation-key.pk8` in the Stencila config directory.
+    pub key_path: Option<PathBuf>,
+}
+
+/// An Ed25519 key used to sign attestations
+pub struct SigningKey {
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Generate a new key, returning it and its PKCS#8 encoding
+    pub fn generate() -> Result<(Self, Vec<u8>)> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| eyre!("Unable to generate signing key"))?;
+        let key = Self::from_pkcs8(pkcs8.as_ref())?;
+        Ok((key, pkcs8.as_ref().to_vec()))
+    }
+
+    /// Create a key from its PKCS#8 encoding
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|error| eyre!("Invalid signing key: {error}"))?;
+        Ok(Self { pair })
+    }
+
+    /// Load a key from a PKCS#8 file, generating and writing one if the file does not exist
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            return Self::from_pkcs8(&read(path)?);
+        }
+
+        let (key, pkcs8) = Self::generate()?;
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write_private(path, &pkcs8)?;
+        Ok(key)
+    }
+
+    /// Get the public key, base64 encoded
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.pair.public_key().as_ref())
+    }
+}
+
+/// Write a file readable only by the current user (where supported)
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(bytes)?;
+    Ok(())
+}
+
+/// A signed record of the generation of an output
+///
+/// Signs the tuple of the fingerprint of the task, the SHA-256 hash of the content
+/// of the output, the id of the model, and the time of generation with a local
+/// Ed25519 key. The attestation is plain JSON so it can be embedded in documents
+/// alongside the generated content and third parties can use [`Attestation::verify`]
+/// (or any Ed25519 implementation) to check that the generation record, and
+/// the content, were not modified.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct Attestation {
+    /// The fingerprint of the task
+    pub fingerprint: String,
+
+    /// The SHA-256 hash of the content of the output, hex encoded
+    pub output_hash: String,
+
+    /// The id of the model which generated the output
+    pub model: String,
+
+    /// The time the attestation was signed, in RFC 3339 format
+    pub timestamp: String,
+
+    /// The signature algorithm; always `Ed25519`
+    pub algorithm: String,
+
+    /// The public key of the signer, base64 encoded
+    pub public_key: String,
+
+    /// The signature of the payload, base64 encoded
+    pub signature: String,
+}
+
+/// The payload of an attestation that is signed
+///
+/// Fields are serialized in this (fixed) order so the payload is reproducible.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+struct Payload<'a> {
+    fingerprint: &'a str,
+    output_hash: &'a str,
+    model: &'a str,
+    timestamp: &'a str,
+}
+
+impl Attestation {
+    /// Sign an attestation for the content generated by a model for a task
+    pub fn sign(key: &SigningKey, fingerprint: &str, model: &str, content: &str) -> Result<Self> {
+        Self::sign_bytes(key, fingerprint, model, content.as_bytes())
+    }
+
+    /// Sign an attestation for binary content (e.g. an image) generated by a model
+    pub fn sign_bytes(
+        key: &SigningKey,
+        fingerprint: &str,
+        model: &str,
+        content: &[u8],
+    ) -> Result<Self> {
+        let mut attestation = Self {
+            fingerprint: fingerprint.into(),
+            output_hash: content_hash(content),
+            model: model.into(),
+            timestamp: Utc::now().to_rfc3339(),
+            algorithm: "Ed25519".into(),
+            public_key: key.public_key(),
+            signature: String::new(),
+        };
+        attestation.signature = BASE64.encode(key.pair.sign(&attestation.payload()?).as_ref());
+        Ok(attestation)
+    }
+
+    /// Get the bytes that are signed
+    fn payload(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&Payload {
+            fingerprint: &self.fingerprint,
+            output_hash: &self.output_hash,
+            model: &self.model,
+            timestamp: &self.timestamp,
+        })?)
+    }
+
+    /// Verify the attestation
+    ///
+    /// Checks that the signature is valid for the public key of the attestation and,
+    /// if `content` is supplied, that it is the content that was attested to. Callers
+    /// should also check that the public key is one that they trust.
+    pub fn verify(&self, content: Option<&str>) -> Result<()> {
+        self.verify_bytes(content.map(str::as_bytes))
+    }
+
+    /// Verify the attestation of binary content
+    ///
+    /// As for [`Attestation::verify`] but for content which is not text.
+    pub fn verify_bytes(&self, content: Option<&[u8]>) -> Result<()> {
+        if self.algorithm != "Ed25519" {
+            bail!("Unsupported attestation algorithm `{}`", self.algorithm);
+        }
+
+        if let Some(content) = content
+            && content_hash(content) != self.output_hash
+        {
+            bail!("Content does not match the hash in the attestation");
+        }
+
+        let public_key = BASE64.decode(&self.public_key)?;
+        let signature = BASE64.decode(&self.signature)?;
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&self.payload()?, &signature)
+            .map_err(|_| eyre!("Attestation signature is invalid"))
+    }
+}
+
+/// Get the hex encoded SHA-256 hash of content
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    digest(&SHA256, content)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Sign an attestation for an output, if enabled in the `[records.attestation]` table of the models config
+pub fn attest(fingerprint: &str, model: &str, content: &str) -> Result<Option<Attestation>> {
+    let Some(key) = signing_key()? else {
+        return Ok(None);
+    };
+    Attestation::sign(&key, fingerprint, model, content).map(Some)
+}
+
+/// Get the key used to sign attestations, if enabled in the models config
+pub(crate) fn signing_key() -> Result<Option<SigningKey>> {
+    let config = &models_config().records.attestation;
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let path = match &config.key_path {
+        Some(path) => path.clone(),
+        None => get_app_dir(DirType::Config, true)?.join(KEY_FILE),
+    };
+    SigningKey::load_or_generate(&path).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn signs_and_verifies() -> Result<()> {
+        let (key, ..) = SigningKey::generate()?;
+        let attestation = Attestation::sign(&key, "0123abcd", "openai/gpt-4o", "Eroding.")?;
+
+        attestation.verify(None)?;
+        attestation.verify(Some("Eroding."))?;
+        assert!(attestation.verify(Some("Accreting.")).is_err());
+
+        // Round trips through JSON, as when embedded in a document
+        let json = serde_json::to_string(&attestation)?;
+        let embedded: Attestation = serde_json::from_str(&json)?;
+        embedded.verify(Some("Eroding."))?;
+
+        // Any modification of the record invalidates the signature
+        let mut modified = attestation.clone();
+        modified.model = "openai/gpt-4o-mini".into();
+        assert!(modified.verify(None).is_err());
+
+        let mut modified = attestation.clone();
+        modified.timestamp = "2020-01-01T00:00:00Z".into();
+        assert!(modified.verify(None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn loads_or_generates_key() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("keys").join(KEY_FILE);
+
+        let generated = SigningKey::load_or_generate(&path)?;
+        let loaded = SigningKey::load_or_generate(&path)?;
+        assert_eq!(generated.public_key(), loaded.public_key());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+use std::{fs::read, path::Path};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::{Result, bail},
+    serde_json::Value,
+};
+use format::Format;
+use schema::{File, InstructionAttachment, InstructionMessage, MessagePart, MessageRole};
+
+use crate::{
+    ChatMemory, CodeInterpreterOptions, ImageDetailLevel, ModelTask, ModelTaskKind, PostProcessing,
+    PromptCompression, RetrievalOptions, TaskPriority, Validator, WebSearchOptions,
+    WorkflowArtifact,
+};
+
+/// A builder for a [`ModelTask`]
+///
+/// Created using [`ModelTask::builder`]. Options are checked when the task is
+/// built so that invalid or conflicting options are reported in one place rather
+/// than causing a provider error (or being silently ignored):
+///
+/// ```ignore
+/// let task = ModelTask::builder()
+///     .system_text("You are a coastal scientist.")
+///     .user_text("Summarize the shoreline trends in the attached file.")
+///     .attach_path("trends.csv")
+///     .temperature(0.2)
+///     .build()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct ModelTaskBuilder {
+    /// The task being built
+    task: ModelTask,
+
+    /// Errors encountered while building e.g. when reading attached files
+    errors: Vec<String>,
+}
+
+impl ModelTask {
+    /// Create a builder for a task
+    pub fn builder() -> ModelTaskBuilder {
+        ModelTaskBuilder::default()
+    }
+}
+
+impl ModelTaskBuilder {
+    /// Add a message
+    pub fn message(mut self, message: InstructionMessage) -> Self {
+        self.task.messages.push(message);
+        self
+    }
+
+    /// Add a message with a role and text
+    fn text(self, role: MessageRole, text: &str) -> Self {
+        self.message(InstructionMessage {
+            role: Some(role),
+            parts: vec![MessagePart::from(text)],
+            ..Default::default()
+        })
+    }
+
+    /// Add a system message
+    pub fn system_text(self, text: &str) -> Self {
+        self.text(MessageRole::System, text)
+    }
+
+    /// Add a user message
+    pub fn user_text(self, text: &str) -> Self {
+        self.text(MessageRole::User, text)
+    }
+
+    /// Add a model (assistant) message e.g. when replaying a conversation
+    pub fn model_text(self, text: &str) -> Self {
+        self.text(MessageRole::Model, text)
+    }
+
+    /// Add an attachment
+    pub fn attach(mut self, attachment: InstructionAttachment) -> Self {
+        self.task
+            .attachments
+            .get_or_insert_with(Vec::new)
+            .push(attachment);
+        self
+    }
+
+    /// Attach a file, using its stem as its alias
+    ///
+    /// The file is read, and its media type determined from its extension,
+    /// immediately. Any error reading the file is reported when the task is built.
+    pub fn attach_path(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let alias = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.attach_path_as(&alias, path)
+    }
+
+    /// Attach a file using an alias
+    pub fn attach_path_as(mut self, alias: &str, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let bytes = match read(path) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                self.errors
+                    .push(format!("Unable to read `{}`: {error}", path.display()));
+                return self;
+            }
+        };
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut file = File::new(name, path.to_string_lossy().to_string());
+        file.media_type = Some(Format::from_path(path).media_type());
+        file.size = Some(bytes.len() as u64);
+        match String::from_utf8(bytes) {
+            Ok(text) => file.content = Some(text),
+            Err(error) => {
+                file.content = Some(BASE64.encode(error.into_bytes()));
+                file.options.transfer_encoding = Some("base64".into());
+            }
+        }
+
+        self.attach(InstructionAttachment::new(alias.into(), file))
+    }
+
+    /// Set the template for the text introducing each attachment
+    ///
+    /// See [`ModelTask::attachment_preamble`] for the placeholders available.
+    pub fn attachment_preamble(mut self, template: &str) -> Self {
+        self.task.attachment_preamble = Some(template.into());
+        self
+    }
+
+    /// Set the kind of task
+    pub fn kind(mut self, kind: ModelTaskKind) -> Self {
+        self.task.kind = kind;
+        self
+    }
+
+    /// Set the format of the generated content
+    pub fn format(mut self, format: Format) -> Self {
+        self.task.format = format;
+        self
+    }
+
+    /// Require the generated content to be JSON conforming to a schema
+    ///
+    /// Sets the format to JSON and adds a [`Validator::JsonSchema`].
+    pub fn json_schema(mut self, schema: Value) -> Self {
+        self.task.format = Format::Json;
+        self.task.validators.push(Validator::JsonSchema(schema));
+        self
+    }
+
+    /// Add a validator for the generated content
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.task.validators.push(validator);
+        self
+    }
+
+    /// Pin the exact model used
+    pub fn model_id(mut self, id: &str) -> Self {
+        self.task.model_id = Some(id.into());
+        self
+    }
+
+    /// Set the temperature (between 0 and 2)
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.task.temperature = Some(temperature);
+        self
+    }
+
+    /// Set the nucleus sampling probability (greater than 0 and at most 1)
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.task.top_p = Some(top_p);
+        self
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn max_tokens(mut self, max_tokens: u16) -> Self {
+        self.task.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Set the maximum number of times to continue truncated output
+    ///
+    /// See [`ModelTask::auto_continue`].
+    pub fn auto_continue(mut self, rounds: usize) -> Self {
+        self.task.auto_continue = Some(rounds);
+        self
+    }
+
+    /// Set the language that the output is expected to be in
+    ///
+    /// See [`ModelTask::expected_language`].
+    pub fn expected_language(mut self, language: &str) -> Self {
+        self.task.expected_language = Some(language.into());
+        self
+    }
+
+    /// Set the post-processing of generated Markdown
+    ///
+    /// See [`PostProcessing`].
+    pub fn post_processing(mut self, post_processing: PostProcessing) -> Self {
+        self.task.post_processing = Some(post_processing);
+        self
+    }
+
+    /// Set the random seed
+    pub fn seed(mut self, seed: i32) -> Self {
+        self.task.seed = Some(seed);
+        self
+    }
+
+    /// Add a stop sequence
+    pub fn stop(mut self, stop: &str) -> Self {
+        self.task
+            .stop
+            .get_or_insert_with(Vec::new)
+            .push(stop.into());
+        self
+    }
+
+    /// Set the number of candidates to generate
+    pub fn candidates(mut self, candidates: u8) -> Self {
+        self.task.candidates = Some(candidates);
+        self
+    }
+
+    /// Set the level of detail with which input images are processed
+    pub fn image_detail(mut self, detail: ImageDetailLevel) -> Self {
+        self.task.image_detail = Some(detail);
+        self
+    }
+
+    /// Set the options for compressing the prompt
+    pub fn compression(mut self, compression: PromptCompression) -> Self {
+        self.task.compression = Some(compression);
+        self
+    }
+
+    /// Set the options for retrieving excerpts of attachments
+    pub fn retrieval(mut self, retrieval: RetrievalOptions) -> Self {
+        self.task.retrieval = Some(retrieval);
+        self
+    }
+
+    /// Allow the model to search the web using the provider's hosted web search tool
+    pub fn web_search(mut self, options: WebSearchOptions) -> Self {
+        self.task.web_search = Some(options);
+        self
+    }
+
+    /// Allow the model to run code using the provider's hosted code interpreter tool
+    pub fn code_interpreter(mut self, options: CodeInterpreterOptions) -> Self {
+        self.task.code_interpreter = Some(options);
+        self
+    }
+
+    /// Set the memory of the chat session
+    pub fn memory(mut self, memory: ChatMemory) -> Self {
+        self.task.memory = Some(memory);
+        self
+    }
+
+    /// Set the priority of the task when queued
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        self.task.priority = Some(priority);
+        self
+    }
+
+    /// Set the key used by the provider to cache the prefix of the prompt
+    pub fn prompt_cache_key(mut self, key: impl Into<String>) -> Self {
+        self.task.prompt_caching.get_or_insert_default().key = Some(key.into());
+        self
+    }
+
+    /// Declare that the task is grounded in an output file of an upstream workflow
+    ///
+    /// The file is hashed so that the generated content can be linked to the
+    /// exact version of the data.
+    pub fn derived_from(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match WorkflowArtifact::from_path(path) {
+            Ok(artifact) => self.task.derived_from.push(artifact),
+            Err(error) => self
+                .errors
+                .push(format!("Unable to hash `{}`: {error}", path.display())),
+        }
+        self
+    }
+
+    /// Declare that the task is grounded in a workflow artifact
+    pub fn derived_from_artifact(mut self, artifact: WorkflowArtifact) -> Self {
+        self.task.derived_from.push(artifact);
+        self
+    }
+
+    /// Set the identifier of the end user the task is performed for
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.task.user = Some(user.into());
+        self
+    }
+
+    /// Record the request and response for auditing
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.task.audit = Some(audit);
+        self
+    }
+
+    /// Prepare the task but do not generate content
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.task.dry_run = dry_run;
+        self
+    }
+
+    /// Validate the options and build the task
+    pub fn build(self) -> Result<ModelTask> {
+        let Self { task, mut errors } = self;
+
+        if task.messages.is_empty() {
+            errors.push("Task has no messages".into());
+        }
+
+        if let Some(temperature) = task.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            errors.push(format!(
+                "Temperature must be between 0 and 2 but was {temperature}"
+            ));
+        }
+
+        if let Some(top_p) = task.top_p
+            && !(top_p > 0.0 && top_p <= 1.0)
+        {
+            errors.push(format!(
+                "Top-p must be greater than 0 and at most 1 but was {top_p}"
+            ));
+        }
+
+        if task.max_tokens == Some(0) {
+            errors.push("Maximum tokens must be greater than 0".into());
+        }
+
+        if task.candidates == Some(0) {
+            errors.push("Number of candidates must be greater than 0".into());
+        }
+
+        if task.model_id.is_some()
+            && task
+                .model_parameters
+                .as_ref()
+                .is_some_and(|pars| pars.model_ids.is_some())
+        {
+            errors.push("A pinned model id and model id patterns can not both be specified".into());
+        }
+
+        if matches!(task.kind, ModelTaskKind::ImageGeneration) {
+            if !task.validators.is_empty() {
+                errors.push("Validators can not be used with image generation tasks".into());
+            }
+            if task.candidates.is_some_and(|candidates| candidates > 1) {
+                errors.push(
+                    "Use image count, rather than candidates, to generate multiple images".into(),
+                );
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("Invalid model task: {}", errors.join("; "));
+        }
+
+        Ok(task)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use common::{serde_json::json, tempfile::tempdir};
+
+    use super::*;
+
+    #[test]
+    fn builds_and_validates() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("trends.csv");
+        write(&path, "transect,rate\n1,-0.5\n")?;
+
+        let task = ModelTask::builder()
+            .system_text("Be brief.")
+            .user_text("Which transects are eroding?")
+            .attach_path(&path)
+            .derived_from(&path)
+            .json_schema(json!({"type": "array"}))
+            .temperature(0.2)
+            .build()?;
+        assert_eq!(task.messages.len(), 2);
+        assert_eq!(task.derived_from.len(), 1);
+        assert!(task.derived_from[0].is_current()?);
+        assert_eq!(task.format, Format::Json);
+        assert_eq!(task.validators.len(), 1);
+        let attachment = &task.attachments.unwrap_or_default()[0];
+        assert_eq!(attachment.alias, "trends");
+        assert_eq!(attachment.file.media_type.as_deref(), Some("text/csv"));
+        assert_eq!(
+            attachment.file.content.as_deref(),
+            Some("transect,rate\n1,-0.5\n")
+        );
+
+        let Err(error) = ModelTask::builder()
+            .user_text("Hi")
+            .temperature(3.0)
+            .max_tokens(0)
+            .attach_path(dir.path().join("missing.csv"))
+            .build()
+        else {
+            bail!("expected error")
+        };
+        let error = error.to_string();
+        assert!(error.starts_with("Invalid model task: Unable to read"));
+        assert!(error.contains("Temperature must be between 0 and 2 but was 3"));
+        assert!(error.contains("Maximum tokens must be greater than 0"));
+
+        assert!(
+            ModelTask::builder()
+                .kind(ModelTaskKind::ImageGeneration)
+                .user_text("A beach")
+                .candidates(2)
+                .build()
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use common::serde::Serialize;
+
+/// An in-memory cache with time-to-live expiry and a size cap
+///
+/// Used by model providers instead of memoizing macros so that cached values
+/// (e.g. lists of models) can be invalidated and cache usage can be monitored.
+/// When the cache is full, the oldest entry is evicted.
+pub struct TtlCache<K, V> {
+    /// The time after which entries expire
+    ttl: Duration,
+
+    /// The maximum number of entries
+    capacity: usize,
+
+    /// The cached entries and the time they were inserted
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Metrics on the usage of a cache
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct CacheMetrics {
+    /// The number of lookups which found an unexpired entry
+    pub hits: u64,
+
+    /// The number of lookups which did not find an unexpired entry
+    pub misses: u64,
+
+    /// The number of entries removed because they expired or the cache was full
+    pub evictions: u64,
+
+    /// The current number of entries
+    pub size: usize,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Create a new cache
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Get an unexpired value from the cache
+    pub fn get(&self, key: &K) -> Option<V> {
+        let Ok(mut entries) = self.entries.lock() else {
+            return None;
+        };
+
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            Some(..) => {
+                entries.remove(key);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Insert a value into the cache, evicting the oldest entries if necessary
+    pub fn insert(&self, key: K, value: V) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        while !entries.contains_key(&key) && entries.len() >= self.capacity.max(1) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(.., (inserted, ..))| *inserted)
+                .map(|(key, ..)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    /// Remove an entry from the cache
+    pub fn invalidate(&self, key: &K) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(key);
+        }
+    }
+
+    /// Remove all entries from the cache
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    /// Get metrics on the usage of the cache
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self
+                .entries
+                .lock()
+                .map(|entries| entries.len())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+
+        assert_eq!(cache.get(&1), None);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+
+        cache.insert(3, "three");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&3), Some("three"));
+
+        cache.invalidate(&3);
+        assert_eq!(cache.get(&3), None);
+
+        assert_eq!(
+            cache.metrics(),
+            CacheMetrics {
+                hits: 2,
+                misses: 3,
+                evictions: 1,
+                size: 1
+            }
+        );
+
+        let expiring = TtlCache::new(Duration::ZERO, 1);
+        expiring.insert((), 1);
+        assert_eq!(expiring.get(&()), None);
+    }
+}
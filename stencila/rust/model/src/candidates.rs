@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use common::{
+    async_trait::async_trait,
+    eyre::Result,
+    once_cell::sync::Lazy,
+    regex::Regex,
+    serde::{Deserialize, Serialize},
+    serde_json, tracing,
+};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Model, ModelTask, Policy};
+
+/// The strategy used to select one of several candidate outputs
+///
+/// Used when a task requests more than one candidate (see [`ModelTask::candidates`]).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub enum CandidateSelection {
+    /// Select the first candidate
+    #[default]
+    First,
+
+    /// Select the longest candidate
+    Longest,
+
+    /// Select the most common candidate
+    ///
+    /// Candidates which are valid JSON are compared by value so that
+    /// differences in whitespace and key order are ignored.
+    MajorityVote,
+
+    /// Ask another model, with an id matching this pattern, to select the best candidate
+    Reranker(String),
+}
+
+/// A selector of one of several candidate outputs
+///
+/// Implement this trait to provide a custom selection strategy.
+#[async_trait]
+pub trait CandidateSelector: Send + Sync {
+    /// Select a candidate, returning its index
+    async fn select(&self, task: &ModelTask, candidates: &[String]) -> Result<usize>;
+}
+
+/// Selects the first candidate
+pub struct FirstSelector;
+
+#[async_trait]
+impl CandidateSelector for FirstSelector {
+    async fn select(&self, _task: &ModelTask, _candidates: &[String]) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Selects the longest candidate, preferring earlier candidates if equal
+pub struct LongestSelector;
+
+#[async_trait]
+impl CandidateSelector for LongestSelector {
+    async fn select(&self, _task: &ModelTask, candidates: &[String]) -> Result<usize> {
+        let mut selected = 0;
+        for (index, candidate) in candidates.iter().enumerate() {
+            if candidate.trim().len() > candidates[selected].trim().len() {
+                selected = index;
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Selects the most common candidate, preferring earlier candidates if tied
+pub struct MajorityVoteSelector;
+
+#[async_trait]
+impl CandidateSelector for MajorityVoteSelector {
+    async fn select(&self, _task: &ModelTask, candidates: &[String]) -> Result<usize> {
+        // JSON values are compared by value (object key order is ignored when comparing)
+        let keys: Vec<Result<serde_json::Value, &str>> = candidates
+            .iter()
+            .map(|candidate| {
+                let candidate = candidate.trim();
+                serde_json::from_str(candidate).map_err(|_| candidate)
+            })
+            .collect();
+
+        let mut selected = 0;
+        let mut max_votes = 0;
+        for (index, key) in keys.iter().enumerate() {
+            let votes = keys.iter().filter(|other| *other == key).count();
+            if votes > max_votes {
+                selected = index;
+                max_votes = votes;
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Asks a model to select the best candidate
+pub struct RerankerSelector {
+    /// The model used to rank the candidates
+    pub model: Arc<dyn Model>,
+
+    /// The policy that the request to the model is checked against, and recorded by
+    pub policy: Policy,
+}
+
+#[async_trait]
+impl CandidateSelector for RerankerSelector {
+    async fn select(&self, task: &ModelTask, candidates: &[String]) -> Result<usize> {
+        static NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+").expect("invalid regex"));
+
+        let mut prompt = String::from("Task:\n\n");
+        prompt.push_str(&task.prompt_as_text().unwrap_or_default());
+        for (index, candidate) in candidates.iter().enumerate() {
+            prompt.push_str(&format!(
+                "\n\n---\n\nResponse {}:\n\n{candidate}",
+                index + 1
+            ));
+        }
+
+        let rerank_task = ModelTask {
+            messages: vec![
+                InstructionMessage {
+                    role: Some(MessageRole::System),
+                    parts: vec![MessagePart::from(
+                        "You will be given a task and several responses to it. Select the response which best completes the task. Respond only with the number of that response.",
+                    )],
+                    ..Default::default()
+                },
+                InstructionMessage {
+                    role: Some(MessageRole::User),
+                    parts: vec![MessagePart::from(prompt)],
+                    ..Default::default()
+                },
+            ],
+            temperature: Some(0.0),
+            dry_run: task.dry_run,
+            ..Default::default()
+        };
+
+        let output = self
+            .policy
+            .perform(self.model.as_ref(), &rerank_task)
+            .await?;
+
+        let selected = NUMBER
+            .find(&output.content)
+            .and_then(|number| number.as_str().parse::<usize>().ok())
+            .and_then(|number| number.checked_sub(1))
+            .filter(|index| *index < candidates.len());
+
+        Ok(selected.unwrap_or_else(|| {
+            tracing::warn!(
+                "Reranker model `{}` did not select a valid candidate, using the first",
+                self.model.id()
+            );
+            0
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::tokio;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn selects() -> Result<()> {
+        let task = ModelTask::default();
+        let candidates = [
+            r#"{"a": 1, "b": 2}"#.to_string(),
+            r#"{"a": 1}"#.to_string(),
+            r#"{"b":2,"a":1}"#.to_string(),
+            "A much longer candidate which is not JSON".to_string(),
+        ];
+
+        assert_eq!(LongestSelector.select(&task, &candidates).await?, 3);
+        assert_eq!(MajorityVoteSelector.select(&task, &candidates).await?, 0);
+        assert_eq!(FirstSelector.select(&task, &candidates[1..]).await?, 0);
+
+        Ok(())
+    }
+}
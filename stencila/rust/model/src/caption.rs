@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use common::{
+    eyre::{Result, bail},
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_json::{self, json},
+    serde_with::skip_serializing_none,
+};
+use format::Format;
+use schema::MessagePart;
+
+use crate::{ModelOutput, ModelOutputPart, ModelTask, Validator, kinds::insert_system_message};
+
+/// The context of a figure to be captioned
+///
+/// Provided to the model, along with the image of the figure, so that the caption
+/// describes what the figure shows in terms of the document and the data it was
+/// derived from (e.g. the satellite missions and transects of a shoreline plot)
+/// rather than only its visual appearance.
+#[skip_serializing_none]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", crate = "common::serde")]
+pub struct FigureContext {
+    /// The text of the document surrounding the figure e.g. the paragraphs before and after it
+    pub document_context: Option<String>,
+
+    /// Metadata about the sources of the data plotted in the figure
+    ///
+    /// For example, `{"satellites": "Landsat 5, 7, 8 and Sentinel-2", "period": "1987-2024"}`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub data_sources: BTreeMap<String, String>,
+}
+
+/// A caption generated for a figure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+struct FigureCaption {
+    caption: String,
+    alt_text: String,
+    keywords: Vec<String>,
+}
+
+/// Prepare a caption generation task
+///
+/// Errors if the task has no image to caption. Adds instructions, and the
+/// figure context, to the system prompt and requires the model to respond with
+/// JSON containing the caption, alt text and keywords.
+pub(crate) fn prepare(task: &mut ModelTask) -> Result<()> {
+    let has_image = task.messages.iter().any(|message| {
+        message
+            .parts
+            .iter()
+            .any(|part| matches!(part, MessagePart::ImageObject(..)))
+    }) || task.attachments.iter().flatten().any(|attachment| {
+        attachment
+            .file
+            .media_type
+            .as_deref()
+            .is_some_and(|media_type| media_type.starts_with("image/"))
+    });
+    if !has_image {
+        bail!("Caption generation tasks require an image of the figure to caption");
+    }
+
+    let mut prompt = String::from(
+        "You are captioning a figure in a scientific document. Describe what the figure shows, \
+and what it means in the context of the document, in a concise caption (one to three sentences). \
+Also write alt text describing the visual content of the figure for readers who can not see it, \
+and up to eight keywords.\n\n\
+Respond only with a JSON object with the properties `caption` (string), `altText` (string) and \
+`keywords` (array of strings).",
+    );
+
+    if let Some(context) = &task.figure_context {
+        if let Some(document) = context
+            .document_context
+            .as_deref()
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+        {
+            prompt.push_str("\n\nThe text of the document surrounding the figure:\n\n");
+            prompt.push_str(document);
+        }
+
+        if !context.data_sources.is_empty() {
+            prompt.push_str("\n\nThe sources of the data in the figure:\n\n");
+            prompt.push_str(
+                &context
+                    .data_sources
+                    .iter()
+                    .map(|(name, value)| format!("- {name}: {value}"))
+                    .join("\n"),
+            );
+        }
+    }
+
+    insert_system_message(task, prompt);
+
+    task.format = Format::Json;
+    task.validators.push(Validator::JsonSchema(json!({
+        "type": "object",
+        "required": ["caption", "altText", "keywords"],
+        "properties": {
+            "caption": {"type": "string", "minLength": 1},
+            "altText": {"type": "string"},
+            "keywords": {"type": "array", "items": {"type": "string"}}
+        }
+    })));
+
+    Ok(())
+}
+
+/// Finish a caption generation task
+///
+/// Replaces the JSON content of the output with the caption and records the
+/// caption, alt text and keywords as a part of the output.
+pub(crate) fn finish(output: &mut ModelOutput) -> Result<()> {
+    let FigureCaption {
+        caption,
+        alt_text,
+        keywords,
+    } = serde_json::from_str(output.content.trim())?;
+
+    output.content = caption.trim().to_string();
+    output.format = Format::Markdown;
+    output.parts.push(ModelOutputPart::Caption {
+        caption,
+        alt_text,
+        keywords,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::{File, InstructionAttachment, InstructionMessage, MessageRole};
+
+    use crate::{ModelTaskKind, finish_specialized_task, specialize_task};
+
+    use super::*;
+
+    #[test]
+    fn prepare_and_finish() -> Result<()> {
+        let mut task = ModelTask {
+            kind: ModelTaskKind::CaptionGeneration,
+            messages: vec![InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::from("Caption this figure")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(specialize_task(&mut task).is_err());
+
+        let mut file = File::new("trend.png".into(), "trend.png".into());
+        file.media_type = Some("image/png".into());
+        task.attachments = Some(vec![InstructionAttachment::new("trend".into(), file)]);
+        task.figure_context = Some(FigureContext {
+            document_context: Some("Narrabeen beach has eroded since 2016.".into()),
+            data_sources: BTreeMap::from([("satellites".into(), "Landsat 8".into())]),
+        });
+
+        let kind = specialize_task(&mut task)?;
+        assert_eq!(kind, Some(ModelTaskKind::CaptionGeneration));
+        assert_eq!(task.kind, ModelTaskKind::MessageGeneration);
+        assert_eq!(task.format, Format::Json);
+        assert_eq!(task.messages[0].role, Some(MessageRole::System));
+        let prompt = task.prompt_as_text().unwrap_or_default();
+        assert!(prompt.contains("Narrabeen beach has eroded"));
+        assert!(prompt.contains("- satellites: Landsat 8"));
+
+        let mut output = ModelOutput {
+            content: r#"{"caption": "Shoreline position at Narrabeen.", "altText": "A line chart.", "keywords": ["shoreline"]}"#.into(),
+            ..Default::default()
+        };
+        if let Some(kind) = kind {
+            finish_specialized_task(kind, &task, &mut output)?;
+        }
+        assert_eq!(output.content, "Shoreline position at Narrabeen.");
+        assert_eq!(output.format, Format::Markdown);
+        assert_eq!(
+            output.parts,
+            vec![ModelOutputPart::Caption {
+                caption: "Shoreline position at Narrabeen.".into(),
+                alt_text: "A line chart.".into(),
+                keywords: vec!["shoreline".into()],
+            }]
+        );
+
+        Ok(())
+    }
+}
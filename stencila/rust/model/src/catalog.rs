@@ -0,0 +1,279 @@
+use std::{collections::HashMap, sync::Arc};
+
+use common::itertools::Itertools;
+
+use crate::{Model, ModelIO, ModelTaskKind, ModelType};
+
+/// A catalog of models from multiple providers
+///
+/// Models with the same id are deduplicated, keeping the one with the lowest
+/// ranked [`ModelType`] (i.e. `Local` over `Remote` over `Proxied`). This avoids
+/// proxied models clashing with remote models when the user has both a Stencila
+/// API key and other provider API keys set.
+#[derive(Default, Clone)]
+pub struct ModelCatalog {
+    models: Vec<Arc<dyn Model>>,
+}
+
+impl ModelCatalog {
+    /// Create a catalog from lists of models from providers
+    pub fn new(models: impl IntoIterator<Item = Arc<dyn Model>>) -> Self {
+        let mut unique: HashMap<String, Arc<dyn Model>> = HashMap::new();
+        for model in models {
+            unique
+                .entry(model.id())
+                .and_modify(|existing| {
+                    if existing.r#type() > model.r#type() {
+                        *existing = model.clone();
+                    }
+                })
+                .or_insert(model);
+        }
+
+        let models = unique
+            .into_values()
+            .sorted_by_key(|model| model.id())
+            .collect();
+
+        Self { models }
+    }
+
+    /// Get all the models in the catalog
+    pub fn models(&self) -> &[Arc<dyn Model>] {
+        &self.models
+    }
+
+    /// Get the model with an id, if any
+    pub fn get(&self, id: &str) -> Option<Arc<dyn Model>> {
+        self.models.iter().find(|model| model.id() == id).cloned()
+    }
+
+    /// Find the models matching a query
+    pub fn find(&self, query: &ModelQuery) -> Vec<Arc<dyn Model>> {
+        self.models
+            .iter()
+            .filter(|model| query.matches(model.as_ref()))
+            .cloned()
+            .collect()
+    }
+
+    /// Consume the catalog, returning its models
+    pub fn into_models(self) -> Vec<Arc<dyn Model>> {
+        self.models
+    }
+}
+
+/// A query for models in a [`ModelCatalog`] based on their capabilities
+///
+/// All criteria must be met for a model to match. Models that do not report
+/// a capability (e.g. their context length or cost) do not match criteria on it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ModelQuery {
+    provider: Option<String>,
+    types: Vec<ModelType>,
+    inputs: Vec<ModelIO>,
+    outputs: Vec<ModelIO>,
+    task_kind: Option<ModelTaskKind>,
+    min_context: Option<usize>,
+    max_cost_per_mtok: Option<f64>,
+    available: bool,
+}
+
+impl ModelQuery {
+    /// Create a new query that matches all models
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match models from a provider e.g. `openai`
+    pub fn provider(mut self, provider: &str) -> Self {
+        self.provider = Some(provider.to_lowercase());
+        self
+    }
+
+    /// Only match models of a type (may be called more than once to match any of several types)
+    pub fn r#type(mut self, r#type: ModelType) -> Self {
+        self.types.push(r#type);
+        self
+    }
+
+    /// Only match models that support an input type
+    pub fn input(mut self, input: ModelIO) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Only match models that support an output type
+    pub fn output(mut self, output: ModelIO) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Only match models that support a kind of task
+    pub fn task_kind(mut self, kind: ModelTaskKind) -> Self {
+        self.task_kind = Some(kind);
+        self
+    }
+
+    /// Only match models with at least this context length
+    pub fn min_context(mut self, tokens: usize) -> Self {
+        self.min_context = Some(tokens);
+        self
+    }
+
+    /// Only match models costing no more than this many USD per million tokens
+    pub fn max_cost_per_mtok(mut self, cost: f64) -> Self {
+        self.max_cost_per_mtok = Some(cost);
+        self
+    }
+
+    /// Only match models that are available on this machine
+    pub fn available(mut self) -> Self {
+        self.available = true;
+        self
+    }
+
+    /// Whether a model matches the query
+    pub fn matches(&self, model: &dyn Model) -> bool {
+        if let Some(provider) = &self.provider
+            && model.provider().to_lowercase() != *provider
+        {
+            return false;
+        }
+
+        if !self.types.is_empty() && !self.types.contains(&model.r#type()) {
+            return false;
+        }
+
+        let inputs = model.supported_inputs();
+        if !self.inputs.iter().all(|input| inputs.contains(input)) {
+            return false;
+        }
+
+        let outputs = model.supported_outputs();
+        if !self.outputs.iter().all(|output| outputs.contains(output)) {
+            return false;
+        }
+
+        if let Some(kind) = &self.task_kind
+            && !model.supported_task_kinds().contains(kind)
+        {
+            return false;
+        }
+
+        if let Some(min) = self.min_context
+            && model.context_length() < min
+        {
+            return false;
+        }
+
+        if let Some(max) = self.max_cost_per_mtok
+            && !model.cost_per_mtok().is_some_and(|cost| cost <= max)
+        {
+            return false;
+        }
+
+        if self.available && !model.is_available() {
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, eyre::Result};
+
+    use crate::{ModelOutput, ModelTask};
+
+    use super::*;
+
+    struct TestModel {
+        id: &'static str,
+        r#type: ModelType,
+        inputs: &'static [ModelIO],
+        context_length: usize,
+        cost: Option<f64>,
+    }
+
+    #[async_trait]
+    impl Model for TestModel {
+        fn id(&self) -> String {
+            self.id.into()
+        }
+
+        fn r#type(&self) -> ModelType {
+            self.r#type.clone()
+        }
+
+        fn supported_inputs(&self) -> &[ModelIO] {
+            self.inputs
+        }
+
+        fn context_length(&self) -> usize {
+            self.context_length
+        }
+
+        fn cost_per_mtok(&self) -> Option<f64> {
+            self.cost
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    #[test]
+    fn dedupes_and_queries() {
+        let catalog = ModelCatalog::new([
+            Arc::new(TestModel {
+                id: "openai/gpt-a",
+                r#type: ModelType::Proxied,
+                inputs: &[ModelIO::Text],
+                context_length: 8_000,
+                cost: None,
+            }) as Arc<dyn Model>,
+            Arc::new(TestModel {
+                id: "openai/gpt-a",
+                r#type: ModelType::Remote,
+                inputs: &[ModelIO::Text, ModelIO::Image],
+                context_length: 128_000,
+                cost: Some(2.5),
+            }),
+            Arc::new(TestModel {
+                id: "google/gemini-b",
+                r#type: ModelType::Remote,
+                inputs: &[ModelIO::Text, ModelIO::Image],
+                context_length: 1_000_000,
+                cost: Some(10.0),
+            }),
+        ]);
+
+        assert_eq!(catalog.models().len(), 2);
+        assert!(matches!(
+            catalog.get("openai/gpt-a").map(|model| model.r#type()),
+            Some(ModelType::Remote)
+        ));
+
+        let ids = |query: ModelQuery| {
+            catalog
+                .find(&query)
+                .iter()
+                .map(|model| model.id())
+                .collect_vec()
+        };
+        assert_eq!(
+            ids(ModelQuery::new()
+                .input(ModelIO::Image)
+                .min_context(100_000)
+                .max_cost_per_mtok(5.0)),
+            vec!["openai/gpt-a"]
+        );
+        assert_eq!(
+            ids(ModelQuery::new().provider("Google")),
+            vec!["google/gemini-b"]
+        );
+        assert!(ids(ModelQuery::new().input(ModelIO::Audio)).is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use common::{
+    itertools::Itertools,
+    once_cell::sync::Lazy,
+    regex::{Captures, Regex},
+};
+use schema::{
+    Block, Citation, CitationGroup, CitationOptions, CreativeWorkType, Inline,
+    InstructionAttachment, MessagePart, Reference,
+    shortcuts::{p, t},
+};
+
+use crate::{ModelOutputPart, ModelTask};
+
+/// Extract citations from the content generated by a model
+///
+/// Detects URLs, DOIs, and numeric `[1]`-style reference markers in the `content`
+/// and resolves them against the attachments and messages of the `task`:
+///
+/// - numeric markers are resolved to the task attachment at that (one-based) position
+///   and are dropped if there is no such attachment
+/// - URLs matching the path or name of an attachment are resolved to that attachment
+/// - URLs matching the URL of an image, audio, or video part in one of the task's
+///   messages are recorded with the corresponding work type
+/// - all other URLs and DOIs are treated as external references
+///
+/// Citations are deduplicated by target and returned in order of first occurrence.
+pub fn extract_citations(content: &str, task: &ModelTask) -> Vec<Citation> {
+    static REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r#"(?P<url>https?://[^\s<>()\[\]"'`]+)|(?i:doi:\s*)?(?P<doi>\b10\.\d{4,9}/[^\s<>()\[\]"'`]+)|\[(?P<num>\d{1,3})\]"#,
+        )
+        .expect("invalid regex")
+    });
+
+    let attachments = task.attachments.as_deref().unwrap_or_default();
+
+    REGEX
+        .captures_iter(content)
+        .filter_map(|captures| citation_from_captures(&captures, attachments, task))
+        .unique_by(|citation| citation.target.clone())
+        .collect()
+}
+
+/// Add the web pages cited by a model (e.g. when using web search) to citations
+///
+/// Unlike citations extracted from the content, these are returned by the provider
+/// as structured annotations and so have titles. Pages which were already extracted
+/// from the content have their reference updated with the title and work type;
+/// other pages are appended.
+pub fn add_web_citations(citations: &mut Vec<Citation>, parts: &[ModelOutputPart]) {
+    for part in parts {
+        let ModelOutputPart::WebCitation { url, title } = part else {
+            continue;
+        };
+
+        let index = match citations
+            .iter()
+            .position(|citation| &citation.target == url)
+        {
+            Some(index) => index,
+            None => {
+                citations.push(url_citation(url, None));
+                citations.len() - 1
+            }
+        };
+
+        if let Some(reference) = citations[index].options.cites.as_mut() {
+            reference.work_type.get_or_insert(CreativeWorkType::WebPage);
+            if reference.title.is_none() {
+                reference.title = title.as_ref().map(|title| vec![t(title)]);
+            }
+        }
+    }
+}
+
+/// Create a block listing the citations of a model output
+///
+/// Appended to the blocks generated from a text output so that the sources cited
+/// by the model are recorded as structured citations in the document. Returns
+/// `None` if there are no citations.
+pub fn citations_block(citations: &[Citation]) -> Option<Block> {
+    (!citations.is_empty()).then(|| {
+        p([Inline::CitationGroup(CitationGroup::new(
+            citations.to_vec(),
+        ))])
+    })
+}
+
+/// Create a citation from a regex match, resolving against attachments and messages
+fn citation_from_captures(
+    captures: &Captures,
+    attachments: &[InstructionAttachment],
+    task: &ModelTask,
+) -> Option<Citation> {
+    if let Some(num) = captures.name("num") {
+        let index = num.as_str().parse::<usize>().ok()?.checked_sub(1)?;
+        let attachment = attachments.get(index)?;
+        return Some(attachment_citation(attachment));
+    }
+
+    if let Some(url) = captures.name("url") {
+        let url = trim_trailing_punctuation(url.as_str());
+
+        if let Some(attachment) = attachments.iter().find(|attachment| {
+            let path = attachment.file.path.trim();
+            let name = attachment.file.name.trim();
+            (!path.is_empty() && url.ends_with(path)) || (!name.is_empty() && url.ends_with(name))
+        }) {
+            return Some(attachment_citation(attachment));
+        }
+
+        if let Some(doi) = url
+            .strip_prefix("https://doi.org/")
+            .or_else(|| url.strip_prefix("http://doi.org/"))
+            .or_else(|| url.strip_prefix("https://dx.doi.org/"))
+        {
+            return Some(doi_citation(doi));
+        }
+
+        let work_type = task.messages.iter().find_map(|message| {
+            message.parts.iter().find_map(|part| match part {
+                MessagePart::ImageObject(object) if object.content_url == url => {
+                    Some(CreativeWorkType::ImageObject)
+                }
+                MessagePart::AudioObject(object) if object.content_url == url => {
+                    Some(CreativeWorkType::AudioObject)
+                }
+                MessagePart::VideoObject(object) if object.content_url == url => {
+                    Some(CreativeWorkType::VideoObject)
+                }
+                _ => None,
+            })
+        });
+
+        return Some(url_citation(url, work_type));
+    }
+
+    if let Some(doi) = captures.name("doi") {
+        return Some(doi_citation(trim_trailing_punctuation(doi.as_str())));
+    }
+
+    None
+}
+
+/// Trim punctuation which commonly trails a URL or DOI at the end of a sentence
+fn trim_trailing_punctuation(string: &str) -> &str {
+    string.trim_end_matches(['.', ',', ';', ':', '!', '?'])
+}
+
+/// Create a citation to a task attachment
+fn attachment_citation(attachment: &InstructionAttachment) -> Citation {
+    let path = attachment.file.path.trim();
+    Citation {
+        target: attachment.alias.clone(),
+        options: Box::new(CitationOptions {
+            cites: Some(Reference {
+                id: Some(attachment.alias.clone()),
+                title: Some(vec![t(&attachment.file.name)]),
+                url: (!path.is_empty()).then(|| path.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Create a citation to a DOI
+fn doi_citation(doi: &str) -> Citation {
+    let url = ["https://doi.org/", doi].concat();
+    Citation {
+        target: url.clone(),
+        options: Box::new(CitationOptions {
+            cites: Some(Reference {
+                doi: Some(doi.to_string()),
+                url: Some(url),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Create a citation to a URL
+///
+/// The `work_type` is set if the URL was resolved to a media part of one of the task's messages.
+fn url_citation(url: &str, work_type: Option<CreativeWorkType>) -> Citation {
+    Citation {
+        target: url.to_string(),
+        options: Box::new(CitationOptions {
+            cites: Some(Reference {
+                work_type,
+                url: Some(url.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::eyre::{Result, bail};
+    use schema::File;
+
+    use super::*;
+
+    #[test]
+    fn extracts_and_resolves() {
+        let task = ModelTask {
+            attachments: Some(vec![InstructionAttachment {
+                alias: "transects".into(),
+                file: File::new("transects.csv".into(), "outputs/transects.csv".into()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let citations = extract_citations(
+            "See [1] and [2], https://example.org/a. Also doi:10.1234/abc.5, \
+            https://doi.org/10.1234/abc.5 and https://host/outputs/transects.csv.",
+            &task,
+        );
+        let targets = citations
+            .iter()
+            .map(|citation| citation.target.as_str())
+            .collect_vec();
+
+        assert_eq!(
+            targets,
+            vec![
+                "transects",
+                "https://example.org/a",
+                "https://doi.org/10.1234/abc.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_web_citations() {
+        let mut citations = extract_citations("See https://example.org/a.", &ModelTask::default());
+        add_web_citations(
+            &mut citations,
+            &[
+                ModelOutputPart::WebCitation {
+                    url: "https://example.org/a".into(),
+                    title: Some("A".into()),
+                },
+                ModelOutputPart::WebCitation {
+                    url: "https://example.org/b".into(),
+                    title: None,
+                },
+            ],
+        );
+
+        assert_eq!(citations.len(), 2);
+        let reference = citations[0].options.cites.clone().unwrap_or_default();
+        assert_eq!(reference.title, Some(vec![t("A")]));
+        assert_eq!(reference.work_type, Some(CreativeWorkType::WebPage));
+        assert_eq!(citations[1].target, "https://example.org/b");
+    }
+
+    #[test]
+    fn creates_citations_block() -> Result<()> {
+        assert_eq!(citations_block(&[]), None);
+
+        let citations = extract_citations("See https://example.org/a.", &ModelTask::default());
+        let Some(Block::Paragraph(paragraph)) = citations_block(&citations) else {
+            bail!("expected paragraph")
+        };
+        assert_eq!(
+            paragraph.content,
+            vec![Inline::CitationGroup(CitationGroup::new(citations))]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use common::eyre::{Result, bail};
+use format::Format;
+use schema::{CodeChunk, Cord};
+
+use crate::{ModelOutput, ModelTask, Validator, kinds::insert_system_message};
+
+/// Prepare a code generation task
+///
+/// Errors if the task has no `code_language`. Instructs the model to respond
+/// with a single fenced code block and adds a validator which checks the
+/// syntax of the code in that block (see [`check_code`]) so that the model is
+/// re-prompted if it is obviously invalid.
+pub(crate) fn prepare(task: &mut ModelTask) -> Result<()> {
+    let Some(language) = task
+        .code_language
+        .as_deref()
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(String::from)
+    else {
+        bail!("Code generation tasks require a `code_language`");
+    };
+
+    insert_system_message(
+        task,
+        format!(
+            "Respond only with {language} code in a single fenced code block starting with ```{}. \
+Do not add any explanation or notes outside of the code block: use comments within the code instead.",
+            language.to_lowercase()
+        ),
+    );
+
+    task.format = Format::Markdown;
+    task.validators.push(Validator::predicate(move |content| {
+        check_code(&language, extract_code(content))
+    }));
+
+    Ok(())
+}
+
+/// Finish a code generation task
+///
+/// Replaces the content of the output with the code within its code block and
+/// sets the format of the output to the language of the code.
+pub(crate) fn finish(task: &ModelTask, output: &mut ModelOutput) {
+    output.content = extract_code(&output.content).to_string();
+    output.format = task
+        .code_language
+        .as_deref()
+        .map(Format::from_name)
+        .unwrap_or_default();
+}
+
+/// Extract the code from the first fenced code block in content
+///
+/// Returns the trimmed content if it has no code block.
+fn extract_code(content: &str) -> &str {
+    let Some((.., rest)) = content.split_once("```") else {
+        return content.trim();
+    };
+    let Some((.., body)) = rest.split_once('\n') else {
+        return content.trim();
+    };
+    match body.find("\n```") {
+        Some(end) => &body[..end],
+        None => body.strip_suffix("```").unwrap_or(body),
+    }
+    .trim_matches('\n')
+}
+
+/// Check the syntax of code
+///
+/// These are sanity checks, rather than a full parse, which catch the most
+/// common ways in which generated code is broken (e.g. truncation): that the
+/// code is not empty and that brackets are balanced. For Python, indentation
+/// is also checked.
+pub fn check_code(language: &str, code: &str) -> Result<(), String> {
+    if code.trim().is_empty() {
+        return Err("the code block is empty".into());
+    }
+
+    let format = Format::from_name(language);
+    let comment = match format {
+        Format::Python | Format::R | Format::Bash | Format::Shell => Some("#"),
+        Format::JavaScript => Some("//"),
+        _ => None,
+    };
+
+    check_brackets(code, comment)?;
+
+    if matches!(format, Format::Python) {
+        check_python_indentation(code)?;
+    }
+
+    Ok(())
+}
+
+/// Check that the brackets in code are balanced, ignoring those in strings and comments
+fn check_brackets(code: &str, comment: Option<&str>) -> Result<(), String> {
+    let mut stack = Vec::new();
+    for (index, line) in code.lines().enumerate() {
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
+        for (position, char) in line.char_indices() {
+            if let Some(open) = quote {
+                if escaped {
+                    escaped = false;
+                } else if char == '\\' {
+                    escaped = true;
+                } else if char == open {
+                    quote = None;
+                }
+                continue;
+            }
+
+            if comment.is_some_and(|comment| line[position..].starts_with(comment)) {
+                break;
+            }
+
+            match char {
+                '"' | '\'' | '`' => quote = Some(char),
+                '(' | '[' | '{' => stack.push((char, index + 1)),
+                ')' | ']' | '}' => {
+                    let expected = match char {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    match stack.pop() {
+                        Some((open, ..)) if open == expected => {}
+                        Some((open, line)) => {
+                            return Err(format!(
+                                "`{char}` on line {} does not match `{open}` on line {line}",
+                                index + 1
+                            ));
+                        }
+                        None => {
+                            return Err(format!("unmatched `{char}` on line {}", index + 1));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match stack.pop() {
+        Some((open, line)) => Err(format!("unclosed `{open}` on line {line}")),
+        None => Ok(()),
+    }
+}
+
+/// Check that the indentation of Python code is consistent
+///
+/// Lines within brackets (i.e. continuation lines) and blank lines are ignored.
+fn check_python_indentation(code: &str) -> Result<(), String> {
+    let mut levels = vec![0usize];
+    let mut expect_block = false;
+    let mut depth = 0i32;
+
+    for (index, line) in code.lines().enumerate() {
+        let number = index + 1;
+        let content = line.trim_start();
+        let continuation = depth > 0;
+        depth += line.matches(['(', '[', '{']).count() as i32
+            - line.matches([')', ']', '}']).count() as i32;
+        if content.is_empty() || content.starts_with('#') || continuation {
+            continue;
+        }
+
+        let indent = &line[..line.len() - content.len()];
+        if indent.contains(' ') && indent.contains('\t') {
+            return Err(format!(
+                "line {number} mixes tabs and spaces in its indentation"
+            ));
+        }
+        let width = indent.len();
+        let current = levels.last().copied().unwrap_or_default();
+
+        if expect_block {
+            if width <= current {
+                return Err(format!("expected an indented block on line {number}"));
+            }
+            levels.push(width);
+        } else if width > current {
+            return Err(format!("unexpected indent on line {number}"));
+        } else if width < current {
+            while levels.last().is_some_and(|&level| level > width) {
+                levels.pop();
+            }
+            if levels.last() != Some(&width) {
+                return Err(format!(
+                    "the indentation of line {number} does not match any outer level"
+                ));
+            }
+        }
+
+        let code = content.split(" #").next().unwrap_or(content).trim_end();
+        expect_block = code.ends_with(':') && depth <= 0;
+    }
+
+    if expect_block {
+        return Err("the code ends where an indented block was expected".into());
+    }
+
+    Ok(())
+}
+
+impl ModelOutput {
+    /// Create a [`CodeChunk`] from the output of a code generation task
+    ///
+    /// The programming language of the chunk is the format of the output,
+    /// if it is known, so that it can be executed when inserted into a document.
+    pub fn to_code_chunk(&self) -> CodeChunk {
+        let mut chunk = CodeChunk::new(Cord::from(self.content.as_str()));
+        if !self.format.is_unknown() {
+            chunk.programming_language = Some(self.format.to_string());
+        }
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ModelTaskKind, finish_specialized_task, specialize_task};
+
+    use super::*;
+
+    #[test]
+    fn checks_code() {
+        assert!(check_code("python", "def f(x):\n    return [x, (x + 1)]\n").is_ok());
+        assert!(check_code("python", "s = ')' # (\nprint(s)").is_ok());
+        assert!(check_code("python", "if x:\n    y = foo(\n  1,\n)\nelse:\n    pass").is_ok());
+        assert_eq!(
+            check_code("python", "print((1)"),
+            Err("unclosed `(` on line 1".into())
+        );
+        assert_eq!(
+            check_code("r", "f <- function(x) {\n  x]\n}"),
+            Err("`]` on line 2 does not match `{` on line 1".into())
+        );
+        assert_eq!(
+            check_code("python", "for x in y:\nprint(x)"),
+            Err("expected an indented block on line 2".into())
+        );
+        assert_eq!(
+            check_code("python", "if x:\n    a = 1\n  b = 2"),
+            Err("the indentation of line 3 does not match any outer level".into())
+        );
+        assert_eq!(
+            check_code("python", "def f():"),
+            Err("the code ends where an indented block was expected".into())
+        );
+    }
+
+    #[test]
+    fn prepare_and_finish() -> Result<()> {
+        let mut task = ModelTask {
+            kind: ModelTaskKind::CodeGeneration,
+            ..Default::default()
+        };
+        assert!(specialize_task(&mut task.clone()).is_err());
+
+        task.code_language = Some("Python".into());
+        let Some(kind) = specialize_task(&mut task)? else {
+            bail!("expected task to be specialized")
+        };
+        assert_eq!(task.validators.len(), 1);
+        assert!(
+            task.validators[0]
+                .validate("Here you go:\n\n```python\nprint(1\n```")
+                .is_err()
+        );
+
+        let mut output = ModelOutput {
+            content: "```python\nprint(1)\n```".into(),
+            ..Default::default()
+        };
+        assert!(task.validators[0].validate(&output.content).is_ok());
+        finish_specialized_task(kind, &task, &mut output)?;
+        assert_eq!(output.content, "print(1)");
+        assert_eq!(output.format, Format::Python);
+
+        let chunk = output.to_code_chunk();
+        assert_eq!(chunk.code.as_str(), "print(1)");
+        assert_eq!(chunk.programming_language.as_deref(), Some("python"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use common::{
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Model, ModelTask, Policy, task::messages_to_prompt_string};
+
+/// Options for compressing the prompt of a task
+///
+/// When the estimated number of tokens in the messages of a task exceeds the
+/// `threshold`, older conversation turns are summarized by a (usually small and cheap)
+/// model and replaced with the summary. System messages and the latest turns are kept verbatim.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct PromptCompression {
+    /// The estimated number of tokens above which the prompt is compressed
+    pub threshold: usize,
+
+    /// The number of latest (non-system) messages to keep verbatim
+    #[serde(default = "PromptCompression::default_keep_latest")]
+    pub keep_latest: usize,
+
+    /// The id pattern of the model used to summarize older messages
+    pub model: String,
+}
+
+impl PromptCompression {
+    fn default_keep_latest() -> usize {
+        2
+    }
+}
+
+/// Estimate the number of tokens in the messages of a task
+///
+/// Uses a heuristic of four characters per token.
+pub fn estimate_prompt_tokens(task: &ModelTask) -> usize {
+    task.prompt_as_text()
+        .map(|text| text.chars().count().div_ceil(4))
+        .unwrap_or_default()
+}
+
+/// Compress the prompt of a task, if necessary, by summarizing older messages
+///
+/// The summarization request is checked against, and recorded by, the `policy`.
+/// Returns `true` if the messages of the task were compressed.
+pub async fn compress_prompt(
+    task: &mut ModelTask,
+    options: &PromptCompression,
+    summarizer: &dyn Model,
+    policy: &Policy,
+) -> Result<bool> {
+    let tokens = estimate_prompt_tokens(task);
+    if tokens <= options.threshold {
+        return Ok(false);
+    }
+
+    let (system, turns): (Vec<_>, Vec<_>) = task.messages.iter().cloned().partition(|message| {
+        matches!(
+            message.role,
+            Some(MessageRole::System | MessageRole::Developer)
+        )
+    });
+
+    if turns.len() <= options.keep_latest {
+        return Ok(false);
+    }
+
+    let (older, latest) = turns.split_at(turns.len() - options.keep_latest);
+    let Some(conversation) = messages_to_prompt_string(older) else {
+        return Ok(false);
+    };
+
+    tracing::debug!(
+        "Compressing prompt of ~{tokens} tokens by summarizing {} messages using `{}`",
+        older.len(),
+        summarizer.id()
+    );
+
+    let summary_task = ModelTask {
+        messages: vec![
+            InstructionMessage {
+                role: Some(MessageRole::System),
+                parts: vec![MessagePart::from(
+                    "Summarize the following conversation concisely. Preserve all facts, decisions, names, numbers and file references needed to continue it. Respond only with the summary.",
+                )],
+                ..Default::default()
+            },
+            InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::from(conversation)],
+                ..Default::default()
+            },
+        ],
+        temperature: Some(0.0),
+        dry_run: task.dry_run,
+        ..Default::default()
+    };
+    let summary = policy.perform(summarizer, &summary_task).await?.content;
+
+    let mut messages = system;
+    messages.push(InstructionMessage {
+        role: Some(MessageRole::System),
+        parts: vec![MessagePart::from(format!(
+            "Summary of the earlier conversation:\n\n{}",
+            summary.trim()
+        ))],
+        ..Default::default()
+    });
+    messages.extend_from_slice(latest);
+    task.messages = messages;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, tokio};
+
+    use crate::ModelOutput;
+
+    use super::*;
+
+    struct Summarizer;
+
+    #[async_trait]
+    impl Model for Summarizer {
+        fn id(&self) -> String {
+            "test/summarizer".into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            Ok(ModelOutput {
+                content: "They discussed transects.".into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn message(role: MessageRole, text: &str) -> InstructionMessage {
+        InstructionMessage {
+            role: Some(role),
+            parts: vec![MessagePart::from(text)],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn compresses() -> Result<()> {
+        let mut task = ModelTask {
+            messages: vec![
+                message(MessageRole::System, "Be brief."),
+                message(MessageRole::User, &"Describe transect 1. ".repeat(20)),
+                message(MessageRole::Model, &"Transect 1 is eroding. ".repeat(20)),
+                message(MessageRole::User, "And transect 2?"),
+            ],
+            ..Default::default()
+        };
+
+        let options = PromptCompression {
+            threshold: 50,
+            keep_latest: 1,
+            model: "test/summarizer".into(),
+        };
+        assert!(compress_prompt(&mut task, &options, &Summarizer, &Policy::default()).await?);
+        assert_eq!(task.messages.len(), 3);
+        assert_eq!(
+            task.prompt_as_text().unwrap_or_default(),
+            "System:\nBe brief.\n\n---\n\nSystem:\nSummary of the earlier conversation:\n\nThey discussed transects.\n\n---\n\nUser:\nAnd transect 2?"
+        );
+
+        assert!(!compress_prompt(&mut task, &options, &Summarizer, &Policy::default()).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    collections::BTreeMap,
+    fs::read_to_string,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use common::{
+    eyre::{Report, Result, bail},
+    itertools::Itertools,
+    once_cell::sync::Lazy,
+    regex::Regex,
+    reqwest::{self, Client},
+    serde::{Deserialize, Serialize},
+    toml, tracing,
+};
+use dirs::{DirType, get_app_dir};
+
+use crate::{
+    ApiKeyRoute, AttachmentDeltaOptions, AttachmentExpansion, AttestationConfig,
+    DerivedCacheConfig, ModelAlias, ModelDeprecation, ModelIO, ModelTask, Policy, PostProcessing,
+    PreviewOptions, RunStoreConfig, SafetyFilter, estimate_prompt_tokens,
+};
+
+/// Configuration shared by model providers
+///
+/// Loaded from the top level of the `models.toml` file in the Stencila config
+/// directory (other tables in that file, such as `[list]`, are ignored), or set
+/// programmatically using [`set_models_config`]. Provider crates get the current
+/// configuration using [`models_config`] rather than reading environment variables
+/// or hard coding values.
+///
+/// Related settings are grouped into tables so that functions can be passed
+/// only the group they need (e.g. the [`Policy`]) rather than the whole config.
+///
+/// Example `models.toml`:
+///
+/// ```toml
+/// [requests]
+/// timeout = 60
+/// default-temperature = 0.2
+///
+/// [requests.base-urls]
+/// openai = "https://example.org/openai/v1"
+///
+/// [requests.cost-caps]
+/// max-output-tokens = 4000
+///
+/// [retry]
+/// max-retries = 3
+///
+/// [records.audit]
+/// enabled = true
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct ModelsConfig {
+    /// Options for requests to provider APIs
+    pub requests: RequestsConfig,
+
+    /// The policy for retrying failed tasks
+    pub retry: RetryPolicy,
+
+    /// Limits on the requests made to providers by the task queue
+    pub queue: QueueConfig,
+
+    /// Rules allowing or denying the flow of tasks, and their attachments, to models
+    pub policy: Policy,
+
+    /// Providers, aliases and deprecations of models, in addition to the built-in ones
+    pub catalog: CatalogConfig,
+
+    /// Options for the expansion, previews, diffs and uploads of attachments
+    pub attachments: AttachmentsConfig,
+
+    /// Options for caches
+    pub cache: CacheConfig,
+
+    /// Options for the checking and post-processing of outputs
+    pub outputs: OutputsConfig,
+
+    /// Options for recording tasks and their outputs
+    pub records: RecordsConfig,
+}
+
+/// Options for requests to provider APIs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct RequestsConfig {
+    /// Base URLs of provider APIs, keyed by provider name e.g. `openai`
+    ///
+    /// Providers use their default base URL if there is no entry for them.
+    pub base_urls: BTreeMap<String, String>,
+
+    /// The timeout for requests to provider APIs, in seconds
+    pub timeout: u64,
+
+    /// Rules routing tasks to one of several API keys for a provider
+    pub api_keys: Vec<ApiKeyRoute>,
+
+    /// The temperature used for tasks that do not specify one
+    pub default_temperature: Option<f32>,
+
+    /// Caps on the cost of each task
+    pub cost_caps: CostCaps,
+}
+
+impl Default for RequestsConfig {
+    fn default() -> Self {
+        Self {
+            base_urls: BTreeMap::new(),
+            timeout: 120,
+            api_keys: Vec::new(),
+            default_temperature: None,
+            cost_caps: CostCaps::default(),
+        }
+    }
+}
+
+/// Providers, aliases and deprecations of models, in addition to the built-in ones
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct CatalogConfig {
+    /// Providers with OpenAI compatible APIs, in addition to the built-in ones
+    pub providers: Vec<ProviderConfig>,
+
+    /// Named aliases for models, with default parameters, keyed by alias name
+    pub aliases: BTreeMap<String, ModelAlias>,
+
+    /// Deprecated models, keyed by model id, in addition to the built-in list
+    pub deprecations: BTreeMap<String, ModelDeprecation>,
+
+    /// Whether to substitute deprecated models with their recommended replacement
+    ///
+    /// When `false` (the default) a warning is added to the output but the
+    /// deprecated model is still used.
+    pub substitute_deprecated: bool,
+}
+
+/// Options for the expansion, previews, diffs and uploads of attachments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct AttachmentsConfig {
+    /// Limits on the files that attachments pointing at directories or globs expand into
+    pub expansion: AttachmentExpansion,
+
+    /// Options for the previews sent for attachments with unsupported media types
+    pub preview: PreviewOptions,
+
+    /// Options for sending changes to previously sent attachments as diffs
+    pub delta: AttachmentDeltaOptions,
+
+    /// The maximum size, in bytes, of image attachments sent inline as data URLs
+    ///
+    /// Larger images are uploaded, by providers that support file uploads.
+    pub inline_image_max_bytes: u64,
+
+    /// The maximum number of attachments uploaded concurrently for each task
+    pub upload_concurrency: usize,
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        Self {
+            expansion: AttachmentExpansion::default(),
+            preview: PreviewOptions::default(),
+            delta: AttachmentDeltaOptions::default(),
+            inline_image_max_bytes: 512 * 1024,
+            upload_concurrency: 4,
+        }
+    }
+}
+
+/// Options for caches
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct CacheConfig {
+    /// Options for caching artifacts derived from attachments
+    pub derived: DerivedCacheConfig,
+}
+
+/// Options for the checking and post-processing of outputs
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct OutputsConfig {
+    /// The filter of disallowed content in outputs
+    pub safety: SafetyFilter,
+
+    /// The post-processing of Markdown generated by models
+    pub post_processing: PostProcessing,
+
+    /// Whether to fail on malformed JSON generated by models rather than repairing it
+    ///
+    /// When `false` (the default) trailing commas, single quotes, unescaped
+    /// newlines and unbalanced braces are repaired before the JSON is decoded.
+    pub strict_json: bool,
+}
+
+/// Options for recording tasks and their outputs
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct RecordsConfig {
+    /// Options for recording requests and responses for auditing
+    pub audit: AuditConfig,
+
+    /// Options for signing attestations of outputs
+    pub attestation: AttestationConfig,
+
+    /// Options for storing a directory of the request, response and artifacts of each task
+    pub runs: RunStoreConfig,
+}
+
+/// The policy for retrying failed tasks
+///
+/// Only transient errors are retried (see [`RetryPolicy::is_retryable`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct RetryPolicy {
+    /// The maximum number of times to retry a failed task
+    pub max_retries: u32,
+
+    /// The delay before the first retry, in milliseconds
+    ///
+    /// The delay is doubled for each subsequent retry.
+    pub initial_backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: 500,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a failed task should be retried
+    ///
+    /// Only transient errors are retried: rate limits (429), server errors (5xx),
+    /// and transport errors such as timeouts and failed connections. Other errors,
+    /// such as invalid requests (400) or authentication failures (401 and 403),
+    /// would fail again and so are not.
+    pub fn is_retryable(error: &Report) -> bool {
+        let is_transient = |status: u16| status == 429 || (500..600).contains(&status);
+
+        for cause in error.chain() {
+            if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+                return match error.status() {
+                    Some(status) => is_transient(status.as_u16()),
+                    None => error.is_timeout() || error.is_connect() || error.is_request(),
+                };
+            }
+        }
+
+        // Provider crates usually include the status of a failed request in the
+        // message of the error, rather than the `reqwest` error itself
+        static STATUS: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"(?:HTTP status (?:client|server) error \(|returned )(\d{3})\b")
+                .expect("invalid regex")
+        });
+        static TRANSPORT: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r"(?i)error sending request|error trying to connect|operation timed out|connection (?:refused|reset|closed)",
+            )
+            .expect("invalid regex")
+        });
+
+        let message = error.chain().map(|cause| cause.to_string()).join(": ");
+        match STATUS
+            .captures(&message)
+            .and_then(|captures| captures[1].parse().ok())
+        {
+            Some(status) => is_transient(status),
+            None => TRANSPORT.is_match(&message),
+        }
+    }
+
+    /// The delay before a retry (the first retry is `attempt` 1)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(
+            self.initial_backoff
+                .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1))),
+        )
+    }
+}
+
+/// Caps on the cost of each task
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct CostCaps {
+    /// The maximum number of tokens a task may generate
+    ///
+    /// The `max_tokens` of tasks is reduced to this value if it is not set or is greater.
+    pub max_output_tokens: Option<u16>,
+
+    /// The maximum (estimated) number of tokens in the prompt of a task
+    ///
+    /// Tasks with longer prompts fail before any request is made.
+    pub max_prompt_tokens: Option<usize>,
+}
+
+/// Options for recording requests and responses for auditing
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct AuditConfig {
+    /// Whether to record the request and response of each task
+    ///
+    /// Can be overridden by the `audit` option of a task.
+    pub enabled: bool,
+
+    /// The directory to write audit records to
+    ///
+    /// Defaults to the `audit` subdirectory of the Stencila cache directory.
+    pub dir: Option<PathBuf>,
+}
+
+/// Limits on the requests made to providers by the task queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct QueueConfig {
+    /// The maximum number of tasks performed concurrently, across all providers
+    pub max_concurrent: usize,
+
+    /// The maximum number of requests per minute, keyed by provider name e.g. `openai`
+    ///
+    /// Providers without an entry are not rate limited.
+    pub requests_per_minute: BTreeMap<String, u32>,
+
+    /// The maximum number of requests per minute for each user, across all providers
+    ///
+    /// Only applies to tasks with a `user`.
+    pub requests_per_minute_per_user: Option<u32>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 8,
+            requests_per_minute: BTreeMap::new(),
+            requests_per_minute_per_user: None,
+        }
+    }
+}
+
+/// A provider with an OpenAI compatible API e.g. a self-hosted vLLM server
+///
+/// ```toml
+/// [[catalog.providers]]
+/// name = "Lab vLLM"
+/// base-url = "http://gpu01.internal:8000/v1"
+///
+/// [[catalog.providers.models]]
+/// id = "Qwen/Qwen2.5-7B-Instruct"
+/// context-length = 32768
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct ProviderConfig {
+    /// The name of the provider
+    ///
+    /// The lowercase, hyphenated, name is used as the prefix for the ids of the provider's models.
+    pub name: String,
+
+    /// The base URL of the API
+    pub base_url: String,
+
+    /// The name of the env var or secret for the API key
+    ///
+    /// If not specified, requests are made without authentication.
+    pub key_env: Option<String>,
+
+    /// The provider's models
+    ///
+    /// If empty, models are listed using the provider's `/models` endpoint.
+    pub models: Vec<ModelSpec>,
+}
+
+/// A model of a provider defined in the models config
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct ModelSpec {
+    /// The provider's id for the model
+    pub id: String,
+
+    /// The context length of the model
+    pub context_length: Option<usize>,
+
+    /// The input types supported by the model
+    ///
+    /// If empty, the input types are inferred from the id of the model.
+    pub inputs: Vec<ModelIO>,
+
+    /// The cost of the model in US dollars per million tokens
+    pub cost_per_mtok: Option<f64>,
+}
+
+impl ModelsConfig {
+    /// Load the configuration from the `models.toml` file, if it exists
+    ///
+    /// Errors reading the file are logged and the default configuration used instead.
+    pub fn load() -> Self {
+        match Self::read_file() {
+            Ok(config) => config,
+            Err(error) => {
+                tracing::warn!("Unable to read models config file: {error}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Read the configuration from the config file
+    fn read_file() -> Result<Self> {
+        let path = get_app_dir(DirType::Config, false)?.join("models.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(toml::from_str(&read_to_string(path)?)?)
+    }
+}
+
+impl RequestsConfig {
+    /// Get the base URL for a provider, falling back to its default
+    pub fn base_url(&self, provider: &str, default: &str) -> String {
+        self.base_urls
+            .get(provider)
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// Get the timeout for requests to provider APIs
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    /// Create an HTTP client, with the configured timeout, for requests to provider APIs
+    ///
+    /// Errors if the client can not be built, rather than falling back to a
+    /// client without a timeout.
+    pub fn http_client(&self) -> Result<Client> {
+        Ok(Client::builder().timeout(self.timeout()).build()?)
+    }
+
+    /// Apply defaults and cost caps to a task
+    ///
+    /// Sets the temperature and maximum number of tokens of the task, and
+    /// errors if its prompt is longer than allowed.
+    pub fn apply(&self, task: &mut ModelTask) -> Result<()> {
+        if task.temperature.is_none() {
+            task.temperature = self.default_temperature;
+        }
+
+        if let Some(max) = self.cost_caps.max_output_tokens {
+            task.max_tokens = Some(task.max_tokens.map_or(max, |tokens| tokens.min(max)));
+        }
+
+        if let Some(max) = self.cost_caps.max_prompt_tokens {
+            let tokens = estimate_prompt_tokens(task);
+            if tokens > max {
+                bail!("Prompt of ~{tokens} tokens exceeds the configured maximum of {max} tokens");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The current configuration
+static CONFIG: Lazy<RwLock<Arc<ModelsConfig>>> =
+    Lazy::new(|| RwLock::new(Arc::new(ModelsConfig::load())));
+
+/// Set the configuration used by model providers
+pub fn set_models_config(config: ModelsConfig) {
+    if let Ok(mut current) = CONFIG.write() {
+        *current = Arc::new(config);
+    }
+}
+
+/// Get the configuration used by model providers
+pub fn models_config() -> Arc<ModelsConfig> {
+    CONFIG
+        .read()
+        .map(|config| config.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::eyre::eyre;
+
+    use super::*;
+
+    #[test]
+    fn parses_and_applies() -> Result<()> {
+        let config: ModelsConfig = toml::from_str(
+            r#"
+[requests]
+timeout = 60
+default-temperature = 0.2
+
+[requests.base-urls]
+openai = "https://example.org/openai/v1/"
+
+[requests.cost-caps]
+max-output-tokens = 100
+
+[retry]
+max-retries = 3
+
+[list]
+exclude = ["openai/*"]
+
+[[catalog.providers]]
+name = "LM Studio"
+base-url = "http://localhost:1234/v1"
+
+[[catalog.providers.models]]
+id = "qwen2.5-7b-instruct"
+context-length = 32768
+inputs = ["text", "image"]
+"#,
+        )?;
+
+        assert_eq!(config.requests.timeout(), Duration::from_secs(60));
+        assert_eq!(
+            config
+                .requests
+                .base_url("openai", "https://api.openai.com/v1"),
+            "https://example.org/openai/v1"
+        );
+        assert_eq!(
+            config
+                .requests
+                .base_url("mistral", "https://api.mistral.ai/v1"),
+            "https://api.mistral.ai/v1"
+        );
+        assert_eq!(config.retry.backoff(3), Duration::from_millis(2000));
+        assert_eq!(config.catalog.providers[0].key_env, None);
+        assert_eq!(
+            config.catalog.providers[0].models[0].context_length,
+            Some(32768)
+        );
+
+        let mut task = ModelTask {
+            max_tokens: Some(500),
+            ..Default::default()
+        };
+        config.requests.apply(&mut task)?;
+        assert_eq!(task.temperature, Some(0.2));
+        assert_eq!(task.max_tokens, Some(100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let retryable = |message: &str| RetryPolicy::is_retryable(&eyre!(message.to_string()));
+
+        assert!(retryable(
+            "HTTP status client error (429 Too Many Requests) for url (https://api.anthropic.com/v1/messages): {}"
+        ));
+        assert!(retryable(
+            "OpenAI responses returned 503 Service Unavailable: "
+        ));
+        assert!(retryable(
+            "error sending request for url (https://api.mistral.ai/v1/chat/completions)"
+        ));
+        assert!(retryable("operation timed out"));
+
+        assert!(!retryable(
+            "HTTP status client error (400 Bad Request) for url (https://api.anthropic.com/v1/messages): {}"
+        ));
+        assert!(!retryable("OpenAI files returned 401 Unauthorized: "));
+        assert!(!retryable("API key `OPENAI_API_KEY` is not available"));
+        assert!(!retryable("Model `openai/gpt-4o` does not support audio"));
+    }
+}
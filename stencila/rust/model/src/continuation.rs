@@ -0,0 +1,231 @@
+use common::{eyre::Result, tracing};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{DeltaCallback, FinishReason, Model, ModelOutput, ModelOutputKind, ModelTask, Policy};
+
+/// The prompt used to ask the model to continue truncated output
+const CONTINUE_PROMPT: &str = "Continue exactly where you left off. Do not repeat any of your previous answer and do not add any preamble.";
+
+/// The minimum number of characters that must overlap for them to be removed when stitching
+///
+/// Shorter overlaps (e.g. a single space or letter) are too likely to be coincidental.
+const MIN_OVERLAP: usize = 8;
+
+/// The maximum number of characters at the end of a segment checked for overlap
+const MAX_OVERLAP: usize = 2000;
+
+/// Continue the output of a model while it is truncated
+///
+/// If the output stopped because the model reached its maximum number of output
+/// tokens, the model is asked to continue where it left off, up to
+/// [`ModelTask::auto_continue`] times, and each continuation stitched onto the
+/// content with any overlap removed. If `on_delta` is supplied it is called with
+/// the (de-duplicated) text of each continuation. Returns the output and the
+/// number of continuations requested.
+///
+/// Each continuation is a request in its own right so is checked against the
+/// `policy`, performed using the task queue, and its tokens recorded against the policy. The token usage of the
+/// output is the sum of that of the continuations (or `None` if any of them did
+/// not report usage).
+pub async fn auto_continue(
+    model: &dyn Model,
+    task: &ModelTask,
+    mut output: ModelOutput,
+    on_delta: Option<&DeltaCallback>,
+    policy: &Policy,
+) -> Result<(ModelOutput, usize)> {
+    let max_rounds = task.auto_continue.unwrap_or_default();
+    if max_rounds == 0 || task.dry_run || !matches!(output.kind, ModelOutputKind::Text) {
+        return Ok((output, 0));
+    }
+
+    let mut rounds = 0;
+    while output.finish_reason == Some(FinishReason::Length) && rounds < max_rounds {
+        rounds += 1;
+        tracing::debug!(
+            "Output of model `{}` is truncated, continuing (round {rounds})",
+            model.id()
+        );
+
+        // The content so far is sent as a single model message so that the
+        // prompt only grows by the length of the content with each round
+        let mut continue_task = task.clone();
+        continue_task.messages.push(InstructionMessage {
+            role: Some(MessageRole::Model),
+            parts: vec![MessagePart::from(output.content.as_str())],
+            ..Default::default()
+        });
+        continue_task.messages.push(InstructionMessage {
+            role: Some(MessageRole::User),
+            parts: vec![MessagePart::from(CONTINUE_PROMPT)],
+            ..Default::default()
+        });
+        continue_task.candidates = None;
+
+        let continuation = policy.perform(model, &continue_task).await?;
+
+        let appended = stitch_segments(&mut output.content, &continuation.content);
+        if let Some(on_delta) = on_delta {
+            on_delta(appended);
+        }
+        if let Some(candidate) = output
+            .selected_candidate
+            .and_then(|index| output.candidates.get_mut(index))
+        {
+            *candidate = output.content.clone();
+        }
+
+        output.finish_reason = continuation.finish_reason;
+        output.usage = output
+            .usage
+            .zip(continuation.usage)
+            .map(|(usage, more)| usage + more);
+        output.parts.extend(continuation.parts);
+        output.warnings.extend(continuation.warnings);
+
+        // Stop if the model has nothing more to add, to avoid looping on repeats
+        if appended.trim().is_empty() {
+            break;
+        }
+    }
+
+    Ok((output, rounds))
+}
+
+/// Stitch a continuation onto the end of content, removing any overlap
+///
+/// Models asked to continue often repeat the last few words (or lines) of their
+/// previous output. The longest prefix of the `continuation` which is also a
+/// suffix of the `content` (and at least a few characters long) is removed
+/// before appending. Returns the part of the continuation that was appended.
+pub fn stitch_segments<'c>(content: &mut String, continuation: &'c str) -> &'c str {
+    let max = content.len().min(continuation.len()).min(MAX_OVERLAP);
+    let overlap = (MIN_OVERLAP..=max)
+        .rev()
+        .filter(|&len| continuation.is_char_boundary(len))
+        .find(|&len| content.ends_with(&continuation[..len]))
+        .unwrap_or_default();
+
+    let appended = &continuation[overlap..];
+    content.push_str(appended);
+    appended
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use common::{async_trait::async_trait, eyre::eyre, tokio};
+
+    use crate::TokenUsage;
+
+    use super::*;
+
+    #[test]
+    fn stitches_segments() {
+        let mut content = String::from("Transect 3 is eroding at");
+        assert_eq!(
+            stitch_segments(&mut content, "is eroding at 0.4 m/yr."),
+            " 0.4 m/yr."
+        );
+        assert_eq!(content, "Transect 3 is eroding at 0.4 m/yr.");
+
+        // Short overlaps are assumed to be coincidental
+        let mut content = String::from("a b");
+        stitch_segments(&mut content, "b c");
+        assert_eq!(content, "a bb c");
+
+        // Continuations entirely repeating the content add nothing
+        let mut content = String::from("The end of the answer.");
+        assert_eq!(stitch_segments(&mut content, "of the answer."), "");
+        assert_eq!(content, "The end of the answer.");
+
+        // Multi-byte characters do not cause panics
+        let mut content = String::from("Café près de la plage");
+        stitch_segments(&mut content, "é près de la plage, oui");
+        assert_eq!(content, "Café près de la plage, oui");
+    }
+
+    struct Truncating {
+        segments: Mutex<Vec<&'static str>>,
+    }
+
+    #[async_trait]
+    impl Model for Truncating {
+        fn id(&self) -> String {
+            "test/truncating".into()
+        }
+
+        async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+            let prompt = task.prompt_as_text().unwrap_or_default();
+            assert!(prompt.contains(CONTINUE_PROMPT));
+            assert_eq!(prompt.matches(CONTINUE_PROMPT).count(), 1);
+
+            let mut segments = self.segments.lock().map_err(|_| eyre!("poisoned"))?;
+            let content = segments.remove(0);
+            Ok(ModelOutput {
+                content: content.into(),
+                finish_reason: Some(if segments.is_empty() {
+                    FinishReason::Stop
+                } else {
+                    FinishReason::Length
+                }),
+                usage: Some(TokenUsage {
+                    prompt_tokens: 100,
+                    output_tokens: 10,
+                    cached_tokens: 50,
+                }),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn continues_truncated_output() -> Result<()> {
+        let model = Truncating {
+            segments: Mutex::new(vec!["rates are falling at", "falling at 0.4 m/yr."]),
+        };
+        let truncated = || ModelOutput {
+            content: "Shoreline rates are".into(),
+            finish_reason: Some(FinishReason::Length),
+            usage: Some(TokenUsage {
+                prompt_tokens: 80,
+                output_tokens: 5,
+                cached_tokens: 0,
+            }),
+            ..Default::default()
+        };
+
+        let task = ModelTask {
+            auto_continue: Some(5),
+            ..Default::default()
+        };
+        let (output, rounds) =
+            auto_continue(&model, &task, truncated(), None, &Policy::default()).await?;
+        assert_eq!(output.content, "Shoreline rates are falling at 0.4 m/yr.");
+        assert_eq!(output.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(rounds, 2);
+        assert_eq!(
+            output.usage,
+            Some(TokenUsage {
+                prompt_tokens: 280,
+                output_tokens: 25,
+                cached_tokens: 100,
+            })
+        );
+
+        // Not continued unless enabled
+        let (output, rounds) = auto_continue(
+            &model,
+            &ModelTask::default(),
+            truncated(),
+            None,
+            &Policy::default(),
+        )
+        .await?;
+        assert_eq!(output.content, "Shoreline rates are");
+        assert_eq!(rounds, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::HashSet, fs::read};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::{Result, bail},
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_with::skip_serializing_none,
+};
+use format::Format;
+use schema::InstructionAttachment;
+
+use crate::{ModelOutput, ModelOutputPart, ModelTask, kinds::insert_system_message};
+
+/// Summary statistics of a tabular dataset
+///
+/// Computed locally, rather than by a model, so that the numbers in a data
+/// description can be verified and are recorded separately from the generated prose.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct DatasetStatistics {
+    /// The alias of the attachment containing the dataset
+    pub alias: String,
+
+    /// The number of rows in the dataset, excluding the header
+    pub rows: usize,
+
+    /// Statistics for each column of the dataset
+    pub columns: Vec<ColumnStatistics>,
+}
+
+/// Summary statistics of a column of a tabular dataset
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ColumnStatistics {
+    /// The name of the column
+    pub name: String,
+
+    /// Whether all non-missing values in the column are numbers
+    pub numeric: bool,
+
+    /// The number of non-missing values
+    pub count: usize,
+
+    /// The number of missing values e.g. empty cells and `NA`
+    pub missing: usize,
+
+    /// The number of distinct non-missing values
+    pub distinct: usize,
+
+    /// The minimum value of numeric columns
+    pub min: Option<f64>,
+
+    /// The maximum value of numeric columns
+    pub max: Option<f64>,
+
+    /// The mean of numeric columns
+    pub mean: Option<f64>,
+
+    /// The sample standard deviation of numeric columns with more than one value
+    pub std_dev: Option<f64>,
+}
+
+impl DatasetStatistics {
+    /// Compute statistics for delimiter separated text e.g. CSV
+    ///
+    /// The first line is assumed to be a header. Quoted fields are supported
+    /// but, for simplicity, may not span multiple lines.
+    pub fn from_text(alias: &str, text: &str, delimiter: char) -> Result<Self> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let Some(header) = lines.next() else {
+            bail!("Dataset `{alias}` is empty");
+        };
+        let names = split_record(header, delimiter);
+        let mut values: Vec<Vec<String>> = vec![Vec::new(); names.len()];
+
+        let mut rows = 0;
+        for line in lines {
+            rows += 1;
+            let mut fields = split_record(line, delimiter).into_iter();
+            for column in values.iter_mut() {
+                column.push(fields.next().unwrap_or_default());
+            }
+        }
+
+        let columns = names
+            .into_iter()
+            .zip(values)
+            .map(|(name, values)| ColumnStatistics::new(name, &values))
+            .collect();
+
+        Ok(Self {
+            alias: alias.into(),
+            rows,
+            columns,
+        })
+    }
+
+    /// Compute statistics for a CSV or TSV attachment
+    fn from_attachment(attachment: &InstructionAttachment) -> Result<Self> {
+        let file = &attachment.file;
+        let bytes = match &file.content {
+            Some(content) if file.options.transfer_encoding.as_deref() == Some("base64") => {
+                BASE64.decode(content.trim())?
+            }
+            Some(content) => content.as_bytes().to_vec(),
+            None => read(file.path.trim_start_matches("file://"))?,
+        };
+        let text = String::from_utf8(bytes)?;
+
+        let delimiter = if matches!(dataset_format(attachment), Some(Format::Tsv)) {
+            '\t'
+        } else {
+            ','
+        };
+
+        Self::from_text(&attachment.alias, &text, delimiter)
+    }
+
+    /// Render the statistics as a Markdown table
+    pub fn to_markdown(&self) -> String {
+        let number = |value: Option<f64>| {
+            value
+                .map(|value| ((value * 1e4).round() / 1e4).to_string())
+                .unwrap_or_default()
+        };
+
+        let mut markdown = format!(
+            "Dataset `{}` has {} rows and {} columns:\n\n| Column | Type | Count | Missing | Distinct | Min | Max | Mean | SD |\n|---|---|---|---|---|---|---|---|---|\n",
+            self.alias,
+            self.rows,
+            self.columns.len()
+        );
+        for column in &self.columns {
+            markdown.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {} |\n",
+                column.name,
+                if column.numeric { "numeric" } else { "text" },
+                column.count,
+                column.missing,
+                column.distinct,
+                number(column.min),
+                number(column.max),
+                number(column.mean),
+                number(column.std_dev)
+            ));
+        }
+
+        markdown
+    }
+}
+
+impl ColumnStatistics {
+    /// Compute statistics for the values of a column
+    fn new(name: String, values: &[String]) -> Self {
+        let present = values
+            .iter()
+            .map(|value| value.trim())
+            .filter(|value| !matches!(*value, "" | "NA" | "NaN" | "N/A" | "null"))
+            .collect_vec();
+        let count = present.len();
+        let missing = values.len() - count;
+        let distinct = present.iter().collect::<HashSet<_>>().len();
+
+        let numbers = present
+            .iter()
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|numbers| !numbers.is_empty());
+
+        let (min, max, mean, std_dev) = match &numbers {
+            Some(numbers) => {
+                let n = numbers.len() as f64;
+                let mean = numbers.iter().sum::<f64>() / n;
+                let std_dev = (numbers.len() > 1).then(|| {
+                    (numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.)).sqrt()
+                });
+                (
+                    numbers.iter().copied().reduce(f64::min),
+                    numbers.iter().copied().reduce(f64::max),
+                    Some(mean),
+                    std_dev,
+                )
+            }
+            None => (None, None, None, None),
+        };
+
+        Self {
+            name,
+            numeric: numbers.is_some(),
+            count,
+            missing,
+            distinct,
+            min,
+            max,
+            mean,
+            std_dev,
+        }
+    }
+}
+
+/// Split a line of delimiter separated text into fields, handling quotes
+fn split_record(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            _ if char == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(char),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+/// Get the format of an attachment if it is a CSV or TSV dataset
+fn dataset_format(attachment: &InstructionAttachment) -> Option<Format> {
+    let file = &attachment.file;
+    let format = match file.media_type.as_deref() {
+        Some("text/tab-separated-values") => Format::Tsv,
+        Some("text/csv") => Format::Csv,
+        _ => Format::from_url(&file.name),
+    };
+    matches!(format, Format::Csv | Format::Tsv).then_some(format)
+}
+
+/// Get the first dataset attached to a task
+fn dataset_attachment(task: &ModelTask) -> Result<&InstructionAttachment> {
+    match task
+        .attachments
+        .iter()
+        .flatten()
+        .find(|attachment| dataset_format(attachment).is_some())
+    {
+        Some(attachment) => Ok(attachment),
+        None => bail!("Data description tasks require a CSV or TSV attachment"),
+    }
+}
+
+/// Prepare a data description task
+///
+/// Computes statistics for the first CSV or TSV attachment of the task and adds
+/// them to the system prompt along with instructions to write a methods-style
+/// description of the dataset.
+pub(crate) fn prepare(task: &mut ModelTask) -> Result<()> {
+    let statistics = DatasetStatistics::from_attachment(dataset_attachment(task)?)?;
+
+    insert_system_message(
+        task,
+        format!(
+            "Write a concise description of the dataset `{}`, suitable for the methods section of a \
+scientific paper. Describe its structure, variables and coverage. Only use the following \
+statistics, which were computed from the dataset, for any numbers you report. Do not speculate \
+about how the data was collected unless told.\n\n{}",
+            statistics.alias,
+            statistics.to_markdown()
+        ),
+    );
+    task.format = Format::Markdown;
+
+    Ok(())
+}
+
+/// Finish a data description task
+///
+/// Adds the statistics of the dataset to the output as a separate part so that
+/// the computed numbers are distinguishable from the generated description (the content).
+pub(crate) fn finish(task: &ModelTask, output: &mut ModelOutput) -> Result<()> {
+    let statistics = DatasetStatistics::from_attachment(dataset_attachment(task)?)?;
+    output
+        .parts
+        .push(ModelOutputPart::DatasetStatistics(statistics));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::File;
+
+    use crate::{ModelTaskKind, finish_specialized_task, specialize_task};
+
+    use super::*;
+
+    #[test]
+    fn statistics() -> Result<()> {
+        let stats = DatasetStatistics::from_text(
+            "transects",
+            "id,site,rate\n1,\"Narrabeen, NSW\",-0.5\n2,Collaroy,NA\n3,Collaroy,0.5\n",
+            ',',
+        )?;
+        assert_eq!(stats.rows, 3);
+        assert_eq!(stats.columns.len(), 3);
+
+        let site = &stats.columns[1];
+        assert!(!site.numeric);
+        assert_eq!((site.count, site.distinct), (3, 2));
+
+        let rate = &stats.columns[2];
+        assert!(rate.numeric);
+        assert_eq!((rate.count, rate.missing), (2, 1));
+        assert_eq!(
+            (rate.min, rate.max, rate.mean),
+            (Some(-0.5), Some(0.5), Some(0.0))
+        );
+        assert!(
+            stats
+                .to_markdown()
+                .contains("| rate | numeric | 2 | 1 | 2 | -0.5 | 0.5 | 0 | 0.7071 |")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_and_finish() -> Result<()> {
+        let mut file = File::new("trends.tsv".into(), "trends.tsv".into());
+        file.content = Some("transect\trate\n1\t-0.5\n2\t0.3\n".into());
+        let mut task = ModelTask {
+            kind: ModelTaskKind::DataDescription,
+            attachments: Some(vec![InstructionAttachment::new("trends".into(), file)]),
+            ..Default::default()
+        };
+
+        let Some(kind) = specialize_task(&mut task)? else {
+            bail!("expected task to be specialized")
+        };
+        assert!(
+            task.prompt_as_text()
+                .unwrap_or_default()
+                .contains("Dataset `trends` has 2 rows and 2 columns")
+        );
+
+        let mut output = ModelOutput {
+            content: "The dataset contains shoreline change rates for two transects.".into(),
+            ..Default::default()
+        };
+        finish_specialized_task(kind, &task, &mut output)?;
+        let [ModelOutputPart::DatasetStatistics(stats)] = output.parts.as_slice() else {
+            bail!("expected statistics part")
+        };
+        assert_eq!(stats.columns[1].max, Some(0.3));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use common::{
+    once_cell::sync::Lazy,
+    serde::{Deserialize, Serialize},
+    similar::TextDiff,
+};
+use schema::InstructionAttachment;
+
+use crate::attestation::content_hash;
+
+/// The maximum number of attachments remembered as having been sent
+const MAX_SENT: usize = 64;
+
+/// The maximum size, in bytes, of an attachment remembered as having been sent
+const MAX_SENT_BYTES: usize = 1024 * 1024;
+
+/// Options for sending changes to attachments as diffs
+///
+/// Off by default because the files previously uploaded to a provider may have
+/// since been deleted (e.g. expired) and a reference to them would then fail.
+///
+/// ```toml
+/// [attachments.delta]
+/// enabled = true
+/// max-change-ratio = 0.1
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct AttachmentDeltaOptions {
+    /// Whether to send diffs of text attachments which have previously been sent
+    pub enabled: bool,
+
+    /// The maximum proportion of lines changed for a diff to be sent
+    ///
+    /// Above this, the full attachment is sent again because the diff is
+    /// likely to be harder for the model to apply than re-reading the file.
+    pub max_change_ratio: f64,
+}
+
+impl Default for AttachmentDeltaOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_change_ratio: 0.2,
+        }
+    }
+}
+
+/// How an attachment can be sent given the version of it previously sent to a provider
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttachmentDelta {
+    /// The attachment has not changed so the reference to the previous version can be reused
+    Unchanged {
+        /// The provider's reference to the previous version e.g. a file id
+        reference: String,
+    },
+
+    /// The attachment has changed by less than the threshold so a diff can be sent
+    Changed {
+        /// The provider's reference to the previous version e.g. a file id
+        reference: String,
+
+        /// A unified diff from the previous version to the current content
+        diff: String,
+    },
+
+    /// The attachment has not been sent before, is not text, or has changed
+    /// above the threshold, so must be sent in full
+    Full,
+}
+
+/// A version of an attachment previously sent to a provider
+struct SentAttachment {
+    reference: String,
+    hash: String,
+    content: String,
+}
+
+/// Attachments previously sent, keyed by [`attachment_delta_key`], with the
+/// order they were sent in so that the oldest can be evicted
+#[derive(Default)]
+struct Sent {
+    attachments: HashMap<String, SentAttachment>,
+    order: VecDeque<String>,
+}
+
+impl Sent {
+    /// Remember an attachment, forgetting the oldest if more than [`MAX_SENT`] are remembered
+    fn insert(&mut self, key: &str, attachment: SentAttachment) {
+        self.order.retain(|existing| existing != key);
+        self.order.push_back(key.to_string());
+        self.attachments.insert(key.to_string(), attachment);
+
+        while self.order.len() > MAX_SENT {
+            if let Some(oldest) = self.order.pop_front() {
+                self.attachments.remove(&oldest);
+            }
+        }
+    }
+}
+
+static SENT: Lazy<Mutex<Sent>> = Lazy::new(Mutex::default);
+
+/// Get the key under which an attachment sent to a provider is remembered
+///
+/// Uploaded files are only accessible using the API key that uploaded them, and
+/// different files may have the same name, so the key is a hash of the provider's
+/// endpoint, the API key, and the path and origin of the attachment.
+pub fn attachment_delta_key(
+    endpoint: &str,
+    api_key: &str,
+    attachment: &InstructionAttachment,
+) -> String {
+    let options = &attachment.options;
+    let parts = [
+        endpoint,
+        api_key,
+        &attachment.file.path,
+        options.source_url.as_deref().unwrap_or_default(),
+        options.generated_by.as_deref().unwrap_or_default(),
+    ];
+    content_hash(parts.join("\0").as_bytes())
+}
+
+/// Determine how an attachment should be sent to a provider
+///
+/// Compares the content of the attachment with the version last sent under the
+/// same `key` (see [`attachment_delta_key`] and [`record_sent_attachment`]).
+/// Follow-up turns which re-send a slightly modified file (e.g. a CSV with an extra
+/// row) can then send a diff, plus the reference to the original file, rather than
+/// uploading it again. The `name` is used in the header of the diff.
+pub fn attachment_delta(
+    key: &str,
+    name: &str,
+    content: &[u8],
+    options: &AttachmentDeltaOptions,
+) -> AttachmentDelta {
+    if !options.enabled {
+        return AttachmentDelta::Full;
+    }
+
+    let Ok(content) = std::str::from_utf8(content) else {
+        return AttachmentDelta::Full;
+    };
+
+    let Ok(sent) = SENT.lock() else {
+        return AttachmentDelta::Full;
+    };
+    let Some(previous) = sent.attachments.get(key) else {
+        return AttachmentDelta::Full;
+    };
+
+    if previous.hash == content_hash(content.as_bytes()) {
+        return AttachmentDelta::Unchanged {
+            reference: previous.reference.clone(),
+        };
+    }
+
+    let diff = TextDiff::from_lines(previous.content.as_str(), content);
+    let change_ratio = 1.0 - diff.ratio() as f64;
+    if change_ratio > options.max_change_ratio {
+        return AttachmentDelta::Full;
+    }
+
+    AttachmentDelta::Changed {
+        reference: previous.reference.clone(),
+        diff: diff
+            .unified_diff()
+            .context_radius(2)
+            .header(&format!("a/{name}"), &format!("b/{name}"))
+            .to_string(),
+    }
+}
+
+/// Record that the full content of an attachment was sent to a provider
+///
+/// Only text content is recorded since diffs are not sent for binary files. Large
+/// attachments are not recorded, and once [`MAX_SENT`] attachments have been
+/// recorded the oldest is forgotten, to bound the memory used.
+pub fn record_sent_attachment(key: &str, reference: &str, content: &[u8]) {
+    if content.len() > MAX_SENT_BYTES {
+        return;
+    }
+    let Ok(content) = std::str::from_utf8(content) else {
+        return;
+    };
+
+    if let Ok(mut sent) = SENT.lock() {
+        sent.insert(
+            key,
+            SentAttachment {
+                reference: reference.into(),
+                hash: content_hash(content.as_bytes()),
+                content: content.into(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::eyre::{Result, bail};
+    use schema::File;
+
+    use super::*;
+
+    fn key(api_key: &str, path: &str) -> String {
+        let attachment = InstructionAttachment::new(
+            "shoreline".into(),
+            File::new("shoreline.csv".into(), path.into()),
+        );
+        attachment_delta_key("https://api.example.org", api_key, &attachment)
+    }
+
+    fn enabled() -> AttachmentDeltaOptions {
+        AttachmentDeltaOptions {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diffs_small_changes() -> Result<()> {
+        let options = enabled();
+        let original = (1..=20)
+            .map(|row| format!("{row},{}\n", row * 2))
+            .collect::<String>();
+        let sent = key("sk-1", "data/shoreline.csv");
+
+        assert_eq!(
+            attachment_delta(&sent, "shoreline.csv", original.as_bytes(), &options),
+            AttachmentDelta::Full
+        );
+        record_sent_attachment(&sent, "file-1", original.as_bytes());
+
+        assert_eq!(
+            attachment_delta(&sent, "shoreline.csv", original.as_bytes(), &options),
+            AttachmentDelta::Unchanged {
+                reference: "file-1".into()
+            }
+        );
+
+        let updated = format!("{original}21,42\n");
+        let AttachmentDelta::Changed { reference, diff } =
+            attachment_delta(&sent, "shoreline.csv", updated.as_bytes(), &options)
+        else {
+            bail!("expected a diff")
+        };
+        assert_eq!(reference, "file-1");
+        assert!(diff.starts_with("--- a/shoreline.csv\n+++ b/shoreline.csv\n"));
+        assert!(diff.contains("+21,42"));
+
+        // Other API keys, and files with the same name at other paths, have not been sent the file
+        for other in [
+            key("sk-2", "data/shoreline.csv"),
+            key("sk-1", "old/shoreline.csv"),
+        ] {
+            assert_eq!(
+                attachment_delta(&other, "shoreline.csv", updated.as_bytes(), &options),
+                AttachmentDelta::Full
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sends_large_changes_in_full() {
+        let sent = key("sk-1", "large.csv");
+        record_sent_attachment(&sent, "file-2", b"a\nb\nc\nd\n");
+        assert_eq!(
+            attachment_delta(&sent, "large.csv", b"a\nx\ny\nz\n", &enabled()),
+            AttachmentDelta::Full
+        );
+
+        // Disabled by default
+        assert_eq!(
+            attachment_delta(
+                &sent,
+                "large.csv",
+                b"a\nb\nc\nd\n",
+                &AttachmentDeltaOptions::default()
+            ),
+            AttachmentDelta::Full
+        );
+    }
+
+    #[test]
+    fn forgets_oldest() {
+        let attachment = || SentAttachment {
+            reference: "file-3".into(),
+            hash: String::new(),
+            content: String::new(),
+        };
+
+        let mut sent = Sent::default();
+        sent.insert("first", attachment());
+        sent.insert("second", attachment());
+        sent.insert("first", attachment());
+        for index in 0..MAX_SENT - 1 {
+            sent.insert(&index.to_string(), attachment());
+        }
+
+        assert_eq!(sent.attachments.len(), MAX_SENT);
+        assert_eq!(sent.order.len(), MAX_SENT);
+        assert!(sent.attachments.contains_key("first"));
+        assert!(!sent.attachments.contains_key("second"));
+
+        // Attachments over the size limit are not remembered
+        let large = key("sk-3", "large.csv");
+        record_sent_attachment(&large, "file-4", &vec![b'a'; MAX_SENT_BYTES + 1]);
+        assert!(
+            SENT.lock()
+                .map(|sent| !sent.attachments.contains_key(&large))
+                .unwrap_or_default()
+        );
+    }
+}
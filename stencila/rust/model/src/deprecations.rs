@@ -0,0 +1,121 @@
+use common::{
+    chrono::{NaiveDate, Utc},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{CatalogConfig, ModelWarning};
+
+/// Metadata about the deprecation of a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub struct ModelDeprecation {
+    /// The date on which the model is, or was, retired by its provider
+    pub sunset: Option<NaiveDate>,
+
+    /// The id of the model recommended as a replacement
+    pub replacement: Option<String>,
+}
+
+/// Deprecated models, their sunset dates, and recommended replacements
+///
+/// Will need to be updated periodically based on the deprecation pages of providers e.g.
+/// https://platform.openai.com/docs/deprecations and https://docs.anthropic.com/en/docs/about-claude/model-deprecations.
+/// Users can add to, or override, these using the `[catalog.deprecations]` table of the models config.
+const DEPRECATIONS: &[(&str, &str, &str)] = &[
+    (
+        "anthropic/claude-2.0",
+        "2025-07-21",
+        "anthropic/claude-sonnet-4-0",
+    ),
+    (
+        "anthropic/claude-2.1",
+        "2025-07-21",
+        "anthropic/claude-sonnet-4-0",
+    ),
+    (
+        "anthropic/claude-3-sonnet-20240229",
+        "2025-07-21",
+        "anthropic/claude-sonnet-4-0",
+    ),
+    (
+        "anthropic/claude-3-opus-20240229",
+        "2026-01-05",
+        "anthropic/claude-opus-4-1",
+    ),
+    (
+        "openai/gpt-3.5-turbo-0613",
+        "2024-09-13",
+        "openai/gpt-4o-mini",
+    ),
+    ("openai/gpt-4-32k", "2025-06-06", "openai/gpt-4o"),
+    ("openai/gpt-4-vision-preview", "2024-12-06", "openai/gpt-4o"),
+    ("openai/gpt-4.5-preview", "2025-07-14", "openai/gpt-4.1"),
+    ("openai/o1-preview", "2025-07-28", "openai/o3"),
+    ("openai/o1-mini", "2025-10-27", "openai/o4-mini"),
+];
+
+impl CatalogConfig {
+    /// Get the deprecation metadata for a model, if it is deprecated
+    ///
+    /// Entries in the config take precedence over the built-in list.
+    pub fn deprecation(&self, id: &str) -> Option<ModelDeprecation> {
+        if let Some(deprecation) = self.deprecations.get(id) {
+            return Some(deprecation.clone());
+        }
+
+        DEPRECATIONS
+            .iter()
+            .find(|(model, ..)| *model == id)
+            .map(|(.., sunset, replacement)| ModelDeprecation {
+                sunset: NaiveDate::parse_from_str(sunset, "%Y-%m-%d").ok(),
+                replacement: Some(replacement.to_string()),
+            })
+    }
+}
+
+impl ModelDeprecation {
+    /// Whether the sunset date of the model has passed
+    pub fn is_retired(&self) -> bool {
+        self.sunset
+            .is_some_and(|sunset| sunset <= Utc::now().date_naive())
+    }
+
+    /// Create a warning that a model is deprecated
+    pub fn warning(&self, id: &str) -> ModelWarning {
+        let mut message = match self.sunset {
+            Some(sunset) if self.is_retired() => format!("Model `{id}` was retired on {sunset}"),
+            Some(sunset) => format!("Model `{id}` is deprecated and will be retired on {sunset}"),
+            None => format!("Model `{id}` is deprecated"),
+        };
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!("; consider using `{replacement}` instead"));
+        }
+
+        ModelWarning::deprecated(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::eyre::{Result, bail};
+
+    use super::*;
+
+    #[test]
+    fn builtin_deprecations() -> Result<()> {
+        let catalog = CatalogConfig::default();
+        let Some(deprecation) = catalog.deprecation("openai/gpt-4-vision-preview") else {
+            bail!("expected deprecation")
+        };
+        assert!(deprecation.is_retired());
+        assert_eq!(deprecation.replacement.as_deref(), Some("openai/gpt-4o"));
+        assert_eq!(
+            deprecation.warning("openai/gpt-4-vision-preview").message,
+            "Model `openai/gpt-4-vision-preview` was retired on 2024-12-06; consider using `openai/gpt-4o` instead"
+        );
+
+        assert!(catalog.deprecation("openai/gpt-4o").is_none());
+
+        Ok(())
+    }
+}
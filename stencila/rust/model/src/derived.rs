@@ -0,0 +1,294 @@
+use std::{
+    env::current_dir,
+    fs::{create_dir_all, read, read_to_string, write},
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use common::{
+    eyre::Result,
+    seahash,
+    serde::{Deserialize, Serialize, de::DeserializeOwned},
+    serde_json, tracing,
+};
+use dirs::{ARTIFACTS_DIR, DirType, STENCILA_DIR, get_app_dir};
+use schema::InstructionAttachment;
+
+use crate::models_config;
+
+/// The name of the subdirectory of a cache directory that derived artifacts are written to
+const DERIVED_DIR: &str = "derived";
+
+/// Options for caching artifacts derived from attachments
+///
+/// ```toml
+/// [cache.derived]
+/// enabled = true
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct DerivedCacheConfig {
+    /// Whether to cache artifacts derived from attachments
+    pub enabled: bool,
+
+    /// The directory to write derived artifacts to
+    ///
+    /// Defaults to `artifacts/derived` in the closest `.stencila` directory to the
+    /// current directory, or if there is none, to the `derived` subdirectory of the
+    /// Stencila cache directory.
+    pub dir: Option<PathBuf>,
+}
+
+/// A cache of artifacts derived from attachments
+///
+/// Preprocessing attachments (e.g. sampling frames from videos, selecting pages
+/// from PDFs, or embedding chunks of text) can be slow and, when it uses a model,
+/// costly. This cache stores the results on disk, keyed by the hash of the
+/// content of the attachment, so that repeated tasks over the same files do not
+/// redo the preprocessing, even across processes.
+///
+/// Each artifact has a `kind` (e.g. `frames`) and the parameters used to derive
+/// it (e.g. the sampling options) so that changing the parameters derives a new
+/// artifact. Artifacts are stored as JSON at `<dir>/<content-hash>/<kind>-<params-hash>.json`.
+/// Errors reading or writing the cache are logged rather than returned so that
+/// a broken cache never prevents a task from being performed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedCache {
+    /// The directory artifacts are stored in
+    dir: PathBuf,
+}
+
+impl DerivedCache {
+    /// Create a cache storing artifacts in a directory
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Get the directory artifacts are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the path of the file for an artifact
+    fn path<P: Serialize>(
+        &self,
+        attachment: &InstructionAttachment,
+        kind: &str,
+        params: &P,
+    ) -> Result<PathBuf> {
+        let content_hash = attachment_content_hash(attachment)?;
+        let params_hash = seahash::hash(serde_json::to_string(params)?.as_bytes());
+        Ok(self
+            .dir
+            .join(content_hash)
+            .join(format!("{kind}-{params_hash:016x}.json")))
+    }
+
+    /// Get an artifact derived from an attachment, if it is in the cache
+    pub fn get<T: DeserializeOwned, P: Serialize>(
+        &self,
+        attachment: &InstructionAttachment,
+        kind: &str,
+        params: &P,
+    ) -> Option<T> {
+        let path = self.path(attachment, kind, params).ok()?;
+        let json = read_to_string(&path).ok()?;
+        match serde_json::from_str(&json) {
+            Ok(artifact) => {
+                tracing::debug!("Using cached {kind} of attachment `{}`", attachment.alias);
+                Some(artifact)
+            }
+            Err(error) => {
+                tracing::debug!(
+                    "Ignoring invalid cached artifact {}: {error}",
+                    path.display()
+                );
+                None
+            }
+        }
+    }
+
+    /// Insert an artifact derived from an attachment into the cache
+    pub fn insert<T: Serialize, P: Serialize>(
+        &self,
+        attachment: &InstructionAttachment,
+        kind: &str,
+        params: &P,
+        artifact: &T,
+    ) {
+        let result = (|| -> Result<()> {
+            let path = self.path(attachment, kind, params)?;
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            write(path, serde_json::to_string(artifact)?)?;
+            Ok(())
+        })();
+        if let Err(error) = result {
+            tracing::debug!(
+                "Unable to cache {kind} of attachment `{}`: {error}",
+                attachment.alias
+            );
+        }
+    }
+
+    /// Get an artifact from the cache, or derive it and insert it into the cache
+    pub fn get_or_derive<T, P, F>(
+        &self,
+        attachment: &InstructionAttachment,
+        kind: &str,
+        params: &P,
+        derive: F,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        P: Serialize,
+        F: FnOnce() -> Result<T>,
+    {
+        if let Some(artifact) = self.get(attachment, kind, params) {
+            return Ok(artifact);
+        }
+
+        let artifact = derive()?;
+        self.insert(attachment, kind, params, &artifact);
+        Ok(artifact)
+    }
+
+    /// Get an artifact from the cache, or derive it asynchronously and insert it into the cache
+    pub async fn get_or_derive_async<T, P, F, Fut>(
+        &self,
+        attachment: &InstructionAttachment,
+        kind: &str,
+        params: &P,
+        derive: F,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        P: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if let Some(artifact) = self.get(attachment, kind, params) {
+            return Ok(artifact);
+        }
+
+        let artifact = derive().await?;
+        self.insert(attachment, kind, params, &artifact);
+        Ok(artifact)
+    }
+}
+
+/// Get the cache of derived artifacts, if enabled in the `[cache.derived]` table of the models config
+pub fn derived_cache() -> Option<DerivedCache> {
+    let config = &models_config().cache.derived;
+    if !config.enabled {
+        return None;
+    }
+
+    let dir = match &config.dir {
+        Some(dir) => dir.clone(),
+        None => default_dir()?,
+    };
+    Some(DerivedCache::new(dir))
+}
+
+/// Get the default directory of the derived cache
+fn default_dir() -> Option<PathBuf> {
+    if let Ok(cwd) = current_dir() {
+        let stencila_dir = cwd
+            .ancestors()
+            .map(|dir| dir.join(STENCILA_DIR))
+            .find(|dir| dir.is_dir());
+        if let Some(stencila_dir) = stencila_dir {
+            return Some(stencila_dir.join(ARTIFACTS_DIR).join(DERIVED_DIR));
+        }
+    }
+
+    get_app_dir(DirType::Cache, false)
+        .ok()
+        .map(|dir| dir.join(DERIVED_DIR))
+}
+
+/// Get the hash of the content of an attachment
+///
+/// Uses the content of the attachment's file if it has any, otherwise reads the
+/// file from disk. The path is not included so that copies of a file share artifacts.
+fn attachment_content_hash(attachment: &InstructionAttachment) -> Result<String> {
+    let file = &attachment.file;
+    let hash = match &file.content {
+        Some(content) => seahash::hash(content.as_bytes()),
+        None => seahash::hash(&read(file.path.trim_start_matches("file://"))?),
+    };
+    Ok(format!("{hash:016x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use common::{eyre::bail, tempfile::tempdir, tokio};
+    use schema::File;
+
+    use super::*;
+
+    fn attachment(alias: &str, content: &str) -> InstructionAttachment {
+        let mut file = File::new(format!("{alias}.csv"), format!("outputs/{alias}.csv"));
+        file.content = Some(content.into());
+        InstructionAttachment::new(alias.into(), file)
+    }
+
+    #[test]
+    fn caches_by_content_and_params() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = DerivedCache::new(dir.path());
+        let calls = Cell::new(0);
+        let derive = || {
+            calls.set(calls.get() + 1);
+            Ok(vec!["chunk".to_string()])
+        };
+
+        let transects = attachment("transects", "id,rate\nnarra_0001,-0.4\n");
+        let copy = attachment("copy", "id,rate\nnarra_0001,-0.4\n");
+        let changed = attachment("transects", "id,rate\nnarra_0001,-0.5\n");
+
+        cache.get_or_derive(&transects, "chunks", &100, derive)?;
+        cache.get_or_derive(&transects, "chunks", &100, derive)?;
+        cache.get_or_derive(&copy, "chunks", &100, derive)?;
+        assert_eq!(calls.get(), 1);
+
+        cache.get_or_derive(&transects, "chunks", &200, derive)?;
+        cache.get_or_derive(&transects, "text", &100, derive)?;
+        cache.get_or_derive(&changed, "chunks", &100, derive)?;
+        assert_eq!(calls.get(), 4);
+
+        let cached: Option<Vec<String>> = cache.get(&transects, "chunks", &100);
+        assert_eq!(cached, Some(vec!["chunk".to_string()]));
+
+        // Errors are not cached
+        let failed: Result<Vec<String>> =
+            cache.get_or_derive(&transects, "frames", &(), || bail!("no ffmpeg"));
+        assert!(failed.is_err());
+        let cached: Option<Vec<String>> = cache.get(&transects, "frames", &());
+        assert_eq!(cached, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn caches_async() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = DerivedCache::new(dir.path());
+        let transects = attachment("transects", "id,rate\n");
+
+        let first: String = cache
+            .get_or_derive_async(&transects, "text", &(), || async { Ok("first".into()) })
+            .await?;
+        let second: String = cache
+            .get_or_derive_async(&transects, "text", &(), || async { Ok("second".into()) })
+            .await?;
+        assert_eq!(first, "first");
+        assert_eq!(second, "first");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use common::{
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_json::{self, Value},
+    serde_with::skip_serializing_none,
+    similar::{ChangeTag, TextDiff},
+};
+use schema::AuthorRoleAuthor;
+
+use crate::ModelOutput;
+
+/// The differences between two outputs of a task
+///
+/// Created by [`diff_outputs`] e.g. when a task is regenerated, so that
+/// provenance records can show exactly what changed between reruns or
+/// between versions of a model.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct OutputDiff {
+    /// Whether the content of the outputs is identical
+    pub unchanged: bool,
+
+    /// The ids of the models which generated the previous output
+    pub previous_models: Vec<String>,
+
+    /// The ids of the models which generated the current output
+    pub current_models: Vec<String>,
+
+    /// The number of lines inserted in the content
+    pub insertions: usize,
+
+    /// The number of lines deleted from the content
+    pub deletions: usize,
+
+    /// A unified diff of the content
+    ///
+    /// `None` if the content is unchanged.
+    pub unified: Option<String>,
+
+    /// Changes to the fields of the content, if both outputs are JSON
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub json_changes: Vec<JsonChange>,
+}
+
+/// A change to a field of JSON content
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct JsonChange {
+    /// The JSON Pointer to the field e.g. `/transects/0/rate`
+    pub path: String,
+
+    /// The previous value of the field, `None` if it was added
+    pub before: Option<Value>,
+
+    /// The current value of the field, `None` if it was removed
+    pub after: Option<Value>,
+}
+
+impl OutputDiff {
+    /// Whether the outputs were generated by different models
+    pub fn models_changed(&self) -> bool {
+        self.previous_models != self.current_models
+    }
+}
+
+/// Diff two outputs of a task
+pub fn diff_outputs(previous: &ModelOutput, current: &ModelOutput) -> OutputDiff {
+    let previous_models = author_models(previous);
+    let current_models = author_models(current);
+
+    if previous.content == current.content {
+        return OutputDiff {
+            unchanged: true,
+            previous_models,
+            current_models,
+            insertions: 0,
+            deletions: 0,
+            unified: None,
+            json_changes: Vec::new(),
+        };
+    }
+
+    let diff = TextDiff::from_lines(&previous.content, &current.content);
+    let (insertions, deletions) =
+        diff.iter_all_changes()
+            .fold((0, 0), |(insertions, deletions), change| {
+                match change.tag() {
+                    ChangeTag::Insert => (insertions + 1, deletions),
+                    ChangeTag::Delete => (insertions, deletions + 1),
+                    ChangeTag::Equal => (insertions, deletions),
+                }
+            });
+    let unified = diff
+        .unified_diff()
+        .header("previous", "current")
+        .to_string();
+
+    let mut json_changes = Vec::new();
+    if let (Ok(before), Ok(after)) = (
+        serde_json::from_str::<Value>(&previous.content),
+        serde_json::from_str::<Value>(&current.content),
+    ) {
+        diff_json(String::new(), &before, &after, &mut json_changes);
+    }
+
+    OutputDiff {
+        unchanged: false,
+        previous_models,
+        current_models,
+        insertions,
+        deletions,
+        unified: Some(unified),
+        json_changes,
+    }
+}
+
+/// Get the ids of the models which authored an output
+fn author_models(output: &ModelOutput) -> Vec<String> {
+    output
+        .authors
+        .iter()
+        .filter_map(|role| match &role.author {
+            AuthorRoleAuthor::SoftwareApplication(app) => app.id.clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Recursively diff two JSON values, recording changes to leaf values
+fn diff_json(path: String, before: &Value, after: &Value, changes: &mut Vec<JsonChange>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for key in before.keys().chain(after.keys()).unique().sorted() {
+                let path = format!("{path}/{}", key.replace('~', "~0").replace('/', "~1"));
+                match (before.get(key), after.get(key)) {
+                    (Some(before), Some(after)) => diff_json(path, before, after, changes),
+                    (before, after) => changes.push(JsonChange {
+                        path,
+                        before: before.cloned(),
+                        after: after.cloned(),
+                    }),
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for index in 0..before.len().max(after.len()) {
+                let path = format!("{path}/{index}");
+                match (before.get(index), after.get(index)) {
+                    (Some(before), Some(after)) => diff_json(path, before, after, changes),
+                    (before, after) => changes.push(JsonChange {
+                        path,
+                        before: before.cloned(),
+                        after: after.cloned(),
+                    }),
+                }
+            }
+        }
+        _ if before != after => changes.push(JsonChange {
+            path,
+            before: Some(before.clone()),
+            after: Some(after.clone()),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::serde_json::json;
+
+    use super::*;
+
+    fn output(content: &str) -> ModelOutput {
+        ModelOutput {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn text_and_json() {
+        let diff = diff_outputs(&output("a\nb\n"), &output("a\nb\n"));
+        assert!(diff.unchanged);
+        assert!(diff.unified.is_none());
+
+        let diff = diff_outputs(&output("a\nb\nc\n"), &output("a\nB\nc\nd\n"));
+        assert!(!diff.unchanged);
+        assert_eq!((diff.insertions, diff.deletions), (2, 1));
+        assert!(diff.unified.unwrap_or_default().contains("-b\n+B\n"));
+        assert!(diff.json_changes.is_empty());
+
+        let diff = diff_outputs(
+            &output(r#"{"rate": -0.5, "transects": [1, 2], "site": "narrabeen"}"#),
+            &output(r#"{"rate": -0.6, "transects": [1], "trend": "eroding", "site": "narrabeen"}"#),
+        );
+        assert_eq!(
+            diff.json_changes,
+            vec![
+                JsonChange {
+                    path: "/rate".into(),
+                    before: Some(json!(-0.5)),
+                    after: Some(json!(-0.6)),
+                },
+                JsonChange {
+                    path: "/transects/1".into(),
+                    before: Some(json!(2)),
+                    after: None,
+                },
+                JsonChange {
+                    path: "/trend".into(),
+                    before: None,
+                    after: Some(json!("eroding")),
+                },
+            ]
+        );
+    }
+}
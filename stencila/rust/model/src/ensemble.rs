@@ -0,0 +1,353 @@
+use std::sync::Arc;
+
+use common::{
+    async_trait::async_trait,
+    eyre::{Result, bail},
+    futures::future::join_all,
+};
+
+use crate::{
+    CandidateSelector, MajorityVoteSelector, Model, ModelAvailability, ModelOutput,
+    ModelOutputKind, ModelTask, ModelTaskKind, ModelType, ModelWarning, Policy, TokenUsage,
+    models_config,
+};
+
+/// A member of an ensemble
+#[derive(Clone)]
+pub struct EnsembleMember {
+    /// The model used by the member
+    pub model: Arc<dyn Model>,
+
+    /// The temperature to use, overriding that of the task
+    pub temperature: Option<f32>,
+}
+
+/// A model which fans a task out to several member models and selects a consensus
+///
+/// Each member performs the task concurrently. The text outputs of the members
+/// that succeed are recorded as candidates of the output, along with the id of the
+/// member model which generated each, and one is selected as the consensus
+/// (by default using a majority vote). All members are included as authors.
+///
+/// The request to each member is checked against the policy (including local-only
+/// mode) of the models config, and waits in the task queue, just as if the task had
+/// been sent to the member directly. The token usage of the output is the sum of
+/// that of the members.
+pub struct EnsembleModel {
+    /// The id of the ensemble
+    id: String,
+
+    /// The members of the ensemble
+    members: Vec<EnsembleMember>,
+
+    /// The selector used to select the consensus
+    selector: Box<dyn CandidateSelector>,
+}
+
+impl EnsembleModel {
+    /// Create an ensemble of several models
+    pub fn new(id: &str, models: Vec<Arc<dyn Model>>) -> Self {
+        Self {
+            id: id.into(),
+            members: models
+                .into_iter()
+                .map(|model| EnsembleMember {
+                    model,
+                    temperature: None,
+                })
+                .collect(),
+            selector: Box::new(MajorityVoteSelector),
+        }
+    }
+
+    /// Create an ensemble of a single model at several temperatures
+    pub fn with_temperatures(id: &str, model: Arc<dyn Model>, temperatures: &[f32]) -> Self {
+        Self {
+            id: id.into(),
+            members: temperatures
+                .iter()
+                .map(|temperature| EnsembleMember {
+                    model: model.clone(),
+                    temperature: Some(*temperature),
+                })
+                .collect(),
+            selector: Box::new(MajorityVoteSelector),
+        }
+    }
+
+    /// Use a different selector to select the consensus
+    pub fn selector(mut self, selector: Box<dyn CandidateSelector>) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Get the members of the ensemble
+    pub fn members(&self) -> &[EnsembleMember] {
+        &self.members
+    }
+
+    /// Perform a task, with each member's request checked against a policy
+    async fn perform_with(&self, task: &ModelTask, policy: &Policy) -> Result<ModelOutput> {
+        let futures = self.members.iter().map(|member| async move {
+            let model = member.model.as_ref();
+            let tuned;
+            let task = match member.temperature {
+                Some(temperature) => {
+                    tuned = ModelTask {
+                        temperature: Some(temperature),
+                        ..task.clone()
+                    };
+                    &tuned
+                }
+                None => task,
+            };
+            (member, policy.perform(model, task).await)
+        });
+
+        let mut authors = Vec::new();
+        let mut candidates = Vec::new();
+        let mut candidate_models = Vec::new();
+        let mut format = task.format.clone();
+        let mut warnings = Vec::new();
+        let mut usage = Some(TokenUsage::default());
+        for (member, result) in join_all(futures).await {
+            match result {
+                Ok(output) => {
+                    usage = usage.zip(output.usage).map(|(usage, more)| usage + more);
+                    if !matches!(output.kind, ModelOutputKind::Text) {
+                        warnings.push(ModelWarning::ignored_part(format!(
+                            "Ignoring non-text output from ensemble member `{}`",
+                            member.model.id()
+                        )));
+                        continue;
+                    }
+                    authors.extend(output.authors);
+                    warnings.extend(output.warnings);
+                    format = output.format;
+                    candidates.push(output.content);
+                    candidate_models.push(member.model.id());
+                }
+                Err(error) => {
+                    warnings.push(ModelWarning::fallback(format!(
+                        "Ensemble member `{}` failed: {error}",
+                        member.model.id()
+                    )));
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            bail!("All members of ensemble `{}` failed", self.id)
+        }
+
+        let mut output = ModelOutput {
+            authors,
+            kind: ModelOutputKind::Text,
+            format,
+            content: candidates[0].clone(),
+            usage,
+            warnings,
+            ..Default::default()
+        };
+
+        if candidates.len() > 1 {
+            output.candidates = candidates;
+            output.candidate_models = candidate_models;
+            output
+                .select_candidate(task, self.selector.as_ref())
+                .await?;
+        }
+
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl Model for EnsembleModel {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn r#type(&self) -> ModelType {
+        // Local if all members are, so that the ensemble can be used in local-only mode
+        if !self.members.is_empty()
+            && self
+                .members
+                .iter()
+                .all(|member| member.model.r#type().is_local())
+        {
+            ModelType::Local
+        } else {
+            ModelType::Router
+        }
+    }
+
+    fn is_composite(&self) -> bool {
+        true
+    }
+
+    fn availability(&self) -> ModelAvailability {
+        if self
+            .members
+            .iter()
+            .any(|member| member.model.is_available())
+        {
+            ModelAvailability::Available
+        } else {
+            ModelAvailability::Unavailable
+        }
+    }
+
+    fn supports_task(&self, task: &ModelTask) -> bool {
+        !self.members.is_empty()
+            && self
+                .members
+                .iter()
+                .all(|member| member.model.supports_task(task))
+    }
+
+    fn supported_task_kinds(&self) -> &[ModelTaskKind] {
+        &[ModelTaskKind::MessageGeneration]
+    }
+
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        self.perform_with(task, &models_config().policy).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{tokio, toml};
+
+    use crate::ModelsConfig;
+
+    use super::*;
+
+    /// A model which always responds with the same text
+    struct FixedModel(&'static str, &'static str);
+
+    #[async_trait]
+    impl Model for FixedModel {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            Ok(ModelOutput {
+                content: self.1.into(),
+                usage: Some(TokenUsage {
+                    prompt_tokens: 10,
+                    output_tokens: 1,
+                    cached_tokens: 0,
+                }),
+                ..Default::default()
+            })
+        }
+    }
+
+    /// A remote model which always responds with the same text
+    struct RemoteModel(&'static str);
+
+    #[async_trait]
+    impl Model for RemoteModel {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+
+        fn r#type(&self) -> ModelType {
+            ModelType::Remote
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            Ok(ModelOutput {
+                content: "yes".into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn consensus() -> Result<()> {
+        let ensemble = EnsembleModel::new(
+            "test/ensemble",
+            vec![
+                Arc::new(FixedModel("test/a", "no")),
+                Arc::new(FixedModel("test/b", "yes")),
+                Arc::new(FixedModel("test/c", "yes")),
+            ],
+        );
+
+        let output = ensemble.perform_task(&ModelTask::default()).await?;
+        assert_eq!(output.content, "yes");
+        assert_eq!(output.selected_candidate, Some(1));
+        assert_eq!(output.candidates, vec!["no", "yes", "yes"]);
+        assert_eq!(output.candidate_models, vec!["test/a", "test/b", "test/c"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sums_usage() -> Result<()> {
+        let ensemble = EnsembleModel::with_temperatures(
+            "test/ensemble",
+            Arc::new(FixedModel("test/a", "yes")),
+            &[0.0, 0.5, 1.0],
+        );
+        assert_eq!(ensemble.r#type(), ModelType::Local);
+        assert!(ensemble.is_composite());
+
+        let output = ensemble.perform_task(&ModelTask::default()).await?;
+        assert_eq!(
+            output.usage,
+            Some(TokenUsage {
+                prompt_tokens: 30,
+                output_tokens: 3,
+                cached_tokens: 0,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_members() -> Result<()> {
+        let ensemble = EnsembleModel::new(
+            "test/ensemble",
+            vec![
+                Arc::new(FixedModel("test/a", "no")),
+                Arc::new(FixedModel("test/b", "yes")),
+                Arc::new(RemoteModel("openai/gpt-4o")),
+            ],
+        );
+        assert_eq!(ensemble.r#type(), ModelType::Router);
+
+        // Members denied by the policy are not sent the task, even though the ensemble is allowed
+        let config: ModelsConfig = toml::from_str(
+            r#"
+[[policy.rules]]
+name = "no-openai"
+models = "openai/*"
+deny = true
+"#,
+        )?;
+        let output = ensemble
+            .perform_with(&ModelTask::default(), &config.policy)
+            .await?;
+        assert_eq!(output.candidate_models, vec!["test/a", "test/b"]);
+        assert!(
+            output
+                .warnings
+                .iter()
+                .any(|warning| warning.message.contains("no-openai"))
+        );
+
+        // As are remote members in local-only mode
+        let config: ModelsConfig = toml::from_str("[policy]\nlocal-only = true")?;
+        let output = ensemble
+            .perform_with(&ModelTask::default(), &config.policy)
+            .await?;
+        assert_eq!(output.candidate_models, vec!["test/a", "test/b"]);
+
+        Ok(())
+    }
+}
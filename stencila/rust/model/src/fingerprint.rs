@@ -0,0 +1,180 @@
+use std::fmt;
+
+use common::{
+    eyre::Result,
+    itertools::Itertools,
+    seahash,
+    serde::{Deserialize, Serialize},
+    serde_json::{self, Map, Value},
+};
+
+use crate::ModelTask;
+
+/// Options for creating a [`TaskFingerprint`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FingerprintOptions {
+    /// Whether to normalize whitespace in the text of messages
+    ///
+    /// When `true`, leading and trailing whitespace is removed and runs of
+    /// whitespace are collapsed to a single space, so that tasks differing only
+    /// in formatting have the same fingerprint.
+    pub normalize_whitespace: bool,
+}
+
+/// A reproducible hash of a [`ModelTask`]
+///
+/// The task is serialized canonically (with object keys sorted, and with the
+/// content of attachments replaced by its digest) before being hashed, so that
+/// the fingerprint is stable across processes and versions of the task's
+/// in-memory representation. Options that do not affect the generated output
+/// (e.g. `dry_run`, `audit` and `priority`) are excluded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent, crate = "common::serde")]
+pub struct TaskFingerprint(String);
+
+impl TaskFingerprint {
+    /// Create a fingerprint for a task using the default options
+    pub fn new(task: &ModelTask) -> Result<Self> {
+        Self::with_options(task, FingerprintOptions::default())
+    }
+
+    /// Create a fingerprint for a task
+    pub fn with_options(task: &ModelTask, options: FingerprintOptions) -> Result<Self> {
+        let json = canonical_json(task, options)?;
+        Ok(Self(format!("{:016x}", seahash::hash(json.as_bytes()))))
+    }
+
+    /// Get the fingerprint as a hexadecimal string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TaskFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Serialize a task canonically
+///
+/// This is the serialization hashed by [`TaskFingerprint`] and may be stored
+/// alongside it in provenance records so that the hash can be verified.
+pub fn canonical_json(task: &ModelTask, options: FingerprintOptions) -> Result<String> {
+    let mut value = serde_json::to_value(task)?;
+
+    if let Value::Object(object) = &mut value {
+        object.remove("dryRun");
+        object.remove("audit");
+        object.remove("priority");
+
+        if options.normalize_whitespace
+            && let Some(messages) = object.get_mut("messages")
+        {
+            normalize_whitespace(messages);
+        }
+
+        if let Some(Value::Array(attachments)) = object.get_mut("attachments") {
+            for attachment in attachments {
+                digest_content(attachment);
+            }
+        }
+    }
+
+    Ok(serde_json::to_string(&canonicalize(value))?)
+}
+
+/// Recursively sort the keys of objects and remove null values
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(.., value)| !value.is_null())
+                .sorted_by(|(a, ..), (b, ..)| a.cmp(b))
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(array) => Value::Array(array.into_iter().map(canonicalize).collect()),
+        _ => value,
+    }
+}
+
+/// Recursively normalize whitespace in strings
+fn normalize_whitespace(value: &mut Value) {
+    match value {
+        Value::String(string) => *string = string.split_whitespace().join(" "),
+        Value::Array(array) => array.iter_mut().for_each(normalize_whitespace),
+        Value::Object(object) => object.values_mut().for_each(normalize_whitespace),
+        _ => {}
+    }
+}
+
+/// Replace the content of an attachment's file with its digest
+fn digest_content(attachment: &mut Value) {
+    let Some(Value::Object(file)) = attachment.get_mut("file") else {
+        return;
+    };
+
+    if let Some(Value::String(content)) = file.remove("content") {
+        file.insert(
+            "contentDigest".into(),
+            Value::String(format!("{:016x}", seahash::hash(content.as_bytes()))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::{File, InstructionAttachment, InstructionMessage, MessagePart};
+
+    use super::*;
+
+    fn task(text: &str, content: &str) -> ModelTask {
+        let mut file = File::new("data.csv".into(), "data.csv".into());
+        file.content = Some(content.into());
+
+        ModelTask {
+            messages: vec![InstructionMessage {
+                parts: vec![MessagePart::from(text)],
+                ..Default::default()
+            }],
+            attachments: Some(vec![InstructionAttachment::new("data".into(), file)]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stable_and_sensitive() -> Result<()> {
+        let a = TaskFingerprint::new(&task("Summarize  the data", "1,2"))?;
+        assert_eq!(
+            a,
+            TaskFingerprint::new(&task("Summarize  the data", "1,2"))?
+        );
+        assert_eq!(a.as_str().len(), 16);
+
+        let mut dry = task("Summarize  the data", "1,2");
+        dry.dry_run = true;
+        assert_eq!(a, TaskFingerprint::new(&dry)?);
+
+        assert_ne!(a, TaskFingerprint::new(&task("Summarize the data", "1,2"))?);
+        assert_ne!(
+            a,
+            TaskFingerprint::new(&task("Summarize  the data", "1,3"))?
+        );
+
+        let normalize = FingerprintOptions {
+            normalize_whitespace: true,
+        };
+        assert_eq!(
+            TaskFingerprint::with_options(&task("Summarize  the data\n", "1,2"), normalize)?,
+            TaskFingerprint::with_options(&task("Summarize the data", "1,2"), normalize)?
+        );
+
+        let json = canonical_json(&task("Hi", "1,2"), FingerprintOptions::default())?;
+        assert!(json.contains("contentDigest"));
+        assert!(!json.contains("1,2"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use common::tracing;
+use format::Format;
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Model, ModelTask, models_config, repair_json};
+
+/// Whether a model supports generating content in a format
+///
+/// Markdown flavors and plain text are supported by all models.
+/// Other formats are supported only if the model can be constrained
+/// to generate them (e.g. a JSON mode) as indicated by [`Model::native_formats`].
+pub fn supports_format(model: &dyn Model, format: &Format) -> bool {
+    format.is_unknown()
+        || format.is_markdown_flavor()
+        || matches!(format, Format::Text)
+        || model.native_formats().contains(format)
+}
+
+/// Get an instruction asking a model to respond in a format
+///
+/// Returns `None` for formats that do not need an instruction (i.e. Markdown and plain text).
+pub fn format_instruction(format: &Format) -> Option<String> {
+    if format.is_unknown() || format.is_markdown_flavor() || matches!(format, Format::Text) {
+        return None;
+    }
+
+    Some(format!(
+        "Respond only with valid {}. Do not wrap it in a code block or add any other text, explanation or notes.",
+        format.name()
+    ))
+}
+
+/// Augment a task so that a model is instructed to respond in the task's format
+///
+/// If the model does not natively support the format of the task, a system message
+/// containing the instruction is appended to the task's messages.
+pub fn negotiate_format(task: &mut ModelTask, model: &dyn Model) {
+    if supports_format(model, &task.format) {
+        return;
+    }
+
+    if let Some(instruction) = format_instruction(&task.format) {
+        tracing::debug!(
+            "Model `{}` does not natively support format `{}`, augmenting prompt",
+            model.id(),
+            task.format
+        );
+
+        task.messages.push(InstructionMessage {
+            role: Some(MessageRole::System),
+            parts: vec![MessagePart::from(instruction)],
+            ..Default::default()
+        });
+    }
+}
+
+/// Repair generated text so that it is more likely to be valid in the format
+///
+/// Strips any code fences wrapping the text and, for JSON flavors, repairs
+/// malformed JSON using [`repair_json`] (unless `strict-json` is set in the
+/// `[outputs]` of the models config). JSON which can not be repaired is returned
+/// as is, so that it fails when decoded, with the original error.
+pub fn repair_text(format: &Format, text: String) -> String {
+    if format.is_unknown() || format.is_markdown_flavor() {
+        return text;
+    }
+
+    let text = strip_code_fences(&text);
+
+    if format.is_json_flavor() && !models_config().outputs.strict_json {
+        repair_json(text).unwrap_or_else(|error| {
+            tracing::debug!("{error}");
+            text.to_string()
+        })
+    } else {
+        text.to_string()
+    }
+}
+
+/// Strip code fences (and any language tag) from around text
+fn strip_code_fences(text: &str) -> &str {
+    let trimmed = text.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+
+    let Some((.., body)) = rest.split_once('\n') else {
+        return trimmed;
+    };
+
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repairs_json() {
+        assert_eq!(
+            repair_text(
+                &Format::Json,
+                "```json\n{\"a\": [1, 2,], \"b\": \"x,]\",\n}\n```".into()
+            ),
+            "{\"a\": [1, 2], \"b\": \"x,]\"\n}"
+        );
+        assert_eq!(repair_text(&Format::Yaml, "```\na: 1\n```".into()), "a: 1");
+        assert_eq!(
+            repair_text(&Format::Markdown, "```\ncode\n```".into()),
+            "```\ncode\n```"
+        );
+    }
+}
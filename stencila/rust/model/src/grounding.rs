@@ -0,0 +1,266 @@
+use std::collections::BTreeSet;
+
+use common::{
+    eyre::{Result, bail},
+    itertools::Itertools,
+    once_cell::sync::Lazy,
+    regex::Regex,
+    serde::{Deserialize, Serialize},
+    serde_with::skip_serializing_none,
+};
+use format::Format;
+use schema::MessageRole;
+
+use crate::{
+    ModelOutput, ModelOutputPart, ModelTask, Validator, kinds::insert_system_message,
+    task::message_part_to_string,
+};
+
+/// A claim in an answer and the location in an attachment that it is grounded in
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct GroundedClaim {
+    /// The text of the claim, usually the sentence before the citation
+    pub claim: String,
+
+    /// The alias of the attachment the claim came from
+    pub alias: String,
+
+    /// The page of the attachment the claim came from, if cited
+    pub page: Option<u32>,
+
+    /// The first line of the attachment the claim came from, if cited
+    pub start_line: Option<usize>,
+
+    /// The last line of the attachment the claim came from, if cited
+    pub end_line: Option<usize>,
+}
+
+/// The regex for grounding citations e.g. `[trends]`, `[report:p3]` and `[trends:L10-L25]`
+static CITATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\[([A-Za-z][\w\-.]*)(?::(?:p\.?\s*(\d+)|L(\d+)(?:-L(\d+))?))?\]")
+        .expect("invalid regex")
+});
+
+/// The regex for the labels of excerpts added by retrieval e.g. `[trends:L10-L25]`
+static EXCERPT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^\[([A-Za-z][\w\-.]*):L\d+-L\d+\]$").expect("invalid regex"));
+
+/// Get the aliases of the documents that answers may cite
+///
+/// Includes attachments and any attachments whose excerpts were added to the
+/// system prompt by retrieval (those attachments are removed from the task).
+fn known_aliases(task: &ModelTask) -> BTreeSet<String> {
+    let mut aliases: BTreeSet<String> = task
+        .attachments
+        .iter()
+        .flatten()
+        .map(|attachment| attachment.alias.clone())
+        .collect();
+
+    for message in &task.messages {
+        if !matches!(message.role, Some(MessageRole::System)) {
+            continue;
+        }
+        for text in message.parts.iter().filter_map(message_part_to_string) {
+            aliases.extend(
+                EXCERPT
+                    .captures_iter(&text)
+                    .map(|captures| captures[1].to_string()),
+            );
+        }
+    }
+
+    aliases
+}
+
+/// Parse the grounding citations in an answer
+///
+/// Only citations of the `aliases` are parsed (so that other bracketed text,
+/// such as `[sic]`, is ignored). The claim of each citation is the text from
+/// the start of its sentence (or the previous citation) up to the citation.
+/// Consecutive citations share the same claim.
+pub fn parse_grounding(content: &str, aliases: &BTreeSet<String>) -> Vec<GroundedClaim> {
+    let mut claims: Vec<GroundedClaim> = Vec::new();
+    let mut previous_end = 0;
+    let mut previous_claim = String::new();
+
+    for captures in CITATION.captures_iter(content) {
+        let Some(marker) = captures.get(0) else {
+            continue;
+        };
+        let alias = &captures[1];
+        if !aliases.contains(alias) || content[marker.end()..].starts_with('(') {
+            continue;
+        }
+
+        let before = &content[previous_end..marker.start()];
+        let claim = if before.trim().is_empty() && !claims.is_empty() {
+            previous_claim.clone()
+        } else {
+            let start = before
+                .trim_end()
+                .trim_end_matches(['.', '!', '?'])
+                .rfind(['.', '!', '?', '\n'])
+                .map_or(0, |index| index + 1);
+            before[start..].trim().to_string()
+        };
+
+        let number = |index: usize| captures.get(index).and_then(|m| m.as_str().parse().ok());
+        let start_line = number(3);
+        claims.push(GroundedClaim {
+            claim: claim.clone(),
+            alias: alias.to_string(),
+            page: captures.get(2).and_then(|m| m.as_str().parse().ok()),
+            start_line,
+            end_line: number(4).or(start_line),
+        });
+
+        previous_end = marker.end();
+        previous_claim = claim;
+    }
+
+    claims
+}
+
+/// Prepare a question answering task
+///
+/// Errors if the task has no attachments to answer from. Instructs the model to
+/// cite the attachment (and page or lines) of each claim and adds a validator
+/// which requires that the answer cites at least one of them.
+pub(crate) fn prepare(task: &mut ModelTask) -> Result<()> {
+    let aliases = known_aliases(task);
+    if aliases.is_empty() {
+        bail!("Question answering tasks require attachments to answer from");
+    }
+
+    insert_system_message(
+        task,
+        format!(
+            "Answer the user's question using only the attached files ({}). After each claim, cite \
+the file it came from using its alias in square brackets, including the page or lines if known \
+e.g. [alias], [alias:p3] or [alias:L10-L25]. If the files do not contain the answer, say so.",
+            aliases.iter().map(|alias| format!("`{alias}`")).join(", ")
+        ),
+    );
+    task.format = Format::Markdown;
+    task.validators.push(Validator::predicate(move |content| {
+        if parse_grounding(content, &aliases).is_empty() {
+            Err("the answer does not cite any of the attached files".into())
+        } else {
+            Ok(())
+        }
+    }));
+
+    Ok(())
+}
+
+/// Finish a question answering task
+///
+/// Records the grounding of each claim in the answer as a part of the output.
+pub(crate) fn finish(task: &ModelTask, output: &mut ModelOutput) {
+    let claims = parse_grounding(&output.content, &known_aliases(task));
+    if !claims.is_empty() {
+        output.parts.push(ModelOutputPart::Grounding { claims });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::{File, InstructionAttachment, InstructionMessage, MessagePart};
+
+    use crate::{ModelTaskKind, finish_specialized_task, specialize_task};
+
+    use super::*;
+
+    #[test]
+    fn parses_grounding() {
+        let aliases = BTreeSet::from(["report".to_string(), "trends".to_string()]);
+        let claims = parse_grounding(
+            "Narrabeen is eroding [trends:L10-L25]. The beach was nourished in 2020 [report:p3][trends]. \
+            See [the docs](https://example.org) [sic].",
+            &aliases,
+        );
+        assert_eq!(
+            claims,
+            vec![
+                GroundedClaim {
+                    claim: "Narrabeen is eroding".into(),
+                    alias: "trends".into(),
+                    page: None,
+                    start_line: Some(10),
+                    end_line: Some(25),
+                },
+                GroundedClaim {
+                    claim: "The beach was nourished in 2020".into(),
+                    alias: "report".into(),
+                    page: Some(3),
+                    start_line: None,
+                    end_line: None,
+                },
+                GroundedClaim {
+                    claim: "The beach was nourished in 2020".into(),
+                    alias: "trends".into(),
+                    page: None,
+                    start_line: None,
+                    end_line: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prepare_and_finish() -> Result<()> {
+        let mut task = ModelTask {
+            kind: ModelTaskKind::QuestionAnswering,
+            messages: vec![
+                InstructionMessage {
+                    role: Some(MessageRole::System),
+                    parts: vec![MessagePart::from(
+                        "The following excerpts were retrieved.\n\n[report:L1-L9]\nThe beach was nourished.",
+                    )],
+                    ..Default::default()
+                },
+                InstructionMessage {
+                    role: Some(MessageRole::User),
+                    parts: vec![MessagePart::from("Was the beach nourished?")],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(known_aliases(&task), BTreeSet::from(["report".to_string()]));
+
+        task.attachments = Some(vec![InstructionAttachment::new(
+            "trends".into(),
+            File::new("trends.csv".into(), "trends.csv".into()),
+        )]);
+        let Some(kind) = specialize_task(&mut task)? else {
+            bail!("expected task to be specialized")
+        };
+        assert!(
+            task.prompt_as_text()
+                .unwrap_or_default()
+                .contains("attached files (`report`, `trends`)")
+        );
+        assert!(task.validators[0].validate("Yes, it was.").is_err());
+
+        let mut output = ModelOutput {
+            content: "Yes, in 2020 [report:L2].".into(),
+            ..Default::default()
+        };
+        assert!(task.validators[0].validate(&output.content).is_ok());
+        finish_specialized_task(kind, &task, &mut output)?;
+        let [ModelOutputPart::Grounding { claims }] = output.parts.as_slice() else {
+            bail!("expected grounding part")
+        };
+        assert_eq!(claims[0].claim, "Yes, in 2020");
+        assert_eq!(
+            (claims[0].start_line, claims[0].end_line),
+            (Some(2), Some(2))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use common::{
+    chrono::{DateTime, Utc},
+    once_cell::sync::Lazy,
+    serde::{Deserialize, Serialize},
+    serde_with::skip_serializing_none,
+};
+
+/// The number of recent latency samples kept for each model
+const LATENCY_SAMPLES: usize = 100;
+
+/// Recent latency samples for each model, keyed by model id
+static LATENCIES: Lazy<Mutex<HashMap<String, VecDeque<Duration>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Record the latency of a request to a model
+///
+/// Called for health probes and for tasks performed by a model so that
+/// [`latency_percentiles`] reflects recent usage.
+pub fn record_latency(model_id: &str, latency: Duration) {
+    let Ok(mut latencies) = LATENCIES.lock() else {
+        return;
+    };
+
+    let samples = latencies.entry(model_id.to_string()).or_default();
+    if samples.len() >= LATENCY_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(latency);
+}
+
+/// Get percentiles of the recent latencies of a model
+///
+/// Returns `None` if no latencies have been recorded for the model.
+pub fn latency_percentiles(model_id: &str) -> Option<LatencyPercentiles> {
+    let latencies = LATENCIES.lock().ok()?;
+    let samples = latencies
+        .get(model_id)
+        .filter(|samples| !samples.is_empty())?;
+
+    let mut sorted: Vec<u64> = samples
+        .iter()
+        .map(|latency| latency.as_millis() as u64)
+        .collect();
+    sorted.sort_unstable();
+
+    let percentile = |p: usize| sorted[((sorted.len() - 1) * p) / 100];
+
+    Some(LatencyPercentiles {
+        samples: sorted.len(),
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+    })
+}
+
+/// Percentiles of recent latencies, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "common::serde")]
+pub struct LatencyPercentiles {
+    /// The number of samples the percentiles are calculated from
+    pub samples: usize,
+
+    /// The median latency
+    pub p50: u64,
+
+    /// The 90th percentile latency
+    pub p90: u64,
+
+    /// The 99th percentile latency
+    pub p99: u64,
+}
+
+/// The health of a model
+///
+/// Returned by [`crate::Model::health`] so that routers and user interfaces can
+/// avoid models which are currently unavailable or broken.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ModelHealth {
+    /// The id of the model
+    pub model_id: String,
+
+    /// Whether the model responded to the health probe (or is available, if not probed)
+    pub healthy: bool,
+
+    /// The latency of the health probe in milliseconds, if one was made
+    pub probe_latency: Option<u64>,
+
+    /// Percentiles of the model's recent latencies
+    pub latencies: Option<LatencyPercentiles>,
+
+    /// The error message if the probe failed
+    pub error: Option<String>,
+
+    /// When the health was checked
+    pub checked_at: DateTime<Utc>,
+}
+
+impl ModelHealth {
+    /// Create a health report without making a probe
+    pub fn unprobed(model_id: String, healthy: bool) -> Self {
+        let latencies = latency_percentiles(&model_id);
+        Self {
+            model_id,
+            healthy,
+            probe_latency: None,
+            latencies,
+            error: None,
+            checked_at: Utc::now(),
+        }
+    }
+
+    /// Create a health report from the result of a probe
+    ///
+    /// Records the latency of successful probes.
+    pub fn probed<T, E: ToString>(
+        model_id: String,
+        latency: Duration,
+        result: Result<T, E>,
+    ) -> Self {
+        let (healthy, error) = match result {
+            Ok(..) => {
+                record_latency(&model_id, latency);
+                (true, None)
+            }
+            Err(error) => (false, Some(error.to_string())),
+        };
+
+        let latencies = latency_percentiles(&model_id);
+        Self {
+            model_id,
+            healthy,
+            probe_latency: Some(latency.as_millis() as u64),
+            latencies,
+            error,
+            checked_at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        for ms in 1..=10 {
+            record_latency("test/percentiles", Duration::from_millis(ms * 10));
+        }
+
+        let percentiles = latency_percentiles("test/percentiles").expect("should have samples");
+        assert_eq!(percentiles.samples, 10);
+        assert_eq!(percentiles.p50, 50);
+        assert_eq!(percentiles.p90, 90);
+        assert_eq!(percentiles.p99, 90);
+
+        assert!(latency_percentiles("test/none").is_none());
+    }
+}
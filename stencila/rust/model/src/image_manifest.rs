@@ -0,0 +1,362 @@
+use std::{
+    fs::write,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    chrono::Utc,
+    eyre::{Result, bail},
+    serde::{Deserialize, Serialize},
+    serde_json,
+};
+use flate2::Crc;
+
+use crate::attestation::{Attestation, SigningKey, content_hash, signing_key};
+
+/// The signature at the start of every PNG file
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The keyword of the PNG `iTXt` chunk that the manifest is embedded in
+const PNG_KEYWORD: &str = "stencila:manifest";
+
+/// The extension appended to the path of an image for its sidecar manifest
+const SIDECAR_EXTENSION: &str = "manifest.json";
+
+/// A provenance manifest for a generated image
+///
+/// Modelled on the claims of a [C2PA](https://c2pa.org) manifest: records the
+/// model which generated the image, a hash of the prompt (so the prompt itself
+/// is not disclosed), a hash of the image and when it was generated. If
+/// attestation is enabled in the models config, the manifest is signed with
+/// the local Ed25519 key so that figures exported from a document carry
+/// verifiable provenance.
+///
+/// Embedded in PNG images as an `iTXt` chunk, or written to a sidecar
+/// `<image>.manifest.json` file for other formats.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ImageManifest {
+    /// The software which generated the claim
+    pub claim_generator: String,
+
+    /// The id of the model which generated the image
+    pub generator: String,
+
+    /// The SHA-256 hash of the prompt, hex encoded
+    pub prompt_hash: String,
+
+    /// The SHA-256 hash of the image (without the manifest), hex encoded
+    pub image_hash: String,
+
+    /// The media type of the image e.g. `image/png`
+    pub media_type: String,
+
+    /// The time the image was generated, in RFC 3339 format
+    pub timestamp: String,
+
+    /// A signed attestation of the image, if attestation is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+}
+
+impl ImageManifest {
+    /// Create a manifest for an image, signing it if attestation is enabled in the models config
+    pub fn new(model: &str, prompt: &str, media_type: &str, image: &[u8]) -> Result<Self> {
+        let mut manifest = Self::unsigned(model, prompt, media_type, image);
+        if let Some(key) = signing_key()? {
+            manifest.sign(&key, image)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Create an unsigned manifest for an image
+    pub fn unsigned(model: &str, prompt: &str, media_type: &str, image: &[u8]) -> Self {
+        Self {
+            claim_generator: format!("Stencila/{}", env!("CARGO_PKG_VERSION")),
+            generator: model.into(),
+            prompt_hash: content_hash(prompt.as_bytes()),
+            image_hash: content_hash(image),
+            media_type: media_type.into(),
+            timestamp: Utc::now().to_rfc3339(),
+            attestation: None,
+        }
+    }
+
+    /// Sign the manifest with a key
+    ///
+    /// The prompt hash is used as the fingerprint of the attestation.
+    pub fn sign(&mut self, key: &SigningKey, image: &[u8]) -> Result<()> {
+        self.attestation = Some(Attestation::sign_bytes(
+            key,
+            &self.prompt_hash,
+            &self.generator,
+            image,
+        )?);
+        Ok(())
+    }
+
+    /// Verify the manifest against an image (without the manifest)
+    ///
+    /// Checks that the image matches the hash in the manifest and, if the manifest
+    /// is signed, that the attestation is valid and consistent with the manifest.
+    pub fn verify(&self, image: &[u8]) -> Result<()> {
+        if content_hash(image) != self.image_hash {
+            bail!("Image does not match the hash in the manifest");
+        }
+
+        if let Some(attestation) = &self.attestation {
+            if attestation.fingerprint != self.prompt_hash || attestation.model != self.generator {
+                bail!("Attestation does not match the manifest");
+            }
+            attestation.verify_bytes(Some(image))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Embed a manifest in a PNG image
+///
+/// Inserts an uncompressed `iTXt` chunk, containing the manifest as JSON,
+/// immediately before the `IEND` chunk. Any existing manifest is replaced.
+pub fn embed_png_manifest(png: &[u8], manifest: &ImageManifest) -> Result<Vec<u8>> {
+    let (image, ..) = split_png_manifest(png)?;
+
+    let mut data = Vec::new();
+    data.extend_from_slice(PNG_KEYWORD.as_bytes());
+    // Null separator, compression flag and method, and empty language tag
+    // and translated keyword
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(&serde_json::to_vec(manifest)?);
+
+    let end = png_chunks(&image)?
+        .into_iter()
+        .find(|chunk| chunk.kind == *b"IEND")
+        .map(|chunk| chunk.start)
+        .unwrap_or(image.len());
+
+    let mut embedded = Vec::with_capacity(image.len() + data.len() + 12);
+    embedded.extend_from_slice(&image[..end]);
+    write_png_chunk(&mut embedded, b"iTXt", &data)?;
+    embedded.extend_from_slice(&image[end..]);
+    Ok(embedded)
+}
+
+/// Read the manifest embedded in a PNG image
+///
+/// Returns the manifest, if any, and the image without it (as hashed in the manifest).
+pub fn read_png_manifest(png: &[u8]) -> Result<Option<(ImageManifest, Vec<u8>)>> {
+    let (image, manifest) = split_png_manifest(png)?;
+    Ok(manifest.map(|manifest| (manifest, image)))
+}
+
+/// Write a manifest for an image file
+///
+/// PNG images have the manifest embedded in them. For other formats, the
+/// manifest is written to a sidecar file alongside the image, the path of
+/// which is returned.
+pub fn write_image_manifest(path: &Path, manifest: &ImageManifest) -> Result<Option<PathBuf>> {
+    let image = std::fs::read(path)?;
+    if image.starts_with(PNG_SIGNATURE) {
+        write(path, embed_png_manifest(&image, manifest)?)?;
+        return Ok(None);
+    }
+
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    let sidecar = PathBuf::from(sidecar);
+    write(&sidecar, serde_json::to_string_pretty(manifest)?)?;
+    Ok(Some(sidecar))
+}
+
+/// Embed a manifest for a generated image in a `data:` URL
+///
+/// Returns the data URL unchanged if it is not a base64 encoded PNG image.
+pub fn embed_data_url_manifest(data_url: &str, model: &str, prompt: &str) -> Result<String> {
+    let Some(b64) = data_url.strip_prefix("data:image/png;base64,") else {
+        return Ok(data_url.to_string());
+    };
+
+    let png = BASE64.decode(b64)?;
+    let manifest = ImageManifest::new(model, prompt, "image/png", &png)?;
+    let embedded = embed_png_manifest(&png, &manifest)?;
+    Ok(format!("data:image/png;base64,{}", BASE64.encode(embedded)))
+}
+
+/// A chunk of a PNG file
+struct PngChunk {
+    /// The offset of the start of the chunk (its length field)
+    start: usize,
+
+    /// The offset of the end of the chunk (after its CRC)
+    end: usize,
+
+    /// The type of the chunk
+    kind: [u8; 4],
+}
+
+/// Parse the chunks of a PNG file
+fn png_chunks(png: &[u8]) -> Result<Vec<PngChunk>> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        bail!("Not a PNG image");
+    }
+
+    let mut chunks = Vec::new();
+    let mut start = PNG_SIGNATURE.len();
+    while start + 8 <= png.len() {
+        let length =
+            u32::from_be_bytes([png[start], png[start + 1], png[start + 2], png[start + 3]])
+                as usize;
+        let end = start + 12 + length;
+        if end > png.len() {
+            bail!("Truncated PNG chunk");
+        }
+
+        let kind = [
+            png[start + 4],
+            png[start + 5],
+            png[start + 6],
+            png[start + 7],
+        ];
+        chunks.push(PngChunk { start, end, kind });
+        start = end;
+    }
+
+    Ok(chunks)
+}
+
+/// Write a chunk to a PNG file
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    let length = u32::try_from(data.len())?;
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    png.extend_from_slice(&length.to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+    Ok(())
+}
+
+/// Split a PNG image into the image without any manifest chunk, and the manifest
+fn split_png_manifest(png: &[u8]) -> Result<(Vec<u8>, Option<ImageManifest>)> {
+    let prefix = [PNG_KEYWORD.as_bytes(), &[0, 0, 0, 0, 0]].concat();
+
+    let mut image = Vec::with_capacity(png.len());
+    image.extend_from_slice(PNG_SIGNATURE);
+    let mut manifest = None;
+    for chunk in png_chunks(png)? {
+        let data = &png[chunk.start + 8..chunk.end - 4];
+        if chunk.kind == *b"iTXt" && data.starts_with(&prefix) {
+            manifest = Some(serde_json::from_slice(&data[prefix.len()..])?);
+        } else {
+            image.extend_from_slice(&png[chunk.start..chunk.end]);
+        }
+    }
+
+    Ok((image, manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    /// A minimal 1x1 PNG image
+    fn png() -> Result<Vec<u8>> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_png_chunk(&mut png, b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0])?;
+        write_png_chunk(
+            &mut png,
+            b"IDAT",
+            &[0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01],
+        )?;
+        write_png_chunk(&mut png, b"IEND", &[])?;
+        Ok(png)
+    }
+
+    #[test]
+    fn embeds_and_reads_png_manifest() -> Result<()> {
+        let png = png()?;
+        let (key, ..) = SigningKey::generate()?;
+        let mut manifest =
+            ImageManifest::unsigned("openai/dall-e-3", "A shoreline", "image/png", &png);
+        manifest.sign(&key, &png)?;
+
+        let embedded = embed_png_manifest(&png, &manifest)?;
+        assert!(embedded.len() > png.len());
+        assert!(
+            png_chunks(&embedded)?
+                .last()
+                .is_some_and(|chunk| chunk.kind == *b"IEND")
+        );
+
+        let Some((read, image)) = read_png_manifest(&embedded)? else {
+            bail!("expected manifest")
+        };
+        assert_eq!(read, manifest);
+        assert_eq!(image, png);
+        read.verify(&image)?;
+
+        // Re-embedding replaces the existing manifest
+        let twice = embed_png_manifest(&embedded, &manifest)?;
+        assert_eq!(twice, embedded);
+
+        // Images without a manifest, or which have been modified, are detected
+        assert!(read_png_manifest(&png)?.is_none());
+        let mut modified = image.clone();
+        modified[PNG_SIGNATURE.len() + 11] = 2;
+        assert!(read.verify(&modified).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn embeds_in_data_urls() -> Result<()> {
+        let png = png()?;
+        let data_url = format!("data:image/png;base64,{}", BASE64.encode(&png));
+
+        let embedded = embed_data_url_manifest(&data_url, "openai/dall-e-3", "A shoreline")?;
+        let Some(b64) = embedded.strip_prefix("data:image/png;base64,") else {
+            bail!("expected PNG data URL")
+        };
+        let Some((manifest, image)) = read_png_manifest(&BASE64.decode(b64)?)? else {
+            bail!("expected manifest")
+        };
+        assert_eq!(manifest.generator, "openai/dall-e-3");
+        assert_eq!(manifest.prompt_hash, content_hash(b"A shoreline"));
+        manifest.verify(&image)?;
+
+        let url = "https://example.org/image.png";
+        assert_eq!(embed_data_url_manifest(url, "openai/dall-e-3", "")?, url);
+
+        Ok(())
+    }
+
+    #[test]
+    fn writes_manifests() -> Result<()> {
+        let dir = tempdir()?;
+
+        let png_path = dir.path().join("figure.png");
+        write(&png_path, png()?)?;
+        let manifest = ImageManifest::unsigned("openai/dall-e-3", "A", "image/png", &png()?);
+        assert_eq!(write_image_manifest(&png_path, &manifest)?, None);
+        assert!(read_png_manifest(&std::fs::read(&png_path)?)?.is_some());
+
+        let jpeg_path = dir.path().join("figure.jpg");
+        write(&jpeg_path, [0xff, 0xd8, 0xff])?;
+        let manifest = ImageManifest::unsigned("openai/dall-e-3", "A", "image/jpeg", &[]);
+        let Some(sidecar) = write_image_manifest(&jpeg_path, &manifest)? else {
+            bail!("expected sidecar")
+        };
+        assert_eq!(sidecar, dir.path().join("figure.jpg.manifest.json"));
+        let read: ImageManifest = serde_json::from_str(&std::fs::read_to_string(sidecar)?)?;
+        assert_eq!(read, manifest);
+
+        Ok(())
+    }
+}
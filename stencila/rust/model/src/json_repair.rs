@@ -0,0 +1,194 @@
+use common::{
+    eyre::{Result, bail},
+    serde::de::IgnoredAny,
+    serde_json,
+};
+
+/// Repair malformed JSON generated by a model
+///
+/// Models (particularly those without a JSON mode, or whose output was truncated)
+/// often generate JSON which is almost, but not quite, valid. If the JSON is
+/// invalid, this attempts to repair:
+///
+/// - trailing commas before closing braces and brackets (and at the end of the text)
+/// - single quoted strings and keys
+/// - unescaped newlines, carriage returns and tabs within strings
+/// - unbalanced braces and brackets, and unterminated strings
+///
+/// Valid JSON is returned unchanged. Returns an error, with the reason the original
+/// JSON was invalid, if the repaired JSON is still invalid.
+pub fn repair_json(json: &str) -> Result<String> {
+    let error = match serde_json::from_str::<IgnoredAny>(json) {
+        Ok(..) => return Ok(json.to_string()),
+        Err(error) => error,
+    };
+
+    let repaired = repair(json);
+    if serde_json::from_str::<IgnoredAny>(&repaired).is_err() {
+        bail!("Unable to repair invalid JSON: {error}")
+    }
+
+    Ok(repaired)
+}
+
+/// Apply the repairs in a single pass over the JSON
+fn repair(json: &str) -> String {
+    let mut repaired = String::with_capacity(json.len());
+    let mut closers: Vec<char> = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut pending_comma: Option<String> = None;
+
+    for char in json.chars() {
+        if let Some(delimiter) = quote {
+            if escaped {
+                escaped = false;
+                // `\'` is not a valid escape in JSON so drop the backslash
+                if char == '\'' {
+                    repaired.pop();
+                }
+                repaired.push(char);
+                continue;
+            }
+
+            match char {
+                '\\' => {
+                    escaped = true;
+                    repaired.push(char);
+                }
+                _ if char == delimiter => {
+                    quote = None;
+                    repaired.push('"');
+                }
+                // Only reachable within single quoted strings
+                '"' => repaired.push_str("\\\""),
+                '\n' => repaired.push_str("\\n"),
+                '\r' => repaired.push_str("\\r"),
+                '\t' => repaired.push_str("\\t"),
+                _ => repaired.push(char),
+            }
+            continue;
+        }
+
+        if let Some(pending) = pending_comma.as_mut() {
+            if char.is_whitespace() {
+                pending.push(char);
+                continue;
+            }
+
+            let pending = pending_comma.take().unwrap_or_default();
+            if char == '}' || char == ']' {
+                // Drop the comma but keep any whitespace after it
+                repaired.push_str(&pending[1..]);
+            } else {
+                repaired.push_str(&pending);
+            }
+        }
+
+        match char {
+            ',' => pending_comma = Some(String::from(",")),
+            '"' | '\'' => {
+                quote = Some(char);
+                repaired.push('"');
+            }
+            '{' => {
+                closers.push('}');
+                repaired.push(char);
+            }
+            '[' => {
+                closers.push(']');
+                repaired.push(char);
+            }
+            '}' | ']' => {
+                // Close any unclosed objects or arrays within the one being closed,
+                // and drop closers which do not match any opener
+                if closers.contains(&char) {
+                    while let Some(closer) = closers.pop() {
+                        repaired.push(closer);
+                        if closer == char {
+                            break;
+                        }
+                    }
+                }
+            }
+            _ => repaired.push(char),
+        }
+    }
+
+    // Terminate any unterminated string (dropping a dangling backslash)
+    if quote.is_some() {
+        if escaped {
+            repaired.pop();
+        }
+        repaired.push('"');
+    }
+
+    // A dangling comma is dropped but a dangling key needs a value
+    if repaired.trim_end().ends_with(':') {
+        repaired.push_str(" null");
+    }
+
+    while let Some(closer) = closers.pop() {
+        repaired.push(closer);
+    }
+
+    repaired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_unchanged() -> Result<()> {
+        let json = r#"{"label": "erosion", "rates": [-0.4, 1e3], "note": "a, b]"}"#;
+        assert_eq!(repair_json(json)?, json);
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_commas() -> Result<()> {
+        assert_eq!(repair_json("[1, 2,]")?, "[1, 2]");
+        assert_eq!(repair_json("{\"a\": 1,\n}")?, "{\"a\": 1\n}");
+        assert_eq!(repair_json(r#"{"a": "x,}",}"#)?, r#"{"a": "x,}"}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn single_quotes() -> Result<()> {
+        assert_eq!(
+            repair_json(r#"{'site': 'Narrabeen', 'quote': 'say "hi"', 'it\'s': 1}"#)?,
+            r#"{"site": "Narrabeen", "quote": "say \"hi\"", "it's": 1}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unescaped_newlines() -> Result<()> {
+        assert_eq!(
+            repair_json("{\"summary\": \"Line one\nLine two\"}")?,
+            r#"{"summary": "Line one\nLine two"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced() -> Result<()> {
+        assert_eq!(
+            repair_json(r#"{"transects": [{"id": "narra_0001", "rate": -0.4"#)?,
+            r#"{"transects": [{"id": "narra_0001", "rate": -0.4}]}"#
+        );
+        assert_eq!(repair_json(r#"{"a": [1, 2}"#)?, r#"{"a": [1, 2]}"#);
+        assert_eq!(repair_json(r#"{"a": 1}}"#)?, r#"{"a": 1}"#);
+        assert_eq!(repair_json(r#"{"a": "trunc"#)?, r#"{"a": "trunc"}"#);
+        assert_eq!(repair_json(r#"{"a": 1, "b":"#)?, r#"{"a": 1, "b": null}"#);
+        assert_eq!(repair_json(r#"[1, 2,"#)?, "[1, 2]");
+        Ok(())
+    }
+
+    #[test]
+    fn unrepairable() {
+        assert!(repair_json("not json at all").is_err());
+        assert!(repair_json(r#"{"a" 1}"#).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use common::{
+    eyre::Result,
+    glob::Pattern,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+
+use crate::{ModelTask, RequestsConfig, TaskPriority, models_config};
+
+/// A rule routing tasks to one of several API keys for a provider
+///
+/// Used in shared deployments so that, for example, background jobs and
+/// interactive users have separate quotas. A route applies to a provider if the
+/// name of its secret starts with the name of the provider's default secret
+/// (e.g. `OPENAI_API_KEY_BATCH` for `OPENAI_API_KEY`). The first route matching
+/// both the priority of the task and the id of the model is used:
+///
+/// ```toml
+/// [[requests.api-keys]]
+/// secret = "OPENAI_API_KEY_BATCH"
+/// priority = "batch"
+///
+/// [[requests.api-keys]]
+/// secret = "OPENAI_API_KEY_INTERACTIVE"
+/// models = "openai/gpt-4o*"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub struct ApiKeyRoute {
+    /// The name of the environment variable or secret containing the API key
+    pub secret: String,
+
+    /// The priority of the tasks routed to the key
+    ///
+    /// If not specified, tasks of any priority are routed to the key.
+    pub priority: Option<TaskPriority>,
+
+    /// A glob pattern for the ids of the models routed to the key e.g. `openai/o*`
+    ///
+    /// If not specified, tasks for any of the provider's models are routed to the key.
+    pub models: Option<String>,
+}
+
+impl ApiKeyRoute {
+    /// Whether the route applies to a request to a model
+    ///
+    /// Routes with a priority do not apply to requests which are not for a task
+    /// (e.g. listing models).
+    fn matches(&self, default: &str, model: &str, task: Option<&ModelTask>) -> bool {
+        if self.secret == default || !self.secret.starts_with(default) {
+            return false;
+        }
+
+        if let Some(priority) = self.priority
+            && task.map(|task| task.priority.unwrap_or_default()) != Some(priority)
+        {
+            return false;
+        }
+
+        match &self.models {
+            Some(pattern) => Pattern::new(pattern)
+                .map(|pattern| pattern.matches(model))
+                .unwrap_or_else(|error| {
+                    tracing::warn!("Invalid model pattern in API key route: {error}");
+                    false
+                }),
+            None => true,
+        }
+    }
+}
+
+impl RequestsConfig {
+    /// Get the name of the secret containing the API key to use for a request to a model
+    ///
+    /// Returns the `default` secret name of the provider if no route matches.
+    pub fn api_key_secret(&self, default: &str, model: &str, task: Option<&ModelTask>) -> String {
+        self.api_keys
+            .iter()
+            .find(|route| route.matches(default, model, task))
+            .map(|route| route.secret.clone())
+            .unwrap_or_else(|| default.to_string())
+    }
+}
+
+/// Get the API key to use for a request to a model
+///
+/// Uses the key routed to by the `[[requests.api-keys]]` of the models config, falling
+/// back to the provider's `default` key (with a warning) if the routed key is
+/// not available.
+pub fn api_key(default: &str, model: &str, task: Option<&ModelTask>) -> Result<String> {
+    let secret = models_config()
+        .requests
+        .api_key_secret(default, model, task);
+    if secret != default {
+        match secrets::env_or_get(&secret) {
+            Ok(key) => return Ok(key),
+            Err(error) => {
+                tracing::warn!("API key `{secret}` is not available, using `{default}`: {error}")
+            }
+        }
+    }
+
+    secrets::env_or_get(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::toml;
+
+    use crate::ModelsConfig;
+
+    use super::*;
+
+    #[test]
+    fn routes_keys() -> Result<()> {
+        let config: ModelsConfig = toml::from_str(
+            r#"
+[[requests.api-keys]]
+secret = "OPENAI_API_KEY_BATCH"
+priority = "batch"
+
+[[requests.api-keys]]
+secret = "OPENAI_API_KEY_INTERACTIVE"
+models = "openai/gpt-4o*"
+
+[[requests.api-keys]]
+secret = "ANTHROPIC_API_KEY_DEMO"
+"#,
+        )?;
+        let config = config.requests;
+
+        let batch = ModelTask {
+            priority: Some(TaskPriority::Batch),
+            ..Default::default()
+        };
+        let interactive = ModelTask::default();
+        let secret = |model: &str, task: Option<&ModelTask>| {
+            config.api_key_secret("OPENAI_API_KEY", model, task)
+        };
+
+        assert_eq!(
+            secret("openai/gpt-4o", Some(&batch)),
+            "OPENAI_API_KEY_BATCH"
+        );
+        assert_eq!(
+            secret("openai/gpt-4o", Some(&interactive)),
+            "OPENAI_API_KEY_INTERACTIVE"
+        );
+        assert_eq!(secret("openai/o3", Some(&interactive)), "OPENAI_API_KEY");
+        assert_eq!(
+            secret("openai/gpt-4o-mini", None),
+            "OPENAI_API_KEY_INTERACTIVE"
+        );
+        assert_eq!(
+            config.api_key_secret("ANTHROPIC_API_KEY", "anthropic/claude", Some(&batch)),
+            "ANTHROPIC_API_KEY_DEMO"
+        );
+        assert_eq!(
+            config.api_key_secret("MISTRAL_API_KEY", "mistral/large", Some(&batch)),
+            "MISTRAL_API_KEY"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use common::eyre::Result;
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{
+    ModelOutput, ModelOutputKind, ModelTask, ModelTaskKind, caption, code, dataset, grounding,
+    translation,
+};
+
+/// Specialize a task of a kind performed as message generation
+///
+/// Task kinds such as [`ModelTaskKind::CaptionGeneration`] are not performed
+/// by models directly. Instead, prompt scaffolding (e.g. instructions and
+/// validators) is added to the task and its kind set to
+/// [`ModelTaskKind::MessageGeneration`] so that it can be performed by any
+/// model supporting that. Returns the original kind of the task, to pass to
+/// [`finish_specialized_task`], or `None` if the task is not specialized.
+pub fn specialize_task(task: &mut ModelTask) -> Result<Option<ModelTaskKind>> {
+    let kind = task.kind;
+    match kind {
+        ModelTaskKind::MessageGeneration | ModelTaskKind::ImageGeneration => return Ok(None),
+        ModelTaskKind::CaptionGeneration => caption::prepare(task)?,
+        ModelTaskKind::CodeGeneration => code::prepare(task)?,
+        ModelTaskKind::DataDescription => dataset::prepare(task)?,
+        ModelTaskKind::Translation => translation::prepare(task)?,
+        ModelTaskKind::QuestionAnswering => grounding::prepare(task)?,
+    }
+
+    task.kind = ModelTaskKind::MessageGeneration;
+    Ok(Some(kind))
+}
+
+/// Finish a specialized task by post-processing its output
+///
+/// Should be called with the kind returned by [`specialize_task`] after the
+/// output has passed the task's validators. Outputs of dry runs, and outputs
+/// which are not text, are left unchanged.
+pub fn finish_specialized_task(
+    kind: ModelTaskKind,
+    task: &ModelTask,
+    output: &mut ModelOutput,
+) -> Result<()> {
+    if task.dry_run || !matches!(output.kind, ModelOutputKind::Text) {
+        return Ok(());
+    }
+
+    match kind {
+        ModelTaskKind::MessageGeneration | ModelTaskKind::ImageGeneration => Ok(()),
+        ModelTaskKind::CaptionGeneration => caption::finish(output),
+        ModelTaskKind::CodeGeneration => {
+            code::finish(task, output);
+            Ok(())
+        }
+        ModelTaskKind::DataDescription => dataset::finish(task, output),
+        ModelTaskKind::Translation => translation::finish(task, output),
+        ModelTaskKind::QuestionAnswering => {
+            grounding::finish(task, output);
+            Ok(())
+        }
+    }
+}
+
+/// Insert a system message after any existing system (or developer) messages of a task
+pub(crate) fn insert_system_message(task: &mut ModelTask, text: String) {
+    let index = task
+        .messages
+        .iter()
+        .position(|message| {
+            !matches!(
+                message.role,
+                Some(MessageRole::System | MessageRole::Developer)
+            )
+        })
+        .unwrap_or(task.messages.len());
+    task.messages.insert(
+        index,
+        InstructionMessage {
+            role: Some(MessageRole::System),
+            parts: vec![MessagePart::from(text)],
+            ..Default::default()
+        },
+    );
+}
@@ -0,0 +1,441 @@
+use common::{
+    eyre::Result,
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Model, ModelOutput, ModelOutputKind, ModelTask, ModelWarning, Policy};
+
+/// The languages which can be detected, as ISO 639-1 code and English name
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("zh", "Chinese"),
+];
+
+/// Common words of languages written in the Latin script
+///
+/// Very common words shared by most of the languages (e.g. "de", "a") are omitted
+/// because they are not evidence for any one language.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "was", "were", "of", "to", "that", "this", "with", "for",
+            "it", "not", "be", "have", "has", "which", "from", "by", "at", "or", "an", "they",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "des", "du", "un", "une", "pour", "que", "qui", "dans",
+            "sur", "pas", "au", "aux", "avec", "ce", "cette", "sont", "il", "nous", "vous",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "y", "es", "del", "una", "por", "con", "para", "que", "su", "al",
+            "lo", "como", "más", "pero", "sus", "está", "son", "este", "esta", "muy", "también",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "den", "von", "mit",
+            "sich", "des", "auf", "für", "dem", "auch", "es", "an", "werden", "sind", "wird",
+            "oder",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "gli", "è", "della", "che", "di", "per", "non", "sono", "nel", "alla", "delle",
+            "con", "si", "anche", "dei", "questo", "una", "come", "più", "ma", "lo", "le", "ed",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "as", "e", "é", "do", "da", "dos", "das", "em", "um", "uma", "para", "com",
+            "não", "no", "na", "por", "mais", "ao", "como", "seu", "sua", "são",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "het", "een", "en", "van", "is", "dat", "op", "te", "zijn", "voor", "niet", "met",
+            "ook", "maar", "wordt", "bij", "door", "deze", "naar", "aan", "werd", "hij", "ze",
+            "wat",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "att", "det", "som", "är", "av", "för", "på", "med", "inte", "till", "den",
+            "ett", "har", "om", "var", "men", "kan", "vi", "från", "så", "eller", "när", "också",
+        ],
+    ),
+];
+
+/// The minimum number of letters in text for its language to be detected
+const MIN_LETTERS: usize = 20;
+
+/// A correction of the language of an output
+///
+/// Recorded in the [`TaskReport`](crate::TaskReport) of the output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct LanguageCorrection {
+    /// The ISO 639-1 code of the language detected in the original output
+    pub detected: String,
+
+    /// The ISO 639-1 code of the expected language
+    pub expected: String,
+
+    /// Whether the output was in the expected language after re-prompting
+    pub corrected: bool,
+}
+
+/// Get the ISO 639-1 code of a language from its code or English name
+///
+/// Returns `None` for languages that can not be detected.
+pub fn language_code(language: &str) -> Option<&'static str> {
+    let language = language.trim();
+    LANGUAGES
+        .iter()
+        .find(|(code, name)| {
+            code.eq_ignore_ascii_case(language)
+                || name.eq_ignore_ascii_case(language)
+                || language
+                    .split(['-', '_'])
+                    .next()
+                    .is_some_and(|primary| code.eq_ignore_ascii_case(primary))
+        })
+        .map(|(code, ..)| *code)
+}
+
+/// Get the English name of a language from its ISO 639-1 code
+fn language_name(code: &str) -> &str {
+    LANGUAGES
+        .iter()
+        .find(|(lang, ..)| *lang == code)
+        .map_or(code, |(.., name)| name)
+}
+
+/// Detect the language of text
+///
+/// A simple, local, detector which uses the script of the text and, for text in
+/// the Latin script, the frequency of common words. Fenced code blocks and inline
+/// code are ignored. Returns the ISO 639-1 code of the language, or `None` if the
+/// text is too short or its language is ambiguous or not one of those detectable.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let prose = strip_code(text);
+
+    let letters = prose
+        .chars()
+        .filter(|char| char.is_alphabetic())
+        .collect_vec();
+    if letters.len() < MIN_LETTERS {
+        return None;
+    }
+
+    // Languages with distinctive scripts. Japanese is distinguished from Chinese
+    // by the presence of kana.
+    let count = |range: &[(u32, u32)]| {
+        letters
+            .iter()
+            .filter(|char| {
+                range
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&(**char as u32)))
+            })
+            .count()
+    };
+    let kana = count(&[(0x3040, 0x30FF)]);
+    let scripts = [
+        ("ar", count(&[(0x0600, 0x06FF)])),
+        ("el", count(&[(0x0370, 0x03FF)])),
+        ("he", count(&[(0x0590, 0x05FF)])),
+        ("hi", count(&[(0x0900, 0x097F)])),
+        ("ja", kana),
+        ("ko", count(&[(0xAC00, 0xD7AF), (0x1100, 0x11FF)])),
+        ("ru", count(&[(0x0400, 0x04FF)])),
+        ("th", count(&[(0x0E00, 0x0E7F)])),
+        ("zh", count(&[(0x4E00, 0x9FFF)])),
+    ];
+    let (script, script_count) = scripts
+        .iter()
+        .max_by_key(|(.., count)| *count)
+        .copied()
+        .unwrap_or_default();
+    if script_count * 2 > letters.len() {
+        return Some(if script == "zh" && kana > 0 {
+            "ja"
+        } else {
+            script
+        });
+    }
+
+    // Languages written in the Latin script
+    let words = prose
+        .split(|char: char| !char.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect_vec();
+    let scores = STOPWORDS
+        .iter()
+        .map(|(code, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*code, hits)
+        })
+        .sorted_by_key(|(.., hits)| std::cmp::Reverse(*hits))
+        .collect_vec();
+
+    match scores.as_slice() {
+        [(code, best), (.., second), ..] if *best >= 2 && *best > *second => Some(code),
+        _ => None,
+    }
+}
+
+/// Remove fenced code blocks, and inline code, from Markdown
+fn strip_code(text: &str) -> String {
+    let mut in_fence = false;
+    text.lines()
+        .filter(|line| {
+            let is_fence = line.trim_start().starts_with("```");
+            if is_fence {
+                in_fence = !in_fence;
+            }
+            !is_fence && !in_fence
+        })
+        .map(|line| {
+            line.split('`')
+                .enumerate()
+                .filter(|(index, ..)| index % 2 == 0)
+                .map(|(.., part)| part)
+                .join(" ")
+        })
+        .join("\n")
+}
+
+/// Enforce the expected language of a task on the output of a model
+///
+/// If the language detected in the output differs from [`ModelTask::expected_language`],
+/// the model is re-prompted, once, asking it to answer in the expected language.
+/// Returns the correction made, if any, for the task report. Outputs which are not
+/// text, or are in a format other than Markdown or plain text, are not checked.
+/// The re-prompt is checked against, and recorded by, the `policy`.
+pub async fn enforce_language(
+    model: &dyn Model,
+    task: &ModelTask,
+    output: &mut ModelOutput,
+    policy: &Policy,
+) -> Result<Option<LanguageCorrection>> {
+    let Some(expected) = task.expected_language.as_deref() else {
+        return Ok(None);
+    };
+    if task.dry_run
+        || !matches!(output.kind, ModelOutputKind::Text)
+        || !(output.format.is_unknown()
+            || output.format.is_markdown_flavor()
+            || matches!(output.format, format::Format::Text))
+    {
+        return Ok(None);
+    }
+
+    let Some(expected) = language_code(expected) else {
+        output.warnings.push(ModelWarning::ignored_option(format!(
+            "Expected language `{expected}` can not be detected so is not enforced"
+        )));
+        return Ok(None);
+    };
+
+    let Some(detected) = detect_language(&output.content).filter(|code| *code != expected) else {
+        return Ok(None);
+    };
+
+    tracing::debug!(
+        "Output of model `{}` is in {} rather than {}, re-prompting",
+        model.id(),
+        language_name(detected),
+        language_name(expected)
+    );
+
+    let mut retry = task.clone();
+    retry.messages.push(InstructionMessage {
+        role: Some(MessageRole::Model),
+        parts: vec![MessagePart::from(output.content.as_str())],
+        ..Default::default()
+    });
+    retry.messages.push(InstructionMessage {
+        role: Some(MessageRole::User),
+        parts: vec![MessagePart::from(format!(
+            "Your previous answer was in {}, but it must be in {}. Please give the same answer, in {}.",
+            language_name(detected),
+            language_name(expected),
+            language_name(expected)
+        ))],
+        ..Default::default()
+    });
+    let retried = policy.perform(model, &retry).await?;
+
+    let corrected = detect_language(&retried.content).is_none_or(|code| code == expected);
+    let message = if corrected {
+        format!(
+            "Output was in {} so the model was asked to answer in {}",
+            language_name(detected),
+            language_name(expected)
+        )
+    } else {
+        format!(
+            "Output was in {}, rather than {}, even after re-prompting",
+            language_name(detected),
+            language_name(expected)
+        )
+    };
+
+    let mut warnings = std::mem::take(&mut output.warnings);
+    warnings.extend(retried.warnings.iter().cloned());
+    warnings.push(ModelWarning::correction(message));
+    *output = ModelOutput {
+        warnings,
+        ..retried
+    };
+
+    Ok(Some(LanguageCorrection {
+        detected: detected.into(),
+        expected: expected.into(),
+        corrected,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, eyre::bail, tokio};
+
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(language_code("fr"), Some("fr"));
+        assert_eq!(language_code("French"), Some("fr"));
+        assert_eq!(language_code("en-AU"), Some("en"));
+        assert_eq!(language_code("Klingon"), None);
+    }
+
+    #[test]
+    fn detects() {
+        assert_eq!(
+            detect_language("The shoreline at Narrabeen is eroding and the rate has increased."),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language("Le trait de côte recule et la plage est plus étroite que prévu."),
+            Some("fr")
+        );
+        assert_eq!(
+            detect_language(
+                "La línea de costa retrocede y la playa es más estrecha por el oleaje."
+            ),
+            Some("es")
+        );
+        assert_eq!(
+            detect_language("Die Küstenlinie weicht zurück und der Strand ist nicht mehr breit."),
+            Some("de")
+        );
+        assert_eq!(
+            detect_language("Береговая линия отступает, и пляж становится уже."),
+            Some("ru")
+        );
+        assert_eq!(
+            detect_language("海岸線は後退しており、砂浜は狭くなっています。"),
+            Some("ja")
+        );
+        assert_eq!(
+            detect_language("海岸线正在后退，海滩变得越来越窄了。我们需要更多数据。"),
+            Some("zh")
+        );
+
+        // Too short, or ambiguous
+        assert_eq!(detect_language("Merci"), None);
+        assert_eq!(detect_language("12345 67890 12345 67890 12345"), None);
+
+        // Code is ignored
+        assert_eq!(
+            detect_language(
+                "Le code est dans `the file` et la sortie est correcte.\n\n```\nthe and is the\n```"
+            ),
+            Some("fr")
+        );
+    }
+
+    struct French;
+
+    #[async_trait]
+    impl Model for French {
+        fn id(&self) -> String {
+            "test/french".into()
+        }
+
+        async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+            let prompt = task.prompt_as_text().unwrap_or_default();
+            if !prompt.contains("must be in French") {
+                bail!("expected a request to answer in French")
+            }
+            Ok(ModelOutput {
+                content: "Le trait de côte recule et la plage est plus étroite.".into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces() -> Result<()> {
+        let task = ModelTask {
+            expected_language: Some("French".into()),
+            ..Default::default()
+        };
+
+        let mut output = ModelOutput {
+            content: "The shoreline is eroding and the beach is narrower than it was.".into(),
+            ..Default::default()
+        };
+        let correction = enforce_language(&French, &task, &mut output, &Policy::default()).await?;
+        assert_eq!(
+            correction,
+            Some(LanguageCorrection {
+                detected: "en".into(),
+                expected: "fr".into(),
+                corrected: true
+            })
+        );
+        assert!(output.content.starts_with("Le trait"));
+        assert_eq!(output.warnings.len(), 1);
+
+        // Output already in the expected language is unchanged
+        let correction = enforce_language(&French, &task, &mut output, &Policy::default()).await?;
+        assert_eq!(correction, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+};
+
+use common::{
+    chrono::{DateTime, Utc},
+    eyre::Result,
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_json,
+};
+
+use crate::{ModelOutput, TaskReport};
+
+/// A ledger of the model invocations made for a document
+///
+/// Records the [`TaskReport`] of each output generated for a node of a document
+/// so that the tokens, cost, models used, cache hits and wall time can be
+/// aggregated, by node and for the whole document. The ledger is plain JSON so
+/// it can be persisted alongside the document (see [`UsageLedger::save`]) and
+/// rendered as a "compute provenance" appendix using [`UsageLedger::to_markdown`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct UsageLedger {
+    /// The entries of the ledger, in the order they were recorded
+    pub entries: Vec<LedgerEntry>,
+}
+
+/// An invocation of a model recorded in a [`UsageLedger`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct LedgerEntry {
+    /// The id of the node that the output was generated for
+    pub node_id: String,
+
+    /// The time the entry was recorded
+    pub recorded: DateTime<Utc>,
+
+    /// The report of how the task was performed
+    pub report: TaskReport,
+}
+
+/// Totals for a set of entries in a [`UsageLedger`]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct LedgerTotals {
+    /// The number of invocations, including those served from the cache
+    pub invocations: usize,
+
+    /// The number of invocations served from the cache
+    pub cache_hits: usize,
+
+    /// The (estimated) number of prompt tokens sent to models
+    pub prompt_tokens: usize,
+
+    /// The (estimated) number of output tokens generated by models
+    pub output_tokens: usize,
+
+    /// The (estimated) cost in US dollars of invocations for which the cost of the model is known
+    pub cost: f64,
+
+    /// The number of invocations, not served from the cache, for which the cost is unknown
+    pub unpriced: usize,
+
+    /// The total wall time of invocations, in milliseconds
+    pub latency_ms: u64,
+
+    /// The ids of the models used
+    pub models: BTreeSet<String>,
+}
+
+impl LedgerTotals {
+    /// Add an entry to the totals
+    ///
+    /// Outputs served from the cache count as hits but do not add to the tokens
+    /// or cost (which were incurred when the output was first generated).
+    fn add(&mut self, entry: &LedgerEntry) {
+        let report = &entry.report;
+        self.invocations += 1;
+        self.models.insert(report.model.clone());
+        if report.cached {
+            self.cache_hits += 1;
+            return;
+        }
+
+        self.prompt_tokens += report.prompt_tokens;
+        self.output_tokens += report.output_tokens;
+        self.latency_ms += report.latency_ms;
+        match report.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+
+    /// The cost formatted for display e.g. `$0.0123`, or `≥ $0.0123` if some invocations are unpriced
+    fn cost_display(&self) -> String {
+        let prefix = if self.unpriced > 0 { "≥ " } else { "" };
+        format!("{prefix}${:.4}", self.cost)
+    }
+}
+
+impl UsageLedger {
+    /// Create an empty ledger
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a ledger from a JSON file, returning an empty ledger if the file does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+
+    /// Save the ledger to a JSON file
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Record a task report for a node
+    pub fn record(&mut self, node_id: &str, report: TaskReport) {
+        self.entries.push(LedgerEntry {
+            node_id: node_id.into(),
+            recorded: Utc::now(),
+            report,
+        });
+    }
+
+    /// Record the output generated for a node
+    ///
+    /// Outputs without a report (e.g. those not generated using `models::perform_task`)
+    /// are not recorded.
+    pub fn record_output(&mut self, node_id: &str, output: &ModelOutput) {
+        if let Some(report) = &output.report {
+            self.record(node_id, report.clone());
+        }
+    }
+
+    /// Get the totals for the whole document
+    pub fn totals(&self) -> LedgerTotals {
+        let mut totals = LedgerTotals::default();
+        for entry in &self.entries {
+            totals.add(entry);
+        }
+        totals
+    }
+
+    /// Get the totals for each user, ordered by user identifier
+    ///
+    /// Entries for tasks without a user are totalled under an empty identifier.
+    pub fn by_user(&self) -> BTreeMap<String, LedgerTotals> {
+        let mut users: BTreeMap<String, LedgerTotals> = BTreeMap::new();
+        for entry in &self.entries {
+            users
+                .entry(entry.report.user.clone().unwrap_or_default())
+                .or_default()
+                .add(entry);
+        }
+        users
+    }
+
+    /// Get the totals for each node, ordered by node id
+    pub fn by_node(&self) -> BTreeMap<String, LedgerTotals> {
+        let mut nodes: BTreeMap<String, LedgerTotals> = BTreeMap::new();
+        for entry in &self.entries {
+            nodes.entry(entry.node_id.clone()).or_default().add(entry);
+        }
+        nodes
+    }
+
+    /// Render the ledger as a Markdown "compute provenance" appendix
+    ///
+    /// Includes a table of the totals for each node and a row of totals for the document.
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::from("# Compute provenance\n\n");
+        if self.entries.is_empty() {
+            md.push_str("No models were used to generate this document.\n");
+            return md;
+        }
+
+        let totals = self.totals();
+        md.push_str(&format!(
+            "{} model invocations ({} from cache) using {}.\n\n",
+            totals.invocations,
+            totals.cache_hits,
+            totals
+                .models
+                .iter()
+                .map(|model| format!("`{model}`"))
+                .join(", ")
+        ));
+
+        md.push_str("| Node | Models | Invocations | Cache hits | Prompt tokens | Output tokens | Cost (USD) | Wall time (s) |\n|---|---|---|---|---|---|---|---|\n");
+        let row = |name: &str, totals: &LedgerTotals| {
+            format!(
+                "| {name} | {} | {} | {} | {} | {} | {} | {:.1} |\n",
+                totals.models.iter().join(", "),
+                totals.invocations,
+                totals.cache_hits,
+                totals.prompt_tokens,
+                totals.output_tokens,
+                totals.cost_display(),
+                totals.latency_ms as f64 / 1000.
+            )
+        };
+        for (node_id, node_totals) in self.by_node() {
+            md.push_str(&row(&format!("`{node_id}`"), &node_totals));
+        }
+        md.push_str(&row("**Total**", &totals));
+
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::bail, tempfile::tempdir};
+
+    use super::*;
+
+    fn report(model: &str, cost: Option<f64>, cached: bool) -> TaskReport {
+        TaskReport {
+            model: model.into(),
+            prompt_tokens: 1000,
+            output_tokens: 200,
+            cost,
+            cached,
+            latency_ms: 1500,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aggregates_by_node() -> Result<()> {
+        let mut ledger = UsageLedger::new();
+        let mut alice = report("openai/gpt-4o", Some(0.01), false);
+        alice.user = Some("alice".into());
+        ledger.record("ins_1", alice.clone());
+        alice.cached = true;
+        ledger.record("ins_1", alice);
+        ledger.record("ins_2", report("ollama/llama3", None, false));
+        ledger.record_output("ins_3", &ModelOutput::default());
+
+        let totals = ledger.totals();
+        assert_eq!(totals.invocations, 3);
+        assert_eq!(totals.cache_hits, 1);
+        assert_eq!(totals.prompt_tokens, 2000);
+        assert_eq!(totals.output_tokens, 400);
+        assert_eq!(totals.unpriced, 1);
+        assert_eq!(totals.latency_ms, 3000);
+        assert!((totals.cost - 0.01).abs() < 1e-9);
+        assert_eq!(totals.models.len(), 2);
+
+        let nodes = ledger.by_node();
+        assert_eq!(nodes.keys().collect_vec(), vec!["ins_1", "ins_2"]);
+        let Some(first) = nodes.get("ins_1") else {
+            bail!("expected node")
+        };
+        assert_eq!((first.invocations, first.cache_hits), (2, 1));
+
+        let users = ledger.by_user();
+        assert_eq!(users.keys().collect_vec(), vec!["", "alice"]);
+        assert_eq!(users.get("alice").map(|totals| totals.invocations), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn renders_markdown() {
+        let mut ledger = UsageLedger::new();
+        assert!(ledger.to_markdown().contains("No models were used"));
+
+        ledger.record("ins_1", report("openai/gpt-4o", Some(0.01), false));
+        ledger.record("ins_2", report("ollama/llama3", None, false));
+        let md = ledger.to_markdown();
+        assert!(md.starts_with("# Compute provenance"));
+        assert!(md.contains("| `ins_1` | openai/gpt-4o | 1 | 0 | 1000 | 200 | $0.0100 | 1.5 |"));
+        assert!(md.contains(
+            "| **Total** | ollama/llama3, openai/gpt-4o | 2 | 0 | 2000 | 400 | ≥ $0.0100 | 3.0 |"
+        ));
+    }
+
+    #[test]
+    fn saves_and_loads() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("ledger.json");
+        assert_eq!(UsageLedger::load(&path)?, UsageLedger::default());
+
+        let mut ledger = UsageLedger::new();
+        ledger.record("ins_1", report("openai/gpt-4o", Some(0.01), false));
+        ledger.save(&path)?;
+        assert_eq!(UsageLedger::load(&path)?, ledger);
+
+        Ok(())
+    }
+}
@@ -13,22 +13,167 @@ use schema::{
     StringOrNumber, Timestamp,
 };
 
+use format::Format;
+
 // Export crates for the convenience of dependant crates
 pub use common;
 pub use format;
 pub use schema;
 pub use secrets;
 
+mod aliases;
+mod attachments;
+mod attestation;
+mod audit;
+mod builder;
+mod cache;
+mod candidates;
+mod caption;
+mod catalog;
+mod citations;
+mod code;
+mod compression;
+mod config;
+mod continuation;
+mod dataset;
+mod delta;
+mod deprecations;
+mod derived;
+mod diff;
+mod ensemble;
+mod fingerprint;
+mod formats;
+mod grounding;
+mod health;
+mod image_manifest;
+mod json_repair;
+mod keys;
+mod kinds;
+mod language;
+mod ledger;
+mod manifest;
+mod media;
+mod memory;
 mod output;
+mod patches;
+mod policy;
+mod postprocess;
+mod preamble;
+mod preview;
+mod prov;
+mod queue;
+mod registry;
+mod report;
+mod retrieval;
+mod ro_crate;
+mod runs;
+mod safety;
+mod selection;
+mod semantic_cache;
+mod staleness;
+mod sweep;
 mod task;
-pub use output::{ModelOutput, ModelOutputKind};
-pub use task::{ModelTask, ModelTaskKind};
+mod tokens;
+mod tool_calls;
+mod translation;
+mod validators;
+mod video;
+mod warnings;
+mod workflow;
+pub use aliases::ModelAlias;
+pub use attachments::{AttachmentExpansion, expand_archive, expand_attachment};
+pub use attestation::{Attestation, AttestationConfig, SigningKey, attest};
+pub use audit::{ModelAudit, audit_dir, audit_enabled, redact_secrets};
+pub use builder::ModelTaskBuilder;
+pub use cache::{CacheMetrics, TtlCache};
+pub use candidates::{
+    CandidateSelection, CandidateSelector, FirstSelector, LongestSelector, MajorityVoteSelector,
+    RerankerSelector,
+};
+pub use caption::FigureContext;
+pub use catalog::{ModelCatalog, ModelQuery};
+pub use citations::{add_web_citations, citations_block, extract_citations};
+pub use code::check_code;
+pub use compression::{PromptCompression, compress_prompt, estimate_prompt_tokens};
+pub use config::{
+    AttachmentsConfig, AuditConfig, CacheConfig, CatalogConfig, CostCaps, ModelSpec, ModelsConfig,
+    OutputsConfig, ProviderConfig, QueueConfig, RecordsConfig, RequestsConfig, RetryPolicy,
+    models_config, set_models_config,
+};
+pub use continuation::{auto_continue, stitch_segments};
+pub use dataset::{ColumnStatistics, DatasetStatistics};
+pub use delta::{
+    AttachmentDelta, AttachmentDeltaOptions, attachment_delta, attachment_delta_key,
+    record_sent_attachment,
+};
+pub use deprecations::ModelDeprecation;
+pub use derived::{DerivedCache, DerivedCacheConfig, derived_cache};
+pub use diff::{JsonChange, OutputDiff, diff_outputs};
+pub use ensemble::{EnsembleMember, EnsembleModel};
+pub use fingerprint::{FingerprintOptions, TaskFingerprint, canonical_json};
+pub use formats::{format_instruction, negotiate_format, repair_text, supports_format};
+pub use grounding::{GroundedClaim, parse_grounding};
+pub use health::{LatencyPercentiles, ModelHealth, latency_percentiles, record_latency};
+pub use image_manifest::{
+    ImageManifest, embed_data_url_manifest, embed_png_manifest, read_png_manifest,
+    write_image_manifest,
+};
+pub use json_repair::repair_json;
+pub use keys::{ApiKeyRoute, api_key};
+pub use kinds::{finish_specialized_task, specialize_task};
+pub use language::{LanguageCorrection, detect_language, enforce_language, language_code};
+pub use ledger::{LedgerEntry, LedgerTotals, UsageLedger};
+pub use manifest::{ManifestDisposition, ManifestEntry, ManifestSource, PromptManifest};
+pub use media::{
+    correct_media_type, file_bytes, file_data_url, file_prefix, file_text,
+    message_part_media_types, sniff_attachment_media_type, sniff_media_type,
+};
+pub use memory::ChatMemory;
+pub use output::{
+    FinishReason, ImageGeneration, ModelOutput, ModelOutputKind, ModelOutputPart, TokenUsage,
+};
+pub use patches::{PatchCallback, PatchStream};
+pub use policy::{Policy, PolicyRule};
+pub use postprocess::PostProcessing;
+pub use preamble::attachment_preamble;
+pub use preview::{PreviewOptions, preview_attachment};
+pub use prov::{PROV_NAMESPACE, ProvDocument, ProvKind, ProvNode, ProvRelation, ProvRelationKind};
+pub use queue::{QueuePermit, QueueStatus, TaskQueue, task_queue};
+pub use registry::{
+    HOST_CAPABILITIES, ProviderCapability, ProviderFactory, ProviderRegistration,
+    register_provider, registered_providers,
+};
+pub use report::{ModelSubstitution, TaskReport};
+pub use retrieval::{Chunk, RetrievalOptions, VectorIndex, chunk_text, retrieve_context};
+pub use ro_crate::{RO_CRATE_METADATA, RoCrate};
+pub use runs::{Run, RunStore, RunStoreConfig, run_store};
+pub use safety::{SafetyAction, SafetyDecision, SafetyFilter, SafetyRule};
+pub use selection::{select_attachment, select_attachments};
+pub use semantic_cache::{Embedder, SemanticCache, cosine_similarity};
+pub use staleness::{ArtifactChange, StaleNode, StalenessChecker, refresh_artifacts};
+pub use sweep::{ParameterGrid, ParameterPoint, Sweep, SweepResult};
+pub use task::{
+    AttachmentFailurePolicy, ImageDetailLevel, ImagePersistence, ModelTask, ModelTaskKind,
+    PromptCaching, TaskPriority,
+};
+pub use tokens::{Bpe, TokenCount, TokenEncoding, count_tokens};
+pub use tool_calls::{
+    CodeInterpreterOptions, ToolCall, ToolDefinition, ToolHandler, WebSearchContextSize,
+    WebSearchOptions,
+};
+pub use translation::{AlignedSentence, TranslationOptions};
+pub use validators::{PredicateFn, Validator, enforce_validators};
+pub use video::{
+    VideoSampling, extract_attachment_frames, extract_video_frames, replace_videos_with_frames,
+};
+pub use warnings::{ModelWarning, ModelWarningKind};
+pub use workflow::WorkflowArtifact;
 
 /// The type of provider of a model
 ///
 /// This ordering here is important as it is used when
 /// selecting a model to execute a task.
-#[derive(Display, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, Display, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(crate = "common::serde")]
 pub enum ModelType {
     Builtin,
@@ -39,6 +184,16 @@ pub enum ModelType {
     Plugin(String),
 }
 
+impl ModelType {
+    /// Whether models of this type run on this machine
+    ///
+    /// Routers and proxies forward tasks to remote models, and plugins may, so
+    /// only builtin and local models are considered local.
+    pub fn is_local(&self) -> bool {
+        matches!(self, ModelType::Builtin | ModelType::Local)
+    }
+}
+
 /// The availability of a model on the current machine
 #[derive(Display, Clone, Copy, Serialize, Deserialize)]
 #[serde(crate = "common::serde")]
@@ -83,6 +238,8 @@ pub struct ModelSpecification {
     version: String,
     r#type: ModelType,
     availability: ModelAvailability,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
     quality_score: Option<u32>,
     cost_score: Option<u32>,
     speed_score: Option<u32>,
@@ -97,6 +254,7 @@ impl From<&dyn Model> for ModelSpecification {
             version: model.version(),
             r#type: model.r#type(),
             availability: model.availability(),
+            stale: model.is_stale(),
             quality_score: model.quality_score(),
             cost_score: model.cost_score(),
             speed_score: model.speed_score(),
@@ -131,6 +289,24 @@ pub trait Model: Sync + Send {
         matches!(self.availability(), ModelAvailability::Available)
     }
 
+    /// Was the model listed from a previously saved list?
+    ///
+    /// Providers which fall back to the last successfully fetched list of models
+    /// when their API is unreachable should override this to return `true` for
+    /// those models so that users can be warned that the model may no longer exist.
+    fn is_stale(&self) -> bool {
+        false
+    }
+
+    /// Does the model perform tasks by making requests to other models?
+    ///
+    /// Models which do (e.g. ensembles) make those requests through the task queue
+    /// and policy themselves, so should override this to return `true` so that they
+    /// do not also hold a queue permit, or have their tokens counted, for the task.
+    fn is_composite(&self) -> bool {
+        false
+    }
+
     /// Get the name of the provider of the model
     ///
     /// This default implementation returns the title cased name
@@ -229,6 +405,23 @@ pub trait Model: Sync + Send {
         0
     }
 
+    /// Get the byte pair encoding used by the model, if known
+    ///
+    /// Used by [`Model::count_tokens`]. Models using a `tiktoken` encoding should override.
+    fn token_encoding(&self) -> Option<TokenEncoding> {
+        None
+    }
+
+    /// Count the number of tokens in a list of messages
+    ///
+    /// Allows callers to check the size of a prompt against the [`Model::context_length`]
+    /// without a request to the model. This default implementation uses the model's
+    /// [`Model::token_encoding`], if its BPE file is available locally, and otherwise
+    /// estimates the count from the number of characters (and marks it as estimated).
+    fn count_tokens(&self, messages: &[InstructionMessage]) -> TokenCount {
+        count_tokens(self.token_encoding(), messages)
+    }
+
     /// Does the model support a specific task
     ///
     /// This default implementation is based solely on whether the models
@@ -253,6 +446,15 @@ pub trait Model: Sync + Send {
         &[]
     }
 
+    /// Get a list of formats that the model can be constrained to generate
+    ///
+    /// For example, models with a JSON mode should include `Format::Json`.
+    /// Markdown and plain text are assumed to be supported by all models
+    /// and do not need to be included. See [`supports_format`].
+    fn native_formats(&self) -> &[Format] {
+        &[]
+    }
+
     /// Get the overall quality score for the model
     ///
     /// This should be a score in the range 0-100 representing the overall quality
@@ -271,6 +473,15 @@ pub trait Model: Sync + Send {
         None
     }
 
+    /// Get the cost of the model in US dollars per million tokens
+    ///
+    /// Where input and output tokens are priced differently this should be a blended
+    /// cost assuming three input tokens for each output token. Used to filter models
+    /// in a [`ModelCatalog`]. Returns `None` if the cost is not known.
+    fn cost_per_mtok(&self) -> Option<f64> {
+        None
+    }
+
     /// Get the speed score for the model
     ///
     /// This should be a score in the range 0-100 representing the speed of
@@ -279,10 +490,45 @@ pub trait Model: Sync + Send {
         None
     }
 
+    /// Check the health of the model
+    ///
+    /// This default implementation does not make a request to the model and
+    /// reports it as healthy if it is available. Remote models should override
+    /// this to make a minimal request (e.g. retrieving the model's metadata)
+    /// and report the result using [`ModelHealth::probed`].
+    async fn health(&self) -> ModelHealth {
+        ModelHealth::unprobed(self.id(), self.is_available())
+    }
+
     /// Perform a generation task
+    ///
+    /// Implementations for models which do not run on this machine should first
+    /// call [`Policy::check_local_only`] so that local-only mode is enforced
+    /// however the model is called.
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput>;
+
+    /// Perform a generation task, streaming text as it is generated
+    ///
+    /// The `on_delta` callback is called with each chunk of generated text. This
+    /// default implementation does not stream: it performs the task and calls `on_delta`
+    /// once with the entire content of text outputs. Models which support streaming
+    /// should override.
+    async fn perform_task_streaming(
+        &self,
+        task: &ModelTask,
+        on_delta: &DeltaCallback,
+    ) -> Result<ModelOutput> {
+        let output = self.perform_task(task).await?;
+        if matches!(output.kind, ModelOutputKind::Text) {
+            on_delta(&output.content);
+        }
+        Ok(output)
+    }
 }
 
+/// A callback for chunks of text streamed from a model
+pub type DeltaCallback = dyn Fn(&str) + Send + Sync;
+
 /// Generate a test task which has system, user and model messages
 ///
 /// Used for tests of implementations of the `Model` trait to check that
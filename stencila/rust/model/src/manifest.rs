@@ -0,0 +1,191 @@
+use common::serde::{Deserialize, Serialize};
+use schema::{InstructionAttachment, MessagePart};
+
+/// A record of exactly what parts of a task's prompt reached the model
+///
+/// Providers map the messages and attachments of a task onto their API's request
+/// format and, in doing so, may drop parts the model does not support, replace
+/// them (e.g. a video with frames sampled from it, or a spreadsheet with a textual
+/// preview), or only send a slice of them. The manifest records an entry for each
+/// message part and attachment saying which of these happened, so that the
+/// provenance of generated content includes the evidence the model actually saw.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct PromptManifest {
+    /// The entries of the manifest, in the order they were recorded
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// An entry in a [`PromptManifest`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ManifestEntry {
+    /// The message part or attachment the entry is for
+    pub source: ManifestSource,
+
+    /// The kind of content e.g. `Text`, `ImageObject`, or the media type of an attachment
+    pub kind: String,
+
+    /// What happened to the content
+    pub disposition: ManifestDisposition,
+
+    /// Details of the disposition e.g. why content was excluded or how it was transformed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The source of a [`ManifestEntry`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", crate = "common::serde")]
+pub enum ManifestSource {
+    /// A part of one of the messages of the task
+    MessagePart {
+        /// The index of the message in the task's messages
+        message: usize,
+
+        /// The index of the part in the message's parts
+        part: usize,
+    },
+
+    /// One of the attachments of the task, or one derived from it (e.g. a video frame)
+    Attachment {
+        /// The alias of the attachment
+        alias: String,
+    },
+}
+
+/// What happened to content on its way to the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", crate = "common::serde")]
+pub enum ManifestDisposition {
+    /// The content was sent to the model as is
+    Included,
+
+    /// The content was not sent to the model
+    Excluded,
+
+    /// Only part of the content was sent to the model
+    Truncated,
+
+    /// The content was converted before being sent to the model
+    Transformed,
+}
+
+impl PromptManifest {
+    /// Create an empty manifest
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record what happened to a part of a message
+    pub fn part(
+        &mut self,
+        message: usize,
+        part: usize,
+        content: &MessagePart,
+        disposition: ManifestDisposition,
+        detail: Option<String>,
+    ) {
+        self.entries.push(ManifestEntry {
+            source: ManifestSource::MessagePart { message, part },
+            kind: content.to_string(),
+            disposition,
+            detail,
+        });
+    }
+
+    /// Record what happened to an attachment
+    pub fn attachment(
+        &mut self,
+        attachment: &InstructionAttachment,
+        disposition: ManifestDisposition,
+        detail: Option<String>,
+    ) {
+        self.entries.push(ManifestEntry {
+            source: ManifestSource::Attachment {
+                alias: attachment.alias.clone(),
+            },
+            kind: attachment
+                .file
+                .media_type
+                .clone()
+                .unwrap_or_else(|| "unknown".into()),
+            disposition,
+            detail,
+        });
+    }
+
+    /// Get the entries with a disposition
+    pub fn with_disposition(
+        &self,
+        disposition: ManifestDisposition,
+    ) -> impl Iterator<Item = &ManifestEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.disposition == disposition)
+    }
+
+    /// Whether everything in the task reached the model unchanged
+    pub fn is_complete(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| entry.disposition == ManifestDisposition::Included)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::Result, serde_json};
+    use schema::File;
+
+    use super::*;
+
+    #[test]
+    fn records_entries() -> Result<()> {
+        let mut manifest = PromptManifest::new();
+        assert!(manifest.is_complete());
+
+        manifest.part(
+            0,
+            0,
+            &MessagePart::from("Summarize"),
+            ManifestDisposition::Included,
+            None,
+        );
+        let mut file = File::new("clip.mp4".into(), "clip.mp4".into());
+        file.media_type = Some("video/mp4".into());
+        manifest.attachment(
+            &InstructionAttachment::new("clip".into(), file),
+            ManifestDisposition::Transformed,
+            Some("replaced by 4 frames".into()),
+        );
+
+        assert!(!manifest.is_complete());
+        assert_eq!(
+            manifest
+                .with_disposition(ManifestDisposition::Transformed)
+                .count(),
+            1
+        );
+        assert_eq!(
+            serde_json::to_value(&manifest)?,
+            serde_json::json!({
+                "entries": [
+                    {
+                        "source": {"type": "messagePart", "message": 0, "part": 0},
+                        "kind": "Text",
+                        "disposition": "included"
+                    },
+                    {
+                        "source": {"type": "attachment", "alias": "clip"},
+                        "kind": "video/mp4",
+                        "disposition": "transformed",
+                        "detail": "replaced by 4 frames"
+                    }
+                ]
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    fs::{File as FsFile, read},
+    io::Read,
+    path::Path,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::eyre::{Context, Result, bail};
+use format::Format;
+use schema::{AudioObject, File, ImageObject, InstructionAttachment, MessagePart, VideoObject};
+
+/// The number of characters of base64 encoded content decoded for sniffing
+///
+/// Must be a multiple of four. Decodes to 48 bytes which is enough for all
+/// the signatures checked.
+const SNIFF_BASE64_CHARS: usize = 64;
+
+/// The number of bytes of a file read for sniffing
+const SNIFF_BYTES: usize = 48;
+
+/// Infer the media type of content from its leading "magic" bytes
+///
+/// Returns `None` if the content does not start with a known signature.
+pub fn sniff_media_type(bytes: &[u8]) -> Option<&'static str> {
+    let starts = |signature: &[u8]| bytes.starts_with(signature);
+    let at = |offset: usize, signature: &[u8]| {
+        bytes
+            .get(offset..offset + signature.len())
+            .is_some_and(|slice| slice == signature)
+    };
+
+    let media_type = if starts(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if starts(b"\xFF\xD8\xFF") {
+        "image/jpeg"
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        "image/gif"
+    } else if starts(b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        "image/tiff"
+    } else if starts(b"BM") && bytes.len() > 14 {
+        "image/bmp"
+    } else if starts(b"%PDF-") {
+        "application/pdf"
+    } else if starts(b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if starts(b"ID3") || starts(b"\xFF\xFB") || starts(b"\xFF\xF3") {
+        "audio/mpeg"
+    } else if starts(b"fLaC") {
+        "audio/flac"
+    } else if starts(b"OggS") {
+        "audio/ogg"
+    } else if at(4, b"ftyp") {
+        "video/mp4"
+    } else if starts(b"\x1A\x45\xDF\xA3") {
+        "video/webm"
+    } else if starts(b"PK\x03\x04") {
+        "application/zip"
+    } else if starts(b"\x1F\x8B") {
+        "application/gzip"
+    } else if starts(b"\x89HDF\r\n\x1a\n") {
+        "application/x-hdf5"
+    } else if starts(b"CDF\x01") || starts(b"CDF\x02") {
+        "application/x-netcdf"
+    } else {
+        return None;
+    };
+
+    Some(media_type)
+}
+
+/// Infer the media type of an attachment from its content
+///
+/// Only the start of the content is decoded. Returns `None` if the attachment
+/// has no inline content or its content does not have a known signature.
+pub fn sniff_attachment_media_type(attachment: &InstructionAttachment) -> Option<&'static str> {
+    let content = attachment.file.content.as_ref()?;
+
+    let is_base64 = attachment
+        .file
+        .options
+        .transfer_encoding
+        .as_deref()
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"));
+
+    if is_base64 {
+        sniff_base64(content)
+    } else {
+        sniff_media_type(content.as_bytes())
+    }
+}
+
+/// Get the media types of a message part, as declared and as inferred from its content and URL
+///
+/// All of them are returned, so that checks of the media types of the data sent to a
+/// model can not be bypassed by a missing or wrong declaration. Text parts and tool
+/// results have no media types.
+pub fn message_part_media_types(part: &MessagePart) -> Vec<String> {
+    let (declared, url, sniffed) = match part {
+        MessagePart::ImageObject(ImageObject {
+            media_type,
+            content_url,
+            ..
+        })
+        | MessagePart::AudioObject(AudioObject {
+            media_type,
+            content_url,
+            ..
+        })
+        | MessagePart::VideoObject(VideoObject {
+            media_type,
+            content_url,
+            ..
+        }) => (media_type, content_url, sniff_data_url(content_url)),
+        MessagePart::File(file) => (
+            &file.media_type,
+            &file.path,
+            file_prefix(file, SNIFF_BYTES)
+                .ok()
+                .and_then(|(bytes, ..)| sniff_media_type(&bytes)),
+        ),
+        MessagePart::Text(..) | MessagePart::ToolResult(..) => return Vec::new(),
+    };
+
+    let mut media_types = Vec::new();
+    media_types.extend(declared.clone());
+    match url.strip_prefix("data:") {
+        Some(data) => media_types.extend(
+            data.split([';', ','])
+                .next()
+                .filter(|media_type| !media_type.is_empty())
+                .map(String::from),
+        ),
+        None => {
+            let format = Format::from_url(url);
+            if !matches!(format, Format::Other(..) | Format::Unknown) {
+                media_types.push(format.media_type())
+            }
+        }
+    }
+    media_types.extend(sniffed.map(String::from));
+
+    media_types
+}
+
+/// Infer the media type of the content of a base64 encoded data URL
+fn sniff_data_url(url: &str) -> Option<&'static str> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    if header.ends_with(";base64") {
+        sniff_base64(data)
+    } else {
+        sniff_media_type(data.as_bytes())
+    }
+}
+
+/// Infer the media type of base64 encoded content, decoding only its start
+fn sniff_base64(content: &str) -> Option<&'static str> {
+    let prefix = content
+        .chars()
+        .filter(|char| !char.is_whitespace())
+        .take(SNIFF_BASE64_CHARS)
+        .collect::<String>();
+    let prefix = &prefix[..prefix.len() - prefix.len() % 4];
+    sniff_media_type(&BASE64.decode(prefix).ok()?)
+}
+
+/// Correct the media type of an attachment if it is missing or does not match its content
+///
+/// Returns a message describing the correction, if one was made.
+pub fn correct_media_type(attachment: &mut InstructionAttachment) -> Option<String> {
+    let sniffed = sniff_attachment_media_type(attachment)?;
+
+    let message = match attachment.file.media_type.as_deref() {
+        Some(declared) if equivalent(declared, sniffed) => return None,
+        Some(declared) => format!(
+            "Media type of attachment `{}` corrected from `{declared}` to `{sniffed}`",
+            attachment.alias
+        ),
+        None => format!(
+            "Media type of attachment `{}` inferred to be `{sniffed}`",
+            attachment.alias
+        ),
+    };
+
+    attachment.file.media_type = Some(sniffed.to_string());
+
+    Some(message)
+}
+
+/// Get the bytes of a file, decoding base64 content or reading the file if it has no content
+///
+/// Files without content must have an absolute path: relative paths should be
+/// resolved against the directory of the document they are in (e.g. when the
+/// document is executed) rather than the current working directory. Remote files
+/// must be downloaded, and their content set, before they are sent to a model.
+pub fn file_bytes(file: &File) -> Result<Vec<u8>> {
+    Ok(match &file.content {
+        Some(..) => content_bytes(file)?,
+        None => {
+            let path = local_path(file)?;
+            read(path).wrap_err_with(|| format!("Unable to read file `{}`", file.path))?
+        }
+    })
+}
+
+/// Get the first `max_bytes` of a file, and its total size in bytes
+///
+/// Like [`file_bytes`] but, for files without content, only reads the start of
+/// the file so that large files can be previewed cheaply.
+pub fn file_prefix(file: &File, max_bytes: usize) -> Result<(Vec<u8>, u64)> {
+    if file.content.is_some() {
+        let mut bytes = content_bytes(file)?;
+        let size = bytes.len() as u64;
+        bytes.truncate(max_bytes);
+        return Ok((bytes, size));
+    }
+
+    let path = local_path(file)?;
+    let (bytes, size) = (|| -> Result<(Vec<u8>, u64)> {
+        let fs_file = FsFile::open(path)?;
+        let size = fs_file.metadata()?.len();
+        let mut bytes = Vec::new();
+        fs_file.take(max_bytes as u64).read_to_end(&mut bytes)?;
+        Ok((bytes, size))
+    })()
+    .wrap_err_with(|| format!("Unable to read file `{}`", file.path))?;
+    Ok((bytes, size))
+}
+
+/// Get the bytes of the content of a file, decoding it if it is base64 encoded
+fn content_bytes(file: &File) -> Result<Vec<u8>> {
+    let content = file.content.as_deref().unwrap_or_default();
+    if file
+        .options
+        .transfer_encoding
+        .as_deref()
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"))
+    {
+        Ok(BASE64.decode(content.trim())?)
+    } else {
+        Ok(content.as_bytes().to_vec())
+    }
+}
+
+/// Get the local path of a file without content
+///
+/// Errors if the file is remote, or if its path is relative.
+fn local_path(file: &File) -> Result<&Path> {
+    let path = file.path.trim();
+    if path.starts_with("http://") || path.starts_with("https://") {
+        bail!("Remote file `{path}` has not been downloaded and can not be sent to a model")
+    }
+
+    let path = Path::new(path.trim_start_matches("file://"));
+    if !path.is_absolute() {
+        bail!(
+            "File `{}` has a relative path which has not been resolved against its document",
+            path.display()
+        )
+    }
+
+    Ok(path)
+}
+
+/// Get the text of a file, for models which are only able to be given text
+///
+/// Errors if the file is not UTF-8 encoded text (e.g. an image or a PDF).
+pub fn file_text(file: &File) -> Result<String> {
+    match String::from_utf8(file_bytes(file)?) {
+        Ok(text) => Ok(text),
+        Err(..) => bail!("File `{}` is not a text file", file.name),
+    }
+}
+
+/// Get a base64 encoded data URL of the content of a file
+///
+/// Uses the media type of the file, sniffing it from the content if not declared,
+/// and falling back to `text/plain` for UTF-8 content.
+pub fn file_data_url(file: &File) -> Result<String> {
+    let bytes = file_bytes(file)?;
+    let media_type = file
+        .media_type
+        .as_deref()
+        .or_else(|| sniff_media_type(&bytes))
+        .unwrap_or(if std::str::from_utf8(&bytes).is_ok() {
+            "text/plain"
+        } else {
+            "application/octet-stream"
+        });
+    Ok(format!("data:{media_type};base64,{}", BASE64.encode(bytes)))
+}
+
+/// Whether a declared media type is equivalent to a sniffed one
+fn equivalent(declared: &str, sniffed: &str) -> bool {
+    let declared = declared
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    declared == sniffed
+        || matches!(
+            (declared.as_str(), sniffed),
+            ("image/jpg", "image/jpeg")
+                | ("audio/mp3", "audio/mpeg")
+                | ("audio/x-wav" | "audio/wave", "audio/wav")
+                | ("application/x-zip-compressed", "application/zip")
+                | ("application/x-gzip", "application/gzip")
+                | ("application/netcdf", "application/x-netcdf")
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use common::tempfile::tempdir;
+    use schema::File;
+
+    use super::*;
+
+    #[test]
+    fn corrects() {
+        let mut file = File::new("transects.dat".into(), "transects.dat".into());
+        file.media_type = Some("application/octet-stream".into());
+        // Base64 encoded PNG signature
+        file.content = Some("iVBORw0KGgoAAAANSUhEUg==".into());
+        file.options.transfer_encoding = Some("base64".into());
+        let mut attachment = InstructionAttachment::new("transects".into(), file);
+
+        assert_eq!(
+            correct_media_type(&mut attachment).as_deref(),
+            Some(
+                "Media type of attachment `transects` corrected from `application/octet-stream` to `image/png`"
+            )
+        );
+        assert_eq!(attachment.file.media_type.as_deref(), Some("image/png"));
+        assert_eq!(correct_media_type(&mut attachment), None);
+
+        assert_eq!(sniff_media_type(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff_media_type(b"date,shoreline"), None);
+    }
+
+    #[test]
+    fn reads_files() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("transects.csv");
+        std::fs::write(&path, "1,2\n")?;
+
+        let file = File::new("transects.csv".into(), path.to_string_lossy().to_string());
+        assert_eq!(file_bytes(&file)?, b"1,2\n");
+
+        let file = File::new("transects.csv".into(), "transects.csv".into());
+        assert!(file_bytes(&file).is_err_and(|error| error.to_string().contains("relative path")));
+
+        let file = File::new(
+            "transects.csv".into(),
+            "https://example.org/transects.csv".into(),
+        );
+        assert!(file_bytes(&file).is_err_and(|error| error.to_string().contains("Remote file")));
+
+        let file = File::new("transects.csv".into(), path.to_string_lossy().to_string());
+        assert_eq!(file_prefix(&file, 2)?, (b"1,".to_vec(), 4));
+        assert_eq!(file_prefix(&file, 100)?, (b"1,2\n".to_vec(), 4));
+
+        let file = File::new("transects.csv".into(), "transects.csv".into());
+        assert!(file_prefix(&file, 2).is_err());
+
+        Ok(())
+    }
+}
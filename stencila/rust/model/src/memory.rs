@@ -0,0 +1,217 @@
+use common::{
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{
+    Model, ModelOutput, ModelTask, Policy, kinds::insert_system_message,
+    task::messages_to_prompt_string,
+};
+
+/// A summary of the facts learned so far in a chat session
+///
+/// The memory is injected into the system prompt of each task and, after the
+/// task is performed, updated by a (usually small and cheap) model with any new
+/// facts from the latest exchange. The updated memory is returned in the `memory`
+/// of the output so that it can be passed to the next task of the session. This
+/// allows long, multi-step conversations to continue without all earlier turns
+/// needing to be included in the prompt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ChatMemory {
+    /// The facts learned so far, as a Markdown list
+    #[serde(default)]
+    pub facts: String,
+
+    /// The number of turns the facts have been updated for
+    #[serde(default)]
+    pub turns: u32,
+
+    /// The id pattern of the model used to update the facts
+    pub model: String,
+
+    /// The maximum number of words in the facts
+    #[serde(default = "ChatMemory::default_max_words")]
+    pub max_words: usize,
+}
+
+impl ChatMemory {
+    /// Create a new, empty, memory updated using a model
+    pub fn new(model: &str) -> Self {
+        Self {
+            facts: String::new(),
+            turns: 0,
+            model: model.into(),
+            max_words: Self::default_max_words(),
+        }
+    }
+
+    fn default_max_words() -> usize {
+        300
+    }
+
+    /// Inject the facts into the system prompt of a task
+    ///
+    /// The facts are added as a system message after any existing system messages.
+    /// Returns `false` if there are no facts yet.
+    pub fn apply(&self, task: &mut ModelTask) -> bool {
+        let facts = self.facts.trim();
+        if facts.is_empty() {
+            return false;
+        }
+
+        insert_system_message(
+            task,
+            format!("Facts learned so far in this session:\n\n{facts}"),
+        );
+
+        true
+    }
+
+    /// Update the facts with those learned from the latest exchange of a task
+    ///
+    /// The latest exchange is the last user message of the task and the content of the output.
+    /// The request to the `updater` is checked against, and recorded by, the `policy`.
+    pub async fn update(
+        &mut self,
+        task: &ModelTask,
+        output: &ModelOutput,
+        updater: &dyn Model,
+        policy: &Policy,
+    ) -> Result<()> {
+        let latest = task
+            .messages
+            .iter()
+            .rev()
+            .find(|message| matches!(message.role, None | Some(MessageRole::User)))
+            .cloned()
+            .into_iter()
+            .chain([InstructionMessage {
+                role: Some(MessageRole::Model),
+                parts: vec![MessagePart::from(output.content.as_str())],
+                ..Default::default()
+            }])
+            .collect::<Vec<_>>();
+        let Some(exchange) = messages_to_prompt_string(&latest) else {
+            return Ok(());
+        };
+
+        tracing::debug!(
+            "Updating chat memory after turn {} using `{}`",
+            self.turns + 1,
+            updater.id()
+        );
+
+        let facts = match self.facts.trim() {
+            "" => "(none yet)",
+            facts => facts,
+        };
+        let update_task = ModelTask {
+            messages: vec![
+                InstructionMessage {
+                    role: Some(MessageRole::System),
+                    parts: vec![MessagePart::from(format!(
+                        "You maintain a list of the facts learned so far in a conversation. Given the current list and the latest exchange, respond only with the updated list as Markdown bullet points. Add new facts, decisions, names, numbers and file references; correct facts that have changed; and remove facts that are no longer relevant. Keep the list under {} words.",
+                        self.max_words
+                    ))],
+                    ..Default::default()
+                },
+                InstructionMessage {
+                    role: Some(MessageRole::User),
+                    parts: vec![MessagePart::from(format!(
+                        "Current facts:\n\n{facts}\n\nLatest exchange:\n\n{exchange}"
+                    ))],
+                    ..Default::default()
+                },
+            ],
+            temperature: Some(0.0),
+            dry_run: task.dry_run,
+            ..Default::default()
+        };
+        let updated = policy.perform(updater, &update_task).await?.content;
+
+        if !updated.trim().is_empty() {
+            self.facts = updated.trim().to_string();
+        }
+        self.turns += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, tokio};
+
+    use super::*;
+
+    struct Updater;
+
+    #[async_trait]
+    impl Model for Updater {
+        fn id(&self) -> String {
+            "test/updater".into()
+        }
+
+        async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+            let prompt = task.prompt_as_text().unwrap_or_default();
+            assert!(prompt.contains("(none yet)"));
+            assert!(prompt.contains("User:\nWhich transect is eroding?"));
+            assert!(prompt.contains("Assistant:\nTransect 3."));
+
+            Ok(ModelOutput {
+                content: "- Transect 3 is eroding\n".into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn message(role: MessageRole, text: &str) -> InstructionMessage {
+        InstructionMessage {
+            role: Some(role),
+            parts: vec![MessagePart::from(text)],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn updates_and_applies() -> Result<()> {
+        let mut memory = ChatMemory::new("test/updater");
+
+        let mut task = ModelTask {
+            messages: vec![
+                message(MessageRole::System, "Be brief."),
+                message(MessageRole::User, "Which transect is eroding?"),
+            ],
+            ..Default::default()
+        };
+        assert!(!memory.apply(&mut task));
+
+        let output = ModelOutput {
+            content: "Transect 3.".into(),
+            ..Default::default()
+        };
+        memory
+            .update(&task, &output, &Updater, &Policy::default())
+            .await?;
+        assert_eq!(memory.facts, "- Transect 3 is eroding");
+        assert_eq!(memory.turns, 1);
+
+        let mut task = ModelTask {
+            messages: vec![
+                message(MessageRole::System, "Be brief."),
+                message(MessageRole::User, "Why?"),
+            ],
+            ..Default::default()
+        };
+        assert!(memory.apply(&mut task));
+        assert_eq!(
+            task.prompt_as_text().unwrap_or_default(),
+            "System:\nBe brief.\n\n---\n\nSystem:\nFacts learned so far in this session:\n\n- Transect 3 is eroding\n\n---\n\nUser:\nWhy?"
+        );
+
+        Ok(())
+    }
+}
@@ -1,15 +1,27 @@
+use std::ops::Add;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use common::{
+    chrono::{DateTime, Utc},
     eyre::Result,
     serde::{Deserialize, Serialize},
     serde_with::skip_serializing_none,
 };
 use format::Format;
-use schema::{AuthorRole, AuthorRoleName};
+use schema::{
+    AudioObject, AuthorRole, AuthorRoleName, Citation, Date, ImageObject, ModelInvocation,
+    Timestamp,
+};
 
-use crate::Model;
+use crate::{
+    AlignedSentence, Attestation, CandidateSelector, ChatMemory, DatasetStatistics, GroundedClaim,
+    Model, ModelAudit, ModelTask, ModelWarning, PromptManifest, TaskFingerprint, TaskReport,
+    ToolCall, WorkflowArtifact, add_web_citations, attestation::content_hash, extract_citations,
+    repair_text, supports_format,
+};
 
 /// The kind of generative model output
-#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(crate = "common::serde")]
 pub enum ModelOutputKind {
     /// Generated text in a text format
@@ -20,9 +32,213 @@ pub enum ModelOutputKind {
     Url,
 }
 
+/// The reason a model stopped generating content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub enum FinishReason {
+    /// The model finished its answer, or reached a stop sequence
+    Stop,
+
+    /// The maximum number of output tokens was reached so the content is truncated
+    Length,
+
+    /// The model stopped to call tools
+    ToolCalls,
+
+    /// Content was omitted by the provider's content filter
+    ContentFilter,
+}
+
+/// A structured part of the output of a model, other than its generated content
+///
+/// Produced by models which use built-in tools (e.g. web search or a code interpreter)
+/// while generating their content.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case", crate = "common::serde")]
+pub enum ModelOutputPart {
+    /// A web search performed by the model
+    WebSearch {
+        /// The query searched for
+        query: Option<String>,
+
+        /// The URLs of the sources consulted
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sources: Vec<String>,
+    },
+
+    /// A citation of a web page in the content
+    WebCitation {
+        /// The URL of the cited page
+        url: String,
+
+        /// The title of the cited page
+        title: Option<String>,
+    },
+
+    /// Code executed by the model
+    CodeExecution {
+        /// The provider's id for the execution
+        id: Option<String>,
+
+        /// The id of the container the code was executed in, if any
+        container_id: Option<String>,
+
+        /// The code that was executed
+        code: String,
+
+        /// Logs output by the code
+        logs: Option<String>,
+
+        /// URLs of images output by the code
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        images: Vec<String>,
+    },
+
+    /// A caption generated for a figure
+    Caption {
+        /// The caption of the figure
+        caption: String,
+
+        /// Alternative text describing the visual content of the figure
+        alt_text: String,
+
+        /// Keywords for the figure
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        keywords: Vec<String>,
+    },
+
+    /// Summary statistics of a dataset, computed locally rather than generated
+    DatasetStatistics(DatasetStatistics),
+
+    /// The alignment of the sentences of a translation with those of the source text
+    SentenceAlignment {
+        /// The language of the source text, if specified
+        source_language: Option<String>,
+
+        /// The language of the translation
+        target_language: String,
+
+        /// The source and translated sentences, in order
+        sentences: Vec<AlignedSentence>,
+    },
+
+    /// The grounding of the claims in an answer in the attachments of the task
+    Grounding {
+        /// The claims, and where they came from, in order of citation
+        claims: Vec<GroundedClaim>,
+    },
+
+    /// A contact sheet grid of the generated images
+    ImageGrid {
+        /// The URL of the grid image
+        url: String,
+    },
+
+    /// A call of one of the tools of the task made by the model
+    ToolCall(ToolCall),
+
+    /// A file cited or created by the model
+    File {
+        /// The provider's id for the file
+        file_id: String,
+
+        /// The name of the file
+        filename: Option<String>,
+
+        /// The id of the container the file was created in, if any
+        container_id: Option<String>,
+
+        /// The id of the `CodeExecution` which created the file, if any
+        generated_by: Option<String>,
+
+        /// The local path of the file, if it was downloaded
+        path: Option<String>,
+
+        /// The media type of the file, if known
+        media_type: Option<String>,
+    },
+}
+
+/// The number of tokens used by a task, as reported by the provider
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct TokenUsage {
+    /// The number of tokens in the prompt, including those read from the cache
+    pub prompt_tokens: usize,
+
+    /// The number of tokens in the output
+    pub output_tokens: usize,
+
+    /// The number of tokens in the prompt which were read from the provider's prompt cache
+    pub cached_tokens: usize,
+}
+
+impl Add for TokenUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+            cached_tokens: self.cached_tokens + other.cached_tokens,
+        }
+    }
+}
+
+/// Metadata about the generation of an image
+///
+/// Recorded so that generated figures carry verifiable provenance: the prompt
+/// as sent and as revised by the model, and the parameters of the request.
+#[skip_serializing_none]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ImageGeneration {
+    /// The id of the model that generated the image
+    pub model: String,
+
+    /// The prompt sent to the model
+    pub prompt: String,
+
+    /// The prompt as revised by the model before generating the image, if any
+    pub revised_prompt: Option<String>,
+
+    /// The size of the image e.g. `1024x1024`
+    pub size: Option<String>,
+
+    /// The quality of the image e.g. `hd`
+    pub quality: Option<String>,
+
+    /// The style of the image e.g. `natural`
+    pub style: Option<String>,
+
+    /// The seed used for generation, if supported by the model
+    pub seed: Option<i32>,
+
+    /// The time that the image was generated
+    pub created: Option<DateTime<Utc>>,
+}
+
+impl ImageGeneration {
+    /// Add the generation metadata to an image
+    ///
+    /// Sets the description of the image to the prompt used to generate it
+    /// (the revised prompt if available) and its creation date.
+    pub fn apply_to(&self, image: &mut ImageObject) {
+        image.options.description = Some(
+            self.revised_prompt
+                .clone()
+                .unwrap_or_else(|| self.prompt.clone()),
+        );
+        if let Some(created) = &self.created {
+            image.options.date_created = Some(Date::new(created.format("%Y-%m-%d").to_string()));
+        }
+    }
+}
+
 /// Output generated by a generative model for a task
 #[skip_serializing_none]
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default, deny_unknown_fields, crate = "common::serde")]
 pub struct ModelOutput {
     /// The models that were involved in generating the output
@@ -42,6 +258,96 @@ pub struct ModelOutput {
 
     /// The content generated by the assistant
     pub content: String,
+
+    /// Why the model stopped generating the content
+    ///
+    /// Only set by providers which report this (currently OpenAI). If the task
+    /// has `auto_continue` set, this is the reason the last continuation stopped.
+    pub finish_reason: Option<FinishReason>,
+
+    /// The message given by the model when it refused to perform the task
+    ///
+    /// Models which refuse (e.g. for safety reasons) usually generate no content, so
+    /// this distinguishes a refusal from an empty answer. Only set by providers which
+    /// report refusals separately from content (currently OpenAI).
+    pub refusal: Option<String>,
+
+    /// The number of tokens used, as reported by the provider
+    ///
+    /// Only set by providers which report usage (currently OpenAI).
+    pub usage: Option<TokenUsage>,
+
+    /// Audio generated by the model, in addition to the text content
+    ///
+    /// For models generating speech, the `content` is the transcript of this audio.
+    /// The `content_url` of the audio is a base64 encoded data URL.
+    pub audio: Option<AudioObject>,
+
+    /// Citations detected in the content and resolved against the task
+    ///
+    /// Populated by [`ModelOutput::link_citations`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+
+    /// All candidate outputs, if more than one was generated
+    ///
+    /// The `content` is the selected candidate.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>,
+
+    /// The ids of the models which generated each of the `candidates`
+    ///
+    /// Only populated if the candidates were generated by different models (e.g. by an ensemble).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidate_models: Vec<String>,
+
+    /// The index of the selected candidate in `candidates`
+    pub selected_candidate: Option<usize>,
+
+    /// Structured parts of the output e.g. web searches and code executed by the model
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ModelOutputPart>,
+
+    /// Outputs of upstream workflows that the content was derived from
+    ///
+    /// Copied from the `derived_from` of the task.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub derived_from: Vec<WorkflowArtifact>,
+
+    /// Metadata about the generation of the image at the content URL, if any
+    pub image_generation: Option<ImageGeneration>,
+
+    /// Warnings about how the task was performed e.g. ignored message parts or options
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ModelWarning>,
+
+    /// The request and response recorded for auditing, if enabled for the task
+    pub audit: Option<ModelAudit>,
+
+    /// A record of which message parts and attachments reached the model
+    ///
+    /// Only set by providers which keep track of this (currently OpenAI).
+    pub manifest: Option<PromptManifest>,
+
+    /// The memory of the chat session, updated with the facts learned from this task
+    ///
+    /// Only set if the task had a `memory`. Should be passed to the next task of the session.
+    pub memory: Option<ChatMemory>,
+
+    /// A report of how the task was performed e.g. the number of retries and total latency
+    pub report: Option<TaskReport>,
+
+    /// A signed record of the generation of the content
+    ///
+    /// Only set if enabled in the `[records.attestation]` table of the models config.
+    pub attestation: Option<Attestation>,
+
+    /// A record of the invocation of the model which generated the content
+    ///
+    /// Started by the provider (with the model version it reports), and completed
+    /// with the parameters, usage and fingerprints of the task once performed.
+    /// Attached to the `modelInvocation` of suggestions of generated content.
+    pub invocation: Option<ModelInvocation>,
 }
 
 impl ModelOutput {
@@ -54,19 +360,68 @@ impl ModelOutput {
             kind: ModelOutputKind::Text,
             format: Format::Unknown,
             content: (String::new()),
+            ..Default::default()
         })
     }
 
+    /// Whether the model refused to perform the task
+    pub fn is_refusal(&self) -> bool {
+        self.refusal.is_some()
+    }
+
     /// Create a `ModelOutput` from text
     ///
     /// If the output format of the task in unknown (i.e. was not specified)
-    /// then assumes it is Markdown.
+    /// then assumes it is Markdown. If the model does not natively support the
+    /// format then the text is repaired (e.g. code fences stripped) before use.
+    /// JSON is always repaired because even models with a JSON mode can generate
+    /// malformed JSON (e.g. when truncated).
     pub async fn from_text(model: &dyn Model, format: &Format, text: String) -> Result<Self> {
+        let text = if supports_format(model, format) && !format.is_json_flavor() {
+            text
+        } else {
+            repair_text(format, text)
+        };
+
         Ok(Self {
             authors: vec![model.to_author_role(AuthorRoleName::Generator)],
             kind: ModelOutputKind::Text,
             format: format.clone(),
             content: text,
+            ..Default::default()
+        })
+    }
+
+    /// Create a `ModelOutput` from several candidate texts
+    ///
+    /// As for [`ModelOutput::from_text`] with each candidate repaired if necessary.
+    /// The first candidate is used as the content until [`ModelOutput::select_candidate`] is called.
+    pub async fn from_candidates(
+        model: &dyn Model,
+        format: &Format,
+        candidates: Vec<String>,
+    ) -> Result<Self> {
+        let mut candidates = if supports_format(model, format) && !format.is_json_flavor() {
+            candidates
+        } else {
+            candidates
+                .into_iter()
+                .map(|text| repair_text(format, text))
+                .collect()
+        };
+
+        let content = candidates.first().cloned().unwrap_or_default();
+        if candidates.len() < 2 {
+            candidates.clear();
+        }
+
+        Ok(Self {
+            authors: vec![model.to_author_role(AuthorRoleName::Generator)],
+            kind: ModelOutputKind::Text,
+            format: format.clone(),
+            content,
+            candidates,
+            ..Default::default()
         })
     }
 
@@ -79,6 +434,192 @@ impl ModelOutput {
             kind: ModelOutputKind::Url,
             format,
             content: url,
+            ..Default::default()
         })
     }
+
+    /// Get the bytes of the generated audio, if any
+    pub fn audio_bytes(&self) -> Option<Vec<u8>> {
+        let url = &self.audio.as_ref()?.content_url;
+        let (.., data) = url.split_once(";base64,")?;
+        BASE64.decode(data).ok()
+    }
+
+    /// Select one of the candidates as the content
+    ///
+    /// Does nothing if there are fewer than two candidates.
+    pub async fn select_candidate(
+        &mut self,
+        task: &ModelTask,
+        selector: &dyn CandidateSelector,
+    ) -> Result<()> {
+        if self.candidates.len() < 2 {
+            return Ok(());
+        }
+
+        let index = selector
+            .select(task, &self.candidates)
+            .await?
+            .min(self.candidates.len() - 1);
+        self.content = self.candidates[index].clone();
+        self.selected_candidate = Some(index);
+
+        Ok(())
+    }
+
+    /// Get the tool calls made by the model
+    pub fn tool_calls(&self) -> Vec<&ToolCall> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                ModelOutputPart::ToolCall(call) => Some(call),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Record the invocation of a model which generated the output
+    ///
+    /// Called by providers once the model has responded. The `version` is that
+    /// reported by the provider, if any, falling back to the version of the model.
+    pub fn record_invocation(
+        &mut self,
+        model: &dyn Model,
+        version: Option<String>,
+        start_time: Timestamp,
+    ) {
+        let version = version.unwrap_or_else(|| model.version());
+        let usage = self.usage.unwrap_or_default();
+        self.invocation = Some(ModelInvocation {
+            model_version: (!version.is_empty()).then_some(version),
+            provider: Some(model.provider()),
+            prompt_tokens: self.usage.map(|_| usage.prompt_tokens as u64),
+            output_tokens: self.usage.map(|_| usage.output_tokens as u64),
+            cached_tokens: self.usage.map(|_| usage.cached_tokens as u64),
+            start_time: Some(start_time),
+            end_time: Some(Timestamp::now()),
+            ..ModelInvocation::new(model.id())
+        });
+    }
+
+    /// Complete the record of the invocation of the model with details of the task
+    ///
+    /// Records an invocation if the provider did not, and fills in the parameters of
+    /// the task, its fingerprint, the hash of the content, and the usage and cost
+    /// from the `report` (which should be set beforehand).
+    pub fn complete_invocation(
+        &mut self,
+        model: &dyn Model,
+        task: &ModelTask,
+        fingerprint: Option<&TaskFingerprint>,
+        start_time: Timestamp,
+    ) {
+        if self.invocation.is_none() {
+            self.record_invocation(model, None, start_time);
+        }
+        let output_hash = content_hash(self.content.as_bytes());
+        let Some(invocation) = self.invocation.as_mut() else {
+            return;
+        };
+
+        invocation.model_parameters = task.model_parameters.clone();
+        if let Some(report) = &self.report {
+            invocation
+                .prompt_tokens
+                .get_or_insert(report.prompt_tokens as u64);
+            invocation
+                .output_tokens
+                .get_or_insert(report.output_tokens as u64);
+            invocation
+                .cached_tokens
+                .get_or_insert(report.cached_tokens as u64);
+            invocation.cost = report.cost;
+        }
+        invocation.task_fingerprint = fingerprint.map(|fingerprint| fingerprint.to_string());
+        invocation.output_hash = Some(output_hash);
+    }
+
+    /// Extract citations from the content and link them to the task's attachments and messages
+    ///
+    /// Only applies to text outputs; the content of URL outputs is not scanned.
+    /// Web pages cited in the `parts` of the output are also added.
+    pub fn link_citations(&mut self, task: &ModelTask) {
+        if matches!(self.kind, ModelOutputKind::Text) {
+            self.citations = extract_citations(&self.content, task);
+            add_web_citations(&mut self.citations, &self.parts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, eyre::bail};
+
+    use super::*;
+
+    struct TestModel;
+
+    #[async_trait]
+    impl Model for TestModel {
+        fn id(&self) -> String {
+            "openai/gpt-4o".into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            Ok(ModelOutput::default())
+        }
+    }
+
+    #[test]
+    fn records_invocations() -> Result<()> {
+        let task = ModelTask::builder().user_text("Summarize").build()?;
+        let fingerprint = TaskFingerprint::new(&task)?;
+
+        let mut output = ModelOutput {
+            content: "Shorelines are retreating".into(),
+            usage: Some(TokenUsage {
+                prompt_tokens: 10,
+                output_tokens: 4,
+                cached_tokens: 0,
+            }),
+            ..Default::default()
+        };
+        output.record_invocation(
+            &TestModel,
+            Some("gpt-4o-2024-08-06".into()),
+            Timestamp::now(),
+        );
+        output.report = Some(TaskReport {
+            prompt_tokens: 12,
+            cost: Some(0.5),
+            ..Default::default()
+        });
+        output.complete_invocation(&TestModel, &task, Some(&fingerprint), Timestamp::now());
+
+        let Some(invocation) = output.invocation else {
+            bail!("Expected an invocation")
+        };
+        assert_eq!(invocation.model_id, "openai/gpt-4o");
+        assert_eq!(
+            invocation.model_version.as_deref(),
+            Some("gpt-4o-2024-08-06")
+        );
+        assert_eq!(invocation.provider.as_deref(), Some("Openai"));
+        assert_eq!(invocation.prompt_tokens, Some(10));
+        assert_eq!(invocation.cost, Some(0.5));
+        assert_eq!(invocation.task_fingerprint, Some(fingerprint.to_string()));
+        assert_eq!(invocation.output_hash.map(|hash| hash.len()), Some(64));
+        assert!(invocation.start_time.is_some() && invocation.end_time.is_some());
+
+        // Outputs of providers which do not record invocations get one when completed
+        let mut output = ModelOutput::default();
+        output.complete_invocation(&TestModel, &task, None, Timestamp::now());
+        assert!(
+            output
+                .invocation
+                .is_some_and(|invocation| invocation.task_fingerprint.is_none())
+        );
+
+        Ok(())
+    }
 }
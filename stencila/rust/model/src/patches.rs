@@ -0,0 +1,176 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use schema::{AuthorRole, CordOp, NodeId, NodePath, Patch, PatchOp};
+
+/// A callback for patches generated from text streamed from a model
+pub type PatchCallback = dyn Fn(Patch) + Send + Sync;
+
+/// Converts text streamed from a model into patches to a node
+///
+/// Each chunk of text is converted into a patch which inserts the text at the end
+/// of a `Cord` property of the node (e.g. the `code` of a `CodeChunk` or the `value`
+/// of a `Text`). This allows documents to be updated live as a node is
+/// generated, rather than having its content replaced when generation is finished.
+///
+/// Because the final content of an output may differ from the text that was
+/// streamed (e.g. after post-processing or redaction), use [`PatchStream::reconcile`]
+/// when generation is finished to replace the streamed text with the final content.
+#[derive(Debug)]
+pub struct PatchStream {
+    /// The id of the node being generated
+    node_id: NodeId,
+
+    /// The path to the `Cord` property within the node
+    path: NodePath,
+
+    /// The authors to attribute the inserted text to
+    authors: Option<Vec<AuthorRole>>,
+
+    /// The position, in characters, at which the first chunk was inserted
+    start: usize,
+
+    /// The position, in characters, at which the next chunk will be inserted
+    position: AtomicUsize,
+
+    /// The text streamed so far
+    streamed: Mutex<String>,
+}
+
+impl PatchStream {
+    /// Create a new stream of patches to the `Cord` at `path` in a node
+    ///
+    /// The `Cord` is assumed to be empty. Use [`PatchStream::start_at`] if not.
+    pub fn new(node_id: NodeId, path: impl Into<NodePath>) -> Self {
+        Self {
+            node_id,
+            path: path.into(),
+            authors: None,
+            start: 0,
+            position: AtomicUsize::new(0),
+            streamed: Mutex::new(String::new()),
+        }
+    }
+
+    /// Set the authors of the inserted text
+    pub fn authors(mut self, authors: Vec<AuthorRole>) -> Self {
+        self.authors = Some(authors);
+        self
+    }
+
+    /// Set the position, in characters, at which the first chunk will be inserted
+    ///
+    /// Usually the length of any existing content of the `Cord`.
+    pub fn start_at(mut self, position: usize) -> Self {
+        self.start = position;
+        self.position.store(position, Ordering::SeqCst);
+        self
+    }
+
+    /// Get the position at which the next chunk will be inserted
+    pub fn position(&self) -> usize {
+        self.position.load(Ordering::SeqCst)
+    }
+
+    /// Create a patch for a chunk of text
+    ///
+    /// Returns `None` if the chunk is empty.
+    pub fn patch(&self, chunk: &str) -> Option<Patch> {
+        if chunk.is_empty() {
+            return None;
+        }
+
+        let length = chunk.chars().count();
+        let position = self.position.fetch_add(length, Ordering::SeqCst);
+        if let Ok(mut streamed) = self.streamed.lock() {
+            streamed.push_str(chunk);
+        }
+
+        Some(self.cord_patch(CordOp::Insert(position, chunk.to_string())))
+    }
+
+    /// Create a patch replacing the streamed text with the final content of an output
+    ///
+    /// Returns `None` if the content is the same as the text that was streamed.
+    pub fn reconcile(&self, content: &str) -> Option<Patch> {
+        let streamed = self.streamed.lock().ok()?;
+        if streamed.as_str() == content {
+            return None;
+        }
+
+        let end = self.start + streamed.chars().count();
+        Some(self.cord_patch(CordOp::Replace(self.start..end, content.to_string())))
+    }
+
+    /// Create a patch applying an operation to the `Cord`
+    fn cord_patch(&self, op: CordOp) -> Patch {
+        Patch {
+            node_id: Some(self.node_id.clone()),
+            ops: vec![(self.path.clone(), PatchOp::Apply(vec![op]))],
+            authors: self.authors.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::eyre::{Result, bail};
+    use schema::NodeProperty;
+
+    use super::*;
+
+    #[test]
+    fn inserts_at_end() -> Result<()> {
+        let node_id = NodeId::new(b"cdc", b"1");
+        let stream = PatchStream::new(node_id.clone(), NodeProperty::Code).start_at(2);
+
+        assert_eq!(stream.patch(""), None);
+
+        let Some(patch) = stream.patch("🙂 a") else {
+            bail!("expected patch")
+        };
+        assert_eq!(patch.node_id, Some(node_id));
+        assert_eq!(
+            patch.ops,
+            vec![(
+                NodePath::from(NodeProperty::Code),
+                PatchOp::Apply(vec![CordOp::Insert(2, "🙂 a".into())])
+            )]
+        );
+
+        let Some(patch) = stream.patch("b") else {
+            bail!("expected patch")
+        };
+        assert_eq!(
+            patch.ops[0].1,
+            PatchOp::Apply(vec![CordOp::Insert(5, "b".into())])
+        );
+        assert_eq!(stream.position(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reconciles_final_content() -> Result<()> {
+        let node_id = NodeId::new(b"cdc", b"1");
+        let stream = PatchStream::new(node_id, NodeProperty::Code).start_at(2);
+        stream.patch("```py\n");
+        stream.patch("x = 1\n```");
+
+        assert_eq!(stream.reconcile("```py\nx = 1\n```"), None);
+
+        // e.g. after code fences are stripped from the output
+        let Some(patch) = stream.reconcile("x = 1") else {
+            bail!("expected patch")
+        };
+        assert_eq!(
+            patch.ops[0].1,
+            PatchOp::Apply(vec![CordOp::Replace(2..17, "x = 1".into())])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashMap, env, sync::Mutex};
+
+use common::{
+    chrono::{NaiveDate, Utc},
+    eyre::{Result, bail},
+    glob::Pattern,
+    once_cell::sync::Lazy,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+
+use crate::{
+    Model, ModelOutput, ModelTask, estimate_prompt_tokens, message_part_media_types,
+    queue::provider_key, sniff_attachment_media_type, task_queue,
+};
+
+/// A rule of the [`Policy`]
+///
+/// A rule applies to a request if the model matches `models` and, if
+/// `media-types` is specified, the task has an attachment, or a message part
+/// (e.g. an image), with a matching media type. Rules which apply are enforced by their `deny`, `allow-providers`
+/// and `max-tokens-per-day` options.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct PolicyRule {
+    /// The name of the rule, used in errors
+    pub name: String,
+
+    /// A glob pattern for the ids of the models the rule applies to e.g. `openai/*`
+    ///
+    /// If not specified, the rule applies to all models.
+    pub models: Option<String>,
+
+    /// A glob pattern for the media types of attachments and message parts the rule applies to e.g. `image/tiff`
+    ///
+    /// If specified, the rule only applies to tasks with a matching attachment or message part.
+    pub media_types: Option<String>,
+
+    /// Whether requests the rule applies to are denied
+    pub deny: bool,
+
+    /// The only providers that requests the rule applies to may be sent to e.g. `["ollama"]`
+    ///
+    /// Matched ignoring case and punctuation, as for the `[queue]` limits, so that
+    /// e.g. `hugging-face` matches the `Hugging Face` provider.
+    pub allow_providers: Option<Vec<String>>,
+
+    /// The maximum number of (estimated) tokens, summed over all requests the rule applies to, each day
+    pub max_tokens_per_day: Option<usize>,
+
+    /// Whether `max-tokens-per-day` applies to each user separately
+    ///
+    /// Tasks without a `user` share a single, anonymous, quota.
+    pub per_user: bool,
+}
+
+impl PolicyRule {
+    /// Whether the rule applies to a request to a model for a task
+    fn applies(&self, model: &dyn Model, task: &ModelTask) -> bool {
+        if let Some(pattern) = &self.models
+            && !glob_matches(pattern, &model.id())
+        {
+            return false;
+        }
+
+        let Some(pattern) = &self.media_types else {
+            return true;
+        };
+
+        // Media types sniffed from content are checked, as well as declared ones,
+        // so that a rule can not be bypassed by mislabeling data
+        let attachments = task.attachments.iter().flatten().flat_map(|attachment| {
+            attachment
+                .file
+                .media_type
+                .clone()
+                .into_iter()
+                .chain(sniff_attachment_media_type(attachment).map(String::from))
+        });
+        let parts = task
+            .messages
+            .iter()
+            .flat_map(|message| &message.parts)
+            .flat_map(message_part_media_types);
+
+        attachments
+            .chain(parts)
+            .any(|media_type| glob_matches(pattern, &media_type))
+    }
+
+    /// The key that the rule's daily token usage is counted under for a task
+    fn usage_key(&self, task: &ModelTask) -> String {
+        match (self.per_user, &task.user) {
+            (true, Some(user)) => format!("{}:{user}", self.name),
+            (true, None) => format!("{}:", self.name),
+            (false, ..) => self.name.clone(),
+        }
+    }
+}
+
+/// Whether a glob pattern matches a value, logging invalid patterns
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    Pattern::new(pattern)
+        .map(|pattern| pattern.matches(value))
+        .unwrap_or_else(|error| {
+            tracing::warn!("Invalid pattern `{pattern}` in policy rule: {error}");
+            false
+        })
+}
+
+/// Allow and deny rules for the flow of data to models
+///
+/// Configured in the `[policy]` table of the `models.toml` file by operators
+/// who need to control which models (and providers) tasks, and the data
+/// attached to them, are sent to e.g.
+///
+/// ```toml
+/// [policy]
+/// local-only = false
+///
+/// # Satellite imagery may only be sent to local models
+/// [[policy.rules]]
+/// name = "imagery-stays-local"
+/// media-types = "image/tiff"
+/// allow-providers = ["ollama"]
+///
+/// # Cap the daily use of OpenAI models by each user
+/// [[policy.rules]]
+/// name = "openai-budget"
+/// models = "openai/*"
+/// max-tokens-per-day = 50000
+/// per-user = true
+/// ```
+///
+/// Enforced after a model has been selected for a task, but before any request
+/// is sent to it. Daily token usage is counted in memory, per process.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct Policy {
+    /// Whether to only use models which run on this machine
+    ///
+    /// When `true`, remote models (including those accessed via routers and
+    /// proxies) are excluded from the catalog and tasks fail rather than being
+    /// sent to them, so that data never leaves the machine. Can also be enabled
+    /// using the `MODELS_LOCAL_ONLY=1` environment variable.
+    pub local_only: bool,
+
+    /// The rules of the policy
+    pub rules: Vec<PolicyRule>,
+}
+
+/// The tokens used each day, keyed by the name of the rule counting them (and the user, for per-user rules)
+static USAGE: Lazy<Mutex<HashMap<String, (NaiveDate, usize)>>> = Lazy::new(Mutex::default);
+
+impl Policy {
+    /// Whether local-only mode is enabled, in the policy or by the `MODELS_LOCAL_ONLY` environment variable
+    pub fn is_local_only(&self) -> bool {
+        self.local_only
+            || env::var("MODELS_LOCAL_ONLY")
+                .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+    }
+
+    /// Check that a model is allowed to be used under the local-only policy
+    ///
+    /// Errors if local-only mode is enabled and the model does not run on this machine.
+    pub fn check_local_only(&self, model: &dyn Model) -> Result<()> {
+        if self.is_local_only() && !model.r#type().is_local() {
+            bail!(
+                "Model `{}` is a {} model but local-only mode is enabled (by `local-only` in the `[policy]` of models.toml or MODELS_LOCAL_ONLY); use a local model or disable local-only mode",
+                model.id(),
+                model.r#type().to_string().to_lowercase()
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that a request to a model for a task is allowed by the policy
+    ///
+    /// Errors if the model is not allowed in local-only mode, or with the name
+    /// of the first rule that the request violates.
+    pub fn check(&self, model: &dyn Model, task: &ModelTask) -> Result<()> {
+        self.check_local_only(model)?;
+
+        let rules = self.rules.iter().filter(|rule| rule.applies(model, task));
+        for rule in rules {
+            let name = &rule.name;
+            let id = model.id();
+
+            if rule.deny {
+                bail!("Policy rule `{name}` denies sending this task to model `{id}`");
+            }
+
+            if let Some(providers) = &rule.allow_providers {
+                let provider = model.provider();
+                if !providers
+                    .iter()
+                    .any(|allowed| provider_key(allowed) == provider_key(&provider))
+                {
+                    bail!(
+                        "Policy rule `{name}` only allows this task to be sent to providers {} but model `{id}` is from `{provider}`",
+                        providers.join(", ")
+                    );
+                }
+            }
+
+            if let Some(max) = rule.max_tokens_per_day {
+                let used = tokens_used_today(&rule.usage_key(task));
+                let tokens = estimate_prompt_tokens(task);
+                if used >= max || used + tokens > max {
+                    bail!(
+                        "Policy rule `{name}` limits usage to {max} tokens per day; {used} tokens have been used today and this task requires ~{tokens}"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the tokens used by a request to a model against the daily limits of the policy
+    pub fn record(&self, model: &dyn Model, task: &ModelTask, tokens: usize) {
+        let Ok(mut usage) = USAGE.lock() else {
+            return;
+        };
+
+        let today = Utc::now().date_naive();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.max_tokens_per_day.is_some() && rule.applies(model, task));
+        for rule in rules {
+            let (date, used) = usage.entry(rule.usage_key(task)).or_insert((today, 0));
+            if *date != today {
+                *date = today;
+                *used = 0;
+            }
+            *used += tokens;
+        }
+    }
+
+    /// Perform a task with a model, checking the request against the policy and recording its tokens
+    ///
+    /// Used for the requests made in the course of performing a task (e.g. to summarize
+    /// its prompt, rerank its candidates, or re-prompt the model) so that they are
+    /// subject to the same policy, and the same task queue limits, as the initial request.
+    pub async fn perform(&self, model: &dyn Model, task: &ModelTask) -> Result<ModelOutput> {
+        self.check(model, task)?;
+        let output = task_queue().perform(model, task, None).await?;
+        self.record(model, task, tokens_used(task, &output));
+        Ok(output)
+    }
+}
+
+/// Get the number of tokens used by a request, estimating them if the provider did not report usage
+fn tokens_used(task: &ModelTask, output: &ModelOutput) -> usize {
+    output.usage.map_or_else(
+        || estimate_prompt_tokens(task) + output.content.chars().count().div_ceil(4),
+        |usage| usage.prompt_tokens + usage.output_tokens,
+    )
+}
+
+/// Get the number of tokens counted under a usage key today
+fn tokens_used_today(key: &str) -> usize {
+    let today = Utc::now().date_naive();
+    USAGE
+        .lock()
+        .ok()
+        .and_then(|usage| usage.get(key).copied())
+        .and_then(|(date, used)| (date == today).then_some(used))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, tokio, toml};
+    use schema::{File, ImageObject, InstructionAttachment, InstructionMessage, MessagePart};
+
+    use crate::{ModelType, ModelsConfig};
+
+    use super::*;
+
+    struct TestModel(&'static str);
+
+    #[async_trait]
+    impl Model for TestModel {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    fn task_with_attachment(media_type: &str) -> ModelTask {
+        ModelTask {
+            attachments: Some(vec![InstructionAttachment {
+                file: File {
+                    media_type: Some(media_type.into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn restricts_attachments_to_providers() -> Result<()> {
+        let policy: Policy = toml::from_str(
+            r#"
+[[rules]]
+name = "imagery-stays-local"
+media-types = "image/tiff"
+allow-providers = ["ollama"]
+"#,
+        )?;
+
+        let tiff = task_with_attachment("image/tiff");
+        policy.check(&TestModel("ollama/llama3.2-vision"), &tiff)?;
+        let Err(error) = policy.check(&TestModel("openai/gpt-4o"), &tiff) else {
+            bail!("expected policy error")
+        };
+        assert!(error.to_string().contains("imagery-stays-local"));
+
+        // Tasks without matching attachments are unaffected
+        policy.check(
+            &TestModel("openai/gpt-4o"),
+            &task_with_attachment("text/csv"),
+        )?;
+        policy.check(&TestModel("openai/gpt-4o"), &ModelTask::default())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn restricts_message_parts_to_providers() -> Result<()> {
+        let policy: Policy = toml::from_str(
+            r#"
+[[rules]]
+name = "imagery-stays-local"
+media-types = "image/tiff"
+allow-providers = ["ollama"]
+"#,
+        )?;
+
+        let task = |part: MessagePart| ModelTask {
+            messages: vec![InstructionMessage {
+                parts: vec![MessagePart::from("Where is the shoreline?"), part],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        // Declared, in a data URL, or sniffed from content
+        let declared = task(MessagePart::ImageObject(ImageObject {
+            content_url: "https://example.org/scene".into(),
+            media_type: Some("image/tiff".into()),
+            ..Default::default()
+        }));
+        let data_url = task(MessagePart::ImageObject(ImageObject::new(
+            "data:image/tiff;base64,SUkqAA==".into(),
+        )));
+        let mut file = File::new("scene.txt".into(), "scene.txt".into());
+        file.media_type = Some("text/plain".into());
+        file.content = Some("SUkqAAgAAAA=".into());
+        file.options.transfer_encoding = Some("base64".into());
+        let mislabeled = task(MessagePart::File(file));
+        for task in [declared, data_url, mislabeled] {
+            policy.check(&TestModel("ollama/llama3.2-vision"), &task)?;
+            assert!(policy.check(&TestModel("openai/gpt-4o"), &task).is_err());
+        }
+
+        // Other media are unaffected
+        policy.check(
+            &TestModel("openai/gpt-4o"),
+            &task(MessagePart::ImageObject(ImageObject::new(
+                "data:image/png;base64,iVBORw0KGgo=".into(),
+            ))),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn matches_providers_like_the_queue() -> Result<()> {
+        let policy: Policy = toml::from_str(
+            r#"
+[[rules]]
+name = "hugging-face-only"
+allow-providers = ["huggingface"]
+"#,
+        )?;
+
+        let model = TestModel("hugging-face/qwen2.5-7b");
+        assert_eq!(model.provider(), "Hugging Face");
+        policy.check(&model, &ModelTask::default())?;
+        assert!(
+            policy
+                .check(&TestModel("openai/gpt-4o"), &ModelTask::default())
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn denies_models() -> Result<()> {
+        let policy = Policy {
+            rules: vec![PolicyRule {
+                name: "no-preview-models".into(),
+                models: Some("*preview*".into()),
+                deny: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let task = ModelTask::default();
+        policy.check(&TestModel("openai/gpt-4o"), &task)?;
+        assert!(
+            policy
+                .check(&TestModel("google/gemini-2.0-preview"), &task)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits_tokens_per_day() -> Result<()> {
+        let policy = Policy {
+            rules: vec![PolicyRule {
+                name: "test-daily-budget".into(),
+                models: Some("openai/*".into()),
+                max_tokens_per_day: Some(1000),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let model = TestModel("openai/gpt-4o");
+        let task = ModelTask::default();
+        policy.check(&model, &task)?;
+        policy.record(&model, &task, 1000);
+        assert!(policy.check(&model, &task).is_err());
+
+        // Other models are not counted or limited
+        policy.record(&TestModel("mistral/mistral-large"), &task, 1000);
+        assert_eq!(tokens_used_today("test-daily-budget"), 1000);
+        policy.check(&TestModel("mistral/mistral-large"), &task)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits_tokens_per_user() -> Result<()> {
+        let policy = Policy {
+            rules: vec![PolicyRule {
+                name: "test-user-budget".into(),
+                max_tokens_per_day: Some(1000),
+                per_user: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let model = TestModel("openai/gpt-4o");
+        let task = |user: &str| ModelTask {
+            user: Some(user.into()),
+            ..Default::default()
+        };
+        policy.record(&model, &task("alice"), 1000);
+        assert!(policy.check(&model, &task("alice")).is_err());
+        policy.check(&model, &task("bob"))?;
+        policy.check(&model, &ModelTask::default())?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn performs_requests() -> Result<()> {
+        let policy = Policy {
+            rules: vec![PolicyRule {
+                name: "test-perform-budget".into(),
+                max_tokens_per_day: Some(40),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let model = TestModel("openai/gpt-4o");
+        let task = ModelTask {
+            messages: vec![InstructionMessage {
+                parts: vec![MessagePart::from("word ".repeat(20))],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        policy.perform(&model, &task).await?;
+        assert!(tokens_used_today("test-perform-budget") > 20);
+        assert!(policy.perform(&model, &task).await.is_err());
+
+        Ok(())
+    }
+
+    struct TypedModel(ModelType);
+
+    #[async_trait]
+    impl Model for TypedModel {
+        fn id(&self) -> String {
+            "test/typed".into()
+        }
+
+        fn r#type(&self) -> ModelType {
+            self.0.clone()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    #[test]
+    fn local_only() -> Result<()> {
+        let config: ModelsConfig = toml::from_str("[policy]\nlocal-only = true")?;
+        let policy = config.policy;
+        assert!(policy.is_local_only());
+
+        policy.check_local_only(&TypedModel(ModelType::Local))?;
+        policy.check(&TypedModel(ModelType::Builtin), &ModelTask::default())?;
+        for r#type in [ModelType::Remote, ModelType::Router, ModelType::Proxied] {
+            let Err(error) = policy.check(&TypedModel(r#type), &ModelTask::default()) else {
+                bail!("expected policy error")
+            };
+            assert!(error.to_string().contains("local-only mode is enabled"));
+        }
+
+        Ok(())
+    }
+}
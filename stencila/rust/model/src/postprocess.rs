@@ -0,0 +1,259 @@
+use common::{
+    itertools::Itertools,
+    once_cell::sync::Lazy,
+    regex::Regex,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{ModelOutput, ModelOutputKind};
+
+/// Matches a leading line of chatter e.g. "Sure, here's the summary:"
+static PREAMBLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(?:sure|certainly|of course|absolutely|okay|ok|great|here(?:'s|’s| is| are))\b.{0,200}[:!.]$")
+        .expect("invalid regex")
+});
+
+/// Matches an ATX heading
+static HEADING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(#{1,6})(\s+.*)?$").expect("invalid regex"));
+
+/// Post-processing of the Markdown generated by models
+///
+/// Configured in the `[outputs.post-processing]` table of the `models.toml` file, and
+/// overridden by the `post_processing` option of a task e.g.
+///
+/// ```toml
+/// [outputs.post-processing]
+/// strip-preamble = true
+/// straight-quotes = true
+/// max-heading-depth = 3
+/// ```
+///
+/// Applied to the content of text outputs in Markdown (or an unspecified format)
+/// before it is decoded into nodes. Fenced code blocks are left unchanged.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct PostProcessing {
+    /// Whether to remove a leading line of chatter e.g. "Sure, here's the summary:"
+    pub strip_preamble: bool,
+
+    /// Whether to convert curly (smart) quotes to straight quotes
+    pub straight_quotes: bool,
+
+    /// The level of the heading that the content is inserted under
+    ///
+    /// Headings are shifted so that the highest level heading in the content is
+    /// one level below this e.g. if the content is inserted into a section with a
+    /// level 2 heading, then a `#` heading in the content becomes `###`. Use `0`
+    /// for content inserted at the top level of a document.
+    pub insertion_level: Option<u8>,
+
+    /// The maximum depth of headings
+    ///
+    /// Headings deeper than this (after any shifting) are raised to this level.
+    pub max_heading_depth: Option<u8>,
+}
+
+impl PostProcessing {
+    /// Whether any post-processing is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.strip_preamble
+            || self.straight_quotes
+            || self.insertion_level.is_some()
+            || self.max_heading_depth.is_some()
+    }
+
+    /// Apply the post-processing to the content of an output
+    pub fn apply(&self, output: &mut ModelOutput) {
+        if !self.is_enabled()
+            || !matches!(output.kind, ModelOutputKind::Text)
+            || !(output.format.is_unknown() || output.format.is_markdown_flavor())
+        {
+            return;
+        }
+
+        output.content = self.process(&output.content);
+    }
+
+    /// Apply the post-processing to Markdown
+    pub fn process(&self, markdown: &str) -> String {
+        let mut lines = markdown.lines().map(String::from).collect_vec();
+
+        if self.strip_preamble {
+            strip_preamble(&mut lines);
+        }
+
+        // Indices of lines outside of fenced code blocks
+        let mut prose = Vec::new();
+        let mut fence: Option<&str> = None;
+        for (index, line) in lines.iter().enumerate() {
+            let trimmed = line.trim_start();
+            let marker = ["```", "~~~"]
+                .into_iter()
+                .find(|marker| trimmed.starts_with(marker));
+            match (fence, marker) {
+                (None, Some(marker)) => fence = Some(marker),
+                (Some(open), Some(marker)) if open == marker => fence = None,
+                (None, None) => prose.push(index),
+                _ => {}
+            }
+        }
+
+        if self.straight_quotes {
+            for &index in &prose {
+                lines[index] = lines[index]
+                    .replace(['“', '”', '„'], "\"")
+                    .replace(['‘', '’', '‚'], "'");
+            }
+        }
+
+        if self.insertion_level.is_some() || self.max_heading_depth.is_some() {
+            let headings = prose
+                .iter()
+                .filter_map(|&index| {
+                    HEADING
+                        .captures(&lines[index])
+                        .map(|captures| (index, captures[1].len() as u8))
+                })
+                .collect_vec();
+
+            let shift = match (
+                self.insertion_level,
+                headings.iter().map(|(.., level)| *level).min(),
+            ) {
+                (Some(insertion), Some(highest)) => insertion as i16 + 1 - highest as i16,
+                _ => 0,
+            };
+            let max = self.max_heading_depth.unwrap_or(6).clamp(1, 6) as i16;
+
+            for (index, level) in headings {
+                let level = (level as i16 + shift).clamp(1, max) as usize;
+                let text = lines[index].trim_start_matches('#').to_string();
+                lines[index] = ["#".repeat(level), text].concat();
+            }
+        }
+
+        let mut processed = lines.join("\n");
+        if markdown.ends_with('\n') {
+            processed.push('\n');
+        }
+        processed
+    }
+}
+
+/// Remove a leading line of chatter, and the blank lines following it
+///
+/// Only removed if it is a paragraph on its own and is followed by other content.
+fn strip_preamble(lines: &mut Vec<String>) {
+    let Some(first) = lines.iter().position(|line| !line.trim().is_empty()) else {
+        return;
+    };
+
+    let is_paragraph = lines
+        .get(first + 1)
+        .is_none_or(|line| line.trim().is_empty());
+    let has_more = lines[first + 1..]
+        .iter()
+        .any(|line| !line.trim().is_empty());
+    if !(is_paragraph && has_more && PREAMBLE.is_match(lines[first].trim())) {
+        return;
+    }
+
+    let rest = lines[first + 1..]
+        .iter()
+        .position(|line| !line.trim().is_empty())
+        .map_or(lines.len(), |offset| first + 1 + offset);
+    lines.drain(..rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_preamble() {
+        let processing = PostProcessing {
+            strip_preamble: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            processing.process("Sure, here's the summary:\n\nShorelines are eroding.\n"),
+            "Shorelines are eroding.\n"
+        );
+        assert_eq!(
+            processing.process("Certainly!\n\n# Results\n\nText"),
+            "# Results\n\nText"
+        );
+
+        // Not stripped if it is the only content, or part of a longer paragraph
+        assert_eq!(processing.process("Sure."), "Sure.");
+        assert_eq!(
+            processing.process("Sure enough, it eroded.\nAt 0.4 m/yr."),
+            "Sure enough, it eroded.\nAt 0.4 m/yr."
+        );
+    }
+
+    #[test]
+    fn straightens_quotes() {
+        let processing = PostProcessing {
+            straight_quotes: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            processing.process("“Erosion” isn’t\n\n```\nlet s = “kept”;\n```"),
+            "\"Erosion\" isn't\n\n```\nlet s = “kept”;\n```"
+        );
+    }
+
+    #[test]
+    fn normalizes_headings() {
+        let markdown = "# Results\n\n## Rates\n\n### Detail\n\n```sh\n# comment\n```\n";
+
+        let shifted = PostProcessing {
+            insertion_level: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            shifted.process(markdown),
+            "## Results\n\n### Rates\n\n#### Detail\n\n```sh\n# comment\n```\n"
+        );
+
+        let raised = PostProcessing {
+            insertion_level: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(raised.process("### A\n\n#### B"), "# A\n\n## B");
+
+        let capped = PostProcessing {
+            insertion_level: Some(1),
+            max_heading_depth: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            capped.process(markdown),
+            "## Results\n\n### Rates\n\n### Detail\n\n```sh\n# comment\n```\n"
+        );
+    }
+
+    #[test]
+    fn only_markdown() {
+        let processing = PostProcessing {
+            straight_quotes: true,
+            ..Default::default()
+        };
+
+        let mut output = ModelOutput {
+            format: format::Format::Json,
+            content: "{\"a\": \"’\"}".into(),
+            ..Default::default()
+        };
+        processing.apply(&mut output);
+        assert_eq!(output.content, "{\"a\": \"’\"}");
+
+        output.format = format::Format::Markdown;
+        processing.apply(&mut output);
+        assert_eq!(output.content, "{\"a\": \"'\"}");
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs::metadata;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use schema::InstructionAttachment;
+
+/// Generate the text introducing an attachment to a model
+///
+/// Providers place this text immediately before the content of each attachment
+/// so that the model can relate the attachment to references to its alias in
+/// the prompt. If no `template` is supplied, the preamble includes the alias,
+/// file name, media type, size and description of the attachment (where known)
+/// e.g.
+///
+/// ```text
+/// Attachment `transects` (transects.csv, text/csv, 1.2 KB): Shoreline change rates for each transect
+/// ```
+///
+/// Templates can use the placeholders `{alias}`, `{filename}`, `{media_type}`,
+/// `{size}` and `{description}`. Placeholders for unknown values are replaced
+/// with an empty string.
+pub fn attachment_preamble(attachment: &InstructionAttachment, template: Option<&str>) -> String {
+    let file = &attachment.file;
+    let media_type = file.media_type.clone().unwrap_or_default();
+    let size = attachment_size(attachment)
+        .map(format_size)
+        .unwrap_or_default();
+    let description = attachment
+        .options
+        .description
+        .as_deref()
+        .map(str::trim)
+        .unwrap_or_default();
+
+    if let Some(template) = template {
+        return template
+            .replace("{alias}", &attachment.alias)
+            .replace("{filename}", &file.name)
+            .replace("{media_type}", &media_type)
+            .replace("{size}", &size)
+            .replace("{description}", description)
+            .trim()
+            .to_string();
+    }
+
+    let options = &attachment.options;
+    let selectors = [
+        options.pages.as_ref().map(|pages| format!("pages {pages}")),
+        options
+            .sheet
+            .as_ref()
+            .map(|sheet| format!("sheet `{sheet}`")),
+        options
+            .byte_range
+            .as_ref()
+            .map(|range| format!("bytes {range}")),
+    ];
+
+    let mut details = [file.name.clone(), media_type, size]
+        .into_iter()
+        .filter(|detail| !detail.is_empty() && *detail != attachment.alias)
+        .collect::<Vec<_>>();
+    details.extend(selectors.into_iter().flatten());
+
+    let mut preamble = format!("Attachment `{}`", attachment.alias);
+    if !details.is_empty() {
+        preamble.push_str(&format!(" ({})", details.join(", ")));
+    }
+    if !description.is_empty() {
+        preamble.push_str(": ");
+        preamble.push_str(description);
+    }
+    preamble
+}
+
+/// Get the size of an attachment in bytes
+///
+/// Uses the `size` of the file if available, falling back to the length of
+/// its (decoded) content, and then to the size of the file on disk.
+fn attachment_size(attachment: &InstructionAttachment) -> Option<u64> {
+    let file = &attachment.file;
+    if let Some(size) = file.size {
+        return Some(size);
+    }
+
+    match &file.content {
+        Some(content) if file.options.transfer_encoding.as_deref() == Some("base64") => BASE64
+            .decode(content.trim())
+            .ok()
+            .map(|bytes| bytes.len() as u64),
+        Some(content) => Some(content.len() as u64),
+        None if !file.path.is_empty() => metadata(file.path.trim_start_matches("file://"))
+            .ok()
+            .map(|metadata| metadata.len()),
+        None => None,
+    }
+}
+
+/// Format a number of bytes for humans e.g. `512 bytes`, `1.2 KB`, `3.4 MB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::File;
+
+    use super::*;
+
+    fn attachment(description: Option<&str>) -> InstructionAttachment {
+        let mut file = File::new("transects.csv".into(), "outputs/transects.csv".into());
+        file.media_type = Some("text/csv".into());
+        file.content = Some("id,rate\nnarra_0001,-0.4\n".into());
+
+        let mut attachment = InstructionAttachment::new("transects".into(), file);
+        attachment.options.description = description.map(String::from);
+        attachment
+    }
+
+    #[test]
+    fn default_preamble() {
+        assert_eq!(
+            attachment_preamble(&attachment(None), None),
+            "Attachment `transects` (transects.csv, text/csv, 24 bytes)"
+        );
+        assert_eq!(
+            attachment_preamble(&attachment(Some("Shoreline change rates")), None),
+            "Attachment `transects` (transects.csv, text/csv, 24 bytes): Shoreline change rates"
+        );
+
+        let mut selected = attachment(None);
+        selected.options.pages = Some("3-5".into());
+        selected.options.byte_range = Some("0-99".into());
+        assert_eq!(
+            attachment_preamble(&selected, None),
+            "Attachment `transects` (transects.csv, text/csv, 24 bytes, pages 3-5, bytes 0-99)"
+        );
+
+        let bare = InstructionAttachment::new("notes".into(), File::default());
+        assert_eq!(attachment_preamble(&bare, None), "Attachment `notes`");
+    }
+
+    #[test]
+    fn templated_preamble() {
+        let template = "## {alias} [{filename}; {media_type}; {size}]\n{description}";
+        assert_eq!(
+            attachment_preamble(&attachment(Some("Rates")), Some(template)),
+            "## transects [transects.csv; text/csv; 24 bytes]\nRates"
+        );
+        assert_eq!(
+            attachment_preamble(&attachment(None), Some(template)),
+            "## transects [transects.csv; text/csv; 24 bytes]"
+        );
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1023), "1023 bytes");
+        assert_eq!(format_size(1229), "1.2 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+}
@@ -0,0 +1,221 @@
+use common::{
+    eyre::Result,
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+};
+use schema::InstructionAttachment;
+
+use crate::file_prefix;
+
+/// The maximum number of bytes of each character of a line read for a preview
+const MAX_CHAR_BYTES: usize = 4;
+
+/// Options for generating a textual preview of an attachment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct PreviewOptions {
+    /// The maximum number of lines of text files to include
+    pub max_lines: usize,
+
+    /// The maximum number of characters of each line to include
+    pub max_line_chars: usize,
+
+    /// The number of bytes at the start of binary files to include as a hexdump
+    pub hexdump_bytes: usize,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            max_lines: 20,
+            max_line_chars: 200,
+            hexdump_bytes: 64,
+        }
+    }
+}
+
+/// Generate a textual preview of an attachment
+///
+/// Used by providers for attachments with media types that the model does not
+/// support so that, rather than being silently skipped, the model knows that
+/// the attachment exists and has some idea of its content. The preview includes
+/// the alias, name, media type and size of the file, followed by the first lines
+/// of text files or a hexdump of the header of binary files.
+///
+/// Only the start of the file (enough for the preview) is read. As for other
+/// attachments, files without content must have a path which has been resolved
+/// against their document (see [`file_prefix`]).
+pub fn preview_attachment(
+    attachment: &InstructionAttachment,
+    options: &PreviewOptions,
+) -> Result<String> {
+    let file = &attachment.file;
+
+    // Read enough for the lines to be previewed, plus a byte to detect truncation
+    let max_bytes = options
+        .hexdump_bytes
+        .max(
+            options
+                .max_lines
+                .saturating_mul(options.max_line_chars.saturating_mul(MAX_CHAR_BYTES) + 1),
+        )
+        .saturating_add(1);
+    let (bytes, size) = file_prefix(file, max_bytes)?;
+    let partial = (bytes.len() as u64) < size;
+
+    let mut preview = format!(
+        "Preview of attachment `{}` (its content could not be provided directly):\n\n- Name: {}\n- Media type: {}\n- Size: {size} bytes\n",
+        attachment.alias,
+        file.name,
+        file.media_type.as_deref().unwrap_or("unknown"),
+    );
+
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => Some(text),
+        // The prefix may have split a multi-byte character at its end
+        Err(error) if partial && error.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..error.valid_up_to()]).ok()
+        }
+        Err(..) => None,
+    };
+
+    match text {
+        Some(text) => {
+            let head = text
+                .lines()
+                .take(options.max_lines)
+                .map(|line| {
+                    if line.chars().count() > options.max_line_chars {
+                        let truncated: String = line.chars().take(options.max_line_chars).collect();
+                        format!("{truncated}…")
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect_vec();
+            let shown = head.len();
+            let head = head.join("\n");
+
+            // The total number of lines is only known if the whole file was read
+            if !partial {
+                preview.push_str(&format!("- Lines: {}\n", text.lines().count()));
+            }
+            preview.push_str(&format!("\nFirst {shown} lines:\n\n```\n{head}\n```\n"));
+        }
+        None => {
+            let head = &bytes[..bytes.len().min(options.hexdump_bytes)];
+            preview.push_str(&format!(
+                "\nFirst {} bytes:\n\n```\n{}\n```\n",
+                head.len(),
+                hexdump(head)
+            ));
+        }
+    }
+
+    Ok(preview)
+}
+
+/// Create a hexdump of bytes in the canonical offset, hex, ASCII format
+fn hexdump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let hex = chunk.iter().map(|byte| format!("{byte:02x}")).join(" ");
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08x}  {hex:<47}  |{ascii}|", index * 16)
+        })
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+    use common::tempfile::tempdir;
+    use schema::File;
+
+    use super::*;
+
+    fn attachment(content: &str, base64: bool, media_type: &str) -> InstructionAttachment {
+        let mut file = File::new("data".into(), "data".into());
+        file.content = Some(content.into());
+        file.media_type = Some(media_type.into());
+        if base64 {
+            file.options.transfer_encoding = Some("base64".into());
+        }
+        InstructionAttachment::new("data".into(), file)
+    }
+
+    #[test]
+    fn text_and_binary() -> Result<()> {
+        let options = PreviewOptions {
+            max_lines: 2,
+            ..Default::default()
+        };
+
+        let preview = preview_attachment(
+            &attachment(
+                "lat,lon\n-33.9,151.2\n-33.8,151.3\n",
+                false,
+                "application/geo+csv",
+            ),
+            &options,
+        )?;
+        assert_eq!(
+            preview,
+            "Preview of attachment `data` (its content could not be provided directly):\n\n- Name: data\n- Media type: application/geo+csv\n- Size: 32 bytes\n- Lines: 3\n\nFirst 2 lines:\n\n```\nlat,lon\n-33.9,151.2\n```\n"
+        );
+
+        let preview = preview_attachment(
+            &attachment(
+                &BASE64.encode([0x89, b'H', b'D', b'F', 0x0d, 0x0a]),
+                true,
+                "application/x-hdf5",
+            ),
+            &options,
+        )?;
+        assert!(preview.contains("- Size: 6 bytes\n"));
+        assert!(preview.contains("00000000  89 48 44 46 0d 0a"));
+        assert!(preview.ends_with("|.HDF..|\n```\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_and_relative_files() -> Result<()> {
+        let options = PreviewOptions {
+            max_lines: 2,
+            max_line_chars: 10,
+            ..Default::default()
+        };
+
+        let dir = tempdir()?;
+        let path = dir.path().join("rates.csv");
+        std::fs::write(&path, "id,rate\n".repeat(100_000))?;
+
+        let file = File::new("rates.csv".into(), path.to_string_lossy().to_string());
+        let preview =
+            preview_attachment(&InstructionAttachment::new("rates".into(), file), &options)?;
+        assert!(
+            preview
+                .contains("- Size: 800000 bytes\n\nFirst 2 lines:\n\n```\nid,rate\nid,rate\n```")
+        );
+
+        let file = File::new("rates.csv".into(), "rates.csv".into());
+        assert!(
+            preview_attachment(&InstructionAttachment::new("rates".into(), file), &options)
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,714 @@
+use std::{collections::BTreeMap, fs::read_to_string};
+
+use common::{
+    chrono::{DateTime, TimeDelta, Utc},
+    eyre::Result,
+    serde_json::{self, Map, Value, json},
+    tracing,
+};
+
+use crate::{
+    ModelOutput, ModelTask, RunStore, TaskFingerprint, UsageLedger, attestation::content_hash,
+};
+
+/// The namespace of the identifiers of the entities, activities and agents of provenance graphs
+pub const PROV_NAMESPACE: &str = "https://stencila.io/prov/";
+
+/// The kind of a node in a [`ProvDocument`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvKind {
+    /// A thing e.g. a prompt, attachment or output
+    Entity,
+
+    /// Something that occurred over a period of time e.g. the performing of a task
+    Activity,
+
+    /// Something bearing responsibility for an activity e.g. a model or user
+    Agent,
+}
+
+/// An entity, activity or agent in a [`ProvDocument`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvNode {
+    /// The kind of node
+    pub kind: ProvKind,
+
+    /// The PROV type of the node e.g. `prov:SoftwareAgent`
+    pub prov_type: Option<String>,
+
+    /// The attributes of the node, keyed by qualified name e.g. `stencila:mediaType`
+    pub attributes: BTreeMap<String, Value>,
+}
+
+/// The kind of a relation in a [`ProvDocument`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvRelationKind {
+    /// An activity used an entity
+    Used,
+
+    /// An entity was generated by an activity
+    WasGeneratedBy,
+
+    /// An activity was associated with an agent
+    WasAssociatedWith,
+
+    /// An entity was attributed to an agent
+    WasAttributedTo,
+
+    /// An entity was derived from another entity
+    WasDerivedFrom,
+
+    /// An agent acted on behalf of another agent
+    ActedOnBehalfOf,
+}
+
+impl ProvRelationKind {
+    /// The name of the relation in PROV-JSON and PROV-O
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Used => "used",
+            Self::WasGeneratedBy => "wasGeneratedBy",
+            Self::WasAssociatedWith => "wasAssociatedWith",
+            Self::WasAttributedTo => "wasAttributedTo",
+            Self::WasDerivedFrom => "wasDerivedFrom",
+            Self::ActedOnBehalfOf => "actedOnBehalfOf",
+        }
+    }
+
+    /// The PROV-JSON keys of the subject and object of the relation
+    fn json_keys(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Used => ("prov:activity", "prov:entity"),
+            Self::WasGeneratedBy => ("prov:entity", "prov:activity"),
+            Self::WasAssociatedWith => ("prov:activity", "prov:agent"),
+            Self::WasAttributedTo => ("prov:entity", "prov:agent"),
+            Self::WasDerivedFrom => ("prov:generatedEntity", "prov:usedEntity"),
+            Self::ActedOnBehalfOf => ("prov:delegate", "prov:responsible"),
+        }
+    }
+}
+
+/// A relation between two nodes in a [`ProvDocument`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvRelation {
+    /// The kind of relation
+    pub kind: ProvRelationKind,
+
+    /// The id of the subject of the relation e.g. the activity of `used`
+    pub subject: String,
+
+    /// The id of the object of the relation e.g. the entity of `used`
+    pub object: String,
+}
+
+/// A W3C PROV graph of the invocations of models
+///
+/// Built from the runs in a [`RunStore`], or the entries of a [`UsageLedger`], and
+/// exported as PROV-JSON (using [`ProvDocument::to_prov_json`]) or RDF Turtle using
+/// the PROV-O vocabulary (using [`ProvDocument::to_turtle`]) for ingestion by external
+/// provenance tooling. Each task is an activity which used a prompt, and attachments,
+/// and generated an output. Models, and the users tasks were performed for, are agents.
+///
+/// Node ids are local to [`PROV_NAMESPACE`] e.g. `task/<run-id>`, `model/openai/gpt-4o`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProvDocument {
+    /// The entities, activities and agents of the graph, keyed by id
+    pub nodes: BTreeMap<String, ProvNode>,
+
+    /// The relations between nodes, in the order they were added
+    pub relations: Vec<ProvRelation>,
+}
+
+impl ProvDocument {
+    /// Create an empty provenance graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a provenance graph of the runs in a run store
+    ///
+    /// Runs without a response (e.g. those which failed) are skipped.
+    pub fn from_runs(store: &RunStore) -> Result<Self> {
+        let mut doc = Self::new();
+        for run in store.list()? {
+            let (Ok(request), Ok(response)) = (
+                read_to_string(run.dir.join("request.json")),
+                read_to_string(run.dir.join("response.json")),
+            ) else {
+                continue;
+            };
+            match (
+                serde_json::from_str::<ModelTask>(&request),
+                serde_json::from_str::<ModelOutput>(&response),
+            ) {
+                (Ok(task), Ok(output)) => doc.add_invocation(&run.id, run.started, &task, &output),
+                (Err(error), ..) | (.., Err(error)) => {
+                    tracing::debug!("Skipping run `{}`: {error}", run.id)
+                }
+            }
+        }
+        Ok(doc)
+    }
+
+    /// Create a provenance graph of the entries of a usage ledger
+    ///
+    /// Ledgers do not record tasks so the graph has no prompts or attachments, only
+    /// the outputs generated for each node, and the models and users involved.
+    pub fn from_ledger(ledger: &UsageLedger) -> Self {
+        let mut doc = Self::new();
+        for (index, entry) in ledger.entries.iter().enumerate() {
+            let report = &entry.report;
+            let activity = format!("task/{}-{index}", entry.node_id);
+            let started = entry.recorded - TimeDelta::milliseconds(report.latency_ms as i64);
+            doc.activity(&activity, started, entry.recorded);
+
+            let output = format!("node/{}", entry.node_id);
+            doc.entity(&output, Some("stencila:Output"), []);
+            doc.relate(ProvRelationKind::WasGeneratedBy, &output, &activity);
+
+            doc.agents(&activity, &output, &report.model, report.user.as_deref());
+        }
+        doc
+    }
+
+    /// Add an invocation of a model for a task to the graph
+    ///
+    /// `id` should uniquely identify the invocation e.g. the id of its run.
+    pub fn add_invocation(
+        &mut self,
+        id: &str,
+        started: DateTime<Utc>,
+        task: &ModelTask,
+        output: &ModelOutput,
+    ) {
+        let report = output.report.as_ref();
+        let ended = started + TimeDelta::milliseconds(report.map_or(0, |r| r.latency_ms) as i64);
+
+        let activity = format!("task/{id}");
+        self.activity(&activity, started, ended);
+        if let Some(node) = self.nodes.get_mut(&activity) {
+            node.attributes
+                .insert("stencila:taskKind".into(), json!(task.kind.to_string()));
+        }
+
+        let prompt = format!("prompt/{id}");
+        let fingerprint = TaskFingerprint::new(task)
+            .map(|fingerprint| fingerprint.as_str().to_string())
+            .ok();
+        self.entity(
+            &prompt,
+            Some("stencila:Prompt"),
+            [
+                ("stencila:messages", json!(task.messages.len())),
+                ("stencila:fingerprint", json!(fingerprint)),
+            ],
+        );
+        self.relate(ProvRelationKind::Used, &activity, &prompt);
+
+        for attachment in task.attachments.iter().flatten() {
+            let entity = format!("attachment/{id}/{}", attachment.alias);
+            let file = &attachment.file;
+            self.entity(
+                &entity,
+                Some("stencila:Attachment"),
+                [
+                    ("prov:label", json!(attachment.alias)),
+                    ("stencila:name", json!(file.name)),
+                    ("stencila:mediaType", json!(file.media_type)),
+                    (
+                        "stencila:contentHash",
+                        json!(
+                            file.content
+                                .as_ref()
+                                .map(|content| content_hash(content.as_bytes()))
+                        ),
+                    ),
+                ],
+            );
+            self.relate(ProvRelationKind::Used, &activity, &entity);
+        }
+
+        let entity = format!("output/{id}");
+        self.entity(
+            &entity,
+            Some("stencila:Output"),
+            [
+                ("stencila:format", json!(output.format.to_string())),
+                (
+                    "stencila:contentHash",
+                    json!(content_hash(output.content.as_bytes())),
+                ),
+                (
+                    "stencila:promptTokens",
+                    json!(report.map(|report| report.prompt_tokens)),
+                ),
+                (
+                    "stencila:outputTokens",
+                    json!(report.map(|report| report.output_tokens)),
+                ),
+            ],
+        );
+        self.relate(ProvRelationKind::WasGeneratedBy, &entity, &activity);
+        self.relate(ProvRelationKind::WasDerivedFrom, &entity, &prompt);
+
+        // Citations of attachments are derivations from the attachment's entity,
+        // all others (e.g. DOIs and web pages) are derivations from a reference
+        for citation in &output.citations {
+            let is_attachment = task
+                .attachments
+                .iter()
+                .flatten()
+                .any(|attachment| attachment.alias == citation.target);
+            let cited = if is_attachment {
+                format!("attachment/{id}/{}", citation.target)
+            } else {
+                let reference = citation.options.cites.as_ref();
+                let id = format!("reference/{}", citation.target);
+                self.entity(
+                    &id,
+                    Some("stencila:Reference"),
+                    [
+                        ("prov:label", json!(citation.target)),
+                        (
+                            "stencila:doi",
+                            json!(reference.and_then(|r| r.doi.as_ref())),
+                        ),
+                        (
+                            "stencila:workType",
+                            json!(
+                                reference
+                                    .and_then(|r| r.work_type.as_ref())
+                                    .map(|work_type| work_type.to_string())
+                            ),
+                        ),
+                    ],
+                );
+                id
+            };
+            self.relate(ProvRelationKind::WasDerivedFrom, &entity, &cited);
+        }
+
+        // Workflow artifacts are identified by their hash so that tasks grounded
+        // in the same version of a file share the entity
+        let artifacts = if output.derived_from.is_empty() {
+            &task.derived_from
+        } else {
+            &output.derived_from
+        };
+        for artifact in artifacts {
+            let id = format!("artifact/{}", artifact.hash);
+            self.entity(
+                &id,
+                Some("stencila:WorkflowArtifact"),
+                [
+                    ("prov:label", json!(artifact.path)),
+                    ("stencila:contentHash", json!(artifact.hash)),
+                    ("stencila:workflow", json!(artifact.workflow)),
+                    ("stencila:step", json!(artifact.step)),
+                ],
+            );
+            self.relate(ProvRelationKind::Used, &activity, &id);
+            self.relate(ProvRelationKind::WasDerivedFrom, &entity, &id);
+        }
+
+        self.agents(
+            &activity,
+            &entity,
+            report.map_or("unknown", |report| report.model.as_str()),
+            report
+                .and_then(|report| report.user.as_deref())
+                .or(task.user.as_deref()),
+        );
+    }
+
+    /// Add the model, and user, agents of an activity which generated an entity
+    fn agents(&mut self, activity: &str, entity: &str, model: &str, user: Option<&str>) {
+        let model_id = format!("model/{model}");
+        self.node(
+            &model_id,
+            ProvKind::Agent,
+            Some("prov:SoftwareAgent"),
+            [("prov:label", json!(model))],
+        );
+        self.relate(ProvRelationKind::WasAssociatedWith, activity, &model_id);
+        self.relate(ProvRelationKind::WasAttributedTo, entity, &model_id);
+
+        if let Some(user) = user {
+            let user_id = format!("user/{user}");
+            self.node(
+                &user_id,
+                ProvKind::Agent,
+                Some("prov:Person"),
+                [("prov:label", json!(user))],
+            );
+            self.relate(ProvRelationKind::WasAssociatedWith, activity, &user_id);
+            self.relate(ProvRelationKind::ActedOnBehalfOf, &model_id, &user_id);
+        }
+    }
+
+    /// Add an activity to the graph
+    fn activity(&mut self, id: &str, started: DateTime<Utc>, ended: DateTime<Utc>) {
+        self.node(
+            id,
+            ProvKind::Activity,
+            Some("stencila:ModelTask"),
+            [
+                ("prov:startTime", json!(started.to_rfc3339())),
+                ("prov:endTime", json!(ended.to_rfc3339())),
+            ],
+        );
+    }
+
+    /// Add an entity to the graph
+    fn entity<const N: usize>(
+        &mut self,
+        id: &str,
+        prov_type: Option<&str>,
+        attributes: [(&str, Value); N],
+    ) {
+        self.node(id, ProvKind::Entity, prov_type, attributes);
+    }
+
+    /// Add a node to the graph, ignoring attributes which are null
+    ///
+    /// Nodes which are already in the graph (e.g. the agent of a model used for
+    /// several tasks) are not replaced.
+    fn node<const N: usize>(
+        &mut self,
+        id: &str,
+        kind: ProvKind,
+        prov_type: Option<&str>,
+        attributes: [(&str, Value); N],
+    ) {
+        self.nodes.entry(id.into()).or_insert_with(|| ProvNode {
+            kind,
+            prov_type: prov_type.map(String::from),
+            attributes: attributes
+                .into_iter()
+                .filter(|(.., value)| !value.is_null())
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        });
+    }
+
+    /// Add a relation to the graph, unless it is already present
+    fn relate(&mut self, kind: ProvRelationKind, subject: &str, object: &str) {
+        let relation = ProvRelation {
+            kind,
+            subject: subject.into(),
+            object: object.into(),
+        };
+        if !self.relations.contains(&relation) {
+            self.relations.push(relation);
+        }
+    }
+
+    /// Export the graph as PROV-JSON
+    ///
+    /// See https://www.w3.org/submissions/prov-json/.
+    pub fn to_prov_json(&self) -> Value {
+        let mut doc = Map::new();
+        doc.insert(
+            "prefix".into(),
+            json!({
+                "stencila": PROV_NAMESPACE,
+                "xsd": "http://www.w3.org/2001/XMLSchema#"
+            }),
+        );
+
+        for (id, node) in &self.nodes {
+            let section = match node.kind {
+                ProvKind::Entity => "entity",
+                ProvKind::Activity => "activity",
+                ProvKind::Agent => "agent",
+            };
+            let mut attributes = Map::new();
+            if let Some(prov_type) = &node.prov_type {
+                attributes.insert(
+                    "prov:type".into(),
+                    json!({"$": prov_type, "type": "prov:QUALIFIED_NAME"}),
+                );
+            }
+            for (key, value) in &node.attributes {
+                attributes.insert(key.clone(), value.clone());
+            }
+            insert_into(&mut doc, section, format!("stencila:{id}"), attributes);
+        }
+
+        for (index, relation) in self.relations.iter().enumerate() {
+            let (subject_key, object_key) = relation.kind.json_keys();
+            let mut attributes = Map::new();
+            attributes.insert(
+                subject_key.into(),
+                json!(format!("stencila:{}", relation.subject)),
+            );
+            attributes.insert(
+                object_key.into(),
+                json!(format!("stencila:{}", relation.object)),
+            );
+            insert_into(
+                &mut doc,
+                relation.kind.name(),
+                format!("_:r{index}"),
+                attributes,
+            );
+        }
+
+        Value::Object(doc)
+    }
+
+    /// Export the graph as RDF Turtle using the PROV-O vocabulary
+    ///
+    /// See https://www.w3.org/TR/prov-o/.
+    pub fn to_turtle(&self) -> String {
+        let mut ttl = format!(
+            "@prefix prov: <http://www.w3.org/ns/prov#> .\n@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .\n@prefix stencila: <{PROV_NAMESPACE}> .\n"
+        );
+
+        for (id, node) in &self.nodes {
+            let kind = match node.kind {
+                ProvKind::Entity => "prov:Entity",
+                ProvKind::Activity => "prov:Activity",
+                ProvKind::Agent => "prov:Agent",
+            };
+            let mut types = vec![kind.to_string()];
+            types.extend(node.prov_type.clone());
+
+            let mut statements = vec![format!("a {}", types.join(", "))];
+            for (key, value) in &node.attributes {
+                let (predicate, object) = match key.as_str() {
+                    "prov:startTime" => ("prov:startedAtTime", turtle_datetime(value)),
+                    "prov:endTime" => ("prov:endedAtTime", turtle_datetime(value)),
+                    "prov:label" => ("rdfs:label", turtle_literal(value)),
+                    key => (key, turtle_literal(value)),
+                };
+                statements.push(format!("{predicate} {object}"));
+            }
+            for relation in self.relations.iter().filter(|rel| &rel.subject == id) {
+                statements.push(format!(
+                    "prov:{} {}",
+                    relation.kind.name(),
+                    turtle_iri(&relation.object)
+                ));
+            }
+
+            ttl.push_str(&format!(
+                "\n{}\n    {} .\n",
+                turtle_iri(id),
+                statements.join(" ;\n    ")
+            ));
+        }
+
+        ttl
+    }
+}
+
+/// Insert a PROV-JSON record into a section (e.g. `entity`, `used`) of a document
+fn insert_into(
+    doc: &mut Map<String, Value>,
+    section: &str,
+    id: String,
+    record: Map<String, Value>,
+) {
+    if let Value::Object(records) = doc
+        .entry(section)
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        records.insert(id, Value::Object(record));
+    }
+}
+
+/// Get the full IRI of a node id for Turtle, percent encoding reserved characters
+fn turtle_iri(id: &str) -> String {
+    let mut iri = String::from(PROV_NAMESPACE);
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/:@".contains(&byte) {
+            iri.push(byte as char);
+        } else {
+            iri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    format!("<{iri}>")
+}
+
+/// Format a JSON value as a Turtle literal
+fn turtle_literal(value: &Value) -> String {
+    match value {
+        Value::Number(number) => number.to_string(),
+        Value::Bool(bool) => bool.to_string(),
+        Value::String(string) => turtle_string(string),
+        value => turtle_string(&value.to_string()),
+    }
+}
+
+/// Format a JSON string value as a Turtle `xsd:dateTime` literal
+fn turtle_datetime(value: &Value) -> String {
+    format!(
+        "{}^^xsd:dateTime",
+        turtle_string(value.as_str().unwrap_or_default())
+    )
+}
+
+/// Format a string as a quoted Turtle string, escaping special characters
+fn turtle_string(string: &str) -> String {
+    let escaped = string
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::bail, tempfile::tempdir};
+    use schema::{File, InstructionAttachment, InstructionMessage};
+
+    use crate::{TaskReport, WorkflowArtifact};
+
+    use super::*;
+
+    fn invocation() -> (ModelTask, ModelOutput) {
+        let mut file = File::new("shoreline.csv".into(), "shoreline.csv".into());
+        file.media_type = Some("text/csv".into());
+        file.content = Some("1,2\n".into());
+
+        let task = ModelTask {
+            messages: vec![InstructionMessage::user("Summarize the \"trend\"", None)],
+            attachments: Some(vec![InstructionAttachment::new("shoreline".into(), file)]),
+            user: Some("alice".into()),
+            derived_from: vec![
+                WorkflowArtifact::new("transects.csv", "abc123").workflow("CoastSat"),
+            ],
+            ..Default::default()
+        };
+        let mut output = ModelOutput {
+            content: "Eroding [1], see https://doi.org/10.1038/s41467-023-39135-z".into(),
+            report: Some(TaskReport {
+                model: "openai/gpt-4o".into(),
+                user: Some("alice".into()),
+                latency_ms: 1500,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        output.link_citations(&task);
+        (task, output)
+    }
+
+    #[test]
+    fn exports_prov_json() -> Result<()> {
+        let (task, output) = invocation();
+        let mut doc = ProvDocument::new();
+        doc.add_invocation("run1", Utc::now(), &task, &output);
+        doc.add_invocation("run2", Utc::now(), &task, &output);
+
+        let json = doc.to_prov_json();
+        let Some(agents) = json["agent"].as_object() else {
+            bail!("expected agents")
+        };
+        assert_eq!(
+            agents.keys().collect::<Vec<_>>(),
+            vec!["stencila:model/openai/gpt-4o", "stencila:user/alice"]
+        );
+        assert_eq!(
+            json["entity"]["stencila:attachment/run1/shoreline"]["stencila:mediaType"],
+            "text/csv"
+        );
+        assert_eq!(
+            json["activity"]["stencila:task/run1"]["prov:type"]["$"],
+            "stencila:ModelTask"
+        );
+        let Some(generated) = json["wasGeneratedBy"].as_object() else {
+            bail!("expected generations")
+        };
+        assert_eq!(generated.len(), 2);
+        assert!(generated.values().any(|record| {
+            record["prov:entity"] == "stencila:output/run1"
+                && record["prov:activity"] == "stencila:task/run1"
+        }));
+        assert_eq!(json["actedOnBehalfOf"].as_object().map(Map::len), Some(1));
+
+        // Both outputs were derived from the single version of the workflow artifact
+        assert_eq!(
+            json["entity"]["stencila:artifact/abc123"]["stencila:workflow"],
+            "CoastSat"
+        );
+        let Some(derivations) = json["wasDerivedFrom"].as_object() else {
+            bail!("expected derivations")
+        };
+        assert_eq!(
+            derivations
+                .values()
+                .filter(|record| record["prov:usedEntity"] == "stencila:artifact/abc123")
+                .count(),
+            2
+        );
+
+        // Outputs were derived from the attachment, and the DOI, that they cite
+        assert!(derivations.values().any(|record| {
+            record["prov:generatedEntity"] == "stencila:output/run1"
+                && record["prov:usedEntity"] == "stencila:attachment/run1/shoreline"
+        }));
+        let reference = "stencila:reference/https://doi.org/10.1038/s41467-023-39135-z";
+        assert_eq!(
+            json["entity"][reference]["stencila:doi"],
+            "10.1038/s41467-023-39135-z"
+        );
+        assert_eq!(
+            derivations
+                .values()
+                .filter(|record| record["prov:usedEntity"] == reference)
+                .count(),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn exports_turtle() {
+        let (task, output) = invocation();
+        let mut doc = ProvDocument::new();
+        doc.add_invocation("run 1", Utc::now(), &task, &output);
+
+        let ttl = doc.to_turtle();
+        assert!(ttl.starts_with("@prefix prov: <http://www.w3.org/ns/prov#> ."));
+        assert!(ttl.contains(
+            "<https://stencila.io/prov/task/run%201>\n    a prov:Activity, stencila:ModelTask ;"
+        ));
+        assert!(ttl.contains("prov:startedAtTime \""));
+        assert!(ttl.contains("\"^^xsd:dateTime"));
+        assert!(ttl.contains("prov:wasGeneratedBy <https://stencila.io/prov/task/run%201>"));
+        assert!(ttl.contains("prov:actedOnBehalfOf <https://stencila.io/prov/user/alice>"));
+    }
+
+    #[test]
+    fn builds_from_runs_and_ledgers() -> Result<()> {
+        let dir = tempdir()?;
+        let store = RunStore::new(dir.path());
+        let (task, output) = invocation();
+        let run = store.create()?;
+        run.write_request(&task)?;
+        run.write_response(&output)?;
+        store.create()?.write_request(&task)?;
+
+        let doc = ProvDocument::from_runs(&store)?;
+        assert!(doc.nodes.contains_key(&format!("output/{}", run.id)));
+        assert_eq!(
+            doc.nodes
+                .values()
+                .filter(|node| node.kind == ProvKind::Activity)
+                .count(),
+            1
+        );
+
+        let mut ledger = UsageLedger::new();
+        ledger.record_output("ins_1", &output);
+        let doc = ProvDocument::from_ledger(&ledger);
+        assert!(doc.nodes.contains_key("node/ins_1"));
+        assert!(doc.nodes.contains_key("model/openai/gpt-4o"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,622 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::{
+    eyre::Result,
+    once_cell::sync::Lazy,
+    serde::Serialize,
+    tokio::{sync::Notify, time::sleep},
+};
+
+use crate::{
+    DeltaCallback, Model, ModelOutput, ModelTask, QueueConfig, TaskPriority, models_config,
+};
+
+/// The window over which request rates are limited
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// A queue of tasks waiting to make requests to providers
+///
+/// Tasks wait in the queue until the number of running tasks is below the
+/// concurrency limit and the provider's request rate limit allows another
+/// request. Interactive tasks preempt batch tasks, so that on-demand user prompts
+/// are not held up behind bulk jobs even though both share the same limits: no
+/// batch task is started while an interactive task for the same provider is
+/// waiting, or while an interactive task for another provider is waiting for
+/// a concurrency slot (rather than for a rate limit). Interactive tasks waiting
+/// only for their user's rate limit do not hold back batch tasks.
+pub struct TaskQueue {
+    /// The limits of the queue
+    config: QueueConfig,
+
+    /// The mutable state of the queue
+    state: Mutex<QueueState>,
+
+    /// Used to wake waiting tasks when a permit is released
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// The number of tasks currently running
+    running: usize,
+
+    /// The number of tasks waiting, by priority
+    waiting: HashMap<TaskPriority, usize>,
+
+    /// The number of interactive tasks waiting, by provider and user
+    waiting_interactive: HashMap<(String, Option<String>), usize>,
+
+    /// The times of recent requests, by provider
+    requests: HashMap<String, VecDeque<Instant>>,
+
+    /// The times of recent requests, by user
+    user_requests: HashMap<String, VecDeque<Instant>>,
+}
+
+/// The status of a [`TaskQueue`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct QueueStatus {
+    /// The number of tasks currently running
+    pub running: usize,
+
+    /// The number of interactive tasks waiting
+    pub waiting_interactive: usize,
+
+    /// The number of batch tasks waiting
+    pub waiting_batch: usize,
+}
+
+/// A permit to make a request, returned by [`TaskQueue::acquire`]
+///
+/// The task is counted as running until the permit is dropped.
+pub struct QueuePermit {
+    queue: Arc<TaskQueue>,
+}
+
+impl Drop for QueuePermit {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.queue.state.lock() {
+            state.running = state.running.saturating_sub(1);
+        }
+        self.queue.notify.notify_waiters();
+    }
+}
+
+/// Counts a task as waiting until dropped
+///
+/// Ensures that the count is decremented if the future acquiring a permit is
+/// dropped (e.g. because the task was cancelled) while waiting.
+struct WaitingGuard<'q> {
+    queue: &'q TaskQueue,
+    priority: TaskPriority,
+    key: (String, Option<String>),
+}
+
+impl<'q> WaitingGuard<'q> {
+    fn new(
+        queue: &'q TaskQueue,
+        priority: TaskPriority,
+        provider: &str,
+        user: Option<&str>,
+    ) -> Self {
+        let key = (provider.to_string(), user.map(String::from));
+        queue.update(|state| {
+            *state.waiting.entry(priority).or_default() += 1;
+            if priority == TaskPriority::Interactive {
+                *state.waiting_interactive.entry(key.clone()).or_default() += 1;
+            }
+        });
+        Self {
+            queue,
+            priority,
+            key,
+        }
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.queue.update(|state| {
+            if let Some(count) = state.waiting.get_mut(&self.priority) {
+                *count = count.saturating_sub(1);
+            }
+            if self.priority == TaskPriority::Interactive
+                && let Some(count) = state.waiting_interactive.get_mut(&self.key)
+            {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    state.waiting_interactive.remove(&self.key);
+                }
+            }
+        });
+    }
+}
+
+impl TaskQueue {
+    /// Create a new task queue with the given limits
+    pub fn new(mut config: QueueConfig) -> Arc<Self> {
+        config.requests_per_minute = config
+            .requests_per_minute
+            .into_iter()
+            .map(|(provider, limit)| (provider_key(&provider), limit))
+            .collect();
+
+        Arc::new(Self {
+            config,
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+        })
+    }
+
+    /// Wait for a permit to make a request to a provider, optionally on behalf of a user
+    pub async fn acquire(
+        self: &Arc<Self>,
+        priority: TaskPriority,
+        provider: &str,
+        user: Option<&str>,
+    ) -> QueuePermit {
+        let provider = provider_key(provider);
+        let provider = provider.as_str();
+        let waiting = WaitingGuard::new(self, priority, provider, user);
+
+        loop {
+            // Create the notification future before checking so that a permit
+            // released between the check and the wait is not missed
+            let notified = self.notify.notified();
+
+            let wait = {
+                let Ok(mut state) = self.state.lock() else {
+                    break;
+                };
+                match self.try_start(&mut state, priority, provider, user) {
+                    None => break,
+                    Some(wait) => wait,
+                }
+            };
+
+            match wait {
+                Some(wait) => {
+                    common::tokio::select! {
+                        _ = notified => {},
+                        _ = sleep(wait) => {},
+                    }
+                }
+                None => notified.await,
+            }
+        }
+
+        // Batch tasks may have been waiting only because this task was, so wake
+        // them to check again now that it is no longer waiting
+        drop(waiting);
+        if priority == TaskPriority::Interactive {
+            self.notify.notify_waiters();
+        }
+
+        QueuePermit {
+            queue: self.clone(),
+        }
+    }
+
+    /// Perform a task with a model once a permit is acquired
+    ///
+    /// The permit is acquired using the priority and user of the task, and the
+    /// provider of the model, and held while the task is performed. The task is
+    /// streamed if `on_delta` is supplied. Composite models (see [`Model::is_composite`])
+    /// are performed without a permit since their requests acquire their own.
+    pub async fn perform(
+        self: &Arc<Self>,
+        model: &dyn Model,
+        task: &ModelTask,
+        on_delta: Option<&DeltaCallback>,
+    ) -> Result<ModelOutput> {
+        let _permit = match model.is_composite() {
+            true => None,
+            false => Some(
+                self.acquire(
+                    task.priority.unwrap_or_default(),
+                    &model.provider(),
+                    task.user.as_deref(),
+                )
+                .await,
+            ),
+        };
+
+        match on_delta {
+            Some(on_delta) => model.perform_task_streaming(task, on_delta).await,
+            None => model.perform_task(task).await,
+        }
+    }
+
+    /// Get the current status of the queue
+    pub fn status(&self) -> QueueStatus {
+        let Ok(state) = self.state.lock() else {
+            return QueueStatus {
+                running: 0,
+                waiting_interactive: 0,
+                waiting_batch: 0,
+            };
+        };
+
+        QueueStatus {
+            running: state.running,
+            waiting_interactive: waiting(&state.waiting, TaskPriority::Interactive),
+            waiting_batch: waiting(&state.waiting, TaskPriority::Batch),
+        }
+    }
+
+    /// Apply an update to the state of the queue
+    fn update(&self, func: impl FnOnce(&mut QueueState)) {
+        if let Ok(mut state) = self.state.lock() {
+            func(&mut state);
+        }
+    }
+
+    /// Try to start a task, updating the state if it can be started
+    ///
+    /// Returns `None` if the task was started, or `Some` with the time to wait
+    /// for the rate limit to allow another request (if that is what is blocking it).
+    fn try_start(
+        &self,
+        state: &mut QueueState,
+        priority: TaskPriority,
+        provider: &str,
+        user: Option<&str>,
+    ) -> Option<Option<Duration>> {
+        if state.running >= self.config.max_concurrent.max(1) {
+            return Some(None);
+        }
+
+        if priority == TaskPriority::Batch && self.interactive_preempts(state, provider) {
+            return Some(None);
+        }
+
+        let now = Instant::now();
+        let requests = state.requests.entry(provider.to_string()).or_default();
+        if let Some(wait) = rate_limit_wait(
+            requests,
+            self.config.requests_per_minute.get(provider).copied(),
+            now,
+        ) {
+            return Some(Some(wait));
+        }
+
+        if let Some(user) = user {
+            let user_requests = state.user_requests.entry(user.to_string()).or_default();
+            if let Some(wait) =
+                rate_limit_wait(user_requests, self.config.requests_per_minute_per_user, now)
+            {
+                return Some(Some(wait));
+            }
+            user_requests.push_back(now);
+        }
+
+        state
+            .requests
+            .entry(provider.to_string())
+            .or_default()
+            .push_back(now);
+        state.running += 1;
+
+        None
+    }
+
+    /// Whether a batch task for a provider should wait for waiting interactive tasks to start
+    ///
+    /// Interactive tasks which are waiting only for their user's rate limit do not compete
+    /// with the batch task (which is not counted against that user) so never take precedence.
+    /// Otherwise, interactive tasks for the same provider always do, and interactive tasks for
+    /// other providers do if they are not waiting for the rate limit of their provider: they are
+    /// waiting for a concurrency slot, which the batch task would otherwise take.
+    fn interactive_preempts(&self, state: &mut QueueState, provider: &str) -> bool {
+        let now = Instant::now();
+        let waiting = state
+            .waiting_interactive
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        waiting.into_iter().any(|(other, user)| {
+            let user_limited = user.is_some_and(|user| {
+                rate_limit_wait(
+                    state.user_requests.entry(user).or_default(),
+                    self.config.requests_per_minute_per_user,
+                    now,
+                )
+                .is_some()
+            });
+            if user_limited {
+                return false;
+            }
+
+            if other == provider {
+                return true;
+            }
+
+            let provider_limited = rate_limit_wait(
+                state.requests.entry(other.clone()).or_default(),
+                self.config.requests_per_minute.get(&other).copied(),
+                now,
+            )
+            .is_some();
+
+            !provider_limited
+        })
+    }
+}
+
+/// Get the time to wait before another request is allowed by a rate limit
+///
+/// Requests older than the rate window are removed. Returns `None` if there
+/// is no limit, or if another request is allowed now.
+fn rate_limit_wait(
+    requests: &mut VecDeque<Instant>,
+    limit: Option<u32>,
+    now: Instant,
+) -> Option<Duration> {
+    while requests
+        .front()
+        .is_some_and(|time| now.duration_since(*time) >= RATE_WINDOW)
+    {
+        requests.pop_front();
+    }
+
+    let limit = limit?;
+    (requests.len() >= limit as usize).then(|| {
+        requests
+            .front()
+            .map(|time| RATE_WINDOW.saturating_sub(now.duration_since(*time)))
+            .unwrap_or(RATE_WINDOW)
+    })
+}
+
+/// Normalize the name of a provider for matching against the config
+///
+/// Ignores case and punctuation so that e.g. `openai` in the config matches the
+/// `Openai` provider of a model, and `hugging-face` matches `Hugging Face`.
+pub(crate) fn provider_key(provider: &str) -> String {
+    provider
+        .chars()
+        .filter(|char| char.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Get the number of tasks waiting with a priority
+fn waiting(waiting: &HashMap<TaskPriority, usize>, priority: TaskPriority) -> usize {
+    waiting.get(&priority).copied().unwrap_or_default()
+}
+
+/// The global task queue
+static TASK_QUEUE: Lazy<Arc<TaskQueue>> =
+    Lazy::new(|| TaskQueue::new(models_config().queue.clone()));
+
+/// Get the global task queue
+///
+/// Created, using the `[queue]` table of the models config, when first used.
+pub fn task_queue() -> Arc<TaskQueue> {
+    TASK_QUEUE.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use common::{
+        async_trait::async_trait,
+        tokio::{
+            self,
+            task::yield_now,
+            time::{Duration, timeout},
+        },
+    };
+
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Model for Echo {
+        fn id(&self) -> String {
+            "openai/echo".into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            Ok(ModelOutput {
+                content: "echo".into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn interactive_preempts_batch() {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 1,
+            requests_per_minute: BTreeMap::new(),
+            ..Default::default()
+        });
+
+        let permit = queue.acquire(TaskPriority::Batch, "openai", None).await;
+        assert_eq!(queue.status().running, 1);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let spawn = |priority: TaskPriority| {
+            let queue = queue.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire(priority, "openai", None).await;
+                if let Ok(mut order) = order.lock() {
+                    order.push(priority);
+                }
+                yield_now().await;
+            })
+        };
+
+        let batch = spawn(TaskPriority::Batch);
+        yield_now().await;
+        let interactive = spawn(TaskPriority::Interactive);
+        while queue.status().waiting_interactive == 0 {
+            yield_now().await;
+        }
+
+        drop(permit);
+        let _ = interactive.await;
+        let _ = batch.await;
+
+        let order = order.lock().map(|order| order.clone()).unwrap_or_default();
+        assert_eq!(order, vec![TaskPriority::Interactive, TaskPriority::Batch]);
+        assert_eq!(queue.status().running, 0);
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 10,
+            requests_per_minute: BTreeMap::from([("openai".to_string(), 1)]),
+            ..Default::default()
+        });
+
+        let _first = queue
+            .acquire(TaskPriority::Interactive, "openai", None)
+            .await;
+
+        let mut state = QueueState::default();
+        state.requests = queue
+            .state
+            .lock()
+            .map(|state| state.requests.clone())
+            .unwrap_or_default();
+        assert!(matches!(
+            queue.try_start(&mut state, TaskPriority::Interactive, "openai", None),
+            Some(Some(..))
+        ));
+        assert!(
+            queue
+                .try_start(&mut state, TaskPriority::Interactive, "anthropic", None)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rate_limited_per_user() {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 10,
+            requests_per_minute_per_user: Some(1),
+            ..Default::default()
+        });
+
+        let mut state = QueueState::default();
+        let mut start =
+            |user| queue.try_start(&mut state, TaskPriority::Interactive, "openai", user);
+        assert!(start(Some("alice")).is_none());
+        assert!(matches!(start(Some("alice")), Some(Some(..))));
+        assert!(start(Some("bob")).is_none());
+        assert!(start(None).is_none());
+    }
+
+    #[tokio::test]
+    async fn perform_rate_limited_by_provider() -> Result<()> {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 10,
+            requests_per_minute: BTreeMap::from([("openai".to_string(), 1)]),
+            ..Default::default()
+        });
+        assert_eq!(Echo.provider(), "Openai");
+
+        let task = ModelTask::default();
+        let output = queue.perform(&Echo, &task, None).await?;
+        assert_eq!(output.content, "echo");
+
+        // The provider of the model matches the limit in the config so the second is held
+        let second = timeout(Duration::from_millis(50), queue.perform(&Echo, &task, None)).await;
+        assert!(second.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_tasks_not_waiting() {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 1,
+            ..Default::default()
+        });
+
+        let _permit = queue.acquire(TaskPriority::Batch, "openai", None).await;
+        let waiting = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _permit = queue
+                    .acquire(TaskPriority::Interactive, "openai", None)
+                    .await;
+            })
+        };
+        while queue.status().waiting_interactive == 0 {
+            yield_now().await;
+        }
+
+        waiting.abort();
+        let _ = waiting.await;
+        assert_eq!(queue.status().waiting_interactive, 0);
+    }
+
+    #[tokio::test]
+    async fn interactive_preempts_batch_by_provider() {
+        let queue = TaskQueue::new(QueueConfig {
+            max_concurrent: 3,
+            requests_per_minute_per_user: Some(1),
+            ..Default::default()
+        });
+        let spawn = |priority: TaskPriority, provider: &'static str, user: &'static str| {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire(priority, provider, Some(user)).await;
+                sleep(Duration::from_secs(60)).await;
+            })
+        };
+
+        // An interactive task for OpenAI which is only waiting for its user's rate limit...
+        let _first = queue
+            .acquire(TaskPriority::Interactive, "openai", Some("alice"))
+            .await;
+        let rate_limited = spawn(TaskPriority::Interactive, "openai", "alice");
+        while queue.status().waiting_interactive == 0 {
+            yield_now().await;
+        }
+
+        // ...does not hold back batch tasks, for OpenAI or other providers
+        let openai = spawn(TaskPriority::Batch, "openai", "bob");
+        let anthropic = spawn(TaskPriority::Batch, "anthropic", "dave");
+        let started = timeout(Duration::from_secs(1), async {
+            while queue.status().running < 3 {
+                yield_now().await;
+            }
+        })
+        .await;
+        assert!(started.is_ok());
+        assert_eq!(queue.status().waiting_batch, 0);
+
+        // An interactive task for Mistral, which is waiting for a concurrency slot,
+        // holds back batch tasks for Mistral and all other providers
+        let concurrency_limited = spawn(TaskPriority::Interactive, "mistral", "carol");
+        while queue.status().waiting_interactive < 2 {
+            yield_now().await;
+        }
+        let mut state = QueueState::default();
+        if let Ok(current) = queue.state.lock() {
+            state.waiting_interactive = current.waiting_interactive.clone();
+            state.requests = current.requests.clone();
+            state.user_requests = current.user_requests.clone();
+        }
+        for provider in ["mistral", "google"] {
+            assert!(queue.interactive_preempts(&mut state, provider));
+        }
+
+        for task in [rate_limited, openai, anthropic, concurrency_limited] {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::{Arc, RwLock};
+
+use common::{
+    eyre::{Result, bail, eyre},
+    futures::future::BoxFuture,
+    once_cell::sync::Lazy,
+    serde::{Deserialize, Serialize},
+    strum::Display,
+    tracing,
+};
+
+use crate::Model;
+
+/// A capability of the host that a provider may require
+///
+/// During registration, the capabilities required by a provider are checked
+/// against those in [`HOST_CAPABILITIES`] so that providers relying on features
+/// of a newer host fail early with a clear error, rather than misbehaving later.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub enum ProviderCapability {
+    /// Tasks with text messages
+    Text,
+
+    /// Tasks with image, audio or video message parts
+    Media,
+
+    /// Streaming of output deltas using `Model::perform_task_streaming`
+    Streaming,
+
+    /// Per-model API key routing using `api_key`
+    KeyRouting,
+}
+
+/// The capabilities supported by this host
+pub const HOST_CAPABILITIES: &[ProviderCapability] = &[
+    ProviderCapability::Text,
+    ProviderCapability::Media,
+    ProviderCapability::Streaming,
+    ProviderCapability::KeyRouting,
+];
+
+/// A function which lists the models of a provider
+pub type ProviderFactory =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Vec<Arc<dyn Model>>>> + Send + Sync>;
+
+/// The registration of a provider
+///
+/// Created by crates linked into the host binary and passed to [`register_provider`],
+/// usually at startup. Providers which are not part of the binary should be
+/// implemented as Stencila plugins instead, which run out of process.
+#[derive(Clone)]
+pub struct ProviderRegistration {
+    /// The name of the provider e.g. "Acme AI"
+    pub name: String,
+
+    /// The host capabilities that the provider requires
+    pub requires: Vec<ProviderCapability>,
+
+    /// The factory for the provider's models
+    pub factory: ProviderFactory,
+}
+
+impl ProviderRegistration {
+    /// Create a registration for a provider with a factory
+    ///
+    /// The registration requires only the [`ProviderCapability::Text`] capability.
+    pub fn new<F>(name: &str, factory: F) -> Self
+    where
+        F: Fn() -> BoxFuture<'static, Result<Vec<Arc<dyn Model>>>> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            requires: vec![ProviderCapability::Text],
+            factory: Arc::new(factory),
+        }
+    }
+
+    /// Add a capability that the provider requires
+    pub fn requires(mut self, capability: ProviderCapability) -> Self {
+        if !self.requires.contains(&capability) {
+            self.requires.push(capability);
+        }
+        self
+    }
+
+    /// Check that the provider is compatible with this host
+    fn handshake(&self) -> Result<()> {
+        let missing: Vec<String> = self
+            .requires
+            .iter()
+            .filter(|capability| !HOST_CAPABILITIES.contains(capability))
+            .map(|capability| capability.to_string())
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "Provider `{}` requires capabilities not supported by this host: {}",
+                self.name,
+                missing.join(", ")
+            )
+        }
+
+        Ok(())
+    }
+}
+
+/// The registered providers
+static PROVIDERS: Lazy<RwLock<Vec<ProviderRegistration>>> = Lazy::new(RwLock::default);
+
+/// Register a provider
+///
+/// Errors if the provider requires capabilities this host does not support, or if a
+/// provider with the same name is already registered.
+pub fn register_provider(registration: ProviderRegistration) -> Result<()> {
+    registration.handshake()?;
+
+    let mut providers = PROVIDERS.write().map_err(|error| eyre!("{error}"))?;
+    if providers
+        .iter()
+        .any(|provider| provider.name == registration.name)
+    {
+        bail!("Provider `{}` is already registered", registration.name)
+    }
+
+    tracing::debug!("Registering provider `{}`", registration.name);
+    providers.push(registration);
+
+    Ok(())
+}
+
+/// Get the names and factories of the registered providers
+pub fn registered_providers() -> Vec<(String, ProviderFactory)> {
+    PROVIDERS
+        .read()
+        .map(|providers| {
+            providers
+                .iter()
+                .map(|provider| (provider.name.clone(), provider.factory.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{async_trait::async_trait, tokio};
+
+    use crate::{ModelOutput, ModelTask};
+
+    use super::*;
+
+    struct TestModel;
+
+    #[async_trait]
+    impl Model for TestModel {
+        fn id(&self) -> String {
+            "acme/test".into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    fn test_provider(name: &str) -> ProviderRegistration {
+        ProviderRegistration::new(name, || {
+            Box::pin(async { Ok(vec![Arc::new(TestModel) as Arc<dyn Model>]) })
+        })
+    }
+
+    #[tokio::test]
+    async fn registers_providers() -> Result<()> {
+        register_provider(test_provider("Acme").requires(ProviderCapability::Streaming))?;
+
+        let (name, factory) = registered_providers()
+            .into_iter()
+            .find(|(name, ..)| name == "Acme")
+            .ok_or_else(|| eyre!("not registered"))?;
+        assert_eq!(name, "Acme");
+        assert_eq!(factory().await?[0].id(), "acme/test");
+
+        assert!(register_provider(test_provider("Acme")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn handshake() {
+        for capability in HOST_CAPABILITIES {
+            assert!(
+                test_provider("New")
+                    .requires(*capability)
+                    .handshake()
+                    .is_ok()
+            );
+        }
+    }
+}
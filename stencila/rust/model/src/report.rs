@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use common::{
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{LanguageCorrection, ModelWarning, ModelWarningKind, SafetyDecision};
+
+/// A report of how a task was performed
+///
+/// Aggregates the retries, substitutions and other adjustments made while
+/// performing a task (many of which are also in the warnings of the output)
+/// into counts which can be recorded in a provenance ledger and compared
+/// across runs.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct TaskReport {
+    /// The id of the model that performed the task
+    pub model: String,
+
+    /// The identifier of the end user the task was performed for, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// The id of the run of the task in the run store, if enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+
+    /// The number of times the task was retried after failing
+    pub retries: u32,
+
+    /// Models substituted for the model originally selected e.g. for a deprecated model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub substitutions: Vec<ModelSubstitution>,
+
+    /// The number of fallbacks used e.g. another model for an unsupported option
+    pub fallbacks: usize,
+
+    /// The number of attachments which were not sent to, or uploaded for, the model
+    pub skipped_attachments: usize,
+
+    /// The number of message parts, or task options, which were ignored by the model
+    pub ignored: usize,
+
+    /// The (estimated) number of prompt tokens removed by compression
+    pub truncated_tokens: usize,
+
+    /// The number of times the model was asked to continue truncated output
+    #[serde(default)]
+    pub continuations: usize,
+
+    /// The total time taken to perform the task, including retries, in milliseconds
+    pub latency_ms: u64,
+
+    /// The (estimated) number of tokens in the prompt sent to the model
+    #[serde(default)]
+    pub prompt_tokens: usize,
+
+    /// The (estimated) number of tokens in the output
+    #[serde(default)]
+    pub output_tokens: usize,
+
+    /// The number of prompt tokens read from the provider's prompt cache, if reported
+    #[serde(default)]
+    pub cached_tokens: usize,
+
+    /// The (estimated) cost of the task in US dollars, if the cost of the model is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+
+    /// Whether the output was served from the semantic cache rather than the model
+    #[serde(default)]
+    pub cached: bool,
+
+    /// The correction of the language of the output, if it was not in the expected language
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<LanguageCorrection>,
+
+    /// The decision of the safety filter, if it found disallowed content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety: Option<SafetyDecision>,
+}
+
+/// The substitution of one model for another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "common::serde")]
+pub struct ModelSubstitution {
+    /// The id of the model originally selected
+    pub from: String,
+
+    /// The id of the model used instead
+    pub to: String,
+}
+
+impl TaskReport {
+    /// Create a report for a task performed by a model, summarizing its warnings
+    pub fn new(model: &str, warnings: &[ModelWarning]) -> Self {
+        let count = |kind: ModelWarningKind| {
+            warnings
+                .iter()
+                .filter(|warning| warning.kind == kind)
+                .count()
+        };
+
+        Self {
+            model: model.into(),
+            fallbacks: count(ModelWarningKind::Fallback),
+            skipped_attachments: count(ModelWarningKind::SkippedAttachment),
+            ignored: count(ModelWarningKind::IgnoredPart) + count(ModelWarningKind::IgnoredOption),
+            ..Default::default()
+        }
+    }
+
+    /// Set the total latency of the task
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency_ms = latency.as_millis() as u64;
+        self
+    }
+
+    /// Set the token usage of the task, and its cost if the cost of the model is known
+    pub fn usage(
+        mut self,
+        prompt_tokens: usize,
+        output_tokens: usize,
+        cost_per_mtok: Option<f64>,
+    ) -> Self {
+        self.prompt_tokens = prompt_tokens;
+        self.output_tokens = output_tokens;
+        self.cost =
+            cost_per_mtok.map(|cost| (prompt_tokens + output_tokens) as f64 * cost / 1_000_000.);
+        self
+    }
+
+    /// A one line, human readable, summary of the report
+    ///
+    /// Counts which are zero are omitted e.g. `openai/gpt-4o: 1 retry, 2 skipped attachments, 3.2s`.
+    pub fn summary(&self) -> String {
+        let plural = |count: usize, noun: &str| {
+            format!("{count} {noun}{}", if count == 1 { "" } else { "s" })
+        };
+
+        let mut items = Vec::new();
+        match self.retries {
+            0 => {}
+            1 => items.push("1 retry".into()),
+            retries => items.push(format!("{retries} retries")),
+        }
+        items.extend(
+            self.substitutions
+                .iter()
+                .map(|sub| format!("substituted `{}` for `{}`", sub.to, sub.from)),
+        );
+        if self.fallbacks > 0 {
+            items.push(plural(self.fallbacks, "fallback"));
+        }
+        if self.skipped_attachments > 0 {
+            items.push(plural(self.skipped_attachments, "skipped attachment"));
+        }
+        if self.ignored > 0 {
+            items.push(format!("{} ignored", self.ignored));
+        }
+        if self.truncated_tokens > 0 {
+            items.push(format!("~{} tokens truncated", self.truncated_tokens));
+        }
+        if self.continuations > 0 {
+            items.push(plural(self.continuations, "continuation"));
+        }
+        if let Some(language) = &self.language {
+            items.push(format!(
+                "language corrected from {} to {}",
+                language.detected, language.expected
+            ));
+        }
+        if let Some(safety) = &self.safety {
+            items.push(format!("safety filter: {}", safety.action));
+        }
+        if self.cached_tokens > 0 {
+            items.push(format!("{} prompt tokens cached", self.cached_tokens));
+        }
+        if self.cached {
+            items.push("cached".into());
+        }
+        items.push(format!("{:.1}s", self.latency_ms as f64 / 1000.));
+
+        format!("{}: {}", self.model, items.iter().join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_warnings() {
+        let warnings = vec![
+            ModelWarning::skipped_attachment("a"),
+            ModelWarning::skipped_attachment("b"),
+            ModelWarning::ignored_option("c"),
+            ModelWarning::deprecated("d"),
+        ];
+        let mut report =
+            TaskReport::new("openai/gpt-4o", &warnings).latency(Duration::from_millis(3210));
+        report.retries = 1;
+        report.substitutions.push(ModelSubstitution {
+            from: "openai/gpt-4".into(),
+            to: "openai/gpt-4o".into(),
+        });
+
+        assert_eq!((report.skipped_attachments, report.ignored), (2, 1));
+        assert_eq!(
+            report.summary(),
+            "openai/gpt-4o: 1 retry, substituted `openai/gpt-4o` for `openai/gpt-4`, 2 skipped attachments, 1 ignored, 3.2s"
+        );
+
+        report.retries = 2;
+        assert!(report.summary().contains("2 retries"));
+    }
+}
@@ -0,0 +1,449 @@
+use std::{
+    collections::{HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+    sync::RwLock,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::Result,
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    tracing,
+};
+use schema::{InstructionAttachment, InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Embedder, ModelTask, cosine_similarity, derived_cache, task::message_part_to_string};
+
+/// Options for retrieving chunks of attachments to include in the prompt of a task
+///
+/// When set on a task, text attachments are split into chunks which are embedded into
+/// a local vector index. The chunks most similar to the latest user message are added
+/// to the prompt, and the attachments themselves are not sent to the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct RetrievalOptions {
+    /// The number of chunks to retrieve
+    #[serde(default = "RetrievalOptions::default_top_k")]
+    pub top_k: usize,
+
+    /// The maximum number of characters in each chunk
+    #[serde(default = "RetrievalOptions::default_chunk_size")]
+    pub chunk_size: usize,
+
+    /// The number of lines repeated at the start of each chunk from the end of the previous chunk
+    #[serde(default = "RetrievalOptions::default_chunk_overlap")]
+    pub chunk_overlap: usize,
+}
+
+impl Default for RetrievalOptions {
+    fn default() -> Self {
+        Self {
+            top_k: Self::default_top_k(),
+            chunk_size: Self::default_chunk_size(),
+            chunk_overlap: Self::default_chunk_overlap(),
+        }
+    }
+}
+
+impl RetrievalOptions {
+    fn default_top_k() -> usize {
+        4
+    }
+
+    fn default_chunk_size() -> usize {
+        1500
+    }
+
+    fn default_chunk_overlap() -> usize {
+        2
+    }
+}
+
+/// A chunk of an attachment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// The alias of the attachment the chunk is from
+    pub alias: String,
+
+    /// The path of the attachment the chunk is from
+    pub path: String,
+
+    /// The first line of the chunk (1-based)
+    pub start_line: usize,
+
+    /// The last line of the chunk (1-based, inclusive)
+    pub end_line: usize,
+
+    /// The text of the chunk
+    pub text: String,
+}
+
+impl Chunk {
+    /// A citation for the chunk e.g. `shorelines:L10-L25`
+    pub fn citation(&self) -> String {
+        format!("{}:L{}-L{}", self.alias, self.start_line, self.end_line)
+    }
+}
+
+/// An embedded chunk of an attachment, as stored in the derived cache
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "common::serde")]
+struct EmbeddedChunk {
+    start_line: usize,
+    end_line: usize,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+/// An in-memory index of embedded chunks of attachments
+///
+/// Attachments are only chunked and embedded once: adding an attachment with
+/// the same path and content as one already indexed does nothing.
+pub struct VectorIndex {
+    /// The function used to embed chunks and queries
+    embedder: Box<Embedder>,
+
+    /// The hashes of the attachments in the index
+    attachments: RwLock<HashSet<u64>>,
+
+    /// The chunks in the index and their embeddings
+    entries: RwLock<Vec<(u64, Chunk, Vec<f32>)>>,
+
+    /// The name of the embedder, used to key embeddings in the derived cache
+    embedder_name: Option<String>,
+}
+
+impl VectorIndex {
+    /// Create a new, empty, index
+    pub fn new(embedder: Box<Embedder>) -> Self {
+        Self {
+            embedder,
+            attachments: RwLock::new(HashSet::new()),
+            entries: RwLock::new(Vec::new()),
+            embedder_name: None,
+        }
+    }
+
+    /// Cache the embeddings of chunks in the [`DerivedCache`](crate::DerivedCache)
+    ///
+    /// The `embedder` name (e.g. the id of the embedding model) is part of the key
+    /// of cached embeddings so should change whenever the embedder does. Embeddings
+    /// are only cached if the derived cache is enabled.
+    pub fn cache_embeddings(mut self, embedder: &str) -> Self {
+        self.embedder_name = Some(embedder.into());
+        self
+    }
+
+    /// Add an attachment to the index
+    ///
+    /// Returns the hash of the attachment, or `None` if it does not have text content.
+    pub fn add_attachment(
+        &self,
+        attachment: &InstructionAttachment,
+        options: &RetrievalOptions,
+    ) -> Result<Option<u64>> {
+        let Some(text) = attachment_text(attachment) else {
+            return Ok(None);
+        };
+
+        let mut hasher = DefaultHasher::new();
+        attachment.file.path.hash(&mut hasher);
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self
+            .attachments
+            .read()
+            .map(|attachments| attachments.contains(&hash))
+            .unwrap_or_default()
+        {
+            return Ok(Some(hash));
+        }
+
+        let embed = || -> Result<Vec<EmbeddedChunk>> {
+            let chunks = chunk_text(&text, options.chunk_size, options.chunk_overlap);
+            tracing::debug!(
+                "Embedding {} chunks of attachment `{}`",
+                chunks.len(),
+                attachment.alias
+            );
+            chunks
+                .into_iter()
+                .map(|(start_line, end_line, text)| {
+                    let embedding = (self.embedder)(&text)?;
+                    Ok(EmbeddedChunk {
+                        start_line,
+                        end_line,
+                        text,
+                        embedding,
+                    })
+                })
+                .collect()
+        };
+        let embedded = match (&self.embedder_name, derived_cache()) {
+            (Some(name), Some(cache)) => cache.get_or_derive(
+                attachment,
+                "embeddings",
+                &(name, options.chunk_size, options.chunk_overlap),
+                embed,
+            )?,
+            _ => embed()?,
+        };
+
+        let mut entries = Vec::with_capacity(embedded.len());
+        for EmbeddedChunk {
+            start_line,
+            end_line,
+            text,
+            embedding,
+        } in embedded
+        {
+            let chunk = Chunk {
+                alias: attachment.alias.clone(),
+                path: attachment.file.path.clone(),
+                start_line,
+                end_line,
+                text,
+            };
+            entries.push((hash, chunk, embedding));
+        }
+
+        if let Ok(mut current) = self.entries.write() {
+            current.extend(entries);
+        }
+        if let Ok(mut attachments) = self.attachments.write() {
+            attachments.insert(hash);
+        }
+
+        Ok(Some(hash))
+    }
+
+    /// Search for the chunks most similar to a query
+    ///
+    /// Only chunks from attachments with one of the `hashes` are considered.
+    pub fn search(&self, query: &str, hashes: &[u64], top_k: usize) -> Result<Vec<(f32, Chunk)>> {
+        let embedding = (self.embedder)(query)?;
+
+        let Ok(entries) = self.entries.read() else {
+            return Ok(Vec::new());
+        };
+
+        Ok(entries
+            .iter()
+            .filter(|(hash, ..)| hashes.contains(hash))
+            .map(|(.., chunk, chunk_embedding)| {
+                (cosine_similarity(&embedding, chunk_embedding), chunk)
+            })
+            .sorted_by(|(a, ..), (b, ..)| b.total_cmp(a))
+            .take(top_k)
+            .map(|(similarity, chunk)| (similarity, chunk.clone()))
+            .collect())
+    }
+
+    /// Get the number of chunks in the index
+    pub fn len(&self) -> usize {
+        self.entries
+            .read()
+            .map(|entries| entries.len())
+            .unwrap_or_default()
+    }
+
+    /// Whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Retrieve chunks of the text attachments of a task and add them to its prompt
+///
+/// The latest user message is used as the query. Retrieved chunks are added in a
+/// system message, each labelled with a citation, and the indexed attachments are
+/// removed from the task so that they are not uploaded. Returns the retrieved chunks.
+pub fn retrieve_context(
+    task: &mut ModelTask,
+    options: &RetrievalOptions,
+    index: &VectorIndex,
+) -> Result<Vec<Chunk>> {
+    let Some(attachments) = task.attachments.take() else {
+        return Ok(Vec::new());
+    };
+
+    let mut hashes = Vec::new();
+    let mut remaining = Vec::new();
+    for attachment in attachments {
+        match index.add_attachment(&attachment, options)? {
+            Some(hash) => hashes.push(hash),
+            None => remaining.push(attachment),
+        }
+    }
+    task.attachments = (!remaining.is_empty()).then_some(remaining);
+
+    let query = task
+        .messages
+        .iter()
+        .rev()
+        .find(|message| matches!(message.role, None | Some(MessageRole::User)))
+        .map(|message| {
+            message
+                .parts
+                .iter()
+                .filter_map(message_part_to_string)
+                .join("\n\n")
+        })
+        .unwrap_or_default();
+
+    if hashes.is_empty() || query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let chunks = index
+        .search(&query, &hashes, options.top_k)?
+        .into_iter()
+        .map(|(.., chunk)| chunk)
+        .collect_vec();
+
+    if chunks.is_empty() {
+        return Ok(chunks);
+    }
+
+    let context = chunks
+        .iter()
+        .map(|chunk| format!("[{}]\n{}", chunk.citation(), chunk.text.trim_end()))
+        .join("\n\n");
+
+    let position = task
+        .messages
+        .iter()
+        .take_while(|message| {
+            matches!(
+                message.role,
+                Some(MessageRole::System | MessageRole::Developer)
+            )
+        })
+        .count();
+    task.messages.insert(
+        position,
+        InstructionMessage {
+            role: Some(MessageRole::System),
+            parts: vec![MessagePart::from(format!(
+                "The following excerpts were retrieved from the attached files. Cite them using the labels in square brackets.\n\n{context}"
+            ))],
+            ..Default::default()
+        },
+    );
+
+    Ok(chunks)
+}
+
+/// Get the text content of an attachment, if any
+fn attachment_text(attachment: &InstructionAttachment) -> Option<String> {
+    let content = attachment.file.content.as_ref()?;
+
+    if attachment
+        .file
+        .options
+        .transfer_encoding
+        .as_deref()
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"))
+    {
+        let bytes = BASE64.decode(content.as_bytes()).ok()?;
+        String::from_utf8(bytes).ok()
+    } else {
+        Some(content.clone())
+    }
+}
+
+/// Split text into chunks of whole lines
+///
+/// Returns the first and last line numbers (1-based) and the text of each chunk.
+/// Lines longer than `size` form a chunk of their own.
+pub fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<(usize, usize, String)> {
+    let lines = text.lines().collect_vec();
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut length = 0;
+        while end < lines.len() && (end == start || length + lines[end].len() < size) {
+            length += lines[end].len() + 1;
+            end += 1;
+        }
+
+        let text = lines[start..end].join("\n");
+        if !text.trim().is_empty() {
+            chunks.push((start + 1, end, text));
+        }
+
+        if end >= lines.len() {
+            break;
+        }
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::File;
+
+    use super::*;
+
+    /// Embed text as counts of each letter
+    fn letters(text: &str) -> Result<Vec<f32>> {
+        let mut counts = vec![0.; 26];
+        for char in text.to_lowercase().chars() {
+            if char.is_ascii_lowercase() {
+                counts[(char as u8 - b'a') as usize] += 1.;
+            }
+        }
+        Ok(counts)
+    }
+
+    #[test]
+    fn chunks() {
+        let chunks = chunk_text("aaaa\nbbbb\ncccc\ndddd", 10, 1);
+        assert_eq!(
+            chunks,
+            vec![
+                (1, 2, "aaaa\nbbbb".into()),
+                (2, 3, "bbbb\ncccc".into()),
+                (3, 4, "cccc\ndddd".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn retrieves() -> Result<()> {
+        let mut file = File::new("beaches.txt".into(), "data/beaches.txt".into());
+        file.content = Some("zzzz zzzz\nxxxx xxxx\nnarrabeen shoreline".into());
+
+        let mut task = ModelTask {
+            messages: vec![InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::from("Where is narrabeen?")],
+                ..Default::default()
+            }],
+            attachments: Some(vec![InstructionAttachment::new("beaches".into(), file)]),
+            ..Default::default()
+        };
+
+        let options = RetrievalOptions {
+            top_k: 1,
+            chunk_size: 5,
+            chunk_overlap: 0,
+        };
+        let index = VectorIndex::new(Box::new(letters));
+        let chunks = retrieve_context(&mut task, &options, &index)?;
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].citation(), "beaches:L3-L3");
+        assert!(task.attachments.is_none());
+        assert_eq!(task.messages.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{copy, create_dir_all, read_to_string, write},
+    path::{Component, Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    chrono::{TimeDelta, Utc},
+    eyre::{Result, bail},
+    serde_json::{self, Value, json},
+};
+
+use crate::{ModelOutput, ModelTask, ProvDocument, RunStore, UsageLedger};
+
+/// The name of the metadata file of a crate
+pub const RO_CRATE_METADATA: &str = "ro-crate-metadata.json";
+
+/// The version of the RO-Crate specification that crates conform to
+const RO_CRATE_SPEC: &str = "https://w3id.org/ro/crate/1.1";
+
+/// The source of the content of a file in the payload of a crate
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    /// A file to copy into the crate
+    Path(PathBuf),
+
+    /// Content to write into the crate
+    Bytes(Vec<u8>),
+}
+
+/// A Research Object Crate of a document and the models used to generate it
+///
+/// Packages the inputs of a document, the outputs of models, the attachments
+/// sent to them, and the provenance of each model invocation (the usage ledger,
+/// runs and PROV graph) into a directory with an `ro-crate-metadata.json` file
+/// describing its contents (see https://www.researchobject.org/ro-crate/1.1/).
+/// Each run of a task is described as a `CreateAction` with the model as its
+/// `instrument`, the request and attachments as its `object`, and the response
+/// and artifacts as its `result`, so that research-object tooling can trace
+/// each output back to the model and data that generated it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoCrate {
+    /// The name of the crate
+    name: String,
+
+    /// A description of the crate
+    description: String,
+
+    /// The license of the content of the crate e.g. `https://spdx.org/licenses/CC-BY-4.0`
+    license: Option<String>,
+
+    /// The contextual entities of the crate (e.g. actions, models, people), keyed by id
+    entities: BTreeMap<String, Value>,
+
+    /// The files of the payload of the crate, keyed by path within the crate
+    files: BTreeMap<String, (Source, Value)>,
+}
+
+impl RoCrate {
+    /// Create an empty crate
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            description: format!("Inputs, model outputs and provenance of {name}"),
+            license: None,
+            entities: BTreeMap::new(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Set the description of the crate
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Set the license of the content of the crate
+    pub fn license(mut self, license: &str) -> Self {
+        self.license = Some(license.into());
+        self
+    }
+
+    /// Add an input file (e.g. the document, or data it reads) to the crate
+    ///
+    /// Inputs are placed in the `inputs` directory of the crate.
+    pub fn add_input(&mut self, path: &Path) -> Result<String> {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            bail!("Invalid input path `{}`", path.display())
+        };
+        if !path.is_file() {
+            bail!("Input `{}` is not a file", path.display())
+        }
+
+        let id = format!("inputs/{name}");
+        self.add_file(
+            &id,
+            Source::Path(path.into()),
+            json!({"name": name, "description": "Input of the document"}),
+        )?;
+        Ok(id)
+    }
+
+    /// Add the usage ledger of the document to the crate
+    ///
+    /// The ledger is included as JSON and as a Markdown "compute provenance" appendix.
+    pub fn add_ledger(&mut self, ledger: &UsageLedger) -> Result<()> {
+        self.add_file(
+            "provenance/ledger.json",
+            Source::Bytes(serde_json::to_vec_pretty(ledger)?),
+            json!({
+                "name": "Usage ledger",
+                "description": "The tokens, cost, models and cache hits of each model invocation",
+                "encodingFormat": "application/json"
+            }),
+        )?;
+        self.add_file(
+            "provenance/compute-provenance.md",
+            Source::Bytes(ledger.to_markdown().into_bytes()),
+            json!({
+                "name": "Compute provenance",
+                "encodingFormat": "text/markdown"
+            }),
+        )?;
+
+        for entry in &ledger.entries {
+            self.add_model(&entry.report.model);
+        }
+
+        Ok(())
+    }
+
+    /// Add a PROV graph to the crate, as PROV-JSON and Turtle
+    pub fn add_prov(&mut self, prov: &ProvDocument) -> Result<()> {
+        self.add_file(
+            "provenance/prov.json",
+            Source::Bytes(serde_json::to_vec_pretty(&prov.to_prov_json())?),
+            json!({
+                "name": "Provenance graph (PROV-JSON)",
+                "encodingFormat": "application/json",
+                "conformsTo": {"@id": "https://www.w3.org/submissions/prov-json/"}
+            }),
+        )?;
+        self.add_file(
+            "provenance/prov.ttl",
+            Source::Bytes(prov.to_turtle().into_bytes()),
+            json!({
+                "name": "Provenance graph (PROV-O)",
+                "encodingFormat": "text/turtle",
+                "conformsTo": {"@id": "http://www.w3.org/ns/prov-o#"}
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Add the runs in a run store to the crate
+    ///
+    /// The request, response and artifacts of each run are placed in `runs/<run-id>`
+    /// along with the attachments of the task. Runs without a response (e.g. those
+    /// which failed) are skipped.
+    pub fn add_runs(&mut self, store: &RunStore) -> Result<()> {
+        for run in store.list()? {
+            let (Ok(request), Ok(response)) = (
+                read_to_string(run.dir.join("request.json")),
+                read_to_string(run.dir.join("response.json")),
+            ) else {
+                continue;
+            };
+            let task: ModelTask = serde_json::from_str(&request)?;
+            let output: ModelOutput = serde_json::from_str(&response)?;
+            let dir = format!("runs/{}", run.id);
+
+            let mut objects = vec![format!("{dir}/request.json")];
+            let mut attachments = HashMap::new();
+            self.add_file(
+                &objects[0],
+                Source::Bytes(request.into_bytes()),
+                json!({"name": "Task request", "encodingFormat": "application/json"}),
+            )?;
+
+            for attachment in task.attachments.iter().flatten() {
+                let file = &attachment.file;
+                let source = match &file.content {
+                    Some(content)
+                        if file.options.transfer_encoding.as_deref() == Some("base64") =>
+                    {
+                        Source::Bytes(BASE64.decode(content.trim())?)
+                    }
+                    Some(content) => Source::Bytes(content.as_bytes().to_vec()),
+                    None => {
+                        let path = PathBuf::from(file.path.trim_start_matches("file://"));
+                        if !path.is_file() {
+                            continue;
+                        }
+                        Source::Path(path)
+                    }
+                };
+                let name = if file.name.trim().is_empty() {
+                    &attachment.alias
+                } else {
+                    &file.name
+                };
+                let id = format!("{dir}/attachments/{name}");
+                self.add_file(
+                    &id,
+                    source,
+                    json!({
+                        "name": name,
+                        "alternateName": attachment.alias,
+                        "encodingFormat": file.media_type
+                    }),
+                )?;
+                attachments.insert(attachment.alias.as_str(), id.clone());
+                objects.push(id);
+            }
+
+            // Citations of attachments refer to their files in the crate, all
+            // others (e.g. DOIs and web pages) to their URL
+            let citations = output
+                .citations
+                .iter()
+                .map(|citation| {
+                    attachments
+                        .get(citation.target.as_str())
+                        .cloned()
+                        .unwrap_or_else(|| citation.target.clone())
+                })
+                .collect::<Vec<_>>();
+
+            let mut results = vec![format!("{dir}/response.json")];
+            self.add_file(
+                &results[0],
+                Source::Bytes(response.into_bytes()),
+                json!({
+                    "name": "Model output",
+                    "encodingFormat": "application/json",
+                    "citation": (!citations.is_empty()).then(|| references(&citations)),
+                }),
+            )?;
+            for path in run.artifacts()? {
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let id = format!("{dir}/artifacts/{name}");
+                self.add_file(&id, Source::Path(path.clone()), json!({"name": name}))?;
+                results.push(id);
+            }
+
+            let report = output.report.as_ref();
+            let ended = run.started
+                + TimeDelta::milliseconds(report.map_or(0, |report| report.latency_ms) as i64);
+            let mut action = json!({
+                "@id": format!("#run-{}", run.id),
+                "@type": "CreateAction",
+                "name": format!("Model task {}", run.id),
+                "startTime": run.started.to_rfc3339(),
+                "endTime": ended.to_rfc3339(),
+                "object": references(&objects),
+                "result": references(&results),
+            });
+            if let Some(report) = report {
+                action["instrument"] = json!({"@id": self.add_model(&report.model)});
+            }
+            if let Some(user) = report
+                .and_then(|report| report.user.as_deref())
+                .or(task.user.as_deref())
+            {
+                let id = format!("#user-{user}");
+                self.entities.insert(
+                    id.clone(),
+                    json!({"@id": id, "@type": "Person", "name": user}),
+                );
+                action["agent"] = json!({"@id": id});
+            }
+            self.entities.insert(format!("#run-{}", run.id), action);
+        }
+
+        Ok(())
+    }
+
+    /// Add a model as a `SoftwareApplication`, returning its id
+    fn add_model(&mut self, model: &str) -> String {
+        let id = format!("#model-{model}");
+        self.entities
+            .entry(id.clone())
+            .or_insert_with(|| json!({"@id": id, "@type": "SoftwareApplication", "name": model}));
+        id
+    }
+
+    /// Add a file to the payload of the crate
+    fn add_file(&mut self, id: &str, source: Source, properties: Value) -> Result<()> {
+        let path = Path::new(id);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(..)))
+        {
+            bail!("Invalid path in crate `{id}`");
+        }
+
+        let mut entity = json!({"@id": id, "@type": "File"});
+        if let (Value::Object(entity), Value::Object(properties)) = (&mut entity, properties) {
+            entity.extend(
+                properties
+                    .into_iter()
+                    .filter(|(.., value)| !value.is_null()),
+            );
+        }
+        self.files.insert(id.into(), (source, entity));
+        Ok(())
+    }
+
+    /// Get the `ro-crate-metadata.json` of the crate
+    pub fn metadata(&self) -> Value {
+        let mut dataset = json!({
+            "@id": "./",
+            "@type": "Dataset",
+            "name": self.name,
+            "description": self.description,
+            "datePublished": Utc::now().to_rfc3339(),
+            "hasPart": references(self.files.keys()),
+        });
+        if let Some(license) = &self.license {
+            dataset["license"] = json!({"@id": license});
+        }
+        let actions = self
+            .entities
+            .keys()
+            .filter(|id| id.starts_with("#run-"))
+            .collect::<Vec<_>>();
+        if !actions.is_empty() {
+            dataset["mentions"] = references(actions);
+        }
+
+        let mut graph = vec![
+            json!({
+                "@id": RO_CRATE_METADATA,
+                "@type": "CreativeWork",
+                "conformsTo": {"@id": RO_CRATE_SPEC},
+                "about": {"@id": "./"}
+            }),
+            dataset,
+        ];
+        graph.extend(self.files.values().map(|(.., entity)| entity.clone()));
+        graph.extend(self.entities.values().cloned());
+
+        json!({
+            "@context": format!("{RO_CRATE_SPEC}/context"),
+            "@graph": graph
+        })
+    }
+
+    /// Write the crate to a directory, returning the path of its metadata file
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        for (id, (source, ..)) in &self.files {
+            let path = dir.join(id);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            match source {
+                Source::Path(source) => {
+                    copy(source, &path)?;
+                }
+                Source::Bytes(bytes) => write(&path, bytes)?,
+            }
+        }
+
+        let path = dir.join(RO_CRATE_METADATA);
+        write(&path, serde_json::to_string_pretty(&self.metadata())?)?;
+        Ok(path)
+    }
+}
+
+/// Create a list of references to entities
+fn references<I: IntoIterator<Item = S>, S: AsRef<str>>(ids: I) -> Value {
+    Value::Array(
+        ids.into_iter()
+            .map(|id| json!({"@id": id.as_ref()}))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::bail, tempfile::tempdir};
+    use schema::{File, InstructionAttachment};
+
+    use crate::TaskReport;
+
+    use super::*;
+
+    #[test]
+    fn packages_document_and_runs() -> Result<()> {
+        let dir = tempdir()?;
+        let document = dir.path().join("coastsat.md");
+        write(&document, "# Shoreline change")?;
+
+        let store = RunStore::new(dir.path().join("runs"));
+        let mut file = File::new("shoreline.csv".into(), "shoreline.csv".into());
+        file.media_type = Some("text/csv".into());
+        file.content = Some("1,2\n".into());
+        let task = ModelTask {
+            attachments: Some(vec![InstructionAttachment::new("shoreline".into(), file)]),
+            user: Some("alice".into()),
+            ..Default::default()
+        };
+        let mut output = ModelOutput {
+            content: "Eroding [1] (https://example.org/narrabeen).".into(),
+            report: Some(TaskReport {
+                model: "openai/gpt-4o".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        output.link_citations(&task);
+        let run = store.create()?;
+        run.write_request(&task)?;
+        run.write_response(&output)?;
+        run.write_artifact("attestation.json", b"{}")?;
+
+        let mut ledger = UsageLedger::new();
+        ledger.record_output("ins_1", &output);
+
+        let mut crate_ =
+            RoCrate::new("CoastSat demo").license("https://spdx.org/licenses/CC-BY-4.0");
+        crate_.add_input(&document)?;
+        crate_.add_runs(&store)?;
+        crate_.add_ledger(&ledger)?;
+        crate_.add_prov(&ProvDocument::from_runs(&store)?)?;
+
+        let out = dir.path().join("crate");
+        let path = crate_.write(&out)?;
+        let metadata: Value = serde_json::from_str(&read_to_string(path)?)?;
+        assert_eq!(
+            metadata["@context"],
+            "https://w3id.org/ro/crate/1.1/context"
+        );
+
+        let Some(graph) = metadata["@graph"].as_array() else {
+            bail!("expected graph")
+        };
+        let entity = |id: &str| graph.iter().find(|entity| entity["@id"] == id);
+        let Some(action) = entity(&format!("#run-{}", run.id)) else {
+            bail!("expected action")
+        };
+        assert_eq!(action["instrument"]["@id"], "#model-openai/gpt-4o");
+        assert_eq!(action["agent"]["@id"], "#user-alice");
+        assert_eq!(action["object"].as_array().map(Vec::len), Some(2));
+        assert_eq!(action["result"].as_array().map(Vec::len), Some(2));
+
+        let attachment = format!("runs/{}/attachments/shoreline.csv", run.id);
+        assert_eq!(
+            entity(&attachment).map(|file| file["encodingFormat"].clone()),
+            Some(json!("text/csv"))
+        );
+        assert_eq!(read_to_string(out.join(&attachment))?, "1,2\n");
+        assert_eq!(
+            entity(&format!("runs/{}/response.json", run.id)).map(|file| file["citation"].clone()),
+            Some(json!([{"@id": attachment}, {"@id": "https://example.org/narrabeen"}]))
+        );
+        for path in [
+            "inputs/coastsat.md",
+            "provenance/ledger.json",
+            "provenance/compute-provenance.md",
+            "provenance/prov.json",
+            "provenance/prov.ttl",
+        ] {
+            assert!(out.join(path).exists(), "{path}");
+            assert!(entity(path).is_some(), "{path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_paths_outside_crate() {
+        let mut crate_ = RoCrate::new("test");
+        assert!(
+            crate_
+                .add_file("../escape.txt", Source::Bytes(Vec::new()), json!({}))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    env::current_dir,
+    fs::{OpenOptions, copy, create_dir_all, read_dir, remove_dir_all, write},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    chrono::{DateTime, NaiveDateTime, SubsecRound, TimeDelta, Utc},
+    eyre::{Result, bail},
+    serde::{Deserialize, Serialize},
+    serde_json, tracing,
+    uuid::Uuid,
+};
+use dirs::{DirType, STENCILA_DIR, get_app_dir};
+
+use crate::{ModelOutput, ModelOutputKind, ModelTask, models_config, redact_secrets};
+
+/// The name of the subdirectory that runs are stored in
+const RUNS_DIR: &str = "runs";
+
+/// The name of the subdirectory of a run that artifacts are written to
+const ARTIFACTS_DIR: &str = "artifacts";
+
+/// The format of the timestamp at the start of the id of each run
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.6fZ";
+
+/// Options for storing a run directory for each task performed
+///
+/// ```toml
+/// [records.runs]
+/// enabled = true
+/// max-runs = 1000
+/// max-age-days = 30
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct RunStoreConfig {
+    /// Whether to store a run directory for each task
+    pub enabled: bool,
+
+    /// The directory to store runs in
+    ///
+    /// Defaults to `runs` in the closest `.stencila` directory to the current
+    /// directory, or if there is none, to the `runs` subdirectory of the
+    /// Stencila cache directory.
+    pub dir: Option<PathBuf>,
+
+    /// The maximum number of runs to keep, the oldest being removed first
+    pub max_runs: Option<usize>,
+
+    /// The maximum age, in days, of runs to keep
+    pub max_age_days: Option<u32>,
+}
+
+/// A store of the runs of tasks performed by models
+///
+/// Each task performed (other than those served from the semantic cache) is given
+/// a directory, named by the time it was started so that runs sort chronologically,
+/// containing:
+///
+/// - `request.json`: the task, as supplied
+/// - `response.json`: the output of the task
+/// - `artifacts/`: files generated during the task e.g. images, audit records and attestations
+/// - `run.log`: a log of the steps taken to perform the task
+///
+/// Secrets are redacted from the request and response. Runs beyond the
+/// retention limits of the store are removed when a new run is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStore {
+    /// The directory runs are stored in
+    dir: PathBuf,
+
+    /// The maximum number of runs to keep
+    max_runs: Option<usize>,
+
+    /// The maximum age of runs to keep
+    max_age: Option<TimeDelta>,
+}
+
+impl RunStore {
+    /// Create a store of runs in a directory, without retention limits
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_runs: None,
+            max_age: None,
+        }
+    }
+
+    /// Set the retention limits of the store
+    pub fn retention(mut self, max_runs: Option<usize>, max_age_days: Option<u32>) -> Self {
+        self.max_runs = max_runs;
+        self.max_age = max_age_days.map(|days| TimeDelta::days(days as i64));
+        self
+    }
+
+    /// Get the directory runs are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Create a new run, removing runs beyond the retention limits
+    pub fn create(&self) -> Result<Run> {
+        let started = Utc::now().trunc_subsecs(6);
+        let id = format!(
+            "{}-{}",
+            started.format(TIMESTAMP_FORMAT),
+            &Uuid::new_v4().simple().to_string()[..8]
+        );
+        let dir = self.dir.join(&id);
+        create_dir_all(dir.join(ARTIFACTS_DIR))?;
+
+        if let Err(error) = self.prune() {
+            tracing::warn!("While pruning runs: {error}");
+        }
+
+        Ok(Run { id, started, dir })
+    }
+
+    /// Get a run by its id
+    pub fn get(&self, id: &str) -> Option<Run> {
+        let dir = self.dir.join(id);
+        if !dir.is_dir() {
+            return None;
+        }
+        Run::open(dir)
+    }
+
+    /// List the runs in the store, oldest first
+    pub fn list(&self) -> Result<Vec<Run>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut runs = Vec::new();
+        for entry in read_dir(&self.dir)?.flatten() {
+            if entry.path().is_dir()
+                && let Some(run) = Run::open(entry.path())
+            {
+                runs.push(run);
+            }
+        }
+        runs.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(runs)
+    }
+
+    /// Remove runs beyond the retention limits of the store
+    ///
+    /// Returns the number of runs removed.
+    pub fn prune(&self) -> Result<usize> {
+        let mut runs = self.list()?;
+
+        let mut expired = match self.max_age {
+            Some(max_age) => {
+                let cutoff = Utc::now() - max_age;
+                runs.iter().take_while(|run| run.started < cutoff).count()
+            }
+            None => 0,
+        };
+        if let Some(max_runs) = self.max_runs {
+            expired = expired.max(runs.len().saturating_sub(max_runs));
+        }
+
+        for run in runs.drain(..expired) {
+            remove_dir_all(&run.dir)?;
+        }
+
+        Ok(expired)
+    }
+}
+
+/// A run of a task in a [`RunStore`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    /// The id of the run (the name of its directory)
+    pub id: String,
+
+    /// The time the run was started
+    pub started: DateTime<Utc>,
+
+    /// The directory of the run
+    pub dir: PathBuf,
+}
+
+impl Run {
+    /// Open an existing run directory, returning `None` if its name is not a run id
+    fn open(dir: PathBuf) -> Option<Self> {
+        let id = dir.file_name()?.to_str()?.to_string();
+        let timestamp = id.split('-').next()?;
+        let started = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+            .ok()?
+            .and_utc();
+        Some(Self { id, started, dir })
+    }
+
+    /// Write the task of the run to `request.json`
+    pub fn write_request(&self, task: &ModelTask) -> Result<()> {
+        self.write_json("request.json", task)
+    }
+
+    /// Write the output of the run to `response.json`
+    ///
+    /// Generated content at a local path or data URL (e.g. an image) is also
+    /// written to the artifacts of the run.
+    pub fn write_response(&self, output: &ModelOutput) -> Result<()> {
+        self.write_json("response.json", output)?;
+
+        if output.kind == ModelOutputKind::Url {
+            let content = output.content.as_str();
+            if let Some((header, data)) = content
+                .strip_prefix("data:")
+                .and_then(|url| url.split_once(";base64,"))
+            {
+                let extension = header.rsplit('/').next().unwrap_or("bin");
+                self.write_artifact(&format!("output.{extension}"), &BASE64.decode(data)?)?;
+            } else {
+                let path = Path::new(content.trim_start_matches("file://"));
+                if path.is_file()
+                    && let Some(name) = path.file_name()
+                {
+                    copy(path, self.artifacts_dir().join(name))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a file to the artifacts of the run, returning its path
+    pub fn write_artifact(&self, name: &str, content: &[u8]) -> Result<PathBuf> {
+        let Some(name) = Path::new(name).file_name() else {
+            bail!("Invalid artifact name `{name}`")
+        };
+        let path = self.artifacts_dir().join(name);
+        write(&path, content)?;
+        Ok(path)
+    }
+
+    /// Get the paths of the artifacts of the run, ordered by name
+    pub fn artifacts(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = read_dir(self.artifacts_dir())?
+            .flatten()
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Append a line to the log of the run
+    ///
+    /// Errors are logged rather than returned so that logging never fails a task.
+    pub fn log(&self, message: &str) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("run.log"))
+            .and_then(|mut file| writeln!(file, "{} {message}", Utc::now().to_rfc3339()));
+        if let Err(error) = result {
+            tracing::debug!("Unable to write to log of run `{}`: {error}", self.id);
+        }
+    }
+
+    /// Get the directory of the artifacts of the run
+    fn artifacts_dir(&self) -> PathBuf {
+        self.dir.join(ARTIFACTS_DIR)
+    }
+
+    /// Write a value to a JSON file in the run directory, redacting any secrets
+    fn write_json<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        let mut value = serde_json::to_value(value)?;
+        redact_secrets(&mut value);
+        write(self.dir.join(name), serde_json::to_string_pretty(&value)?)?;
+        Ok(())
+    }
+}
+
+/// Get the run store, if enabled in the `[records.runs]` table of the models config
+pub fn run_store() -> Option<RunStore> {
+    let config = &models_config().records.runs;
+    if !config.enabled {
+        return None;
+    }
+
+    let dir = match &config.dir {
+        Some(dir) => dir.clone(),
+        None => default_dir()?,
+    };
+    Some(RunStore::new(dir).retention(config.max_runs, config.max_age_days))
+}
+
+/// Get the default directory of the run store
+fn default_dir() -> Option<PathBuf> {
+    if let Ok(cwd) = current_dir() {
+        let stencila_dir = cwd
+            .ancestors()
+            .map(|dir| dir.join(STENCILA_DIR))
+            .find(|dir| dir.is_dir());
+        if let Some(stencila_dir) = stencila_dir {
+            return Some(stencila_dir.join(RUNS_DIR));
+        }
+    }
+
+    get_app_dir(DirType::Cache, false)
+        .ok()
+        .map(|dir| dir.join(RUNS_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn creates_runs() -> Result<()> {
+        let dir = tempdir()?;
+        let store = RunStore::new(dir.path());
+        assert!(store.list()?.is_empty());
+
+        let run = store.create()?;
+        run.write_request(&ModelTask::default())?;
+        run.write_response(&ModelOutput {
+            kind: ModelOutputKind::Url,
+            content: "data:image/png;base64,iVBORw0KGgo=".into(),
+            ..Default::default()
+        })?;
+        run.write_artifact("../attestation.json", b"{}")?;
+        run.log("Selected model `openai/gpt-4o`");
+
+        assert!(run.dir.join("request.json").exists());
+        assert!(run.dir.join("response.json").exists());
+        assert!(read_to_string(run.dir.join("run.log"))?.contains("Selected model"));
+        assert_eq!(
+            run.artifacts()?
+                .iter()
+                .filter_map(|path| path.file_name()?.to_str())
+                .collect::<Vec<_>>(),
+            vec!["attestation.json", "output.png"]
+        );
+
+        assert_eq!(store.get(&run.id), Some(run.clone()));
+        assert_eq!(store.list()?, vec![run]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prunes_runs() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("20200101T000000.000000Z-00000000"))?;
+        create_dir_all(dir.path().join("not-a-run"))?;
+
+        // Runs older than the maximum age are removed
+        let store = RunStore::new(dir.path()).retention(None, Some(30));
+        let first = store.create()?;
+        assert_eq!(store.list()?, vec![first.clone()]);
+
+        // As are the oldest runs beyond the maximum number
+        let store = store.retention(Some(2), None);
+        let second = store.create()?;
+        let third = store.create()?;
+        assert_eq!(store.list()?, vec![second, third]);
+        assert!(dir.path().join("not-a-run").exists());
+
+        Ok(())
+    }
+}
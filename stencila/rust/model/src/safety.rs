@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+
+use common::{
+    eyre::Result,
+    itertools::Itertools,
+    regex::{self, Regex, RegexBuilder},
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    strum::Display,
+};
+
+use crate::{ModelOutput, ModelOutputKind, ModelOutputPart, ModelWarning};
+
+/// The text that disallowed content is replaced with when redacted
+const REDACTED: &str = "[REDACTED]";
+
+/// Patterns for common credentials
+///
+/// Used when the `credentials` option of the filter is enabled.
+const CREDENTIAL_PATTERNS: &[&str] = &[
+    // OpenAI, Anthropic, Stripe, and similar, secret keys
+    r"\b(?:sk|pk|rk)[-_][A-Za-z0-9_-]{20,}",
+    // Hugging Face, xAI and Groq tokens
+    r"\b(?:hf_|xai-|gsk_)[A-Za-z0-9_-]{20,}",
+    // AWS access key ids
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    // Google API keys
+    r"\bAIza[0-9A-Za-z_-]{35}",
+    // GitHub tokens
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}",
+    // Slack tokens
+    r"\bxox[abprs]-[A-Za-z0-9-]{10,}",
+    // Private keys
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+];
+
+/// The action taken when disallowed content is found in an output
+///
+/// Ordered by severity: when several rules match, the most severe action is taken.
+#[derive(
+    Debug, Default, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase", crate = "common::serde")]
+#[strum(serialize_all = "lowercase")]
+pub enum SafetyAction {
+    /// Leave the content unchanged but add a warning to the output
+    Annotate,
+
+    /// Replace the disallowed content with `[REDACTED]`
+    #[default]
+    Redact,
+
+    /// Remove all content, including any candidates and parts, from the output
+    Block,
+}
+
+/// A custom rule of the safety filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub struct SafetyRule {
+    /// The name of the rule, used in warnings and the task report
+    pub name: String,
+
+    /// The regular expression matching disallowed content
+    pub pattern: String,
+
+    /// The action to take for this rule
+    ///
+    /// Defaults to the `action` of the filter.
+    pub action: Option<SafetyAction>,
+}
+
+/// A filter of disallowed content in the text generated by models
+///
+/// Configured in the `[outputs.safety]` table of the `models.toml` file e.g.
+///
+/// ```toml
+/// [outputs.safety]
+/// enabled = true
+/// credentials = true
+/// hostnames = ["internal.example.org"]
+/// words = ["darn"]
+/// action = "redact"
+///
+/// [[outputs.safety.rules]]
+/// name = "project-codename"
+/// pattern = "(?i)project\\s+falcon"
+/// action = "block"
+/// ```
+///
+/// The filter is applied to outputs after they are generated so text which has
+/// already been streamed to the caller is not filtered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", crate = "common::serde")]
+pub struct SafetyFilter {
+    /// Whether the filter is enabled
+    pub enabled: bool,
+
+    /// Whether to match common credentials e.g. API keys and private keys
+    pub credentials: bool,
+
+    /// Internal hostnames (and their subdomains) to match e.g. `corp.example.org`
+    pub hostnames: Vec<String>,
+
+    /// Words (e.g. profanities) to match, ignoring case, as whole words
+    pub words: Vec<String>,
+
+    /// Custom rules
+    pub rules: Vec<SafetyRule>,
+
+    /// The action taken for matches of the built-in rules and custom rules without an action
+    pub action: SafetyAction,
+}
+
+impl Default for SafetyFilter {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            credentials: true,
+            hostnames: Vec::new(),
+            words: Vec::new(),
+            rules: Vec::new(),
+            action: SafetyAction::default(),
+        }
+    }
+}
+
+/// The decision made by the [`SafetyFilter`] for an output
+///
+/// Recorded in the [`TaskReport`](crate::TaskReport) of the output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct SafetyDecision {
+    /// The action taken
+    pub action: SafetyAction,
+
+    /// The number of matches of each rule, keyed by rule name
+    pub matches: BTreeMap<String, usize>,
+}
+
+impl SafetyFilter {
+    /// Compile the rules of the filter into regexes with their actions
+    fn compile(&self) -> Result<Vec<(String, Regex, SafetyAction)>> {
+        let mut rules = Vec::new();
+
+        if self.credentials {
+            rules.push((
+                "credentials".to_string(),
+                Regex::new(&CREDENTIAL_PATTERNS.join("|"))?,
+                self.action,
+            ));
+        }
+
+        if !self.hostnames.is_empty() {
+            let hosts = self
+                .hostnames
+                .iter()
+                .map(|host| regex::escape(host))
+                .join("|");
+            rules.push((
+                "hostnames".to_string(),
+                RegexBuilder::new(&format!(r"\b(?:[a-z0-9-]+\.)*(?:{hosts})\b"))
+                    .case_insensitive(true)
+                    .build()?,
+                self.action,
+            ));
+        }
+
+        if !self.words.is_empty() {
+            let words = self.words.iter().map(|word| regex::escape(word)).join("|");
+            rules.push((
+                "words".to_string(),
+                RegexBuilder::new(&format!(r"\b(?:{words})\b"))
+                    .case_insensitive(true)
+                    .build()?,
+                self.action,
+            ));
+        }
+
+        for rule in &self.rules {
+            rules.push((
+                rule.name.clone(),
+                Regex::new(&rule.pattern)?,
+                rule.action.unwrap_or(self.action),
+            ));
+        }
+
+        Ok(rules)
+    }
+
+    /// Apply the filter to the text of an output
+    ///
+    /// The content, candidates, and text-bearing parts (e.g. tool call arguments
+    /// and captions) of the output are checked. Returns `None` if the filter is
+    /// disabled, the output is not text, or no disallowed content was found.
+    /// Otherwise, the text is redacted or blocked as necessary (including the raw
+    /// response in the audit record, if any), a warning added to the output, and
+    /// the decision returned.
+    pub fn apply(&self, output: &mut ModelOutput) -> Result<Option<SafetyDecision>> {
+        if !self.enabled || !matches!(output.kind, ModelOutputKind::Text) {
+            return Ok(None);
+        }
+
+        let rules = self.compile()?;
+
+        let mut matches = BTreeMap::new();
+        let mut action = None;
+        for (name, regex, rule_action) in &rules {
+            let count: usize = output_texts(output)
+                .iter()
+                .map(|text| regex.find_iter(text).count())
+                .sum();
+            if count > 0 {
+                *matches.entry(name.clone()).or_default() += count;
+                action = action.max(Some(*rule_action));
+            }
+        }
+
+        let Some(action) = action else {
+            return Ok(None);
+        };
+
+        match action {
+            SafetyAction::Block => {
+                output.content.clear();
+                output.candidates.clear();
+                output.parts.clear();
+                if let Some(audit) = output.audit.as_mut() {
+                    audit.response = None;
+                }
+            }
+            SafetyAction::Redact => {
+                let redact = |texts: Vec<&mut String>| {
+                    for text in texts {
+                        for (.., regex, rule_action) in &rules {
+                            if *rule_action == SafetyAction::Redact && regex.is_match(text) {
+                                *text = regex.replace_all(text, REDACTED).to_string();
+                            }
+                        }
+                    }
+                };
+                redact(output_texts(output));
+                if let Some(response) = output
+                    .audit
+                    .as_mut()
+                    .and_then(|audit| audit.response.as_mut())
+                {
+                    let mut strings = Vec::new();
+                    json_strings(response, &mut strings);
+                    redact(strings);
+                }
+            }
+            SafetyAction::Annotate => {}
+        }
+
+        let rules = matches
+            .iter()
+            .map(|(name, count)| format!("{name} ({count})"))
+            .join(", ");
+        output.warnings.push(ModelWarning::filtered(match action {
+            SafetyAction::Annotate => format!("Output contains disallowed content: {rules}"),
+            SafetyAction::Redact => format!("Disallowed content was redacted from output: {rules}"),
+            SafetyAction::Block => format!("Output was blocked for disallowed content: {rules}"),
+        }));
+
+        Ok(Some(SafetyDecision { action, matches }))
+    }
+}
+
+/// Get mutable references to the generated text of an output
+fn output_texts(output: &mut ModelOutput) -> Vec<&mut String> {
+    let ModelOutput {
+        content,
+        candidates,
+        parts,
+        ..
+    } = output;
+
+    let mut texts = vec![content];
+    texts.extend(candidates.iter_mut());
+    for part in parts.iter_mut() {
+        match part {
+            ModelOutputPart::ToolCall(call) => texts.push(&mut call.arguments),
+            ModelOutputPart::Caption {
+                caption,
+                alt_text,
+                keywords,
+            } => {
+                texts.push(caption);
+                texts.push(alt_text);
+                texts.extend(keywords.iter_mut());
+            }
+            ModelOutputPart::SentenceAlignment { sentences, .. } => {
+                for sentence in sentences.iter_mut() {
+                    texts.push(&mut sentence.source);
+                    texts.push(&mut sentence.target);
+                }
+            }
+            ModelOutputPart::Grounding { claims } => {
+                texts.extend(claims.iter_mut().map(|claim| &mut claim.claim));
+            }
+            ModelOutputPart::CodeExecution { code, logs, .. } => {
+                texts.push(code);
+                texts.extend(logs.as_mut());
+            }
+            _ => {}
+        }
+    }
+    texts
+}
+
+/// Collect mutable references to the strings in a JSON value
+fn json_strings<'v>(value: &'v mut Value, strings: &mut Vec<&'v mut String>) {
+    match value {
+        Value::String(string) => strings.push(string),
+        Value::Array(items) => {
+            for item in items {
+                json_strings(item, strings);
+            }
+        }
+        Value::Object(object) => {
+            for item in object.values_mut() {
+                json_strings(item, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{eyre::bail, serde_json::json};
+
+    use crate::{
+        ModelAudit, ModelTask, ModelTaskKind, ModelWarningKind, ToolCall, finish_specialized_task,
+    };
+
+    use super::*;
+
+    fn output(content: &str) -> ModelOutput {
+        ModelOutput {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn disabled_by_default() -> Result<()> {
+        let mut output = output("Key sk-proj-0123456789abcdefghij");
+        assert_eq!(SafetyFilter::default().apply(&mut output)?, None);
+        assert_eq!(output.content, "Key sk-proj-0123456789abcdefghij");
+
+        Ok(())
+    }
+
+    #[test]
+    fn redacts() -> Result<()> {
+        let filter = SafetyFilter {
+            enabled: true,
+            hostnames: vec!["corp.example.org".into()],
+            words: vec!["darn".into()],
+            rules: vec![SafetyRule {
+                name: "codename".into(),
+                pattern: "(?i)project falcon".into(),
+                action: Some(SafetyAction::Annotate),
+            }],
+            ..Default::default()
+        };
+
+        let mut output = output(
+            "Darn, use sk-proj-0123456789abcdefghij on db1.corp.example.org for Project Falcon (not darned.org)",
+        );
+        let decision = filter.apply(&mut output)?;
+        assert_eq!(
+            output.content,
+            "[REDACTED], use [REDACTED] on [REDACTED] for Project Falcon (not darned.org)"
+        );
+        assert_eq!(
+            decision,
+            Some(SafetyDecision {
+                action: SafetyAction::Redact,
+                matches: BTreeMap::from([
+                    ("codename".into(), 1),
+                    ("credentials".into(), 1),
+                    ("hostnames".into(), 1),
+                    ("words".into(), 1)
+                ])
+            })
+        );
+        assert_eq!(output.warnings[0].kind, ModelWarningKind::Filtered);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_and_annotates() -> Result<()> {
+        let mut filter = SafetyFilter {
+            enabled: true,
+            words: vec!["darn".into()],
+            action: SafetyAction::Block,
+            ..Default::default()
+        };
+
+        let mut blocked = output("Darn it");
+        let decision = filter.apply(&mut blocked)?;
+        assert_eq!(blocked.content, "");
+        assert_eq!(
+            decision.map(|decision| decision.action),
+            Some(SafetyAction::Block)
+        );
+
+        filter.action = SafetyAction::Annotate;
+        let mut annotated = output("Darn it");
+        let decision = filter.apply(&mut annotated)?;
+        assert_eq!(annotated.content, "Darn it");
+        assert_eq!(
+            decision.map(|decision| decision.action),
+            Some(SafetyAction::Annotate)
+        );
+
+        let mut clean = output("All good");
+        assert_eq!(filter.apply(&mut clean)?, None);
+        assert!(clean.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redacts_candidates_parts_and_audit() -> Result<()> {
+        let filter = SafetyFilter {
+            enabled: true,
+            hostnames: vec!["corp.example.org".into()],
+            ..Default::default()
+        };
+
+        let mut output = ModelOutput {
+            content: "See the docs".into(),
+            candidates: vec!["See db.corp.example.org".into()],
+            parts: vec![
+                ModelOutputPart::ToolCall(ToolCall {
+                    id: "call_1".into(),
+                    name: "fetch".into(),
+                    arguments: r#"{"host":"db.corp.example.org"}"#.into(),
+                }),
+                ModelOutputPart::Caption {
+                    caption: "Served from corp.example.org".into(),
+                    alt_text: "A plot".into(),
+                    keywords: vec![],
+                },
+            ],
+            audit: Some(ModelAudit::new(
+                "test/model",
+                &json!({}),
+                Some(json!({"output": [{"text": "See db.corp.example.org"}]})),
+            )?),
+            ..Default::default()
+        };
+
+        let decision = filter.apply(&mut output)?;
+        assert_eq!(
+            decision.map(|decision| decision.matches),
+            Some(BTreeMap::from([("hostnames".into(), 3)]))
+        );
+        assert_eq!(output.candidates, vec!["See [REDACTED]"]);
+        let ModelOutputPart::ToolCall(call) = &output.parts[0] else {
+            bail!("expected tool call")
+        };
+        assert_eq!(call.arguments, r#"{"host":"[REDACTED]"}"#);
+        let ModelOutputPart::Caption { caption, .. } = &output.parts[1] else {
+            bail!("expected caption")
+        };
+        assert_eq!(caption, "Served from [REDACTED]");
+        assert_eq!(
+            output.audit.and_then(|audit| audit.response),
+            Some(json!({"output": [{"text": "See [REDACTED]"}]}))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_finished_specialized_output() -> Result<()> {
+        let filter = SafetyFilter {
+            enabled: true,
+            words: vec!["darn".into()],
+            action: SafetyAction::Block,
+            ..Default::default()
+        };
+
+        // The filter is applied after the output is finished so that blocking
+        // does not cause the parsing of the JSON response to fail
+        let mut output =
+            output(r#"{"caption": "A darn fine plot", "altText": "A line", "keywords": []}"#);
+        let task = ModelTask::default();
+        finish_specialized_task(ModelTaskKind::CaptionGeneration, &task, &mut output)?;
+        filter.apply(&mut output)?;
+        assert_eq!(output.content, "");
+        assert!(output.parts.is_empty());
+
+        Ok(())
+    }
+}
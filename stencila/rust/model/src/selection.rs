@@ -0,0 +1,783 @@
+use std::{
+    io::{Cursor, Read},
+    ops::Range,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::{Context, Result, bail},
+    itertools::Itertools,
+    once_cell::sync::Lazy,
+    regex::{Captures, Regex},
+};
+use schema::InstructionAttachment;
+use zip::ZipArchive;
+
+use crate::{
+    ModelTask, ModelWarning, WorkflowArtifact, attestation::content_hash, correct_media_type,
+    derived_cache, file_bytes, models_config,
+};
+
+/// The media type of XLSX spreadsheets
+const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// The maximum number of columns in a sheet of an XLSX spreadsheet (`XFD`)
+const XLSX_MAX_COLUMNS: usize = 16_384;
+
+/// The maximum number of rows in a sheet of an XLSX spreadsheet
+const XLSX_MAX_ROWS: usize = 1_048_576;
+
+/// Apply the `pages`, `byteRange` and `sheet` selectors of an attachment
+///
+/// Returns `None` if the attachment has no selectors. Otherwise, returns a copy of
+/// the attachment with its content replaced by the selected slice: the pages of
+/// a PDF (requires `qpdf`), a sheet of an XLSX spreadsheet (as CSV), or a range
+/// of bytes of any file. The `byteRange` selector is applied last, so can be
+/// combined with `sheet` to limit the size of the CSV.
+///
+/// The selectors are kept on the returned attachment (so that they can be
+/// described to the model in its preamble) so it should not be selected again.
+pub async fn select_attachment(
+    attachment: &InstructionAttachment,
+) -> Result<Option<InstructionAttachment>> {
+    let options = &attachment.options;
+    if options.pages.is_none() && options.byte_range.is_none() && options.sheet.is_none() {
+        return Ok(None);
+    }
+
+    let mut selected = attachment.clone();
+    let mut bytes = attachment_bytes(attachment)?;
+    let media_type = attachment.file.media_type.clone().unwrap_or_default();
+
+    if let Some(pages) = &options.pages {
+        if media_type != "application/pdf" {
+            bail!(
+                "Attachment `{}` has a `pages` selector but is not a PDF",
+                attachment.alias
+            )
+        }
+        let pages = parse_pages(pages)?;
+        let selected = match derived_cache() {
+            // Cached as base64 since JSON arrays of bytes are much larger
+            Some(cache) => cache
+                .get_or_derive_async(attachment, "pages", &pages, || async {
+                    Ok(BASE64.encode(select_pdf_pages(bytes.clone(), &pages).await?))
+                })
+                .await
+                .and_then(|encoded: String| Ok(BASE64.decode(encoded)?)),
+            None => select_pdf_pages(bytes, &pages).await,
+        };
+        bytes = selected.wrap_err_with(|| {
+            format!(
+                "Unable to select pages of attachment `{}`",
+                attachment.alias
+            )
+        })?;
+    }
+
+    if let Some(sheet) = &options.sheet {
+        if media_type != XLSX {
+            bail!(
+                "Attachment `{}` has a `sheet` selector but is not an XLSX spreadsheet",
+                attachment.alias
+            )
+        }
+        let max_bytes = models_config().attachments.expansion.max_total_bytes;
+        let (name, csv) = select_xlsx_sheet(&bytes, sheet, max_bytes).wrap_err_with(|| {
+            format!(
+                "Unable to select sheet of attachment `{}`",
+                attachment.alias
+            )
+        })?;
+        bytes = csv.into_bytes();
+
+        let file = &mut selected.file;
+        let stem = file
+            .name
+            .rsplit_once('.')
+            .map_or(file.name.as_str(), |(stem, ..)| stem);
+        file.name = format!("{stem}-{}.csv", slug(&name));
+        file.media_type = Some("text/csv".into());
+    }
+
+    if let Some(range) = &options.byte_range {
+        let mut range = parse_byte_range(range, bytes.len())?;
+        if is_text(selected.file.media_type.as_deref())
+            && let Ok(text) = std::str::from_utf8(&bytes)
+        {
+            range = snap_to_char_boundaries(text, range);
+        }
+        bytes = bytes[range].to_vec();
+    }
+
+    let file = &mut selected.file;
+    file.size = Some(bytes.len() as u64);
+    match String::from_utf8(bytes) {
+        Ok(text) => {
+            file.content = Some(text);
+            file.options.transfer_encoding = None;
+        }
+        Err(error) => {
+            file.content = Some(BASE64.encode(error.into_bytes()));
+            file.options.transfer_encoding = Some("base64".into());
+        }
+    }
+
+    Ok(Some(selected))
+}
+
+/// Apply the selectors of each of the attachments of a task
+///
+/// Called before a task is performed so that only the relevant slices of large
+/// attachments are uploaded to (or retrieved from for) the model. Missing or wrong
+/// media types are corrected first, so that selectors, and the policy, are applied
+/// to the actual type of each attachment. Returns a warning for each correction.
+pub async fn select_attachments(task: &mut ModelTask) -> Result<Vec<ModelWarning>> {
+    let Some(attachments) = &mut task.attachments else {
+        return Ok(Vec::new());
+    };
+
+    let mut warnings = Vec::new();
+    for attachment in attachments.iter_mut() {
+        if let Some(message) = correct_media_type(attachment) {
+            warnings.push(ModelWarning::correction(message));
+        }
+        if let Some(selected) = select_attachment(attachment).await? {
+            *attachment = selected;
+        }
+        record_provenance(attachment, &task.derived_from);
+    }
+
+    Ok(warnings)
+}
+
+/// Record the provenance of an attachment, as it will be provided to a model
+///
+/// Sets the `checksum` and `sizeBytes` of the (selected) content of the attachment,
+/// its `sourceUrl` if its file is at a URL, and its `generatedBy` if its file is one
+/// of the workflow artifacts the task was derived from. Properties which are already
+/// set are kept, as is the checksum of an attachment whose content can not be read.
+fn record_provenance(attachment: &mut InstructionAttachment, derived_from: &[WorkflowArtifact]) {
+    let path = attachment
+        .file
+        .path
+        .trim_start_matches("file://")
+        .to_string();
+
+    if attachment.options.checksum.is_none()
+        && let Ok(bytes) = attachment_bytes(attachment)
+    {
+        attachment.options.checksum = Some(content_hash(&bytes));
+        attachment.options.size_bytes = Some(bytes.len() as u64);
+    }
+
+    if path.starts_with("http://") || path.starts_with("https://") {
+        attachment.options.source_url.get_or_insert(path.clone());
+    }
+
+    if attachment.options.generated_by.is_none()
+        && let Some(artifact) = derived_from.iter().find(|artifact| artifact.path == path)
+    {
+        attachment.options.generated_by = match (&artifact.workflow, &artifact.step) {
+            (Some(workflow), Some(step)) => Some(format!("{workflow}#{step}")),
+            (workflow, step) => workflow.clone().or_else(|| step.clone()),
+        };
+    }
+}
+
+/// Get the bytes of an attachment, decoding base64 content or reading the file if necessary
+fn attachment_bytes(attachment: &InstructionAttachment) -> Result<Vec<u8>> {
+    file_bytes(&attachment.file)
+        .wrap_err_with(|| format!("Unable to read attachment `{}`", attachment.alias))
+}
+
+/// Parse a page selector e.g. `3-5,9` into a list of inclusive page ranges
+fn parse_pages(pages: &str) -> Result<Vec<(usize, usize)>> {
+    let mut ranges = Vec::new();
+    for part in pages
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (part, part),
+        };
+        let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+            bail!("Invalid page range `{part}` in `{pages}`")
+        };
+        if start == 0 || end < start {
+            bail!("Invalid page range `{part}` in `{pages}`: pages start at 1")
+        }
+        ranges.push((start, end));
+    }
+
+    if ranges.is_empty() {
+        bail!("No pages specified in `{pages}`")
+    }
+
+    Ok(ranges)
+}
+
+/// Parse a byte range selector e.g. `0-4095`, `1024-`, `-512` for content of `len` bytes
+fn parse_byte_range(range: &str, len: usize) -> Result<Range<usize>> {
+    let Some((start, end)) = range.trim().split_once('-') else {
+        bail!("Invalid byte range `{range}`: expected `start-end`")
+    };
+    let parse = |offset: &str| -> Result<Option<usize>> {
+        let offset = offset.trim();
+        if offset.is_empty() {
+            return Ok(None);
+        }
+        match offset.parse() {
+            Ok(offset) => Ok(Some(offset)),
+            Err(..) => bail!("Invalid byte range `{range}`: `{offset}` is not an offset"),
+        }
+    };
+
+    let selected = match (parse(start)?, parse(end)?) {
+        (Some(start), Some(end)) if end >= start => start..end.saturating_add(1).min(len),
+        (Some(start), None) => start..len,
+        (None, Some(last)) => len.saturating_sub(last)..len,
+        _ => bail!("Invalid byte range `{range}`"),
+    };
+
+    if selected.start >= len {
+        bail!("Byte range `{range}` starts beyond the end of the content ({len} bytes)")
+    }
+
+    Ok(selected)
+}
+
+/// Whether a media type is for text content
+fn is_text(media_type: Option<&str>) -> bool {
+    media_type.is_some_and(|media_type| {
+        media_type.starts_with("text/")
+            || media_type.ends_with("+json")
+            || media_type.ends_with("+xml")
+            || matches!(
+                media_type,
+                "application/json" | "application/xml" | "application/yaml"
+            )
+    })
+}
+
+/// Widen a byte range of text to the nearest character boundaries
+///
+/// Avoids a selected slice of text starting or ending part way through a multi-byte character.
+fn snap_to_char_boundaries(text: &str, range: Range<usize>) -> Range<usize> {
+    let mut start = range.start;
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+
+    let mut end = range.end;
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    start..end
+}
+
+/// Select pages from a PDF
+///
+/// Requires `qpdf` to be installed.
+#[cfg(feature = "qpdf")]
+async fn select_pdf_pages(pdf: Vec<u8>, pages: &[(usize, usize)]) -> Result<Vec<u8>> {
+    use common::{
+        tempfile::tempdir,
+        tokio::fs::{read, write},
+    };
+    use tools::{Qpdf, Tool};
+
+    if !Qpdf.is_installed() {
+        bail!("Selecting pages from PDFs requires `qpdf` to be installed")
+    }
+
+    let dir = tempdir()?;
+    let input = dir.path().join("input.pdf");
+    let output = dir.path().join("output.pdf");
+    write(&input, pdf).await?;
+
+    let pages = pages
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .join(",");
+
+    let result = Qpdf
+        .async_command()
+        .arg("--empty")
+        .arg("--pages")
+        .arg(&input)
+        .arg(&pages)
+        .arg("--")
+        .arg(&output)
+        .output()
+        .await
+        .wrap_err("Failed to run qpdf")?;
+    if !result.status.success() {
+        bail!(
+            "qpdf failed to select pages: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+    }
+
+    Ok(read(&output).await?)
+}
+
+/// Select pages from a PDF
+///
+/// This build of Stencila does not include the `qpdf` feature so this always errors.
+#[cfg(not(feature = "qpdf"))]
+async fn select_pdf_pages(_pdf: Vec<u8>, _pages: &[(usize, usize)]) -> Result<Vec<u8>> {
+    bail!("Selecting pages from PDFs requires Stencila to be built with the `qpdf` feature")
+}
+
+static SHEET: Lazy<Regex> = Lazy::new(|| Regex::new(r"<sheet\b[^>]*>").expect("invalid regex"));
+
+static RELATIONSHIP: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<Relationship\b[^>]*>").expect("invalid regex"));
+
+static ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).expect("invalid regex"));
+
+static SHARED_STRING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<si>(.*?)</si>").expect("invalid regex"));
+
+static TEXT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<t\b[^>]*>(.*?)</t>").expect("invalid regex"));
+
+static ROW: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<row\b([^>]*)>(.*?)</row>").expect("invalid regex"));
+
+static CELL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<c\b([^>]*?)(?:/>|>(.*?)</c>)").expect("invalid regex"));
+
+static VALUE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<v>(.*?)</v>").expect("invalid regex"));
+
+static ENTITY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"&(lt|gt|amp|quot|apos|#\d+|#x[0-9a-fA-F]+);").expect("invalid regex")
+});
+
+/// Select a sheet, by name or number, from an XLSX spreadsheet
+///
+/// Returns the name of the sheet and its cell values as CSV. Formulas are not
+/// evaluated: the value cached in the spreadsheet when it was last saved is used.
+///
+/// Errors if any of the XML entries read from the spreadsheet, or the CSV, is
+/// larger than `max_bytes`, or if a cell is beyond the limits of a sheet.
+fn select_xlsx_sheet(xlsx: &[u8], sheet: &str, max_bytes: u64) -> Result<(String, String)> {
+    let mut archive = ZipArchive::new(Cursor::new(xlsx))?;
+    let mut read_entry = |name: &str| -> Result<Option<String>> {
+        let Ok(entry) = archive.by_name(name) else {
+            return Ok(None);
+        };
+        // The declared size of the entry is not trusted so the decompressed bytes are capped
+        let mut xml = String::new();
+        if entry
+            .take(max_bytes.saturating_add(1))
+            .read_to_string(&mut xml)? as u64
+            > max_bytes
+        {
+            bail!("Spreadsheet entry `{name}` is larger than {max_bytes} bytes")
+        }
+        Ok(Some(xml))
+    };
+
+    let Some(workbook) = read_entry("xl/workbook.xml")? else {
+        bail!("Spreadsheet has no workbook")
+    };
+    let sheets = SHEET
+        .find_iter(&workbook)
+        .map(|tag| attributes(tag.as_str()))
+        .collect_vec();
+    let names = sheets
+        .iter()
+        .filter_map(|attrs| attr(attrs, "name"))
+        .collect_vec();
+
+    let index = match sheet.trim().parse::<usize>() {
+        Ok(number) if number >= 1 && number <= sheets.len() => number - 1,
+        _ => match names.iter().position(|name| name == sheet.trim()) {
+            Some(index) => index,
+            None => bail!(
+                "Spreadsheet has no sheet `{sheet}`; available sheets: {}",
+                names.iter().map(|name| format!("`{name}`")).join(", ")
+            ),
+        },
+    };
+    let name = attr(&sheets[index], "name").unwrap_or_else(|| (index + 1).to_string());
+    let Some(id) = attr(&sheets[index], "r:id") else {
+        bail!("Sheet `{name}` has no relationship id")
+    };
+
+    let rels = read_entry("xl/_rels/workbook.xml.rels")?.unwrap_or_default();
+    let Some(target) = RELATIONSHIP
+        .find_iter(&rels)
+        .map(|tag| attributes(tag.as_str()))
+        .find(|attrs| attr(attrs, "Id").as_deref() == Some(id.as_str()))
+        .and_then(|attrs| attr(&attrs, "Target"))
+    else {
+        bail!("Sheet `{name}` has no target")
+    };
+    let path = match target.strip_prefix('/') {
+        Some(path) => path.to_string(),
+        None => format!("xl/{target}"),
+    };
+
+    let shared_strings = read_entry("xl/sharedStrings.xml")?
+        .map(|xml| {
+            SHARED_STRING
+                .captures_iter(&xml)
+                .map(|si| text_content(&si[1]))
+                .collect_vec()
+        })
+        .unwrap_or_default();
+
+    let Some(xml) = read_entry(&path)? else {
+        bail!("Spreadsheet has no `{path}` for sheet `{name}`")
+    };
+
+    // The CSV is written as rows are parsed, rather than collected into a grid, so
+    // that the padding for empty rows and cells is bounded by `max_bytes`
+    let mut csv = String::new();
+    let mut rows = 0;
+    for row in ROW.captures_iter(&xml) {
+        // Use the row number, if any, so that empty rows are preserved
+        if let Some(number) = attr(&attributes(&row[1]), "r").and_then(|r| r.parse::<usize>().ok())
+        {
+            if number > XLSX_MAX_ROWS {
+                bail!("Sheet `{name}` has row {number} which is beyond the last row of a sheet")
+            }
+            if number > rows + 1 {
+                let empty = number - rows - 1;
+                csv.push_str(&"\n".repeat(if rows == 0 { empty - 1 } else { empty }));
+                rows += empty;
+            }
+        }
+        if rows > 0 {
+            csv.push('\n');
+        }
+        rows += 1;
+
+        let mut columns = 0;
+        for cell in CELL.captures_iter(&row[2]) {
+            let attrs = attributes(&cell[1]);
+            if let Some(reference) = attr(&attrs, "r") {
+                let Some(column) = column_index(&reference) else {
+                    bail!("Sheet `{name}` has cell `{reference}` with an invalid column")
+                };
+                if column > columns {
+                    csv.push_str(&",".repeat(if columns == 0 {
+                        column - 1
+                    } else {
+                        column - columns
+                    }));
+                    columns = column;
+                }
+            }
+
+            let inner = cell.get(2).map_or("", |inner| inner.as_str());
+            let value = VALUE
+                .captures(inner)
+                .map(|value| unescape(&value[1]))
+                .unwrap_or_default();
+            let value = match attr(&attrs, "t").as_deref() {
+                Some("s") => value
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| shared_strings.get(index).cloned())
+                    .unwrap_or_default(),
+                Some("inlineStr") => text_content(inner),
+                Some("b") => (if value == "1" { "TRUE" } else { "FALSE" }).to_string(),
+                _ => value,
+            };
+            if columns > 0 {
+                csv.push(',');
+            }
+            csv.push_str(&csv_field(&value));
+            columns += 1;
+
+            if csv.len() as u64 > max_bytes {
+                bail!("Sheet `{name}` is larger than {max_bytes} bytes as CSV")
+            }
+        }
+    }
+
+    Ok((name, csv))
+}
+
+/// Parse the attributes of an XML tag
+fn attributes(tag: &str) -> Vec<(String, String)> {
+    ATTRIBUTE
+        .captures_iter(tag)
+        .map(|captures| (captures[1].to_string(), unescape(&captures[2])))
+        .collect()
+}
+
+/// Get the value of an attribute
+fn attr(attributes: &[(String, String)], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|(key, ..)| key == name)
+        .map(|(.., value)| value.clone())
+}
+
+/// Get the concatenated text of the `<t>` elements within XML
+fn text_content(xml: &str) -> String {
+    TEXT.captures_iter(xml)
+        .map(|captures| unescape(&captures[1]))
+        .join("")
+}
+
+/// Unescape the XML entities in a string
+fn unescape(string: &str) -> String {
+    ENTITY
+        .replace_all(string, |captures: &Captures| match &captures[1] {
+            "lt" => "<".to_string(),
+            "gt" => ">".to_string(),
+            "amp" => "&".to_string(),
+            "quot" => "\"".to_string(),
+            "apos" => "'".to_string(),
+            code => {
+                let code = match code.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code[1..].parse().ok(),
+                };
+                code.and_then(char::from_u32)
+                    .map(String::from)
+                    .unwrap_or_default()
+            }
+        })
+        .to_string()
+}
+
+/// Get the zero-based column index of a cell reference e.g. `C7` -> 2
+///
+/// Returns `None` if the reference has no column letters or is beyond the last column of a sheet.
+fn column_index(reference: &str) -> Option<usize> {
+    let number = reference
+        .chars()
+        .take_while(|char| char.is_ascii_alphabetic())
+        .try_fold(0usize, |number, char| {
+            number
+                .checked_mul(26)?
+                .checked_add(char.to_ascii_uppercase() as usize - 'A' as usize + 1)
+        })?;
+    (1..=XLSX_MAX_COLUMNS).contains(&number).then(|| number - 1)
+}
+
+/// Quote a CSV field if necessary
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Convert a sheet name into a string suitable for a file name
+fn slug(name: &str) -> String {
+    name.chars()
+        .map(|char| {
+            if char.is_alphanumeric() {
+                char.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use common::tokio;
+    use schema::File;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    fn xlsx() -> Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let entries = [
+            (
+                "xl/workbook.xml",
+                r#"<workbook><sheets><sheet name="Summary" sheetId="1" r:id="rId1"/><sheet name="Rates &amp; Trends" sheetId="2" r:id="rId2"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships><Relationship Id="rId1" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Target="/xl/worksheets/sheet2.xml"/></Relationships>"#,
+            ),
+            (
+                "xl/sharedStrings.xml",
+                r#"<sst><si><t>transect</t></si><si><t>rate</t></si><si><r><t>narra</t></r><r><t xml:space="preserve">, 0001</t></r></si></sst>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData><row r="1"><c r="A1" t="inlineStr"><is><t>summary</t></is></c></row></sheetData></worksheet>"#,
+            ),
+            (
+                "xl/worksheets/sheet2.xml",
+                r#"<worksheet><sheetData><row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c><c r="C3"><v>-0.4</v></c><c r="D3" t="b"><v>1</v></c></row></sheetData></worksheet>"#,
+            ),
+        ];
+        for (name, content) in entries {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(content.as_bytes())?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+
+    #[test]
+    fn byte_ranges() -> Result<()> {
+        assert_eq!(parse_byte_range("0-3", 10)?, 0..4);
+        assert_eq!(parse_byte_range("5-100", 10)?, 5..10);
+        assert_eq!(parse_byte_range("4-", 10)?, 4..10);
+        assert_eq!(parse_byte_range("-3", 10)?, 7..10);
+        assert!(parse_byte_range("3-1", 10).is_err());
+        assert!(parse_byte_range("10-", 10).is_err());
+        assert!(parse_byte_range("a-b", 10).is_err());
+        assert!(parse_byte_range("5", 10).is_err());
+        assert_eq!(parse_byte_range(&format!("0-{}", usize::MAX), 10)?, 0..10);
+
+        let text = "Café près";
+        assert_eq!(snap_to_char_boundaries(text, 4..7), 3..7);
+        assert_eq!(snap_to_char_boundaries(text, 0..9), 0..10);
+        assert_eq!(&text[snap_to_char_boundaries(text, 4..9)], "é prè");
+        assert_eq!(snap_to_char_boundaries(text, 0..3), 0..3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_ranges() -> Result<()> {
+        assert_eq!(parse_pages("3-5, 9")?, vec![(3, 5), (9, 9)]);
+        assert!(parse_pages("0-2").is_err());
+        assert!(parse_pages("5-3").is_err());
+        assert!(parse_pages("first").is_err());
+        assert!(parse_pages("").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn xlsx_sheets() -> Result<()> {
+        let xlsx = xlsx()?;
+
+        let (name, csv) = select_xlsx_sheet(&xlsx, "Rates & Trends", 1024)?;
+        assert_eq!(name, "Rates & Trends");
+        assert_eq!(csv, "transect,rate\n\n\"narra, 0001\",,-0.4,TRUE");
+
+        let (name, csv) = select_xlsx_sheet(&xlsx, "1", 1024)?;
+        assert_eq!(name, "Summary");
+        assert_eq!(csv, "summary");
+
+        let Err(error) = select_xlsx_sheet(&xlsx, "Missing", 1024) else {
+            bail!("expected error for missing sheet")
+        };
+        assert!(error.to_string().contains("`Summary`, `Rates & Trends`"));
+
+        let Err(error) = select_xlsx_sheet(&xlsx, "2", 20) else {
+            bail!("expected error for sheet larger than the limit")
+        };
+        assert!(error.to_string().contains("larger than 20 bytes"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn xlsx_limits() -> Result<()> {
+        assert_eq!(column_index("A1"), Some(0));
+        assert_eq!(column_index("c7"), Some(2));
+        assert_eq!(column_index("XFD1"), Some(16_383));
+        assert_eq!(column_index("XFE1"), None);
+        assert_eq!(column_index(&"Z".repeat(100)), None);
+        assert_eq!(column_index("7"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn selects_attachments() -> Result<()> {
+        let mut file = File::new("rates.xlsx".into(), "rates.xlsx".into());
+        file.media_type = Some(XLSX.into());
+        file.content = Some(BASE64.encode(xlsx()?));
+        file.options.transfer_encoding = Some("base64".into());
+        let mut attachment = InstructionAttachment::new("rates".into(), file);
+
+        assert!(select_attachment(&attachment).await?.is_none());
+
+        attachment.options.sheet = Some("2".into());
+        attachment.options.byte_range = Some("0-12".into());
+        let Some(selected) = select_attachment(&attachment).await? else {
+            bail!("expected attachment to be selected")
+        };
+        assert_eq!(selected.file.name, "rates-rates-trends.csv");
+        assert_eq!(selected.file.media_type.as_deref(), Some("text/csv"));
+        assert_eq!(selected.file.content.as_deref(), Some("transect,rate"));
+        assert_eq!(selected.file.options.transfer_encoding, None);
+        assert_eq!(selected.file.size, Some(13));
+
+        attachment.options.pages = Some("1".into());
+        assert!(select_attachment(&attachment).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn records_provenance() -> Result<()> {
+        let dir = common::tempfile::tempdir()?;
+        let path = dir
+            .path()
+            .join("transect_time_series_tidally_corrected.csv");
+        std::fs::write(&path, "dates,1\n2020-01-01,42.1\n")?;
+
+        let mut task = ModelTask::builder()
+            .user_text("Describe the trend")
+            .attach_path(&path)
+            .derived_from_artifact(
+                WorkflowArtifact::from_path(&path)?
+                    .workflow("CoastSat")
+                    .step("tidal-correction"),
+            )
+            .attach(InstructionAttachment::new(
+                "map".into(),
+                File::new(
+                    "map.png".into(),
+                    "https://example.org/coastsat/map.png".into(),
+                ),
+            ))
+            .build()?;
+        select_attachments(&mut task).await?;
+
+        let attachments = task.attachments.unwrap_or_default();
+        let options = &attachments[0].options;
+        assert_eq!(options.checksum.as_ref().map(String::len), Some(64));
+        assert_eq!(options.size_bytes, Some(24));
+        assert_eq!(options.source_url, None);
+        assert_eq!(
+            options.generated_by.as_deref(),
+            Some("CoastSat#tidal-correction")
+        );
+
+        let options = &attachments[1].options;
+        assert_eq!(options.checksum, None);
+        assert_eq!(
+            options.source_url.as_deref(),
+            Some("https://example.org/coastsat/map.png")
+        );
+
+        Ok(())
+    }
+}
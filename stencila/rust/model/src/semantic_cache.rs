@@ -0,0 +1,264 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use common::{eyre::Result, tracing};
+
+use crate::{
+    FingerprintOptions, ModelOutput, ModelOutputKind, ModelTask, attestation::content_hash,
+    canonical_json, media::file_bytes,
+};
+
+/// A function which generates an embedding for a text
+pub type Embedder = dyn Fn(&str) -> Result<Vec<f32>> + Send + Sync;
+
+/// A cache of model outputs keyed by the semantic similarity of prompts
+///
+/// Prompts are embedded and, if the cosine similarity of a prompt to a previously
+/// cached one exceeds the `threshold`, the previously generated output is returned.
+/// Only text outputs are cached and prompts are only compared with those for
+/// tasks which are otherwise identical: the same kind, format, model, generation
+/// parameters, and attachment contents.
+pub struct SemanticCache {
+    /// The minimum cosine similarity for a cache hit
+    threshold: f32,
+
+    /// The maximum number of entries, after which the oldest are evicted
+    capacity: usize,
+
+    /// The function used to embed prompts
+    embedder: Box<Embedder>,
+
+    /// The cached entries, oldest first
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+struct Entry {
+    /// The context of the task which must match exactly for a hit
+    context: String,
+
+    /// The embedding of the prompt
+    embedding: Vec<f32>,
+
+    /// The output generated for the prompt
+    output: ModelOutput,
+}
+
+impl SemanticCache {
+    /// Create a new semantic cache
+    pub fn new(threshold: f32, capacity: usize, embedder: Box<Embedder>) -> Self {
+        Self {
+            threshold,
+            capacity,
+            embedder,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Get the cached output for the most similar prompt, if any is similar enough
+    pub fn get(&self, task: &ModelTask) -> Option<ModelOutput> {
+        let (context, prompt) = Self::key(task)?;
+        let embedding = self.embed(&prompt)?;
+
+        let entries = self.entries.lock().ok()?;
+        let (similarity, entry) = entries
+            .iter()
+            .filter(|entry| entry.context == context)
+            .map(|entry| (cosine_similarity(&embedding, &entry.embedding), entry))
+            .max_by(|(a, ..), (b, ..)| a.total_cmp(b))?;
+
+        if similarity < self.threshold {
+            return None;
+        }
+
+        tracing::debug!("Semantic cache hit with similarity {similarity:.3}");
+        Some(entry.output.clone())
+    }
+
+    /// Add the output for a task to the cache
+    pub fn insert(&self, task: &ModelTask, output: &ModelOutput) {
+        if !matches!(output.kind, ModelOutputKind::Text) || output.content.is_empty() {
+            return;
+        }
+
+        let Some((context, prompt)) = Self::key(task) else {
+            return;
+        };
+        let Some(embedding) = self.embed(&prompt) else {
+            return;
+        };
+
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        while entries.len() >= self.capacity.max(1) {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            context,
+            embedding,
+            output: output.clone(),
+        });
+    }
+
+    /// Get the number of entries in the cache
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map(|entries| entries.len())
+            .unwrap_or_default()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all entries from the cache
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    /// Get the context and prompt of a task
+    ///
+    /// The context is the canonical serialization of the task without its
+    /// messages, with the content of each attachment replaced by its hash, so
+    /// that any difference in model, generation parameters, or attachment content
+    /// prevents a hit.
+    ///
+    /// Returns `None` for tasks which should not be cached: dry runs, those
+    /// without a prompt, and those with attachments which can not be read.
+    fn key(task: &ModelTask) -> Option<(String, String)> {
+        if task.dry_run {
+            return None;
+        }
+
+        let prompt = task.prompt_as_text()?;
+
+        let mut task = task.clone();
+        task.messages.clear();
+        for attachment in task.attachments.iter_mut().flatten() {
+            let bytes = file_bytes(&attachment.file).ok()?;
+            attachment.file.content = Some(content_hash(&bytes));
+        }
+        let context = canonical_json(&task, FingerprintOptions::default()).ok()?;
+
+        Some((context, prompt))
+    }
+
+    /// Embed a prompt, logging any error
+    fn embed(&self, prompt: &str) -> Option<Vec<f32>> {
+        match (self.embedder)(prompt) {
+            Ok(embedding) => Some(embedding),
+            Err(error) => {
+                tracing::warn!("Unable to embed prompt for semantic cache: {error}");
+                None
+            }
+        }
+    }
+}
+
+/// Calculate the cosine similarity of two vectors
+///
+/// Returns zero if the vectors have different lengths or either has zero magnitude.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+
+    if norm_a == 0. || norm_b == 0. {
+        0.
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::{File, InstructionAttachment, InstructionMessage, MessagePart, MessageRole};
+
+    use super::*;
+
+    /// Embed text as counts of each letter
+    fn letters(text: &str) -> Result<Vec<f32>> {
+        let mut counts = vec![0.; 26];
+        for char in text.to_lowercase().chars() {
+            if char.is_ascii_lowercase() {
+                counts[(char as u8 - b'a') as usize] += 1.;
+            }
+        }
+        Ok(counts)
+    }
+
+    fn task(prompt: &str) -> ModelTask {
+        ModelTask {
+            messages: vec![InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::from(prompt)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hits_similar_prompts() {
+        let cache = SemanticCache::new(0.99, 2, Box::new(letters));
+        let output = ModelOutput {
+            content: "A sandy beach".into(),
+            ..Default::default()
+        };
+
+        cache.insert(&task("Describe the shoreline at Narrabeen"), &output);
+        assert_eq!(
+            cache
+                .get(&task("Describe the shoreline at Narrabeen."))
+                .map(|output| output.content),
+            Some("A sandy beach".into())
+        );
+        assert!(cache.get(&task("Summarize the tide data")).is_none());
+
+        let mut hotter = task("Describe the shoreline at Narrabeen");
+        hotter.temperature = Some(0.9);
+        assert!(cache.get(&hotter).is_none());
+
+        let mut pinned = task("Describe the shoreline at Narrabeen");
+        pinned.model_id = Some("openai/gpt-4o".into());
+        assert!(cache.get(&pinned).is_none());
+
+        cache.insert(&task("One"), &output);
+        cache.insert(&task("Two"), &output);
+        assert_eq!(cache.len(), 2);
+        assert!(
+            cache
+                .get(&task("Describe the shoreline at Narrabeen"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn distinguishes_attachment_content() {
+        let with_data = |content: &str| {
+            let mut file = File::new("transects.csv".into(), "transects.csv".into());
+            file.content = Some(content.into());
+            ModelTask {
+                attachments: Some(vec![InstructionAttachment::new("data".into(), file)]),
+                ..task("Summarize the transects")
+            }
+        };
+
+        let cache = SemanticCache::new(0.99, 2, Box::new(letters));
+        let output = ModelOutput {
+            content: "Eroding".into(),
+            ..Default::default()
+        };
+
+        cache.insert(&with_data("1,-0.4"), &output);
+        assert!(cache.get(&with_data("1,-0.4")).is_some());
+        assert!(cache.get(&with_data("1,0.6")).is_none());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, fs::read};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{ModelOutput, ModelTask, WorkflowArtifact, attestation::content_hash};
+
+/// A change to a workflow artifact since an output was derived from it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ArtifactChange {
+    /// The artifact, with the hash it had when the output was generated
+    pub artifact: WorkflowArtifact,
+
+    /// The current hash of the artifact
+    ///
+    /// `None` if the file no longer exists, or can not be read.
+    pub current_hash: Option<String>,
+}
+
+/// A node whose model-generated content is based on evidence which has since changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct StaleNode {
+    /// The id of the node
+    pub node_id: String,
+
+    /// The changes to the artifacts the content of the node was derived from
+    pub changes: Vec<ArtifactChange>,
+}
+
+/// Checks for changes to the workflow artifacts that outputs were derived from
+///
+/// Hashes each file at most once so that checking the outputs of many nodes
+/// grounded in the same data files (as is typical for a document) is cheap.
+#[derive(Debug, Default)]
+pub struct StalenessChecker {
+    /// The current hashes of files, keyed by path
+    hashes: HashMap<String, Option<String>>,
+}
+
+impl StalenessChecker {
+    /// Create a new checker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the changes to the artifacts that an output was derived from
+    ///
+    /// Returns an empty list if the output is current (or was not derived from any artifacts).
+    pub fn check(&mut self, output: &ModelOutput) -> Vec<ArtifactChange> {
+        output
+            .derived_from
+            .iter()
+            .filter_map(|artifact| {
+                let current_hash = self
+                    .hashes
+                    .entry(artifact.path.clone())
+                    .or_insert_with(|| read(&artifact.path).ok().map(|bytes| content_hash(&bytes)))
+                    .clone();
+                (current_hash.as_ref() != Some(&artifact.hash)).then(|| ArtifactChange {
+                    artifact: artifact.clone(),
+                    current_hash,
+                })
+            })
+            .collect()
+    }
+
+    /// Find the nodes whose outputs are stale
+    pub fn find_stale<'o>(
+        &mut self,
+        outputs: impl IntoIterator<Item = (&'o str, &'o ModelOutput)>,
+    ) -> Vec<StaleNode> {
+        outputs
+            .into_iter()
+            .filter_map(|(node_id, output)| {
+                let changes = self.check(output);
+                (!changes.is_empty()).then(|| StaleNode {
+                    node_id: node_id.into(),
+                    changes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Update a task to use the current versions of changed artifacts
+///
+/// Updates the hashes of the artifacts the task is derived from, and reloads the
+/// content of attachments of those files, so that regenerating the task uses the
+/// new evidence (and the new output records the version it was derived from).
+/// Errors if a changed artifact no longer exists.
+pub fn refresh_artifacts(task: &mut ModelTask, changes: &[ArtifactChange]) -> Result<()> {
+    for change in changes {
+        let path = &change.artifact.path;
+        let bytes = read(path)?;
+        let hash = content_hash(&bytes);
+
+        for artifact in task
+            .derived_from
+            .iter_mut()
+            .filter(|artifact| &artifact.path == path)
+        {
+            artifact.hash = hash.clone();
+        }
+
+        for attachment in task.attachments.iter_mut().flatten() {
+            let file = &mut attachment.file;
+            if file.path.trim_start_matches("file://") != path || file.content.is_none() {
+                continue;
+            }
+            file.size = Some(bytes.len() as u64);
+            match String::from_utf8(bytes.clone()) {
+                Ok(text) => {
+                    file.content = Some(text);
+                    file.options.transfer_encoding = None;
+                }
+                Err(..) => {
+                    file.content = Some(BASE64.encode(&bytes));
+                    file.options.transfer_encoding = Some("base64".into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn detects_and_refreshes_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let trends = dir.path().join("trends.csv");
+        let tides = dir.path().join("tides.csv");
+        write(&trends, "transect,rate\n1,-0.5\n")?;
+        write(&tides, "date,tide\n")?;
+
+        let mut task = ModelTask::builder()
+            .user_text("Which transects are eroding?")
+            .attach_path(&trends)
+            .derived_from(&trends)
+            .derived_from(&tides)
+            .build()?;
+        let output = ModelOutput {
+            derived_from: task.derived_from.clone(),
+            ..Default::default()
+        };
+
+        let mut checker = StalenessChecker::new();
+        assert!(checker.find_stale([("par_1", &output)]).is_empty());
+
+        write(&trends, "transect,rate\n1,-0.7\n")?;
+        remove_file(&tides)?;
+        let mut checker = StalenessChecker::new();
+        let stale = checker.find_stale([("par_1", &output), ("par_2", &ModelOutput::default())]);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].node_id, "par_1");
+        assert_eq!(stale[0].changes.len(), 2);
+        assert!(stale[0].changes[0].current_hash.is_some());
+        assert!(stale[0].changes[1].current_hash.is_none());
+
+        // Missing artifacts can not be refreshed
+        assert!(refresh_artifacts(&mut task, &stale[0].changes).is_err());
+
+        refresh_artifacts(&mut task, &stale[0].changes[..1])?;
+        assert!(task.derived_from[0].is_current()?);
+        let attachments = task.attachments.unwrap_or_default();
+        assert_eq!(
+            attachments[0].file.content.as_deref(),
+            Some("transect,rate\n1,-0.7\n")
+        );
+
+        Ok(())
+    }
+}
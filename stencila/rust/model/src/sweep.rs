@@ -0,0 +1,297 @@
+use common::{
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_with::skip_serializing_none,
+};
+use schema::ModelParameters;
+
+use crate::{ModelOutput, ModelTask, estimate_prompt_tokens};
+
+/// A grid of parameters to perform a task with
+///
+/// Each combination of the values is a point in the grid. Empty lists leave
+/// the corresponding parameter of the task unchanged so, for example, a grid
+/// with only temperatures sweeps the temperature of the task's model.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", crate = "common::serde")]
+pub struct ParameterGrid {
+    /// The ids (or aliases) of the models to use
+    pub models: Vec<String>,
+
+    /// The temperatures to use
+    pub temperatures: Vec<f32>,
+
+    /// The nucleus sampling probabilities to use
+    pub top_ps: Vec<f32>,
+}
+
+/// A point in a [`ParameterGrid`]
+#[skip_serializing_none]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ParameterPoint {
+    /// The id of the model, if specified
+    pub model: Option<String>,
+
+    /// The temperature, if specified
+    pub temperature: Option<f32>,
+
+    /// The nucleus sampling probability, if specified
+    pub top_p: Option<f32>,
+}
+
+impl ParameterGrid {
+    /// Get all points in the grid
+    ///
+    /// Points are ordered by model, then temperature, then top-p.
+    pub fn points(&self) -> Vec<ParameterPoint> {
+        fn values<T: Clone>(values: &[T]) -> Vec<Option<T>> {
+            if values.is_empty() {
+                vec![None]
+            } else {
+                values.iter().cloned().map(Some).collect()
+            }
+        }
+
+        values(&self.models)
+            .into_iter()
+            .cartesian_product(values(&self.temperatures))
+            .cartesian_product(values(&self.top_ps))
+            .map(|((model, temperature), top_p)| ParameterPoint {
+                model,
+                temperature,
+                top_p,
+            })
+            .collect()
+    }
+}
+
+impl ParameterPoint {
+    /// Create a copy of a task with the parameters of the point
+    ///
+    /// The model is pinned using the task's `model_id`, replacing any other selection criteria.
+    pub fn apply(&self, task: &ModelTask) -> ModelTask {
+        let mut task = task.clone();
+        if let Some(model) = &self.model {
+            task.model_id = Some(model.clone());
+            task.model_parameters = task.model_parameters.map(|pars| ModelParameters {
+                model_ids: None,
+                ..pars
+            });
+        }
+        if let Some(temperature) = self.temperature {
+            task.temperature = Some(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            task.top_p = Some(top_p);
+        }
+        task
+    }
+}
+
+/// The result of performing a task at a point in a [`ParameterGrid`]
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct SweepResult {
+    /// The parameters the task was performed with
+    pub point: ParameterPoint,
+
+    /// The output, if the task succeeded
+    pub output: Option<ModelOutput>,
+
+    /// The error, if the task failed
+    pub error: Option<String>,
+
+    /// The number of tokens in the prompt
+    pub prompt_tokens: usize,
+
+    /// The number of tokens in the output
+    pub output_tokens: usize,
+
+    /// Whether the numbers of tokens are estimates
+    ///
+    /// The numbers of tokens reported by the provider are used if available,
+    /// and otherwise they are estimated from the number of characters.
+    #[serde(default)]
+    pub tokens_estimated: bool,
+
+    /// The time taken to perform the task, in milliseconds
+    pub latency_ms: Option<u64>,
+}
+
+impl SweepResult {
+    /// Create a result for a task performed at a point
+    pub fn new(
+        point: ParameterPoint,
+        task: &ModelTask,
+        output: Result<ModelOutput, String>,
+    ) -> Self {
+        match output {
+            Ok(output) => {
+                let (prompt_tokens, output_tokens, tokens_estimated) = match output.usage {
+                    Some(usage) => (usage.prompt_tokens, usage.output_tokens, false),
+                    None => (
+                        estimate_prompt_tokens(task),
+                        output.content.chars().count().div_ceil(4),
+                        true,
+                    ),
+                };
+                Self {
+                    point,
+                    prompt_tokens,
+                    output_tokens,
+                    tokens_estimated,
+                    latency_ms: output.report.as_ref().map(|report| report.latency_ms),
+                    output: Some(output),
+                    error: None,
+                }
+            }
+            Err(error) => Self {
+                point,
+                prompt_tokens: estimate_prompt_tokens(task),
+                output_tokens: 0,
+                tokens_estimated: true,
+                latency_ms: None,
+                output: None,
+                error: Some(error),
+            },
+        }
+    }
+
+    /// The id of the model that performed the task, if it succeeded
+    fn model(&self) -> Option<String> {
+        self.output
+            .as_ref()
+            .and_then(|output| output.report.as_ref())
+            .map(|report| report.model.clone())
+            .or_else(|| self.point.model.clone())
+    }
+}
+
+/// The results of performing a task across a [`ParameterGrid`]
+///
+/// Serialize to record an experiment, or use [`Sweep::to_markdown`] for a
+/// table comparing the outputs.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(crate = "common::serde")]
+pub struct Sweep {
+    /// The results, in the order of [`ParameterGrid::points`]
+    pub results: Vec<SweepResult>,
+}
+
+impl Sweep {
+    /// Render a Markdown table comparing the results
+    ///
+    /// Outputs are shown on a single line, truncated to `max_chars` characters.
+    /// Estimated numbers of tokens are prefixed with `~`.
+    pub fn to_markdown(&self, max_chars: usize) -> String {
+        let mut table = String::from(
+            "| Model | Temperature | Top-p | Latency (ms) | Prompt tokens | Output tokens | Output |\n|---|---|---|---|---|---|---|\n",
+        );
+
+        for result in &self.results {
+            let option = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+            let output = match (&result.output, &result.error) {
+                (Some(output), ..) => {
+                    let content = output.content.split_whitespace().join(" ");
+                    if content.chars().count() > max_chars {
+                        let truncated: String = content.chars().take(max_chars).collect();
+                        format!("{truncated}…")
+                    } else {
+                        content
+                    }
+                }
+                (None, Some(error)) => format!("Error: {error}"),
+                (None, None) => String::new(),
+            };
+
+            let tokens = |count: usize| {
+                if result.tokens_estimated {
+                    format!("~{count}")
+                } else {
+                    count.to_string()
+                }
+            };
+
+            table.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} |\n",
+                option(result.model()),
+                option(result.point.temperature.map(|value| value.to_string())),
+                option(result.point.top_p.map(|value| value.to_string())),
+                option(result.latency_ms.map(|value| value.to_string())),
+                tokens(result.prompt_tokens),
+                tokens(result.output_tokens),
+                output.replace('|', "\\|")
+            ));
+        }
+
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::TokenUsage;
+
+    use super::*;
+
+    #[test]
+    fn points_and_table() {
+        let grid = ParameterGrid {
+            models: vec!["a".into(), "b".into()],
+            temperatures: vec![0.0, 1.0],
+            top_ps: vec![],
+        };
+        let points = grid.points();
+        assert_eq!(points.len(), 4);
+        assert_eq!(
+            points[1],
+            ParameterPoint {
+                model: Some("a".into()),
+                temperature: Some(1.0),
+                top_p: None,
+            }
+        );
+
+        let task = ModelTask {
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+        let applied = points[1].apply(&task);
+        assert_eq!(applied.model_id.as_deref(), Some("a"));
+        assert_eq!(applied.temperature, Some(1.0));
+        assert_eq!(applied.top_p, Some(0.9));
+
+        let sweep = Sweep {
+            results: vec![
+                SweepResult::new(
+                    points[0].clone(),
+                    &task,
+                    Ok(ModelOutput {
+                        content: "Shoreline | retreat\nof 12 m".into(),
+                        ..Default::default()
+                    }),
+                ),
+                SweepResult::new(points[1].clone(), &task, Err("timeout".into())),
+                SweepResult::new(
+                    points[2].clone(),
+                    &task,
+                    Ok(ModelOutput {
+                        content: "Accretion".into(),
+                        usage: Some(TokenUsage {
+                            prompt_tokens: 42,
+                            output_tokens: 3,
+                            cached_tokens: 0,
+                        }),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+        };
+        let table = sweep.to_markdown(15);
+        assert!(table.contains("| a | 0 | - | - | ~0 | ~7 | Shoreline \\| ret… |"));
+        assert!(table.contains("| a | 1 | - | - | ~0 | ~0 | Error: timeout |"));
+        assert!(table.contains("| b | 0 | - | - | 42 | 3 | Accretion |"));
+    }
+}
@@ -1,6 +1,8 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use common::{
     serde::{Deserialize, Serialize},
-    serde_with::skip_serializing_none,
+    serde_with::{OneOrMany, formats::PreferMany, serde_as, skip_serializing_none},
     smart_default::SmartDefault,
     strum::Display,
 };
@@ -10,6 +12,12 @@ use schema::{
     ModelParameters,
 };
 
+use crate::{
+    CandidateSelection, ChatMemory, CodeInterpreterOptions, FigureContext, PostProcessing,
+    PromptCompression, RetrievalOptions, ToolCall, ToolDefinition, TranslationOptions, Validator,
+    VideoSampling, WebSearchOptions, WorkflowArtifact,
+};
+
 /// The kind of generative model task
 #[derive(Debug, Default, Display, Clone, Copy, PartialEq, Deserialize, Serialize)]
 #[serde(crate = "common::serde")]
@@ -30,6 +38,152 @@ pub enum ModelTaskKind {
     /// - OpenAI Images: https://platform.openai.com/docs/api-reference/images
     /// - Anthropic Messages: https://docs.anthropic.com/en/api/messages
     ImageGeneration,
+
+    /// Given a figure (an image) and its context, generate a caption, alt text and keywords
+    ///
+    /// Performed as a message generation task with figure specific prompt scaffolding
+    /// (see [`FigureContext`]). The caption is returned as the content of the output and
+    /// all three as a [`ModelOutputPart::Caption`](crate::ModelOutputPart::Caption).
+    CaptionGeneration,
+
+    /// Given a description of what some code should do, generate code in a language
+    ///
+    /// Performed as a message generation task which asks for a fenced code block in
+    /// the task's `code_language`. The syntax of the code is checked, and the model
+    /// re-prompted if it is invalid. The content of the output is the code itself,
+    /// ready to be inserted into a document using [`ModelOutput::to_code_chunk`](crate::ModelOutput::to_code_chunk).
+    CodeGeneration,
+
+    /// Given a tabular dataset attachment, generate a methods-style description of it
+    ///
+    /// Summary statistics of the first CSV or TSV attachment are computed locally
+    /// and provided to the model. The generated description is the content of the
+    /// output and the statistics are a separate [`ModelOutputPart::DatasetStatistics`](crate::ModelOutputPart::DatasetStatistics)
+    /// so that verifiable numbers can be distinguished from generated prose.
+    DataDescription,
+
+    /// Given text in a user message, translate it into another language
+    ///
+    /// Performed as a message generation task using the task's [`TranslationOptions`],
+    /// including any glossary of domain terms. The translated text is the content of
+    /// the output and the alignment between source and translated sentences is a
+    /// [`ModelOutputPart::SentenceAlignment`](crate::ModelOutputPart::SentenceAlignment).
+    Translation,
+
+    /// Given a question and attachments, answer the question citing the attachments
+    ///
+    /// Performed as a message generation task which requires the model to cite the
+    /// attachment (by alias), and the page or lines, each claim came from. The citations
+    /// are parsed into a [`ModelOutputPart::Grounding`](crate::ModelOutputPart::Grounding).
+    QuestionAnswering,
+}
+
+/// The priority with which a task is queued for a provider
+///
+/// When the number of concurrent requests, or the request rate, to a provider
+/// is limited, queued interactive tasks are always started before batch tasks.
+#[derive(Debug, Default, Display, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", crate = "common::serde")]
+#[strum(serialize_all = "lowercase")]
+pub enum TaskPriority {
+    /// A task that a user is waiting on e.g. an on-demand prompt
+    #[default]
+    Interactive,
+
+    /// A background task e.g. one of many in a bulk captioning job
+    Batch,
+}
+
+/// The level of detail with which a model processes input images
+///
+/// Lower detail uses fewer tokens, and so costs less, but the model may miss
+/// fine features (e.g. of high-resolution satellite imagery).
+#[derive(Debug, Default, Display, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", crate = "common::serde")]
+#[strum(serialize_all = "lowercase")]
+pub enum ImageDetailLevel {
+    /// Let the model decide based on the size of the image
+    #[default]
+    Auto,
+
+    /// Process a low-resolution version of the image
+    Low,
+
+    /// Process the image at high resolution
+    High,
+}
+
+/// What to do when some of the attachments of a task can not be provided to the model
+#[derive(Debug, Default, Display, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+#[strum(serialize_all = "kebab-case")]
+pub enum AttachmentFailurePolicy {
+    /// Fail the task as soon as any attachment fails
+    ///
+    /// Uploads of other attachments still in progress are abandoned.
+    FailFast,
+
+    /// Warn about failed attachments and proceed with the rest
+    ///
+    /// The task only fails if every attachment needing upload fails.
+    #[default]
+    BestEffort,
+
+    /// Attempt all attachments, then fail the task if any failed
+    ///
+    /// Unlike `fail-fast`, the error lists every failed attachment.
+    RequireAll,
+}
+
+/// How generated images are returned in the output of a task
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+pub enum ImagePersistence {
+    /// Return the URL provided by the model provider
+    ///
+    /// These URLs are often transient (e.g. OpenAI image URLs expire after about an hour).
+    #[default]
+    Url,
+
+    /// Return the image as a base64 encoded data URL
+    DataUrl,
+
+    /// Write the image to a file in a directory and return the path of the file
+    Directory(PathBuf),
+}
+
+/// Options for the caching of prompts by the model provider
+///
+/// Providers which support it (currently OpenAI) cache the longest prefix of a
+/// prompt which they have seen recently, reducing the cost and latency of tasks
+/// which repeat it e.g. the same system prompt and attachments with a different
+/// question.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase", crate = "common::serde")]
+pub struct PromptCaching {
+    /// A key used by the provider to route requests sharing a prefix to the same cache
+    ///
+    /// Use the same key for tasks which share a system prompt and attachments
+    /// (e.g. all questions about one dataset) to improve the rate of cache hits.
+    pub key: Option<String>,
+
+    /// Whether to order the prompt so that static content forms a stable prefix
+    ///
+    /// When `true` (the default) attachments, and their preambles, are placed
+    /// immediately after the leading system messages rather than being appended
+    /// to the last user message, so that they form a prefix which is the same
+    /// for each turn of a conversation. Only applies if the task's `prompt_caching`
+    /// is set.
+    pub stable_prefix: bool,
+}
+
+impl Default for PromptCaching {
+    fn default() -> Self {
+        Self {
+            key: None,
+            stable_prefix: true,
+        }
+    }
 }
 
 /// A task to generate content
@@ -55,6 +209,7 @@ pub enum ModelTaskKind {
 ///
 /// Currently, the names and descriptions are based mainly on those documented for `ollama`
 /// with some additions for OpenAI.
+#[serde_as(crate = "common::serde_with")]
 #[skip_serializing_none]
 #[derive(Debug, SmartDefault, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields, crate = "common::serde")]
@@ -65,15 +220,51 @@ pub struct ModelTask {
     /// The model selection and execution options set by the user
     pub model_parameters: Option<ModelParameters>,
 
+    /// The exact id of the model to use e.g. `openai/gpt-4o-2024-08-06`
+    ///
+    /// Unlike the model id patterns in `model_parameters`, this must match the id
+    /// of a model exactly and takes precedence over all other selection criteria
+    /// (including user preferences). Used to pin the model used so that the
+    /// generated content is reproducible.
+    pub model_id: Option<String>,
+
     /// The list of input messages
     pub messages: Vec<InstructionMessage>,
 
     /// Attachments associated with the task (if any)
     pub attachments: Option<Vec<InstructionAttachment>>,
 
+    /// What to do if some attachments can not be provided to the model
+    ///
+    /// Defaults to [`AttachmentFailurePolicy::BestEffort`]. Use `require-all` for
+    /// pipelines which should not proceed without all of their evidence files.
+    pub attachment_failure_policy: Option<AttachmentFailurePolicy>,
+
+    /// A template for the text introducing each attachment to the model
+    ///
+    /// Can use the placeholders `{alias}`, `{filename}`, `{media_type}`, `{size}`
+    /// and `{description}`. Defaults to a preamble including each of these that
+    /// are known. See [`attachment_preamble`](crate::attachment_preamble).
+    pub attachment_preamble: Option<String>,
+
     /// The kind of model task
     pub kind: ModelTaskKind,
 
+    /// The context of the figure to caption
+    ///
+    /// Used by [`ModelTaskKind::CaptionGeneration`] tasks.
+    pub figure_context: Option<FigureContext>,
+
+    /// The programming language of the code to generate e.g. `python`
+    ///
+    /// Required by [`ModelTaskKind::CodeGeneration`] tasks.
+    pub code_language: Option<String>,
+
+    /// The languages, and glossary, for translation
+    ///
+    /// Required by [`ModelTaskKind::Translation`] tasks.
+    pub translation: Option<TranslationOptions>,
+
     /// The desired format of the generated content
     pub format: Format,
 
@@ -137,6 +328,22 @@ pub struct ModelTask {
     /// Supported by Ollama, OpenAI Chat.
     pub repeat_penalty: Option<f32>,
 
+    /// Sets how strongly to penalize tokens based on how frequently they have appeared so far.
+    ///
+    /// A value between -2.0 and 2.0. Positive values decrease the likelihood of the model
+    /// repeating the same phrases verbatim.
+    ///
+    /// Supported by OpenAI Chat.
+    pub frequency_penalty: Option<f32>,
+
+    /// Modifies the likelihood of specified tokens appearing in the generated text.
+    ///
+    /// A map of token ids (as used by the model's tokenizer) to a bias between -100 and 100
+    /// which is added to the logits generated by the model prior to sampling.
+    ///
+    /// Supported by OpenAI Chat.
+    pub logit_bias: Option<BTreeMap<String, i32>>,
+
     /// The temperature of the model.
     ///
     /// Increasing the temperature will make the model answer more creatively.
@@ -149,8 +356,24 @@ pub struct ModelTask {
 
     /// Sets the stop sequences to use.
     ///
-    /// When this pattern is encountered the LLM will stop generating text and return.
-    pub stop: Option<String>,
+    /// When any of these patterns is encountered the LLM will stop generating text and return.
+    /// May be deserialized from a single string. Providers limit the number of stop
+    /// sequences (e.g. a maximum of 4 for OpenAI).
+    #[serde_as(as = "Option<OneOrMany<_, PreferMany>>")]
+    pub stop: Option<Vec<String>>,
+
+    /// The number of candidate outputs to generate.
+    ///
+    /// If greater than one, one of the candidates is selected using the
+    /// `candidate_selection` strategy and all candidates are recorded in the output.
+    ///
+    /// Supported by OpenAI Chat.
+    pub candidates: Option<u8>,
+
+    /// The strategy used to select one of the candidate outputs.
+    ///
+    /// Defaults to selecting the first candidate.
+    pub candidate_selection: Option<CandidateSelection>,
 
     /// The maximum number of tokens to generate.
     ///
@@ -195,12 +418,153 @@ pub struct ModelTask {
     /// Supported by `openai/dall-e-3`.
     pub image_style: Option<String>,
 
+    /// The number of images to generate
+    ///
+    /// Defaults to one. All generated images are returned as the candidates of the output.
+    pub image_count: Option<u8>,
+
+    /// Whether to assemble multiple generated images into a contact sheet grid
+    ///
+    /// The grid is returned as a part of the output for quick visual selection.
+    pub image_grid: Option<bool>,
+
+    /// How generated images are returned
+    ///
+    /// Defaults to the URL provided by the model provider. Use `data-url`, or a
+    /// directory, so that documents referencing the image do not break when that
+    /// URL expires.
+    pub image_persistence: Option<ImagePersistence>,
+
+    /// The level of detail with which input images are processed
+    ///
+    /// Applies to images in messages and image attachments.
+    /// Supported by OpenAI. Defaults to `auto`.
+    pub image_detail: Option<ImageDetailLevel>,
+
+    /// Options for sampling frames from videos
+    ///
+    /// Used when videos are sent to models which accept images but not video.
+    pub video_sampling: Option<VideoSampling>,
+
+    /// Options for compressing the prompt if it is too long
+    ///
+    /// If set, older messages are summarized before the task is performed.
+    pub compression: Option<PromptCompression>,
+
+    /// The memory of facts learned so far in the chat session
+    ///
+    /// If set, the facts are added to the system prompt and, after the task is
+    /// performed, updated and returned in the `memory` of the output.
+    pub memory: Option<ChatMemory>,
+
+    /// Options for retrieving excerpts of attachments to include in the prompt
+    ///
+    /// If set, text attachments are indexed locally and only the excerpts most
+    /// relevant to the latest user message are sent to the model.
+    pub retrieval: Option<RetrievalOptions>,
+
+    /// Validators run on the generated content
+    ///
+    /// If the content fails validation, the model is re-prompted with the reasons
+    /// for the failure. Not serialized because validators may be closures.
+    #[serde(skip)]
+    pub validators: Vec<Validator>,
+
+    /// The maximum number of times to re-prompt the model if its output fails validation
+    ///
+    /// Defaults to 2.
+    pub validation_retries: Option<u8>,
+
+    /// The language that the output is expected to be in
+    ///
+    /// An ISO 639-1 code (e.g. `fr`) or English name (e.g. `French`). If the language
+    /// detected in the output differs, the model is re-prompted to answer in this language.
+    pub expected_language: Option<String>,
+
+    /// The post-processing of the Markdown generated by the model
+    ///
+    /// Overrides the `[outputs.post-processing]` table of the models config e.g. to set
+    /// the heading level that the content is being inserted under.
+    pub post_processing: Option<PostProcessing>,
+
+    /// The maximum number of times to ask the model to continue if its output is truncated
+    ///
+    /// If the model stops because it reached the maximum number of output tokens,
+    /// it is asked to continue where it left off, and the segments stitched together.
+    /// Defaults to no continuation.
+    pub auto_continue: Option<usize>,
+
+    /// Tools that the model may call
+    ///
+    /// Calls of the tools are returned in the parts of the output. Usually set, and
+    /// the calls dispatched, by an agent loop.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+
+    /// The tool calls made by the model in previous turns of the conversation
+    ///
+    /// Providers replay the call that each `ToolResult` message part is the result of
+    /// (matched on its `call_id`) before the result.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+
+    /// Options for the caching of the prompt by the provider
+    ///
+    /// When `None`, attachments are appended to the last user message. When set,
+    /// the prompt defaults to a stable prefix ordering.
+    pub prompt_caching: Option<PromptCaching>,
+
+    /// Outputs of upstream workflows that the task is grounded in
+    ///
+    /// Copied to the output so that the generated content can be linked to
+    /// the exact versions of the data files it was derived from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub derived_from: Vec<WorkflowArtifact>,
+
+    /// Options for the web search tool hosted by the provider
+    ///
+    /// If set, models which support it can search the web while performing the task.
+    pub web_search: Option<WebSearchOptions>,
+
+    /// Options for the code interpreter tool hosted by the provider
+    ///
+    /// If set, models which support it can run code while performing the task.
+    pub code_interpreter: Option<CodeInterpreterOptions>,
+
+    /// The voice used for generated audio.
+    ///
+    /// Supported by `openai/gpt-4o-audio-*` models. Defaults to `alloy`.
+    pub audio_voice: Option<String>,
+
+    /// The format of generated audio. Must be one of `wav`, `mp3`, `flac`, `opus`, or `pcm16`.
+    ///
+    /// Supported by `openai/gpt-4o-audio-*` models. Defaults to `mp3`.
+    pub audio_format: Option<String>,
+
     /// Prepare the task but do not actually generate content
     ///
     /// Model implementations should respect this option by returning an empty `ModelOutput`
     /// at the last possible moment before generation (usually just before an API request is made).
     #[serde(default)]
     pub dry_run: bool,
+
+    /// An identifier of the end user that the task is performed for
+    ///
+    /// Passed to providers which support it (e.g. as the `user` field of OpenAI
+    /// requests) for abuse monitoring, and used for per-user rate limits, policy
+    /// quotas and usage attribution. Should not contain personal information
+    /// (e.g. use a hash of an email address rather than the address itself).
+    pub user: Option<String>,
+
+    /// The priority of the task when queued for a provider
+    ///
+    /// Defaults to [`TaskPriority::Interactive`].
+    pub priority: Option<TaskPriority>,
+
+    /// Whether to record the exact request and response for auditing
+    ///
+    /// Overrides the `[records.audit]` table of the models config. See [`ModelAudit`](crate::ModelAudit).
+    pub audit: Option<bool>,
 }
 
 impl ModelTask {
@@ -236,7 +600,7 @@ impl ModelTask {
     }
 }
 
-fn messages_to_prompt_string(messages: &[InstructionMessage]) -> Option<String> {
+pub(crate) fn messages_to_prompt_string(messages: &[InstructionMessage]) -> Option<String> {
     let mut sections: Vec<String> = Vec::new();
 
     for message in messages {
@@ -264,7 +628,7 @@ fn messages_to_prompt_string(messages: &[InstructionMessage]) -> Option<String>
     }
 }
 
-fn message_part_to_string(part: &MessagePart) -> Option<String> {
+pub(crate) fn message_part_to_string(part: &MessagePart) -> Option<String> {
     match part {
         MessagePart::Text(text) => {
             let value = text.value.string.trim();
@@ -294,12 +658,23 @@ fn message_part_to_string(part: &MessagePart) -> Option<String> {
                 Some(format!("(video: {url})"))
             }
         }
+        MessagePart::ToolResult(result) => {
+            let content = result.content.trim();
+            if content.is_empty() {
+                None
+            } else {
+                let name = result.name.as_deref().unwrap_or("tool");
+                Some(format!("({name} result: {content})"))
+            }
+        }
+        MessagePart::File(file) => Some(format!("(file: {})", file.path)),
     }
 }
 
 fn message_role_label(role: MessageRole) -> &'static str {
     match role {
         MessageRole::System => "System",
+        MessageRole::Developer => "Developer",
         MessageRole::User => "User",
         MessageRole::Model => "Assistant",
     }
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use common::{
+    eyre::{OptionExt, Result},
+    once_cell::sync::Lazy,
+    regex::Regex,
+    tracing,
+};
+use dirs::{DirType, get_app_dir};
+use schema::InstructionMessage;
+
+use crate::task::message_part_to_string;
+
+/// The number of tokens added for each message by chat formats
+///
+/// See https://github.com/openai/openai-cookbook/blob/main/examples/How_to_count_tokens_with_tiktoken.ipynb
+const TOKENS_PER_MESSAGE: usize = 3;
+
+/// The number of tokens used to prime the reply to a list of messages
+const TOKENS_PER_REPLY: usize = 3;
+
+/// A byte pair encoding used by a family of models
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenEncoding {
+    /// Used by GPT-4, GPT-3.5 Turbo, and OpenAI embedding models
+    Cl100kBase,
+
+    /// Used by GPT-4o, GPT-4.1, GPT-5 and o-series models
+    O200kBase,
+}
+
+impl TokenEncoding {
+    /// The name of the encoding (and its BPE file)
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenEncoding::Cl100kBase => "cl100k_base",
+            TokenEncoding::O200kBase => "o200k_base",
+        }
+    }
+
+    /// The path of the BPE file for the encoding
+    ///
+    /// BPE files (in the `.tiktoken` format published by OpenAI) are read from the
+    /// `tiktoken` subdirectory of the Stencila models directory.
+    pub fn path(&self) -> Result<PathBuf> {
+        Ok(get_app_dir(DirType::Models, false)?
+            .join("tiktoken")
+            .join([self.name(), ".tiktoken"].concat()))
+    }
+
+    /// The regex used to split text into pieces before byte pair encoding
+    ///
+    /// The original patterns use a `\s+(?!\S)` alternative which is not supported
+    /// by the `regex` crate so is emulated in [`Bpe::pieces`].
+    fn pattern(&self) -> &'static str {
+        match self {
+            TokenEncoding::Cl100kBase => {
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+"
+            }
+            TokenEncoding::O200kBase => {
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+"
+            }
+        }
+    }
+}
+
+/// A count of the tokens in a list of messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenCount {
+    /// The number of tokens
+    pub tokens: usize,
+
+    /// Whether the count is an estimate
+    ///
+    /// Counts are estimated, from the number of characters, if the model's
+    /// byte pair encoding is not known or its BPE file is not available locally.
+    pub estimated: bool,
+}
+
+/// A byte pair encoder
+pub struct Bpe {
+    /// The rank of each token
+    ranks: HashMap<Vec<u8>, u32>,
+
+    /// The regex used to split text into pieces
+    regex: Regex,
+}
+
+impl Bpe {
+    /// Create a byte pair encoder from token ranks
+    pub fn new(encoding: TokenEncoding, ranks: HashMap<Vec<u8>, u32>) -> Result<Self> {
+        Ok(Self {
+            ranks,
+            regex: Regex::new(encoding.pattern())?,
+        })
+    }
+
+    /// Load a byte pair encoder from a `.tiktoken` file
+    ///
+    /// Each line of the file has a base64 encoded token followed by its rank.
+    pub fn load(encoding: TokenEncoding) -> Result<Self> {
+        let content = read_to_string(encoding.path()?)?;
+
+        let mut ranks = HashMap::new();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let (token, rank) = line.split_once(' ').ok_or_eyre("invalid BPE line")?;
+            ranks.insert(BASE64.decode(token)?, rank.parse()?);
+        }
+
+        Self::new(encoding, ranks)
+    }
+
+    /// Count the number of tokens in some text
+    pub fn count(&self, text: &str) -> usize {
+        self.pieces(text)
+            .into_iter()
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+
+    /// Split text into pieces
+    ///
+    /// Emulates the `\s+(?!\S)` alternative by leaving the last whitespace
+    /// character of a run of whitespace followed by non-whitespace to be
+    /// matched as part of the next piece.
+    fn pieces<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while let Some(found) = self.regex.find_at(text, start) {
+            let mut end = found.end();
+            let piece = found.as_str();
+
+            if piece.chars().all(char::is_whitespace)
+                && text[end..]
+                    .chars()
+                    .next()
+                    .is_some_and(|next| !next.is_whitespace())
+                && let Some((last, ..)) = piece.char_indices().next_back()
+                && last > 0
+            {
+                end = found.start() + last;
+            }
+
+            pieces.push(&text[found.start()..end]);
+            start = end;
+        }
+        pieces
+    }
+
+    /// Count the number of tokens in a piece by merging byte pairs in order of rank
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+
+        // Boundaries between parts, initially single bytes
+        let mut boundaries: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..boundaries.len().saturating_sub(2))
+                .filter_map(|index| {
+                    let merged = &piece[boundaries[index]..boundaries[index + 2]];
+                    self.ranks.get(merged).map(|rank| (*rank, index))
+                })
+                .min();
+
+            match best {
+                Some((.., index)) => {
+                    boundaries.remove(index + 1);
+                }
+                None => break,
+            }
+        }
+
+        boundaries.len() - 1
+    }
+}
+
+/// Loaded byte pair encoders, or `None` if they could not be loaded
+static ENCODERS: Lazy<Mutex<HashMap<TokenEncoding, Option<Arc<Bpe>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Get the byte pair encoder for an encoding, loading it if necessary
+fn encoder(encoding: TokenEncoding) -> Option<Arc<Bpe>> {
+    let mut encoders = ENCODERS.lock().ok()?;
+    encoders
+        .entry(encoding)
+        .or_insert_with(|| match Bpe::load(encoding) {
+            Ok(bpe) => Some(Arc::new(bpe)),
+            Err(error) => {
+                tracing::debug!(
+                    "Unable to load BPE file for `{}`, estimating token counts: {error}",
+                    encoding.name()
+                );
+                None
+            }
+        })
+        .clone()
+}
+
+/// Count the number of tokens in a list of messages
+///
+/// Uses the byte pair encoding, if it is available, and otherwise estimates
+/// the count using a heuristic of four characters per token (in which case the
+/// count is marked as `estimated`). BPE files are not bundled so must be placed
+/// in the directory given by [`TokenEncoding::path`] for counts to be exact.
+pub fn count_tokens(
+    encoding: Option<TokenEncoding>,
+    messages: &[InstructionMessage],
+) -> TokenCount {
+    let encoder = encoding.and_then(encoder);
+
+    let count = |text: &str| match &encoder {
+        Some(encoder) => encoder.count(text),
+        None => text.chars().count().div_ceil(4),
+    };
+
+    let tokens = messages
+        .iter()
+        .map(|message| {
+            TOKENS_PER_MESSAGE
+                + message
+                    .parts
+                    .iter()
+                    .filter_map(message_part_to_string)
+                    .map(|text| count(&text))
+                    .sum::<usize>()
+        })
+        .sum::<usize>()
+        + TOKENS_PER_REPLY;
+
+    TokenCount {
+        tokens,
+        estimated: encoder.is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::MessagePart;
+
+    use super::*;
+
+    #[test]
+    fn counts() -> Result<()> {
+        let ranks = [
+            "h", "e", "l", "o", " ", "w", "r", "d", "he", "ll", "hell", "hello", " w", " wor", "or",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(rank, token)| (token.as_bytes().to_vec(), rank as u32))
+        .collect();
+        let bpe = Bpe::new(TokenEncoding::Cl100kBase, ranks)?;
+
+        assert_eq!(bpe.pieces("hello  world"), vec!["hello", " ", " world"]);
+        assert_eq!(bpe.count("hello"), 1);
+        // " world" -> " wor" + "l" + "d"
+        assert_eq!(bpe.count(" world"), 3);
+        assert_eq!(bpe.count("hello  world"), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn marks_estimates() {
+        let messages = [InstructionMessage {
+            parts: vec![MessagePart::from("Twelve chars")],
+            ..Default::default()
+        }];
+        assert_eq!(
+            count_tokens(None, &messages),
+            TokenCount {
+                tokens: TOKENS_PER_MESSAGE + 3 + TOKENS_PER_REPLY,
+                estimated: true
+            }
+        );
+    }
+}
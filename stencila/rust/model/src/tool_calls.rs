@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+
+use common::{
+    async_trait::async_trait,
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+    serde_json::{self, Value},
+};
+use dirs::{DirType, get_app_dir};
+
+/// The definition of a tool (a function) that a model may call
+///
+/// Added to [`ModelTask::tools`](crate::ModelTask::tools). Models which support
+/// tool use respond with [`ToolCall`]s in the parts of their output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ToolDefinition {
+    /// The name of the tool e.g. `read_file`
+    pub name: String,
+
+    /// A description of what the tool does, used by the model to decide when to call it
+    pub description: String,
+
+    /// A JSON Schema for the arguments of the tool
+    pub parameters: Value,
+}
+
+impl ToolDefinition {
+    /// Create a tool definition
+    pub fn new(name: &str, description: &str, parameters: Value) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+}
+
+/// A call of a tool made by a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ToolCall {
+    /// The provider's id for the call
+    ///
+    /// Used as the `call_id` of the `ToolResult` for the call.
+    pub id: String,
+
+    /// The name of the tool called
+    pub name: String,
+
+    /// The arguments of the call as a JSON string
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Parse the arguments of the call
+    ///
+    /// Empty arguments are parsed as an empty object.
+    pub fn parse_arguments(&self) -> Result<Value> {
+        if self.arguments.trim().is_empty() {
+            return Ok(Value::Object(Default::default()));
+        }
+        Ok(serde_json::from_str(&self.arguments)?)
+    }
+}
+
+/// Options for the web search tool hosted by a model provider
+///
+/// When set on a task, providers which support it (currently OpenAI, via the
+/// Responses API) allow the model to search the web. The searches are recorded
+/// as [`ModelOutputPart::WebSearch`](crate::ModelOutputPart::WebSearch) parts of
+/// the output, and the pages cited as `citations` of the output.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct WebSearchOptions {
+    /// How much context is retrieved from the web for the model
+    ///
+    /// Defaults to the provider's default (usually `medium`).
+    pub context_size: Option<WebSearchContextSize>,
+
+    /// Domains to restrict searches to e.g. `["usgs.gov", "nature.com"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_domains: Vec<String>,
+}
+
+/// The amount of context retrieved by a web search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", crate = "common::serde")]
+pub enum WebSearchContextSize {
+    Low,
+    Medium,
+    High,
+}
+
+/// Options for the code interpreter tool hosted by a model provider
+///
+/// When set on a task, providers which support it (currently OpenAI, via the
+/// Responses API) allow the model to write and run code in a sandboxed container.
+/// The code run is recorded as [`ModelOutputPart::CodeExecution`](crate::ModelOutputPart::CodeExecution)
+/// parts of the output, and files created by it (e.g. plots and CSVs) as
+/// [`ModelOutputPart::File`](crate::ModelOutputPart::File) parts linked to the execution
+/// which created them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", crate = "common::serde")]
+pub struct CodeInterpreterOptions {
+    /// The ids of files, already uploaded to the provider, to make available to the code
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub file_ids: Vec<String>,
+
+    /// Whether to download the files created by the code
+    ///
+    /// Files in provider containers expire soon after the container is idle so
+    /// they are downloaded by default.
+    pub download_files: bool,
+
+    /// The directory to download files to
+    ///
+    /// Files are written to a subdirectory named after the container they were
+    /// created in. Defaults to `code-interpreter` in the cache directory.
+    pub download_dir: Option<PathBuf>,
+}
+
+impl Default for CodeInterpreterOptions {
+    fn default() -> Self {
+        Self {
+            file_ids: Vec::new(),
+            download_files: true,
+            download_dir: None,
+        }
+    }
+}
+
+impl CodeInterpreterOptions {
+    /// Get the directory to download files to
+    pub fn download_dir(&self) -> Result<PathBuf> {
+        match &self.download_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => Ok(get_app_dir(DirType::Cache, false)?.join("code-interpreter")),
+        }
+    }
+}
+
+/// A handler of calls of a tool
+///
+/// Implemented by tools which are dispatched, in-process, by an agent loop.
+#[async_trait]
+pub trait ToolHandler: Send + Sync {
+    /// The definition of the tool sent to the model
+    fn definition(&self) -> ToolDefinition;
+
+    /// Call the tool with the parsed arguments of a call, returning its result
+    ///
+    /// Errors are returned to the model as the result of the call so that it can
+    /// correct the arguments, rather than failing the task.
+    async fn call(&self, arguments: Value) -> Result<String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use common::serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_arguments() -> Result<()> {
+        let call = |arguments: &str| ToolCall {
+            id: "call_1".into(),
+            name: "add".into(),
+            arguments: arguments.into(),
+        };
+
+        assert_eq!(call(r#"{"a": 1}"#).parse_arguments()?, json!({"a": 1}));
+        assert_eq!(call(" ").parse_arguments()?, json!({}));
+        assert!(call("{").parse_arguments().is_err());
+
+        Ok(())
+    }
+}
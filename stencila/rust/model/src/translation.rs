@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use common::{
+    eyre::{Result, bail},
+    itertools::Itertools,
+    serde::{Deserialize, Serialize},
+    serde_json::{self, json},
+    serde_with::skip_serializing_none,
+};
+use format::Format;
+
+use crate::{ModelOutput, ModelOutputPart, ModelTask, Validator, kinds::insert_system_message};
+
+/// Options for a translation task
+///
+/// A glossary can be used so that domain specific terms (e.g. of coastal
+/// geomorphology, where words like "berm" and "swash" have precise meanings)
+/// are translated consistently:
+///
+/// ```ignore
+/// TranslationOptions {
+///     source_language: Some("English".into()),
+///     target_language: "French".into(),
+///     glossary: BTreeMap::from([("shoreline".into(), "trait de côte".into())]),
+/// }
+/// ```
+#[skip_serializing_none]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", crate = "common::serde")]
+pub struct TranslationOptions {
+    /// The language of the text to translate
+    ///
+    /// Detected by the model if not specified.
+    pub source_language: Option<String>,
+
+    /// The language to translate the text into
+    pub target_language: String,
+
+    /// Translations of terms, keyed by the term in the source language
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub glossary: BTreeMap<String, String>,
+}
+
+/// A sentence of translated text aligned with the sentence it was translated from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "common::serde")]
+pub struct AlignedSentence {
+    /// The sentence in the source language
+    pub source: String,
+
+    /// The translation of the sentence in the target language
+    pub target: String,
+}
+
+/// The JSON response requested from the model
+#[derive(Deserialize)]
+#[serde(crate = "common::serde")]
+struct TranslationResponse {
+    translation: String,
+    alignment: Vec<AlignedSentence>,
+}
+
+/// Prepare a translation task
+///
+/// Errors if the task has no target language. Adds instructions, including any
+/// glossary, to the system prompt and requires the model to respond with JSON
+/// containing the translation and its sentence alignment.
+pub(crate) fn prepare(task: &mut ModelTask) -> Result<()> {
+    let Some(options) = task
+        .translation
+        .as_ref()
+        .filter(|options| !options.target_language.trim().is_empty())
+    else {
+        bail!("Translation tasks require a target language");
+    };
+
+    let source = options
+        .source_language
+        .as_deref()
+        .map(|language| format!(" from {language}"))
+        .unwrap_or_default();
+    let mut prompt = format!(
+        "Translate the text in the user's message{source} into {}. Preserve the meaning, tone and \
+formatting (e.g. paragraphs and Markdown) of the text. Do not add any explanation or notes.\n\n\
+Respond only with a JSON object with the properties `translation` (the full translated text) and \
+`alignment` (an array of objects, one for each sentence of the text in order, with the \
+properties `source`, the sentence in the original text, and `target`, its translation).",
+        options.target_language
+    );
+
+    if !options.glossary.is_empty() {
+        prompt.push_str("\n\nAlways translate these terms as follows:\n\n");
+        prompt.push_str(
+            &options
+                .glossary
+                .iter()
+                .map(|(term, translation)| format!("- {term}: {translation}"))
+                .join("\n"),
+        );
+    }
+
+    insert_system_message(task, prompt);
+
+    task.format = Format::Json;
+    task.validators.push(Validator::JsonSchema(json!({
+        "type": "object",
+        "required": ["translation", "alignment"],
+        "properties": {
+            "translation": {"type": "string", "minLength": 1},
+            "alignment": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["source", "target"],
+                    "properties": {
+                        "source": {"type": "string"},
+                        "target": {"type": "string"}
+                    }
+                }
+            }
+        }
+    })));
+
+    Ok(())
+}
+
+/// Finish a translation task
+///
+/// Replaces the JSON content of the output with the translated text and records
+/// the sentence alignment as a part of the output.
+pub(crate) fn finish(task: &ModelTask, output: &mut ModelOutput) -> Result<()> {
+    let TranslationResponse {
+        translation,
+        alignment,
+    } = serde_json::from_str(output.content.trim())?;
+
+    let options = task.translation.clone().unwrap_or_default();
+    output.content = translation;
+    output.format = Format::Markdown;
+    output.parts.push(ModelOutputPart::SentenceAlignment {
+        source_language: options.source_language,
+        target_language: options.target_language,
+        sentences: alignment,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use schema::{InstructionMessage, MessagePart, MessageRole};
+
+    use crate::{ModelTaskKind, finish_specialized_task, specialize_task};
+
+    use super::*;
+
+    #[test]
+    fn prepare_and_finish() -> Result<()> {
+        let mut task = ModelTask {
+            kind: ModelTaskKind::Translation,
+            messages: vec![InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::from(
+                    "The shoreline retreated. The berm eroded.",
+                )],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(specialize_task(&mut task.clone()).is_err());
+
+        task.translation = Some(TranslationOptions {
+            source_language: Some("English".into()),
+            target_language: "French".into(),
+            glossary: BTreeMap::from([("shoreline".into(), "trait de côte".into())]),
+        });
+        let Some(kind) = specialize_task(&mut task)? else {
+            bail!("expected task to be specialized")
+        };
+        let prompt = task.prompt_as_text().unwrap_or_default();
+        assert!(prompt.contains("from English into French"));
+        assert!(prompt.contains("- shoreline: trait de côte"));
+
+        let mut output = ModelOutput {
+            content: json!({
+                "translation": "Le trait de côte a reculé. La berme s'est érodée.",
+                "alignment": [
+                    {"source": "The shoreline retreated.", "target": "Le trait de côte a reculé."},
+                    {"source": "The berm eroded.", "target": "La berme s'est érodée."}
+                ]
+            })
+            .to_string(),
+            ..Default::default()
+        };
+        finish_specialized_task(kind, &task, &mut output)?;
+        assert_eq!(
+            output.content,
+            "Le trait de côte a reculé. La berme s'est érodée."
+        );
+        let [ModelOutputPart::SentenceAlignment { sentences, .. }] = output.parts.as_slice() else {
+            bail!("expected alignment part")
+        };
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(sentences[1].target, "La berme s'est érodée.");
+
+        Ok(())
+    }
+}
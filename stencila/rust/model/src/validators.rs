@@ -0,0 +1,317 @@
+use std::{fmt, sync::Arc};
+
+use common::{
+    eyre::{Result, bail},
+    itertools::Itertools,
+    regex::Regex,
+    serde_json::{self, Value},
+    tracing,
+};
+use schema::{InstructionMessage, MessagePart, MessageRole};
+
+use crate::{Model, ModelOutput, ModelOutputKind, ModelTask, Policy};
+
+/// The default number of times a model is re-prompted after its output fails validation
+const DEFAULT_RETRIES: u8 = 2;
+
+/// A predicate on the content generated by a model, returning the reason for any failure
+pub type PredicateFn = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+
+/// A validator of the content generated by a model
+///
+/// Added to [`ModelTask::validators`] to check outputs before they are returned.
+#[derive(Clone)]
+pub enum Validator {
+    /// The content must match the regular expression
+    Regex(Regex),
+
+    /// The content must be JSON conforming to the schema
+    ///
+    /// Only a subset of JSON Schema is supported: the `type`, `enum`, `const`,
+    /// `required`, `properties`, `additionalProperties` (when `false`), `items`,
+    /// `minItems`, `maxItems`, `minLength`, `maxLength`, `minimum` and `maximum` keywords.
+    JsonSchema(Value),
+
+    /// The content must satisfy the predicate, which returns the reason for any failure
+    Predicate(Arc<PredicateFn>),
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Validator::Regex(regex) => f.debug_tuple("Regex").field(&regex.as_str()).finish(),
+            Validator::JsonSchema(schema) => f.debug_tuple("JsonSchema").field(schema).finish(),
+            Validator::Predicate(..) => f.write_str("Predicate"),
+        }
+    }
+}
+
+impl Validator {
+    /// Create a regex validator
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Ok(Self::Regex(Regex::new(pattern)?))
+    }
+
+    /// Create a predicate validator
+    pub fn predicate<F>(predicate: F) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Self::Predicate(Arc::new(predicate))
+    }
+
+    /// Validate content, returning the reason it is invalid
+    pub fn validate(&self, content: &str) -> Result<(), String> {
+        match self {
+            Validator::Regex(regex) => {
+                if regex.is_match(content) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "the answer does not match the pattern `{}`",
+                        regex.as_str()
+                    ))
+                }
+            }
+            Validator::JsonSchema(schema) => {
+                let value = serde_json::from_str::<Value>(content.trim())
+                    .map_err(|error| format!("the answer is not valid JSON: {error}"))?;
+                validate_json(&value, schema, "$")
+            }
+            Validator::Predicate(predicate) => predicate(content),
+        }
+    }
+}
+
+/// Validate a JSON value against a (subset of) JSON Schema
+fn validate_json(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|name| is_type(value, name)) {
+            return Err(format!("`{path}` should be of type {}", types.join(" or ")));
+        }
+    }
+
+    if let Some(Value::Array(values)) = schema.get("enum")
+        && !values.contains(value)
+    {
+        return Err(format!(
+            "`{path}` should be one of {}",
+            values.iter().map(Value::to_string).join(", ")
+        ));
+    }
+
+    if let Some(expected) = schema.get("const")
+        && value != expected
+    {
+        return Err(format!("`{path}` should be {expected}"));
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        return Err(format!("`{path}` is missing required property `{name}`"));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, property) in object {
+                let property_path = format!("{path}.{name}");
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property_schema) => {
+                        validate_json(property, property_schema, &property_path)?
+                    }
+                    None => {
+                        if matches!(schema.get("additionalProperties"), Some(Value::Bool(false))) {
+                            return Err(format!("`{path}` has unexpected property `{name}`"));
+                        }
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+                && (items.len() as u64) < min
+            {
+                return Err(format!("`{path}` should have at least {min} items"));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+                && (items.len() as u64) > max
+            {
+                return Err(format!("`{path}` should have at most {max} items"));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_json(item, item_schema, &format!("{path}[{index}]"))?;
+                }
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+                && length < min
+            {
+                return Err(format!("`{path}` should be at least {min} characters"));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+                && length > max
+            {
+                return Err(format!("`{path}` should be at most {max} characters"));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64)
+                && number < min
+            {
+                return Err(format!("`{path}` should be at least {min}"));
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64)
+                && number > max
+            {
+                return Err(format!("`{path}` should be at most {max}"));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Whether a JSON value is of a JSON Schema type
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Enforce the validators of a task on the output of a model
+///
+/// If the output fails validation, the model is re-prompted with its previous
+/// answer and the reasons that it failed, up to [`ModelTask::validation_retries`]
+/// times, with each re-prompt checked against, and recorded by, the `policy`.
+/// Returns an error if the output still fails validation.
+pub async fn enforce_validators(
+    model: &dyn Model,
+    task: &ModelTask,
+    mut output: ModelOutput,
+    policy: &Policy,
+) -> Result<ModelOutput> {
+    if task.validators.is_empty() || task.dry_run || !matches!(output.kind, ModelOutputKind::Text) {
+        return Ok(output);
+    }
+
+    let retries = task.validation_retries.unwrap_or(DEFAULT_RETRIES);
+    let mut task = task.clone();
+    for attempt in 0..=retries {
+        let failures = task
+            .validators
+            .iter()
+            .filter_map(|validator| validator.validate(&output.content).err())
+            .collect_vec();
+
+        if failures.is_empty() {
+            return Ok(output);
+        }
+
+        if attempt == retries {
+            bail!(
+                "Output of model `{}` failed validation after {retries} retries: {}",
+                model.id(),
+                failures.join("; ")
+            );
+        }
+
+        tracing::debug!(
+            "Output of model `{}` failed validation, re-prompting: {}",
+            model.id(),
+            failures.join("; ")
+        );
+
+        task.messages.push(InstructionMessage {
+            role: Some(MessageRole::Model),
+            parts: vec![MessagePart::from(output.content.as_str())],
+            ..Default::default()
+        });
+        task.messages.push(InstructionMessage {
+            role: Some(MessageRole::User),
+            parts: vec![MessagePart::from(format!(
+                "Your previous answer failed because {}. Please answer again, correcting these problems.",
+                failures.join(", and ")
+            ))],
+            ..Default::default()
+        });
+
+        output = policy.perform(model, &task).await?;
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn validates() -> Result<()> {
+        let schema = Validator::JsonSchema(json!({
+            "type": "object",
+            "required": ["label"],
+            "properties": {
+                "label": {"enum": ["erosion", "accretion"]},
+                "rate": {"type": "number", "minimum": 0}
+            }
+        }));
+
+        assert!(
+            schema
+                .validate(r#"{"label": "erosion", "rate": 1.5}"#)
+                .is_ok()
+        );
+        assert_eq!(
+            schema.validate(r#"{"label": "stable"}"#),
+            Err(r#"`$.label` should be one of "erosion", "accretion""#.into())
+        );
+        assert_eq!(
+            schema.validate(r#"{"rate": -1}"#),
+            Err("`$` is missing required property `label`".into())
+        );
+        assert!(schema.validate("not json").is_err());
+
+        let regex = Validator::regex(r"^\d{4}-\d{2}-\d{2}$")?;
+        assert!(regex.validate("2024-01-31").is_ok());
+        assert!(regex.validate("31 Jan 2024").is_err());
+
+        let predicate = Validator::predicate(|content| {
+            if content.len() <= 10 {
+                Ok(())
+            } else {
+                Err("the answer is too long".into())
+            }
+        });
+        assert!(predicate.validate("short").is_ok());
+        assert!(predicate.validate("much too long").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use common::{
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+};
+use schema::{File, ImageObject, InstructionAttachment, MessagePart, VideoObject};
+
+use crate::{ModelTask, ModelWarning, derived_cache};
+
+/// Options for sampling frames from videos
+///
+/// Used when sending videos to models which accept images but not video:
+/// frames are extracted from the video and sent as images instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct VideoSampling {
+    /// The number of frames to extract for each second of video
+    #[serde(default = "VideoSampling::default_frames_per_second")]
+    pub frames_per_second: f32,
+
+    /// The maximum number of frames to extract from each video
+    #[serde(default = "VideoSampling::default_max_frames")]
+    pub max_frames: usize,
+}
+
+impl Default for VideoSampling {
+    fn default() -> Self {
+        Self {
+            frames_per_second: Self::default_frames_per_second(),
+            max_frames: Self::default_max_frames(),
+        }
+    }
+}
+
+impl VideoSampling {
+    fn default_frames_per_second() -> f32 {
+        1.0
+    }
+
+    fn default_max_frames() -> usize {
+        8
+    }
+
+    /// Get the rate, in frames per second, at which to sample a video
+    ///
+    /// For videos longer than `max_frames / frames_per_second` seconds the rate is
+    /// lowered so that the frames are spread evenly across the whole video, rather
+    /// than all being sampled from its start. If the duration of the video is not
+    /// known, `frames_per_second` is used.
+    pub fn rate(&self, duration: Option<f64>) -> f64 {
+        let frames_per_second = self.frames_per_second as f64;
+        match duration {
+            Some(duration) if duration > 0.0 && self.max_frames > 0 => {
+                frames_per_second.min(self.max_frames as f64 / duration)
+            }
+            _ => frames_per_second,
+        }
+    }
+
+    /// A description of the sampling e.g. for a message preceding the frames
+    pub fn describe(&self, frames: usize) -> String {
+        format!(
+            "{frames} frame{} sampled from the video at even intervals",
+            if frames == 1 { "" } else { "s" },
+        )
+    }
+}
+
+/// Parse the duration, in seconds, of a video from the diagnostic output of `ffmpeg`
+///
+/// Returns `None` if the output has no duration (e.g. `Duration: N/A` for streams).
+#[cfg(feature = "ffmpeg")]
+fn parse_duration(output: &str) -> Option<f64> {
+    let (.., rest) = output.split_once("Duration: ")?;
+    let duration = rest.split([',', '\n']).next()?.trim();
+
+    let mut seconds = 0.0;
+    for part in duration.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Extract frames from a video as images
+///
+/// The `content_url` of the video may be a base64 encoded data URL, the path of a
+/// local file, or a URL which `ffmpeg` can read. The frames are returned as JPEG
+/// data URLs. Requires `ffmpeg` to be installed.
+#[cfg(feature = "ffmpeg")]
+pub async fn extract_video_frames(
+    video: &VideoObject,
+    sampling: &VideoSampling,
+) -> Result<Vec<ImageObject>> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+    use common::{
+        eyre::{Context, bail},
+        itertools::Itertools,
+        tempfile::tempdir,
+        tokio::fs::{read, read_dir, write},
+    };
+    use tools::{Ffmpeg, Tool};
+
+    if !Ffmpeg.is_installed() {
+        bail!("Extracting frames from video requires `ffmpeg` to be installed")
+    }
+
+    let dir = tempdir()?;
+
+    let url = video.content_url.trim();
+    let input = if let Some(rest) = url.strip_prefix("data:") {
+        let Some((.., data)) = rest.split_once(";base64,") else {
+            bail!("Video data URL is not base64 encoded")
+        };
+        let path = dir.path().join("video");
+        write(&path, BASE64.decode(data.as_bytes())?).await?;
+        path.to_string_lossy().to_string()
+    } else {
+        url.strip_prefix("file://").unwrap_or(url).to_string()
+    };
+
+    // Probe the duration of the video so that frames can be sampled across all of it
+    // (without an output file ffmpeg exits with an error but reports the duration)
+    let probe = Ffmpeg
+        .async_command()
+        .args(["-hide_banner", "-i", &input])
+        .output()
+        .await
+        .wrap_err("Failed to run ffmpeg")?;
+    let duration = parse_duration(&String::from_utf8_lossy(&probe.stderr));
+
+    let output = Ffmpeg
+        .async_command()
+        .args(["-hide_banner", "-loglevel", "error", "-i", &input])
+        .args(["-vf", &format!("fps={}", sampling.rate(duration))])
+        .args(["-frames:v", &sampling.max_frames.to_string()])
+        .arg(dir.path().join("frame-%04d.jpg"))
+        .output()
+        .await
+        .wrap_err("Failed to run ffmpeg")?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed to extract frames: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    let mut paths = Vec::new();
+    let mut entries = read_dir(dir.path()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("frame-"))
+        {
+            paths.push(path);
+        }
+    }
+
+    let mut frames = Vec::new();
+    for path in paths.into_iter().sorted() {
+        let data = BASE64.encode(read(&path).await?);
+        frames.push(ImageObject {
+            content_url: format!("data:image/jpeg;base64,{data}"),
+            media_type: Some("image/jpeg".into()),
+            ..Default::default()
+        });
+    }
+
+    Ok(frames)
+}
+
+/// Extract frames from a video as images
+///
+/// This build of Stencila does not include the `ffmpeg` feature so this always errors.
+#[cfg(not(feature = "ffmpeg"))]
+pub async fn extract_video_frames(
+    _video: &VideoObject,
+    _sampling: &VideoSampling,
+) -> Result<Vec<ImageObject>> {
+    common::eyre::bail!(
+        "Extracting frames from video requires Stencila to be built with the `ffmpeg` feature"
+    )
+}
+
+/// Extract frames from a video attachment as image attachments
+///
+/// The frames are returned as attachments with base64 encoded JPEG content and
+/// aliases derived from the alias of the video e.g. `clip-frame-1`.
+pub async fn extract_attachment_frames(
+    attachment: &InstructionAttachment,
+    sampling: &VideoSampling,
+) -> Result<Vec<InstructionAttachment>> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+    let file = &attachment.file;
+    let content_url = match &file.content {
+        Some(content) => {
+            let media_type = file.media_type.as_deref().unwrap_or("video/mp4");
+            let is_base64 = file
+                .options
+                .transfer_encoding
+                .as_deref()
+                .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"));
+            let data = if is_base64 {
+                content.clone()
+            } else {
+                BASE64.encode(content.as_bytes())
+            };
+            format!("data:{media_type};base64,{data}")
+        }
+        None => file.path.clone(),
+    };
+
+    let video = VideoObject {
+        content_url,
+        media_type: file.media_type.clone(),
+        ..Default::default()
+    };
+    let frames = match derived_cache() {
+        Some(cache) => {
+            cache
+                .get_or_derive_async(attachment, "frames", sampling, || {
+                    extract_video_frames(&video, sampling)
+                })
+                .await?
+        }
+        None => extract_video_frames(&video, sampling).await?,
+    };
+
+    let stem = file
+        .name
+        .rsplit_once('.')
+        .map_or(file.name.as_str(), |(stem, ..)| stem);
+
+    Ok(frames
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let number = index + 1;
+            let name = format!("{stem}-frame-{number}.jpg");
+            let mut file = File::new(name.clone(), name);
+            file.media_type = Some("image/jpeg".into());
+            file.content = frame
+                .content_url
+                .split_once(";base64,")
+                .map(|(.., data)| data.to_string());
+            file.options.transfer_encoding = Some("base64".into());
+            InstructionAttachment::new(format!("{}-frame-{number}", attachment.alias), file)
+        })
+        .collect())
+}
+
+/// Replace the videos in the messages of a task with frames sampled from them
+///
+/// Each video is replaced by a text part describing the sampling followed by the
+/// frames as image parts. Videos that frames can not be extracted from are left in
+/// place, with a warning. Returns `None` if the task has no videos.
+pub async fn replace_videos_with_frames(
+    task: &ModelTask,
+    warnings: &mut Vec<ModelWarning>,
+) -> Option<ModelTask> {
+    let has_videos = task.messages.iter().any(|message| {
+        message
+            .parts
+            .iter()
+            .any(|part| matches!(part, MessagePart::VideoObject(..)))
+    });
+    if !has_videos {
+        return None;
+    }
+
+    let sampling = task.video_sampling.clone().unwrap_or_default();
+
+    let mut task = task.clone();
+    for message in &mut task.messages {
+        let mut parts = Vec::with_capacity(message.parts.len());
+        for part in message.parts.drain(..) {
+            let MessagePart::VideoObject(video) = &part else {
+                parts.push(part);
+                continue;
+            };
+
+            match extract_video_frames(video, &sampling).await {
+                Ok(frames) if !frames.is_empty() => {
+                    parts.push(MessagePart::from(sampling.describe(frames.len())));
+                    parts.extend(frames.into_iter().map(MessagePart::ImageObject));
+                }
+                Ok(..) => {
+                    warnings.push(ModelWarning::ignored_part(
+                        "No frames could be extracted from video",
+                    ));
+                    parts.push(part);
+                }
+                Err(error) => {
+                    warnings.push(ModelWarning::ignored_part(format!(
+                        "Unable to extract frames from video: {error}"
+                    )));
+                    parts.push(part);
+                }
+            }
+        }
+        message.parts = parts;
+    }
+
+    Some(task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_sampling() {
+        let sampling = VideoSampling::default();
+        assert_eq!(
+            sampling.describe(8),
+            "8 frames sampled from the video at even intervals"
+        );
+    }
+
+    #[test]
+    fn samples_whole_video() {
+        let sampling = VideoSampling::default();
+
+        // Short videos are sampled at the configured rate
+        assert_eq!(sampling.rate(Some(5.0)), 1.0);
+
+        // Long videos are sampled evenly across their duration
+        assert_eq!(sampling.rate(Some(80.0)), 0.1);
+
+        assert_eq!(sampling.rate(None), 1.0);
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn parses_durations() {
+        let output = "Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'clip.mp4':\n  Duration: 00:01:20.50, start: 0.000000, bitrate: 1205 kb/s\n";
+        assert_eq!(parse_duration(output), Some(80.5));
+
+        assert_eq!(parse_duration("  Duration: N/A, bitrate: N/A"), None);
+        assert_eq!(parse_duration("No such file or directory"), None);
+    }
+}
@@ -0,0 +1,98 @@
+use common::{
+    serde::{Deserialize, Serialize},
+    strum::Display,
+    tracing,
+};
+
+/// The kind of a [`ModelWarning`]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", crate = "common::serde")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ModelWarningKind {
+    /// A part of a message was not sent to the model
+    IgnoredPart,
+
+    /// An option of the task is not supported by the model
+    IgnoredOption,
+
+    /// An attachment was not sent to the model
+    SkippedAttachment,
+
+    /// A fallback was used e.g. another model, or a previously saved list of models
+    Fallback,
+
+    /// Something about the task was corrected e.g. the media type of an attachment
+    Correction,
+
+    /// The model is deprecated, or was substituted for a deprecated model
+    Deprecated,
+
+    /// Disallowed content in the output was flagged, redacted or blocked
+    Filtered,
+
+    /// A file created by the model could not be downloaded
+    FailedDownload,
+}
+
+/// A warning about how a task was performed
+///
+/// Warnings are collected on the [`ModelOutput`](crate::ModelOutput) so that
+/// they can be displayed alongside the generated content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct ModelWarning {
+    /// The kind of warning
+    pub kind: ModelWarningKind,
+
+    /// A human readable description of the warning
+    pub message: String,
+}
+
+impl ModelWarning {
+    /// Create a warning, also logging it
+    pub fn new(kind: ModelWarningKind, message: impl Into<String>) -> Self {
+        let message = message.into();
+        tracing::warn!("{message}");
+        Self { kind, message }
+    }
+
+    /// Create a warning that a message part was ignored
+    pub fn ignored_part(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::IgnoredPart, message)
+    }
+
+    /// Create a warning that a task option was ignored
+    pub fn ignored_option(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::IgnoredOption, message)
+    }
+
+    /// Create a warning that a file created by the model could not be downloaded
+    pub fn failed_download(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::FailedDownload, message)
+    }
+
+    /// Create a warning that an attachment was skipped
+    pub fn skipped_attachment(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::SkippedAttachment, message)
+    }
+
+    /// Create a warning that a fallback was used
+    pub fn fallback(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::Fallback, message)
+    }
+
+    /// Create a warning that a model is deprecated
+    pub fn deprecated(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::Deprecated, message)
+    }
+
+    /// Create a warning that the output was filtered
+    pub fn filtered(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::Filtered, message)
+    }
+
+    /// Create a warning that a correction was made
+    pub fn correction(message: impl Into<String>) -> Self {
+        Self::new(ModelWarningKind::Correction, message)
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fs::read, path::Path};
+
+use common::{
+    eyre::Result,
+    serde::{Deserialize, Serialize},
+    serde_with::skip_serializing_none,
+};
+
+use crate::attestation::content_hash;
+
+/// An output of an upstream workflow that a task was grounded in
+///
+/// Declared on a [`ModelTask`](crate::ModelTask) (e.g. using
+/// [`ModelTaskBuilder::derived_from`](crate::ModelTaskBuilder::derived_from)) to record
+/// that the content generated for it is based on specific data files produced by a
+/// pipeline (e.g. the shoreline time series of the CoastSat workflow). Artifacts are
+/// copied to the output, and become `wasDerivedFrom` edges in provenance graphs, so
+/// that generated prose can be traced back to the exact version of the data.
+#[skip_serializing_none]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct WorkflowArtifact {
+    /// The path of the file, as produced by the workflow
+    pub path: String,
+
+    /// The SHA-256 hash of the content of the file, hex encoded
+    pub hash: String,
+
+    /// The name, or URL, of the workflow that produced the file
+    pub workflow: Option<String>,
+
+    /// The step of the workflow that produced the file
+    pub step: Option<String>,
+}
+
+impl WorkflowArtifact {
+    /// Create an artifact with a path and a known hash
+    pub fn new(path: impl Into<String>, hash: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            hash: hash.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Create an artifact for a file, hashing its current content
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Ok(Self::new(
+            path.to_string_lossy(),
+            content_hash(&read(path)?),
+        ))
+    }
+
+    /// Set the workflow that produced the artifact
+    pub fn workflow(mut self, workflow: impl Into<String>) -> Self {
+        self.workflow = Some(workflow.into());
+        self
+    }
+
+    /// Set the step of the workflow that produced the artifact
+    pub fn step(mut self, step: impl Into<String>) -> Self {
+        self.step = Some(step.into());
+        self
+    }
+
+    /// Whether the file at the path of the artifact still has the recorded hash
+    ///
+    /// Errors if the file can not be read e.g. because it has been deleted.
+    pub fn is_current(&self) -> Result<bool> {
+        Ok(content_hash(&read(&self.path)?) == self.hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use common::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn hashes_files() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("transect_time_series.csv");
+        write(&path, "dates,1\n2020-01-01,42.1\n")?;
+
+        let artifact = WorkflowArtifact::from_path(&path)?
+            .workflow("CoastSat")
+            .step("tidal-correction");
+        assert_eq!(artifact.hash.len(), 64);
+        assert_eq!(artifact.step.as_deref(), Some("tidal-correction"));
+        assert!(artifact.is_current()?);
+
+        write(&path, "dates,1\n2020-01-01,42.2\n")?;
+        assert!(!artifact.is_current()?);
+
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Duration};
 use cached::proc_macro::cached;
 
 use model::{
-    Model, ModelIO, ModelOutput, ModelTask, ModelType,
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning, api_key,
     common::{
         async_trait::async_trait,
         eyre::{Result, bail},
@@ -13,6 +13,7 @@ use model::{
         serde_with::skip_serializing_none,
         tracing,
     },
+    models_config,
     schema::{ImageObject, MessagePart, MessageRole},
     secrets,
 };
@@ -20,6 +21,11 @@ use model::{
 /// The base URL for the Anthropic API
 const BASE_URL: &str = "https://api.anthropic.com/v1";
 
+/// Get the base URL of the Anthropic API from the models config
+fn base_url() -> String {
+    models_config().requests.base_url("anthropic", BASE_URL)
+}
+
 /// The version of the Anthropic API used
 const API_VERSION: &str = "2023-06-01";
 
@@ -43,12 +49,12 @@ pub struct AnthropicModel {
 
 impl AnthropicModel {
     /// Create an Anthropic model
-    fn new(model: &str, context_length: usize) -> Self {
-        Self {
+    fn new(model: &str, context_length: usize) -> Result<Self> {
+        Ok(Self {
             model: model.into(),
             context_length,
-            client: Client::new(),
-        }
+            client: models_config().requests.http_client()?,
+        })
     }
 }
 
@@ -75,22 +81,29 @@ impl Model for AnthropicModel {
     }
 
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+
         let mut system = None;
         let messages = task
             .messages
             .iter()
             .filter_map(|message| {
-                if matches!(message.role, Some(MessageRole::System)) {
+                if matches!(
+                    message.role,
+                    Some(MessageRole::System | MessageRole::Developer)
+                ) {
                     let text = message
                         .parts
                         .iter()
                         .filter_map(|part| match part {
                             MessagePart::Text(text) => Some(text.to_value_string()),
                             _ => {
-                                tracing::warn!(
+                                warnings.push(ModelWarning::ignored_part(format!(
                                     "System message part `{part}` is ignored by model `{}`",
                                     self.id()
-                                );
+                                )));
                                 None
                             }
                         })
@@ -127,17 +140,17 @@ impl Model for AnthropicModel {
                                     },
                                 })
                             } else {
-                                tracing::warn!(
+                                warnings.push(ModelWarning::ignored_part(format!(
                                     "Image does not appear to have a DataURI so was ignored by model `{}`",
                                     self.id()
-                                );
+                                )));
                                 None
                             }
                         _ => {
-                            tracing::warn!(
+                            warnings.push(ModelWarning::ignored_part(format!(
                                 "User message part `{part}` is ignored by model `{}`",
                                 self.id()
-                            );
+                            )));
                             None
                         }
                     })
@@ -165,8 +178,8 @@ impl Model for AnthropicModel {
 
         let response = self
             .client
-            .post(format!("{BASE_URL}/messages/"))
-            .header("x-api-key", secrets::env_or_get(API_KEY)?)
+            .post(format!("{}/messages/", base_url()))
+            .header("x-api-key", api_key(API_KEY, &self.id(), Some(task))?)
             .header("anthropic-version", API_VERSION)
             .json(&request)
             .send()
@@ -188,7 +201,10 @@ impl Model for AnthropicModel {
             })
             .join("\n\n");
 
-        ModelOutput::from_text(self, &task.format, text).await
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
     }
 }
 
@@ -211,9 +227,9 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
         .into_iter()
         .sorted_by(|a, b| a.id.cmp(&b.id))
         .map(|ModelSpec { id: model }| {
-            Arc::new(AnthropicModel::new(&model, 200_000)) as Arc<dyn Model>
+            Ok(Arc::new(AnthropicModel::new(&model, 200_000)?) as Arc<dyn Model>)
         })
-        .collect();
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(models)
 }
@@ -223,8 +239,10 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
 /// In-memory cached for six hours to reduce requests to remote API.
 #[cached(time = 21_600, result = true)]
 async fn list_anthropic_models() -> Result<ModelsResponse> {
-    let response = Client::new()
-        .get(format!("{BASE_URL}/models"))
+    let response = models_config()
+        .requests
+        .http_client()?
+        .get(format!("{}/models", base_url()))
         .header("x-api-key", secrets::env_or_get(API_KEY)?)
         .header("anthropic-version", API_VERSION)
         .send()
@@ -335,7 +353,7 @@ mod tests {
             return Ok(());
         }
 
-        let model = AnthropicModel::new("claude-3-5-sonnet-20240620", 0);
+        let model = AnthropicModel::new("claude-3-5-sonnet-20240620", 0)?;
         let output = model.perform_task(&test_task_repeat_word()).await?;
 
         assert_eq!(output.content.trim(), "HELLO".to_string());
@@ -0,0 +1,562 @@
+use std::{env, sync::Arc, time::Duration};
+
+use cached::proc_macro::cached;
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning,
+    common::{
+        async_trait::async_trait,
+        chrono::Utc,
+        eyre::{Result, bail},
+        itertools::Itertools,
+        reqwest::{Client, Method},
+        serde::{Deserialize, Serialize, de::DeserializeOwned},
+        serde_json,
+        serde_with::skip_serializing_none,
+        tracing,
+    },
+    models_config,
+    schema::{ImageObject, MessagePart, MessageRole},
+    secrets,
+};
+
+mod sigv4;
+use sigv4::{Credentials, Request, sign, uri_encode};
+
+/// The region used if neither `AWS_REGION` nor `AWS_DEFAULT_REGION` are set
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// The name of the service used when signing requests
+const SERVICE: &str = "bedrock";
+
+/// The publishers of models supported
+///
+/// Models from other publishers may require request parameters which are
+/// not supported by the Converse API so are not listed.
+const PUBLISHERS: &[&str] = &["Amazon", "Anthropic", "Meta"];
+
+/// Get the AWS region to use
+fn region() -> String {
+    env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| DEFAULT_REGION.to_string())
+}
+
+/// Get the host of the Bedrock runtime API (used for inference)
+///
+/// Can be overridden using the `bedrock` entry in the `[requests.base-urls]` of
+/// the models config (e.g. to use a VPC endpoint).
+fn runtime_host() -> String {
+    let url = models_config().requests.base_url(
+        "bedrock",
+        &format!("https://bedrock-runtime.{}.amazonaws.com", region()),
+    );
+    url.trim_start_matches("https://").to_string()
+}
+
+/// Get the host of the Bedrock control plane API (used for listing models)
+fn control_host() -> String {
+    format!("bedrock.{}.amazonaws.com", region())
+}
+
+/// Make a signed request to a Bedrock API
+///
+/// The `path` should already be URI encoded.
+async fn request<T: DeserializeOwned>(
+    client: &Client,
+    method: Method,
+    host: &str,
+    path: &str,
+    query: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> Result<T> {
+    let credentials = Credentials::from_env()?;
+    let body = body.unwrap_or_default();
+    let headers = sign(
+        &Request {
+            method: method.as_str(),
+            host,
+            path,
+            query,
+            body: &body,
+        },
+        &credentials,
+        &region(),
+        SERVICE,
+        Utc::now(),
+    )?;
+
+    let mut builder = client
+        .request(method, format!("https://{host}{path}"))
+        .query(query);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    if !body.is_empty() {
+        builder = builder
+            .header("content-type", "application/json")
+            .body(body);
+    }
+
+    let response = builder.send().await?;
+    if let Err(error) = response.error_for_status_ref() {
+        let message = response.text().await?;
+        bail!("{error}: {message}");
+    }
+
+    Ok(response.json().await?)
+}
+
+struct BedrockModel {
+    /// The Bedrock id of the model e.g. `anthropic.claude-3-5-sonnet-20240620-v1:0`
+    model: String,
+
+    /// The name of the model e.g. `Claude 3.5 Sonnet`
+    name: String,
+
+    /// The name of the publisher of the model e.g. `Anthropic`
+    publisher: String,
+
+    /// The input types supported by the model
+    inputs: Vec<ModelIO>,
+
+    /// The HTTP client
+    client: Client,
+}
+
+impl BedrockModel {
+    /// Create a Bedrock model from its summary
+    fn new(summary: ModelSummary) -> Result<Self> {
+        let mut inputs = vec![ModelIO::Text];
+        if summary.input_modalities.iter().any(|io| io == "IMAGE") {
+            inputs.push(ModelIO::Image);
+        }
+
+        Ok(Self {
+            model: summary.model_id,
+            name: summary.model_name,
+            publisher: summary.provider_name,
+            inputs,
+            client: models_config().requests.http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Model for BedrockModel {
+    fn id(&self) -> String {
+        format!("bedrock/{}", self.model)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Remote
+    }
+
+    fn provider(&self) -> String {
+        "AWS Bedrock".to_string()
+    }
+
+    fn name(&self) -> String {
+        format!("{} {}", self.publisher, self.name)
+    }
+
+    fn version(&self) -> String {
+        // Model ids are of the form <publisher>.<model>-<version>
+        self.model
+            .split_once('.')
+            .map_or(self.model.as_str(), |(.., model)| model)
+            .split_once('-')
+            .map(|(.., version)| version)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        &self.inputs
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+        let images = self.inputs.contains(&ModelIO::Image);
+
+        let mut system = Vec::new();
+        let mut messages: Vec<ConverseMessage> = Vec::new();
+        for message in &task.messages {
+            let role = match message.role.unwrap_or_default() {
+                MessageRole::System | MessageRole::Developer => {
+                    system.extend(message.parts.iter().filter_map(|part| match part {
+                        MessagePart::Text(text) => Some(SystemContentBlock {
+                            text: text.to_value_string(),
+                        }),
+                        _ => None,
+                    }));
+                    continue;
+                }
+                MessageRole::Model => ConverseRole::Assistant,
+                MessageRole::User => ConverseRole::User,
+            };
+
+            let content = message
+                .parts
+                .iter()
+                .filter_map(|part| match part {
+                    MessagePart::Text(text) => Some(ContentBlock::Text(text.to_value_string())),
+                    MessagePart::ImageObject(ImageObject { content_url, .. }) if images => {
+                        match ImageBlock::from_data_uri(content_url) {
+                            Some(image) => Some(ContentBlock::Image(image)),
+                            None => {
+                                warnings.push(ModelWarning::ignored_part(format!(
+                                    "Image does not appear to have a supported DataURI so was ignored by model `{}`",
+                                    self.id()
+                                )));
+                                None
+                            }
+                        }
+                    }
+                    _ => {
+                        warnings.push(ModelWarning::ignored_part(format!(
+                            "Message part of type `{part}` is ignored by model `{}`",
+                            self.id()
+                        )));
+                        None
+                    }
+                })
+                .collect_vec();
+
+            // The Converse API requires that roles alternate so merge consecutive messages
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(ConverseMessage { role, content }),
+            }
+        }
+
+        let request = ConverseRequest {
+            messages,
+            system: (!system.is_empty()).then_some(system),
+            inference_config: InferenceConfig {
+                max_tokens: task.max_tokens,
+                temperature: task.temperature,
+                top_p: task.top_p,
+            },
+        };
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        let path = format!("/model/{}/converse", uri_encode(&self.model, true));
+        let response: ConverseResponse = self::request(
+            &self.client,
+            Method::POST,
+            &runtime_host(),
+            &path,
+            &[],
+            Some(serde_json::to_vec(&request)?),
+        )
+        .await?;
+
+        let text = response
+            .output
+            .message
+            .content
+            .into_iter()
+            .filter_map(|block| block.text)
+            .join("");
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
+    }
+}
+
+/// A Converse API request
+///
+/// Based on https://docs.aws.amazon.com/bedrock/latest/APIReference/API_runtime_Converse.html
+#[skip_serializing_none]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+struct ConverseRequest {
+    messages: Vec<ConverseMessage>,
+    system: Option<Vec<SystemContentBlock>>,
+    inference_config: InferenceConfig,
+}
+
+/// A message within a `ConverseRequest`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ConverseMessage {
+    role: ConverseRole,
+    content: Vec<ContentBlock>,
+}
+
+/// A role in a `ConverseMessage`
+#[derive(PartialEq, Serialize)]
+#[serde(rename_all = "lowercase", crate = "model::common::serde")]
+enum ConverseRole {
+    User,
+    Assistant,
+}
+
+/// A block of content within a `ConverseMessage`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+enum ContentBlock {
+    Text(String),
+    Image(ImageBlock),
+}
+
+/// An image within a `ContentBlock`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ImageBlock {
+    format: String,
+    source: ImageSource,
+}
+
+impl ImageBlock {
+    /// Create an image block from a base64 encoded data URI
+    ///
+    /// Returns `None` if the URI is not a data URI or the format is not supported.
+    fn from_data_uri(uri: &str) -> Option<Self> {
+        let (media_type, bytes) = uri.strip_prefix("data:")?.split_once(";base64,")?;
+        let format = match media_type {
+            "image/png" => "png",
+            "image/jpeg" | "image/jpg" => "jpeg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => return None,
+        };
+
+        Some(Self {
+            format: format.into(),
+            source: ImageSource {
+                bytes: bytes.into(),
+            },
+        })
+    }
+}
+
+/// The source of an `ImageBlock`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ImageSource {
+    /// The base64 encoded bytes of the image
+    bytes: String,
+}
+
+/// A block of the system prompt within a `ConverseRequest`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct SystemContentBlock {
+    text: String,
+}
+
+/// Inference parameters within a `ConverseRequest`
+#[skip_serializing_none]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+struct InferenceConfig {
+    max_tokens: Option<u16>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+}
+
+/// A Converse API response
+///
+/// Note: at present several other fields are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ConverseResponse {
+    output: ConverseOutput,
+}
+
+/// The output within a `ConverseResponse`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ConverseOutput {
+    message: ConverseOutputMessage,
+}
+
+/// The message within a `ConverseOutput`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ConverseOutputMessage {
+    content: Vec<ConverseOutputBlock>,
+}
+
+/// A block of content within a `ConverseOutputMessage`
+///
+/// Note: at present blocks other than text (e.g. tool use) are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ConverseOutputBlock {
+    text: Option<String>,
+}
+
+/// A list foundation models response
+///
+/// Based on https://docs.aws.amazon.com/bedrock/latest/APIReference/API_ListFoundationModels.html
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+struct ModelsResponse {
+    model_summaries: Vec<ModelSummary>,
+}
+
+/// A model within a `ModelsResponse`
+///
+/// Note: at present several other fields are ignored.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+struct ModelSummary {
+    model_id: String,
+    model_name: String,
+    provider_name: String,
+
+    #[serde(default)]
+    input_modalities: Vec<String>,
+
+    #[serde(default)]
+    inference_types_supported: Vec<String>,
+}
+
+/// Get a list of available Bedrock models
+///
+/// Returns an empty list if the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+/// env vars are not set. Only text generation models from supported publishers
+/// which can be invoked on demand are listed.
+///
+/// Memoized for two minutes to avoid loading from disk cache too frequently
+/// but allowing user to set credentials while process is running.
+#[cached(time = 120, result = true)]
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    // Check for credentials before calling IO cached function so that we never cache an empty list
+    // and allow for users to set them, and then get list, while process is running
+    if Credentials::from_env().is_err() {
+        tracing::trace!(
+            "The environment variables or secrets `{}` and `{}` are not available",
+            secrets::AWS_ACCESS_KEY_ID,
+            secrets::AWS_SECRET_ACCESS_KEY
+        );
+        return Ok(vec![]);
+    };
+
+    let models = list_bedrock_models()
+        .await?
+        .model_summaries
+        .into_iter()
+        .filter(|summary| {
+            PUBLISHERS.contains(&summary.provider_name.as_str())
+                && summary
+                    .inference_types_supported
+                    .iter()
+                    .any(|inference| inference == "ON_DEMAND")
+        })
+        .sorted_by(|a, b| a.model_id.cmp(&b.model_id))
+        .map(|summary| Ok(Arc::new(BedrockModel::new(summary)?) as Arc<dyn Model>))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(models)
+}
+
+/// Fetch the list of models
+///
+/// In-memory cached for six hours to reduce requests to remote API.
+#[cached(time = 21_600, result = true)]
+async fn list_bedrock_models() -> Result<ModelsResponse> {
+    request(
+        &models_config().requests.http_client()?,
+        Method::GET,
+        &control_host(),
+        "/foundation-models",
+        &[("byOutputModality", "TEXT")],
+        None,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{
+        common::{eyre::eyre, tokio},
+        test_task_repeat_word,
+    };
+
+    #[test]
+    fn converse_request() -> Result<()> {
+        let request = ConverseRequest {
+            messages: vec![ConverseMessage {
+                role: ConverseRole::User,
+                content: vec![
+                    ContentBlock::Text("Describe".into()),
+                    ContentBlock::Image(
+                        ImageBlock::from_data_uri("data:image/png;base64,AAAA")
+                            .ok_or_else(|| eyre!("expected image"))?,
+                    ),
+                ],
+            }],
+            system: None,
+            inference_config: InferenceConfig {
+                max_tokens: Some(100),
+                temperature: None,
+                top_p: None,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&request)?,
+            r#"{"messages":[{"role":"user","content":[{"text":"Describe"},{"image":{"format":"png","source":{"bytes":"AAAA"}}}]}],"inferenceConfig":{"maxTokens":100}}"#
+        );
+
+        let response: ConverseResponse = serde_json::from_str(
+            r#"{"output":{"message":{"role":"assistant","content":[{"text":"HELLO"},{"toolUse":{}}]}},"stopReason":"end_turn"}"#,
+        )?;
+        assert_eq!(response.output.message.content.len(), 2);
+        assert_eq!(
+            response.output.message.content[0].text.as_deref(),
+            Some("HELLO")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_models() -> Result<()> {
+        let list = list().await?;
+
+        if Credentials::from_env().is_err() {
+            assert_eq!(list.len(), 0)
+        } else {
+            assert!(!list.is_empty())
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn perform_task() -> Result<()> {
+        if Credentials::from_env().is_err() {
+            return Ok(());
+        }
+
+        let Some(model) = list()
+            .await?
+            .into_iter()
+            .find(|model| model.id().starts_with("bedrock/anthropic.claude"))
+        else {
+            return Ok(());
+        };
+        let output = model.perform_task(&test_task_repeat_word()).await?;
+
+        assert_eq!(output.content.trim(), "HELLO");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Signing of requests to AWS APIs using Signature Version 4
+//!
+//! Only the subset of the algorithm needed for Bedrock is implemented: headers
+//! are signed (not query strings) and the payload is always hashed.
+//! See https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv-create-signed-request.html
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use model::{
+    common::{
+        chrono::{DateTime, Utc},
+        eyre::{Result, eyre},
+        itertools::Itertools,
+    },
+    secrets,
+};
+
+/// AWS credentials used to sign requests
+#[derive(Clone)]
+pub(crate) struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Get credentials from the standard AWS env vars or secrets
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            access_key_id: secrets::env_or_get(secrets::AWS_ACCESS_KEY_ID)?,
+            secret_access_key: secrets::env_or_get(secrets::AWS_SECRET_ACCESS_KEY)?,
+            session_token: secrets::env_or_get("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// A request to be signed
+pub(crate) struct Request<'req> {
+    pub method: &'req str,
+    pub host: &'req str,
+
+    /// The URI encoded path of the request
+    pub path: &'req str,
+
+    /// The query parameters of the request (not encoded)
+    pub query: &'req [(&'req str, &'req str)],
+
+    pub body: &'req [u8],
+}
+
+/// URI encode a string as required by SigV4
+///
+/// All characters other than unreserved characters are percent encoded.
+/// Forward slashes are only encoded if `slash` is true.
+pub(crate) fn uri_encode(value: &str, slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Calculate the hex encoded SHA256 hash of some bytes
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Calculate the HMAC-SHA256 of a message
+fn hmac_sha256(key: &[u8], message: &str) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|error| eyre!(error))?;
+    mac.update(message.as_bytes());
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Sign a request
+///
+/// Returns the headers (including `Authorization`) which must be added to the request.
+pub(crate) fn sign(
+    request: &Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    time: DateTime<Utc>,
+) -> Result<Vec<(String, String)>> {
+    let datetime = time.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &datetime[..8];
+
+    let mut headers = vec![
+        ("host".to_string(), request.host.to_string()),
+        ("x-amz-date".to_string(), datetime.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    headers.sort();
+
+    // For services other than S3, each segment of the (already encoded) path is encoded again
+    let canonical_uri = request
+        .path
+        .split('/')
+        .map(|segment| uri_encode(segment, true))
+        .join("/");
+    let canonical_query = request
+        .query
+        .iter()
+        .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+        .sorted()
+        .join("&");
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .join("");
+    let signed_headers = headers.iter().map(|(name, ..)| name).join(";");
+
+    let canonical_request = [
+        request.method,
+        &canonical_uri,
+        &canonical_query,
+        &canonical_headers,
+        &signed_headers,
+        &sha256_hex(request.body),
+    ]
+    .join("\n");
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{datetime}\n{scope}\n{}",
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date,
+    )?;
+    let key = hmac_sha256(&key, region)?;
+    let key = hmac_sha256(&key, service)?;
+    let key = hmac_sha256(&key, "aws4_request")?;
+    let signature = hex::encode(hmac_sha256(&key, &string_to_sign)?);
+
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    );
+
+    Ok(headers
+        .into_iter()
+        .filter(|(name, ..)| name != "host")
+        .chain([("authorization".to_string(), authorization)])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use model::common::chrono::TimeZone;
+
+    use super::*;
+
+    /// Test using the `get-vanilla` and `get-vanilla-query-order-key-case`
+    /// examples of the AWS SigV4 test suite
+    #[test]
+    fn test_suite() -> Result<()> {
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let time = Utc
+            .with_ymd_and_hms(2015, 8, 30, 12, 36, 0)
+            .single()
+            .ok_or_else(|| eyre!("invalid time"))?;
+        let authorization = |query: &[(&str, &str)]| -> Result<String> {
+            let headers = sign(
+                &Request {
+                    method: "GET",
+                    host: "example.amazonaws.com",
+                    path: "/",
+                    query,
+                    body: &[],
+                },
+                &credentials,
+                "us-east-1",
+                "service",
+                time,
+            )?;
+            Ok(headers
+                .into_iter()
+                .find(|(name, ..)| name == "authorization")
+                .map(|(.., value)| value)
+                .unwrap_or_default())
+        };
+
+        assert_eq!(
+            authorization(&[])?,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        assert!(
+            authorization(&[("Param2", "value2"), ("Param1", "value1")])?.ends_with(
+                "Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(
+            uri_encode("anthropic.claude-v2:1", true),
+            "anthropic.claude-v2%3A1"
+        );
+        assert_eq!(uri_encode("a b/c", false), "a%20b/c");
+    }
+}
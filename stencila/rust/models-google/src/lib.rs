@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Duration};
 use cached::proc_macro::cached;
 
 use model::{
-    Model, ModelIO, ModelOutput, ModelTask, ModelType,
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning, api_key,
     common::{
         async_trait::async_trait,
         eyre::{Result, bail},
@@ -13,12 +13,18 @@ use model::{
         serde_with::skip_serializing_none,
         tracing,
     },
+    models_config,
     schema::{ImageObject, MessagePart, MessageRole},
     secrets,
 };
 
 const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
 
+/// Get the base URL of the Google API from the models config
+fn base_url() -> String {
+    models_config().requests.base_url("google", BASE_URL)
+}
+
 /// The name of the env var or secret for the API key
 const API_KEY: &str = "GOOGLE_AI_API_KEY";
 
@@ -35,12 +41,12 @@ struct GoogleModel {
 
 impl GoogleModel {
     /// Create a Google AI model
-    fn new(model: &str, context_length: usize) -> Self {
-        Self {
+    fn new(model: &str, context_length: usize) -> Result<Self> {
+        Ok(Self {
             model: model.into(),
             context_length,
-            client: Client::new(),
-        }
+            client: models_config().requests.http_client()?,
+        })
     }
 }
 
@@ -72,22 +78,29 @@ impl Model for GoogleModel {
 
     #[tracing::instrument(skip(self))]
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+
         let mut system_instruction = None;
         let contents = task
             .messages
             .iter()
             .flat_map(|message| {
-                if matches!(message.role, Some(MessageRole::System)) {
+                if matches!(
+                    message.role,
+                    Some(MessageRole::System | MessageRole::Developer)
+                ) {
                     let parts = message
                         .parts
                         .iter()
                         .filter_map(|part| match part {
                             MessagePart::Text(text) => Some(Part::text(&text.value)),
                             _ => {
-                                tracing::warn!(
+                                warnings.push(ModelWarning::ignored_part(format!(
                                     "System message part `{part}` is ignored by model `{}`",
                                     self.id()
-                                );
+                                )));
                                 None
                             }
                         })
@@ -114,18 +127,18 @@ impl Model for GoogleModel {
                                 let base64 = &content_url[(pos + 8)..];
                                 Some(Part::inline_data(mime_type, base64))
                             } else {
-                                tracing::warn!(
+                                warnings.push(ModelWarning::ignored_part(format!(
                                     "Image does not appear to have a DataURI so was ignored by model `{}`",
                                     self.id()
-                                );
+                                )));
                                 None
                             }
                         },
                         _ => {
-                            tracing::warn!(
+                            warnings.push(ModelWarning::ignored_part(format!(
                                 "User message part `{part}` is ignored by model `{}`",
                                 self.id()
-                            );
+                            )));
                             None
                         }
                     })
@@ -139,6 +152,7 @@ impl Model for GoogleModel {
             contents,
             system_instruction,
             generation_config: Some(GenerationConfig {
+                stop_sequences: task.stop.clone(),
                 max_output_tokens: task.max_tokens,
                 temperature: task.temperature,
                 top_p: task.top_p,
@@ -155,9 +169,10 @@ impl Model for GoogleModel {
             .client
             .post(format!(
                 "{}/models/{}:generateContent",
-                BASE_URL, self.model
+                base_url(),
+                self.model
             ))
-            .query(&[("key", secrets::env_or_get(API_KEY)?)])
+            .query(&[("key", api_key(API_KEY, &self.id(), Some(task))?)])
             .json(&request)
             .send()
             .await?;
@@ -176,18 +191,22 @@ impl Model for GoogleModel {
             .parts
             .swap_remove(0);
 
-        match content {
+        let mut output = match content {
             Part {
                 text: Some(text), ..
-            } => ModelOutput::from_text(self, &task.format, text).await,
+            } => ModelOutput::from_text(self, &task.format, text).await?,
             Part {
                 inline_data: Some(Blob { mime_type, data }),
                 ..
             } => {
-                ModelOutput::from_url(self, &mime_type, format!("{mime_type};base64,{data}")).await
+                ModelOutput::from_url(self, &mime_type, format!("{mime_type};base64,{data}"))
+                    .await?
             }
             _ => bail!("Unexpected response content part"),
-        }
+        };
+        output.warnings = warnings;
+
+        Ok(output)
     }
 }
 
@@ -362,9 +381,9 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
             let name = model.name.strip_prefix("models/").unwrap_or(&model.name);
             let context_length = model.input_token_limit.unwrap_or(4_096);
 
-            Arc::new(GoogleModel::new(name, context_length)) as Arc<dyn Model>
+            Ok(Arc::new(GoogleModel::new(name, context_length)?) as Arc<dyn Model>)
         })
-        .collect();
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(models)
 }
@@ -374,8 +393,10 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
 /// In-memory cached for six hours to reduce requests to remote API.
 #[cached(time = 21_600, result = true)]
 async fn list_google_models() -> Result<ModelsResponse> {
-    let response = Client::new()
-        .get(format!("{BASE_URL}/models"))
+    let response = models_config()
+        .requests
+        .http_client()?
+        .get(format!("{}/models", base_url()))
         .query(&[("key", secrets::env_or_get(API_KEY)?)])
         .send()
         .await?;
@@ -412,7 +433,7 @@ mod tests {
             return Ok(());
         }
 
-        let model = GoogleModel::new("gemini-2.0-flash-001", 0);
+        let model = GoogleModel::new("gemini-2.0-flash-001", 0)?;
         let output = model.perform_task(&test_task_repeat_word()).await?;
 
         assert_eq!(output.content.trim(), "HELLO".to_string());
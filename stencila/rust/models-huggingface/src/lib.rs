@@ -0,0 +1,479 @@
+use std::sync::Arc;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelTask, ModelTaskKind, ModelType, ModelWarning, api_key,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail},
+        itertools::Itertools,
+        reqwest::Client,
+        serde::{Deserialize, Serialize},
+        serde_with::skip_serializing_none,
+        tracing,
+    },
+    models_config,
+    schema::{ImageObject, MessagePart, MessageRole},
+    secrets,
+};
+
+const BASE_URL: &str = "https://api-inference.huggingface.co/models";
+
+/// Get the base URL of the Hugging Face Inference API from the models config
+///
+/// Set the `huggingface` entry in the `[requests.base-urls]` of the models config
+/// to use a dedicated Inference Endpoint or self-hosted Text Generation Inference
+/// (TGI) server.
+fn base_url() -> String {
+    models_config().requests.base_url("huggingface", BASE_URL)
+}
+
+/// The name of the env var or secret for the access token
+const API_KEY: &str = "HF_TOKEN";
+
+/// The task that a Hugging Face model performs
+#[derive(Clone, Copy, PartialEq)]
+enum Capability {
+    /// Chat completion using the OpenAI compatible Messages API of TGI
+    TextGeneration,
+
+    /// Captioning of an image sent as raw bytes
+    ImageCaptioning,
+}
+
+/// Popular models hosted on the Inference API and their capabilities and context lengths
+///
+/// Will need to be updated periodically as models are added to, or removed from, the API.
+const MODELS: &[(&str, Capability, usize)] = &[
+    (
+        "meta-llama/Llama-3.1-8B-Instruct",
+        Capability::TextGeneration,
+        128_000,
+    ),
+    (
+        "meta-llama/Llama-3.3-70B-Instruct",
+        Capability::TextGeneration,
+        128_000,
+    ),
+    (
+        "mistralai/Mistral-7B-Instruct-v0.3",
+        Capability::TextGeneration,
+        32_768,
+    ),
+    (
+        "Qwen/Qwen2.5-72B-Instruct",
+        Capability::TextGeneration,
+        32_768,
+    ),
+    ("google/gemma-2-9b-it", Capability::TextGeneration, 8_192),
+    (
+        "HuggingFaceH4/zephyr-7b-beta",
+        Capability::TextGeneration,
+        4_096,
+    ),
+    (
+        "Salesforce/blip-image-captioning-large",
+        Capability::ImageCaptioning,
+        512,
+    ),
+    (
+        "nlpconnect/vit-gpt2-image-captioning",
+        Capability::ImageCaptioning,
+        512,
+    ),
+];
+
+struct HuggingFaceModel {
+    /// The Hugging Face id of the model e.g. `mistralai/Mistral-7B-Instruct-v0.3`
+    model: String,
+
+    /// The task that the model performs
+    capability: Capability,
+
+    /// The context length of the model
+    context_length: usize,
+
+    /// The HTTP client
+    client: Client,
+}
+
+impl HuggingFaceModel {
+    /// Create a Hugging Face model
+    fn new(model: &str, capability: Capability, context_length: usize) -> Result<Self> {
+        Ok(Self {
+            model: model.into(),
+            capability,
+            context_length,
+            client: models_config().requests.http_client()?,
+        })
+    }
+
+    /// Generate text using the Messages API
+    async fn generate_text(
+        &self,
+        task: &ModelTask,
+        warnings: &mut Vec<ModelWarning>,
+    ) -> Result<Option<String>> {
+        let messages = task
+            .messages
+            .iter()
+            .map(|message| {
+                let role = match message.role.unwrap_or_default() {
+                    MessageRole::Model => ChatRole::Assistant,
+                    MessageRole::System | MessageRole::Developer => ChatRole::System,
+                    MessageRole::User => ChatRole::User,
+                };
+
+                let content = message
+                    .parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        MessagePart::Text(text) => Some(text.to_value_string()),
+                        _ => {
+                            warnings.push(ModelWarning::ignored_part(format!(
+                                "Message part of type `{part}` is ignored by model `{}`",
+                                self.id()
+                            )));
+                            None
+                        }
+                    })
+                    .join("");
+
+                ChatMessage { role, content }
+            })
+            .collect();
+
+        let request = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: task.temperature,
+            top_p: task.top_p,
+            max_tokens: task.max_tokens,
+            seed: task.seed,
+        };
+
+        if task.dry_run {
+            return Ok(None);
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/{}/v1/chat/completions", base_url(), self.model))
+            .bearer_auth(api_key(API_KEY, &self.id(), Some(task))?)
+            .json(&request)
+            .send()
+            .await?;
+
+        if let Err(error) = response.error_for_status_ref() {
+            let message = response.text().await?;
+            bail!("{error}: {message}");
+        }
+
+        let response: ChatCompletionResponse = response.json().await?;
+        let Some(choice) = response.choices.into_iter().next() else {
+            bail!("No choices in response from model `{}`", self.id())
+        };
+
+        Ok(Some(choice.message.content))
+    }
+
+    /// Caption the last image in the messages of a task
+    ///
+    /// Captioning models do not accept text so any text parts are ignored.
+    async fn caption_image(
+        &self,
+        task: &ModelTask,
+        warnings: &mut Vec<ModelWarning>,
+    ) -> Result<Option<String>> {
+        let mut image = None;
+        for message in &task.messages {
+            for part in &message.parts {
+                match part {
+                    MessagePart::ImageObject(ImageObject { content_url, .. }) => {
+                        image = Some(content_url)
+                    }
+                    MessagePart::Text(..) => {}
+                    _ => warnings.push(ModelWarning::ignored_part(format!(
+                        "Message part of type `{part}` is ignored by model `{}`",
+                        self.id()
+                    ))),
+                }
+            }
+        }
+
+        let Some(image) = image else {
+            bail!("Model `{}` requires an image to caption", self.id())
+        };
+        let Some((media_type, data)) = image
+            .strip_prefix("data:")
+            .and_then(|uri| uri.split_once(";base64,"))
+        else {
+            bail!(
+                "Image does not appear to have a DataURI so can not be sent to model `{}`",
+                self.id()
+            )
+        };
+        let bytes = BASE64.decode(data)?;
+
+        if task.dry_run {
+            return Ok(None);
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/{}", base_url(), self.model))
+            .bearer_auth(api_key(API_KEY, &self.id(), Some(task))?)
+            .header("content-type", media_type)
+            .body(bytes)
+            .send()
+            .await?;
+
+        if let Err(error) = response.error_for_status_ref() {
+            let message = response.text().await?;
+            bail!("{error}: {message}");
+        }
+
+        let response: Vec<GeneratedText> = response.json().await?;
+        let Some(generated) = response.into_iter().next() else {
+            bail!("No caption in response from model `{}`", self.id())
+        };
+
+        Ok(Some(generated.generated_text))
+    }
+}
+
+#[async_trait]
+impl Model for HuggingFaceModel {
+    fn id(&self) -> String {
+        format!("huggingface/{}", self.model)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Remote
+    }
+
+    fn provider(&self) -> String {
+        "Hugging Face".to_string()
+    }
+
+    fn name(&self) -> String {
+        let name = self
+            .model
+            .rsplit_once('/')
+            .map_or(self.model.as_str(), |(.., name)| name);
+        name.split_once('-')
+            .map_or(name, |(name, ..)| name)
+            .to_string()
+    }
+
+    fn version(&self) -> String {
+        let name = self
+            .model
+            .rsplit_once('/')
+            .map_or(self.model.as_str(), |(.., name)| name);
+        name.split_once('-')
+            .map(|(.., version)| version)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn context_length(&self) -> usize {
+        self.context_length
+    }
+
+    fn supports_task(&self, task: &ModelTask) -> bool {
+        if !self.supported_task_kinds().contains(&task.kind) {
+            return false;
+        }
+
+        // Captioning models can only perform tasks with an image
+        self.capability == Capability::TextGeneration
+            || task.messages.iter().any(|message| {
+                message
+                    .parts
+                    .iter()
+                    .any(|part| matches!(part, MessagePart::ImageObject(..)))
+            })
+    }
+
+    fn supported_task_kinds(&self) -> &[ModelTaskKind] {
+        &[ModelTaskKind::MessageGeneration]
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        match self.capability {
+            Capability::TextGeneration => &[ModelIO::Text],
+            Capability::ImageCaptioning => &[ModelIO::Image],
+        }
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+
+        let text = match self.capability {
+            Capability::TextGeneration => self.generate_text(task, &mut warnings).await?,
+            Capability::ImageCaptioning => self.caption_image(task, &mut warnings).await?,
+        };
+        let Some(text) = text else {
+            return ModelOutput::empty(self);
+        };
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
+    }
+}
+
+/// A chat completion request to the Messages API
+///
+/// Based on https://huggingface.co/docs/text-generation-inference/messages_api
+#[skip_serializing_none]
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u16>,
+    seed: Option<i32>,
+}
+
+/// A chat completion response
+///
+/// Note: at present several other fields are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionResponse {
+    choices: Vec<ChatCompletionChoice>,
+}
+
+/// A choice within a `ChatCompletionResponse`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionChoice {
+    message: ChatMessage,
+}
+
+/// A chat message within a `ChatCompletionRequest` or a `ChatCompletionResponse`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatMessage {
+    role: ChatRole,
+    content: String,
+}
+
+/// A role in a `ChatMessage`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", crate = "model::common::serde")]
+enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// An item in the response from an image captioning model
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct GeneratedText {
+    generated_text: String,
+}
+
+/// Get a list of available Hugging Face models
+///
+/// Returns an empty list if the `HF_TOKEN` env var is not set. Unlike other
+/// providers, the list is not fetched from the API (which hosts many thousands
+/// of models, of varying quality) but is a curated list of popular models.
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    if secrets::env_or_get(API_KEY).is_err() {
+        tracing::trace!("The environment variable or secret `{API_KEY}` is not available");
+        return Ok(vec![]);
+    };
+
+    MODELS
+        .iter()
+        .map(|&(model, capability, context_length)| {
+            Ok(
+                Arc::new(HuggingFaceModel::new(model, capability, context_length)?)
+                    as Arc<dyn Model>,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{
+        common::tokio,
+        schema::{InstructionMessage, MessagePart},
+        test_task_repeat_word,
+    };
+
+    #[tokio::test]
+    async fn models() -> Result<()> {
+        let model = HuggingFaceModel::new(
+            "mistralai/Mistral-7B-Instruct-v0.3",
+            Capability::TextGeneration,
+            32_768,
+        )?;
+        assert_eq!(model.id(), "huggingface/mistralai/Mistral-7B-Instruct-v0.3");
+        assert_eq!(model.name(), "Mistral");
+        assert_eq!(model.version(), "7B-Instruct-v0.3");
+
+        let captioner = HuggingFaceModel::new(
+            "Salesforce/blip-image-captioning-large",
+            Capability::ImageCaptioning,
+            512,
+        )?;
+        assert_eq!(captioner.supported_inputs(), &[ModelIO::Image]);
+        assert!(model.supports_task(&test_task_repeat_word()));
+        assert!(!captioner.supports_task(&test_task_repeat_word()));
+
+        let mut task = ModelTask {
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut warnings = Vec::new();
+        assert!(captioner.caption_image(&task, &mut warnings).await.is_err());
+
+        task.messages = vec![InstructionMessage {
+            role: Some(MessageRole::User),
+            parts: vec![MessagePart::ImageObject(ImageObject::new(
+                "data:image/png;base64,AAAA".into(),
+            ))],
+            ..Default::default()
+        }];
+        assert!(captioner.supports_task(&task));
+        assert_eq!(captioner.caption_image(&task, &mut warnings).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn perform_task() -> Result<()> {
+        if secrets::env_or_get(API_KEY).is_err() {
+            return Ok(());
+        }
+
+        let model = HuggingFaceModel::new(
+            "meta-llama/Llama-3.1-8B-Instruct",
+            Capability::TextGeneration,
+            128_000,
+        )?;
+        let output = model.perform_task(&test_task_repeat_word()).await?;
+
+        assert_eq!(output.content.trim(), "HELLO");
+
+        Ok(())
+    }
+}
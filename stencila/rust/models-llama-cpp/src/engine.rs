@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    fs::metadata,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use llama_cpp_2::{
+    context::params::LlamaContextParams,
+    llama_backend::LlamaBackend,
+    llama_batch::LlamaBatch,
+    model::{AddBos, LlamaChatMessage, LlamaModel, Special, params::LlamaModelParams},
+    sampling::LlamaSampler,
+};
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail, eyre},
+        once_cell::sync::{Lazy, OnceCell},
+        tokio::task::spawn_blocking,
+        tracing,
+    },
+    schema::{MessagePart, MessageRole},
+};
+
+use crate::{RuntimeOptions, gguf, model_name};
+
+/// The llama.cpp backend
+///
+/// Can only be initialized once per process.
+static BACKEND: OnceCell<LlamaBackend> = OnceCell::new();
+
+/// Get the llama.cpp backend, initializing it if necessary
+fn backend() -> Result<&'static LlamaBackend> {
+    BACKEND.get_or_try_init(|| {
+        let mut backend = LlamaBackend::init()?;
+        backend.void_logs();
+        Ok(backend)
+    })
+}
+
+/// Loaded models, keyed by path and number of GPU layers
+///
+/// Loading a model can take many seconds so models are kept in memory for reuse.
+static LOADED: Lazy<Mutex<HashMap<(PathBuf, u32), Arc<LlamaModel>>>> = Lazy::new(Mutex::default);
+
+/// Load a model, or get it if already loaded
+fn load(path: &PathBuf, gpu_layers: u32) -> Result<Arc<LlamaModel>> {
+    let key = (path.clone(), gpu_layers);
+    if let Some(model) = LOADED.lock().map_err(|error| eyre!("{error}"))?.get(&key) {
+        return Ok(model.clone());
+    }
+
+    tracing::debug!("Loading GGUF model from `{}`", path.display());
+    let params = LlamaModelParams::default().with_n_gpu_layers(gpu_layers);
+    let model = Arc::new(LlamaModel::load_from_file(backend()?, path, &params)?);
+
+    LOADED
+        .lock()
+        .map_err(|error| eyre!("{error}"))?
+        .insert(key, model.clone());
+
+    Ok(model)
+}
+
+/// Context lengths read from the headers of GGUF files, keyed by path and modification time
+///
+/// Models are created each time they are listed so this avoids reading the
+/// header of every GGUF file each time, while still noticing replaced files.
+static CONTEXT_LENGTHS: Lazy<Mutex<HashMap<(PathBuf, SystemTime), Option<usize>>>> =
+    Lazy::new(Mutex::default);
+
+/// Get the context length a model was trained with, reading it from its GGUF file if necessary
+fn context_length(path: &Path) -> Option<usize> {
+    let modified = metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let key = (path.to_path_buf(), modified);
+    if let Some(length) = CONTEXT_LENGTHS
+        .lock()
+        .ok()
+        .and_then(|lengths| lengths.get(&key).copied())
+    {
+        return length;
+    }
+
+    let length = gguf::context_length(path).unwrap_or_else(|error| {
+        tracing::debug!("Unable to read header of `{}`: {error}", path.display());
+        None
+    });
+
+    if let Ok(mut lengths) = CONTEXT_LENGTHS.lock() {
+        lengths.retain(|(cached, ..), ..| cached != path);
+        lengths.insert(key, length);
+    }
+
+    length
+}
+
+/// A GGUF model run in-process using llama.cpp
+pub(crate) struct LlamaCppModel {
+    /// The path of the GGUF file
+    path: PathBuf,
+
+    /// The name of the model, derived from the file name
+    name: String,
+
+    /// The context length the model was trained with, read from the header of the GGUF file
+    context_length: Option<usize>,
+}
+
+impl LlamaCppModel {
+    /// Create a model from the path of its GGUF file
+    pub fn new(path: PathBuf) -> Self {
+        let name = model_name(&path);
+        let context_length = context_length(&path);
+        Self {
+            path,
+            name,
+            context_length,
+        }
+    }
+}
+
+#[async_trait]
+impl Model for LlamaCppModel {
+    fn id(&self) -> String {
+        format!("llama-cpp/{}", self.name)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Local
+    }
+
+    fn provider(&self) -> String {
+        "llama.cpp".to_string()
+    }
+
+    fn context_length(&self) -> usize {
+        // Fall back to the loaded model if the header of the file could not be read
+        self.context_length
+            .or_else(|| {
+                LOADED.lock().ok().and_then(|loaded| {
+                    loaded
+                        .iter()
+                        .find(|((path, ..), ..)| path == &self.path)
+                        .map(|(.., model)| model.n_ctx_train() as usize)
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        let mut warnings = Vec::new();
+
+        let messages = task
+            .messages
+            .iter()
+            .map(|message| {
+                let role = match message.role.unwrap_or_default() {
+                    MessageRole::Model => "assistant",
+                    MessageRole::System | MessageRole::Developer => "system",
+                    MessageRole::User => "user",
+                };
+
+                let mut content = String::new();
+                for part in &message.parts {
+                    match part {
+                        MessagePart::Text(text) => content += &text.value,
+                        _ => warnings.push(ModelWarning::ignored_part(format!(
+                            "Message part `{part}` is ignored by model `{}`",
+                            self.id()
+                        ))),
+                    }
+                }
+
+                (role.to_string(), content)
+            })
+            .collect::<Vec<_>>();
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        let path = self.path.clone();
+        let options = RuntimeOptions::from_task(task);
+        let sampling = Sampling {
+            temperature: task.temperature,
+            top_p: task.top_p,
+            seed: task.seed,
+            max_tokens: task.max_tokens,
+        };
+        let text = spawn_blocking(move || generate(&path, options, sampling, messages)).await??;
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
+    }
+}
+
+/// Sampling options for generation
+struct Sampling {
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    seed: Option<i32>,
+    max_tokens: Option<u16>,
+}
+
+/// Generate text for chat messages
+///
+/// Blocks while the model is loaded and text generated so should be run on a
+/// blocking thread.
+fn generate(
+    path: &PathBuf,
+    options: RuntimeOptions,
+    sampling: Sampling,
+    messages: Vec<(String, String)>,
+) -> Result<String> {
+    let model = load(path, options.gpu_layers)?;
+
+    let messages = messages
+        .into_iter()
+        .map(|(role, content)| LlamaChatMessage::new(role, content))
+        .collect::<Result<Vec<_>, _>>()?;
+    let template = model.chat_template(None)?;
+    let prompt = model.apply_chat_template(&template, &messages, true)?;
+    let tokens = model.str_to_token(&prompt, AddBos::Always)?;
+
+    let context_length = options.context_length.unwrap_or(model.n_ctx_train());
+    let max_tokens = sampling
+        .max_tokens
+        .map_or(context_length as usize, |max| max as usize);
+    if tokens.len() >= context_length as usize {
+        bail!(
+            "Prompt of {} tokens exceeds the context length of {context_length}",
+            tokens.len()
+        );
+    }
+
+    let mut params = LlamaContextParams::default().with_n_ctx(NonZeroU32::new(context_length));
+    if let Some(threads) = options.threads {
+        params = params
+            .with_n_threads(threads as i32)
+            .with_n_threads_batch(threads as i32);
+    }
+    let mut context = model.new_context(backend()?, params)?;
+
+    let mut batch = LlamaBatch::new(tokens.len().max(512), 1);
+    let last = tokens.len().saturating_sub(1);
+    for (index, token) in tokens.iter().enumerate() {
+        batch.add(*token, index as i32, &[0], index == last)?;
+    }
+    context.decode(&mut batch)?;
+
+    let seed = sampling.seed.map_or(1234, |seed| seed as u32);
+    let mut sampler = match sampling.temperature {
+        Some(temperature) if temperature <= 0.0 => LlamaSampler::greedy(),
+        temperature => LlamaSampler::chain_simple([
+            LlamaSampler::top_p(sampling.top_p.unwrap_or(1.0), 1),
+            LlamaSampler::temp(temperature.unwrap_or(0.8)),
+            LlamaSampler::dist(seed),
+        ]),
+    };
+
+    let mut text = String::new();
+    let mut position = tokens.len() as i32;
+    for _ in 0..max_tokens {
+        let token = sampler.sample(&context, batch.n_tokens() - 1);
+        sampler.accept(token);
+        if model.is_eog_token(token) {
+            break;
+        }
+
+        text += &model.token_to_str(token, Special::Tokenize)?;
+
+        batch.clear();
+        batch.add(token, position, &[0], true)?;
+        position += 1;
+        if position as u32 >= context_length {
+            tracing::warn!("Generation stopped at context length of {context_length}");
+            break;
+        }
+        context.decode(&mut batch)?;
+    }
+
+    Ok(text)
+}
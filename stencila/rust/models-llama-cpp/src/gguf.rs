@@ -0,0 +1,211 @@
+//! Reading of the metadata in the header of GGUF files
+//!
+//! See https://github.com/ggml-org/ggml/blob/master/docs/gguf.md for the format.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use model::common::eyre::{Result, bail};
+
+/// The metadata value type of strings
+const STRING: u32 = 8;
+
+/// The metadata value type of arrays
+const ARRAY: u32 = 9;
+
+/// The maximum length of the metadata keys, and string values, read from a GGUF file
+const MAX_STRING_LENGTH: u64 = 65_536;
+
+/// Read the context length a model was trained with from the header of its GGUF file
+///
+/// Allows the context length of a model to be known when it is listed, rather than
+/// only once it has been loaded. Returns `None` if the metadata in the header has no
+/// `<architecture>.context_length` key.
+pub(crate) fn context_length(path: &Path) -> Result<Option<usize>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"GGUF" {
+        bail!("File `{}` is not a GGUF file", path.display())
+    }
+    let version = read_u32(&mut reader)?;
+    if version < 2 {
+        bail!("GGUF version {version} is not supported")
+    }
+    let _tensor_count = read_u64(&mut reader)?;
+    let metadata_count = read_u64(&mut reader)?;
+
+    let mut architecture = None;
+    let mut lengths = Vec::new();
+    for _ in 0..metadata_count {
+        let key = read_string(&mut reader)?;
+        let value_type = read_u32(&mut reader)?;
+        if key == "general.architecture" && value_type == STRING {
+            architecture = Some(read_string(&mut reader)?);
+        } else if let Some(arch) = key.strip_suffix(".context_length")
+            && let Some(length) = read_integer(&mut reader, value_type)?
+        {
+            lengths.push((arch.to_string(), length));
+        } else {
+            skip_value(&mut reader, value_type)?;
+        }
+    }
+
+    Ok(lengths
+        .iter()
+        .find(|(arch, ..)| Some(arch) == architecture.as_ref())
+        .or(lengths.first())
+        .map(|&(.., length)| length))
+}
+
+/// Read a little-endian `u32` from a GGUF file
+fn read_u32(reader: &mut BufReader<File>) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read a little-endian `u64` from a GGUF file
+fn read_u64(reader: &mut BufReader<File>) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read a length-prefixed string from a GGUF file
+fn read_string(reader: &mut BufReader<File>) -> Result<String> {
+    let length = read_u64(reader)?;
+    if length > MAX_STRING_LENGTH {
+        bail!("GGUF string of {length} bytes is too long")
+    }
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Read an integer metadata value from a GGUF file
+///
+/// Values of other types are skipped and `None` returned.
+fn read_integer(reader: &mut BufReader<File>, value_type: u32) -> Result<Option<usize>> {
+    Ok(match value_type {
+        4 | 5 => Some(read_u32(reader)? as usize),
+        10 | 11 => Some(read_u64(reader)? as usize),
+        _ => {
+            skip_value(reader, value_type)?;
+            None
+        }
+    })
+}
+
+/// Skip a metadata value in a GGUF file
+fn skip_value(reader: &mut BufReader<File>, value_type: u32) -> Result<()> {
+    let size = |value_type: u32| -> Option<u64> {
+        match value_type {
+            0 | 1 | 7 => Some(1),
+            2 | 3 => Some(2),
+            4..=6 => Some(4),
+            10..=12 => Some(8),
+            _ => None,
+        }
+    };
+
+    match value_type {
+        STRING => {
+            let length = read_u64(reader)?;
+            reader.seek_relative(i64::try_from(length)?)?;
+        }
+        ARRAY => {
+            let element_type = read_u32(reader)?;
+            let count = read_u64(reader)?;
+            match size(element_type) {
+                Some(size) => {
+                    let Some(length) = size.checked_mul(count) else {
+                        bail!("GGUF array of {count} elements is too long")
+                    };
+                    reader.seek_relative(i64::try_from(length)?)?;
+                }
+                None => {
+                    for _ in 0..count {
+                        skip_value(reader, element_type)?;
+                    }
+                }
+            }
+        }
+        _ => {
+            let Some(size) = size(value_type) else {
+                bail!("GGUF value type {value_type} is not known")
+            };
+            reader.seek_relative(size as i64)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use model::common::tempfile::tempdir;
+
+    use super::*;
+
+    /// Encode a GGUF header with metadata
+    fn header(metadata: &[(&str, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = b"GGUF".to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        bytes.extend((metadata.len() as u64).to_le_bytes());
+        for (key, value_type, value) in metadata {
+            bytes.extend((key.len() as u64).to_le_bytes());
+            bytes.extend(key.as_bytes());
+            bytes.extend(value_type.to_le_bytes());
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = (value.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(value.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn reads_context_length() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("model.gguf");
+
+        let mut tokens = STRING.to_le_bytes().to_vec();
+        tokens.extend(2u64.to_le_bytes());
+        tokens.extend(string("<s>"));
+        tokens.extend(string("</s>"));
+        write(
+            &path,
+            header(&[
+                ("general.architecture", STRING, string("llama")),
+                ("general.name", STRING, string("Llama")),
+                ("tokenizer.ggml.tokens", ARRAY, tokens),
+                ("clip.context_length", 4, 512u32.to_le_bytes().to_vec()),
+                ("llama.context_length", 4, 131_072u32.to_le_bytes().to_vec()),
+                ("llama.block_count", 4, 28u32.to_le_bytes().to_vec()),
+            ]),
+        )?;
+        assert_eq!(context_length(&path)?, Some(131_072));
+
+        write(
+            &path,
+            header(&[("general.architecture", STRING, string("llama"))]),
+        )?;
+        assert_eq!(context_length(&path)?, None);
+
+        write(&path, "not a gguf file")?;
+        assert!(context_length(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! In-process generation using GGUF models and llama.cpp
+//!
+//! GGUF files placed in the `gguf` subdirectory of the Stencila models directory
+//! (or in the directory set by the `STENCILA_GGUF_DIR` env var) are listed as
+//! models with ids of the form `llama-cpp/<file stem>`. No network requests are
+//! made, making these models suitable for air-gapped deployments.
+//!
+//! Generation requires the `llama-cpp` feature (with the `cuda` or `metal` features
+//! for GPU offload). Without it, no models are listed.
+
+use std::{
+    env,
+    fs::read_dir,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use dirs::{DirType, get_app_dir};
+#[cfg(any(feature = "llama-cpp", test))]
+use model::ModelTask;
+#[cfg(not(feature = "llama-cpp"))]
+use model::common::tracing;
+use model::{Model, common::eyre::Result};
+
+#[cfg(feature = "llama-cpp")]
+mod engine;
+#[cfg(any(feature = "llama-cpp", test))]
+mod gguf;
+
+/// Get the directory containing GGUF model files
+pub fn models_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("STENCILA_GGUF_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    Ok(get_app_dir(DirType::Models, false)?.join("gguf"))
+}
+
+/// Find the GGUF model files in a directory
+///
+/// Only the first part of models split across several files
+/// (e.g. `model-00001-of-00003.gguf`) is included because llama.cpp
+/// loads the other parts automatically.
+fn discover(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = Vec::new();
+    for entry in read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"))
+        {
+            continue;
+        }
+
+        // Names of first parts have the split suffix removed so any remaining suffix is for a later part
+        if let Some((.., count)) = model_name(&path).rsplit_once("-of-")
+            && count.chars().all(|c| c.is_ascii_digit())
+        {
+            continue;
+        }
+
+        paths.push(path);
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Get the name of a model from the path of its GGUF file
+///
+/// The file stem, without any split file suffix, is used.
+fn model_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match stem.split_once("-00001-of-") {
+        Some((name, ..)) => name.to_string(),
+        None => stem,
+    }
+}
+
+/// Options for loading and running a model, derived from the options of a task
+#[cfg(any(feature = "llama-cpp", test))]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct RuntimeOptions {
+    /// The number of layers to offload to the GPU(s)
+    ///
+    /// From the task's `num_gpu`. Defaults to zero so that models run on the CPU
+    /// unless offload is explicitly requested.
+    gpu_layers: u32,
+
+    /// The number of threads to use for generation
+    ///
+    /// From the task's `num_thread`. If `None`, llama.cpp's default is used.
+    threads: Option<u32>,
+
+    /// The size of the context window
+    ///
+    /// From the task's `num_ctx`. If `None`, the model's training context length is used.
+    context_length: Option<u32>,
+}
+
+#[cfg(any(feature = "llama-cpp", test))]
+impl RuntimeOptions {
+    /// Get the runtime options for a task
+    fn from_task(task: &ModelTask) -> Self {
+        Self {
+            gpu_layers: task.num_gpu.unwrap_or(0),
+            threads: task.num_thread.filter(|&threads| threads > 0),
+            context_length: task.num_ctx.filter(|&length| length > 0),
+        }
+    }
+}
+
+/// Get a list of available llama.cpp models
+///
+/// Returns an empty list if the `llama-cpp` feature is not enabled or there
+/// are no GGUF files in the [`models_dir`].
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    let paths = discover(&models_dir()?)?;
+
+    #[cfg(feature = "llama-cpp")]
+    {
+        Ok(paths
+            .into_iter()
+            .map(|path| Arc::new(engine::LlamaCppModel::new(path)) as Arc<dyn Model>)
+            .collect())
+    }
+
+    #[cfg(not(feature = "llama-cpp"))]
+    {
+        if !paths.is_empty() {
+            tracing::debug!(
+                "Found {} GGUF models but the `llama-cpp` feature is not enabled",
+                paths.len()
+            );
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use model::common::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn discovers_models() -> Result<()> {
+        let dir = tempdir()?;
+        for file in [
+            "llama-3.2-3b-instruct-q4_k_m.gguf",
+            "qwen2.5-72b-instruct-q4_k_m-00001-of-00002.gguf",
+            "qwen2.5-72b-instruct-q4_k_m-00002-of-00002.gguf",
+            "README.md",
+        ] {
+            write(dir.path().join(file), "")?;
+        }
+
+        let names: Vec<String> = discover(dir.path())?
+            .iter()
+            .map(|path| model_name(path))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "llama-3.2-3b-instruct-q4_k_m",
+                "qwen2.5-72b-instruct-q4_k_m"
+            ]
+        );
+
+        assert!(discover(&dir.path().join("missing"))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_options() {
+        assert_eq!(
+            RuntimeOptions::from_task(&ModelTask::default()),
+            RuntimeOptions::default()
+        );
+
+        let task = ModelTask {
+            num_gpu: Some(99),
+            num_thread: Some(0),
+            num_ctx: Some(8192),
+            ..Default::default()
+        };
+        assert_eq!(
+            RuntimeOptions::from_task(&task),
+            RuntimeOptions {
+                gpu_layers: 99,
+                threads: None,
+                context_length: Some(8192),
+            }
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Duration};
 use cached::proc_macro::cached;
 
 use model::{
-    Model, ModelIO, ModelOutput, ModelTask, ModelType,
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning, api_key,
     common::{
         async_trait::async_trait,
         eyre::{Result, bail},
@@ -14,12 +14,18 @@ use model::{
         serde_with::skip_serializing_none,
         tracing,
     },
-    schema::{MessagePart, MessageRole},
+    models_config,
+    schema::{ImageObject, MessagePart, MessageRole},
     secrets,
 };
 
 const BASE_URL: &str = "https://api.mistral.ai/v1";
 
+/// Get the base URL of the Mistral API from the models config
+fn base_url() -> String {
+    models_config().requests.base_url("mistral", BASE_URL)
+}
+
 /// The name of the env var or secret for the API key
 const API_KEY: &str = "MISTRAL_API_KEY";
 
@@ -30,18 +36,27 @@ struct MistralModel {
     /// The context length of the model
     context_length: usize,
 
+    /// The input types supported by the model
+    inputs: Vec<ModelIO>,
+
     /// The HTTP client
     client: Client,
 }
 
 impl MistralModel {
     /// Create a Mistral model
-    fn new(model: &str, context_length: usize) -> Self {
-        Self {
+    fn new(model: &str, context_length: usize, vision: bool) -> Result<Self> {
+        let mut inputs = vec![ModelIO::Text];
+        if vision {
+            inputs.push(ModelIO::Image);
+        }
+
+        Ok(Self {
             model: model.into(),
             context_length,
-            client: Client::new(),
-        }
+            inputs,
+            client: models_config().requests.http_client()?,
+        })
     }
 }
 
@@ -60,6 +75,8 @@ impl Model for MistralModel {
             "Mistral Nemo".to_string()
         } else if self.model.starts_with("open-mixtral") {
             "Mixtral".to_string()
+        } else if self.model.starts_with("pixtral") {
+            "Pixtral".to_string()
         } else {
             let parts = self.model.split('-').collect_vec();
             if parts.len() > 2 {
@@ -83,7 +100,7 @@ impl Model for MistralModel {
     }
 
     fn supported_inputs(&self) -> &[ModelIO] {
-        &[ModelIO::Text]
+        &self.inputs
     }
 
     fn supported_outputs(&self) -> &[ModelIO] {
@@ -92,30 +109,61 @@ impl Model for MistralModel {
 
     #[tracing::instrument(skip(self))]
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+        let vision = self.inputs.contains(&ModelIO::Image);
+
         let messages = task
             .messages
             .iter()
             .map(|message| {
                 let role = match message.role.unwrap_or_default() {
                     MessageRole::Model => ChatRole::Assistant,
-                    MessageRole::System => ChatRole::System,
+                    MessageRole::System | MessageRole::Developer => ChatRole::System,
                     MessageRole::User => ChatRole::User,
                 };
 
-                let content = message
+                let parts = message
                     .parts
                     .iter()
                     .filter_map(|part: &MessagePart| match part {
-                        MessagePart::Text(text) => Some(text.to_value_string()),
+                        MessagePart::Text(text) => Some(ChatContentPart::Text {
+                            text: text.to_value_string(),
+                        }),
+                        MessagePart::ImageObject(ImageObject { content_url, .. }) if vision => {
+                            Some(ChatContentPart::ImageUrl {
+                                image_url: content_url.clone(),
+                            })
+                        }
                         _ => {
-                            tracing::warn!(
+                            warnings.push(ModelWarning::ignored_part(format!(
                                 "Message part of type `{part}` is ignored by model `{}`",
                                 self.id()
-                            );
+                            )));
                             None
                         }
                     })
-                    .join("");
+                    .collect_vec();
+
+                // Only use an array of content parts if necessary, for compatibility
+                // with older models
+                let content = if parts
+                    .iter()
+                    .all(|part| matches!(part, ChatContentPart::Text { .. }))
+                {
+                    ChatContent::Text(
+                        parts
+                            .into_iter()
+                            .filter_map(|part| match part {
+                                ChatContentPart::Text { text } => Some(text),
+                                _ => None,
+                            })
+                            .join(""),
+                    )
+                } else {
+                    ChatContent::Parts(parts)
+                };
 
                 ChatMessage { role, content }
             })
@@ -136,8 +184,8 @@ impl Model for MistralModel {
 
         let response = self
             .client
-            .post(format!("{BASE_URL}/chat/completions"))
-            .bearer_auth(secrets::env_or_get(API_KEY)?)
+            .post(format!("{}/chat/completions", base_url()))
+            .bearer_auth(api_key(API_KEY, &self.id(), Some(task))?)
             .json(&request)
             .send()
             .await?;
@@ -151,7 +199,10 @@ impl Model for MistralModel {
 
         let text = response.choices.swap_remove(0).message.content;
 
-        ModelOutput::from_text(self, &task.format, text).await
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
     }
 }
 
@@ -171,6 +222,26 @@ struct ModelsResponse {
 #[serde(crate = "model::common::serde")]
 struct ModelSpec {
     id: String,
+
+    #[serde(default)]
+    capabilities: ModelCapabilities,
+}
+
+/// The capabilities of a model within a `ModelSpec`
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, crate = "model::common::serde")]
+struct ModelCapabilities {
+    completion_chat: bool,
+    vision: bool,
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self {
+            completion_chat: true,
+            vision: false,
+        }
+    }
 }
 
 /// A chat completion request
@@ -205,15 +276,41 @@ struct ChatCompletionResponse {
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "model::common::serde")]
 struct ChatCompletionChoice {
-    message: ChatMessage,
+    message: ChatResponseMessage,
 }
 
-/// A chat message within a `ChatCompletionRequest` or a `ChatCompletionResponse`
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize)]
+/// A chat message within a `ChatCompletionRequest`
+#[derive(Serialize)]
 #[serde(crate = "model::common::serde")]
 struct ChatMessage {
     role: ChatRole,
+    content: ChatContent,
+}
+
+/// The content of a `ChatMessage`
+#[derive(Serialize)]
+#[serde(untagged, crate = "model::common::serde")]
+enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+/// A part of the content of a `ChatMessage`
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    crate = "model::common::serde"
+)]
+enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: String },
+}
+
+/// A chat message within a `ChatCompletionResponse`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatResponseMessage {
     content: String,
 }
 
@@ -226,9 +323,86 @@ enum ChatRole {
     Assistant,
 }
 
+/// An embeddings request
+///
+/// Based on https://docs.mistral.ai/api#operation/createEmbedding
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct EmbeddingsRequest<'texts> {
+    model: String,
+    input: &'texts [String],
+}
+
+/// An embeddings response
+///
+/// Note: at present several other fields are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct EmbeddingsResponse {
+    data: Vec<Embedding>,
+}
+
+/// An embedding within an `EmbeddingsResponse`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// The default model used to generate embeddings
+const EMBEDDING_MODEL: &str = "mistral-embed";
+
+/// Generate embeddings for texts
+///
+/// Uses the `mistral-embed` model if no `model` is specified. Embeddings are
+/// returned in the same order as the texts.
+pub async fn embed(texts: &[String], model: Option<&str>) -> Result<Vec<Vec<f32>>> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let model = model.unwrap_or(EMBEDDING_MODEL).to_string();
+    let key = api_key(API_KEY, &format!("mistral/{model}"), None)?;
+
+    let response = models_config()
+        .requests
+        .http_client()?
+        .post(format!("{}/embeddings", base_url()))
+        .bearer_auth(key)
+        .json(&EmbeddingsRequest {
+            model,
+            input: texts,
+        })
+        .send()
+        .await?;
+
+    if let Err(error) = response.error_for_status_ref() {
+        let message = response.text().await?;
+        bail!("{error}: {message}");
+    }
+
+    let response: EmbeddingsResponse = response.json().await?;
+    if response.data.len() != texts.len() {
+        bail!(
+            "Expected {} embeddings but got {}",
+            texts.len(),
+            response.data.len()
+        );
+    }
+
+    Ok(response
+        .data
+        .into_iter()
+        .sorted_by_key(|embedding| embedding.index)
+        .map(|embedding| embedding.embedding)
+        .collect())
+}
+
 /// Get a list of available Mistral models
 ///
 /// Returns an empty list if the `MISTRAL_API_KEY` env var is not set.
+/// Models which do not support chat completions (e.g. embedding models) are not listed.
 ///
 /// Memoized for two minutes to avoid loading from disk cache too frequently
 /// but allowing user to set API key while process is running.
@@ -245,29 +419,48 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
         .await?
         .data
         .into_iter()
-        .filter(|ModelSpec { id: model }| {
-            // Only include models with numeric version (not un-versioned or latest)
-            let parts = model.split('-').collect_vec();
-            ((model.starts_with("codestral") && parts.len() == 2) || parts.len() >= 3)
-                && parts
-                    .last()
-                    .map(|&version| version.starts_with('2'))
-                    .unwrap_or(false)
-        })
+        .filter(
+            |ModelSpec {
+                 id: model,
+                 capabilities,
+             }| {
+                if !capabilities.completion_chat {
+                    return false;
+                }
+
+                // Only include models with numeric version (not un-versioned or latest)
+                let parts = model.split('-').collect_vec();
+                ((model.starts_with("codestral") && parts.len() == 2) || parts.len() >= 3)
+                    && parts
+                        .last()
+                        .map(|&version| version.starts_with('2'))
+                        .unwrap_or(false)
+            },
+        )
         .sorted_by(|a, b| a.id.cmp(&b.id))
-        .map(|ModelSpec { id: model }| {
-            let (name, _version) = model.rsplit_once('-').unwrap_or_default();
-            let context_length = match name {
-                "mistral-tiny" => 4_096,
-                "mistral-small" => 8_192,
-                "mistral-medium" => 32_768,
-                "mistral-large" => 128_000,
-                _ => 4_096,
-            };
-
-            Arc::new(MistralModel::new(&model, context_length)) as Arc<dyn Model>
-        })
-        .collect();
+        .map(
+            |ModelSpec {
+                 id: model,
+                 capabilities,
+             }| {
+                let (name, _version) = model.rsplit_once('-').unwrap_or_default();
+                let context_length = match name {
+                    "mistral-tiny" => 4_096,
+                    "mistral-small" => 8_192,
+                    "mistral-medium" => 32_768,
+                    "mistral-large" => 128_000,
+                    name if name.starts_with("pixtral") => 128_000,
+                    _ => 4_096,
+                };
+
+                Ok(Arc::new(MistralModel::new(
+                    &model,
+                    context_length,
+                    capabilities.vision,
+                )?) as Arc<dyn Model>)
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(models)
 }
@@ -277,8 +470,10 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
 /// In-memory cached for six hours to reduce requests to remote API.
 #[cached(time = 21_600, result = true)]
 async fn list_mistral_models() -> Result<ModelsResponse> {
-    let response = Client::new()
-        .get(format!("{BASE_URL}/models"))
+    let response = models_config()
+        .requests
+        .http_client()?
+        .get(format!("{}/models", base_url()))
         .bearer_auth(secrets::env_or_get(API_KEY)?)
         .send()
         .await?;
@@ -294,7 +489,40 @@ async fn list_mistral_models() -> Result<ModelsResponse> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use model::{common::tokio, test_task_repeat_word};
+    use model::{
+        common::{serde_json, tokio},
+        test_task_repeat_word,
+    };
+
+    #[test]
+    fn chat_content() -> Result<()> {
+        let text = ChatMessage {
+            role: ChatRole::User,
+            content: ChatContent::Text("Describe".into()),
+        };
+        assert_eq!(
+            serde_json::to_string(&text)?,
+            r#"{"role":"user","content":"Describe"}"#
+        );
+
+        let parts = ChatMessage {
+            role: ChatRole::User,
+            content: ChatContent::Parts(vec![
+                ChatContentPart::Text {
+                    text: "Describe".into(),
+                },
+                ChatContentPart::ImageUrl {
+                    image_url: "data:image/png;base64,AAAA".into(),
+                },
+            ]),
+        };
+        assert_eq!(
+            serde_json::to_string(&parts)?,
+            r#"{"role":"user","content":[{"type":"text","text":"Describe"},{"type":"image_url","image_url":"data:image/png;base64,AAAA"}]}"#
+        );
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn list_models() -> Result<()> {
@@ -315,11 +543,24 @@ mod tests {
             return Ok(());
         }
 
-        let model = MistralModel::new("mistral-large-latest", 0);
+        let model = MistralModel::new("mistral-large-latest", 0, false)?;
         let output = model.perform_task(&test_task_repeat_word()).await?;
 
         assert_eq!(output.content.trim(), "HELLO");
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn embeddings() -> Result<()> {
+        if secrets::env_or_get(API_KEY).is_err() {
+            return Ok(());
+        }
+
+        let embeddings = embed(&["shoreline".into(), "beach".into()], None).await?;
+        assert_eq!(embeddings.len(), 2);
+        assert!(!embeddings[0].is_empty());
+
+        Ok(())
+    }
 }
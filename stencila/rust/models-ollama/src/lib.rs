@@ -11,12 +11,11 @@ use ollama_rs::{
 };
 
 use model::{
-    Model, ModelIO, ModelOutput, ModelTask, ModelType,
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning,
     common::{
         async_trait::async_trait,
         eyre::{Result, eyre},
         inflector::Inflector,
-        tracing,
     },
     schema::{self, ImageObject, MessagePart},
 };
@@ -116,13 +115,17 @@ impl Model for OllamaModel {
     }
 
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        let mut warnings = Vec::new();
+
         let messages = task
             .messages
             .iter()
             .map(|message| {
                 let role = match message.role.unwrap_or_default() {
                     schema::MessageRole::Model => MessageRole::Assistant,
-                    schema::MessageRole::System => MessageRole::System,
+                    schema::MessageRole::System | schema::MessageRole::Developer => {
+                        MessageRole::System
+                    }
                     schema::MessageRole::User => MessageRole::User,
                 };
 
@@ -138,17 +141,17 @@ impl Model for OllamaModel {
                                 let base64 = &content_url[(pos + 8)..];
                                 images.push(Image::from_base64(base64))
                             } else {
-                                tracing::warn!(
+                                warnings.push(ModelWarning::ignored_part(format!(
                                     "Image does not appear to have a DataURI so was ignored by model `{}`",
                                     self.id()
-                                );
+                                )));
                             }
                         }
                         _ => {
-                            tracing::warn!(
+                            warnings.push(ModelWarning::ignored_part(format!(
                                 "Message part `{part}` is ignored by model `{}`",
                                 self.id()
-                            );
+                            )));
                         }
                     }
                 }
@@ -184,11 +187,11 @@ impl Model for OllamaModel {
         macro_rules! ignore_option {
             ($name:ident) => {
                 if task.$name.is_some() {
-                    tracing::warn!(
+                    warnings.push(ModelWarning::ignored_option(format!(
                         "Option `{}` is ignored by model `{}` for text-to-text generation",
                         stringify!($name),
                         self.name()
-                    )
+                    )))
                 }
             };
         }
@@ -206,7 +209,7 @@ impl Model for OllamaModel {
         map_option!(temperature);
         map_option!(seed);
         if let Some(value) = &task.stop {
-            options = options.stop(vec![value.clone()]);
+            options = options.stop(value.clone());
         }
         if let Some(value) = task.max_tokens {
             options = options.num_predict(value as i32);
@@ -232,7 +235,10 @@ impl Model for OllamaModel {
 
         let text = response.message.content;
 
-        ModelOutput::from_text(self, &task.format, text).await
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
     }
 }
 
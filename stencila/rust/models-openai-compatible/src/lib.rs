@@ -0,0 +1,705 @@
+//! Models from providers with OpenAI compatible APIs
+//!
+//! Many providers implement the OpenAI `/models` and `/chat/completions`
+//! endpoints. Rather than a crate for each, a [`CompatibleProvider`] is
+//! parameterized by the base URL, the env var for the API key, and the prefix
+//! for the ids of its models.
+//!
+//! As well as the built-in providers, users can add providers (e.g. self-hosted
+//! vLLM or LM Studio servers) in the `[[catalog.providers]]` of the models config.
+
+use std::{sync::Arc, time::Duration};
+
+use cached::proc_macro::cached;
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelSpec, ModelTask, ModelType, ModelWarning, ModelsConfig,
+    api_key,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail},
+        futures::future::join_all,
+        inflector::Inflector,
+        itertools::Itertools,
+        reqwest::{Client, RequestBuilder},
+        serde::{Deserialize, Serialize},
+        serde_with::skip_serializing_none,
+        tracing,
+    },
+    models_config,
+    schema::{ImageObject, MessagePart, MessageRole},
+    secrets,
+};
+
+/// A provider with an OpenAI compatible API
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibleProvider {
+    /// The name of the provider e.g. `DeepSeek`
+    pub name: String,
+
+    /// The prefix for the ids of the provider's models e.g. `deepseek`
+    ///
+    /// Also used as the key for overriding the base URL in the `[requests.base-urls]`
+    /// of the models config.
+    pub prefix: String,
+
+    /// The base URL of the API e.g. `https://api.deepseek.com/v1`
+    pub base_url: String,
+
+    /// The name of the env var or secret for the API key e.g. `DEEPSEEK_API_KEY`
+    ///
+    /// If `None`, requests are made without authentication (e.g. for local servers).
+    pub key_env: Option<String>,
+
+    /// The provider's models
+    ///
+    /// If empty, models are listed using the provider's `/models` endpoint.
+    pub models: Vec<ModelSpec>,
+}
+
+impl CompatibleProvider {
+    /// Create a new provider
+    pub fn new(name: &str, prefix: &str, base_url: &str, key_env: &str) -> Self {
+        Self {
+            name: name.into(),
+            prefix: prefix.into(),
+            base_url: base_url.trim_end_matches('/').into(),
+            key_env: Some(key_env.into()),
+            models: Vec::new(),
+        }
+    }
+
+    /// Create a provider from its configuration
+    ///
+    /// The prefix for model ids is the kebab cased `name` of the provider.
+    pub fn from_config(
+        name: &str,
+        base_url: &str,
+        key_env: Option<&str>,
+        models: Vec<ModelSpec>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            prefix: name.to_kebab_case(),
+            base_url: base_url.trim_end_matches('/').into(),
+            key_env: key_env.map(String::from),
+            models,
+        }
+    }
+
+    /// xAI's Grok models
+    pub fn grok() -> Self {
+        Self::new("xAI", "xai", "https://api.x.ai/v1", secrets::XAI_API_KEY)
+    }
+
+    /// DeepSeek's models
+    pub fn deepseek() -> Self {
+        Self::new(
+            "DeepSeek",
+            "deepseek",
+            "https://api.deepseek.com/v1",
+            secrets::DEEPSEEK_API_KEY,
+        )
+    }
+
+    /// Open models hosted by Together AI
+    pub fn together() -> Self {
+        Self::new(
+            "Together AI",
+            "together",
+            "https://api.together.xyz/v1",
+            secrets::TOGETHER_API_KEY,
+        )
+    }
+
+    /// Get the base URL of the provider, allowing override from the models config
+    fn base_url(&self, config: &ModelsConfig) -> String {
+        config.requests.base_url(&self.prefix, &self.base_url)
+    }
+
+    /// Add authentication to a request, if the provider requires it
+    fn authenticate(
+        &self,
+        request: RequestBuilder,
+        model: &str,
+        task: Option<&ModelTask>,
+    ) -> Result<RequestBuilder> {
+        Ok(match &self.key_env {
+            Some(key_env) => request.bearer_auth(api_key(key_env, model, task)?),
+            None => request,
+        })
+    }
+
+    /// Get a list of the provider's models
+    ///
+    /// Returns an empty list if the provider's API key is not available.
+    pub async fn list(self: Arc<Self>) -> Result<Vec<Arc<dyn Model>>> {
+        // Check for API key before calling IO cached function so that we never cache an empty list
+        // and allow for users to set key, and then get list, while process is running
+        if let Some(key_env) = &self.key_env
+            && secrets::env_or_get(key_env).is_err()
+        {
+            tracing::trace!("The environment variable or secret `{key_env}` is not available");
+            return Ok(vec![]);
+        };
+
+        let specs = if self.models.is_empty() {
+            list_provider_models(self.base_url(&models_config()), self.key_env.clone())
+                .await?
+                .into_iter()
+                .sorted()
+                .map(|id| ModelSpec {
+                    id,
+                    ..Default::default()
+                })
+                .collect()
+        } else {
+            self.models.clone()
+        };
+
+        specs
+            .into_iter()
+            .map(|spec| Ok(Arc::new(CompatibleModel::new(self.clone(), spec)?) as Arc<dyn Model>))
+            .collect()
+    }
+}
+
+/// Get a list of the models from all compatible providers
+///
+/// Includes the built-in providers and those in the `providers` of the models
+/// config. Errors listing the models of a provider are logged and its models are omitted.
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    let mut providers = vec![
+        CompatibleProvider::deepseek(),
+        CompatibleProvider::grok(),
+        CompatibleProvider::together(),
+    ];
+    providers.extend(models_config().catalog.providers.iter().map(|config| {
+        CompatibleProvider::from_config(
+            &config.name,
+            &config.base_url,
+            config.key_env.as_deref(),
+            config.models.clone(),
+        )
+    }));
+
+    let futures = providers.into_iter().map(|provider| async move {
+        let name = provider.name.clone();
+        match Arc::new(provider).list().await {
+            Ok(list) => list,
+            Err(error) => {
+                tracing::warn!("While listing {name} models: {error}");
+                vec![]
+            }
+        }
+    });
+
+    Ok(join_all(futures).await.into_iter().flatten().collect())
+}
+
+/// A model from a [`CompatibleProvider`]
+struct CompatibleModel {
+    /// The provider of the model
+    provider: Arc<CompatibleProvider>,
+
+    /// The provider's id for the model
+    model: String,
+
+    /// The context length of the model, if known
+    context_length: Option<usize>,
+
+    /// The input types supported by the model
+    inputs: Vec<ModelIO>,
+
+    /// The cost of the model in US dollars per million tokens, if known
+    cost_per_mtok: Option<f64>,
+
+    /// The HTTP client
+    client: Client,
+}
+
+impl CompatibleModel {
+    /// Create a model for a provider
+    ///
+    /// Compatible APIs do not list the input types that models support so, if
+    /// they are not specified, vision models are identified by name.
+    fn new(provider: Arc<CompatibleProvider>, spec: ModelSpec) -> Result<Self> {
+        let lower = spec.id.to_lowercase();
+        let inputs = if !spec.inputs.is_empty() {
+            spec.inputs
+        } else if lower.contains("vision") || lower.contains("-vl") {
+            vec![ModelIO::Text, ModelIO::Image]
+        } else {
+            vec![ModelIO::Text]
+        };
+
+        Ok(Self {
+            provider,
+            model: spec.id,
+            context_length: spec.context_length,
+            inputs,
+            cost_per_mtok: spec.cost_per_mtok,
+            client: models_config().requests.http_client()?,
+        })
+    }
+
+    /// Perform a task, with the request checked against, and sent to the base URL of, a config
+    #[tracing::instrument(skip(self, config))]
+    async fn perform_with(&self, task: &ModelTask, config: &ModelsConfig) -> Result<ModelOutput> {
+        config.policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+        let images = self.inputs.contains(&ModelIO::Image);
+
+        let messages = task
+            .messages
+            .iter()
+            .map(|message| {
+                let role = match message.role.unwrap_or_default() {
+                    MessageRole::Model => ChatRole::Assistant,
+                    MessageRole::System | MessageRole::Developer => ChatRole::System,
+                    MessageRole::User => ChatRole::User,
+                };
+
+                let parts = message
+                    .parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        MessagePart::Text(text) => Some(ChatContentPart::Text {
+                            text: text.to_value_string(),
+                        }),
+                        MessagePart::ImageObject(ImageObject { content_url, .. }) if images => {
+                            Some(ChatContentPart::ImageUrl {
+                                image_url: ImageUrl {
+                                    url: content_url.clone(),
+                                },
+                            })
+                        }
+                        _ => {
+                            warnings.push(ModelWarning::ignored_part(format!(
+                                "Message part of type `{part}` is ignored by model `{}`",
+                                self.id()
+                            )));
+                            None
+                        }
+                    })
+                    .collect_vec();
+
+                ChatMessage::new(role, parts)
+            })
+            .collect();
+
+        let request = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: task.temperature,
+            top_p: task.top_p,
+            max_tokens: task.max_tokens,
+            seed: task.seed,
+        };
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        let response = self
+            .provider
+            .authenticate(
+                self.client.post(format!(
+                    "{}/chat/completions",
+                    self.provider.base_url(config)
+                )),
+                &self.id(),
+                Some(task),
+            )?
+            .json(&request)
+            .send()
+            .await?;
+
+        if let Err(error) = response.error_for_status_ref() {
+            let message = response.text().await?;
+            bail!("{error}: {message}");
+        }
+
+        let response: ChatCompletionResponse = response.json().await?;
+        let Some(choice) = response.choices.into_iter().next() else {
+            bail!("No choices in response from model `{}`", self.id())
+        };
+        let text = choice.message.content.unwrap_or_default();
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl Model for CompatibleModel {
+    fn id(&self) -> String {
+        format!("{}/{}", self.provider.prefix, self.model)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Remote
+    }
+
+    fn provider(&self) -> String {
+        self.provider.name.clone()
+    }
+
+    fn name(&self) -> String {
+        // Some providers (e.g. Together) use ids with a publisher prefix
+        let name = self
+            .model
+            .rsplit_once('/')
+            .map_or(self.model.as_str(), |(.., name)| name);
+        name.split_once('-')
+            .map_or(name, |(name, ..)| name)
+            .to_string()
+    }
+
+    fn version(&self) -> String {
+        let name = self
+            .model
+            .rsplit_once('/')
+            .map_or(self.model.as_str(), |(.., name)| name);
+        name.split_once('-')
+            .map(|(.., version)| version)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn context_length(&self) -> usize {
+        self.context_length.unwrap_or_default()
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        &self.inputs
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    fn cost_per_mtok(&self) -> Option<f64> {
+        self.cost_per_mtok
+    }
+
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        self.perform_with(task, &models_config()).await
+    }
+}
+
+/// A model list response
+///
+/// Most providers respond with an object with a `data` array but some
+/// (e.g. Together) respond with the array itself.
+#[derive(Clone, Deserialize)]
+#[serde(untagged, crate = "model::common::serde")]
+enum ModelsResponse {
+    Object { data: Vec<ListedModel> },
+    Array(Vec<ListedModel>),
+}
+
+/// A model returned within a `ModelsResponse`
+///
+/// Note: at present several other fields are ignored.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ListedModel {
+    id: String,
+
+    /// The type of the model (only provided by some providers)
+    r#type: Option<String>,
+}
+
+/// A chat completion request
+///
+/// Based on https://platform.openai.com/docs/api-reference/chat/create
+#[skip_serializing_none]
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u16>,
+    seed: Option<i32>,
+}
+
+/// A chat message within a `ChatCompletionRequest`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatMessage {
+    role: ChatRole,
+    content: ChatContent,
+}
+
+impl ChatMessage {
+    /// Create a message
+    ///
+    /// Messages with only text parts are sent as a string because some
+    /// providers do not support arrays of content parts.
+    fn new(role: ChatRole, parts: Vec<ChatContentPart>) -> Self {
+        let content = if parts
+            .iter()
+            .all(|part| matches!(part, ChatContentPart::Text { .. }))
+        {
+            ChatContent::Text(
+                parts
+                    .into_iter()
+                    .filter_map(|part| match part {
+                        ChatContentPart::Text { text } => Some(text),
+                        _ => None,
+                    })
+                    .join(""),
+            )
+        } else {
+            ChatContent::Parts(parts)
+        };
+
+        Self { role, content }
+    }
+}
+
+/// A role in a `ChatMessage`
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase", crate = "model::common::serde")]
+enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// The content of a `ChatMessage`
+#[derive(Serialize)]
+#[serde(untagged, crate = "model::common::serde")]
+enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+/// A part of the content of a `ChatMessage`
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    crate = "model::common::serde"
+)]
+enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// The URL (usually a data URI) of an image in a `ChatContentPart`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ImageUrl {
+    url: String,
+}
+
+/// A chat completion response
+///
+/// Note: at present several other fields are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionResponse {
+    choices: Vec<ChatCompletionChoice>,
+}
+
+/// A choice within a `ChatCompletionResponse`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+/// The message of a `ChatCompletionChoice`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+/// Fetch the ids of the chat models of a provider
+///
+/// Models with a type other than chat, language or code (e.g. embedding or
+/// image generation models) are excluded.
+///
+/// In-memory cached for six hours to reduce requests to remote API.
+#[cached(time = 21_600, result = true)]
+async fn list_provider_models(base_url: String, key_env: Option<String>) -> Result<Vec<String>> {
+    let mut request = models_config()
+        .requests
+        .http_client()?
+        .get(format!("{base_url}/models"));
+    if let Some(key_env) = key_env {
+        request = request.bearer_auth(secrets::env_or_get(&key_env)?);
+    }
+
+    let response = request.send().await?;
+
+    if let Err(error) = response.error_for_status_ref() {
+        let message = response.text().await?;
+        bail!("{error}: {message}");
+    }
+
+    let specs = match response.json().await? {
+        ModelsResponse::Object { data } => data,
+        ModelsResponse::Array(specs) => specs,
+    };
+
+    Ok(specs
+        .into_iter()
+        .filter(|spec| {
+            spec.r#type
+                .as_deref()
+                .is_none_or(|kind| matches!(kind, "chat" | "language" | "code"))
+        })
+        .map(|spec| spec.id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::common::{serde_json, tokio, toml};
+
+    #[test]
+    fn models() -> Result<()> {
+        let provider = Arc::new(CompatibleProvider::together());
+        let spec = |id: &str| ModelSpec {
+            id: id.into(),
+            ..Default::default()
+        };
+
+        let model = CompatibleModel::new(
+            provider.clone(),
+            spec("meta-llama/Llama-3.2-11B-Vision-Instruct-Turbo"),
+        )?;
+        assert_eq!(
+            model.id(),
+            "together/meta-llama/Llama-3.2-11B-Vision-Instruct-Turbo"
+        );
+        assert_eq!(model.provider(), "Together AI");
+        assert_eq!(model.name(), "Llama");
+        assert_eq!(model.supported_inputs(), &[ModelIO::Text, ModelIO::Image]);
+
+        let model = CompatibleModel::new(
+            Arc::new(CompatibleProvider::deepseek()),
+            spec("deepseek-chat"),
+        )?;
+        assert_eq!(model.id(), "deepseek/deepseek-chat");
+        assert_eq!(model.version(), "chat");
+        assert_eq!(model.supported_inputs(), &[ModelIO::Text]);
+
+        let provider = Arc::new(CompatibleProvider::from_config(
+            "LM Studio",
+            "http://localhost:1234/v1/",
+            None,
+            vec![ModelSpec {
+                id: "qwen2.5-7b-instruct".into(),
+                context_length: Some(32_768),
+                inputs: vec![ModelIO::Text, ModelIO::Image],
+                cost_per_mtok: Some(0.0),
+            }],
+        ));
+        assert_eq!(provider.prefix, "lm-studio");
+        assert_eq!(
+            provider.base_url(&ModelsConfig::default()),
+            "http://localhost:1234/v1"
+        );
+        let model = CompatibleModel::new(provider.clone(), provider.models[0].clone())?;
+        assert_eq!(model.id(), "lm-studio/qwen2.5-7b-instruct");
+        assert_eq!(model.context_length(), 32_768);
+        assert_eq!(model.supported_inputs(), &[ModelIO::Text, ModelIO::Image]);
+        assert_eq!(model.cost_per_mtok(), Some(0.0));
+
+        let response: ModelsResponse =
+            serde_json::from_str(r#"[{"id": "a", "type": "chat"}, {"id": "b"}]"#)?;
+        assert!(matches!(response, ModelsResponse::Array(specs) if specs.len() == 2));
+        let response: ModelsResponse = serde_json::from_str(r#"{"data": [{"id": "a"}]}"#)?;
+        assert!(matches!(response, ModelsResponse::Object { data } if data.len() == 1));
+
+        let message = ChatMessage::new(
+            ChatRole::User,
+            vec![ChatContentPart::Text { text: "Hi".into() }],
+        );
+        assert_eq!(
+            serde_json::to_string(&message)?,
+            r#"{"role":"user","content":"Hi"}"#
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_models() -> Result<()> {
+        for provider in [
+            CompatibleProvider::deepseek(),
+            CompatibleProvider::grok(),
+            CompatibleProvider::together(),
+        ] {
+            let available = provider
+                .key_env
+                .as_ref()
+                .is_some_and(|key_env| secrets::env_or_get(key_env).is_ok());
+            let list = Arc::new(provider).list().await?;
+            if !available {
+                assert_eq!(list.len(), 0)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_configured_models() -> Result<()> {
+        // Models specified in config are listed without a request to the provider
+        let provider = CompatibleProvider::from_config(
+            "Local",
+            "http://localhost:1",
+            None,
+            vec![ModelSpec {
+                id: "llama3".into(),
+                ..Default::default()
+            }],
+        );
+        let list = Arc::new(provider).list().await?;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id(), "local/llama3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_only() -> Result<()> {
+        // Called directly, rather than via `models::perform_task`, so that the
+        // check is not made before the request
+        let provider = Arc::new(CompatibleProvider::from_config(
+            "Local",
+            "http://localhost:1",
+            None,
+            Vec::new(),
+        ));
+        let model = CompatibleModel::new(
+            provider,
+            ModelSpec {
+                id: "llama3".into(),
+                ..Default::default()
+            },
+        )?;
+
+        let config: ModelsConfig = toml::from_str("[policy]\nlocal-only = true")?;
+        let result = model.perform_with(&ModelTask::default(), &config).await;
+
+        let Err(error) = result else {
+            bail!("expected error in local-only mode")
+        };
+        assert!(error.to_string().contains("local-only mode is enabled"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,536 @@
+//! Retrieval-augmented question answering using the OpenAI Assistants API
+//!
+//! An assistant is created with a vector store containing uploaded documents
+//! and the `file_search` tool enabled. Each task is performed in a new thread
+//! containing the task's messages, so that the assistant can be used through
+//! the [`Model`] trait like any other model.
+//!
+//! See https://platform.openai.com/docs/assistants/overview
+
+use std::time::Duration;
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelTask, ModelType,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail},
+        itertools::Itertools,
+        serde_json::{Value, json},
+        tokio::time::sleep,
+        tracing,
+    },
+    models_config,
+    schema::{InstructionAttachment, MessagePart, MessageRole},
+    secrets,
+};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{API_KEY, AttachmentSource, OpenAIModel, ReqwestTransport, base_url};
+
+/// The interval between polls of the status of a run
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The maximum time to wait for a run to complete
+const RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The maximum time to wait for the files of a vector store to be ingested
+const INGESTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An OpenAI assistant with a vector store of documents
+pub struct OpenAIAssistant {
+    /// The name of the assistant, used in its model id
+    name: String,
+
+    /// The OpenAI model used by the assistant
+    model: String,
+
+    /// The id of the assistant
+    assistant_id: String,
+
+    /// The id of the vector store of the assistant
+    vector_store_id: String,
+
+    /// The ids of the files uploaded to the vector store
+    file_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct VectorStoreResponse {
+    id: String,
+    status: String,
+    file_counts: FileCounts,
+}
+
+#[derive(Deserialize)]
+struct FileCounts {
+    in_progress: usize,
+    failed: usize,
+    cancelled: usize,
+}
+
+impl VectorStoreResponse {
+    /// Whether the files of the vector store have finished being ingested
+    ///
+    /// Errors if the store has expired, or if any of its files failed to be
+    /// ingested or were cancelled, since the assistant would otherwise answer
+    /// without them.
+    fn is_ingested(&self) -> Result<bool> {
+        let counts = &self.file_counts;
+        if self.status == "expired" {
+            bail!("Vector store `{}` has expired", self.id)
+        }
+        if counts.in_progress > 0 {
+            return Ok(false);
+        }
+        if counts.failed > 0 || counts.cancelled > 0 {
+            bail!(
+                "Ingestion of files into vector store `{}` did not complete: {} failed, {} cancelled",
+                self.id,
+                counts.failed,
+                counts.cancelled
+            )
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Deserialize)]
+struct RunResponse {
+    id: String,
+    status: String,
+    last_error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    data: Vec<ThreadMessage>,
+}
+
+#[derive(Deserialize)]
+struct ThreadMessage {
+    role: String,
+    content: Vec<Value>,
+}
+
+impl OpenAIAssistant {
+    /// Create an assistant, uploading documents to a new vector store
+    ///
+    /// The `instructions` are used as the system prompt of the assistant. Waits
+    /// until the documents have been ingested into the vector store so that they
+    /// can be searched by the first task performed by the assistant.
+    pub async fn create(
+        name: &str,
+        model: &str,
+        instructions: Option<&str>,
+        documents: &[InstructionAttachment],
+    ) -> Result<Self> {
+        let (client, api_key) = Self::http_client()?;
+        let transport = ReqwestTransport::new(api_key.clone())?;
+
+        let mut file_ids = Vec::new();
+        for document in documents {
+            let uploaded = OpenAIModel::upload_attachment(&transport, document).await?;
+            if let AttachmentSource::FileId(file_id) = uploaded.source {
+                file_ids.push(file_id);
+            }
+        }
+
+        let vector_store: VectorStoreResponse = Self::post(
+            &client,
+            &api_key,
+            "vector_stores",
+            json!({
+                "name": name,
+                "file_ids": file_ids,
+            }),
+        )
+        .await?;
+
+        if let Err(error) = Self::wait_for_ingestion(&client, &api_key, &vector_store).await {
+            let store = ["vector_stores/", &vector_store.id].concat();
+            if let Err(error) = Self::delete_resource(&client, &api_key, &store).await {
+                tracing::debug!(
+                    "Unable to delete vector store `{}`: {error}",
+                    vector_store.id
+                );
+            }
+            for file_id in &file_ids {
+                let file = ["files/", file_id].concat();
+                if let Err(error) = Self::delete_resource(&client, &api_key, &file).await {
+                    tracing::debug!("Unable to delete file `{file_id}`: {error}");
+                }
+            }
+            return Err(error);
+        }
+
+        let assistant: IdResponse = Self::post(
+            &client,
+            &api_key,
+            "assistants",
+            json!({
+                "name": name,
+                "model": model,
+                "instructions": instructions,
+                "tools": [{"type": "file_search"}],
+                "tool_resources": {
+                    "file_search": {"vector_store_ids": [vector_store.id]}
+                }
+            }),
+        )
+        .await?;
+
+        tracing::debug!(
+            "Created assistant `{}` with vector store `{}` of {} files",
+            assistant.id,
+            vector_store.id,
+            file_ids.len()
+        );
+
+        Ok(Self {
+            name: name.into(),
+            model: model.into(),
+            assistant_id: assistant.id,
+            vector_store_id: vector_store.id,
+            file_ids,
+        })
+    }
+
+    /// Wait for the files of a vector store to be ingested
+    async fn wait_for_ingestion(
+        client: &HttpClient,
+        api_key: &str,
+        vector_store: &VectorStoreResponse,
+    ) -> Result<()> {
+        let path = ["vector_stores/", &vector_store.id].concat();
+
+        let mut waited = Duration::ZERO;
+        let mut is_ingested = vector_store.is_ingested()?;
+        while !is_ingested {
+            if waited >= INGESTION_TIMEOUT {
+                bail!(
+                    "Files of vector store `{}` were not ingested within {} seconds",
+                    vector_store.id,
+                    INGESTION_TIMEOUT.as_secs()
+                );
+            }
+            sleep(POLL_INTERVAL).await;
+            waited += POLL_INTERVAL;
+
+            let vector_store: VectorStoreResponse = Self::get(client, api_key, &path).await?;
+            is_ingested = vector_store.is_ingested()?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the id of the assistant
+    pub fn assistant_id(&self) -> &str {
+        &self.assistant_id
+    }
+
+    /// Get the id of the vector store of the assistant
+    pub fn vector_store_id(&self) -> &str {
+        &self.vector_store_id
+    }
+
+    /// Delete the assistant, its vector store, and uploaded files
+    pub async fn delete(self) -> Result<()> {
+        let (client, api_key) = Self::http_client()?;
+
+        Self::delete_resource(
+            &client,
+            &api_key,
+            &["assistants/", &self.assistant_id].concat(),
+        )
+        .await?;
+        Self::delete_resource(
+            &client,
+            &api_key,
+            &["vector_stores/", &self.vector_store_id].concat(),
+        )
+        .await?;
+        for file_id in &self.file_ids {
+            Self::delete_resource(&client, &api_key, &["files/", file_id].concat()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a thread containing the user and assistant messages of a task
+    ///
+    /// Returns the id of the thread. Only text parts of messages are included.
+    pub async fn create_thread(&self, task: &ModelTask) -> Result<String> {
+        let (client, api_key) = Self::http_client()?;
+
+        let messages = task
+            .messages
+            .iter()
+            .filter_map(|message| {
+                let role = match message.role.unwrap_or_default() {
+                    MessageRole::System | MessageRole::Developer => return None,
+                    MessageRole::User => "user",
+                    MessageRole::Model => "assistant",
+                };
+                let content = Self::message_text(&message.parts)?;
+                Some(json!({"role": role, "content": content}))
+            })
+            .collect_vec();
+
+        let thread: IdResponse =
+            Self::post(&client, &api_key, "threads", json!({"messages": messages})).await?;
+
+        Ok(thread.id)
+    }
+
+    /// Delete a thread
+    pub async fn delete_thread(&self, thread_id: &str) -> Result<()> {
+        let (client, api_key) = Self::http_client()?;
+        Self::delete_resource(&client, &api_key, &["threads/", thread_id].concat()).await
+    }
+
+    /// Run the assistant on a thread and get the text of its answer
+    ///
+    /// System and developer messages of the task are passed as additional instructions for the run.
+    pub async fn run_thread(&self, thread_id: &str, task: &ModelTask) -> Result<String> {
+        let (client, api_key) = Self::http_client()?;
+
+        let instructions = task
+            .messages
+            .iter()
+            .filter(|message| {
+                matches!(
+                    message.role,
+                    Some(MessageRole::System | MessageRole::Developer)
+                )
+            })
+            .filter_map(|message| Self::message_text(&message.parts))
+            .join("\n\n");
+
+        let mut run: RunResponse = Self::post(
+            &client,
+            &api_key,
+            &["threads/", thread_id, "/runs"].concat(),
+            json!({
+                "assistant_id": self.assistant_id,
+                "additional_instructions": (!instructions.is_empty()).then_some(instructions),
+                "temperature": task.temperature,
+                "top_p": task.top_p,
+                "max_completion_tokens": task.max_tokens,
+            }),
+        )
+        .await?;
+
+        let mut waited = Duration::ZERO;
+        while matches!(run.status.as_str(), "queued" | "in_progress") {
+            if waited >= RUN_TIMEOUT {
+                bail!(
+                    "Assistant run `{}` did not complete within {} seconds",
+                    run.id,
+                    RUN_TIMEOUT.as_secs()
+                );
+            }
+            sleep(POLL_INTERVAL).await;
+            waited += POLL_INTERVAL;
+
+            run = Self::get(
+                &client,
+                &api_key,
+                &["threads/", thread_id, "/runs/", &run.id].concat(),
+            )
+            .await?;
+        }
+
+        if run.status != "completed" {
+            let error = run
+                .last_error
+                .map(|error| error.to_string())
+                .unwrap_or_default();
+            bail!("Assistant run ended with status `{}` {error}", run.status);
+        }
+
+        let messages: MessagesResponse = Self::get(
+            &client,
+            &api_key,
+            &["threads/", thread_id, "/messages?order=desc&limit=1"].concat(),
+        )
+        .await?;
+
+        let Some(message) = messages
+            .data
+            .into_iter()
+            .find(|message| message.role == "assistant")
+        else {
+            bail!("Assistant run did not add a message to the thread")
+        };
+
+        Ok(message
+            .content
+            .iter()
+            .filter_map(|content| content.pointer("/text/value").and_then(Value::as_str))
+            .join("\n"))
+    }
+
+    /// Get the text of message parts, if any
+    fn message_text(parts: &[MessagePart]) -> Option<String> {
+        let text = parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::Text(text) => Some(text.to_value_string()),
+                _ => None,
+            })
+            .join("");
+        (!text.trim().is_empty()).then_some(text)
+    }
+
+    /// Create an HTTP client and get the API key
+    fn http_client() -> Result<(HttpClient, String)> {
+        let api_key = secrets::env_or_get(API_KEY)?;
+        Ok((models_config().requests.http_client()?, api_key))
+    }
+
+    /// Make a POST request to the API
+    async fn post<T: DeserializeOwned>(
+        client: &HttpClient,
+        api_key: &str,
+        path: &str,
+        body: Value,
+    ) -> Result<T> {
+        let response = client
+            .post(format!("{}/{path}", base_url()))
+            .bearer_auth(api_key)
+            .header("OpenAI-Beta", "assistants=v2")
+            .json(&body)
+            .send()
+            .await?;
+        Self::parse(path, response).await
+    }
+
+    /// Make a GET request to the API
+    async fn get<T: DeserializeOwned>(client: &HttpClient, api_key: &str, path: &str) -> Result<T> {
+        let response = client
+            .get(format!("{}/{path}", base_url()))
+            .bearer_auth(api_key)
+            .header("OpenAI-Beta", "assistants=v2")
+            .send()
+            .await?;
+        Self::parse(path, response).await
+    }
+
+    /// Make a DELETE request to the API
+    async fn delete_resource(client: &HttpClient, api_key: &str, path: &str) -> Result<()> {
+        let response = client
+            .delete(format!("{}/{path}", base_url()))
+            .bearer_auth(api_key)
+            .header("OpenAI-Beta", "assistants=v2")
+            .send()
+            .await?;
+        Self::parse::<Value>(path, response).await?;
+        Ok(())
+    }
+
+    /// Parse a response, returning an error if it was not successful
+    async fn parse<T: DeserializeOwned>(path: &str, response: reqwest::Response) -> Result<T> {
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("OpenAI API `{path}` returned {status}: {body}")
+        }
+    }
+}
+
+#[async_trait]
+impl Model for OpenAIAssistant {
+    fn id(&self) -> String {
+        format!("openai/assistant-{}", self.name)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Remote
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!(
+            "OpenAI assistant using `{}` with file search over {} documents",
+            self.model,
+            self.file_ids.len()
+        ))
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        let thread_id = self.create_thread(task).await?;
+        let result = self.run_thread(&thread_id, task).await;
+
+        if let Err(error) = self.delete_thread(&thread_id).await {
+            tracing::debug!("Unable to delete assistant thread `{thread_id}`: {error}");
+        }
+
+        ModelOutput::from_text(self, &task.format, result?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use model::common::{
+        eyre::{Result, bail},
+        serde_json::{self, json},
+    };
+
+    use super::*;
+
+    fn vector_store(
+        status: &str,
+        in_progress: usize,
+        failed: usize,
+    ) -> Result<VectorStoreResponse> {
+        Ok(serde_json::from_value(json!({
+            "id": "vs_1",
+            "object": "vector_store",
+            "status": status,
+            "file_counts": {
+                "in_progress": in_progress,
+                "completed": 2 - in_progress - failed,
+                "failed": failed,
+                "cancelled": 0,
+                "total": 2
+            }
+        }))?)
+    }
+
+    #[test]
+    fn waits_for_ingestion() -> Result<()> {
+        assert!(!vector_store("in_progress", 2, 0)?.is_ingested()?);
+        assert!(!vector_store("in_progress", 1, 1)?.is_ingested()?);
+        assert!(vector_store("completed", 0, 0)?.is_ingested()?);
+
+        let Err(error) = vector_store("completed", 0, 1)?.is_ingested() else {
+            bail!("expected error")
+        };
+        assert!(error.to_string().contains("1 failed"));
+
+        assert!(vector_store("expired", 0, 0)?.is_ingested().is_err());
+
+        Ok(())
+    }
+}
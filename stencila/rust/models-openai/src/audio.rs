@@ -0,0 +1,103 @@
+use std::{fs::read, path::Path};
+
+use async_openai::types::{
+    ChatCompletionAudio, ChatCompletionAudioFormat, ChatCompletionAudioVoice,
+    ChatCompletionRequestMessageContentPartAudio, ChatCompletionRequestUserMessageContentPart,
+    InputAudio, InputAudioFormat,
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use model::{
+    ModelTask,
+    common::eyre::{Result, bail},
+    schema::AudioObject,
+};
+
+/// Create a chat completion content part for an audio input
+///
+/// The `content_url` of the audio may be a base64 encoded data URL or the path of a
+/// local file. Only WAV and MP3 audio are supported by the OpenAI API.
+pub(crate) fn chat_audio_part(
+    audio: &AudioObject,
+) -> Result<ChatCompletionRequestUserMessageContentPart> {
+    let url = audio.content_url.trim();
+
+    let (media_type, data) = if let Some(rest) = url.strip_prefix("data:") {
+        let Some((media_type, data)) = rest.split_once(";base64,") else {
+            bail!("Audio data URL is not base64 encoded")
+        };
+        (media_type.to_string(), data.to_string())
+    } else {
+        let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+        if !path.exists() {
+            bail!("Audio `{url}` is neither a data URL nor an existing file")
+        }
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        (
+            audio.media_type.clone().unwrap_or(extension),
+            BASE64.encode(read(path)?),
+        )
+    };
+
+    let format = if media_type.contains("wav") {
+        InputAudioFormat::Wav
+    } else if media_type.contains("mp3") || media_type.contains("mpeg") {
+        InputAudioFormat::Mp3
+    } else {
+        bail!("Audio format `{media_type}` is not supported; use WAV or MP3")
+    };
+
+    Ok(ChatCompletionRequestUserMessageContentPart::InputAudio(
+        ChatCompletionRequestMessageContentPartAudio {
+            input_audio: InputAudio { data, format },
+        },
+    ))
+}
+
+/// Get the audio output options for a task
+pub(crate) fn audio_options(task: &ModelTask) -> Result<ChatCompletionAudio> {
+    use ChatCompletionAudioVoice::*;
+    let voice = match task.audio_voice.as_deref().unwrap_or("alloy") {
+        "alloy" => Alloy,
+        "ash" => Ash,
+        "ballad" => Ballad,
+        "coral" => Coral,
+        "echo" => Echo,
+        "sage" => Sage,
+        "shimmer" => Shimmer,
+        "verse" => Verse,
+        voice => bail!("Unsupported audio voice `{voice}`"),
+    };
+
+    use ChatCompletionAudioFormat::*;
+    let format = match task.audio_format.as_deref().unwrap_or("mp3") {
+        "wav" => Wav,
+        "mp3" => Mp3,
+        "flac" => Flac,
+        "opus" => Opus,
+        "pcm16" => Pcm16,
+        format => bail!("Unsupported audio format `{format}`"),
+    };
+
+    Ok(ChatCompletionAudio { voice, format })
+}
+
+/// Create an `AudioObject` from base64 encoded audio generated for a task
+pub(crate) fn audio_object(task: &ModelTask, data: &str) -> AudioObject {
+    let media_type = match task.audio_format.as_deref().unwrap_or("mp3") {
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "opus" => "audio/opus",
+        "pcm16" => "audio/L16",
+        _ => "audio/mpeg",
+    };
+
+    AudioObject {
+        content_url: format!("data:{media_type};base64,{data}"),
+        media_type: Some(media_type.to_string()),
+        ..Default::default()
+    }
+}
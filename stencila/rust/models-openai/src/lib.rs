@@ -1,39 +1,110 @@
-use std::{sync::Arc, time::Duration};
+#![recursion_limit = "256"]
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read_to_string, write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_openai::{
     Client as AsyncOpenAIClient,
     config::OpenAIConfig,
     types::{
+        ChatCompletionMessageToolCall, ChatCompletionModalities,
         ChatCompletionRequestAssistantMessage, ChatCompletionRequestAssistantMessageContent,
+        ChatCompletionRequestDeveloperMessage, ChatCompletionRequestDeveloperMessageContent,
         ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartImage,
         ChatCompletionRequestMessageContentPartText, ChatCompletionRequestSystemMessage,
-        ChatCompletionRequestSystemMessageContent, ChatCompletionRequestUserMessage,
+        ChatCompletionRequestSystemMessageContent, ChatCompletionRequestToolMessage,
+        ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessage,
         ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
-        CreateChatCompletionRequest, CreateImageRequestArgs, Image, ImageDetail, ImageQuality,
-        ImageResponseFormat, ImageSize, ImageStyle, ImageUrl, ListModelResponse, Stop,
+        ChatCompletionStreamOptions, ChatCompletionTool, ChatCompletionToolType, CompletionUsage,
+        CreateChatCompletionRequest, CreateImageRequestArgs, FinishReason as ChatFinishReason,
+        FunctionCall, FunctionObject, Image, ImageDetail, ImageQuality, ImageResponseFormat,
+        ImageSize, ImageStyle, ImageUrl, ImagesResponse, ResponseFormat, Stop,
     },
 };
-use cached::proc_macro::cached;
 
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use dirs::{DirType, get_app_dir};
 use model::{
-    Model, ModelIO, ModelOutput, ModelTask, ModelTaskKind, ModelType,
+    AttachmentDelta, AttachmentFailurePolicy, CacheMetrics, DeltaCallback, FinishReason,
+    ImageDetailLevel, ImageGeneration, ImagePersistence, ManifestDisposition, Model, ModelAudit,
+    ModelHealth, ModelIO, ModelOutput, ModelOutputPart, ModelTask, ModelTaskKind, ModelType,
+    ModelWarning, PromptManifest, TokenEncoding, TokenUsage, ToolCall, TtlCache,
+    WebSearchContextSize, api_key, attachment_delta, attachment_delta_key, attachment_preamble,
+    audit_enabled,
     common::{
         async_trait::async_trait,
-        eyre::{Result, bail, eyre},
+        chrono::DateTime,
+        eyre::{Report, Result, bail, eyre},
+        futures::{
+            StreamExt,
+            future::try_join_all,
+            stream::{BoxStream, FuturesUnordered},
+        },
         inflector::Inflector,
         itertools::Itertools,
-        tracing,
+        once_cell::sync::Lazy,
+        serde_json, tokio, tracing,
+    },
+    correct_media_type, embed_data_url_manifest, extract_attachment_frames, file_data_url,
+    file_text,
+    format::Format,
+    format_instruction, models_config, preview_attachment, record_sent_attachment,
+    replace_videos_with_frames,
+    schema::{
+        ImageObject, InstructionAttachment, InstructionMessage, MessagePart, MessageRole, Timestamp,
     },
-    schema::{ImageObject, InstructionAttachment, MessagePart, MessageRole},
     secrets,
 };
-use reqwest::{Client as HttpClient, multipart};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
+mod assistants;
+mod audio;
+mod transport;
+mod verify;
+mod vision;
+pub use assistants::OpenAIAssistant;
+pub use transport::{
+    Cassette, HttpRequest, HttpResponse, Interaction, RecordedResponse, RequestBody,
+    ReqwestTransport, Transport,
+};
+pub use verify::{KeyReport, ScopeAccess, verify_key};
+pub use vision::{VisionFallback, VisionRetryPolicy, set_vision_retry_policy, vision_retry_policy};
+
 /// The name of the env var or secret for the API key
 const API_KEY: &str = "OPENAI_API_KEY";
 
+/// The default base URL of the OpenAI API
+const BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Get the base URL of the OpenAI API from the models config
+fn base_url() -> String {
+    models_config().requests.base_url("openai", BASE_URL)
+}
+
+/// The maximum number of stop sequences allowed by the OpenAI API
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The number of times to retry an upload that fails verification
+const UPLOAD_VERIFY_RETRIES: usize = 2;
+
+/// The number of times to poll for a file created by the code interpreter before giving up
+const CONTAINER_FILE_RETRIES: usize = 3;
+
+/// Text preceding images from an assistant message which are replayed as a user message
+const ASSISTANT_IMAGES_PREAMBLE: &str = "Images from the previous assistant message:";
+
+/// The detail recorded in the prompt manifest for images in assistant messages
+const REPLAYED_IMAGE: &str = "replayed in a following user message";
+
+/// The detail recorded in the prompt manifest for files provided as text to chat completions
+const EXTRACTED_FILE_TEXT: &str = "provided as extracted text";
+
 /// A model running on OpenAI
 pub struct OpenAIModel {
     /// The OpenAI name for a model including any tag e.g. "llama2:13b"
@@ -50,6 +121,18 @@ pub struct OpenAIModel {
 
     /// The type of output that the model generates
     outputs: Vec<ModelIO>,
+
+    /// The formats that the model can be constrained to generate
+    formats: Vec<Format>,
+
+    /// Whether the model was listed from the saved list of models
+    /// because the OpenAI API could not be reached
+    stale: bool,
+
+    /// The transport used for raw requests to the API
+    ///
+    /// If `None`, a [`ReqwestTransport`] using the API key for the task is used.
+    transport: Option<Arc<dyn Transport>>,
 }
 
 impl OpenAIModel {
@@ -59,13 +142,93 @@ impl OpenAIModel {
         context_length: usize,
         inputs: Vec<ModelIO>,
         outputs: Vec<ModelIO>,
+        formats: Vec<Format>,
     ) -> Self {
         Self {
             model,
             context_length,
             inputs,
             outputs,
+            formats,
+            stale: false,
+            transport: None,
+        }
+    }
+
+    /// Use a transport for raw requests to the API
+    ///
+    /// Intended for testing e.g. replaying a [`Cassette`] of recorded interactions.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Get the transport for raw requests to the API for a task
+    fn transport(&self, task: &ModelTask) -> Result<Arc<dyn Transport>> {
+        if let Some(transport) = &self.transport {
+            return Ok(transport.clone());
+        }
+
+        Ok(Arc::new(ReqwestTransport::new(api_key(
+            API_KEY,
+            &self.id(),
+            Some(task),
+        )?)?))
+    }
+
+    /// Get the stop sequences of a task, checking that there are no more than OpenAI allows
+    fn stop_sequences(task: &ModelTask) -> Result<Option<Vec<String>>> {
+        let Some(stop) = task.stop.as_ref().filter(|stop| !stop.is_empty()) else {
+            return Ok(None);
+        };
+
+        if stop.len() > MAX_STOP_SEQUENCES {
+            bail!(
+                "OpenAI models support at most {MAX_STOP_SEQUENCES} stop sequences but {} were provided",
+                stop.len()
+            )
+        }
+
+        Ok(Some(stop.clone()))
+    }
+
+    /// Whether the model supports the `developer` message role
+    ///
+    /// Developer messages are sent as system messages to other models.
+    fn supports_developer_role(&self) -> bool {
+        ["o1", "o3", "o4", "gpt-5"]
+            .iter()
+            .any(|prefix| self.model.starts_with(prefix))
+            && !self.model.starts_with("o1-mini")
+            && !self.model.starts_with("o1-preview")
+    }
+
+    /// Whether the model should be constrained to generate JSON for a task
+    fn json_mode(&self, task: &ModelTask) -> bool {
+        task.format == Format::Json && self.formats.contains(&Format::Json)
+    }
+
+    /// Ensure that the messages of a task mention JSON when JSON mode is used
+    ///
+    /// The OpenAI API rejects requests in JSON mode if the word "JSON" does
+    /// not appear in the messages so, if necessary, this appends the format instruction.
+    fn ensure_json_mentioned(task: &ModelTask) -> Option<ModelTask> {
+        let mentioned = task.messages.iter().any(|message| {
+            message.parts.iter().any(|part| match part {
+                MessagePart::Text(text) => text.value.to_lowercase().contains("json"),
+                _ => false,
+            })
+        });
+        if mentioned {
+            return None;
+        }
+
+        let mut task = task.clone();
+        if let Some(instruction) = format_instruction(&Format::Json) {
+            task.messages
+                .push(InstructionMessage::system(instruction, None));
         }
+        Some(task)
     }
 }
 
@@ -125,21 +288,147 @@ impl Model for OpenAIModel {
         &self.outputs
     }
 
-    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
-        match task.kind {
-            ModelTaskKind::MessageGeneration => self.message_generation(task).await,
-            ModelTaskKind::ImageGeneration => self.image_generation(task).await,
+    fn cost_per_mtok(&self) -> Option<f64> {
+        // Prices in USD per million input and output tokens, based on
+        // https://openai.com/api/pricing/ (will need to be updated periodically)
+        let (input, output) = match self.model.as_str() {
+            name if name.starts_with("gpt-4o-mini") => (0.15, 0.6),
+            name if name.starts_with("gpt-4o") => (2.5, 10.0),
+            name if name.starts_with("gpt-4.1-nano") => (0.1, 0.4),
+            name if name.starts_with("gpt-4.1-mini") => (0.4, 1.6),
+            name if name.starts_with("gpt-4.1") => (2.0, 8.0),
+            name if name.starts_with("gpt-4-turbo") => (10.0, 30.0),
+            name if name.starts_with("gpt-3.5-turbo") => (0.5, 1.5),
+            name if name.starts_with("o4-mini") || name.starts_with("o3-mini") => (1.1, 4.4),
+            name if name.starts_with("o3") => (2.0, 8.0),
+            _ => return None,
+        };
+        Some((3.0 * input + output) / 4.0)
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    fn token_encoding(&self) -> Option<TokenEncoding> {
+        let name = self.model.as_str();
+        if name.starts_with("gpt-4o")
+            || name.starts_with("gpt-4.1")
+            || name.starts_with("gpt-5")
+            || name.starts_with("o1")
+            || name.starts_with("o3")
+            || name.starts_with("o4")
+        {
+            Some(TokenEncoding::O200kBase)
+        } else if name.starts_with("gpt-4") || name.starts_with("gpt-3.5") {
+            Some(TokenEncoding::Cl100kBase)
+        } else {
+            None
         }
     }
+
+    fn native_formats(&self) -> &[Format] {
+        &self.formats
+    }
+
+    async fn health(&self) -> ModelHealth {
+        let started = Instant::now();
+        let result = match Self::client() {
+            Ok(client) => client
+                .models()
+                .retrieve(&self.model)
+                .await
+                .map_err(Report::from),
+            Err(error) => Err(error),
+        };
+        ModelHealth::probed(self.id(), started.elapsed(), result)
+    }
+
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        self.perform(task, None).await
+    }
+
+    async fn perform_task_streaming(
+        &self,
+        task: &ModelTask,
+        on_delta: &DeltaCallback,
+    ) -> Result<ModelOutput> {
+        self.perform(task, Some(on_delta)).await
+    }
 }
 
 impl OpenAIModel {
+    /// Perform a task, optionally streaming generated text to a callback
+    async fn perform(
+        &self,
+        task: &ModelTask,
+        on_delta: Option<&DeltaCallback>,
+    ) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let json_task = self
+            .json_mode(task)
+            .then(|| Self::ensure_json_mentioned(task))
+            .flatten();
+        let task = json_task.as_ref().unwrap_or(task);
+
+        // Send frames of videos to models which accept images but not video
+        let mut warnings = Vec::new();
+        let video_task =
+            if self.inputs.contains(&ModelIO::Image) && !self.inputs.contains(&ModelIO::Video) {
+                replace_videos_with_frames(task, &mut warnings).await
+            } else {
+                None
+            };
+        let task = video_task.as_ref().unwrap_or(task);
+
+        let mut output = match task.kind {
+            ModelTaskKind::MessageGeneration
+            | ModelTaskKind::CaptionGeneration
+            | ModelTaskKind::CodeGeneration
+            | ModelTaskKind::DataDescription
+            | ModelTaskKind::Translation
+            | ModelTaskKind::QuestionAnswering => self.message_generation(task, on_delta).await,
+            ModelTaskKind::ImageGeneration => self.image_generation(task).await,
+        }?;
+        output.warnings.splice(0..0, warnings);
+
+        Ok(output)
+    }
+
+    /// Create an audit record of a request and its response, if auditing is enabled for the task
+    fn audit<Req: Serialize, Res: Serialize>(
+        &self,
+        task: &ModelTask,
+        request: &Req,
+        response: Option<&Res>,
+    ) -> Result<Option<ModelAudit>> {
+        if !audit_enabled(task) {
+            return Ok(None);
+        }
+
+        let response = response.map(serde_json::to_value).transpose()?;
+        Ok(Some(ModelAudit::new(&self.id(), request, response)?))
+    }
+
     /// Create a client with the correct API key
     fn client() -> Result<AsyncOpenAIClient<OpenAIConfig>> {
-        let api_key = secrets::env_or_get(API_KEY)?;
+        Self::client_with_key(secrets::env_or_get(API_KEY)?)
+    }
+
+    /// Create a client with the API key routed to for a task
+    fn task_client(&self, task: &ModelTask) -> Result<AsyncOpenAIClient<OpenAIConfig>> {
+        Self::client_with_key(api_key(API_KEY, &self.id(), Some(task))?)
+    }
+
+    /// Create a client with an API key
+    fn client_with_key(api_key: String) -> Result<AsyncOpenAIClient<OpenAIConfig>> {
         Ok(AsyncOpenAIClient::with_config(
-            OpenAIConfig::new().with_api_key(api_key),
-        ))
+            OpenAIConfig::new()
+                .with_api_key(api_key)
+                .with_api_base(base_url()),
+        )
+        .with_http_client(models_config().requests.http_client()?))
     }
 
     fn should_upload_attachment(attachment: &InstructionAttachment) -> bool {
@@ -152,24 +441,12 @@ impl OpenAIModel {
         }
     }
 
-    fn should_retry_with_vision(model: &str, error_body: &str) -> bool {
-        (model.starts_with("gpt-5") || model.starts_with("gpt-4.1"))
-            && (error_body.contains("Invalid input") && error_body.contains("context stuffing")
-                || error_body.contains("does not support image inputs"))
-    }
-
-    fn map_to_vision_model(model: &str) -> Option<String> {
-        if model.starts_with("gpt-5") {
-            Some("gpt-4.1-mini".to_string())
-        } else if model.starts_with("gpt-4.1") {
-            Some("gpt-4o-mini".to_string())
-        } else {
-            None
-        }
-    }
-
     #[tracing::instrument(skip_all)]
-    async fn message_generation(&self, task: &ModelTask) -> Result<ModelOutput> {
+    async fn message_generation(
+        &self,
+        task: &ModelTask,
+        on_delta: Option<&DeltaCallback>,
+    ) -> Result<ModelOutput> {
         if let Some(attachments) = task
             .attachments
             .as_ref()
@@ -186,109 +463,71 @@ impl OpenAIModel {
                 return ModelOutput::empty(self);
             }
 
-            return self.responses_message_generation(task, attachments).await;
+            return self
+                .responses_message_generation(task, attachments, on_delta)
+                .await;
         }
 
-        tracing::debug!("Sending chat completion request");
+        // Hosted tools are only available via the Responses API
+        if task.web_search.is_some() || task.code_interpreter.is_some() {
+            if task.dry_run {
+                return ModelOutput::empty(self);
+            }
 
-        let messages = task
-            .messages
-            .iter()
-            .map(|message| match message.role.unwrap_or_default() {
-                MessageRole::System => {
-                    ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
-                        content: ChatCompletionRequestSystemMessageContent::Text(
-                            message
-                                .parts
-                                .iter()
-                                .filter_map(|part| match part {
-                                    MessagePart::Text(text) => Some(text.to_value_string()),
-                                    _ => {
-                                        tracing::warn!(
-                                            "System message part `{part}` is ignored by model `{}`",
-                                            self.id()
-                                        );
-                                        None
-                                    }
-                                })
-                                .join("\n\n"),
-                        ),
-                        ..Default::default()
-                    })
-                }
-                MessageRole::User => {
-                    let content = message
-                        .parts
-                        .iter()
-                        .filter_map(|part| match part {
-                            MessagePart::Text(text) => {
-                                Some(ChatCompletionRequestUserMessageContentPart::Text(
-                                    ChatCompletionRequestMessageContentPartText {
-                                        text: text.to_value_string(),
-                                    },
-                                ))
-                            }
-                            MessagePart::ImageObject(ImageObject { content_url, .. }) => {
-                                Some(ChatCompletionRequestUserMessageContentPart::ImageUrl(
-                                    ChatCompletionRequestMessageContentPartImage {
-                                        image_url: ImageUrl {
-                                            url: content_url.clone(),
-                                            detail: Some(ImageDetail::Auto),
-                                        },
-                                    },
-                                ))
-                            }
-                            _ => {
-                                tracing::warn!(
-                                    "User message part `{part}` is ignored by model `{}`",
-                                    self.id()
-                                );
-                                None
-                            }
-                        })
-                        .collect_vec();
+            return self.responses_message_generation(task, &[], on_delta).await;
+        }
 
-                    ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
-                        content: ChatCompletionRequestUserMessageContent::Array(content),
-                        ..Default::default()
-                    })
-                }
-                MessageRole::Model => {
-                    let content = ChatCompletionRequestAssistantMessageContent::Text(
-                        message
-                            .parts
-                            .iter()
-                            .filter_map(|part| match part {
-                                MessagePart::Text(text) => Some(text.to_value_string()),
-                                _ => {
-                                    tracing::warn!(
-                                        "Assistant message part `{part}` is ignored by model `{}`",
-                                        self.id()
-                                    );
-                                    None
-                                }
-                            })
-                            .join(""),
-                    );
+        tracing::debug!("Sending chat completion request");
 
-                    ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
-                        content: Some(content),
-                        ..Default::default()
-                    })
-                }
-            })
-            .collect();
+        let mut warnings = Vec::new();
+        let mut manifest = PromptManifest::new();
+        let messages = self.messages_to_chat_messages(task, &mut warnings, &mut manifest);
+
+        // Request audio, as well as text, from models which can generate it
+        let audio_output = self.outputs.contains(&ModelIO::Audio);
 
         // Create the request
         let request = CreateChatCompletionRequest {
             model: self.model.clone(),
             messages,
             presence_penalty: task.repeat_penalty,
+            frequency_penalty: task.frequency_penalty,
+            n: task.candidates.filter(|candidates| *candidates > 1),
+            logit_bias: task.logit_bias.as_ref().map(|bias| {
+                bias.iter()
+                    .map(|(token, bias)| (token.clone(), serde_json::Value::from(*bias)))
+                    .collect()
+            }),
             temperature: task.temperature,
             seed: task.seed.map(|seed| seed as i64),
             max_completion_tokens: task.max_tokens.map(|tokens| tokens as u32),
             top_p: task.top_p,
-            stop: task.stop.clone().map(Stop::String),
+            stop: Self::stop_sequences(task)?.map(Stop::StringArray),
+            response_format: self.json_mode(task).then_some(ResponseFormat::JsonObject),
+            modalities: audio_output.then(|| {
+                vec![
+                    ChatCompletionModalities::Text,
+                    ChatCompletionModalities::Audio,
+                ]
+            }),
+            audio: audio_output
+                .then(|| audio::audio_options(task))
+                .transpose()?,
+            tools: (!task.tools.is_empty()).then(|| {
+                task.tools
+                    .iter()
+                    .map(|tool| ChatCompletionTool {
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionObject {
+                            name: tool.name.clone(),
+                            description: Some(tool.description.clone()),
+                            parameters: Some(tool.parameters.clone()),
+                            strict: None,
+                        },
+                    })
+                    .collect()
+            }),
+            user: task.user.clone(),
             ..Default::default()
         };
 
@@ -296,11 +535,11 @@ impl OpenAIModel {
         macro_rules! ignore_option {
             ($name:ident) => {
                 if task.$name.is_some() {
-                    tracing::warn!(
+                    warnings.push(ModelWarning::ignored_option(format!(
                         "Option `{}` is ignored by model `{}` for chat completion",
                         stringify!($name),
                         self.name()
-                    )
+                    )))
                 }
             };
             ($($name:ident),*) => {
@@ -319,637 +558,3642 @@ impl OpenAIModel {
             tfs_z,
             top_k
         );
+        if task
+            .prompt_caching
+            .as_ref()
+            .is_some_and(|caching| caching.key.is_some())
+        {
+            warnings.push(ModelWarning::ignored_option(format!(
+                "Option `prompt_caching.key` is ignored by model `{}` for chat completion",
+                self.name()
+            )));
+        }
 
         if task.dry_run {
             return ModelOutput::empty(self);
         }
 
         // Send the request
-        let client = Self::client()?;
-        let mut response = client.chat().create(request).await?;
-
-        // Get the content of the first message
-        let text = response
-            .choices
-            .pop()
-            .and_then(|choice| choice.message.content)
-            .unwrap_or_default();
-
-        ModelOutput::from_text(self, &task.format, text).await
-    }
-
-    fn supports_attachments(&self) -> bool {
-        self.inputs.contains(&ModelIO::Image)
-            || self.inputs.contains(&ModelIO::Audio)
-            || self.inputs.contains(&ModelIO::Video)
-    }
-
-    #[tracing::instrument(skip_all)]
-    async fn responses_message_generation(
-        &self,
-        task: &ModelTask,
-        attachments: &[InstructionAttachment],
-    ) -> Result<ModelOutput> {
-        tracing::debug!("Sending responses request with attachments");
+        let client = self.task_client(task)?;
+        let start_time = Timestamp::now();
 
-        let api_key = secrets::env_or_get(API_KEY)?;
-        let http_client = HttpClient::builder()
-            .timeout(Duration::from_secs(120))
-            .build()?;
+        // Audio is not streamed so, if requested, get the complete response
+        // and pass the transcript to `on_delta`
+        if audio_output {
+            let response = client.chat().create(request.clone()).await?;
+            let audit = self.audit(task, &request, Some(&response))?;
+            let usage = response.usage.as_ref().map(chat_usage);
+            let version = response.model;
+            let Some(choice) = response
+                .choices
+                .into_iter()
+                .min_by_key(|choice| choice.index)
+            else {
+                bail!("OpenAI response did not contain any choices");
+            };
+            let finish_reason = choice.finish_reason.map(finish_reason);
+            let refusal = choice.message.refusal;
 
-        let mut uploaded = Vec::new();
-        let mut attempted_upload = false;
-        for attachment in attachments {
-            if !Self::should_upload_attachment(attachment) {
-                tracing::debug!(
-                    "Skipping upload for attachment `{}` with media type {:?}",
-                    attachment.alias,
-                    attachment.file.media_type
-                );
-                continue;
+            let (transcript, audio) = match choice.message.audio {
+                Some(audio) => (
+                    audio.transcript,
+                    Some(audio::audio_object(task, &audio.data)),
+                ),
+                None => (String::new(), None),
+            };
+            let text = choice.message.content.unwrap_or(transcript);
+            if let Some(on_delta) = on_delta {
+                on_delta(&text);
             }
 
-            attempted_upload = true;
-            match self
-                .upload_attachment(&http_client, &api_key, attachment)
-                .await
-            {
-                Ok(uploaded_attachment) => uploaded.push(uploaded_attachment),
-                Err(error) => {
-                    tracing::warn!(
-                        "Failed to upload attachment `{}`: {error}",
-                        attachment.alias
-                    );
-                }
-            }
+            let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+            output.audio = audio;
+            output.finish_reason = finish_reason;
+            output.refusal = refusal;
+            output.usage = usage;
+            output.warnings = warnings;
+            output.audit = audit;
+            output.manifest = Some(manifest);
+            output.record_invocation(self, Some(version), start_time);
+            return Ok(output);
         }
 
-        let mut messages = self.messages_to_response_input(task);
+        let mut tool_calls = Vec::new();
+        let mut finish = None;
+        let mut refusal: Option<String> = None;
+        let mut usage = None;
+        let mut version = None;
+        let (candidates, audit) = if let Some(on_delta) = on_delta {
+            if !task.tools.is_empty() {
+                warnings.push(ModelWarning::ignored_option(format!(
+                    "Option `tools` is ignored by model `{}` when streaming",
+                    self.name()
+                )));
+            }
 
-        if attempted_upload && uploaded.is_empty() {
-            bail!("No attachments were uploaded successfully.");
-        }
+            // Token usage is only sent, in a final chunk without any choices, if requested.
+            // Tools are not sent because tool calls are not read from streamed deltas.
+            let request = CreateChatCompletionRequest {
+                stream_options: Some(ChatCompletionStreamOptions {
+                    include_usage: true,
+                }),
+                tools: None,
+                ..request
+            };
 
-        if let Some(position) = messages.iter().rposition(|message| message.role == "user") {
-            if !uploaded.is_empty() {
-                for attachment in &uploaded {
-                    messages[position].content.extend(attachment.to_contents());
+            // Stream the response, accumulating the content of each choice
+            // but only calling `on_delta` for the first
+            let audit = self.audit(task, &request, None::<&serde_json::Value>)?;
+            let mut stream = client.chat().create_stream(request).await?;
+            let mut candidates: Vec<String> = Vec::new();
+            while let Some(response) = stream.next().await {
+                let response = response?;
+                version.get_or_insert(response.model);
+                if let Some(completion) = &response.usage {
+                    usage = Some(chat_usage(completion));
+                }
+                for choice in response.choices {
+                    if choice.index == 0 {
+                        if choice.finish_reason.is_some() {
+                            finish = choice.finish_reason;
+                        }
+                        if let Some(delta) = choice.delta.refusal {
+                            refusal.get_or_insert_default().push_str(&delta);
+                        }
+                    }
+                    let Some(content) = choice.delta.content else {
+                        continue;
+                    };
+                    let index = choice.index as usize;
+                    if candidates.len() <= index {
+                        candidates.resize(index + 1, String::new());
+                    }
+                    if index == 0 {
+                        on_delta(&content);
+                    }
+                    candidates[index].push_str(&content);
                 }
             }
-        } else if !uploaded.is_empty() {
-            messages.push(ResponseMessage {
-                role: "user".to_string(),
-                content: uploaded
-                    .iter()
-                    .flat_map(UploadedAttachment::to_contents)
-                    .collect(),
-            });
-        }
+            (candidates, audit)
+        } else {
+            let response = client.chat().create(request.clone()).await?;
+            let audit = self.audit(task, &request, Some(&response))?;
+            usage = response.usage.as_ref().map(chat_usage);
+            version = Some(response.model);
 
-        let mut request = ResponsesRequest {
-            model: self.model.clone(),
-            input: messages,
-            temperature: task.temperature,
-            top_p: task.top_p,
-            stop: task.stop.as_ref().map(|stop| vec![stop.clone()]),
-            seed: task.seed,
-            max_output_tokens: task.max_tokens,
+            // Get the content of each choice, in order, and the tool calls of the first
+            let candidates = response
+                .choices
+                .into_iter()
+                .sorted_by_key(|choice| choice.index)
+                .map(|choice| {
+                    if choice.index == 0 {
+                        finish = choice.finish_reason;
+                        refusal = choice.message.refusal;
+                        tool_calls.extend(choice.message.tool_calls.into_iter().flatten().map(
+                            |call| {
+                                ModelOutputPart::ToolCall(ToolCall {
+                                    id: call.id,
+                                    name: call.function.name,
+                                    arguments: call.function.arguments,
+                                })
+                            },
+                        ));
+                    }
+                    choice.message.content.unwrap_or_default()
+                })
+                .collect();
+            (candidates, audit)
         };
 
-        let response = http_client
-            .post("https://api.openai.com/v1/responses")
-            .bearer_auth(&api_key)
-            .header("OpenAI-Beta", "assistants=v2")
-            .json(&request)
-            .send()
-            .await?;
+        let mut output = ModelOutput::from_candidates(self, &task.format, candidates).await?;
+        output.parts = tool_calls;
+        output.finish_reason = finish.map(finish_reason);
+        output.refusal = refusal;
+        output.usage = usage;
+        output.warnings = warnings;
+        output.audit = audit;
+        output.manifest = Some(manifest);
+        output.record_invocation(self, version, start_time);
 
-        let response = if response.status().is_success() {
-            response.json::<ResponsesResponse>().await?
-        } else {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            tracing::warn!("OpenAI responses API returned {status}: {body}");
+        Ok(output)
+    }
 
-            if Self::should_retry_with_vision(&self.model, &body) {
-                if let Some(mapped) = Self::map_to_vision_model(&self.model) {
-                    tracing::info!(
-                        "Retrying attachment request with vision-capable model `{}`",
-                        mapped
-                    );
-                    request.model = mapped;
-
-                    let retry = http_client
-                        .post("https://api.openai.com/v1/responses")
-                        .bearer_auth(&api_key)
-                        .header("OpenAI-Beta", "assistants=v2")
-                        .json(&request)
-                        .send()
-                        .await?;
-
-                    if retry.status().is_success() {
-                        retry.json::<ResponsesResponse>().await?
-                    } else {
-                        let retry_status = retry.status();
-                        let retry_body = retry.text().await.unwrap_or_default();
-                        bail!(
-                            "OpenAI responses API returned {retry_status} after vision retry: {retry_body}"
-                        );
+    /// Convert the messages of a task into chat completion request messages
+    ///
+    /// Tool results are sent as separate `tool` messages preceding the message
+    /// they are a part of. Because assistant messages can only contain text, any
+    /// images in them are replayed as a following user message. What happens to
+    /// each part is recorded in the `manifest`.
+    fn messages_to_chat_messages(
+        &self,
+        task: &ModelTask,
+        warnings: &mut Vec<ModelWarning>,
+        manifest: &mut PromptManifest,
+    ) -> Vec<ChatCompletionRequestMessage> {
+        let mut messages = Vec::new();
+        for (message_index, message) in task.messages.iter().enumerate() {
+            let mut record = |part_index: usize,
+                              part: &MessagePart,
+                              disposition: ManifestDisposition,
+                              detail: Option<String>| {
+                manifest.part(message_index, part_index, part, disposition, detail)
+            };
+
+            // Replay the calls that the tool results are for on the preceding
+            // assistant message (adding one if necessary) as required by the API
+            let calls = message
+                .parts
+                .iter()
+                .filter_map(|part| match part {
+                    MessagePart::ToolResult(result) => task
+                        .tool_calls
+                        .iter()
+                        .find(|call| call.id == result.call_id),
+                    _ => None,
+                })
+                .map(|call| ChatCompletionMessageToolCall {
+                    id: call.id.clone(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect_vec();
+            if !calls.is_empty() {
+                match messages.last_mut() {
+                    Some(ChatCompletionRequestMessage::Assistant(assistant)) => {
+                        assistant.tool_calls.get_or_insert_default().extend(calls)
                     }
-                } else {
-                    bail!("OpenAI responses API returned {status}: {body}");
+                    _ => messages.push(ChatCompletionRequestMessage::Assistant(
+                        ChatCompletionRequestAssistantMessage {
+                            tool_calls: Some(calls),
+                            ..Default::default()
+                        },
+                    )),
                 }
-            } else {
-                bail!("OpenAI responses API returned {status}: {body}");
             }
-        };
 
-        let mut text_segments = Vec::new();
-        for item in response.output {
-            for content in item.content {
-                match content {
-                    ResponseOutputContent::OutputText { text } => text_segments.push(text),
-                    ResponseOutputContent::SummaryText { text } => text_segments.push(text),
-                    _ => {}
+            for part in &message.parts {
+                if let MessagePart::ToolResult(result) = part {
+                    messages.push(ChatCompletionRequestMessage::Tool(
+                        ChatCompletionRequestToolMessage {
+                            content: ChatCompletionRequestToolMessageContent::Text(
+                                result.content.clone(),
+                            ),
+                            tool_call_id: result.call_id.clone(),
+                        },
+                    ));
                 }
             }
-        }
 
-        let text = text_segments.join("\n").trim().to_string();
-
-        if text.is_empty() {
-            bail!("OpenAI response did not contain output text");
-        }
-
-        ModelOutput::from_text(self, &task.format, text).await
-    }
+            let role = message.role.unwrap_or_default();
+            match role {
+                MessageRole::System | MessageRole::Developer => {
+                    let text = message
+                        .parts
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, part)| match part {
+                            MessagePart::Text(text) => {
+                                record(index, part, ManifestDisposition::Included, None);
+                                Some(text.to_value_string())
+                            }
+                            MessagePart::ToolResult(..) => {
+                                record(index, part, ManifestDisposition::Included, None);
+                                None
+                            }
+                            _ => {
+                                let warning = format!(
+                                    "{role} message part `{part}` is ignored by model `{}`",
+                                    self.id()
+                                );
+                                record(
+                                    index,
+                                    part,
+                                    ManifestDisposition::Excluded,
+                                    Some(warning.clone()),
+                                );
+                                warnings.push(ModelWarning::ignored_part(warning));
+                                None
+                            }
+                        })
+                        .join("\n\n");
 
-    #[tracing::instrument(skip_all)]
-    async fn upload_attachment(
-        &self,
-        client: &HttpClient,
-        api_key: &str,
-        attachment: &InstructionAttachment,
-    ) -> Result<UploadedAttachment> {
-        let bytes = attachment_bytes(attachment)?;
-        let filename = if attachment.file.name.trim().is_empty() {
-            format!("{}.bin", attachment.alias)
-        } else {
-            attachment.file.name.clone()
-        };
-        let media_type = attachment
-            .file
-            .media_type
-            .clone()
-            .unwrap_or_else(|| "application/octet-stream".to_string());
-
-        tracing::debug!(
-            "Uploading attachment `{}` ({} bytes, {})",
-            attachment.alias,
-            bytes.len(),
-            media_type
-        );
+                    messages.push(
+                        if matches!(role, MessageRole::Developer) && self.supports_developer_role()
+                        {
+                            ChatCompletionRequestMessage::Developer(
+                                ChatCompletionRequestDeveloperMessage {
+                                    content: ChatCompletionRequestDeveloperMessageContent::Text(
+                                        text,
+                                    ),
+                                    name: message.name.clone(),
+                                },
+                            )
+                        } else {
+                            ChatCompletionRequestMessage::System(
+                                ChatCompletionRequestSystemMessage {
+                                    content: ChatCompletionRequestSystemMessageContent::Text(text),
+                                    name: message.name.clone(),
+                                },
+                            )
+                        },
+                    );
+                }
+                MessageRole::User => {
+                    let content = message
+                        .parts
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, part)| {
+                            let content = match part {
+                                MessagePart::Text(text) => {
+                                    Some(ChatCompletionRequestUserMessageContentPart::Text(
+                                        ChatCompletionRequestMessageContentPartText {
+                                            text: text.to_value_string(),
+                                        },
+                                    ))
+                                }
+                                MessagePart::ImageObject(image) => {
+                                    Some(Self::chat_image_part(image, task))
+                                }
+                                MessagePart::AudioObject(audio)
+                                    if self.inputs.contains(&ModelIO::Audio) =>
+                                {
+                                    match audio::chat_audio_part(audio) {
+                                        Ok(part) => Some(part),
+                                        Err(error) => {
+                                            let warning = format!(
+                                                "Audio is ignored by model `{}`: {error}",
+                                                self.id()
+                                            );
+                                            record(
+                                                index,
+                                                part,
+                                                ManifestDisposition::Excluded,
+                                                Some(warning.clone()),
+                                            );
+                                            warnings.push(ModelWarning::ignored_part(warning));
+                                            return None;
+                                        }
+                                    }
+                                }
+                                MessagePart::ToolResult(..) => None,
+                                MessagePart::File(file) => {
+                                    // Chat completions only accept files as text
+                                    let result = match file_text(file) {
+                                        Ok(text) => {
+                                            record(
+                                                index,
+                                                part,
+                                                ManifestDisposition::Transformed,
+                                                Some(EXTRACTED_FILE_TEXT.into()),
+                                            );
+                                            Some(ChatCompletionRequestUserMessageContentPart::Text(
+                                                ChatCompletionRequestMessageContentPartText {
+                                                    text: format!(
+                                                        "File `{}`:\n\n{text}",
+                                                        file.name
+                                                    ),
+                                                },
+                                            ))
+                                        }
+                                        Err(error) => {
+                                            let warning = format!(
+                                                "File is ignored by model `{}`: {error}",
+                                                self.id()
+                                            );
+                                            record(
+                                                index,
+                                                part,
+                                                ManifestDisposition::Excluded,
+                                                Some(warning.clone()),
+                                            );
+                                            warnings.push(ModelWarning::ignored_part(warning));
+                                            None
+                                        }
+                                    };
+                                    return result;
+                                }
+                                _ => {
+                                    let warning = format!(
+                                        "User message part `{part}` is ignored by model `{}`",
+                                        self.id()
+                                    );
+                                    record(
+                                        index,
+                                        part,
+                                        ManifestDisposition::Excluded,
+                                        Some(warning.clone()),
+                                    );
+                                    warnings.push(ModelWarning::ignored_part(warning));
+                                    return None;
+                                }
+                            };
+                            record(index, part, ManifestDisposition::Included, None);
+                            content
+                        })
+                        .collect_vec();
 
-        let part = multipart::Part::bytes(bytes)
-            .file_name(filename.clone())
-            .mime_str(&media_type)?;
+                    if !content.is_empty() {
+                        messages.push(ChatCompletionRequestMessage::User(
+                            ChatCompletionRequestUserMessage {
+                                content: ChatCompletionRequestUserMessageContent::Array(content),
+                                name: message.name.clone(),
+                            },
+                        ));
+                    }
+                }
+                MessageRole::Model => {
+                    let mut images = Vec::new();
+                    let content = ChatCompletionRequestAssistantMessageContent::Text(
+                        message
+                            .parts
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(index, part)| match part {
+                                MessagePart::Text(text) => {
+                                    record(index, part, ManifestDisposition::Included, None);
+                                    Some(text.to_value_string())
+                                }
+                                MessagePart::ImageObject(image) => {
+                                    record(
+                                        index,
+                                        part,
+                                        ManifestDisposition::Transformed,
+                                        Some(REPLAYED_IMAGE.into()),
+                                    );
+                                    images.push(Self::chat_image_part(image, task));
+                                    None
+                                }
+                                MessagePart::ToolResult(..) => {
+                                    record(index, part, ManifestDisposition::Included, None);
+                                    None
+                                }
+                                _ => {
+                                    let warning = format!(
+                                        "Assistant message part `{part}` is ignored by model `{}`",
+                                        self.id()
+                                    );
+                                    record(
+                                        index,
+                                        part,
+                                        ManifestDisposition::Excluded,
+                                        Some(warning.clone()),
+                                    );
+                                    warnings.push(ModelWarning::ignored_part(warning));
+                                    None
+                                }
+                            })
+                            .join(""),
+                    );
 
-        let form = multipart::Form::new()
-            .text("purpose", "assistants")
-            .part("file", part);
+                    messages.push(ChatCompletionRequestMessage::Assistant(
+                        ChatCompletionRequestAssistantMessage {
+                            content: Some(content),
+                            name: message.name.clone(),
+                            ..Default::default()
+                        },
+                    ));
 
-        let response = client
-            .post("https://api.openai.com/v1/files")
-            .bearer_auth(&api_key)
-            .header("OpenAI-Beta", "assistants=v2")
-            .multipart(form)
-            .send()
-            .await?;
+                    if !images.is_empty() {
+                        let mut content = vec![ChatCompletionRequestUserMessageContentPart::Text(
+                            ChatCompletionRequestMessageContentPartText {
+                                text: ASSISTANT_IMAGES_PREAMBLE.to_string(),
+                            },
+                        )];
+                        content.append(&mut images);
 
-        let response = if response.status().is_success() {
-            response.json::<UploadFileResponse>().await?
-        } else {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            bail!("OpenAI file upload returned {status}: {body}");
-        };
+                        messages.push(ChatCompletionRequestMessage::User(
+                            ChatCompletionRequestUserMessage {
+                                content: ChatCompletionRequestUserMessageContent::Array(content),
+                                ..Default::default()
+                            },
+                        ));
+                    }
+                }
+            }
+        }
 
-        Ok(UploadedAttachment {
-            alias: attachment.alias.clone(),
-            file_id: response.id,
-            media_type,
-        })
+        messages
     }
 
-    fn messages_to_response_input(&self, task: &ModelTask) -> Vec<ResponseMessage> {
-        task.messages
-            .iter()
-            .map(|message| {
-                let role = match message.role.unwrap_or_default() {
-                    MessageRole::System => "system",
-                    MessageRole::User => "user",
-                    MessageRole::Model => "assistant",
-                }
-                .to_string();
+    /// Create a chat completion content part for an image
+    fn chat_image_part(
+        image: &ImageObject,
+        task: &ModelTask,
+    ) -> ChatCompletionRequestUserMessageContentPart {
+        let detail = match task.image_detail.unwrap_or_default() {
+            ImageDetailLevel::Auto => ImageDetail::Auto,
+            ImageDetailLevel::Low => ImageDetail::Low,
+            ImageDetailLevel::High => ImageDetail::High,
+        };
 
-                let content = message
-                    .parts
-                    .iter()
-                    .filter_map(|part| match part {
-                        MessagePart::Text(text) => {
-                            if role == "assistant" {
-                                Some(ResponseContent::OutputText {
-                                    text: text.to_value_string(),
-                                })
-                            } else {
-                                Some(ResponseContent::InputText {
-                                    text: text.to_value_string(),
-                                })
-                            }
-                        }
-                        MessagePart::ImageObject(ImageObject { content_url, .. }) => Some(
-                            ResponseContent::InputImage {
-                                file_id: None,
-                                image_url: Some(content_url.clone()),
-                            },
-                        ),
-                        other => {
-                            tracing::warn!(
-                                "Message part `{other}` is currently unsupported by OpenAI Responses API"
-                            );
-                            None
-                        }
-                    })
-                    .collect();
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(
+            ChatCompletionRequestMessageContentPartImage {
+                image_url: ImageUrl {
+                    url: image.content_url.clone(),
+                    detail: Some(detail),
+                },
+            },
+        )
+    }
 
-                ResponseMessage { role, content }
-            })
-            .collect()
+    fn supports_attachments(&self) -> bool {
+        self.inputs.contains(&ModelIO::Image)
+            || self.inputs.contains(&ModelIO::Audio)
+            || self.inputs.contains(&ModelIO::Video)
     }
 
     #[tracing::instrument(skip_all)]
-    async fn image_generation(&self, task: &ModelTask) -> Result<ModelOutput> {
-        tracing::debug!("Sending image generation request");
+    async fn responses_message_generation(
+        &self,
+        task: &ModelTask,
+        attachments: &[InstructionAttachment],
+        on_delta: Option<&DeltaCallback>,
+    ) -> Result<ModelOutput> {
+        tracing::debug!("Sending responses request");
 
-        // Create a prompt from the last message (assumed to be a user message)
-        let prompt = task
-            .messages
-            .last()
-            .map(|message| {
-                message
-                    .parts
-                    .iter()
-                    .flat_map(|part| match part {
-                        MessagePart::Text(text) => Some(text.to_value_string()),
-                        _ => {
-                            tracing::warn!(
-                                "Message part `{part}` is ignored by model `{}`",
-                                self.id()
-                            );
-                            None
-                        }
-                    })
-                    .join("")
-            })
-            .unwrap_or_default();
+        let mut warnings = Vec::new();
 
-        // Create the request
-        let mut request = CreateImageRequestArgs::default();
-        let request = request
-            .prompt(prompt)
-            .response_format(ImageResponseFormat::Url);
+        if task.frequency_penalty.is_some()
+            || task.logit_bias.is_some()
+            || task.candidates.is_some_and(|candidates| candidates > 1)
+        {
+            warnings.push(ModelWarning::ignored_option(format!(
+                "Options `frequency_penalty`, `logit_bias`, and `candidates` are ignored by model `{}` for requests with attachments",
+                self.name()
+            )));
+        }
 
-        if let Some((w, h)) = task.image_size {
-            match (w, h) {
-                (256, 256) => {
-                    request.size(ImageSize::S256x256);
-                }
-                (512, 512) => {
-                    request.size(ImageSize::S512x512);
+        // Correct missing or wrong media types before deciding whether to upload
+        let attachments = attachments
+            .iter()
+            .cloned()
+            .map(|mut attachment| {
+                if let Some(message) = correct_media_type(&mut attachment) {
+                    warnings.push(ModelWarning::correction(message));
                 }
-                (1024, 1024) => {
-                    request.size(ImageSize::S1024x1024);
+                attachment
+            })
+            .collect_vec();
+
+        let transport = self.transport(task)?;
+
+        let policy = task.attachment_failure_policy.unwrap_or_default();
+        let mut failures = Vec::new();
+        let mut manifest = PromptManifest::new();
+
+        // Replace videos with frames sampled from them for models without native video
+        // support, describing the sampling in the description of each frame
+        let mut expanded = Vec::with_capacity(attachments.len());
+        for attachment in attachments {
+            let is_video = attachment
+                .file
+                .media_type
+                .as_deref()
+                .is_some_and(|media_type| media_type.starts_with("video/"));
+            if !is_video
+                || self.inputs.contains(&ModelIO::Video)
+                || !self.inputs.contains(&ModelIO::Image)
+            {
+                expanded.push(attachment);
+                continue;
+            }
+
+            let sampling = task.video_sampling.clone().unwrap_or_default();
+            match extract_attachment_frames(&attachment, &sampling).await {
+                Ok(frames) if !frames.is_empty() => {
+                    let count = frames.len();
+                    manifest.attachment(
+                        &attachment,
+                        ManifestDisposition::Transformed,
+                        Some(format!(
+                            "replaced by {count} frame(s) ({})",
+                            sampling.describe(count)
+                        )),
+                    );
+                    for (index, mut frame) in frames.into_iter().enumerate() {
+                        frame.options.description = Some(format!(
+                            "Frame {} of {count} from video `{}` ({})",
+                            index + 1,
+                            attachment.alias,
+                            sampling.describe(count)
+                        ));
+                        expanded.push(frame);
+                    }
                 }
-                (1024, 1792) => {
-                    request.size(ImageSize::S1024x1792);
+                Ok(..) => {
+                    let reason = format!(
+                        "No frames could be extracted from video attachment `{}`",
+                        attachment.alias
+                    );
+                    manifest.attachment(
+                        &attachment,
+                        ManifestDisposition::Excluded,
+                        Some(reason.clone()),
+                    );
+                    attachment_failed(policy, reason, &mut warnings, &mut failures)?
                 }
-                (1792, 1024) => {
-                    request.size(ImageSize::S1792x1024);
+                Err(error) => {
+                    let reason = format!(
+                        "Unable to extract frames from video attachment `{}`: {error}",
+                        attachment.alias
+                    );
+                    manifest.attachment(
+                        &attachment,
+                        ManifestDisposition::Excluded,
+                        Some(reason.clone()),
+                    );
+                    attachment_failed(policy, reason, &mut warnings, &mut failures)?
                 }
-                _ => bail!("Unsupported image size `{w}x{h}`"),
-            };
+            }
         }
+        let attachments = expanded;
 
-        if let Some(quality) = &task.image_quality {
-            match quality.to_lowercase().as_str() {
-                "std" | "standard" => {
-                    request.quality(ImageQuality::Standard);
-                }
-                "hd" | "high-definition" => {
-                    request.quality(ImageQuality::HD);
+        // Previews and inlined attachments are placed in their slot immediately
+        // whereas those needing upload are queued and slotted once uploaded. What
+        // happens to each is recorded by index so that the manifest is in the same
+        // order as the attachments, regardless of the order uploads complete in.
+        let mut slots: Vec<Option<UploadedAttachment>> = Vec::with_capacity(attachments.len());
+        let mut dispositions: Vec<Option<(ManifestDisposition, Option<String>)>> =
+            vec![None; attachments.len()];
+        let mut queued = Vec::new();
+        let delta_api_key = api_key(API_KEY, &self.id(), Some(task)).unwrap_or_default();
+        let delta_key = |attachment: &InstructionAttachment| {
+            attachment_delta_key(&base_url(), &delta_api_key, attachment)
+        };
+        for (index, attachment) in attachments.iter().enumerate() {
+            // Send a textual preview of attachments that can not be uploaded so
+            // that the model still knows that they exist
+            if !Self::should_upload_attachment(attachment) {
+                match preview_attachment(attachment, &models_config().attachments.preview) {
+                    Ok(preview) => {
+                        dispositions[index] = Some((
+                            ManifestDisposition::Transformed,
+                            Some("sent as a textual preview".into()),
+                        ));
+                        warnings.push(ModelWarning::skipped_attachment(format!(
+                            "Attachment `{}` with media type {:?} is not supported by model `{}` so a preview was sent instead",
+                            attachment.alias,
+                            attachment.file.media_type,
+                            self.name()
+                        )));
+                        slots.push(Some(UploadedAttachment {
+                            alias: attachment.alias.clone(),
+                            source: AttachmentSource::Preview(preview),
+                            media_type: "text/plain".into(),
+                            preamble: None,
+                        }));
+                    }
+                    Err(error) => {
+                        let reason = format!(
+                            "Attachment `{}` with media type {:?} is not supported by model `{}` and could not be previewed: {error}",
+                            attachment.alias,
+                            attachment.file.media_type,
+                            self.name()
+                        );
+                        dispositions[index] =
+                            Some((ManifestDisposition::Excluded, Some(reason.clone())));
+                        attachment_failed(policy, reason, &mut warnings, &mut failures)?
+                    }
                 }
-                _ => bail!("Unsupported image quality `{quality}`"),
-            };
-        }
+                continue;
+            }
 
-        if let Some(style) = &task.image_style {
-            match style.to_lowercase().as_str() {
-                "nat" | "natural" => {
-                    request.style(ImageStyle::Natural);
-                }
-                "viv" | "vivid" => {
-                    request.style(ImageStyle::Vivid);
-                }
-                _ => bail!("Unsupported image style `{style}`"),
-            };
-        }
+            let preamble = attachment_preamble(attachment, task.attachment_preamble.as_deref());
 
-        let request = request.build()?;
+            if let Some(mut inlined) = Self::inline_attachment(attachment) {
+                inlined.preamble = Some(preamble);
+                dispositions[index] = Some(sent_disposition(attachment, "inlined as a data URL"));
+                slots.push(Some(inlined));
+                continue;
+            }
 
-        // Warn about ignored task options
-        macro_rules! ignore_option {
-            ($name:ident) => {
-                if task.$name.is_some() {
-                    tracing::warn!(
-                        "Option `{}` is ignored by model `{}` for text-to-image generation",
-                        stringify!($name),
-                        self.name()
+            // Send attachments which were previously uploaded as a reference to the
+            // uploaded file, plus a diff if they have changed a little since
+            let name = delta_name(attachment);
+            let delta = attachment_bytes(attachment)
+                .map(|bytes| {
+                    attachment_delta(
+                        &delta_key(attachment),
+                        &name,
+                        &bytes,
+                        &models_config().attachments.delta,
                     )
+                })
+                .unwrap_or(AttachmentDelta::Full);
+            match delta {
+                AttachmentDelta::Unchanged { reference } => {
+                    dispositions[index] = Some(sent_disposition(
+                        attachment,
+                        "sent as a reference to the previously uploaded file",
+                    ));
+                    slots.push(Some(UploadedAttachment {
+                        alias: attachment.alias.clone(),
+                        source: AttachmentSource::FileId(reference),
+                        media_type: attachment_media_type(attachment),
+                        preamble: Some(preamble),
+                    }));
+                }
+                AttachmentDelta::Changed { reference, diff } => {
+                    dispositions[index] = Some((
+                        ManifestDisposition::Transformed,
+                        Some("sent as a diff against the previously uploaded file".into()),
+                    ));
+                    slots.push(Some(UploadedAttachment {
+                        alias: attachment.alias.clone(),
+                        source: AttachmentSource::Diff {
+                            file_id: reference,
+                            diff,
+                        },
+                        media_type: attachment_media_type(attachment),
+                        preamble: Some(preamble),
+                    }));
+                }
+                AttachmentDelta::Full => {
+                    queued.push((slots.len(), index, attachment, preamble));
+                    slots.push(None);
                 }
-            };
-            ($($name:ident),*) => {
-                $( ignore_option!($name); )*
             }
         }
-        ignore_option!(
-            mirostat,
-            mirostat_eta,
-            mirostat_tau,
-            num_ctx,
-            num_gqa,
-            num_gpu,
-            num_thread,
-            repeat_last_n,
-            repeat_penalty,
-            temperature,
-            seed,
-            stop,
-            max_tokens,
-            tfs_z,
-            top_k,
-            top_p
-        );
 
-        if task.dry_run {
-            return ModelOutput::empty(self);
+        // Upload attachments concurrently, with at most `upload-concurrency` in flight
+        let attempted_upload = !queued.is_empty();
+        let upload = |(slot, index, attachment, preamble)| {
+            let transport = transport.as_ref();
+            async move {
+                let result = Self::upload_attachment(transport, attachment).await;
+                (slot, index, attachment, preamble, result)
+            }
+        };
+        let mut queued = queued.into_iter();
+        let mut uploads = FuturesUnordered::new();
+        for item in queued
+            .by_ref()
+            .take(models_config().attachments.upload_concurrency.max(1))
+        {
+            uploads.push(upload(item));
+        }
+        while let Some((slot, index, attachment, preamble, result)) = uploads.next().await {
+            match result {
+                Ok(mut uploaded_attachment) => {
+                    if let (AttachmentSource::FileId(file_id), Ok(bytes)) =
+                        (&uploaded_attachment.source, attachment_bytes(attachment))
+                    {
+                        record_sent_attachment(&delta_key(attachment), file_id, &bytes);
+                    }
+                    uploaded_attachment.preamble = Some(preamble);
+                    dispositions[index] = Some(sent_disposition(attachment, "uploaded as a file"));
+                    slots[slot] = Some(uploaded_attachment);
+                }
+                Err(error) => {
+                    let reason = format!(
+                        "Failed to upload attachment `{}`: {error}",
+                        attachment.alias
+                    );
+                    dispositions[index] =
+                        Some((ManifestDisposition::Excluded, Some(reason.clone())));
+                    attachment_failed(policy, reason, &mut warnings, &mut failures)?
+                }
+            }
+            if let Some(item) = queued.next() {
+                uploads.push(upload(item));
+            }
+        }
+        let uploaded = slots.into_iter().flatten().collect_vec();
+        for (attachment, disposition) in attachments.iter().zip(dispositions) {
+            if let Some((disposition, detail)) = disposition {
+                manifest.attachment(attachment, disposition, detail);
+            }
         }
 
-        // Send the request
-        let client = Self::client()?;
-        let mut response = client.images().create(request).await?;
-
-        // Get the output
-        if response.data.is_empty() {
-            bail!("Response data is unexpectedly empty")
+        if policy == AttachmentFailurePolicy::RequireAll && !failures.is_empty() {
+            bail!(
+                "{} attachment(s) could not be provided to the model:\n\n- {}",
+                failures.len(),
+                failures.join("\n- ")
+            );
         }
-        let image = response.data.remove(0);
 
-        match image.as_ref() {
-            Image::Url { url, .. } => {
-                ModelOutput::from_url(self, "image/png", url.to_string()).await
-            }
-            _ => bail!("Unexpected image type"),
+        let mut messages = self.messages_to_response_input(task, &mut warnings, &mut manifest);
+
+        if attempted_upload && uploaded.is_empty() {
+            bail!("No attachments were uploaded successfully.");
         }
-    }
-}
 
-fn attachment_bytes(attachment: &InstructionAttachment) -> Result<Vec<u8>> {
-    let Some(content) = attachment.file.content.as_ref() else {
-        bail!(
-            "Attachment `{}` does not have any inline content to upload.",
-            attachment.alias
+        // Only reorder attachments into a stable prefix if caching is opted into
+        let stable_prefix = task
+            .prompt_caching
+            .as_ref()
+            .is_some_and(|caching| caching.stable_prefix);
+        add_attachment_contents(
+            &mut messages,
+            uploaded
+                .iter()
+                .flat_map(|attachment| attachment.to_contents(task.image_detail))
+                .collect(),
+            stable_prefix,
         );
-    };
 
-    if attachment
-        .file
-        .options
-        .transfer_encoding
-        .as_deref()
-        .map(|encoding| encoding.eq_ignore_ascii_case("base64"))
-        .unwrap_or(false)
-    {
-        BASE64.decode(content.as_bytes()).map_err(|error| {
-            eyre!(
-                "Attachment `{}` content is invalid base64: {error}",
-                attachment.alias
+        let mut request = self.responses_request(task, messages, on_delta.is_some())?;
+
+        let start_time = Timestamp::now();
+        let response = transport
+            .send(HttpRequest::post_json("/responses", &request)?)
+            .await?;
+
+        let response = if response.status().is_success() {
+            response
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            tracing::warn!("OpenAI responses API returned {status}: {body}");
+
+            let Some(mapped) = vision_retry_policy().substitute(&self.model, &body) else {
+                bail!("OpenAI responses API returned {status}: {body}");
+            };
+
+            warnings.push(ModelWarning::fallback(format!(
+                "Retrying attachment request with vision-capable model `{mapped}`"
+            )));
+            request.model = mapped;
+
+            let retry = transport
+                .send(HttpRequest::post_json("/responses", &request)?)
+                .await?;
+
+            if retry.status().is_success() {
+                retry
+            } else {
+                let retry_status = retry.status();
+                let retry_body = retry.text().await.unwrap_or_default();
+                bail!(
+                    "OpenAI responses API returned {retry_status} after vision retry: {retry_body}"
+                );
+            }
+        };
+
+        let (response, raw) = if let Some(on_delta) = on_delta {
+            (read_responses_stream(response, on_delta).await?, None)
+        } else {
+            let raw = response.json::<serde_json::Value>().await?;
+            (
+                serde_json::from_value::<ResponsesResponse>(raw.clone())?,
+                Some(raw),
+            )
+        };
+        let audit = self.audit(task, &request, raw.as_ref())?;
+
+        let finish_reason = response.finish_reason();
+        let refusal = response.refusal();
+        let usage = response.usage.as_ref().map(ResponseUsage::to_token_usage);
+        let version = response.model.clone();
+        let (text, parts) = response.into_text_and_parts();
+
+        let has_tool_calls = parts
+            .iter()
+            .any(|part| matches!(part, ModelOutputPart::ToolCall(..)));
+        if text.is_empty() && !has_tool_calls && refusal.is_none() {
+            bail!("OpenAI response did not contain output text");
+        }
+
+        let mut parts = parts;
+        if let Some(options) = task
+            .code_interpreter
+            .as_ref()
+            .filter(|options| options.download_files)
+        {
+            Self::download_container_files(
+                transport.as_ref(),
+                &options.download_dir()?,
+                &mut parts,
+                &mut warnings,
             )
+            .await;
+        }
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.parts = parts;
+        output.finish_reason = finish_reason;
+        output.refusal = refusal;
+        output.usage = usage;
+        output.warnings = warnings;
+        output.audit = audit;
+        output.manifest = Some(manifest);
+        output.record_invocation(self, version, start_time);
+
+        Ok(output)
+    }
+
+    /// Download the files created by the code interpreter
+    ///
+    /// Files are written to a subdirectory of `dir` named after their container and
+    /// the local path and media type recorded on their part. Files may not be available
+    /// immediately after the response so each is polled for, with a backoff, before
+    /// giving up with a warning.
+    async fn download_container_files(
+        transport: &dyn Transport,
+        dir: &Path,
+        parts: &mut [ModelOutputPart],
+        warnings: &mut Vec<ModelWarning>,
+    ) {
+        let mut used = HashSet::new();
+        for part in parts.iter_mut() {
+            let ModelOutputPart::File {
+                file_id,
+                filename,
+                container_id: Some(container_id),
+                path,
+                media_type,
+                ..
+            } = part
+            else {
+                continue;
+            };
+
+            let dest =
+                match download_path(dir, container_id, filename.as_deref(), file_id, &mut used) {
+                    Ok(dest) => dest,
+                    Err(error) => {
+                        warnings.push(ModelWarning::failed_download(format!(
+                        "Failed to download file `{file_id}` created by code interpreter: {error}"
+                    )));
+                        continue;
+                    }
+                };
+
+            match Self::download_container_file(transport, container_id, file_id, &dest).await {
+                Ok(content_type) => {
+                    *path = Some(dest.to_string_lossy().to_string());
+                    *media_type = content_type;
+                }
+                Err(error) => warnings.push(ModelWarning::failed_download(format!(
+                    "Failed to download file `{file_id}` created by code interpreter: {error}"
+                ))),
+            }
+        }
+    }
+
+    /// Download a file from a container, polling until it is available
+    ///
+    /// Returns the media type of the file, if provided.
+    async fn download_container_file(
+        transport: &dyn Transport,
+        container_id: &str,
+        file_id: &str,
+        dest: &Path,
+    ) -> Result<Option<String>> {
+        let path = format!("/containers/{container_id}/files/{file_id}/content");
+
+        let mut attempt = 0;
+        let response = loop {
+            let response = transport.send(HttpRequest::get(&path)).await?;
+            let status = response.status();
+            if status.is_success() {
+                break response;
+            }
+
+            attempt += 1;
+            if !(status == StatusCode::NOT_FOUND || status.is_server_error())
+                || attempt > CONTAINER_FILE_RETRIES
+            {
+                let body = response.text().await.unwrap_or_default();
+                bail!("OpenAI containers API returned {status}: {body}");
+            }
+
+            tracing::debug!("Container file `{file_id}` not yet available, retrying");
+            tokio::time::sleep(Duration::from_millis(500 * 2u64.pow(attempt as u32))).await;
+        };
+
+        let media_type = response.content_type().map(String::from);
+        let bytes = response.bytes().await?;
+
+        if let Some(parent) = dest.parent() {
+            create_dir_all(parent)?;
+        }
+        write(dest, &bytes)?;
+
+        Ok(media_type)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn upload_attachment(
+        transport: &dyn Transport,
+        attachment: &InstructionAttachment,
+    ) -> Result<UploadedAttachment> {
+        let bytes = attachment_bytes(attachment)?;
+        let filename = if attachment.file.name.trim().is_empty() {
+            format!("{}.bin", attachment.alias)
+        } else {
+            attachment.file.name.clone()
+        };
+        let media_type = attachment_media_type(attachment);
+
+        tracing::debug!(
+            "Uploading attachment `{}` ({} bytes, {})",
+            attachment.alias,
+            bytes.len(),
+            media_type
+        );
+
+        // Uploads are verified, and retried if verification fails, so that a
+        // silently truncated file does not become part of the provenance of the output
+        let mut attempt = 0;
+        let id = loop {
+            let response = transport
+                .send(HttpRequest::post_file(
+                    "/files",
+                    "assistants",
+                    filename.clone(),
+                    media_type.clone(),
+                    bytes.clone(),
+                ))
+                .await?
+                .error_for_status("file upload")
+                .await?
+                .json::<UploadFileResponse>()
+                .await?;
+
+            match Self::verify_upload(transport, &response.id, bytes.len()).await {
+                Ok(..) => break response.id,
+                Err(error) => {
+                    Self::delete_upload(transport, &response.id).await;
+                    if attempt >= UPLOAD_VERIFY_RETRIES {
+                        bail!(
+                            "Upload of attachment `{}` failed verification: {error}",
+                            attachment.alias
+                        );
+                    }
+                    attempt += 1;
+                    tracing::warn!(
+                        "Upload of attachment `{}` failed verification, retrying (attempt {attempt}): {error}",
+                        attachment.alias
+                    );
+                }
+            }
+        };
+
+        Ok(UploadedAttachment {
+            alias: attachment.alias.clone(),
+            source: AttachmentSource::FileId(id),
+            media_type,
+            preamble: None,
         })
-    } else {
-        Ok(content.as_bytes().to_vec())
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct UploadFileResponse {
-    id: String,
-}
+    /// Verify that an uploaded file has the number of bytes that were sent
+    ///
+    /// Fetches the metadata of the file from the API. OpenAI does not report
+    /// checksums of files so only the byte count can be verified.
+    async fn verify_upload(transport: &dyn Transport, id: &str, sent: usize) -> Result<()> {
+        let metadata = transport
+            .send(HttpRequest::get(format!("/files/{id}")))
+            .await?
+            .error_for_status("file metadata")
+            .await?
+            .json::<UploadFileResponse>()
+            .await?;
+
+        metadata.verify(sent)
+    }
+
+    /// Delete an uploaded file, logging any error
+    async fn delete_upload(transport: &dyn Transport, id: &str) {
+        let result = match transport
+            .send(HttpRequest::delete(format!("/files/{id}")))
+            .await
+        {
+            Ok(response) => response.error_for_status("file deletion").await.map(|_| ()),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            tracing::warn!("While deleting uploaded file `{id}`: {error}");
+        }
+    }
+
+    /// Prepare an image attachment to be sent inline as a data URL
+    ///
+    /// Returns `None` if the attachment is not an image or is larger than the
+    /// `inline_image_max_bytes` of the models config, in which case it should be uploaded.
+    fn inline_attachment(attachment: &InstructionAttachment) -> Option<UploadedAttachment> {
+        let media_type = attachment.file.media_type.as_deref()?;
+        if !media_type.starts_with("image/") {
+            return None;
+        }
+
+        let max_bytes = models_config().attachments.inline_image_max_bytes;
+        let bytes = attachment_bytes(attachment).ok()?;
+        if bytes.len() as u64 > max_bytes {
+            return None;
+        }
+
+        tracing::debug!(
+            "Inlining attachment `{}` ({} bytes, {media_type})",
+            attachment.alias,
+            bytes.len()
+        );
+
+        Some(UploadedAttachment {
+            alias: attachment.alias.clone(),
+            source: AttachmentSource::DataUrl(format!(
+                "data:{media_type};base64,{}",
+                BASE64.encode(bytes)
+            )),
+            media_type: media_type.to_string(),
+            preamble: None,
+        })
+    }
+
+    /// Create a request to the Responses API for a task, given its input items
+    fn responses_request(
+        &self,
+        task: &ModelTask,
+        input: Vec<ResponseInputItem>,
+        stream: bool,
+    ) -> Result<ResponsesRequest> {
+        Ok(ResponsesRequest {
+            model: self.model.clone(),
+            input,
+            temperature: task.temperature,
+            top_p: task.top_p,
+            stop: Self::stop_sequences(task)?,
+            seed: task.seed,
+            max_output_tokens: task.max_tokens,
+            text: self.json_mode(task).then_some(ResponseTextOptions {
+                format: ResponseTextFormat::JsonObject,
+            }),
+            tools: ResponseTool::for_task(task),
+            user: task.user.clone(),
+            prompt_cache_key: task
+                .prompt_caching
+                .as_ref()
+                .and_then(|caching| caching.key.clone()),
+            stream,
+        })
+    }
+
+    /// Convert the messages of a task into Responses API input items
+    ///
+    /// As for chat completions, tool results become separate `function_call_output`
+    /// items, and images in assistant messages are replayed as a following user message.
+    /// What happens to each part is recorded in the `manifest`.
+    fn messages_to_response_input(
+        &self,
+        task: &ModelTask,
+        warnings: &mut Vec<ModelWarning>,
+        manifest: &mut PromptManifest,
+    ) -> Vec<ResponseInputItem> {
+        let mut items = Vec::new();
+        for (message_index, message) in task.messages.iter().enumerate() {
+            let role = match message.role.unwrap_or_default() {
+                MessageRole::System => "system",
+                MessageRole::Developer if self.supports_developer_role() => "developer",
+                MessageRole::Developer => "system",
+                MessageRole::User => "user",
+                MessageRole::Model => "assistant",
+            }
+            .to_string();
+
+            let mut content = Vec::new();
+            let mut images = Vec::new();
+            for (part_index, part) in message.parts.iter().enumerate() {
+                let mut disposition = ManifestDisposition::Included;
+                let mut detail = None;
+                match part {
+                    MessagePart::Text(text) => {
+                        let text = text.to_value_string();
+                        content.push(if role == "assistant" {
+                            ResponseContent::OutputText { text }
+                        } else {
+                            ResponseContent::InputText { text }
+                        })
+                    }
+                    MessagePart::ImageObject(ImageObject { content_url, .. }) => {
+                        let image = ResponseContent::InputImage {
+                            file_id: None,
+                            image_url: Some(content_url.clone()),
+                            detail: task.image_detail,
+                        };
+                        if role == "assistant" {
+                            disposition = ManifestDisposition::Transformed;
+                            detail = Some(REPLAYED_IMAGE.to_string());
+                            images.push(image)
+                        } else {
+                            content.push(image)
+                        }
+                    }
+                    MessagePart::ToolResult(result) => {
+                        // Replay the call the result is for, if known, as required by the API
+                        if let Some(call) = task
+                            .tool_calls
+                            .iter()
+                            .find(|call| call.id == result.call_id)
+                        {
+                            items.push(ResponseInputItem::FunctionCall {
+                                call_id: call.id.clone(),
+                                name: call.name.clone(),
+                                arguments: call.arguments.clone(),
+                            });
+                        }
+                        items.push(ResponseInputItem::FunctionCallOutput {
+                            call_id: result.call_id.clone(),
+                            output: result.content.clone(),
+                        });
+                    }
+                    MessagePart::File(file) if role != "assistant" => match file_data_url(file) {
+                        Ok(file_data) => content.push(ResponseContent::InputFile {
+                            file_id: None,
+                            filename: Some(file.name.clone()),
+                            file_data: Some(file_data),
+                        }),
+                        Err(error) => {
+                            let warning =
+                                format!("File is ignored by model `{}`: {error}", self.id());
+                            disposition = ManifestDisposition::Excluded;
+                            detail = Some(warning.clone());
+                            warnings.push(ModelWarning::ignored_part(warning));
+                        }
+                    },
+                    other => {
+                        let warning = format!(
+                            "Message part `{other}` is currently unsupported by OpenAI Responses API"
+                        );
+                        disposition = ManifestDisposition::Excluded;
+                        detail = Some(warning.clone());
+                        warnings.push(ModelWarning::ignored_part(warning));
+                    }
+                }
+                manifest.part(message_index, part_index, part, disposition, detail);
+            }
+
+            if !content.is_empty() || role == "assistant" {
+                items.push(ResponseInputItem::Message(ResponseMessage {
+                    role,
+                    content,
+                }));
+            }
+
+            if !images.is_empty() {
+                let mut content = vec![ResponseContent::InputText {
+                    text: ASSISTANT_IMAGES_PREAMBLE.to_string(),
+                }];
+                content.append(&mut images);
+                items.push(ResponseInputItem::Message(ResponseMessage {
+                    role: "user".to_string(),
+                    content,
+                }));
+            }
+        }
+
+        items
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn image_generation(&self, task: &ModelTask) -> Result<ModelOutput> {
+        tracing::debug!("Sending image generation request");
+
+        let mut warnings = Vec::new();
+
+        // Create a prompt from the last message (assumed to be a user message)
+        let prompt = task
+            .messages
+            .last()
+            .map(|message| {
+                message
+                    .parts
+                    .iter()
+                    .flat_map(|part| match part {
+                        MessagePart::Text(text) => Some(text.to_value_string()),
+                        _ => {
+                            warnings.push(ModelWarning::ignored_part(format!(
+                                "Message part `{part}` is ignored by model `{}`",
+                                self.id()
+                            )));
+                            None
+                        }
+                    })
+                    .join("")
+            })
+            .unwrap_or_default();
+
+        // Create the request
+        let mut request = CreateImageRequestArgs::default();
+        // Unless the provider's (transient) URL is wanted, request the image data
+        // so that it does not need to be downloaded before the URL expires. The data
+        // is also needed to assemble a grid.
+        let persistence = task.image_persistence.clone().unwrap_or_default();
+        let grid = task.image_grid.unwrap_or_default();
+        let request = request.prompt(prompt).response_format(
+            if matches!(persistence, ImagePersistence::Url) && !grid {
+                ImageResponseFormat::Url
+            } else {
+                ImageResponseFormat::B64Json
+            },
+        );
+
+        // DALL-E 3 only supports generating one image per request so for that
+        // model, multiple requests are made
+        let count = task.image_count.unwrap_or(1).max(1);
+        let requests = if self.model.starts_with("dall-e-3") {
+            count
+        } else {
+            request.n(count);
+            1
+        };
+
+        if let Some((w, h)) = task.image_size {
+            match (w, h) {
+                (256, 256) => {
+                    request.size(ImageSize::S256x256);
+                }
+                (512, 512) => {
+                    request.size(ImageSize::S512x512);
+                }
+                (1024, 1024) => {
+                    request.size(ImageSize::S1024x1024);
+                }
+                (1024, 1792) => {
+                    request.size(ImageSize::S1024x1792);
+                }
+                (1792, 1024) => {
+                    request.size(ImageSize::S1792x1024);
+                }
+                _ => bail!("Unsupported image size `{w}x{h}`"),
+            };
+        }
+
+        if let Some(quality) = &task.image_quality {
+            match quality.to_lowercase().as_str() {
+                "std" | "standard" => {
+                    request.quality(ImageQuality::Standard);
+                }
+                "hd" | "high-definition" => {
+                    request.quality(ImageQuality::HD);
+                }
+                _ => bail!("Unsupported image quality `{quality}`"),
+            };
+        }
+
+        if let Some(style) = &task.image_style {
+            match style.to_lowercase().as_str() {
+                "nat" | "natural" => {
+                    request.style(ImageStyle::Natural);
+                }
+                "viv" | "vivid" => {
+                    request.style(ImageStyle::Vivid);
+                }
+                _ => bail!("Unsupported image style `{style}`"),
+            };
+        }
+
+        if let Some(user) = &task.user {
+            request.user(user);
+        }
+
+        let request = request.build()?;
+
+        let mut generation = ImageGeneration {
+            model: self.id(),
+            prompt: request.prompt.clone(),
+            size: task.image_size.map(|(w, h)| format!("{w}x{h}")),
+            quality: task.image_quality.clone(),
+            style: task.image_style.clone(),
+            ..Default::default()
+        };
+
+        // Warn about ignored task options
+        macro_rules! ignore_option {
+            ($name:ident) => {
+                if task.$name.is_some() {
+                    warnings.push(ModelWarning::ignored_option(format!(
+                        "Option `{}` is ignored by model `{}` for text-to-image generation",
+                        stringify!($name),
+                        self.name()
+                    )))
+                }
+            };
+            ($($name:ident),*) => {
+                $( ignore_option!($name); )*
+            }
+        }
+        ignore_option!(
+            mirostat,
+            mirostat_eta,
+            mirostat_tau,
+            num_ctx,
+            num_gqa,
+            num_gpu,
+            num_thread,
+            repeat_last_n,
+            repeat_penalty,
+            frequency_penalty,
+            logit_bias,
+            candidates,
+            temperature,
+            seed,
+            stop,
+            max_tokens,
+            tfs_z,
+            top_k,
+            top_p
+        );
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        // Send the requests
+        let transport = self.transport(task)?;
+        let responses = try_join_all((0..requests).map(|_| async {
+            transport
+                .send(HttpRequest::post_json("/images/generations", &request)?)
+                .await?
+                .error_for_status("images API")
+                .await?
+                .json::<ImagesResponse>()
+                .await
+        }))
+        .await?;
+        let audit = self.audit(task, &request, Some(&responses))?;
+
+        // Get the output
+        let created = responses.first().map(|response| response.created);
+        let generated = responses
+            .into_iter()
+            .flat_map(|response| response.data)
+            .collect_vec();
+        if generated.is_empty() {
+            bail!("Response data is unexpectedly empty")
+        }
+
+        let mut urls = Vec::new();
+        let mut bytes = Vec::new();
+        for image in &generated {
+            let (url, revised_prompt) = match image.as_ref() {
+                Image::Url {
+                    url,
+                    revised_prompt,
+                } => (url.to_string(), revised_prompt),
+                Image::B64Json {
+                    b64_json,
+                    revised_prompt,
+                } => {
+                    if grid {
+                        bytes.push(BASE64.decode(b64_json.as_bytes())?);
+                    }
+                    let url = persist_image(
+                        format!("data:image/png;base64,{b64_json}"),
+                        &generation,
+                        &persistence,
+                    )?;
+                    (url, revised_prompt)
+                }
+            };
+            urls.push(url);
+            if generation.revised_prompt.is_none() {
+                generation.revised_prompt = revised_prompt.clone();
+            }
+        }
+        generation.created =
+            created.and_then(|created| DateTime::from_timestamp(created as i64, 0));
+
+        let mut output = ModelOutput::from_url(self, "image/png", urls[0].clone()).await?;
+        if urls.len() > 1 {
+            output.candidates = urls;
+        }
+        if grid && bytes.len() > 1 {
+            let sheet = images::contact_sheet(&bytes, 512)?;
+            let url = persist_image(
+                format!("data:image/png;base64,{}", BASE64.encode(sheet)),
+                &generation,
+                &persistence,
+            )?;
+            output.parts.push(ModelOutputPart::ImageGrid { url });
+        }
+        output.image_generation = Some(generation);
+        output.warnings = warnings;
+        output.audit = audit;
+        Ok(output)
+    }
+}
+
+/// Convert a chat completion finish reason
+fn finish_reason(reason: ChatFinishReason) -> FinishReason {
+    match reason {
+        ChatFinishReason::Stop => FinishReason::Stop,
+        ChatFinishReason::Length => FinishReason::Length,
+        ChatFinishReason::ToolCalls | ChatFinishReason::FunctionCall => FinishReason::ToolCalls,
+        ChatFinishReason::ContentFilter => FinishReason::ContentFilter,
+    }
+}
+
+/// Convert the usage reported for a chat completion
+fn chat_usage(usage: &CompletionUsage) -> TokenUsage {
+    TokenUsage {
+        prompt_tokens: usage.prompt_tokens as usize,
+        output_tokens: usage.completion_tokens as usize,
+        cached_tokens: usage
+            .prompt_tokens_details
+            .as_ref()
+            .and_then(|details| details.cached_tokens)
+            .unwrap_or_default() as usize,
+    }
+}
+
+/// Add the contents of attachments to the input of a Responses request
+///
+/// With a `stable_prefix`, the contents are sent in a user message placed
+/// immediately after the leading system and developer messages so that, with
+/// those messages, they form a prefix which is the same for each turn of a
+/// conversation and can be cached by the provider. Otherwise, they are appended
+/// to the last user message.
+fn add_attachment_contents(
+    messages: &mut Vec<ResponseInputItem>,
+    contents: Vec<ResponseContent>,
+    stable_prefix: bool,
+) {
+    if contents.is_empty() {
+        return;
+    }
+
+    if stable_prefix {
+        let index = messages
+            .iter()
+            .position(|item| match item {
+                ResponseInputItem::Message(message) => {
+                    message.role != "system" && message.role != "developer"
+                }
+                _ => true,
+            })
+            .unwrap_or(messages.len());
+        messages.insert(
+            index,
+            ResponseInputItem::Message(ResponseMessage {
+                role: "user".to_string(),
+                content: contents,
+            }),
+        );
+        return;
+    }
+
+    let last_user = messages.iter_mut().rev().find_map(|item| match item {
+        ResponseInputItem::Message(message) if message.role == "user" => Some(message),
+        _ => None,
+    });
+    match last_user {
+        Some(message) => message.content.extend(contents),
+        None => messages.push(ResponseInputItem::Message(ResponseMessage {
+            role: "user".to_string(),
+            content: contents,
+        })),
+    }
+}
+
+/// Get the manifest disposition of an attachment which was sent to the model
+///
+/// Attachments with pages, a byte range, or a sheet selected have only part of
+/// their file sent so are recorded as truncated.
+fn sent_disposition(
+    attachment: &InstructionAttachment,
+    how: &str,
+) -> (ManifestDisposition, Option<String>) {
+    let options = &attachment.options;
+    let selectors = [
+        options.pages.as_ref().map(|pages| format!("pages {pages}")),
+        options
+            .sheet
+            .as_ref()
+            .map(|sheet| format!("sheet `{sheet}`")),
+        options
+            .byte_range
+            .as_ref()
+            .map(|range| format!("bytes {range}")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect_vec();
+
+    if selectors.is_empty() {
+        (ManifestDisposition::Included, Some(how.to_string()))
+    } else {
+        (
+            ManifestDisposition::Truncated,
+            Some(format!("{how}, only {}", selectors.join(", "))),
+        )
+    }
+}
+
+/// Record that an attachment could not be provided to the model
+///
+/// Errors immediately if the policy is `fail-fast` and otherwise adds a warning
+/// and records the failure (so that it can be reported if the policy is `require-all`).
+fn attachment_failed(
+    policy: AttachmentFailurePolicy,
+    message: String,
+    warnings: &mut Vec<ModelWarning>,
+    failures: &mut Vec<String>,
+) -> Result<()> {
+    if policy == AttachmentFailurePolicy::FailFast {
+        bail!(message);
+    }
+
+    warnings.push(ModelWarning::skipped_attachment(message.clone()));
+    failures.push(message);
+
+    Ok(())
+}
+
+/// Persist a generated image, returning its URL
+///
+/// Embeds a provenance manifest in the image and writes it to a file if the
+/// persistence option is a directory, otherwise returns it as a data URL.
+fn persist_image(
+    data_url: String,
+    generation: &ImageGeneration,
+    persistence: &ImagePersistence,
+) -> Result<String> {
+    let data_url = embed_data_url_manifest(&data_url, &generation.model, &generation.prompt)?;
+    match persistence {
+        ImagePersistence::Directory(dir) => {
+            let name = images::data_uri_to_file(&data_url, dir)?;
+            Ok(dir.join(name).to_string_lossy().to_string())
+        }
+        _ => Ok(data_url),
+    }
+}
+
+/// Get the media type of an attachment, defaulting to `application/octet-stream`
+fn attachment_media_type(attachment: &InstructionAttachment) -> String {
+    attachment
+        .file
+        .media_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+/// Get the name under which uploads of an attachment are recorded for sending diffs
+///
+/// Uses the name of the file so that a file re-sent in a follow-up turn is
+/// recognized even if it is given a different alias.
+/// Get the path to download a file created by the code interpreter to
+///
+/// The container id, and the file id if there is no filename, are supplied by the
+/// provider so must be a single normal path component. Only the final component
+/// of the filename is used. Together these ensure that files can not be written
+/// outside of the download directory. Files with the same name in the same
+/// container are given a numeric suffix so that they do not overwrite each other.
+fn download_path(
+    dir: &Path,
+    container_id: &str,
+    filename: Option<&str>,
+    file_id: &str,
+    used: &mut HashSet<PathBuf>,
+) -> Result<PathBuf> {
+    let is_component = |name: &str| {
+        let mut components = Path::new(name).components();
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(..)), None)
+        )
+    };
+    if !is_component(container_id) {
+        bail!("invalid container id `{container_id}`");
+    }
+
+    let name = match filename
+        .and_then(|name| Path::new(name).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+    {
+        Some(name) => name,
+        None if is_component(file_id) => file_id.to_string(),
+        None => bail!("invalid file id `{file_id}`"),
+    };
+
+    let container_dir = dir.join(container_id);
+    let mut dest = container_dir.join(&name);
+    let mut index = 1;
+    while used.contains(&dest) {
+        let path = Path::new(&name);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        dest = container_dir.join(match path.extension() {
+            Some(extension) => format!("{stem}-{index}.{}", extension.to_string_lossy()),
+            None => format!("{stem}-{index}"),
+        });
+        index += 1;
+    }
+    used.insert(dest.clone());
+
+    Ok(dest)
+}
+
+fn delta_name(attachment: &InstructionAttachment) -> String {
+    if attachment.file.name.trim().is_empty() {
+        attachment.alias.clone()
+    } else {
+        attachment.file.name.clone()
+    }
+}
+
+fn attachment_bytes(attachment: &InstructionAttachment) -> Result<Vec<u8>> {
+    let Some(content) = attachment.file.content.as_ref() else {
+        bail!(
+            "Attachment `{}` does not have any inline content to upload.",
+            attachment.alias
+        );
+    };
+
+    if attachment
+        .file
+        .options
+        .transfer_encoding
+        .as_deref()
+        .map(|encoding| encoding.eq_ignore_ascii_case("base64"))
+        .unwrap_or(false)
+    {
+        BASE64.decode(content.as_bytes()).map_err(|error| {
+            eyre!(
+                "Attachment `{}` content is invalid base64: {error}",
+                attachment.alias
+            )
+        })
+    } else {
+        Ok(content.as_bytes().to_vec())
+    }
+}
+
+/// A file object returned when uploading, or getting the metadata of, a file
+#[derive(Debug, Deserialize)]
+struct UploadFileResponse {
+    id: String,
+
+    /// The size of the file in bytes
+    bytes: Option<u64>,
+}
+
+impl UploadFileResponse {
+    /// Verify that the size of the file is the number of bytes sent
+    ///
+    /// Passes if the size is not reported (e.g. by some OpenAI compatible APIs).
+    fn verify(&self, sent: usize) -> Result<()> {
+        match self.bytes {
+            Some(bytes) if bytes != sent as u64 => {
+                bail!("file `{}` has {bytes} bytes but {sent} were sent", self.id)
+            }
+            Some(..) => Ok(()),
+            None => {
+                tracing::debug!("Size of file `{}` not reported so not verified", self.id);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UploadedAttachment {
+    alias: String,
+    source: AttachmentSource,
+    media_type: String,
+
+    /// The text introducing the attachment to the model
+    ///
+    /// Defaults to the alias of the attachment if not set.
+    preamble: Option<String>,
+}
+
+/// How the content of an attachment is provided to the API
+#[derive(Debug)]
+enum AttachmentSource {
+    /// The id of a file uploaded using the Files API
+    FileId(String),
+
+    /// A base64 encoded data URL (only used for small images)
+    DataUrl(String),
+
+    /// A textual preview (used for attachments with unsupported media types)
+    Preview(String),
+
+    /// The id of a previously uploaded version of the file, and a diff to the current content
+    Diff { file_id: String, diff: String },
+}
+
+impl UploadedAttachment {
+    fn to_contents(&self, detail: Option<ImageDetailLevel>) -> Vec<ResponseContent> {
+        if let AttachmentSource::Preview(preview) = &self.source {
+            return vec![ResponseContent::InputText {
+                text: preview.clone(),
+            }];
+        }
+
+        let mut contents = vec![ResponseContent::InputText {
+            text: self
+                .preamble
+                .clone()
+                .unwrap_or_else(|| format!("Attachment `{}`", self.alias)),
+        }];
+
+        match &self.source {
+            AttachmentSource::FileId(file_id) if self.media_type.starts_with("image/") => {
+                contents.push(ResponseContent::InputImage {
+                    file_id: Some(file_id.clone()),
+                    image_url: None,
+                    detail,
+                });
+            }
+            AttachmentSource::FileId(file_id) => {
+                contents.push(ResponseContent::InputFile {
+                    file_id: Some(file_id.clone()),
+                    filename: None,
+                    file_data: None,
+                });
+            }
+            AttachmentSource::DataUrl(url) => {
+                contents.push(ResponseContent::InputImage {
+                    file_id: None,
+                    image_url: Some(url.clone()),
+                    detail,
+                });
+            }
+            AttachmentSource::Diff { file_id, diff } => {
+                contents.push(ResponseContent::InputFile {
+                    file_id: Some(file_id.clone()),
+                    filename: None,
+                    file_data: None,
+                });
+                contents.push(ResponseContent::InputText {
+                    text: format!(
+                        "The file above has since changed. Apply the following diff to get its current content:\n\n```diff\n{diff}```"
+                    ),
+                });
+            }
+            AttachmentSource::Preview(..) => {}
+        }
+
+        contents
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ResponsesRequest {
+    model: String,
+    input: Vec<ResponseInputItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<ResponseTextOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ResponseTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt_cache_key: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseTool {
+    Function {
+        name: String,
+        description: String,
+        parameters: serde_json::Value,
+    },
+    WebSearch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        search_context_size: Option<WebSearchContextSize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filters: Option<WebSearchFilters>,
+    },
+    CodeInterpreter {
+        container: CodeInterpreterContainer,
+    },
+}
+
+impl ResponseTool {
+    /// Create the tools of a Responses request for a task
+    fn for_task(task: &ModelTask) -> Vec<Self> {
+        let mut tools = task
+            .tools
+            .iter()
+            .map(|tool| ResponseTool::Function {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters: tool.parameters.clone(),
+            })
+            .collect_vec();
+
+        if let Some(web_search) = &task.web_search {
+            tools.push(ResponseTool::WebSearch {
+                search_context_size: web_search.context_size,
+                filters: (!web_search.allowed_domains.is_empty()).then(|| WebSearchFilters {
+                    allowed_domains: web_search.allowed_domains.clone(),
+                }),
+            });
+        }
+
+        if let Some(code_interpreter) = &task.code_interpreter {
+            tools.push(ResponseTool::CodeInterpreter {
+                container: CodeInterpreterContainer::Auto {
+                    file_ids: code_interpreter.file_ids.clone(),
+                },
+            });
+        }
+
+        tools
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WebSearchFilters {
+    allowed_domains: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CodeInterpreterContainer {
+    Auto {
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        file_ids: Vec<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ResponseTextOptions {
+    format: ResponseTextFormat,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseTextFormat {
+    JsonObject,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseInputItem {
+    Message(ResponseMessage),
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ResponseMessage {
+    role: String,
+    content: Vec<ResponseContent>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseContent {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    InputFile {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file_data: Option<String>,
+    },
+    InputImage {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<ImageDetailLevel>,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponsesResponse {
+    output: Vec<ResponseOutput>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    incomplete_details: Option<ResponseIncompleteDetails>,
+    #[serde(default)]
+    usage: Option<ResponseUsage>,
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ResponseUsage {
+    input_tokens: usize,
+    output_tokens: usize,
+    input_tokens_details: Option<ResponseInputTokensDetails>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ResponseInputTokensDetails {
+    cached_tokens: usize,
+}
+
+impl ResponseUsage {
+    /// Convert to the token usage of an output
+    fn to_token_usage(&self) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.input_tokens,
+            output_tokens: self.output_tokens,
+            cached_tokens: self
+                .input_tokens_details
+                .as_ref()
+                .map(|details| details.cached_tokens)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseIncompleteDetails {
+    reason: Option<String>,
+}
+
+impl ResponsesResponse {
+    /// Get the reason the response finished, if known
+    fn finish_reason(&self) -> Option<FinishReason> {
+        if let Some(reason) = self
+            .incomplete_details
+            .as_ref()
+            .and_then(|details| details.reason.as_deref())
+        {
+            return match reason {
+                "max_output_tokens" => Some(FinishReason::Length),
+                "content_filter" => Some(FinishReason::ContentFilter),
+                _ => None,
+            };
+        }
+
+        match self.status.as_deref() {
+            Some("completed") => Some(
+                if self
+                    .output
+                    .iter()
+                    .any(|item| matches!(item, ResponseOutput::FunctionCall { .. }))
+                {
+                    FinishReason::ToolCalls
+                } else {
+                    FinishReason::Stop
+                },
+            ),
+            Some("incomplete") => Some(FinishReason::Length),
+            _ => None,
+        }
+    }
+
+    /// Get the refusal message of the response, if the model refused
+    fn refusal(&self) -> Option<String> {
+        let refusals = self
+            .output
+            .iter()
+            .filter_map(|item| match item {
+                ResponseOutput::Message { content, .. } => Some(content),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|content| match content {
+                ResponseOutputContent::Refusal { refusal } => Some(refusal.as_str()),
+                _ => None,
+            })
+            .collect_vec();
+
+        (!refusals.is_empty()).then(|| refusals.join("\n"))
+    }
+
+    /// Get the output text of the response and any structured parts (e.g. from tool calls)
+    fn into_text_and_parts(self) -> (String, Vec<ModelOutputPart>) {
+        let mut text_segments = Vec::new();
+        let mut parts = Vec::new();
+        for item in self.output {
+            match item {
+                ResponseOutput::Message { content, .. } | ResponseOutput::Reasoning { content } => {
+                    for content in content {
+                        match content {
+                            ResponseOutputContent::OutputText { text, annotations } => {
+                                text_segments.push(text);
+                                parts.extend(
+                                    annotations
+                                        .into_iter()
+                                        .filter_map(ResponseAnnotation::into_output_part),
+                                );
+                            }
+                            ResponseOutputContent::SummaryText { text } => text_segments.push(text),
+                            _ => {}
+                        }
+                    }
+                }
+                ResponseOutput::WebSearchCall { action } => {
+                    parts.push(ModelOutputPart::WebSearch {
+                        query: action.as_ref().and_then(|action| action.query.clone()),
+                        sources: action
+                            .map(|action| {
+                                action
+                                    .sources
+                                    .into_iter()
+                                    .filter_map(|source| source.url)
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    })
+                }
+                ResponseOutput::CodeInterpreterCall {
+                    id,
+                    code,
+                    container_id,
+                    outputs,
+                } => {
+                    let mut logs = Vec::new();
+                    let mut images = Vec::new();
+                    for output in outputs {
+                        match output {
+                            CodeInterpreterOutput::Logs { logs: text } => logs.push(text),
+                            CodeInterpreterOutput::Image { url } => images.push(url),
+                            CodeInterpreterOutput::Other => {}
+                        }
+                    }
+                    parts.push(ModelOutputPart::CodeExecution {
+                        id,
+                        container_id,
+                        code: code.unwrap_or_default(),
+                        logs: (!logs.is_empty()).then(|| logs.join("\n")),
+                        images,
+                    });
+                }
+                ResponseOutput::FunctionCall {
+                    call_id,
+                    name,
+                    arguments,
+                } => parts.push(ModelOutputPart::ToolCall(ToolCall {
+                    id: call_id,
+                    name,
+                    arguments,
+                })),
+                ResponseOutput::Other => {}
+            }
+        }
+
+        (
+            text_segments.join("\n").trim().to_string(),
+            link_container_files(parts),
+        )
+    }
+}
+
+/// Deduplicate the files cited in a response and link them to the code execution which created them
+///
+/// A file created in a container is attributed to the last code execution in the same
+/// container before the file was cited.
+fn link_container_files(parts: Vec<ModelOutputPart>) -> Vec<ModelOutputPart> {
+    let mut linked = Vec::new();
+    let mut last_executions: HashMap<String, String> = HashMap::new();
+    for mut part in parts {
+        match &mut part {
+            ModelOutputPart::CodeExecution {
+                id: Some(id),
+                container_id: Some(container_id),
+                ..
+            } => {
+                last_executions.insert(container_id.clone(), id.clone());
+            }
+            ModelOutputPart::File {
+                file_id,
+                container_id,
+                generated_by,
+                ..
+            } => {
+                let duplicate = linked.iter().any(|part| {
+                    matches!(part, ModelOutputPart::File { file_id: existing, .. } if existing == file_id)
+                });
+                if duplicate {
+                    continue;
+                }
+                if let Some(container_id) = container_id {
+                    *generated_by = last_executions.get(container_id).cloned();
+                }
+            }
+            _ => {}
+        }
+        linked.push(part);
+    }
+    linked
+}
+
+/// An item in the output of a response
+///
+/// See https://platform.openai.com/docs/api-reference/responses/object#responses/object-output.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseOutput {
+    Message {
+        #[allow(unused)]
+        role: Option<String>,
+        #[serde(default)]
+        content: Vec<ResponseOutputContent>,
+    },
+    Reasoning {
+        #[serde(default)]
+        content: Vec<ResponseOutputContent>,
+    },
+    WebSearchCall {
+        action: Option<WebSearchAction>,
+    },
+    CodeInterpreterCall {
+        id: Option<String>,
+        code: Option<String>,
+        container_id: Option<String>,
+        #[serde(default)]
+        outputs: Vec<CodeInterpreterOutput>,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        #[serde(default)]
+        arguments: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebSearchAction {
+    query: Option<String>,
+    #[serde(default)]
+    sources: Vec<WebSearchSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebSearchSource {
+    url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CodeInterpreterOutput {
+    Logs {
+        logs: String,
+    },
+    Image {
+        url: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// An annotation on output text
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseAnnotation {
+    UrlCitation {
+        url: String,
+        title: Option<String>,
+    },
+    FileCitation {
+        file_id: String,
+        filename: Option<String>,
+    },
+    ContainerFileCitation {
+        container_id: String,
+        file_id: String,
+        filename: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl ResponseAnnotation {
+    fn into_output_part(self) -> Option<ModelOutputPart> {
+        match self {
+            Self::UrlCitation { url, title } => Some(ModelOutputPart::WebCitation { url, title }),
+            Self::FileCitation { file_id, filename } => Some(ModelOutputPart::File {
+                file_id,
+                filename,
+                container_id: None,
+                generated_by: None,
+                path: None,
+                media_type: None,
+            }),
+            Self::ContainerFileCitation {
+                container_id,
+                file_id,
+                filename,
+            } => Some(ModelOutputPart::File {
+                file_id,
+                filename,
+                container_id: Some(container_id),
+                generated_by: None,
+                path: None,
+                media_type: None,
+            }),
+            Self::Other => None,
+        }
+    }
+}
+
+/// An event streamed from the Responses API
+///
+/// See https://platform.openai.com/docs/api-reference/responses-streaming.
+/// Only the events needed to accumulate output text are handled.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ResponseStreamEvent {
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponsesResponse },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: ResponsesResponse },
+    #[serde(rename = "response.failed")]
+    Failed { response: ResponseStreamFailure },
+    #[serde(rename = "error")]
+    Error { message: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseStreamFailure {
+    error: Option<ResponseStreamError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseStreamError {
+    message: String,
+}
+
+/// Read a server-sent event stream from the Responses API
+///
+/// Calls `on_delta` with each chunk of output text and returns the completed response.
+/// If the stream ends without a `response.completed` event, the accumulated text
+/// is returned as the single output of the response.
+async fn read_responses_stream(
+    response: HttpResponse,
+    on_delta: &DeltaCallback,
+) -> Result<ResponsesResponse> {
+    read_response_events(response.bytes_stream(), on_delta).await
+}
+
+/// Read the server-sent events of a streamed response
+///
+/// Bytes are buffered, and only complete events are decoded, so that multi-byte
+/// characters split across chunks are not corrupted. Events may be separated
+/// by either `\n\n` or `\r\n\r\n`.
+async fn read_response_events(
+    mut stream: BoxStream<'static, Result<Vec<u8>>>,
+    on_delta: &DeltaCallback,
+) -> Result<ResponsesResponse> {
+    let mut buffer = Vec::new();
+    let mut text = String::new();
+
+    let mut ended = false;
+    while !ended {
+        match stream.next().await {
+            Some(bytes) => buffer.extend(bytes?),
+            None => ended = true,
+        }
+
+        loop {
+            let event = match sse_event_end(&buffer) {
+                Some((end, separator)) => {
+                    let event = buffer[..end].to_vec();
+                    buffer.drain(..end + separator);
+                    event
+                }
+                // The final event may not be followed by a blank line
+                None if ended && !buffer.is_empty() => std::mem::take(&mut buffer),
+                None => break,
+            };
+
+            let data = std::str::from_utf8(&event)?
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .join("\n");
+            if data.is_empty() || data == "[DONE]" {
+                continue;
+            }
+
+            match serde_json::from_str::<ResponseStreamEvent>(&data)? {
+                ResponseStreamEvent::OutputTextDelta { delta } => {
+                    on_delta(&delta);
+                    text.push_str(&delta);
+                }
+                ResponseStreamEvent::Completed { response }
+                | ResponseStreamEvent::Incomplete { response } => return Ok(response),
+                ResponseStreamEvent::Failed { response } => bail!(
+                    "OpenAI response failed: {}",
+                    response
+                        .error
+                        .map(|error| error.message)
+                        .unwrap_or_default()
+                ),
+                ResponseStreamEvent::Error { message } => {
+                    bail!("OpenAI response stream error: {message}")
+                }
+                ResponseStreamEvent::Other => {}
+            }
+        }
+    }
+
+    Ok(ResponsesResponse {
+        output: vec![ResponseOutput::Message {
+            role: Some("assistant".to_string()),
+            content: vec![ResponseOutputContent::OutputText {
+                text,
+                annotations: Vec::new(),
+            }],
+        }],
+        ..Default::default()
+    })
+}
+
+/// Find the end of the first server-sent event in a buffer
+///
+/// Returns the index of the end of the event and the length of the blank
+/// line separating it from the next.
+fn sse_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    [b"\r\n\r\n".as_slice(), b"\n\n", b"\r\r"]
+        .into_iter()
+        .filter_map(|separator| {
+            buffer
+                .windows(separator.len())
+                .position(|window| window == separator)
+                .map(|end| (end, separator.len()))
+        })
+        .min_by_key(|(end, ..)| *end)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseOutputContent {
+    OutputText {
+        text: String,
+        #[serde(default)]
+        annotations: Vec<ResponseAnnotation>,
+    },
+    SummaryText {
+        text: String,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Get a list of all available OpenAI models
+///
+/// If the OpenAI API key is not available returns an empty list.
+/// If the OpenAI API can not be reached, falls back to the list of models
+/// last fetched successfully, with each model flagged as stale.
+/// Lists the models available for the account in lexical order.
+///
+/// This mapping of model name to context_length and input/output types will need to be
+/// updated periodically based on https://platform.openai.com/docs/models/.
+///
+/// Cached for two minutes to avoid loading from disk cache too frequently
+/// but allowing user to set API key while process is running.
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    if let Some(models) = LIST_CACHE.get(&()) {
+        return Ok(models);
+    }
+
+    // Check for API key before calling IO cached function so that we never cache an empty list
+    // and allow for users to set key, and then get list, while process is running
+    if secrets::env_or_get(API_KEY).is_err() {
+        tracing::trace!("The environment variable or secret `{API_KEY}` is not available");
+        return Ok(vec![]);
+    };
+
+    let (names, stale) = match list_openai_models().await {
+        Ok(names) => {
+            if let Err(error) = save_model_names(&names) {
+                tracing::debug!("Unable to save list of OpenAI models: {error}");
+            }
+            (names, false)
+        }
+        Err(error) => match load_model_names() {
+            Some(names) => {
+                tracing::warn!(
+                    "Unable to fetch list of OpenAI models, using previously saved list: {error}"
+                );
+                (names, true)
+            }
+            None => return Err(error),
+        },
+    };
+
+    let models: Vec<Arc<dyn Model>> = names
+        .into_iter()
+        .sorted()
+        .filter_map(|name| {
+            // Exclude model names that are not versioned
+            if name == "gpt-3.5-turbo"
+                || name == "gpt-3.5-turbo-instruct"
+                || name == "gpt-4"
+                || name == "gpt-4-turbo"
+                || name == "gpt-4o"
+                || name == "gpt-4o-mini"
+                || name == "o1"
+                || name == "o1-mini"
+                || name == "tts-1"
+                || name == "tts-1-hd"
+            {
+                return None;
+            }
+
+            let context_length =
+                if name.starts_with("gpt-4-1106") || name.starts_with("gpt-4-vision") {
+                    128_000
+                } else if name.contains("-32k") {
+                    32_768
+                } else if name.contains("-16k") || name == "gpt-3.5-turbo-1106" {
+                    16_385
+                } else if name.starts_with("gpt-4") {
+                    8_192
+                } else if name.starts_with("dall-e-2") {
+                    // Note: This seems to be characters, not tokens?
+                    1_000
+                } else {
+                    4_096
+                };
+
+            use ModelIO::*;
+            let (inputs, outputs) = if name.contains("-audio") {
+                (vec![Text, Audio], vec![Text, Audio])
+            } else if name.contains("vision")
+                || name.starts_with("gpt-4o")
+                || name.starts_with("o1")
+                || name.starts_with("gpt-5")
+                || name.starts_with("gpt-4.1")
+            {
+                (vec![Text, Image], vec![Text])
+            } else if name.starts_with("gpt-4") || name.starts_with("gpt-3.5") {
+                (vec![Text], vec![Text])
+            } else if name.starts_with("dall-e") {
+                (vec![Text], vec![Image])
+            } else if name.starts_with("tts") {
+                (vec![Text], vec![Audio])
+            } else if name.starts_with("whisper") {
+                (vec![Audio], vec![Text])
+            } else {
+                // Other models are assumed to be text-text only
+                (vec![Text], vec![Text])
+            };
+
+            let formats = if supports_json_mode(&name) {
+                vec![Format::Json]
+            } else {
+                vec![]
+            };
+
+            let mut model = OpenAIModel::new(name, context_length, inputs, outputs, formats);
+            model.stale = stale;
+
+            Some(Arc::new(model) as Arc<dyn Model>)
+        })
+        .collect_vec();
+
+    LIST_CACHE.insert((), models.clone());
+
+    Ok(models)
+}
+
+/// The cache of the list of models
+static LIST_CACHE: Lazy<TtlCache<(), Vec<Arc<dyn Model>>>> =
+    Lazy::new(|| TtlCache::new(Duration::from_secs(120), 1));
+
+/// The cache of the list of model names fetched from the API
+static NAMES_CACHE: Lazy<TtlCache<(), Vec<String>>> =
+    Lazy::new(|| TtlCache::new(Duration::from_secs(21_600), 1));
+
+/// Invalidate the cached list of models
+///
+/// The next call to [`list`] will fetch the list of models from the API.
+pub fn invalidate_list_cache() {
+    LIST_CACHE.clear();
+    NAMES_CACHE.clear();
+}
+
+/// Get metrics on the usage of the caches of the list of models
+///
+/// Returns metrics for the list of models and the list of names fetched from the API.
+pub fn list_cache_metrics() -> (CacheMetrics, CacheMetrics) {
+    (LIST_CACHE.metrics(), NAMES_CACHE.metrics())
+}
+
+/// Whether a model supports JSON mode
+///
+/// See https://platform.openai.com/docs/guides/structured-outputs#json-mode
+fn supports_json_mode(name: &str) -> bool {
+    name.starts_with("gpt-4o")
+        || name.starts_with("gpt-4.1")
+        || name.starts_with("gpt-5")
+        || name.starts_with("gpt-4-turbo")
+        || name.starts_with("gpt-4-1106")
+        || name.starts_with("gpt-4-0125")
+        || name.starts_with("gpt-3.5-turbo-1106")
+        || name.starts_with("gpt-3.5-turbo-0125")
+        || name.starts_with("o1")
+        || name.starts_with("o3")
+        || name.starts_with("o4")
+}
+
+/// Fetch the names of the list of models
+///
+/// In-memory cached for six hours to reduce requests to remote API.
+async fn list_openai_models() -> Result<Vec<String>> {
+    if let Some(names) = NAMES_CACHE.get(&()) {
+        return Ok(names);
+    }
+
+    let response = OpenAIModel::client()?.models().list().await?;
+    let names = response
+        .data
+        .into_iter()
+        .map(|model| model.id)
+        .collect_vec();
+
+    NAMES_CACHE.insert((), names.clone());
+
+    Ok(names)
+}
+
+/// Get the path of the file that the last fetched list of model names is saved to
+fn model_names_path() -> Result<PathBuf> {
+    Ok(get_app_dir(DirType::Cache, true)?.join("models-openai.json"))
+}
+
+/// Save the list of model names so it can be used when the API is unreachable
+fn save_model_names(names: &[String]) -> Result<()> {
+    let json = serde_json::to_string(names)?;
+    write(model_names_path()?, json)?;
+    Ok(())
+}
+
+/// Load the previously saved list of model names
+///
+/// Returns `None` if no list has been saved or if it could not be read.
+fn load_model_names() -> Option<Vec<String>> {
+    let json = read_to_string(model_names_path().ok()?).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use model::{
+        CodeInterpreterOptions, WebSearchOptions,
+        common::{
+            futures,
+            glob::glob,
+            serde_json::{self, Value, json},
+            tempfile, tokio,
+        },
+        schema::{File, ToolResult},
+        test_task_repeat_word,
+    };
+
+    #[test]
+    fn stop_sequences() -> Result<()> {
+        let task: ModelTask = serde_json::from_str(
+            r#"{"messages": [], "kind": "MessageGeneration", "format": "json", "stop": "END"}"#,
+        )?;
+        assert_eq!(
+            OpenAIModel::stop_sequences(&task)?,
+            Some(vec!["END".to_string()])
+        );
+
+        let task: ModelTask = serde_json::from_str(
+            r#"{"messages": [], "kind": "MessageGeneration", "format": "json", "stop": ["a", "b", "c", "d", "e"]}"#,
+        )?;
+        assert!(OpenAIModel::stop_sequences(&task).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_uploads() -> Result<()> {
+        let file: UploadFileResponse =
+            serde_json::from_str(r#"{"id": "file-abc", "object": "file", "bytes": 120}"#)?;
+        assert!(file.verify(120).is_ok());
+        assert!(file.verify(200).is_err());
+
+        let file: UploadFileResponse = serde_json::from_str(r#"{"id": "file-abc"}"#)?;
+        assert!(file.verify(200).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn response_input_replays_tool_results_and_images() -> Result<()> {
+        let model = OpenAIModel::new("gpt-5".into(), 0, vec![], vec![], vec![]);
+
+        let task = ModelTask {
+            messages: vec![
+                InstructionMessage {
+                    role: Some(MessageRole::Model),
+                    parts: vec![
+                        MessagePart::Text("Here is the plot".into()),
+                        MessagePart::ImageObject(ImageObject::new(
+                            "https://example.org/plot.png".into(),
+                        )),
+                    ],
+                    ..Default::default()
+                },
+                InstructionMessage {
+                    role: Some(MessageRole::User),
+                    parts: vec![MessagePart::ToolResult(ToolResult::new(
+                        "call_1".into(),
+                        "42".into(),
+                    ))],
+                    ..Default::default()
+                },
+            ],
+            tool_calls: vec![ToolCall {
+                id: "call_1".into(),
+                name: "count_transects".into(),
+                arguments: "{}".into(),
+            }],
+            ..Default::default()
+        };
+
+        let mut manifest = PromptManifest::new();
+        let input = serde_json::to_value(model.messages_to_response_input(
+            &task,
+            &mut Vec::new(),
+            &mut manifest,
+        ))?;
+        assert_eq!(
+            input,
+            serde_json::json!([
+                {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "output_text", "text": "Here is the plot"}]
+                },
+                {
+                    "type": "message",
+                    "role": "user",
+                    "content": [
+                        {"type": "input_text", "text": ASSISTANT_IMAGES_PREAMBLE},
+                        {"type": "input_image", "image_url": "https://example.org/plot.png"}
+                    ]
+                },
+                {"type": "function_call", "call_id": "call_1", "name": "count_transects", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "42"}
+            ])
+        );
+
+        let dispositions = manifest
+            .entries
+            .iter()
+            .map(|entry| (entry.kind.as_str(), entry.disposition))
+            .collect_vec();
+        assert_eq!(
+            dispositions,
+            vec![
+                ("Text", ManifestDisposition::Included),
+                ("ImageObject", ManifestDisposition::Transformed),
+                ("ToolResult", ManifestDisposition::Included)
+            ]
+        );
+
+        let mut chat_manifest = PromptManifest::new();
+        let chat = model.messages_to_chat_messages(&task, &mut Vec::new(), &mut chat_manifest);
+        assert_eq!(
+            serde_json::to_value(&chat)?,
+            serde_json::json!([
+                {"role": "assistant", "content": "Here is the plot"},
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": ASSISTANT_IMAGES_PREAMBLE},
+                        {"type": "image_url", "image_url": {"url": "https://example.org/plot.png", "detail": "auto"}}
+                    ]
+                },
+                {
+                    "role": "assistant",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "count_transects", "arguments": "{}"}
+                    }]
+                },
+                {"role": "tool", "content": "42", "tool_call_id": "call_1"}
+            ])
+        );
+        assert_eq!(chat_manifest, manifest);
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_parts() -> Result<()> {
+        let model = OpenAIModel::new("gpt-5".into(), 0, vec![], vec![], vec![]);
+
+        let mut csv = File::new("rates.csv".into(), "rates.csv".into());
+        csv.content = Some("transect,rate\n1,-0.5\n".into());
+        let mut pdf = File::new("report.pdf".into(), "report.pdf".into());
+        pdf.content = Some(BASE64.encode(b"%PDF-1.7\n%\xE2\xE3\xCF\xD3"));
+        pdf.options.transfer_encoding = Some("base64".into());
+
+        let task = ModelTask {
+            messages: vec![InstructionMessage {
+                role: Some(MessageRole::User),
+                parts: vec![MessagePart::File(csv), MessagePart::File(pdf)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut manifest = PromptManifest::new();
+        let input = serde_json::to_value(model.messages_to_response_input(
+            &task,
+            &mut Vec::new(),
+            &mut manifest,
+        ))?;
+        assert_eq!(
+            input[0]["content"][1],
+            serde_json::json!({
+                "type": "input_file",
+                "filename": "report.pdf",
+                "file_data": "data:application/pdf;base64,JVBERi0xLjcKJeLjz9M="
+            })
+        );
+
+        // Chat completions get the text of text files, and ignore binary ones
+        let mut warnings = Vec::new();
+        let mut manifest = PromptManifest::new();
+        let chat = model.messages_to_chat_messages(&task, &mut warnings, &mut manifest);
+        let ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+            content: ChatCompletionRequestUserMessageContent::Array(content),
+            ..
+        }) = &chat[0]
+        else {
+            bail!("Expected a user message")
+        };
+        assert_eq!(content.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            manifest
+                .entries
+                .iter()
+                .map(|entry| entry.disposition)
+                .collect_vec(),
+            vec![
+                ManifestDisposition::Transformed,
+                ManifestDisposition::Excluded
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn finish_reasons_and_refusals() -> Result<()> {
+        let response = |json| serde_json::from_value::<ResponsesResponse>(json);
+
+        let completed = response(serde_json::json!({"status": "completed", "output": []}))?;
+        assert_eq!(completed.finish_reason(), Some(FinishReason::Stop));
+
+        let truncated = response(serde_json::json!({
+            "status": "incomplete",
+            "incomplete_details": {"reason": "max_output_tokens"},
+            "output": []
+        }))?;
+        assert_eq!(truncated.finish_reason(), Some(FinishReason::Length));
+
+        let refused = response(serde_json::json!({
+            "status": "completed",
+            "output": [{
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "refusal", "refusal": "I can't help with that."}]
+            }]
+        }))?;
+        assert_eq!(
+            refused.refusal().as_deref(),
+            Some("I can't help with that.")
+        );
+        assert_eq!(completed.refusal(), None);
+
+        assert_eq!(
+            finish_reason(ChatFinishReason::ToolCalls),
+            FinishReason::ToolCalls
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn attachments_form_stable_prefix() -> Result<()> {
+        let message = |role: &str, text: &str| {
+            ResponseInputItem::Message(ResponseMessage {
+                role: role.into(),
+                content: vec![ResponseContent::InputText { text: text.into() }],
+            })
+        };
+        let attachment = || {
+            vec![ResponseContent::InputFile {
+                file_id: Some("file-1".into()),
+                filename: None,
+                file_data: None,
+            }]
+        };
+        let conversation = || {
+            vec![
+                message("system", "You are a coastal scientist."),
+                message("user", "Summarize the transects."),
+                message("assistant", "They are eroding."),
+                message("user", "How fast?"),
+            ]
+        };
+        let roles = |messages: &[ResponseInputItem]| -> Result<String> {
+            Ok(serde_json::to_value(messages)?
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|item| {
+                    let files = item["content"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter(|content| content["type"] == "input_file")
+                        .count();
+                    format!(
+                        "{}{}",
+                        item["role"].as_str().unwrap_or_default(),
+                        "+file".repeat(files)
+                    )
+                })
+                .join(","))
+        };
+
+        let mut stable = conversation();
+        add_attachment_contents(&mut stable, attachment(), true);
+        assert_eq!(roles(&stable)?, "system,user+file,user,assistant,user");
+
+        let mut appended = conversation();
+        add_attachment_contents(&mut appended, attachment(), false);
+        assert_eq!(roles(&appended)?, "system,user,assistant,user+file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn usage_with_cached_tokens() -> Result<()> {
+        let response: ResponsesResponse = serde_json::from_value(serde_json::json!({
+            "output": [],
+            "usage": {
+                "input_tokens": 2048,
+                "input_tokens_details": {"cached_tokens": 1920},
+                "output_tokens": 100
+            }
+        }))?;
+        assert_eq!(
+            response.usage.as_ref().map(ResponseUsage::to_token_usage),
+            Some(TokenUsage {
+                prompt_tokens: 2048,
+                output_tokens: 100,
+                cached_tokens: 1920
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sent_dispositions() {
+        let mut attachment = InstructionAttachment::new(
+            "report".into(),
+            File::new("report.pdf".into(), "report.pdf".into()),
+        );
+        assert_eq!(
+            sent_disposition(&attachment, "uploaded as a file"),
+            (
+                ManifestDisposition::Included,
+                Some("uploaded as a file".into())
+            )
+        );
+
+        attachment.options.pages = Some("3-5".into());
+        assert_eq!(
+            sent_disposition(&attachment, "uploaded as a file"),
+            (
+                ManifestDisposition::Truncated,
+                Some("uploaded as a file, only pages 3-5".into())
+            )
+        );
+    }
+
+    #[test]
+    fn tool_calls() -> Result<()> {
+        let model = OpenAIModel::new("gpt-4o".into(), 0, vec![], vec![], vec![]);
+
+        let task = ModelTask {
+            messages: vec![
+                InstructionMessage {
+                    role: Some(MessageRole::Model),
+                    parts: vec![MessagePart::from("")],
+                    ..Default::default()
+                },
+                InstructionMessage {
+                    role: Some(MessageRole::User),
+                    parts: vec![MessagePart::ToolResult(ToolResult::new(
+                        "call_1".into(),
+                        "3".into(),
+                    ))],
+                    ..Default::default()
+                },
+            ],
+            tool_calls: vec![ToolCall {
+                id: "call_1".into(),
+                name: "add".into(),
+                arguments: r#"{"a":1,"b":2}"#.into(),
+            }],
+            ..Default::default()
+        };
+        let messages = serde_json::to_value(model.messages_to_chat_messages(
+            &task,
+            &mut vec![],
+            &mut PromptManifest::new(),
+        ))?;
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[0]["tool_calls"][0]["function"]["name"], "add");
+        assert_eq!(messages[1]["role"], "tool");
+        assert_eq!(messages.as_array().map(Vec::len), Some(2));
+
+        let response: ResponsesResponse = serde_json::from_value(serde_json::json!({
+            "output": [{"type": "function_call", "id": "fc_1", "call_id": "call_2", "name": "add", "arguments": "{}"}]
+        }))?;
+        let (text, parts) = response.into_text_and_parts();
+        assert_eq!(text, "");
+        assert_eq!(
+            parts,
+            vec![ModelOutputPart::ToolCall(ToolCall {
+                id: "call_2".into(),
+                name: "add".into(),
+                arguments: "{}".into()
+            })]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn hosted_tools() -> Result<()> {
+        let task = ModelTask {
+            web_search: Some(WebSearchOptions {
+                context_size: Some(WebSearchContextSize::High),
+                allowed_domains: vec!["usgs.gov".into()],
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(ResponseTool::for_task(&task))?,
+            serde_json::json!([{
+                "type": "web_search",
+                "search_context_size": "high",
+                "filters": {"allowed_domains": ["usgs.gov"]}
+            }])
+        );
+
+        let task = ModelTask {
+            web_search: Some(WebSearchOptions::default()),
+            code_interpreter: Some(CodeInterpreterOptions {
+                file_ids: vec!["file_1".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(ResponseTool::for_task(&task))?,
+            serde_json::json!([
+                {"type": "web_search"},
+                {"type": "code_interpreter", "container": {"type": "auto", "file_ids": ["file_1"]}}
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn response_tool_outputs() -> Result<()> {
+        let response: ResponsesResponse = serde_json::from_value(serde_json::json!({
+            "output": [
+                {
+                    "type": "web_search_call",
+                    "id": "ws_1",
+                    "status": "completed",
+                    "action": {"type": "search", "query": "Narrabeen erosion", "sources": [{"type": "url", "url": "https://example.org/a"}]}
+                },
+                {
+                    "type": "code_interpreter_call",
+                    "id": "ci_1",
+                    "code": "print(1 + 1)",
+                    "container_id": "cntr_1",
+                    "outputs": [{"type": "logs", "logs": "2"}]
+                },
+                {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{
+                        "type": "output_text",
+                        "text": "Erosion increased.",
+                        "annotations": [
+                            {"type": "url_citation", "url": "https://example.org/a", "title": "A", "start_index": 0, "end_index": 18},
+                            {"type": "container_file_citation", "container_id": "cntr_1", "file_id": "cfile_1", "filename": "plot.png"},
+                            {"type": "container_file_citation", "container_id": "cntr_1", "file_id": "cfile_1", "filename": "plot.png"}
+                        ]
+                    }]
+                }
+            ]
+        }))?;
+
+        let (text, parts) = response.into_text_and_parts();
+        assert_eq!(text, "Erosion increased.");
+        assert_eq!(
+            parts,
+            vec![
+                ModelOutputPart::WebSearch {
+                    query: Some("Narrabeen erosion".into()),
+                    sources: vec!["https://example.org/a".into()]
+                },
+                ModelOutputPart::CodeExecution {
+                    id: Some("ci_1".into()),
+                    container_id: Some("cntr_1".into()),
+                    code: "print(1 + 1)".into(),
+                    logs: Some("2".into()),
+                    images: vec![]
+                },
+                ModelOutputPart::WebCitation {
+                    url: "https://example.org/a".into(),
+                    title: Some("A".into())
+                },
+                ModelOutputPart::File {
+                    file_id: "cfile_1".into(),
+                    filename: Some("plot.png".into()),
+                    container_id: Some("cntr_1".into()),
+                    generated_by: Some("ci_1".into()),
+                    path: None,
+                    media_type: None
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// A recorded request to, and response from, the Responses API
+    ///
+    /// Fixtures are in `tests/fixtures/responses`. The `request` is the body that
+    /// should be sent for the `task`, and the `expected` output is that which
+    /// should be derived from the `response`.
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ResponsesFixture {
+        description: String,
+        model: String,
+        task: ModelTask,
+        request: serde_json::Value,
+        response: serde_json::Value,
+        expected: ResponsesFixtureOutput,
+    }
 
-#[derive(Debug)]
-struct UploadedAttachment {
-    alias: String,
-    file_id: String,
-    media_type: String,
-}
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ResponsesFixtureOutput {
+        text: String,
+        finish_reason: Option<FinishReason>,
+        refusal: Option<String>,
+        #[serde(default)]
+        parts: Vec<ModelOutputPart>,
+        usage: Option<TokenUsage>,
+    }
 
-impl UploadedAttachment {
-    fn to_contents(&self) -> Vec<ResponseContent> {
-        let mut contents = vec![ResponseContent::InputText {
-            text: format!("Attachment `{}`", self.alias),
-        }];
+    #[test]
+    fn responses_fixtures() -> Result<()> {
+        let paths = glob("tests/fixtures/responses/*.json")?
+            .flatten()
+            .collect_vec();
+        assert!(!paths.is_empty(), "no fixtures found");
 
-        if self.media_type.starts_with("image/") {
-            contents.push(ResponseContent::InputImage {
-                file_id: Some(self.file_id.clone()),
-                image_url: None,
-            });
-        } else {
-            contents.push(ResponseContent::InputFile {
-                file_id: self.file_id.clone(),
-            });
+        for path in paths {
+            let name = path.display();
+            let fixture: ResponsesFixture = serde_json::from_str(&read_to_string(&path)?)?;
+            let model = OpenAIModel::new(fixture.model, 0, vec![], vec![], vec![]);
+
+            let input = model.messages_to_response_input(
+                &fixture.task,
+                &mut Vec::new(),
+                &mut PromptManifest::new(),
+            );
+            let request = model.responses_request(&fixture.task, input, false)?;
+            assert_eq!(
+                serde_json::to_value(&request)?,
+                fixture.request,
+                "request of {name}: {}",
+                fixture.description
+            );
+
+            let response: ResponsesResponse = serde_json::from_value(fixture.response)?;
+            let finish_reason = response.finish_reason();
+            let refusal = response.refusal();
+            let usage = response.usage.as_ref().map(ResponseUsage::to_token_usage);
+            let (text, parts) = response.into_text_and_parts();
+            assert_eq!(
+                ResponsesFixtureOutput {
+                    text,
+                    finish_reason,
+                    refusal,
+                    parts,
+                    usage
+                },
+                fixture.expected,
+                "output of {name}: {}",
+                fixture.description
+            );
         }
 
-        contents
+        Ok(())
     }
-}
 
-#[derive(Debug, Serialize)]
-struct ResponsesRequest {
-    model: String,
-    input: Vec<ResponseMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    top_p: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    stop: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    seed: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_output_tokens: Option<u16>,
-}
+    #[test]
+    fn inline_small_images() -> Result<()> {
+        let mut file = File::new("dot.png".into(), "dot.png".into());
+        file.media_type = Some("image/png".into());
+        file.content = Some("iVBORw0KGgo=".into());
+        file.options.transfer_encoding = Some("base64".into());
+        let attachment = InstructionAttachment::new("dot".into(), file);
 
-#[derive(Debug, Serialize)]
-struct ResponseMessage {
-    role: String,
-    content: Vec<ResponseContent>,
-}
+        let Some(inlined) = OpenAIModel::inline_attachment(&attachment) else {
+            bail!("small image should be inlined")
+        };
+        assert_eq!(
+            serde_json::to_value(inlined.to_contents(Some(ImageDetailLevel::Low)))?,
+            serde_json::json!([
+                {"type": "input_text", "text": "Attachment `dot`"},
+                {"type": "input_image", "image_url": "data:image/png;base64,iVBORw0KGgo=", "detail": "low"}
+            ])
+        );
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum ResponseContent {
-    InputText {
-        text: String,
-    },
-    OutputText {
-        text: String,
-    },
-    InputFile {
-        file_id: String,
-    },
-    InputImage {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        file_id: Option<String>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        image_url: Option<String>,
-    },
-}
+        Ok(())
+    }
 
-#[derive(Debug, Deserialize)]
-struct ResponsesResponse {
-    output: Vec<ResponseOutput>,
-}
+    #[test]
+    fn download_paths() -> Result<()> {
+        let dir = Path::new("downloads");
+        let mut used = HashSet::new();
 
-#[derive(Debug, Deserialize)]
-struct ResponseOutput {
-    #[allow(unused)]
-    role: Option<String>,
-    content: Vec<ResponseOutputContent>,
-}
+        assert_eq!(
+            download_path(dir, "cntr_1", Some("../../plot.png"), "file-1", &mut used)?,
+            dir.join("cntr_1").join("plot.png")
+        );
+        assert_eq!(
+            download_path(dir, "cntr_1", Some("plot.png"), "file-2", &mut used)?,
+            dir.join("cntr_1").join("plot-1.png")
+        );
+        assert_eq!(
+            download_path(dir, "cntr_1", None, "file-3", &mut used)?,
+            dir.join("cntr_1").join("file-3")
+        );
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum ResponseOutputContent {
-    OutputText {
-        text: String,
-    },
-    SummaryText {
-        text: String,
-    },
-    #[serde(other)]
-    Other,
-}
+        assert!(download_path(dir, "../cntr_1", Some("plot.png"), "file-4", &mut used).is_err());
+        assert!(download_path(dir, "/tmp", Some("plot.png"), "file-4", &mut used).is_err());
+        assert!(download_path(dir, "a/b", Some("plot.png"), "file-4", &mut used).is_err());
+        assert!(download_path(dir, "cntr_1", None, "..", &mut used).is_err());
 
-/// Get a list of all available OpenAI models
-///
-/// If the OpenAI API key is not available returns an empty list.
-/// Lists the models available for the account in lexical order.
-///
-/// This mapping of model name to context_length and input/output types will need to be
-/// updated periodically based on https://platform.openai.com/docs/models/.
-///
-/// Memoized for two minutes to avoid loading from disk cache too frequently
-/// but allowing user to set API key while process is running.
-#[cached(time = 120, result = true)]
-pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
-    // Check for API key before calling IO cached function so that we never cache an empty list
-    // and allow for users to set key, and then get list, while process is running
-    if secrets::env_or_get(API_KEY).is_err() {
-        tracing::trace!("The environment variable or secret `{API_KEY}` is not available");
-        return Ok(vec![]);
-    };
+        Ok(())
+    }
 
-    let models: Vec<Arc<dyn Model>> = list_openai_models(0)
-        .await?
-        .data
-        .into_iter()
-        .sorted_by(|a, b| a.id.cmp(&b.id))
-        .filter_map(|model| {
-            let name = model.id;
+    #[test]
+    fn diff_attachments() -> Result<()> {
+        let attachment = UploadedAttachment {
+            alias: "shoreline".into(),
+            source: AttachmentSource::Diff {
+                file_id: "file-1".into(),
+                diff: "--- a/shoreline.csv\n+++ b/shoreline.csv\n@@ -1 +1,2 @@\n 1,2\n+2,4\n"
+                    .into(),
+            },
+            media_type: "text/csv".into(),
+            preamble: None,
+        };
 
-            // Exclude model names that are not versioned
-            if name == "gpt-3.5-turbo"
-                || name == "gpt-3.5-turbo-instruct"
-                || name == "gpt-4"
-                || name == "gpt-4-turbo"
-                || name == "gpt-4o"
-                || name == "gpt-4o-mini"
-                || name == "o1"
-                || name == "o1-mini"
-                || name == "tts-1"
-                || name == "tts-1-hd"
-            {
-                return None;
+        let contents = serde_json::to_value(attachment.to_contents(None))?;
+        assert_eq!(
+            contents[1],
+            serde_json::json!({"type": "input_file", "file_id": "file-1"})
+        );
+        let Some(text) = contents[2]["text"].as_str() else {
+            bail!("expected diff text")
+        };
+        assert!(text.contains("```diff\n--- a/shoreline.csv"));
+        assert!(text.ends_with("+2,4\n```"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_split_events() -> Result<()> {
+        let body = [
+            r#"data: {"type":"response.output_text.delta","delta":"Érosion 🙂"}"#,
+            "\r\n\r\n",
+            r#"data: {"type":"response.output_text.delta","delta":" à Narrabeen"}"#,
+            "\n\n",
+            r#"data: {"type":"response.output_text.delta","delta":"."}"#,
+        ]
+        .concat()
+        .into_bytes();
+
+        // Split within the multi-byte emoji, and within the first separator
+        let Some(emoji) = body.windows(4).position(|window| window == "🙂".as_bytes()) else {
+            bail!("expected emoji")
+        };
+        let chunks: Vec<Result<Vec<u8>>> = vec![
+            Ok(body[..emoji + 2].to_vec()),
+            Ok(body[emoji + 2..emoji + 6].to_vec()),
+            Ok(body[emoji + 6..].to_vec()),
+        ];
+
+        let deltas = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_delta = {
+            let deltas = deltas.clone();
+            move |delta: &str| {
+                if let Ok(mut deltas) = deltas.lock() {
+                    deltas.push(delta.to_string());
+                }
             }
+        };
+        let response =
+            read_response_events(futures::stream::iter(chunks).boxed(), &on_delta).await?;
 
-            let context_length =
-                if name.starts_with("gpt-4-1106") || name.starts_with("gpt-4-vision") {
-                    128_000
-                } else if name.contains("-32k") {
-                    32_768
-                } else if name.contains("-16k") || name == "gpt-3.5-turbo-1106" {
-                    16_385
-                } else if name.starts_with("gpt-4") {
-                    8_192
-                } else if name.starts_with("dall-e-2") {
-                    // Note: This seems to be characters, not tokens?
-                    1_000
-                } else {
-                    4_096
-                };
+        assert_eq!(
+            deltas
+                .lock()
+                .map(|deltas| deltas.clone())
+                .unwrap_or_default(),
+            vec!["Érosion 🙂", " à Narrabeen", "."]
+        );
+        let Some(ResponseOutput::Message { content, .. }) = response.output.first() else {
+            bail!("expected message")
+        };
+        let Some(ResponseOutputContent::OutputText { text, .. }) = content.first() else {
+            bail!("expected text")
+        };
+        assert_eq!(text, "Érosion 🙂 à Narrabeen.");
 
-            use ModelIO::*;
-            let (inputs, outputs) = if name.contains("vision")
-                || name.starts_with("gpt-4o")
-                || name.starts_with("o1")
-                || name.starts_with("gpt-5")
-                || name.starts_with("gpt-4.1")
-            {
-                (vec![Text, Image], vec![Text])
-            } else if name.starts_with("gpt-4") || name.starts_with("gpt-3.5") {
-                (vec![Text], vec![Text])
-            } else if name.starts_with("dall-e") {
-                (vec![Text], vec![Image])
-            } else if name.starts_with("tts") {
-                (vec![Text], vec![Audio])
-            } else if name.starts_with("whisper") {
-                (vec![Audio], vec![Text])
-            } else {
-                // Other models are assumed to be text-text only
-                (vec![Text], vec![Text])
+        Ok(())
+    }
+
+    /// Create a task with a single attachment with Base64 encoded content
+    fn attachment_task(name: &str, media_type: &str, content: &str) -> ModelTask {
+        let mut task = test_task_repeat_word();
+        let mut file = File::new(name.into(), name.into());
+        file.media_type = Some(media_type.into());
+        file.content = Some(content.into());
+        file.options.transfer_encoding = Some("base64".into());
+        task.attachments = Some(vec![InstructionAttachment::new(name.into(), file)]);
+        task
+    }
+
+    /// Create a vision-capable model which uses a cassette for requests
+    fn cassette_model(name: &str, cassette: Arc<Cassette>) -> OpenAIModel {
+        OpenAIModel::new(
+            name.into(),
+            0,
+            vec![ModelIO::Text, ModelIO::Image],
+            vec![ModelIO::Text],
+            vec![],
+        )
+        .with_transport(cassette)
+    }
+
+    #[tokio::test]
+    async fn upload_retry() -> Result<()> {
+        // The first upload reports fewer bytes than were sent so is deleted and retried
+        let cassette = Arc::new(Cassette::replay(
+            "tests/fixtures/cassettes/upload_retry.json",
+        )?);
+        let model = cassette_model("gpt-4.1", cassette.clone());
+        let task = attachment_task("report.pdf", "application/pdf", "JVBERi0xLjcKJeLjz9M=");
+
+        let output = model.perform_task(&task).await?;
+        assert_eq!(output.content, "The report covers Narrabeen.");
+        assert_eq!(cassette.unused(), 0);
+
+        // Recording replays the same interactions, with full requests, which can then be replayed
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("cassette.json");
+        let recorder = Arc::new(Cassette::record(
+            &path,
+            Arc::new(Cassette::replay(
+                "tests/fixtures/cassettes/upload_retry.json",
+            )?),
+        ));
+        cassette_model("gpt-4.1", recorder.clone())
+            .perform_task(&task)
+            .await?;
+        recorder.save()?;
+
+        let recorded: Vec<Interaction> = serde_json::from_str(&read_to_string(&path)?)?;
+        assert_eq!(recorded.len(), 6);
+        let Some(RequestBody::File { filename, .. }) = &recorded[0].request.body else {
+            bail!("expected file upload body")
+        };
+        assert_eq!(filename, "report.pdf");
+
+        let replayer = Arc::new(Cassette::replay(&path)?);
+        let output = cassette_model("gpt-4.1", replayer.clone())
+            .perform_task(&task)
+            .await?;
+        assert_eq!(output.content, "The report covers Narrabeen.");
+        assert_eq!(replayer.unused(), 0);
+
+        // Requests which are not in the cassette are errors
+        assert!(
+            cassette_model("gpt-4.1", replayer)
+                .perform_task(&task)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vision_retry() -> Result<()> {
+        let cassette = Arc::new(Cassette::replay(
+            "tests/fixtures/cassettes/vision_retry.json",
+        )?);
+        let model = cassette_model("gpt-4.1", cassette.clone());
+        let task = attachment_task("dot.png", "image/png", "iVBORw0KGgo=");
+
+        let output = model.perform_task(&task).await?;
+        assert_eq!(output.content, "A single dot.");
+        assert!(
+            output
+                .warnings
+                .iter()
+                .any(|warning| warning.message.contains("`gpt-4o-mini`"))
+        );
+        assert_eq!(cassette.unused(), 0);
+
+        Ok(())
+    }
+
+    /// A transport which uploads files, tracking how many uploads are in flight
+    ///
+    /// Uploads of files with names starting with `bad` fail. Uploads take longer
+    /// for files earlier in the list so that they complete in reverse order.
+    #[derive(Default)]
+    struct Uploads {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        responses: Mutex<Vec<Value>>,
+    }
+
+    #[async_trait]
+    impl Transport for Uploads {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse> {
+            let json = |value: Value| {
+                Ok(HttpResponse::new(
+                    StatusCode::OK,
+                    Some("application/json".into()),
+                    serde_json::to_vec(&value)?,
+                ))
             };
 
-            Some(
-                Arc::new(OpenAIModel::new(name, context_length, inputs, outputs)) as Arc<dyn Model>,
-            )
-        })
-        .collect();
+            match (request.method.as_str(), request.path.as_str(), request.body) {
+                ("POST", "/files", Some(RequestBody::File { filename, .. })) => {
+                    let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                    let number: u64 = filename
+                        .trim_start_matches(|c: char| !c.is_ascii_digit())
+                        .trim_end_matches(".pdf")
+                        .parse()?;
+                    tokio::time::sleep(Duration::from_millis(50 - number * 5)).await;
+                    self.in_flight.fetch_sub(1, Ordering::SeqCst);
 
-    Ok(models)
-}
+                    if filename.starts_with("bad") {
+                        return Ok(HttpResponse::new(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            None,
+                            b"upload failed".to_vec(),
+                        ));
+                    }
+                    json(json!({"id": format!("file-{filename}")}))
+                }
+                ("GET", path, ..) => json(json!({"id": path.trim_start_matches("/files/")})),
+                ("POST", "/responses", Some(RequestBody::Json { value })) => {
+                    self.responses
+                        .lock()
+                        .map_err(|_| eyre!("poisoned"))?
+                        .push(value);
+                    json(json!({
+                        "id": "resp_01",
+                        "object": "response",
+                        "status": "completed",
+                        "model": "gpt-4.1",
+                        "output": [{
+                            "type": "message",
+                            "id": "msg_01",
+                            "status": "completed",
+                            "role": "assistant",
+                            "content": [{"type": "output_text", "text": "Done.", "annotations": []}]
+                        }]
+                    }))
+                }
+                (method, path, ..) => bail!("unexpected request {method} {path}"),
+            }
+        }
+    }
 
-/// Fetch the list of models
-///
-/// In-memory cached for six hours to reduce requests to remote API.
-#[cached(time = 21_600, result = true)]
-async fn list_openai_models(_unused: u8) -> Result<ListModelResponse> {
-    Ok(OpenAIModel::client()?.models().list().await?)
-}
+    /// Create a model which uses an uploads transport for requests
+    fn upload_model(transport: Arc<Uploads>) -> OpenAIModel {
+        OpenAIModel::new(
+            "gpt-4.1".into(),
+            0,
+            vec![ModelIO::Text, ModelIO::Image],
+            vec![ModelIO::Text],
+            vec![],
+        )
+        .with_transport(transport)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use model::{common::tokio, schema::File, test_task_repeat_word};
+    /// Create a task with PDF attachments with the names
+    fn pdf_task(names: &[&str]) -> ModelTask {
+        let mut task = test_task_repeat_word();
+        task.attachments = Some(
+            names
+                .iter()
+                .map(|name| {
+                    let mut file = File::new(name.to_string(), name.to_string());
+                    file.media_type = Some("application/pdf".into());
+                    file.content = Some("JVBERi0xLjcKJeLjz9M=".into());
+                    file.options.transfer_encoding = Some("base64".into());
+                    InstructionAttachment::new(name.trim_end_matches(".pdf").into(), file)
+                })
+                .collect(),
+        );
+        task
+    }
+
+    #[tokio::test]
+    async fn uploads_concurrently() -> Result<()> {
+        let names = [
+            "report1.pdf",
+            "report2.pdf",
+            "report3.pdf",
+            "report4.pdf",
+            "report5.pdf",
+            "report6.pdf",
+        ];
+        let transport = Arc::new(Uploads::default());
+        let output = upload_model(transport.clone())
+            .perform_task(&pdf_task(&names))
+            .await?;
+        assert_eq!(output.content, "Done.");
+
+        // Uploads are concurrent, but capped
+        let max_in_flight = transport.max_in_flight.load(Ordering::SeqCst);
+        let cap = models_config().attachments.upload_concurrency;
+        assert!(max_in_flight > 1 && max_in_flight <= cap, "{max_in_flight}");
+
+        // Uploaded files are sent in the order of the attachments, not the order uploads completed in
+        let responses = transport.responses.lock().map_err(|_| eyre!("poisoned"))?;
+        let file_ids = responses[0]["input"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|item| item["content"].as_array().into_iter().flatten())
+            .filter_map(|content| content["file_id"].as_str())
+            .collect_vec();
+        assert_eq!(
+            file_ids,
+            names
+                .iter()
+                .map(|name| format!("file-{name}"))
+                .collect_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attachment_failure_policies() -> Result<()> {
+        let perform = |policy: Option<AttachmentFailurePolicy>| async move {
+            let mut task = pdf_task(&["report1.pdf", "bad2.pdf", "bad3.pdf"]);
+            task.attachment_failure_policy = policy;
+            upload_model(Arc::new(Uploads::default()))
+                .perform_task(&task)
+                .await
+        };
+
+        // Best effort (the default) proceeds with the attachments that were uploaded
+        let output = perform(None).await?;
+        assert_eq!(output.content, "Done.");
+        assert_eq!(
+            output
+                .warnings
+                .iter()
+                .filter(|warning| warning.message.contains("Failed to upload attachment"))
+                .count(),
+            2
+        );
+
+        // Require all reports every failed attachment
+        let Err(error) = perform(Some(AttachmentFailurePolicy::RequireAll)).await else {
+            bail!("expected error")
+        };
+        let error = error.to_string();
+        assert!(error.starts_with("2 attachment(s) could not be provided"));
+        assert!(error.contains("`bad2`") && error.contains("`bad3`"));
+
+        // Fail fast errors on the first failed attachment
+        let Err(error) = perform(Some(AttachmentFailurePolicy::FailFast)).await else {
+            bail!("expected error")
+        };
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to upload attachment `bad")
+        );
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn list_models() -> Result<()> {
@@ -0,0 +1,441 @@
+//! HTTP transport for the raw requests made to the OpenAI API
+//!
+//! The requests made directly with `reqwest` (to the Responses, Files, Containers,
+//! and Images APIs) are sent through a [`Transport`] so that they can be recorded
+//! to, and replayed from, a [`Cassette`]. This allows the attachment upload,
+//! verification and retry logic to be tested deterministically without network access.
+
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use model::{
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail, eyre},
+        futures::{
+            StreamExt,
+            stream::{self, BoxStream},
+        },
+        serde_json::{self, Value},
+        tracing,
+    },
+    models_config,
+};
+use reqwest::{Client as HttpClient, Method, StatusCode, header::CONTENT_TYPE, multipart};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::base_url;
+
+/// A request to the OpenAI API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRequest {
+    /// The HTTP method e.g. `POST`
+    pub method: String,
+
+    /// The path of the endpoint, relative to the base URL, e.g. `/responses`
+    pub path: String,
+
+    /// The body of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RequestBody>,
+}
+
+impl HttpRequest {
+    /// Create a `GET` request
+    pub fn get(path: impl Into<String>) -> Self {
+        Self {
+            method: "GET".into(),
+            path: path.into(),
+            body: None,
+        }
+    }
+
+    /// Create a `DELETE` request
+    pub fn delete(path: impl Into<String>) -> Self {
+        Self {
+            method: "DELETE".into(),
+            path: path.into(),
+            body: None,
+        }
+    }
+
+    /// Create a `POST` request with a JSON body
+    pub fn post_json<T: Serialize>(path: impl Into<String>, body: &T) -> Result<Self> {
+        Ok(Self {
+            method: "POST".into(),
+            path: path.into(),
+            body: Some(RequestBody::Json {
+                value: serde_json::to_value(body)?,
+            }),
+        })
+    }
+
+    /// Create a `POST` request uploading a file
+    pub fn post_file(
+        path: impl Into<String>,
+        purpose: impl Into<String>,
+        filename: impl Into<String>,
+        media_type: impl Into<String>,
+        content: Vec<u8>,
+    ) -> Self {
+        Self {
+            method: "POST".into(),
+            path: path.into(),
+            body: Some(RequestBody::File {
+                purpose: purpose.into(),
+                filename: filename.into(),
+                media_type: media_type.into(),
+                content: BASE64.encode(content),
+            }),
+        }
+    }
+
+    /// Whether a recorded request matches this request
+    ///
+    /// The method and path must be the same. A recorded request without a body
+    /// matches any body, so that hand written cassettes do not need to include
+    /// large request bodies.
+    fn matches(&self, recorded: &HttpRequest) -> bool {
+        self.method == recorded.method
+            && self.path == recorded.path
+            && (recorded.body.is_none() || self.body == recorded.body)
+    }
+}
+
+/// The body of a request to the OpenAI API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RequestBody {
+    /// A JSON body
+    Json { value: Value },
+
+    /// A multipart form uploading a file
+    #[serde(rename_all = "camelCase")]
+    File {
+        purpose: String,
+        filename: String,
+        media_type: String,
+
+        /// The content of the file, Base64 encoded
+        content: String,
+    },
+}
+
+/// A response from the OpenAI API
+pub struct HttpResponse {
+    status: StatusCode,
+    content_type: Option<String>,
+    body: ResponseBody,
+}
+
+enum ResponseBody {
+    Buffered(Vec<u8>),
+    Streamed(reqwest::Response),
+}
+
+impl HttpResponse {
+    /// Create a response with a buffered body
+    pub fn new(status: StatusCode, content_type: Option<String>, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: ResponseBody::Buffered(body),
+        }
+    }
+
+    /// The status code of the response
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The media type of the body of the response, if any
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Get the body of the response as bytes
+    pub async fn bytes(self) -> Result<Vec<u8>> {
+        Ok(match self.body {
+            ResponseBody::Buffered(bytes) => bytes,
+            ResponseBody::Streamed(response) => response.bytes().await?.to_vec(),
+        })
+    }
+
+    /// Get the body of the response as text
+    pub async fn text(self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes().await?).to_string())
+    }
+
+    /// Deserialize the body of the response from JSON
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.bytes().await?)?)
+    }
+
+    /// Get the body of the response as a stream of chunks
+    pub fn bytes_stream(self) -> BoxStream<'static, Result<Vec<u8>>> {
+        match self.body {
+            ResponseBody::Buffered(bytes) => stream::iter([Ok(bytes)]).boxed(),
+            ResponseBody::Streamed(response) => response
+                .bytes_stream()
+                .map(|chunk| Ok(chunk?.to_vec()))
+                .boxed(),
+        }
+    }
+
+    /// Return an error, including the body of the response, if the status is not successful
+    pub async fn error_for_status(self, api: &str) -> Result<Self> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+
+        let status = self.status;
+        let body = self.text().await.unwrap_or_default();
+        bail!("OpenAI {api} returned {status}: {body}")
+    }
+}
+
+/// A transport for sending requests to the OpenAI API
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Send a request and get the response
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse>;
+}
+
+/// A transport which sends requests to the OpenAI API using `reqwest`
+pub struct ReqwestTransport {
+    client: HttpClient,
+    api_key: String,
+}
+
+impl ReqwestTransport {
+    /// Create a transport using an API key
+    pub fn new(api_key: String) -> Result<Self> {
+        Ok(Self {
+            client: models_config().requests.http_client()?,
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let method = Method::from_bytes(request.method.as_bytes())?;
+        let mut builder = self
+            .client
+            .request(method, format!("{}{}", base_url(), request.path))
+            .bearer_auth(&self.api_key)
+            .header("OpenAI-Beta", "assistants=v2");
+
+        builder = match request.body {
+            Some(RequestBody::Json { value }) => builder.json(&value),
+            Some(RequestBody::File {
+                purpose,
+                filename,
+                media_type,
+                content,
+            }) => {
+                let part = multipart::Part::bytes(BASE64.decode(content)?)
+                    .file_name(filename)
+                    .mime_str(&media_type)?;
+                builder.multipart(
+                    multipart::Form::new()
+                        .text("purpose", purpose)
+                        .part("file", part),
+                )
+            }
+            None => builder,
+        };
+
+        let response = builder.send().await?;
+        Ok(HttpResponse {
+            status: response.status(),
+            content_type: response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from),
+            body: ResponseBody::Streamed(response),
+        })
+    }
+}
+
+/// A recorded request and response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Interaction {
+    pub request: HttpRequest,
+    pub response: RecordedResponse,
+}
+
+/// A recorded response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedResponse {
+    /// The status code of the response
+    pub status: u16,
+
+    /// The media type of the body of the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
+    /// The body of the response
+    ///
+    /// Bodies which are not valid UTF-8 (e.g. downloaded files) are Base64 encoded.
+    #[serde(default)]
+    pub body: Value,
+
+    /// Whether the body is a Base64 encoded string
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub base64: bool,
+}
+
+impl RecordedResponse {
+    /// Record the body of a response
+    ///
+    /// JSON bodies are recorded as JSON, rather than as a string, so that
+    /// cassettes are easier to read and write.
+    fn record(status: StatusCode, content_type: Option<String>, bytes: &[u8]) -> Self {
+        let (body, base64) = match std::str::from_utf8(bytes) {
+            Ok(text) => (
+                serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.into())),
+                false,
+            ),
+            Err(..) => (Value::String(BASE64.encode(bytes)), true),
+        };
+        Self {
+            status: status.as_u16(),
+            content_type,
+            body,
+            base64,
+        }
+    }
+
+    /// Convert to a response
+    fn to_response(&self) -> Result<HttpResponse> {
+        let bytes = match &self.body {
+            Value::String(string) if self.base64 => BASE64.decode(string)?,
+            Value::String(string) => string.clone().into_bytes(),
+            Value::Null => Vec::new(),
+            value => serde_json::to_vec(value)?,
+        };
+        Ok(HttpResponse::new(
+            StatusCode::from_u16(self.status)?,
+            self.content_type.clone(),
+            bytes,
+        ))
+    }
+}
+
+/// A transport which records interactions to, or replays them from, a file
+///
+/// When recording, requests are sent using an inner transport and the interactions
+/// are saved when [`Cassette::save`] is called. Streamed responses are buffered
+/// when recording. When replaying, each request is answered with the response of
+/// the first unused recorded interaction with a matching request. Matching does not
+/// depend upon order so that concurrent requests (e.g. uploads) can be replayed.
+pub struct Cassette {
+    /// The path of the cassette file
+    path: PathBuf,
+
+    /// The transport to record from, `None` when replaying
+    inner: Option<Arc<dyn Transport>>,
+
+    /// The interactions, and whether each has been replayed
+    interactions: Mutex<Vec<(Interaction, bool)>>,
+}
+
+impl Cassette {
+    /// Create a cassette which records interactions using a transport
+    pub fn record(path: impl AsRef<Path>, inner: Arc<dyn Transport>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            inner: Some(inner),
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Create a cassette which replays the interactions in a file
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let interactions: Vec<Interaction> = serde_json::from_str(&read_to_string(path)?)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            inner: None,
+            interactions: Mutex::new(
+                interactions
+                    .into_iter()
+                    .map(|interaction| (interaction, false))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Save the recorded interactions to the cassette file
+    pub fn save(&self) -> Result<()> {
+        let interactions = self
+            .interactions
+            .lock()
+            .map_err(|_| eyre!("Cassette lock poisoned"))?
+            .iter()
+            .map(|(interaction, ..)| interaction.clone())
+            .collect::<Vec<_>>();
+        write(&self.path, serde_json::to_string_pretty(&interactions)?)?;
+        Ok(())
+    }
+
+    /// The number of recorded interactions that have not been replayed
+    pub fn unused(&self) -> usize {
+        self.interactions
+            .lock()
+            .map(|interactions| interactions.iter().filter(|(.., used)| !used).count())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Transport for Cassette {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let lock_error = || eyre!("Cassette lock poisoned");
+
+        if let Some(inner) = &self.inner {
+            let response = inner.send(request.clone()).await?;
+            let status = response.status();
+            let content_type = response.content_type().map(String::from);
+            let bytes = response.bytes().await?;
+
+            let recorded = RecordedResponse::record(status, content_type.clone(), &bytes);
+            self.interactions.lock().map_err(|_| lock_error())?.push((
+                Interaction {
+                    request,
+                    response: recorded,
+                },
+                true,
+            ));
+
+            return Ok(HttpResponse::new(status, content_type, bytes));
+        }
+
+        let mut interactions = self.interactions.lock().map_err(|_| lock_error())?;
+        let Some((interaction, used)) = interactions
+            .iter_mut()
+            .find(|(interaction, used)| !used && request.matches(&interaction.request))
+        else {
+            bail!(
+                "No unused interaction in cassette `{}` matches request {} {}",
+                self.path.display(),
+                request.method,
+                request.path
+            );
+        };
+        *used = true;
+
+        tracing::trace!("Replaying {} {}", request.method, request.path);
+        interaction.response.to_response()
+    }
+}
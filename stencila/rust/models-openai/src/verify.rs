@@ -0,0 +1,155 @@
+use model::{
+    common::{eyre::Result, futures::future::join4, serde_json, tracing},
+    secrets,
+};
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+
+use crate::{API_KEY, base_url};
+
+/// Whether an API key has access to a scope
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScopeAccess {
+    /// The key has access to the scope
+    Allowed,
+
+    /// The key does not have access to the scope
+    Denied,
+
+    /// Access could not be determined e.g. because of a network or server error
+    Unknown,
+}
+
+/// A report of the validity and scopes of an OpenAI API key
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyReport {
+    /// Whether the key was accepted by the API
+    pub valid: bool,
+
+    /// Access to chat completions
+    pub chat: ScopeAccess,
+
+    /// Access to image generation
+    pub images: ScopeAccess,
+
+    /// Access to files (needed for attachments)
+    pub files: ScopeAccess,
+
+    /// Access to fine-tuning
+    pub fine_tuning: ScopeAccess,
+
+    /// Any error encountered while checking the key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Verify the OpenAI API key and probe the scopes it has access to
+///
+/// The key is first checked by listing models. Scopes are then probed using requests
+/// which are either read-only, or are deliberately invalid (e.g. a chat completion
+/// with no messages) so that they are rejected before any generation (and cost).
+/// A key without a scope gets a 401 or 403 response, rather than a 400 validation error.
+pub async fn verify_key() -> Result<KeyReport> {
+    let api_key = secrets::env_or_get(API_KEY)?;
+    let client = model::models_config().requests.http_client()?;
+
+    let probe = |method: Method, path: &'static str| {
+        let request = client
+            .request(method.clone(), format!("{}/{path}", base_url()))
+            .bearer_auth(&api_key);
+        let request = if method == Method::POST {
+            request.json(&serde_json::json!({}))
+        } else {
+            request
+        };
+        async move {
+            match request.send().await {
+                Ok(response) => Ok(response.status()),
+                Err(error) => {
+                    tracing::debug!("While probing `{path}`: {error}");
+                    Err(error.to_string())
+                }
+            }
+        }
+    };
+
+    let status = match probe(Method::GET, "models").await {
+        Ok(status) => status,
+        Err(error) => {
+            return Ok(KeyReport {
+                valid: false,
+                chat: ScopeAccess::Unknown,
+                images: ScopeAccess::Unknown,
+                files: ScopeAccess::Unknown,
+                fine_tuning: ScopeAccess::Unknown,
+                error: Some(error),
+            });
+        }
+    };
+    if status == StatusCode::UNAUTHORIZED {
+        return Ok(KeyReport {
+            valid: false,
+            chat: ScopeAccess::Denied,
+            images: ScopeAccess::Denied,
+            files: ScopeAccess::Denied,
+            fine_tuning: ScopeAccess::Denied,
+            error: Some("API key was rejected".into()),
+        });
+    }
+
+    let (chat, images, files, fine_tuning) = join4(
+        probe(Method::POST, "chat/completions"),
+        probe(Method::POST, "images/generations"),
+        probe(Method::GET, "files?limit=1"),
+        probe(Method::GET, "fine_tuning/jobs?limit=1"),
+    )
+    .await;
+
+    Ok(KeyReport {
+        valid: true,
+        chat: scope_access(chat),
+        images: scope_access(images),
+        files: scope_access(files),
+        fine_tuning: scope_access(fine_tuning),
+        error: None,
+    })
+}
+
+/// Determine access to a scope from the status of a probe
+fn scope_access(status: Result<StatusCode, String>) -> ScopeAccess {
+    match status {
+        Ok(status) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+            ScopeAccess::Denied
+        }
+        Ok(status)
+            if status.is_success()
+                || status == StatusCode::BAD_REQUEST
+                || status == StatusCode::UNPROCESSABLE_ENTITY =>
+        {
+            ScopeAccess::Allowed
+        }
+        _ => ScopeAccess::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_access_from_status() {
+        assert_eq!(scope_access(Ok(StatusCode::OK)), ScopeAccess::Allowed);
+        assert_eq!(
+            scope_access(Ok(StatusCode::BAD_REQUEST)),
+            ScopeAccess::Allowed
+        );
+        assert_eq!(scope_access(Ok(StatusCode::FORBIDDEN)), ScopeAccess::Denied);
+        assert_eq!(
+            scope_access(Ok(StatusCode::INTERNAL_SERVER_ERROR)),
+            ScopeAccess::Unknown
+        );
+        assert_eq!(scope_access(Err("timeout".into())), ScopeAccess::Unknown);
+    }
+}
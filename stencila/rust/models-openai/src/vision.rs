@@ -0,0 +1,213 @@
+use std::{env, sync::RwLock};
+
+use model::common::{glob::Pattern, once_cell::sync::Lazy, serde_json, tracing};
+use serde::Deserialize;
+
+/// The name of the env var for vision fallbacks
+///
+/// A semicolon separated list of `pattern=substitute` pairs
+/// e.g. `gpt-5*=gpt-4.1-mini;gpt-4.1*=gpt-4o-mini`.
+const FALLBACKS_ENV_VAR: &str = "STENCILA_OPENAI_VISION_FALLBACKS";
+
+/// The name of the env var for the OpenAI error codes which trigger a vision retry
+///
+/// A comma separated list of error codes.
+const ERROR_CODES_ENV_VAR: &str = "STENCILA_OPENAI_VISION_ERROR_CODES";
+
+/// The current vision retry policy
+static POLICY: Lazy<RwLock<VisionRetryPolicy>> =
+    Lazy::new(|| RwLock::new(VisionRetryPolicy::from_env()));
+
+/// Get the current vision retry policy
+pub fn vision_retry_policy() -> VisionRetryPolicy {
+    POLICY
+        .read()
+        .map(|policy| policy.clone())
+        .unwrap_or_default()
+}
+
+/// Set the vision retry policy
+///
+/// Replaces the policy loaded from environment variables (or the default policy).
+pub fn set_vision_retry_policy(policy: VisionRetryPolicy) {
+    if let Ok(mut current) = POLICY.write() {
+        *current = policy;
+    }
+}
+
+/// A substitute model to retry a request with when a model rejects image inputs
+#[derive(Debug, Clone)]
+pub struct VisionFallback {
+    /// A glob pattern matched against the name of the model e.g. `gpt-5*`
+    pub pattern: String,
+
+    /// The name of the model to retry the request with
+    pub substitute: String,
+}
+
+impl VisionFallback {
+    pub fn new(pattern: &str, substitute: &str) -> Self {
+        Self {
+            pattern: pattern.into(),
+            substitute: substitute.into(),
+        }
+    }
+}
+
+/// A policy for retrying requests with attachments using a vision-capable model
+///
+/// When the OpenAI API rejects a request because the model does not accept image
+/// inputs, the first fallback with a pattern matching the model is used to retry the request.
+#[derive(Debug, Clone)]
+pub struct VisionRetryPolicy {
+    /// Fallbacks, checked in order
+    pub fallbacks: Vec<VisionFallback>,
+
+    /// OpenAI error codes indicating that image inputs are not supported
+    pub error_codes: Vec<String>,
+
+    /// Substrings of OpenAI error messages indicating that image inputs are not supported
+    ///
+    /// Each entry is a list of substrings which must all be present in the message.
+    pub error_messages: Vec<Vec<String>>,
+}
+
+impl Default for VisionRetryPolicy {
+    fn default() -> Self {
+        Self {
+            fallbacks: vec![
+                VisionFallback::new("gpt-5*", "gpt-4.1-mini"),
+                VisionFallback::new("gpt-4.1*", "gpt-4o-mini"),
+            ],
+            error_codes: vec!["image_input_not_supported".into()],
+            error_messages: vec![
+                vec!["Invalid input".into(), "context stuffing".into()],
+                vec!["does not support image inputs".into()],
+            ],
+        }
+    }
+}
+
+/// The body of an OpenAI API error response
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    message: String,
+    code: Option<String>,
+}
+
+impl VisionRetryPolicy {
+    /// Create the default policy, with fallbacks and error codes overridden by env vars
+    fn from_env() -> Self {
+        let mut policy = Self::default();
+
+        if let Ok(value) = env::var(FALLBACKS_ENV_VAR) {
+            policy.fallbacks = value
+                .split(';')
+                .filter_map(|pair| {
+                    let (pattern, substitute) = pair.split_once('=')?;
+                    Some(VisionFallback::new(pattern.trim(), substitute.trim()))
+                })
+                .collect();
+        }
+
+        if let Ok(value) = env::var(ERROR_CODES_ENV_VAR) {
+            policy.error_codes = value
+                .split(',')
+                .map(str::trim)
+                .filter(|code| !code.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        policy
+    }
+
+    /// Get the substitute model to retry with, if any, given the model and error response body
+    pub fn substitute(&self, model: &str, error_body: &str) -> Option<String> {
+        if !self.is_vision_error(error_body) {
+            return None;
+        }
+
+        self.fallbacks
+            .iter()
+            .find(|fallback| match Pattern::new(&fallback.pattern) {
+                Ok(pattern) => pattern.matches(model),
+                Err(error) => {
+                    tracing::warn!(
+                        "Invalid vision fallback pattern `{}`: {error}",
+                        fallback.pattern
+                    );
+                    false
+                }
+            })
+            .map(|fallback| fallback.substitute.clone())
+            .filter(|substitute| substitute != model)
+    }
+
+    /// Whether an error response body indicates that image inputs are not supported
+    ///
+    /// Uses the structured error code and message if the body is an OpenAI error
+    /// response, falling back to matching message substrings against the whole body.
+    fn is_vision_error(&self, error_body: &str) -> bool {
+        let (code, message) = match serde_json::from_str::<ErrorResponse>(error_body) {
+            Ok(ErrorResponse { error }) => (error.code, error.message),
+            Err(..) => (None, error_body.to_string()),
+        };
+
+        if let Some(code) = code
+            && self.error_codes.contains(&code)
+        {
+            return true;
+        }
+
+        self.error_messages.iter().any(|substrings| {
+            !substrings.is_empty()
+                && substrings
+                    .iter()
+                    .all(|substring| message.contains(substring.as_str()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes() {
+        let policy = VisionRetryPolicy::default();
+
+        assert_eq!(
+            policy.substitute(
+                "gpt-5-mini",
+                r#"{"error":{"message":"Model does not support image inputs","code":null}}"#
+            ),
+            Some("gpt-4.1-mini".into())
+        );
+        assert_eq!(
+            policy.substitute(
+                "gpt-4.1-nano",
+                r#"{"error":{"message":"Bad","code":"image_input_not_supported"}}"#
+            ),
+            Some("gpt-4o-mini".into())
+        );
+        assert_eq!(
+            policy.substitute("gpt-4.1", "Invalid input: context stuffing"),
+            Some("gpt-4o-mini".into())
+        );
+        assert_eq!(
+            policy.substitute("gpt-4o", "does not support image inputs"),
+            None
+        );
+        assert_eq!(
+            policy.substitute("gpt-5", r#"{"error":{"message":"Rate limited"}}"#),
+            None
+        );
+    }
+}
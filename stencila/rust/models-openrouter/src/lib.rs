@@ -0,0 +1,490 @@
+use std::{sync::Arc, time::Duration};
+
+use cached::proc_macro::cached;
+
+use model::{
+    Model, ModelIO, ModelOutput, ModelTask, ModelType, ModelWarning, api_key,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail},
+        itertools::Itertools,
+        reqwest::Client,
+        serde::{Deserialize, Serialize},
+        serde_with::skip_serializing_none,
+        tracing,
+    },
+    models_config,
+    schema::{ImageObject, MessagePart, MessageRole},
+    secrets,
+};
+
+const BASE_URL: &str = "https://openrouter.ai/api/v1";
+
+/// Get the base URL of the OpenRouter API from the models config
+fn base_url() -> String {
+    models_config().requests.base_url("openrouter", BASE_URL)
+}
+
+/// The name of the env var or secret for the API key
+const API_KEY: &str = "OPENROUTER_API_KEY";
+
+/// The URL and title of the app, sent to OpenRouter for attribution
+const APP_URL: &str = "https://stencila.io";
+const APP_TITLE: &str = "Stencila";
+
+struct OpenRouterModel {
+    /// The OpenRouter id of the model e.g. `anthropic/claude-3.5-sonnet`
+    model: String,
+
+    /// The display name of the model
+    name: String,
+
+    /// The description of the model
+    description: Option<String>,
+
+    /// The context length of the model
+    context_length: usize,
+
+    /// The cost of the model in US dollars per million tokens
+    cost_per_mtok: Option<f64>,
+
+    /// The input types supported by the model
+    inputs: Vec<ModelIO>,
+
+    /// The HTTP client
+    client: Client,
+}
+
+impl OpenRouterModel {
+    /// Create an OpenRouter model from its specification
+    fn new(spec: ModelSpec) -> Result<Self> {
+        // Names are prefixed by the publisher e.g. "Anthropic: Claude 3.5 Sonnet"
+        let name = spec
+            .name
+            .split_once(": ")
+            .map(|(.., name)| name.to_string())
+            .unwrap_or(spec.name);
+
+        let mut inputs = vec![ModelIO::Text];
+        if let Some(architecture) = &spec.architecture
+            && architecture.input_modalities.iter().any(|io| io == "image")
+        {
+            inputs.push(ModelIO::Image);
+        }
+
+        Ok(Self {
+            cost_per_mtok: spec.pricing.as_ref().and_then(ModelPricing::per_mtok),
+            model: spec.id,
+            name,
+            description: spec.description.filter(|desc| !desc.trim().is_empty()),
+            context_length: spec.context_length.unwrap_or_default(),
+            inputs,
+            client: models_config().requests.http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Model for OpenRouterModel {
+    fn id(&self) -> String {
+        format!("openrouter/{}", self.model)
+    }
+
+    fn r#type(&self) -> ModelType {
+        ModelType::Remote
+    }
+
+    fn provider(&self) -> String {
+        "OpenRouter".to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn version(&self) -> String {
+        self.model
+            .rsplit_once('/')
+            .map_or(self.model.as_str(), |(.., model)| model)
+            .split_once('-')
+            .map(|(.., version)| version)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn description(&self) -> Option<String> {
+        self.description.clone()
+    }
+
+    fn context_length(&self) -> usize {
+        self.context_length
+    }
+
+    fn supported_inputs(&self) -> &[ModelIO] {
+        &self.inputs
+    }
+
+    fn supported_outputs(&self) -> &[ModelIO] {
+        &[ModelIO::Text]
+    }
+
+    fn cost_per_mtok(&self) -> Option<f64> {
+        self.cost_per_mtok
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
+        let mut warnings = Vec::new();
+        let images = self.inputs.contains(&ModelIO::Image);
+
+        let messages = task
+            .messages
+            .iter()
+            .map(|message| {
+                let role = match message.role.unwrap_or_default() {
+                    MessageRole::Model => ChatRole::Assistant,
+                    MessageRole::System | MessageRole::Developer => ChatRole::System,
+                    MessageRole::User => ChatRole::User,
+                };
+
+                let content = message
+                    .parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        MessagePart::Text(text) => Some(ChatContentPart::Text {
+                            text: text.to_value_string(),
+                        }),
+                        MessagePart::ImageObject(ImageObject { content_url, .. }) if images => {
+                            Some(ChatContentPart::ImageUrl {
+                                image_url: ImageUrl {
+                                    url: content_url.clone(),
+                                },
+                            })
+                        }
+                        _ => {
+                            warnings.push(ModelWarning::ignored_part(format!(
+                                "Message part of type `{part}` is ignored by model `{}`",
+                                self.id()
+                            )));
+                            None
+                        }
+                    })
+                    .collect();
+
+                ChatMessage { role, content }
+            })
+            .collect();
+
+        let request = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: task.temperature,
+            top_p: task.top_p,
+            max_tokens: task.max_tokens,
+            seed: task.seed,
+        };
+
+        if task.dry_run {
+            return ModelOutput::empty(self);
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/chat/completions", base_url()))
+            .bearer_auth(api_key(API_KEY, &self.id(), Some(task))?)
+            .header("HTTP-Referer", APP_URL)
+            .header("X-Title", APP_TITLE)
+            .json(&request)
+            .send()
+            .await?;
+
+        if let Err(error) = response.error_for_status_ref() {
+            let message = response.text().await?;
+            bail!("{error}: {message}");
+        }
+
+        let response: ChatCompletionResponse = response.json().await?;
+        if let Some(error) = response.error {
+            bail!("OpenRouter error: {}", error.message);
+        }
+
+        let Some(choice) = response.choices.into_iter().next() else {
+            bail!("No choices in response from model `{}`", self.id())
+        };
+        let text = choice.message.content.unwrap_or_default();
+
+        let mut output = ModelOutput::from_text(self, &task.format, text).await?;
+        output.warnings = warnings;
+
+        Ok(output)
+    }
+}
+
+/// A model list response
+///
+/// Based on https://openrouter.ai/docs/api-reference/list-available-models
+#[derive(Clone, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ModelsResponse {
+    data: Vec<ModelSpec>,
+}
+
+/// A model returned within a `ModelsResponse`
+///
+/// Note: at present several other fields are ignored.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ModelSpec {
+    id: String,
+    name: String,
+    description: Option<String>,
+    context_length: Option<usize>,
+    architecture: Option<ModelArchitecture>,
+    pricing: Option<ModelPricing>,
+}
+
+/// The architecture of a model within a `ModelSpec`
+#[derive(Clone, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ModelArchitecture {
+    #[serde(default)]
+    input_modalities: Vec<String>,
+
+    #[serde(default)]
+    output_modalities: Vec<String>,
+}
+
+/// The pricing of a model within a `ModelSpec`
+///
+/// Prices are strings of US dollars per token.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ModelPricing {
+    prompt: String,
+    completion: String,
+}
+
+impl ModelPricing {
+    /// Get the blended cost in US dollars per million tokens
+    ///
+    /// Assumes three input tokens for each output token (as for [`Model::cost_per_mtok`]).
+    /// Returns `None` if the prices can not be parsed or are negative (used
+    /// by OpenRouter for routers with variable pricing).
+    fn per_mtok(&self) -> Option<f64> {
+        let prompt: f64 = self.prompt.parse().ok()?;
+        let completion: f64 = self.completion.parse().ok()?;
+        if prompt < 0.0 || completion < 0.0 {
+            return None;
+        }
+        Some((3.0 * prompt + completion) / 4.0 * 1_000_000.0)
+    }
+}
+
+/// A chat completion request
+///
+/// Based on https://openrouter.ai/docs/api-reference/chat-completion
+#[skip_serializing_none]
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u16>,
+    seed: Option<i32>,
+}
+
+/// A chat message within a `ChatCompletionRequest`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatMessage {
+    role: ChatRole,
+    content: Vec<ChatContentPart>,
+}
+
+/// A role in a `ChatMessage`
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase", crate = "model::common::serde")]
+enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// A part of the content of a `ChatMessage`
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    crate = "model::common::serde"
+)]
+enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// The URL (usually a data URI) of an image in a `ChatContentPart`
+#[derive(Serialize)]
+#[serde(crate = "model::common::serde")]
+struct ImageUrl {
+    url: String,
+}
+
+/// A chat completion response
+///
+/// Note: at present several other fields are ignored.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionResponse {
+    #[serde(default)]
+    choices: Vec<ChatCompletionChoice>,
+    error: Option<ChatCompletionError>,
+}
+
+/// A choice within a `ChatCompletionResponse`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+/// The message of a `ChatCompletionChoice`
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+/// An error within a `ChatCompletionResponse`
+///
+/// OpenRouter may return errors from the upstream provider with a 200 status.
+#[derive(Deserialize)]
+#[serde(crate = "model::common::serde")]
+struct ChatCompletionError {
+    message: String,
+}
+
+/// Get a list of available OpenRouter models
+///
+/// Returns an empty list if the `OPENROUTER_API_KEY` env var is not set.
+/// Only models which generate text are listed.
+///
+/// Memoized for two minutes to avoid loading from disk cache too frequently
+/// but allowing user to set API key while process is running.
+#[cached(time = 120, result = true)]
+pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
+    // Check for API key before calling IO cached function so that we never cache an empty list
+    // and allow for users to set key, and then get list, while process is running
+    if secrets::env_or_get(API_KEY).is_err() {
+        tracing::trace!("The environment variable or secret `{API_KEY}` is not available");
+        return Ok(vec![]);
+    };
+
+    let models = list_openrouter_models()
+        .await?
+        .data
+        .into_iter()
+        .filter(|spec| {
+            spec.architecture.as_ref().is_none_or(|architecture| {
+                architecture.output_modalities.is_empty()
+                    || architecture.output_modalities.iter().any(|io| io == "text")
+            })
+        })
+        .sorted_by(|a, b| a.id.cmp(&b.id))
+        .map(|spec| Ok(Arc::new(OpenRouterModel::new(spec)?) as Arc<dyn Model>))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(models)
+}
+
+/// Fetch the list of models
+///
+/// In-memory cached for six hours to reduce requests to remote API.
+#[cached(time = 21_600, result = true)]
+async fn list_openrouter_models() -> Result<ModelsResponse> {
+    let response = models_config()
+        .requests
+        .http_client()?
+        .get(format!("{}/models", base_url()))
+        .bearer_auth(secrets::env_or_get(API_KEY)?)
+        .send()
+        .await?;
+
+    if let Err(error) = response.error_for_status_ref() {
+        let message = response.text().await?;
+        bail!("{error}: {message}");
+    }
+
+    Ok(response.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{
+        common::{serde_json, tokio},
+        test_task_repeat_word,
+    };
+
+    #[test]
+    fn model_from_spec() -> Result<()> {
+        let spec: ModelSpec = serde_json::from_str(
+            r#"{
+                "id": "anthropic/claude-3.5-sonnet",
+                "name": "Anthropic: Claude 3.5 Sonnet",
+                "description": "",
+                "context_length": 200000,
+                "architecture": {"input_modalities": ["text", "image"], "output_modalities": ["text"]},
+                "pricing": {"prompt": "0.000003", "completion": "0.000015"}
+            }"#,
+        )?;
+        let model = OpenRouterModel::new(spec)?;
+
+        assert_eq!(model.id(), "openrouter/anthropic/claude-3.5-sonnet");
+        assert_eq!(model.provider(), "OpenRouter");
+        assert_eq!(model.name(), "Claude 3.5 Sonnet");
+        assert_eq!(model.version(), "3.5-sonnet");
+        assert_eq!(model.description(), None);
+        assert_eq!(model.context_length(), 200_000);
+        assert_eq!(model.supported_inputs(), &[ModelIO::Text, ModelIO::Image]);
+        assert!((model.cost_per_mtok().unwrap_or_default() - 6.0).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_models() -> Result<()> {
+        let list = list().await?;
+
+        if secrets::env_or_get(API_KEY).is_err() {
+            assert_eq!(list.len(), 0)
+        } else {
+            assert!(!list.is_empty())
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn perform_task() -> Result<()> {
+        if secrets::env_or_get(API_KEY).is_err() {
+            return Ok(());
+        }
+
+        let Some(model) = list()
+            .await?
+            .into_iter()
+            .find(|model| model.id() == "openrouter/openai/gpt-4o-mini")
+        else {
+            return Ok(());
+        };
+        let output = model.perform_task(&test_task_repeat_word()).await?;
+
+        assert_eq!(output.content.trim(), "HELLO");
+
+        Ok(())
+    }
+}
@@ -12,6 +12,7 @@ use model::{
         reqwest::Client,
         serde::{Deserialize, Serialize},
     },
+    models_config,
 };
 
 /// A model available via Stencila Cloud
@@ -101,6 +102,8 @@ impl Model for StencilaModel {
     }
 
     async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+        models_config().policy.check_local_only(self)?;
+
         let token = cloud::api_token().ok_or_else(|| eyre!("No STENCILA_API_TOKEN environment variable or key chain entry found. Get one at https://stencila.cloud/."))?;
 
         if task.dry_run {
@@ -156,7 +159,8 @@ pub async fn list() -> Result<Vec<Arc<dyn Model>>> {
 /// In-memory cached for six hours to reduce requests to remote API.
 #[cached(time = 21_600, result = true)]
 async fn list_stencila_models(_unused: u8) -> Result<Vec<StencilaModel>> {
-    let response = Client::new()
+    let client = models_config().requests.http_client()?;
+    let response = client
         .get(format!("{}/models", cloud::base_url()))
         .send()
         .await?;
@@ -166,7 +170,12 @@ async fn list_stencila_models(_unused: u8) -> Result<Vec<StencilaModel>> {
         bail!("{error}: {message}");
     }
 
-    Ok(response.json().await?)
+    let mut models: Vec<StencilaModel> = response.json().await?;
+    for model in &mut models {
+        model.client = client.clone();
+    }
+
+    Ok(models)
 }
 
 #[cfg(test)]
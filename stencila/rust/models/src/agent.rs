@@ -0,0 +1,363 @@
+use std::{sync::Arc, time::Instant};
+
+use model::{
+    Model, ModelOutput, ModelTask, ModelWarning, ToolCall, ToolHandler,
+    common::{
+        eyre::{Result, eyre},
+        serde::Serialize,
+        tracing,
+    },
+    schema::{InstructionMessage, MessagePart, MessageRole, ToolResult},
+};
+
+/// The default maximum number of iterations of an agent loop
+const DEFAULT_MAX_ITERATIONS: usize = 10;
+
+/// An executor of multi-turn tool use
+///
+/// Sends the task to a model and, while the model responds with tool calls,
+/// dispatches the calls to the registered [`ToolHandler`]s, appends the results
+/// to the task, and resends it. Stops when the model responds without tool calls
+/// (i.e. with its final answer) or when the maximum number of iterations is reached.
+///
+/// ```ignore
+/// let run = Agent::new().tool(ReadFile::new(dirs)).run(task).await?;
+/// ```
+pub struct Agent {
+    /// The handlers of the tools available to the model
+    tools: Vec<Arc<dyn ToolHandler>>,
+
+    /// The maximum number of times the task is sent to the model
+    max_iterations: usize,
+
+    /// The model to use
+    ///
+    /// If `None`, each iteration is performed using [`crate::perform_task`]
+    /// (i.e. with model selection, caching, retries etc).
+    model: Option<Arc<dyn Model>>,
+}
+
+impl Default for Agent {
+    fn default() -> Self {
+        Self {
+            tools: Vec::new(),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            model: None,
+        }
+    }
+}
+
+/// The record of a run of an [`Agent`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct AgentRun {
+    /// The output of the last iteration
+    pub output: ModelOutput,
+
+    /// The steps of the run, one for each iteration
+    pub trace: Vec<AgentStep>,
+
+    /// Whether the model gave a final answer before the maximum number of iterations
+    pub completed: bool,
+}
+
+/// A step in the [`AgentRun::trace`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct AgentStep {
+    /// The iteration number, starting at 1
+    pub iteration: usize,
+
+    /// The id of the model that performed the iteration
+    pub model: String,
+
+    /// The content generated by the model
+    pub content: String,
+
+    /// The tool calls made by the model
+    pub tool_calls: Vec<ToolCall>,
+
+    /// The results of the tool calls
+    pub results: Vec<AgentToolResult>,
+
+    /// The time taken by the model, in milliseconds
+    pub latency_ms: u64,
+}
+
+/// The result of a tool call in an [`AgentStep`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct AgentToolResult {
+    /// The id of the call
+    pub call_id: String,
+
+    /// The name of the tool called
+    pub name: String,
+
+    /// The result returned to the model
+    pub content: String,
+
+    /// Whether the call failed (in which case `content` is the error message)
+    pub is_error: bool,
+
+    /// The time taken by the tool, in milliseconds
+    pub latency_ms: u64,
+}
+
+impl Agent {
+    /// Create an agent with no tools
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tool
+    pub fn tool(mut self, handler: impl ToolHandler + 'static) -> Self {
+        self.tools.push(Arc::new(handler));
+        self
+    }
+
+    /// Set the maximum number of iterations
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations.max(1);
+        self
+    }
+
+    /// Set the model to use rather than selecting one for each iteration
+    pub fn model(mut self, model: Arc<dyn Model>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Run the agent loop for a task
+    ///
+    /// If the maximum number of iterations is reached, the output of the last
+    /// iteration is returned (with a warning) and the run is not `completed`.
+    #[tracing::instrument(skip_all)]
+    pub async fn run(&self, mut task: ModelTask) -> Result<AgentRun> {
+        task.tools
+            .extend(self.tools.iter().map(|handler| handler.definition()));
+
+        let mut trace = Vec::new();
+        let mut last = None;
+        for iteration in 1..=self.max_iterations {
+            let started = Instant::now();
+            let output = match &self.model {
+                Some(model) => model.perform_task(&task).await?,
+                None => crate::perform_task(task.clone()).await?,
+            };
+            let latency_ms = started.elapsed().as_millis() as u64;
+
+            let calls: Vec<ToolCall> = output.tool_calls().into_iter().cloned().collect();
+            let mut step = AgentStep {
+                iteration,
+                model: output.report.as_ref().map_or_else(
+                    || {
+                        self.model
+                            .as_ref()
+                            .map(|model| model.id())
+                            .unwrap_or_default()
+                    },
+                    |report| report.model.clone(),
+                ),
+                content: output.content.clone(),
+                tool_calls: calls.clone(),
+                results: Vec::new(),
+                latency_ms,
+            };
+
+            if calls.is_empty() {
+                trace.push(step);
+                return Ok(AgentRun {
+                    output,
+                    trace,
+                    completed: true,
+                });
+            }
+
+            tracing::debug!("Agent iteration {iteration}: {} tool calls", calls.len());
+
+            let mut parts = Vec::new();
+            for call in &calls {
+                let result = self.dispatch(call).await;
+                parts.push(MessagePart::ToolResult(ToolResult {
+                    name: Some(call.name.clone()),
+                    ..ToolResult::new(call.id.clone(), result.content.clone())
+                }));
+                step.results.push(result);
+            }
+
+            task.messages.push(InstructionMessage {
+                role: Some(MessageRole::Model),
+                parts: vec![MessagePart::from(output.content.as_str())],
+                ..Default::default()
+            });
+            task.messages.push(InstructionMessage {
+                role: Some(MessageRole::User),
+                parts,
+                ..Default::default()
+            });
+            task.tool_calls.extend(calls);
+
+            trace.push(step);
+            last = Some(output);
+        }
+
+        let mut output = last.ok_or_else(|| eyre!("Agent loop performed no iterations"))?;
+        output.warnings.push(ModelWarning::fallback(format!(
+            "Agent stopped after reaching the maximum of {} iterations",
+            self.max_iterations
+        )));
+
+        Ok(AgentRun {
+            output,
+            trace,
+            completed: false,
+        })
+    }
+
+    /// Dispatch a tool call to its handler
+    async fn dispatch(&self, call: &ToolCall) -> AgentToolResult {
+        let started = Instant::now();
+
+        let result = match self
+            .tools
+            .iter()
+            .find(|handler| handler.definition().name == call.name)
+        {
+            Some(handler) => match call.parse_arguments() {
+                Ok(arguments) => handler.call(arguments).await,
+                Err(error) => Err(eyre!("invalid arguments: {error}")),
+            },
+            None => Err(eyre!("unknown tool `{}`", call.name)),
+        };
+
+        let (content, is_error) = match result {
+            Ok(content) => (content, false),
+            Err(error) => {
+                tracing::debug!("Tool call `{}` failed: {error}", call.name);
+                (format!("Error: {error}"), true)
+            }
+        };
+
+        AgentToolResult {
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            content,
+            is_error,
+            latency_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use model::{
+        ModelOutputPart, ToolDefinition,
+        common::{
+            async_trait::async_trait,
+            eyre::bail,
+            serde_json::{Value, json},
+            tokio,
+        },
+    };
+
+    use super::*;
+
+    /// A model which calls the `add` tool until it has seen a result
+    struct Adder {
+        calls: AtomicUsize,
+        limit: usize,
+    }
+
+    #[async_trait]
+    impl Model for Adder {
+        fn id(&self) -> String {
+            "test/adder".into()
+        }
+
+        async fn perform_task(&self, task: &ModelTask) -> Result<ModelOutput> {
+            let result = task
+                .messages
+                .iter()
+                .flat_map(|message| &message.parts)
+                .find_map(|part| match part {
+                    MessagePart::ToolResult(result) => Some(result.content.clone()),
+                    _ => None,
+                });
+
+            let count = self.calls.fetch_add(1, Ordering::SeqCst);
+            let mut output = ModelOutput::empty(self)?;
+            match result {
+                Some(result) if count >= self.limit => {
+                    output.content = format!("The sum is {result}")
+                }
+                _ => output.parts.push(ModelOutputPart::ToolCall(ToolCall {
+                    id: format!("call_{count}"),
+                    name: task.tools[0].name.clone(),
+                    arguments: r#"{"a": 1, "b": 2}"#.into(),
+                })),
+            }
+            Ok(output)
+        }
+    }
+
+    struct Add;
+
+    #[async_trait]
+    impl ToolHandler for Add {
+        fn definition(&self) -> ToolDefinition {
+            ToolDefinition::new("add", "Add two numbers", json!({"type": "object"}))
+        }
+
+        async fn call(&self, arguments: Value) -> Result<String> {
+            let (Some(a), Some(b)) = (arguments["a"].as_i64(), arguments["b"].as_i64()) else {
+                bail!("expected integers `a` and `b`")
+            };
+            Ok((a + b).to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn loops_until_answer() -> Result<()> {
+        let model = Arc::new(Adder {
+            calls: AtomicUsize::new(0),
+            limit: 1,
+        });
+        let run = Agent::new()
+            .tool(Add)
+            .model(model)
+            .run(ModelTask::default())
+            .await?;
+
+        assert!(run.completed);
+        assert_eq!(run.output.content, "The sum is 3");
+        assert_eq!(run.trace.len(), 2);
+        assert_eq!(run.trace[0].tool_calls[0].name, "add");
+        assert_eq!(run.trace[0].results[0].content, "3");
+        assert!(!run.trace[0].results[0].is_error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stops_at_iteration_cap() -> Result<()> {
+        let model = Arc::new(Adder {
+            calls: AtomicUsize::new(0),
+            limit: usize::MAX,
+        });
+        let run = Agent::new()
+            .tool(Add)
+            .model(model)
+            .max_iterations(3)
+            .run(ModelTask::default())
+            .await?;
+
+        assert!(!run.completed);
+        assert_eq!(run.trace.len(), 3);
+        assert_eq!(run.output.warnings.len(), 1);
+
+        Ok(())
+    }
+}
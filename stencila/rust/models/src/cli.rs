@@ -15,6 +15,8 @@ use model::{
     schema::{InstructionMessage, ModelParameters},
 };
 
+use models_openai::ScopeAccess;
+
 use crate::select;
 
 /// Manage generative models
@@ -55,6 +57,7 @@ pub static CLI_AFTER_LONG_HELP: &str = cstr!(
 enum Command {
     List(List),
     Run(Run),
+    VerifyKey(VerifyKey),
 }
 
 impl Cli {
@@ -67,6 +70,7 @@ impl Cli {
         match command {
             Command::List(list) => list.run().await?,
             Command::Run(run) => run.run().await?,
+            Command::VerifyKey(verify) => verify.run().await?,
         }
 
         Ok(())
@@ -156,6 +160,9 @@ impl List {
                     Plugin(name) => Cell::new(format!("plugin \"{name}\"")).fg(Color::DarkCyan),
                 },
                 match availability {
+                    Available if model.is_stale() => {
+                        Cell::new(format!("{availability} (stale)")).fg(Color::DarkGreen)
+                    }
                     Available => Cell::new(availability).fg(Color::Green),
                     Disabled => Cell::new(availability).fg(Color::DarkYellow),
                     RequiresKey => Cell::new(availability).fg(Color::Yellow),
@@ -177,6 +184,72 @@ impl List {
     }
 }
 
+/// Verify an API key and the scopes it has access to
+///
+/// Currently only supports OpenAI API keys. Makes requests which do not
+/// generate content (and so do not incur costs).
+#[derive(Default, Debug, Args)]
+#[command(after_long_help = VERIFY_KEY_AFTER_LONG_HELP)]
+struct VerifyKey {
+    /// Output the report as JSON or YAML
+    #[arg(long, short)]
+    r#as: Option<AsFormat>,
+}
+
+pub static VERIFY_KEY_AFTER_LONG_HELP: &str = cstr!(
+    "<bold><b>Examples</b></bold>
+  <dim># Check the OpenAI API key</dim>
+  <b>stencila models verify-key</b>
+
+  <dim># Output the report as JSON</dim>
+  <b>stencila models verify-key</b> <c>--as</c> <g>json</g>
+"
+);
+
+impl VerifyKey {
+    async fn run(self) -> Result<()> {
+        let report = models_openai::verify_key().await?;
+
+        if let Some(format) = self.r#as {
+            Code::new_from(format.into(), &report)?.to_stdout();
+            return Ok(());
+        }
+
+        let mut table = Tabulated::new();
+        table.set_header(["Scope", "Access"]);
+
+        let access = |access: &ScopeAccess| match access {
+            ScopeAccess::Allowed => Cell::new("allowed").fg(Color::Green),
+            ScopeAccess::Denied => Cell::new("denied").fg(Color::Red),
+            ScopeAccess::Unknown => Cell::new("unknown").fg(Color::Grey),
+        };
+
+        table.add_row([
+            Cell::new("key").add_attribute(Attribute::Bold),
+            if report.valid {
+                Cell::new("valid").fg(Color::Green)
+            } else {
+                Cell::new(report.error.as_deref().unwrap_or("invalid")).fg(Color::Red)
+            },
+        ]);
+        for (scope, value) in [
+            ("chat", &report.chat),
+            ("images", &report.images),
+            ("files", &report.files),
+            ("fine-tuning", &report.fine_tuning),
+        ] {
+            table.add_row([
+                Cell::new(scope).add_attribute(Attribute::Bold),
+                access(value),
+            ]);
+        }
+
+        table.to_stdout();
+
+        Ok(())
+    }
+}
+
 /// Run a model task
 ///
 /// Mainly intended for testing of model selection and routing.
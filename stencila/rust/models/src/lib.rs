@@ -1,32 +1,73 @@
 #![recursion_limit = "256"]
 
-use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+use std::{
+    cmp::Ordering,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
 
 use model::common::{
     eyre::{Result, bail},
-    futures::future::join_all,
+    futures::{future::join_all, join},
     itertools::Itertools,
+    once_cell::sync::Lazy,
+    serde_json,
+    tokio::time::sleep,
     tracing,
 };
 
+use model::{
+    CandidateSelection, CandidateSelector, CatalogConfig, ChatMemory, FirstSelector,
+    LongestSelector, MajorityVoteSelector, ModelSubstitution, ModelWarning, ModelsConfig,
+    RerankerSelector, RetryPolicy, Run, SemanticCache, SweepResult, TaskFingerprint, TaskPriority,
+    VectorIndex, registered_providers, schema::Timestamp,
+};
+
 pub use model::{
-    Model, ModelAvailability, ModelOutput, ModelOutputKind, ModelSpecification, ModelTask,
-    ModelType,
+    ArtifactChange, AttachmentExpansion, DeltaCallback, Model, ModelAvailability, ModelCatalog,
+    ModelHealth, ModelIO, ModelOutput, ModelOutputKind, ModelQuery, ModelSpecification, ModelTask,
+    ModelType, OutputDiff, ParameterGrid, PatchCallback, PatchStream, ProviderCapability,
+    ProviderRegistration, StalenessChecker, Sweep, TaskReport, ToolCall, ToolDefinition,
+    ToolHandler, attachment_preamble, citations_block, expand_attachment, register_provider,
+    select_attachment,
 };
 
 pub mod cli;
+pub mod tools;
 
-/// Get a list of available models
-pub async fn list() -> Vec<Arc<dyn Model>> {
-    let futures = (0..=6).map(|provider| async move {
+mod agent;
+pub use agent::{Agent, AgentRun, AgentStep, AgentToolResult};
+
+mod preferences;
+pub use preferences::ListPreferences;
+
+/// Get a catalog of the models from all providers
+///
+/// Includes the models of providers registered, by crates linked into this
+/// binary, using [`register_provider`], and of Stencila plugins (which run out
+/// of process and are the way to add providers without rebuilding).
+///
+/// Unlike [`list`], the catalog is not filtered by the user's [`ListPreferences`]
+/// and can be queried for models with particular capabilities e.g.
+///
+/// ```ignore
+/// catalog().await.find(&ModelQuery::new().input(ModelIO::Image).min_context(100_000))
+/// ```
+pub async fn catalog() -> ModelCatalog {
+    let futures = (0..=11).map(|provider| async move {
         let (provider, result) = match provider {
             0 => ("Anthropic", models_anthropic::list().await),
-            1 => ("Google", models_google::list().await),
-            2 => ("Mistral", models_mistral::list().await),
-            3 => ("Ollama", models_ollama::list().await),
-            4 => ("OpenAI", models_openai::list().await),
-            5 => ("Plugins", plugins::models::list().await),
-            6 => ("Stencila", models_stencila::list().await),
+            1 => ("Bedrock", models_bedrock::list().await),
+            2 => ("Google", models_google::list().await),
+            3 => ("Hugging Face", models_huggingface::list().await),
+            4 => ("llama.cpp", models_llama_cpp::list().await),
+            5 => ("Mistral", models_mistral::list().await),
+            6 => ("Ollama", models_ollama::list().await),
+            7 => ("OpenAI", models_openai::list().await),
+            8 => ("OpenAI compatible", models_openai_compatible::list().await),
+            9 => ("OpenRouter", models_openrouter::list().await),
+            10 => ("Plugins", plugins::models::list().await),
+            11 => ("Stencila", models_stencila::list().await),
             _ => return vec![],
         };
 
@@ -46,27 +87,40 @@ pub async fn list() -> Vec<Arc<dyn Model>> {
         }
     });
 
-    let list = join_all(futures).await.into_iter().flatten();
-
-    // Ensure that ids are unique, taking those with lower ranked type (higher
-    // preference): Local < Remote < Proxied. This avoids having
-    // proxied models clashing with remote models when user has both
-    // Stencila API key and other provider API keys set
-    let mut unique = HashMap::new();
-    for model in list {
-        unique
-            .entry(model.id())
-            .and_modify(|existing: &mut Arc<dyn Model>| {
-                if existing.r#type() > model.r#type() {
-                    *existing = model.clone();
-                }
+    let registered = registered_providers()
+        .into_iter()
+        .map(|(provider, factory)| async move {
+            factory().await.unwrap_or_else(|error| {
+                tracing::warn!("While listing {provider} models: {error}");
+                vec![]
             })
-            .or_insert(model);
-    }
+        });
+
+    let (builtin, registered) = join!(join_all(futures), join_all(registered));
 
+    // In local-only mode, models which do not run on this machine are excluded
+    let local_only = model::models_config().policy.is_local_only();
+    ModelCatalog::new(
+        builtin
+            .into_iter()
+            .chain(registered)
+            .flatten()
+            .filter(|model| !local_only || model.r#type().is_local()),
+    )
+}
+
+/// Get a list of available models
+///
+/// The list is filtered and ordered according to the user's [`ListPreferences`].
+pub async fn list() -> Vec<Arc<dyn Model>> {
+    sort(catalog().await.into_models(), &ListPreferences::load())
+}
+
+/// Sort models, and filter and order them according to the user's [`ListPreferences`]
+fn sort(models: Vec<Arc<dyn Model>>, preferences: &ListPreferences) -> Vec<Arc<dyn Model>> {
     // Sort by router/rest, then by descending quality score, and then by provider and model name
-    unique
-        .into_values()
+    let sorted = models
+        .into_iter()
         .sorted_by(|a, b| match (a.r#type(), b.r#type()) {
             (ModelType::Router, _) => Ordering::Less,
             (_, ModelType::Router) => Ordering::Greater,
@@ -81,16 +135,54 @@ pub async fn list() -> Vec<Arc<dyn Model>> {
                 order => order,
             },
         })
-        .collect_vec()
+        .collect_vec();
+
+    preferences.apply(sorted)
+}
+
+/// Check the health of all available models
+///
+/// Models are checked concurrently. Unavailable models are not probed.
+pub async fn health() -> Vec<ModelHealth> {
+    let models = list().await;
+    let futures = models.iter().map(|model| async move {
+        if model.is_available() {
+            model.health().await
+        } else {
+            ModelHealth::unprobed(model.id(), false)
+        }
+    });
+    join_all(futures).await
 }
 
 /// Select a model based on selection criteria of the `ModelParameters`
 #[tracing::instrument(skip_all)]
 pub async fn select(task: &ModelTask) -> Result<Arc<dyn Model>> {
-    tracing::trace!("Selecting a model for task");
+    let catalog = catalog().await;
+    let preferences = ListPreferences::load();
+    let models = sort(catalog.models().to_vec(), &preferences);
+    select_from(
+        task,
+        &catalog,
+        &models,
+        &preferences,
+        &model::models_config(),
+    )
+}
 
-    // Get the list models
-    let models = list().await;
+/// Select a model for a task from the catalog and the sorted list of models
+///
+/// Separate from [`select`] so that the providers are only listed, and the
+/// preferences only loaded, once when performing a task.
+#[tracing::instrument(skip_all)]
+fn select_from(
+    task: &ModelTask,
+    catalog: &ModelCatalog,
+    models: &[Arc<dyn Model>],
+    preferences: &ListPreferences,
+    config: &ModelsConfig,
+) -> Result<Arc<dyn Model>> {
+    tracing::trace!("Selecting a model for task");
 
     // Check that there is at least one model available so we can advise the user
     // that they might need to provide an API key or run a model locally
@@ -104,6 +196,24 @@ pub async fn select(task: &ModelTask) -> Result<Arc<dyn Model>> {
         bail!(message)
     }
 
+    // If the task pins a model, use it (regardless of list preferences) or error
+    if let Some(id) = &task.model_id {
+        let id = config.catalog.resolve_model_id(id);
+        return match catalog.get(&id) {
+            Some(model) if model.is_available() => Ok(model),
+            Some(model) => bail!(
+                "Pinned model `{id}` is not available ({})",
+                model.availability()
+            ),
+            None if config.policy.is_local_only() => {
+                bail!(
+                    "No local model with id `{id}` (remote models are excluded in local-only mode)"
+                )
+            }
+            None => bail!("No model with id `{id}`"),
+        };
+    }
+
     // If the task includes model ids, use the first model matching on of the
     // ids (there should normally only be one) and error if not found
     if let Some(model_ids) = task
@@ -111,13 +221,17 @@ pub async fn select(task: &ModelTask) -> Result<Arc<dyn Model>> {
         .as_ref()
         .and_then(|pars| pars.model_ids.as_ref())
     {
+        let model_ids = model_ids
+            .iter()
+            .map(|id| config.catalog.resolve_model_id(id))
+            .collect_vec();
         for model in models {
             if !model.is_available() {
                 continue;
             }
-            for id in model_ids {
+            for id in &model_ids {
                 if id == "*" || model.id().contains(id) {
-                    return Ok(model);
+                    return Ok(model.clone());
                 }
             }
         }
@@ -125,6 +239,14 @@ pub async fn select(task: &ModelTask) -> Result<Arc<dyn Model>> {
         bail!("No model with id matching '{}'", model_ids.join(","))
     }
 
+    // If the user has pinned a default model (which will be first in the list)
+    // and it supports the task then use it
+    if let Some(model) = models.first().filter(|model| {
+        model.is_available() && model.supports_task(task) && preferences.is_default(model.as_ref())
+    }) {
+        return Ok(model.clone());
+    }
+
     // If the task does not specify model ids and a model router is available
     // then use the first router
     if let Some(model) = models
@@ -134,29 +256,617 @@ pub async fn select(task: &ModelTask) -> Result<Arc<dyn Model>> {
         return Ok(model.clone());
     }
 
-    // Filter according to whether the task is supported
-    let mut models = models
-        .into_iter()
-        .filter(|model| {
-            if !model.is_available() || !model.supports_task(task) {
-                return false;
-            }
-            true
-        })
-        .collect_vec();
-
-    if models.is_empty() {
-        bail!("No AI models available that support this task")
+    // Use the first model which supports the task
+    match models
+        .iter()
+        .find(|model| model.is_available() && model.supports_task(task))
+    {
+        Some(model) => Ok(model.clone()),
+        None => bail!("No AI models available that support this task"),
     }
-
-    Ok(models.swap_remove(0))
 }
 
 /// Perform a model task
 #[tracing::instrument(skip_all)]
 pub async fn perform_task(task: ModelTask) -> Result<ModelOutput> {
     tracing::debug!("Performing model task");
+    perform(task, None, true).await
+}
+
+/// Perform a model task, streaming text as it is generated
+///
+/// As for [`perform_task`] but with `on_delta` called with each chunk of generated text.
+#[tracing::instrument(skip_all)]
+pub async fn perform_task_streaming(
+    task: ModelTask,
+    on_delta: &DeltaCallback,
+) -> Result<ModelOutput> {
+    tracing::debug!("Performing model task with streaming");
+    perform(task, Some(on_delta), true).await
+}
+
+/// Perform a model task, streaming generated text as patches to a node
+///
+/// As for [`perform_task_streaming`] but with each chunk of generated text converted
+/// into a patch, by the `stream`, and passed to `on_patch`. This allows the document
+/// containing the node to be updated live as the node's content is generated.
+///
+/// Because the final content of the output can differ from the streamed text
+/// (e.g. after continuation, validation, post-processing, or safety filtering), a
+/// final patch replacing the streamed text with the content of the output is
+/// passed to `on_patch` if necessary.
+#[tracing::instrument(skip_all)]
+pub async fn perform_task_patching(
+    task: ModelTask,
+    stream: PatchStream,
+    on_patch: Box<PatchCallback>,
+) -> Result<ModelOutput> {
+    tracing::debug!("Performing model task with patching");
+    let stream = Arc::new(stream);
+    let on_patch: Arc<PatchCallback> = Arc::from(on_patch);
+    let on_delta = {
+        let stream = stream.clone();
+        let on_patch = on_patch.clone();
+        move |chunk: &str| {
+            if let Some(patch) = stream.patch(chunk) {
+                on_patch(patch)
+            }
+        }
+    };
+    let output = perform(task, Some(&on_delta), true).await?;
+
+    if let Some(patch) = stream.reconcile(&output.content) {
+        on_patch(patch)
+    }
+
+    Ok(output)
+}
+
+/// Perform a model task again and diff the output against a previous output
+///
+/// The semantic cache is not consulted (although it is updated) so that the
+/// task is always regenerated. The diff can be recorded in provenance to show
+/// exactly what changed between reruns, or between versions of a model.
+#[tracing::instrument(skip_all)]
+pub async fn regenerate(
+    task: ModelTask,
+    previous: &ModelOutput,
+) -> Result<(ModelOutput, OutputDiff)> {
+    tracing::debug!("Regenerating model task");
+    let output = perform(task, None, false).await?;
+    let diff = model::diff_outputs(previous, &output);
+    Ok((output, diff))
+}
+
+/// The regeneration of a node whose output was derived from changed evidence
+#[derive(Debug)]
+pub struct StaleRegeneration {
+    /// The id of the node
+    pub node_id: String,
+
+    /// The changes to the artifacts that the previous output was derived from
+    pub changes: Vec<ArtifactChange>,
+
+    /// The new output and its diff against the previous output, or the error regenerating it
+    pub result: Result<(ModelOutput, OutputDiff)>,
+}
+
+/// Regenerate the outputs of nodes derived from workflow artifacts which have since changed
+///
+/// Each node is given as its id, the task it was generated for, and its output. Nodes
+/// whose outputs are current are skipped. The tasks of stale nodes are updated to
+/// use the current versions of the changed artifacts and regenerated concurrently,
+/// subject to the limits of the task queue (with batch priority unless the task
+/// specifies otherwise). Use [`StalenessChecker`] to only flag stale nodes.
+#[tracing::instrument(skip_all)]
+pub async fn regenerate_stale(
+    nodes: Vec<(String, ModelTask, ModelOutput)>,
+) -> Vec<StaleRegeneration> {
+    let mut checker = StalenessChecker::new();
+    let futures = nodes
+        .into_iter()
+        .filter_map(|(node_id, task, output)| {
+            let changes = checker.check(&output);
+            (!changes.is_empty()).then_some((node_id, task, output, changes))
+        })
+        .map(|(node_id, mut task, previous, changes)| async move {
+            tracing::debug!("Regenerating stale node `{node_id}`");
+            task.priority.get_or_insert(TaskPriority::Batch);
+            let result = match model::refresh_artifacts(&mut task, &changes) {
+                Ok(..) => regenerate(task, &previous).await,
+                Err(error) => Err(error),
+            };
+            StaleRegeneration {
+                node_id,
+                changes,
+                result,
+            }
+        });
+    join_all(futures).await
+}
+
+/// Perform a task across a grid of parameters
+///
+/// The task is performed once for each point in the grid, concurrently but
+/// subject to the limits of the task queue (with batch priority unless the task
+/// specifies otherwise), and without consulting the semantic cache. Failures are
+/// recorded in the results rather than failing the sweep. Use [`Sweep::to_markdown`]
+/// for a comparison table, or serialize the sweep to record the experiment.
+#[tracing::instrument(skip_all)]
+pub async fn sweep(task: ModelTask, grid: &ParameterGrid) -> Sweep {
+    tracing::debug!("Sweeping model task across parameter grid");
+
+    let futures = grid.points().into_iter().map(|point| {
+        let mut task = point.apply(&task);
+        task.priority.get_or_insert(TaskPriority::Batch);
+        async move {
+            let result = perform(task.clone(), None, false)
+                .await
+                .map_err(|error| error.to_string());
+            SweepResult::new(point, &task, result)
+        }
+    });
+
+    Sweep {
+        results: join_all(futures).await,
+    }
+}
+
+/// The semantic cache used when performing tasks, if any
+static SEMANTIC_CACHE: Lazy<RwLock<Option<Arc<SemanticCache>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Set the semantic cache used when performing tasks
+///
+/// Pass `None` to disable semantic caching (the default).
+pub fn set_semantic_cache(cache: Option<SemanticCache>) {
+    if let Ok(mut current) = SEMANTIC_CACHE.write() {
+        *current = cache.map(Arc::new);
+    }
+}
+
+/// Get the semantic cache used when performing tasks, if any
+pub fn semantic_cache() -> Option<Arc<SemanticCache>> {
+    SEMANTIC_CACHE.read().ok().and_then(|cache| cache.clone())
+}
+
+/// The local vector index used for retrieval, if any
+static VECTOR_INDEX: Lazy<RwLock<Option<Arc<VectorIndex>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Set the local vector index used for tasks with retrieval options
+///
+/// Pass `None` to disable retrieval (the default). Tasks with retrieval
+/// options are performed without retrieval if no index is set.
+pub fn set_vector_index(index: Option<VectorIndex>) {
+    if let Ok(mut current) = VECTOR_INDEX.write() {
+        *current = index.map(Arc::new);
+    }
+}
+
+/// Get the local vector index used for retrieval, if any
+pub fn vector_index() -> Option<Arc<VectorIndex>> {
+    VECTOR_INDEX.read().ok().and_then(|index| index.clone())
+}
+
+/// Perform a model task, streaming if `on_delta` is supplied
+///
+/// If `lookup` is false the semantic cache is not checked for an existing output.
+async fn perform(
+    mut task: ModelTask,
+    on_delta: Option<&DeltaCallback>,
+    lookup: bool,
+) -> Result<ModelOutput> {
+    // Semantic cache lookups and inserts use the task as supplied (i.e. before
+    // compression and format negotiation)
+    let cache = semantic_cache();
+    if lookup
+        && let Some(cache) = &cache
+        && let Some(mut output) = cache.get(&task)
+    {
+        if let Some(report) = output.report.as_mut() {
+            report.cached = true;
+        }
+        if let Some(on_delta) = on_delta {
+            on_delta(&output.content);
+        }
+        return Ok(output);
+    }
+    let original = cache.as_ref().map(|_| task.clone());
+
+    let config = model::models_config();
+    let started = Instant::now();
+    let start_time = Timestamp::now();
+
+    // Attestations and invocation records are of the task as supplied, so that
+    // they can be checked against it
+    let fingerprint = match TaskFingerprint::new(&task) {
+        Ok(fingerprint) => Some(fingerprint),
+        Err(error) if config.records.attestation.enabled => return Err(error),
+        Err(..) => None,
+    };
+
+    // Runs record the task as supplied, and the steps taken to perform it
+    let run = model::run_store().and_then(|store| match store.create() {
+        Ok(run) => Some(run),
+        Err(error) => {
+            tracing::warn!("While creating run: {error}");
+            None
+        }
+    });
+    if let Some(run) = &run
+        && let Err(error) = run.write_request(&task)
+    {
+        tracing::warn!("While writing request of run: {error}");
+    }
+    let log = |message: &str| {
+        if let Some(run) = &run {
+            run.log(message)
+        }
+    };
+
+    // The providers are listed, and preferences loaded, once for the selection
+    // of the model and of any auxiliary models (e.g. summarizers)
+    let catalog = catalog().await;
+    let preferences = ListPreferences::load();
+    let models = sort(catalog.models().to_vec(), &preferences);
+
+    let correction_warnings = model::select_attachments(&mut task).await?;
+    retrieve_context(&mut task)?;
+    if let Some(alias) = config.catalog.apply_aliases(&mut task) {
+        tracing::debug!("Resolved model alias `{alias}`");
+    }
+    let memory = task.memory.clone();
+    if let Some(memory) = &memory {
+        memory.apply(&mut task);
+    }
+    let specialized = model::specialize_task(&mut task)?;
+    let pinned = task.model_id.is_some();
+    let selected = select_from(&task, &catalog, &models, &preferences, &config)?;
+    let selected_id = selected.id();
+    let (model, deprecation_warning) =
+        check_deprecation(selected, pinned, &config.catalog, &models);
+    config.policy.check_local_only(model.as_ref())?;
+    let uncompressed_tokens = model::estimate_prompt_tokens(&task);
+    compress_prompt(&mut task, &models, &config).await?;
+    let truncated_tokens = uncompressed_tokens.saturating_sub(model::estimate_prompt_tokens(&task));
+    model::negotiate_format(&mut task, model.as_ref());
+    config.requests.apply(&mut task)?;
+    config.policy.check(model.as_ref(), &task)?;
+    log(&format!("Selected model `{}`", model.id()));
+
+    // Wait for the provider's limits to allow the request (the permit is held
+    // while the task is performed but released before any retry backoff)
+    let queue = model::task_queue();
+
+    let request_started = Instant::now();
+    let mut retries = 0;
+    let mut output = match on_delta {
+        // Streaming tasks are not retried because deltas may already have been emitted
+        Some(on_delta) => queue.perform(model.as_ref(), &task, Some(on_delta)).await?,
+        None => loop {
+            match queue.perform(model.as_ref(), &task, None).await {
+                Ok(output) => break output,
+                Err(error)
+                    if retries < config.retry.max_retries && RetryPolicy::is_retryable(&error) =>
+                {
+                    retries += 1;
+                    log(&format!("Attempt {retries} failed: {error}"));
+                    let backoff = config.retry.backoff(retries);
+                    tracing::warn!(
+                        "Task failed, retrying in {}ms (attempt {retries}): {error}",
+                        backoff.as_millis()
+                    );
+                    sleep(backoff).await;
+                }
+                Err(error) => {
+                    log(&format!("Task failed: {error}"));
+                    return Err(error);
+                }
+            }
+        },
+    };
+    log("Model responded");
+    model::record_latency(&model.id(), request_started.elapsed());
+
+    // Record the tokens of the request against the policy before any continuations
+    // (which are checked against the policy and record their own tokens). Composite
+    // models, such as ensembles, have already recorded the tokens of their requests.
+    if !model.is_composite() {
+        let tokens = output.usage.map_or_else(
+            || model::estimate_prompt_tokens(&task) + output.content.chars().count().div_ceil(4),
+            |usage| usage.prompt_tokens + usage.output_tokens,
+        );
+        config.policy.record(model.as_ref(), &task, tokens);
+    }
+
+    select_candidate(&task, &mut output, &models, &config).await?;
+    let (mut output, continuations) =
+        model::auto_continue(model.as_ref(), &task, output, on_delta, &config.policy).await?;
+    let language =
+        model::enforce_language(model.as_ref(), &task, &mut output, &config.policy).await?;
+    task.post_processing
+        .as_ref()
+        .unwrap_or(&config.outputs.post_processing)
+        .apply(&mut output);
+    let mut output =
+        model::enforce_validators(model.as_ref(), &task, output, &config.policy).await?;
+    if let Some(kind) = specialized {
+        model::finish_specialized_task(kind, &task, &mut output)?;
+    }
+    // Filtered after finishing so that parts are checked and blocking does not
+    // break the parsing of specialized outputs, and before the audit record is persisted
+    let safety = config.outputs.safety.apply(&mut output)?;
+    output.link_citations(&task);
+    output.derived_from = task.derived_from.clone();
+    output.warnings.extend(correction_warnings);
+    output.warnings.extend(deprecation_warning);
+    persist_audit(&task, &mut output);
+    if let Some(memory) = memory {
+        output.memory = Some(update_memory(memory, &task, &output, &models, &config).await);
+    }
+
+    // Use the token usage reported by the provider, if any, rather than estimates
+    let (prompt_tokens, output_tokens) = match (&output.usage, &output.kind) {
+        (Some(usage), ..) => (usage.prompt_tokens, usage.output_tokens),
+        (None, ModelOutputKind::Text) => (
+            model::estimate_prompt_tokens(&task),
+            output.content.chars().count().div_ceil(4),
+        ),
+        (None, ..) => (model::estimate_prompt_tokens(&task), 0),
+    };
+    let mut report = TaskReport::new(&model.id(), &output.warnings)
+        .latency(started.elapsed())
+        .usage(prompt_tokens, output_tokens, model.cost_per_mtok());
+    report.cached_tokens = output
+        .usage
+        .map(|usage| usage.cached_tokens)
+        .unwrap_or_default();
+    report.retries = retries;
+    report.user = task.user.clone();
+    report.run = run.as_ref().map(|run| run.id.clone());
+    report.truncated_tokens = truncated_tokens;
+    report.continuations = continuations;
+    report.language = language;
+    report.safety = safety;
+    if model.id() != selected_id {
+        report.substitutions.push(ModelSubstitution {
+            from: selected_id,
+            to: model.id(),
+        });
+    }
+    output.report = Some(report);
+    output.complete_invocation(model.as_ref(), &task, fingerprint.as_ref(), start_time);
+    if config.records.attestation.enabled
+        && let Some(fingerprint) = fingerprint
+    {
+        output.attestation = model::attest(fingerprint.as_str(), &model.id(), &output.content)?;
+    }
+    if let Some(run) = &run {
+        persist_run(run, &output);
+    }
+
+    if let (Some(cache), Some(original)) = (cache, original) {
+        cache.insert(&original, &output);
+    }
+
+    Ok(output)
+}
+
+/// Update the memory of a chat session with the facts learned from a task
+///
+/// Errors are logged and the memory returned unchanged rather than failing the task.
+async fn update_memory(
+    mut memory: ChatMemory,
+    task: &ModelTask,
+    output: &ModelOutput,
+    models: &[Arc<dyn Model>],
+    config: &ModelsConfig,
+) -> ChatMemory {
+    let updater = match find_available(&memory.model, "memory", models, &config.catalog) {
+        Ok(updater) => updater,
+        Err(error) => {
+            tracing::warn!("While updating chat memory: {error}");
+            return memory;
+        }
+    };
+
+    if let Err(error) = memory
+        .update(task, output, updater.as_ref(), &config.policy)
+        .await
+    {
+        tracing::warn!("While updating chat memory: {error}");
+    }
+
+    memory
+}
+
+/// Write the output, and its audit record and attestation, to a run
+///
+/// Errors are logged rather than failing the task.
+fn persist_run(run: &Run, output: &ModelOutput) {
+    let result = (|| -> Result<()> {
+        run.write_response(output)?;
+        if let Some(audit) = &output.audit {
+            run.write_artifact("audit.json", &serde_json::to_vec_pretty(audit)?)?;
+        }
+        if let Some(attestation) = &output.attestation {
+            run.write_artifact("attestation.json", &serde_json::to_vec_pretty(attestation)?)?;
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(..) => run.log("Wrote response and artifacts"),
+        Err(error) => tracing::warn!("While writing response of run `{}`: {error}", run.id),
+    }
+}
 
-    let model = select(&task).await?;
-    model.perform_task(&task).await
+/// Write the audit record of an output, if any, to the audit log directory
+///
+/// Errors are logged rather than failing the task.
+fn persist_audit(task: &ModelTask, output: &mut ModelOutput) {
+    let Some(audit) = output.audit.as_mut() else {
+        return;
+    };
+
+    audit.task_fingerprint = TaskFingerprint::new(task).ok();
+    match audit.persist() {
+        Ok(path) => tracing::debug!("Wrote audit record to `{}`", path.display()),
+        Err(error) => tracing::error!("While writing audit record: {error}"),
+    }
+}
+
+/// Check whether a model is deprecated, substituting it if configured to do so
+///
+/// Returns the model to use and a warning if the selected model is deprecated.
+/// The model is only substituted if the `substitute-deprecated` option of the
+/// `[catalog]` config is set and its replacement is available. Models pinned using the task's `model_id`
+/// are never substituted, only warned about.
+fn check_deprecation(
+    model: Arc<dyn Model>,
+    pinned: bool,
+    catalog: &CatalogConfig,
+    models: &[Arc<dyn Model>],
+) -> (Arc<dyn Model>, Option<ModelWarning>) {
+    let id = model.id();
+    let Some(deprecation) = catalog.deprecation(&id) else {
+        return (model, None);
+    };
+
+    if !pinned
+        && catalog.substitute_deprecated
+        && let Some(replacement) = &deprecation.replacement
+    {
+        match find_available(replacement, "replacement", models, catalog) {
+            Ok(substitute) => {
+                let warning = ModelWarning::deprecated(format!(
+                    "Model `{id}` is deprecated so was substituted with `{}`",
+                    substitute.id()
+                ));
+                return (substitute, Some(warning));
+            }
+            Err(error) => tracing::debug!("Unable to substitute `{id}`: {error}"),
+        }
+    }
+
+    (model, Some(deprecation.warning(&id)))
+}
+
+/// Select one of the candidates of an output using the task's selection strategy
+///
+/// Does nothing if the model has already selected a candidate (e.g. an ensemble).
+async fn select_candidate(
+    task: &ModelTask,
+    output: &mut ModelOutput,
+    models: &[Arc<dyn Model>],
+    config: &ModelsConfig,
+) -> Result<()> {
+    if output.candidates.len() < 2 || output.selected_candidate.is_some() {
+        return Ok(());
+    }
+
+    let selector: Box<dyn CandidateSelector> =
+        match task.candidate_selection.clone().unwrap_or_default() {
+            CandidateSelection::First => Box::new(FirstSelector),
+            CandidateSelection::Longest => Box::new(LongestSelector),
+            CandidateSelection::MajorityVote => Box::new(MajorityVoteSelector),
+            CandidateSelection::Reranker(id) => Box::new(RerankerSelector {
+                model: find_available(&id, "reranker", models, &config.catalog)?,
+                policy: config.policy.clone(),
+            }),
+        };
+
+    output.select_candidate(task, selector.as_ref()).await
+}
+
+/// Add excerpts of attachments to the prompt of a task if it has retrieval options
+fn retrieve_context(task: &mut ModelTask) -> Result<()> {
+    let Some(options) = task.retrieval.clone() else {
+        return Ok(());
+    };
+
+    let Some(index) = vector_index() else {
+        tracing::debug!("No vector index set, performing task without retrieval");
+        return Ok(());
+    };
+
+    model::retrieve_context(task, &options, &index)?;
+
+    Ok(())
+}
+
+/// Compress the prompt of a task if it has compression options
+async fn compress_prompt(
+    task: &mut ModelTask,
+    models: &[Arc<dyn Model>],
+    config: &ModelsConfig,
+) -> Result<()> {
+    let Some(options) = task.compression.clone() else {
+        return Ok(());
+    };
+
+    let summarizer = find_available(&options.model, "summarizer", models, &config.catalog)?;
+    model::compress_prompt(task, &options, summarizer.as_ref(), &config.policy).await?;
+
+    Ok(())
+}
+
+/// Find the first available model with an id matching a pattern
+///
+/// Used for auxiliary models (e.g. rerankers and summarizers) with the
+/// `purpose` used in the error message if no model is found.
+fn find_available(
+    id: &str,
+    purpose: &str,
+    models: &[Arc<dyn Model>],
+    catalog: &CatalogConfig,
+) -> Result<Arc<dyn Model>> {
+    let id = &catalog.resolve_model_id(id);
+    match models
+        .iter()
+        .find(|model| model.is_available() && model.id().contains(id))
+    {
+        Some(model) => Ok(model.clone()),
+        None => bail!("No {purpose} model with id matching '{id}'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use model::{ModelOutput, ModelTask, common::async_trait::async_trait};
+
+    use super::*;
+
+    struct Stub(&'static str);
+
+    #[async_trait]
+    impl Model for Stub {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    #[test]
+    fn pinned_models_not_substituted() -> Result<()> {
+        let config = CatalogConfig {
+            substitute_deprecated: true,
+            ..Default::default()
+        };
+        let models: Vec<Arc<dyn Model>> = vec![Arc::new(Stub("openai/gpt-4o"))];
+        let deprecated = Arc::new(Stub("openai/gpt-4-vision-preview"));
+
+        let (model, ..) = check_deprecation(deprecated.clone(), false, &config, &models);
+        assert_eq!(model.id(), "openai/gpt-4o");
+
+        let (model, warning) = check_deprecation(deprecated, true, &config, &models);
+        assert_eq!(model.id(), "openai/gpt-4-vision-preview");
+        let Some(warning) = warning else {
+            bail!("expected warning")
+        };
+        assert!(warning.message.contains("was retired"));
+
+        Ok(())
+    }
 }
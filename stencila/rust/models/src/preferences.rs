@@ -0,0 +1,303 @@
+use std::{
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use dirs::{DirType, get_app_dir};
+use model::{
+    Model,
+    common::{
+        eyre::Result, glob::Pattern, itertools::Itertools, once_cell::sync::Lazy,
+        serde::Deserialize, toml, tracing,
+    },
+};
+
+/// User preferences for the models that are listed
+///
+/// Read from the `[list]` table of the `models.toml` file in the Stencila
+/// config directory, with each field able to be overridden by a
+/// comma separated list in the corresponding environment variable:
+///
+/// - `STENCILA_MODELS_INCLUDE`
+/// - `STENCILA_MODELS_EXCLUDE`
+/// - `STENCILA_MODELS_DEFAULT`
+/// - `STENCILA_MODELS_ORDER`
+///
+/// All patterns are globs matched against model ids e.g. `openai/*-preview`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, crate = "model::common::serde")]
+pub struct ListPreferences {
+    /// Only list models with an id matching one of these patterns
+    ///
+    /// If empty, all models are included.
+    pub include: Vec<String>,
+
+    /// Do not list models with an id matching any of these patterns
+    pub exclude: Vec<String>,
+
+    /// The id pattern of the model to use by default
+    ///
+    /// The first model matching this pattern is placed first in the list
+    /// and is selected for tasks that do not specify a model.
+    pub default: Option<String>,
+
+    /// Place models matching these patterns first, in the order of the patterns
+    pub order: Vec<String>,
+}
+
+/// The preferences last read from a config file
+///
+/// Avoids reading and parsing the config file each time models are listed or
+/// selected. The file is read again if its modification time changes.
+static CACHE: Lazy<Mutex<Option<CachedPreferences>>> = Lazy::new(Mutex::default);
+
+/// Preferences read from a config file, with its path and modification time
+struct CachedPreferences {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    prefs: ListPreferences,
+}
+
+/// The contents of the `models.toml` config file
+#[derive(Default, Deserialize)]
+#[serde(default, crate = "model::common::serde")]
+struct ConfigFile {
+    list: ListPreferences,
+}
+
+impl ListPreferences {
+    /// Load preferences from the config file and environment variables
+    ///
+    /// Errors reading the config file are logged and defaults used instead.
+    pub fn load() -> Self {
+        let mut prefs = match Self::read_file() {
+            Ok(prefs) => prefs,
+            Err(error) => {
+                tracing::warn!("Unable to read models config file: {error}");
+                Self::default()
+            }
+        };
+
+        let list = |name: &str| {
+            env::var(name).ok().map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(String::from)
+                    .collect_vec()
+            })
+        };
+
+        if let Some(include) = list("STENCILA_MODELS_INCLUDE") {
+            prefs.include = include;
+        }
+        if let Some(exclude) = list("STENCILA_MODELS_EXCLUDE") {
+            prefs.exclude = exclude;
+        }
+        if let Ok(default) = env::var("STENCILA_MODELS_DEFAULT") {
+            let default = default.trim();
+            prefs.default = (!default.is_empty()).then(|| default.to_string());
+        }
+        if let Some(order) = list("STENCILA_MODELS_ORDER") {
+            prefs.order = order;
+        }
+
+        prefs
+    }
+
+    /// Read preferences from the config file, if it exists
+    fn read_file() -> Result<Self> {
+        Self::read_path(&get_app_dir(DirType::Config, false)?.join("models.toml"))
+    }
+
+    /// Read preferences from a config file, if it exists
+    ///
+    /// Uses the cached preferences if the file has not been modified since last read.
+    fn read_path(path: &Path) -> Result<Self> {
+        let modified = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        if let Ok(cache) = CACHE.lock()
+            && let Some(cached) = cache.as_ref()
+            && cached.path == path
+            && cached.modified == modified
+        {
+            return Ok(cached.prefs.clone());
+        }
+
+        let prefs = if path.exists() {
+            let config: ConfigFile = toml::from_str(&read_to_string(path)?)?;
+            config.list
+        } else {
+            Self::default()
+        };
+
+        if let Ok(mut cache) = CACHE.lock() {
+            *cache = Some(CachedPreferences {
+                path: path.to_path_buf(),
+                modified,
+                prefs: prefs.clone(),
+            });
+        }
+
+        Ok(prefs)
+    }
+
+    /// Apply the preferences to a list of models
+    ///
+    /// Filters out models that are not included, or are excluded, and then
+    /// moves the default model, followed by models matching the order patterns, to the front.
+    /// The relative order of all other models is preserved.
+    pub fn apply(&self, models: Vec<Arc<dyn Model>>) -> Vec<Arc<dyn Model>> {
+        let include = patterns(&self.include);
+        let exclude = patterns(&self.exclude);
+        let order = patterns(&self.order);
+        let default = self.default.as_deref().and_then(pattern);
+
+        let models = models.into_iter().filter(|model| {
+            let id = model.id();
+            (include.is_empty() || include.iter().any(|pattern| pattern.matches(&id)))
+                && !exclude.iter().any(|pattern| pattern.matches(&id))
+        });
+
+        let mut default_found = false;
+        models
+            .map(|model| {
+                let id = model.id();
+                let rank = if !default_found
+                    && default.as_ref().is_some_and(|pattern| pattern.matches(&id))
+                {
+                    default_found = true;
+                    0
+                } else {
+                    order
+                        .iter()
+                        .position(|pattern| pattern.matches(&id))
+                        .map_or(usize::MAX, |index| index + 1)
+                };
+                (rank, model)
+            })
+            .sorted_by_key(|(rank, ..)| *rank)
+            .map(|(.., model)| model)
+            .collect()
+    }
+
+    /// Whether a model is the pinned default model
+    pub fn is_default(&self, model: &dyn Model) -> bool {
+        self.default
+            .as_deref()
+            .and_then(pattern)
+            .is_some_and(|pattern| pattern.matches(&model.id()))
+    }
+}
+
+/// Parse a glob pattern, logging and ignoring it if invalid
+fn pattern(pattern: &str) -> Option<Pattern> {
+    match Pattern::new(pattern) {
+        Ok(pattern) => Some(pattern),
+        Err(error) => {
+            tracing::warn!("Invalid model id pattern `{pattern}`: {error}");
+            None
+        }
+    }
+}
+
+/// Parse a list of glob patterns
+fn patterns(patterns: &[String]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|string| pattern(string))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{File, write},
+        time::Duration,
+    };
+
+    use model::{
+        ModelOutput, ModelTask,
+        common::{async_trait::async_trait, tempfile::tempdir},
+    };
+
+    use super::*;
+
+    struct TestModel(&'static str);
+
+    #[async_trait]
+    impl Model for TestModel {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+
+        async fn perform_task(&self, _task: &ModelTask) -> Result<ModelOutput> {
+            ModelOutput::empty(self)
+        }
+    }
+
+    #[test]
+    fn loads_and_applies() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("models.toml");
+        write(
+            &path,
+            r#"
+[list]
+exclude = ["openai/*-preview"]
+default = "anthropic/*"
+order = ["google/*"]
+"#,
+        )?;
+
+        let prefs = ListPreferences::read_path(&path)?;
+        assert_eq!(prefs.exclude, vec!["openai/*-preview"]);
+
+        let models: Vec<Arc<dyn Model>> = vec![
+            Arc::new(TestModel("openai/gpt-4o")),
+            Arc::new(TestModel("openai/gpt-4.5-preview")),
+            Arc::new(TestModel("google/gemini-2.5-pro")),
+            Arc::new(TestModel("anthropic/claude-sonnet-4-0")),
+        ];
+        let ids = prefs
+            .apply(models)
+            .iter()
+            .map(|model| model.id())
+            .collect_vec();
+        assert_eq!(
+            ids,
+            vec![
+                "anthropic/claude-sonnet-4-0",
+                "google/gemini-2.5-pro",
+                "openai/gpt-4o"
+            ]
+        );
+        assert!(prefs.is_default(&TestModel("anthropic/claude-opus-4-1")));
+
+        // Cached until the file is modified
+        write(&path, "[list]\ninclude = [\"openai/*\"]\n")?;
+        let modified = path.metadata()?.modified()?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(modified + Duration::from_secs(10))?;
+        let prefs = ListPreferences::read_path(&path)?;
+        assert_eq!(prefs.include, vec!["openai/*"]);
+        assert!(prefs.exclude.is_empty());
+
+        assert!(
+            ListPreferences::read_path(&dir.path().join("missing.toml"))?
+                .include
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
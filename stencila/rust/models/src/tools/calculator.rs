@@ -0,0 +1,683 @@
+use std::{fmt, iter::Peekable, str::Chars};
+
+use model::{
+    ToolDefinition, ToolHandler,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail, eyre},
+        serde_json::{self, Value, json},
+    },
+};
+
+/// The base units of each dimension, in the order of [`Dims`]
+const BASE_UNITS: [&str; 3] = ["m", "kg", "s"];
+
+/// The exponents of length, mass and time of a quantity
+type Dims = [i32; 3];
+
+/// The maximum depth of nested parentheses and function calls in an expression
+const MAX_DEPTH: usize = 64;
+
+/// A dimensionless quantity
+const NONE: Dims = [0, 0, 0];
+
+/// A length
+const LENGTH: Dims = [1, 0, 0];
+
+/// A mass
+const MASS: Dims = [0, 1, 0];
+
+/// A time
+const TIME: Dims = [0, 0, 1];
+
+/// The units recognized by the calculator, with their value in base units
+const UNITS: &[(&[&str], f64, Dims)] = &[
+    (&["m", "meter", "meters", "metre", "metres"], 1.0, LENGTH),
+    (
+        &["km", "kilometer", "kilometers", "kilometre", "kilometres"],
+        1e3,
+        LENGTH,
+    ),
+    (
+        &[
+            "cm",
+            "centimeter",
+            "centimeters",
+            "centimetre",
+            "centimetres",
+        ],
+        1e-2,
+        LENGTH,
+    ),
+    (
+        &[
+            "mm",
+            "millimeter",
+            "millimeters",
+            "millimetre",
+            "millimetres",
+        ],
+        1e-3,
+        LENGTH,
+    ),
+    (&["ft", "foot", "feet"], 0.3048, LENGTH),
+    (&["mi", "mile", "miles"], 1609.344, LENGTH),
+    (&["nmi"], 1852.0, LENGTH),
+    (&["ha", "hectare", "hectares"], 1e4, [2, 0, 0]),
+    (&["kg", "kilogram", "kilograms"], 1.0, MASS),
+    (&["g", "gram", "grams"], 1e-3, MASS),
+    (&["tonne", "tonnes"], 1e3, MASS),
+    (&["s", "sec", "second", "seconds"], 1.0, TIME),
+    (&["min", "minute", "minutes"], 60.0, TIME),
+    (&["h", "hr", "hour", "hours"], 3600.0, TIME),
+    (&["d", "day", "days"], 86400.0, TIME),
+    (&["wk", "week", "weeks"], 604800.0, TIME),
+    // Julian year, as used in astronomy and for rates of change
+    (&["yr", "year", "years"], 31557600.0, TIME),
+    (&["%", "percent"], 1e-2, NONE),
+];
+
+/// A number with dimensions
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quantity {
+    /// The value in base units
+    value: f64,
+
+    /// The dimensions of the quantity
+    dims: Dims,
+}
+
+impl Quantity {
+    fn number(value: f64) -> Self {
+        Self { value, dims: NONE }
+    }
+
+    fn add(self, other: Self, sign: f64) -> Result<Self> {
+        if self.dims != other.dims {
+            bail!(
+                "cannot {} quantities in {} and {}",
+                if sign > 0.0 { "add" } else { "subtract" },
+                unit_of(self.dims),
+                unit_of(other.dims)
+            )
+        }
+        Ok(Self {
+            value: self.value + sign * other.value,
+            dims: self.dims,
+        })
+    }
+
+    fn mul(self, other: Self) -> Result<Self> {
+        Ok(Self {
+            value: self.value * other.value,
+            dims: combine(self.dims, other.dims, i32::checked_add)?,
+        })
+    }
+
+    fn div(self, other: Self) -> Result<Self> {
+        if other.value == 0.0 {
+            bail!("division by zero")
+        }
+        Ok(Self {
+            value: self.value / other.value,
+            dims: combine(self.dims, other.dims, i32::checked_sub)?,
+        })
+    }
+
+    fn pow(self, exponent: f64) -> Result<Self> {
+        if self.dims != NONE && exponent.fract() != 0.0 {
+            bail!("quantities with units can only be raised to integer powers")
+        }
+        let dims = if self.dims == NONE {
+            NONE
+        } else {
+            if exponent.abs() > i32::MAX as f64 {
+                bail!("exponent is too large for a quantity with units")
+            }
+            combine(self.dims, [exponent as i32; 3], i32::checked_mul)?
+        };
+        Ok(Self {
+            value: self.value.powf(exponent),
+            dims,
+        })
+    }
+
+    /// Ensure that the quantity is dimensionless, e.g. for the argument of `ln`
+    fn dimensionless(self, context: &str) -> Result<f64> {
+        if self.dims != NONE {
+            bail!(
+                "{context} requires a dimensionless argument, got {}",
+                unit_of(self.dims)
+            )
+        }
+        Ok(self.value)
+    }
+}
+
+/// The result of a calculation
+#[derive(Debug, Clone, PartialEq)]
+struct Answer {
+    /// The numeric value, in `unit`
+    value: f64,
+
+    /// The unit of the value (empty if dimensionless)
+    unit: String,
+}
+
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.unit.is_empty() {
+            write!(f, "{}", self.value)
+        } else {
+            write!(f, "{} {}", self.value, self.unit)
+        }
+    }
+}
+
+/// A token in an expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+/// Split an expression into tokens
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = expr.chars().peekable();
+
+    while let Some(&char) = chars.peek() {
+        if char.is_whitespace() {
+            chars.next();
+        } else if char.is_ascii_digit() || char == '.' {
+            let mut number = String::new();
+            while let Some(&char) = chars.peek() {
+                if char.is_ascii_digit() || char == '.' {
+                    number.push(char);
+                } else if char == '_' {
+                    // Allow digit separators e.g. 1_000
+                } else if (char == 'e' || char == 'E') && !number.contains(['e', 'E']) {
+                    // Only treat as an exponent if followed by a digit or sign and digit
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    let next = lookahead.next();
+                    let after = lookahead.next();
+                    let is_exponent = match next {
+                        Some(digit) if digit.is_ascii_digit() => true,
+                        Some('+' | '-') => after.is_some_and(|digit| digit.is_ascii_digit()),
+                        _ => false,
+                    };
+                    if !is_exponent {
+                        break;
+                    }
+                    number.push(char);
+                    chars.next();
+                    if let Some(&sign @ ('+' | '-')) = chars.peek() {
+                        number.push(sign);
+                        chars.next();
+                    }
+                    continue;
+                } else {
+                    break;
+                }
+                chars.next();
+            }
+            let value = number
+                .parse()
+                .map_err(|_| eyre!("invalid number `{number}`"))?;
+            tokens.push(Token::Number(value));
+        } else if char.is_alphabetic() || char == '_' || char == '%' {
+            let mut ident = String::new();
+            while let Some(&char) = chars.peek() {
+                if char.is_alphanumeric() || char == '_' || char == '%' {
+                    ident.push(char);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Ident(ident));
+        } else if "+-*/^(),×÷·".contains(char) {
+            tokens.push(Token::Symbol(match char {
+                '×' | '·' => '*',
+                '÷' => '/',
+                _ => char,
+            }));
+            chars.next();
+        } else {
+            bail!("unexpected character `{char}`")
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Combine the dimensions of two quantities, failing if any exponent overflows
+fn combine(first: Dims, second: Dims, op: fn(i32, i32) -> Option<i32>) -> Result<Dims> {
+    let mut dims = NONE;
+    for index in 0..dims.len() {
+        dims[index] = op(first[index], second[index])
+            .ok_or_else(|| eyre!("exponent of units is too large"))?;
+    }
+    Ok(dims)
+}
+
+/// A recursive descent parser and evaluator of expressions
+///
+/// Juxtaposition (e.g. `0.4 m`) binds more tightly than `*` and `/` so that
+/// `10 m / 2 s` is `(10 m) / (2 s)`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_conversion(&self) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == "to" || ident == "in")
+    }
+
+    /// expr := term (('+' | '-') term)*
+    ///
+    /// Expressions are nested within parentheses and function calls, so their
+    /// depth is limited to avoid overflowing the stack.
+    fn expr(&mut self) -> Result<Quantity> {
+        if self.depth >= MAX_DEPTH {
+            bail!("expression is nested more than {MAX_DEPTH} levels deep")
+        }
+        self.depth += 1;
+        let result = self.terms();
+        self.depth -= 1;
+        result
+    }
+
+    fn terms(&mut self) -> Result<Quantity> {
+        let mut quantity = self.term()?;
+        loop {
+            if self.eat('+') {
+                quantity = quantity.add(self.term()?, 1.0)?;
+            } else if self.eat('-') {
+                quantity = quantity.add(self.term()?, -1.0)?;
+            } else {
+                return Ok(quantity);
+            }
+        }
+    }
+
+    /// term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Quantity> {
+        let mut quantity = self.unary()?;
+        loop {
+            if self.eat('*') {
+                quantity = quantity.mul(self.unary()?)?;
+            } else if self.eat('/') {
+                quantity = quantity.div(self.unary()?)?;
+            } else {
+                return Ok(quantity);
+            }
+        }
+    }
+
+    /// unary := ('-' | '+')* implicit
+    fn unary(&mut self) -> Result<Quantity> {
+        let mut negative = false;
+        loop {
+            if self.eat('-') {
+                negative = !negative;
+            } else if !self.eat('+') {
+                break;
+            }
+        }
+        let quantity = self.implicit()?;
+        Ok(if negative {
+            Quantity {
+                value: -quantity.value,
+                ..quantity
+            }
+        } else {
+            quantity
+        })
+    }
+
+    /// implicit := power (power)* where each subsequent power starts with a unit
+    fn implicit(&mut self) -> Result<Quantity> {
+        let mut quantity = self.power()?;
+        while matches!(self.peek(), Some(Token::Ident(..))) && !self.is_conversion() {
+            quantity = quantity.mul(self.power()?)?;
+        }
+        Ok(quantity)
+    }
+
+    /// power := atom ('^' ('-')? atom)?
+    fn power(&mut self) -> Result<Quantity> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            let negative = self.eat('-');
+            let exponent = self.atom()?.dimensionless("an exponent")?;
+            base.pow(if negative { -exponent } else { exponent })
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// atom := number | unit | constant | function '(' expr (',' expr)* ')' | '(' expr ')'
+    fn atom(&mut self) -> Result<Quantity> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Quantity::number(value)),
+            Some(Token::Symbol('(')) => {
+                let quantity = self.expr()?;
+                if !self.eat(')') {
+                    bail!("expected `)`")
+                }
+                Ok(quantity)
+            }
+            Some(Token::Ident(ident)) => {
+                if self.peek() == Some(&Token::Symbol('(')) {
+                    return self.function(&ident);
+                }
+                match ident.as_str() {
+                    "pi" => return Ok(Quantity::number(std::f64::consts::PI)),
+                    "e" => return Ok(Quantity::number(std::f64::consts::E)),
+                    _ => {}
+                }
+                unit(&ident)
+            }
+            Some(Token::Symbol(symbol)) => bail!("unexpected `{symbol}`"),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+
+    /// Evaluate a function call
+    fn function(&mut self, name: &str) -> Result<Quantity> {
+        self.eat('(');
+        let mut args = vec![self.expr()?];
+        while self.eat(',') {
+            args.push(self.expr()?);
+        }
+        if !self.eat(')') {
+            bail!("expected `)` after arguments of `{name}`")
+        }
+
+        let arity = |expected: usize| -> Result<()> {
+            if args.len() != expected {
+                bail!(
+                    "`{name}` expects {expected} argument(s), got {}",
+                    args.len()
+                )
+            }
+            Ok(())
+        };
+
+        match name {
+            "sqrt" => {
+                arity(1)?;
+                let arg = args[0];
+                if arg.dims.iter().any(|dim| dim % 2 != 0) {
+                    bail!(
+                        "cannot take the square root of a quantity in {}",
+                        unit_of(arg.dims)
+                    )
+                }
+                Ok(Quantity {
+                    value: arg.value.sqrt(),
+                    dims: arg.dims.map(|dim| dim / 2),
+                })
+            }
+            "abs" => {
+                arity(1)?;
+                Ok(Quantity {
+                    value: args[0].value.abs(),
+                    ..args[0]
+                })
+            }
+            "min" | "max" => {
+                let mut result = args[0];
+                for arg in &args[1..] {
+                    if arg.dims != result.dims {
+                        bail!("arguments of `{name}` must have the same units")
+                    }
+                    if (name == "min") == (arg.value < result.value) {
+                        result = *arg;
+                    }
+                }
+                Ok(result)
+            }
+            "ln" | "log10" | "exp" => {
+                arity(1)?;
+                let value = args[0].dimensionless(&format!("`{name}`"))?;
+                Ok(Quantity::number(match name {
+                    "ln" => value.ln(),
+                    "log10" => value.log10(),
+                    _ => value.exp(),
+                }))
+            }
+            _ => bail!("unknown function `{name}`"),
+        }
+    }
+}
+
+/// Look up a unit by name
+fn unit(name: &str) -> Result<Quantity> {
+    UNITS
+        .iter()
+        .find(|(names, ..)| names.contains(&name))
+        .map(|(.., value, dims)| Quantity {
+            value: *value,
+            dims: *dims,
+        })
+        .ok_or_else(|| eyre!("unknown unit or identifier `{name}`"))
+}
+
+/// Format dimensions as a unit in base units e.g. `m/s`
+fn unit_of(dims: Dims) -> String {
+    let part = |exponent: i32, unit: &str| {
+        if exponent == 1 {
+            unit.to_string()
+        } else {
+            format!("{unit}^{exponent}")
+        }
+    };
+
+    let numerator: Vec<String> = (0..3)
+        .filter(|&index| dims[index] > 0)
+        .map(|index| part(dims[index], BASE_UNITS[index]))
+        .collect();
+    let denominator: Vec<String> = (0..3)
+        .filter(|&index| dims[index] < 0)
+        .map(|index| part(-dims[index], BASE_UNITS[index]))
+        .collect();
+
+    match (numerator.is_empty(), denominator.is_empty()) {
+        (true, true) => String::new(),
+        (false, true) => numerator.join("*"),
+        (true, false) => format!("1/{}", denominator.join("/")),
+        (false, false) => format!("{}/{}", numerator.join("*"), denominator.join("/")),
+    }
+}
+
+/// Round a value to 12 significant digits to hide floating point noise
+fn round(value: f64) -> f64 {
+    format!("{value:.11e}").parse().unwrap_or(value)
+}
+
+/// Evaluate an expression, optionally with a trailing conversion e.g. `to m/yr`
+fn calculate(expression: &str) -> Result<Answer> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        depth: 0,
+    };
+
+    let quantity = parser.expr()?;
+
+    if parser.is_conversion() {
+        parser.next();
+        let target = parser.expr()?;
+        if parser.peek().is_some() {
+            bail!("unexpected tokens after conversion unit")
+        }
+        if target.dims != quantity.dims {
+            bail!(
+                "cannot convert a quantity in {} to {}",
+                unit_of(quantity.dims),
+                unit_of(target.dims)
+            )
+        }
+
+        let unit = expression
+            .split_whitespace()
+            .skip_while(|word| *word != "to" && *word != "in")
+            .skip(1)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        return Ok(Answer {
+            value: round(quantity.value / target.value),
+            unit,
+        });
+    }
+
+    if let Some(token) = parser.peek() {
+        bail!("unexpected {token:?}")
+    }
+
+    Ok(Answer {
+        value: round(quantity.value),
+        unit: unit_of(quantity.dims),
+    })
+}
+
+/// A tool for performing calculations with units
+///
+/// Lets models get numbers in generated text (e.g. the total shoreline change
+/// over a period given a rate in m/yr) from deterministic computation rather than
+/// their own arithmetic. Units are checked, so adding a length to a rate is an
+/// error, and results can be converted to a unit with `to` e.g.
+/// `-0.4 m/yr * 35 yr to km`.
+#[derive(Debug, Default, Clone)]
+pub struct Calculator;
+
+impl Calculator {
+    /// Create a calculator tool
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl ToolHandler for Calculator {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::new(
+            "calculate",
+            "Evaluate an arithmetic expression with optional units (e.g. m, km, yr, day, %), checking that units are consistent. Use `to` to convert the result e.g. `-0.4 m/yr * 35 yr to km`. Supports + - * / ^, parentheses, and sqrt, abs, min, max, ln, log10, exp.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "expression": {
+                        "type": "string",
+                        "description": "The expression to evaluate"
+                    }
+                },
+                "required": ["expression"],
+                "additionalProperties": false
+            }),
+        )
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let Some(expression) = arguments["expression"].as_str() else {
+            bail!("argument `expression` is required")
+        };
+
+        let answer = calculate(expression)?;
+        Ok(serde_json::to_string(&json!({
+            "expression": expression,
+            "value": answer.value,
+            "unit": answer.unit,
+            "result": answer.to_string(),
+        }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use model::common::tokio;
+
+    use super::*;
+
+    #[test]
+    fn calculates() -> Result<()> {
+        let answer = |expr: &str| calculate(expr).map(|answer| answer.to_string());
+
+        assert_eq!(answer("0.1 + 0.2")?, "0.3");
+        assert_eq!(answer("2 * (3 + 4) ^ 2")?, "98");
+        assert_eq!(answer("-2^2")?, "-4");
+        assert_eq!(answer("1.5e3 m")?, "1500 m");
+        assert_eq!(answer("10 m / 2 s")?, "5 m/s");
+        assert_eq!(answer("-0.4 m/yr * 35 yr")?, "-14 m");
+        assert_eq!(answer("-0.4 m/yr * 35 yr to km")?, "-0.014 km");
+        assert_eq!(answer("120 m / 40 years to m/yr")?, "3 m/yr");
+        assert_eq!(answer("1 km + 250 m to m")?, "1250 m");
+        assert_eq!(answer("sqrt(4 ha) to m")?, "200 m");
+        assert_eq!(answer("25 % * 80")?, "20");
+        assert_eq!(answer("max(1 m, 50 cm)")?, "1 m");
+
+        assert!(calculate("1 m + 1 s").is_err());
+        assert!(calculate("1 m to yr").is_err());
+        assert!(calculate("2 ^ 0.5 m").is_ok());
+        assert!(calculate("(2 m) ^ 0.5").is_err());
+        assert!(calculate("1 / 0").is_err());
+        assert!(calculate("3 parsecs").is_err());
+        assert!(calculate("ln(2 m)").is_err());
+        assert!(calculate("(1 + 2").is_err());
+        assert!(calculate("1 2 3").is_err());
+
+        assert_eq!(answer("---2")?, "-2");
+        assert!(calculate("(1 ha)^2000000000").is_err());
+        assert!(calculate("(1 m)^1e12").is_err());
+        assert!(calculate("1 m^2000000000 * 1 m^2000000000").is_err());
+        assert!(calculate("1 / 1 m^2000000000 / 1 m^2000000000").is_err());
+        assert!(calculate(&format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000))).is_err());
+        assert!(calculate(&format!("{}1", "-".repeat(100_000))).is_ok());
+        assert_eq!(
+            answer(&format!("{}1{}", "(".repeat(32), ")".repeat(32)))?,
+            "1"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tool() -> Result<()> {
+        let result: Value = serde_json::from_str(
+            &Calculator::new()
+                .call(json!({"expression": "0.5 m/yr * 10 yr"}))
+                .await?,
+        )?;
+        assert_eq!(result["value"], 5.0);
+        assert_eq!(result["unit"], "m");
+        assert_eq!(result["result"], "5 m");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+//! Built-in tools which can be dispatched by an [`Agent`](crate::Agent)
+
+mod calculator;
+mod provenance;
+mod read_file;
+
+pub use calculator::Calculator;
+pub use provenance::{
+    Direction, ProvenanceEdge, ProvenanceExecution, ProvenanceGraph, ProvenanceNode,
+    QueryProvenance,
+};
+pub use read_file::ReadFile;
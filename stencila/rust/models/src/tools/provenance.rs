@@ -0,0 +1,792 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+use model::{
+    ToolDefinition, ToolHandler,
+    common::{
+        async_trait::async_trait,
+        chrono::{DateTime, Utc},
+        eyre::{Result, bail},
+        indexmap::IndexMap,
+        itertools::Itertools,
+        once_cell::sync::Lazy,
+        regex::Regex,
+        serde::Serialize,
+        serde_json::{self, Value, json},
+    },
+    schema::{
+        Author, Block, CodeChunk, Duration, ExecutionDependant, ExecutionDependantNode,
+        ExecutionDependantRelation, ExecutionDependency, ExecutionDependencyNode, ExecutionStatus,
+        ImageObject, Inline, Integer, LabelType, Node, NodeId, NodeType, Timestamp, Visitor,
+        WalkControl, WalkNode,
+    },
+};
+
+/// The maximum number of characters of code included for a node
+const MAX_CODE_CHARS: usize = 2000;
+
+/// The default number of hops followed when querying the graph
+const DEFAULT_DEPTH: usize = 3;
+
+/// A regex for figure and table labels e.g. `Figure 3`, `fig. 3`, `Table 1`
+static LABEL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(fig(?:ure)?|tab(?:le)?)\.?\s*(\S+)$").expect("invalid regex"));
+
+/// A node in a [`ProvenanceGraph`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct ProvenanceNode {
+    /// The id of the node
+    ///
+    /// The document node id for document nodes, `variable:<name>` for variables,
+    /// and `file:<path>` for files.
+    pub id: String,
+
+    /// The type of the node e.g. `CodeChunk`, `Figure`, `Variable`
+    pub node_type: String,
+
+    /// The label of the node e.g. `Figure 3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// The name of the node e.g. its HTML id, a variable name, file path, or image URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// The programming language of executable nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub programming_language: Option<String>,
+
+    /// The code of executable nodes (truncated if long)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+
+    /// A summary of the last execution of executable nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution: Option<ProvenanceExecution>,
+
+    /// The names of the authors of the node
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+}
+
+/// A summary of the last execution of a [`ProvenanceNode`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct ProvenanceExecution {
+    /// The status of the execution e.g. `Succeeded`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+
+    /// The number of times the node has been executed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<Integer>,
+
+    /// When the execution ended, as an RFC 3339 timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended: Option<String>,
+
+    /// The duration of the execution, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<i64>,
+}
+
+/// An edge in a [`ProvenanceGraph`]
+///
+/// Edges point from a node to a node it was derived from, so following edges
+/// forward traces the lineage of a node (upstream) and following them in reverse
+/// finds the nodes which depend on it (downstream).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", crate = "model::common::serde")]
+pub struct ProvenanceEdge {
+    /// The id of the derived node
+    pub from: String,
+
+    /// The relation between the nodes e.g. `contains`, `uses`, `reads`, `assignedBy`
+    pub relation: String,
+
+    /// The id of the node it was derived from
+    pub to: String,
+}
+
+/// A graph of the provenance of the nodes in a document
+///
+/// Built from the figures, tables, images and executable nodes of a document,
+/// the execution dependencies and dependants recorded when the document was
+/// compiled, and the containment of nodes within figures and tables. For example,
+/// for a figure generated by a code chunk which uses a variable assigned by another
+/// chunk which reads a CSV file, the lineage of the figure is:
+///
+/// ```text
+/// Figure 3 -contains-> CodeChunk -uses-> variable:shorelines -assignedBy-> CodeChunk -reads-> file:transects.csv
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProvenanceGraph {
+    /// The nodes of the graph, in document order
+    nodes: IndexMap<String, ProvenanceNode>,
+
+    /// The edges of the graph
+    edges: Vec<ProvenanceEdge>,
+}
+
+impl ProvenanceGraph {
+    /// Build the provenance graph of a document
+    pub fn from_node(node: &Node) -> Self {
+        let mut collector = Collector::default();
+        node.walk(&mut collector);
+        collector.graph
+    }
+
+    /// Get the nodes of the graph
+    pub fn nodes(&self) -> impl Iterator<Item = &ProvenanceNode> {
+        self.nodes.values()
+    }
+
+    /// Get the edges of the graph
+    pub fn edges(&self) -> &[ProvenanceEdge] {
+        &self.edges
+    }
+
+    /// Find the node matching a query
+    ///
+    /// The query can be a figure or table label (e.g. `Figure 3`, `fig. 3`, `Table 1`),
+    /// a node id, an HTML id, a variable name, a file path, or an image URL.
+    pub fn find(&self, query: &str) -> Option<&ProvenanceNode> {
+        let query = query.trim();
+
+        if let Some(node) = self.nodes.get(query) {
+            return Some(node);
+        }
+
+        if let Some(captures) = LABEL.captures(query) {
+            let kind = if captures[1].to_lowercase().starts_with("fig") {
+                "Figure"
+            } else {
+                "Table"
+            };
+            let label = format!("{kind} {}", &captures[2]);
+            if let Some(node) = self.nodes.values().find(|node| {
+                node.label
+                    .as_ref()
+                    .is_some_and(|node_label| node_label.eq_ignore_ascii_case(&label))
+            }) {
+                return Some(node);
+            }
+        }
+
+        self.nodes
+            .values()
+            .find(|node| node.name.as_deref() == Some(query))
+            .or_else(|| self.nodes.get(&format!("variable:{query}")))
+            .or_else(|| self.nodes.get(&format!("file:{query}")))
+    }
+
+    /// Trace the lineage of a node, following edges up to `depth` hops
+    ///
+    /// Returns the edges traversed, each with the number of hops from the node,
+    /// and the nodes reached.
+    pub fn trace(
+        &self,
+        id: &str,
+        direction: Direction,
+        depth: usize,
+    ) -> (Vec<(usize, &ProvenanceEdge)>, Vec<&ProvenanceNode>) {
+        let mut edges = Vec::new();
+        let mut visited = HashSet::from([id.to_string()]);
+        let mut queue = VecDeque::from([(id.to_string(), 0)]);
+
+        while let Some((current, hops)) = queue.pop_front() {
+            if hops >= depth {
+                continue;
+            }
+
+            for edge in &self.edges {
+                let next = match direction {
+                    Direction::Upstream if edge.from == current => &edge.to,
+                    Direction::Downstream if edge.to == current => &edge.from,
+                    _ => continue,
+                };
+
+                edges.push((hops + 1, edge));
+                if visited.insert(next.clone()) {
+                    queue.push_back((next.clone(), hops + 1));
+                }
+            }
+        }
+
+        let nodes = visited
+            .iter()
+            .filter(|visited| *visited != id)
+            .filter_map(|id| self.nodes.get_index_of(id))
+            .sorted()
+            .filter_map(|index| self.nodes.get_index(index).map(|(.., node)| node))
+            .collect();
+
+        (edges, nodes)
+    }
+
+    /// Add a node, replacing any placeholder for it
+    fn add(&mut self, node: ProvenanceNode) {
+        self.nodes.insert(node.id.clone(), node);
+    }
+
+    /// Add a placeholder for a node if it is not already in the graph
+    fn reference(&mut self, id: String, node_type: &str, name: Option<String>) -> String {
+        self.nodes
+            .entry(id.clone())
+            .or_insert_with(|| ProvenanceNode {
+                id: id.clone(),
+                node_type: node_type.into(),
+                name,
+                ..Default::default()
+            });
+        id
+    }
+
+    /// Add an edge
+    fn edge(&mut self, from: String, relation: &str, to: String) {
+        let edge = ProvenanceEdge {
+            from,
+            relation: relation.into(),
+            to,
+        };
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+}
+
+/// The direction in which to trace the lineage of a node
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The nodes that a node was derived from
+    #[default]
+    Upstream,
+
+    /// The nodes derived from a node
+    Downstream,
+}
+
+/// A visitor which collects the nodes and edges of a [`ProvenanceGraph`]
+#[derive(Default)]
+struct Collector {
+    /// The graph being built
+    graph: ProvenanceGraph,
+
+    /// The stack of structs the visitor is currently within
+    ancestors: Vec<(NodeType, NodeId)>,
+}
+
+impl Collector {
+    /// Add an edge from the figure or table (if any) containing the current node
+    fn contained(&mut self, id: &str) {
+        if let Some((.., container)) = self
+            .ancestors
+            .iter()
+            .rev()
+            .find(|(node_type, ..)| matches!(node_type, NodeType::Figure | NodeType::Table))
+        {
+            self.graph
+                .edge(container.to_string(), "contains", id.to_string());
+        }
+    }
+
+    /// Add a node for a code chunk
+    fn code_chunk(&mut self, chunk: &CodeChunk) {
+        let id = chunk.node_id().to_string();
+        let label = chunk
+            .label
+            .as_ref()
+            .and_then(|label| match chunk.label_type {
+                Some(LabelType::FigureLabel) => Some(format!("Figure {label}")),
+                Some(LabelType::TableLabel) => Some(format!("Table {label}")),
+                _ => None,
+            });
+
+        self.graph.add(ProvenanceNode {
+            id: id.clone(),
+            node_type: "CodeChunk".into(),
+            label,
+            name: chunk.id.clone(),
+            programming_language: chunk.programming_language.clone(),
+            code: Some(truncate(&chunk.code)),
+            execution: execution(
+                &chunk.options.execution_status,
+                &chunk.options.execution_count,
+                &chunk.options.execution_ended,
+                &chunk.options.execution_duration,
+            ),
+            authors: authors(&chunk.authors),
+        });
+
+        self.contained(&id);
+        self.dependencies(&id, &chunk.options.execution_dependencies);
+        self.dependants(&id, &chunk.options.execution_dependants);
+    }
+
+    /// Add an image
+    fn image(&mut self, image: &ImageObject) {
+        let id = image.node_id().to_string();
+        self.graph.add(ProvenanceNode {
+            id: id.clone(),
+            node_type: "ImageObject".into(),
+            name: Some(image.content_url.clone()).filter(|url| !url.starts_with("data:")),
+            ..Default::default()
+        });
+        self.contained(&id);
+    }
+
+    /// Add edges from a node to its execution dependencies
+    fn dependencies(&mut self, id: &str, dependencies: &Option<Vec<ExecutionDependency>>) {
+        for dependency in dependencies.iter().flatten() {
+            let to = match &dependency.dependency_node {
+                ExecutionDependencyNode::Variable(var) => self.graph.reference(
+                    format!("variable:{}", var.name),
+                    "Variable",
+                    Some(var.name.clone()),
+                ),
+                ExecutionDependencyNode::File(file) => self.graph.reference(
+                    format!("file:{}", file.path),
+                    "File",
+                    Some(file.path.clone()),
+                ),
+                ExecutionDependencyNode::Button(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "Button", None)
+                }
+                ExecutionDependencyNode::CodeChunk(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "CodeChunk", None)
+                }
+                ExecutionDependencyNode::Parameter(node) => self.graph.reference(
+                    node.node_id().to_string(),
+                    "Parameter",
+                    Some(node.name.clone()),
+                ),
+                ExecutionDependencyNode::SoftwareSourceCode(node) => self.graph.reference(
+                    node.node_id().to_string(),
+                    "SoftwareSourceCode",
+                    Some(node.name.clone()),
+                ),
+            };
+
+            let relation = dependency.dependency_relation.to_string().to_lowercase();
+            self.graph.edge(id.to_string(), &relation, to);
+        }
+    }
+
+    /// Add edges to a node from its execution dependants (e.g. the variables it assigns)
+    fn dependants(&mut self, id: &str, dependants: &Option<Vec<ExecutionDependant>>) {
+        for dependant in dependants.iter().flatten() {
+            let from = match &dependant.dependant_node {
+                ExecutionDependantNode::Variable(var) => self.graph.reference(
+                    format!("variable:{}", var.name),
+                    "Variable",
+                    Some(var.name.clone()),
+                ),
+                ExecutionDependantNode::File(file) => self.graph.reference(
+                    format!("file:{}", file.path),
+                    "File",
+                    Some(file.path.clone()),
+                ),
+                ExecutionDependantNode::Function(node) => self.graph.reference(
+                    node.node_id().to_string(),
+                    "Function",
+                    Some(node.name.clone()),
+                ),
+                ExecutionDependantNode::Parameter(node) => self.graph.reference(
+                    node.node_id().to_string(),
+                    "Parameter",
+                    Some(node.name.clone()),
+                ),
+                ExecutionDependantNode::Button(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "Button", None)
+                }
+                ExecutionDependantNode::CallBlock(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "CallBlock", None)
+                }
+                ExecutionDependantNode::CodeChunk(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "CodeChunk", None)
+                }
+                ExecutionDependantNode::CodeExpression(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "CodeExpression", None)
+                }
+                ExecutionDependantNode::StyledBlock(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "StyledBlock", None)
+                }
+                ExecutionDependantNode::StyledInline(node) => {
+                    self.graph
+                        .reference(node.node_id().to_string(), "StyledInline", None)
+                }
+            };
+
+            let relation = match dependant.dependant_relation {
+                ExecutionDependantRelation::Assigns => "assignedBy",
+                ExecutionDependantRelation::Alters => "alteredBy",
+                ExecutionDependantRelation::Declares => "declaredBy",
+                ExecutionDependantRelation::Writes => "writtenBy",
+            };
+            self.graph.edge(from, relation, id.to_string());
+        }
+    }
+}
+
+impl Visitor for Collector {
+    fn enter_struct(&mut self, node_type: NodeType, node_id: NodeId) -> WalkControl {
+        self.ancestors.push((node_type, node_id));
+        WalkControl::Continue
+    }
+
+    fn exit_struct(&mut self) {
+        self.ancestors.pop();
+    }
+
+    fn visit_block(&mut self, block: &Block) -> WalkControl {
+        match block {
+            Block::CodeChunk(chunk) => self.code_chunk(chunk),
+            Block::Figure(figure) => {
+                let id = figure.node_id().to_string();
+                self.graph.add(ProvenanceNode {
+                    id,
+                    node_type: "Figure".into(),
+                    label: figure.label.as_ref().map(|label| format!("Figure {label}")),
+                    name: figure.id.clone(),
+                    authors: authors(&figure.authors),
+                    ..Default::default()
+                });
+            }
+            Block::Table(table) => {
+                let id = table.node_id().to_string();
+                self.graph.add(ProvenanceNode {
+                    id,
+                    node_type: "Table".into(),
+                    label: table.label.as_ref().map(|label| format!("Table {label}")),
+                    name: table.id.clone(),
+                    authors: authors(&table.authors),
+                    ..Default::default()
+                });
+            }
+            Block::ImageObject(image) => self.image(image),
+            _ => {}
+        }
+
+        WalkControl::Continue
+    }
+
+    fn visit_inline(&mut self, inline: &Inline) -> WalkControl {
+        match inline {
+            Inline::CodeExpression(expr) => {
+                let id = expr.node_id().to_string();
+                self.graph.add(ProvenanceNode {
+                    id: id.clone(),
+                    node_type: "CodeExpression".into(),
+                    name: expr.id.clone(),
+                    programming_language: expr.programming_language.clone(),
+                    code: Some(truncate(&expr.code)),
+                    execution: execution(
+                        &expr.options.execution_status,
+                        &expr.options.execution_count,
+                        &expr.options.execution_ended,
+                        &expr.options.execution_duration,
+                    ),
+                    authors: authors(&expr.authors),
+                    ..Default::default()
+                });
+                self.contained(&id);
+                self.dependencies(&id, &expr.options.execution_dependencies);
+                self.dependants(&id, &expr.options.execution_dependants);
+            }
+            Inline::Parameter(param) => {
+                let id = param.node_id().to_string();
+                self.graph.add(ProvenanceNode {
+                    id: id.clone(),
+                    node_type: "Parameter".into(),
+                    name: Some(param.name.clone()),
+                    execution: execution(
+                        &param.options.execution_status,
+                        &param.options.execution_count,
+                        &param.options.execution_ended,
+                        &param.options.execution_duration,
+                    ),
+                    ..Default::default()
+                });
+                self.dependencies(&id, &param.options.execution_dependencies);
+                self.dependants(&id, &param.options.execution_dependants);
+            }
+            Inline::ImageObject(image) => self.image(image),
+            _ => {}
+        }
+
+        WalkControl::Continue
+    }
+}
+
+/// Truncate code to at most [`MAX_CODE_CHARS`]
+fn truncate(code: &str) -> String {
+    if code.chars().count() <= MAX_CODE_CHARS {
+        code.to_string()
+    } else {
+        let mut code: String = code.chars().take(MAX_CODE_CHARS).collect();
+        code.push('…');
+        code
+    }
+}
+
+/// Summarize the last execution of a node, if any
+fn execution(
+    status: &Option<ExecutionStatus>,
+    count: &Option<Integer>,
+    ended: &Option<Timestamp>,
+    duration: &Option<Duration>,
+) -> Option<ProvenanceExecution> {
+    if status.is_none() && count.is_none() && ended.is_none() {
+        return None;
+    }
+
+    Some(ProvenanceExecution {
+        status: status.map(|status| status.to_string()),
+        count: *count,
+        ended: ended.as_ref().and_then(|ended| {
+            let ended: Result<DateTime<Utc>> = ended.try_into();
+            ended.ok().map(|ended| ended.to_rfc3339())
+        }),
+        duration_ms: duration.as_ref().map(|duration| duration.to_milliseconds()),
+    })
+}
+
+/// Get the unique names of authors
+fn authors(authors: &Option<Vec<Author>>) -> Vec<String> {
+    authors
+        .iter()
+        .flatten()
+        .map(|author| author.name())
+        .unique()
+        .collect()
+}
+
+/// A tool for querying the provenance of the nodes in a document
+///
+/// Lets models answer questions such as "what produced figure 3?" with
+/// structured JSON describing the lineage of the node rather than having to
+/// infer it from the text of the document.
+#[derive(Debug, Clone)]
+pub struct QueryProvenance {
+    /// The provenance graph of the document
+    graph: Arc<ProvenanceGraph>,
+}
+
+impl QueryProvenance {
+    /// Create a tool for querying the provenance graph of a document
+    pub fn new(graph: ProvenanceGraph) -> Self {
+        Self {
+            graph: Arc::new(graph),
+        }
+    }
+
+    /// Create a tool for querying the provenance of a document node
+    pub fn from_node(node: &Node) -> Self {
+        Self::new(ProvenanceGraph::from_node(node))
+    }
+
+    /// Query the graph
+    fn query(&self, arguments: &Value) -> Result<Value> {
+        let Some(query) = arguments["node"].as_str() else {
+            // Without a node, return an overview of the graph so the model can
+            // discover the nodes it can ask about
+            return Ok(json!({
+                "nodes": self.graph.nodes().map(|node| json!({
+                    "id": node.id,
+                    "nodeType": node.node_type,
+                    "label": node.label,
+                    "name": node.name,
+                })).collect_vec(),
+                "edges": self.graph.edges().len(),
+            }));
+        };
+
+        let direction = match arguments["direction"].as_str() {
+            None | Some("upstream") => Direction::Upstream,
+            Some("downstream") => Direction::Downstream,
+            Some(direction) => {
+                bail!("invalid direction `{direction}`, expected `upstream` or `downstream`")
+            }
+        };
+
+        let depth = arguments["depth"]
+            .as_u64()
+            .map_or(DEFAULT_DEPTH, |depth| depth.max(1) as usize);
+
+        let Some(node) = self.graph.find(query) else {
+            let labels = self
+                .graph
+                .nodes()
+                .filter_map(|node| node.label.as_deref())
+                .join(", ");
+            bail!("no node matching `{query}` (labelled nodes: {labels})")
+        };
+
+        let (edges, related) = self.graph.trace(&node.id, direction, depth);
+
+        Ok(json!({
+            "node": node,
+            "direction": match direction {
+                Direction::Upstream => "upstream",
+                Direction::Downstream => "downstream",
+            },
+            "edges": edges
+                .into_iter()
+                .map(|(depth, edge)| json!({
+                    "from": edge.from,
+                    "relation": edge.relation,
+                    "to": edge.to,
+                    "depth": depth,
+                }))
+                .collect_vec(),
+            "related": related,
+        }))
+    }
+}
+
+#[async_trait]
+impl ToolHandler for QueryProvenance {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::new(
+            "query_provenance",
+            "Query the provenance graph of the current document e.g. to find what code, variables and files produced a figure or table. Omit `node` to list the nodes in the graph.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "node": {
+                        "type": "string",
+                        "description": "The node to query: a label (e.g. \"Figure 3\"), a node id, a variable name, or a file path"
+                    },
+                    "direction": {
+                        "type": "string",
+                        "enum": ["upstream", "downstream"],
+                        "description": "Whether to trace what the node was derived from (upstream, the default) or what was derived from it (downstream)"
+                    },
+                    "depth": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "The maximum number of hops to follow (default 3)"
+                    }
+                },
+                "additionalProperties": false
+            }),
+        )
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let result = self.query(&arguments)?;
+        Ok(serde_json::to_string(&result)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use model::{
+        common::tokio,
+        schema::{
+            CodeChunkOptions, ExecutionDependencyRelation, File, Variable,
+            shortcuts::{art, cc, p, t},
+        },
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn traces_lineage() -> Result<()> {
+        let load = CodeChunk {
+            code: "shorelines = pd.read_csv('transects.csv')".into(),
+            programming_language: Some("python".into()),
+            options: Box::new(CodeChunkOptions {
+                execution_dependencies: Some(vec![ExecutionDependency::new(
+                    ExecutionDependencyRelation::Reads,
+                    ExecutionDependencyNode::File(File::new(
+                        "transects.csv".into(),
+                        "transects.csv".into(),
+                    )),
+                )]),
+                execution_dependants: Some(vec![ExecutionDependant::new(
+                    ExecutionDependantRelation::Assigns,
+                    ExecutionDependantNode::Variable(Variable::new("shorelines".into())),
+                )]),
+                execution_status: Some(ExecutionStatus::Succeeded),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let plot = CodeChunk {
+            code: "shorelines.plot()".into(),
+            label_type: Some(LabelType::FigureLabel),
+            label: Some("3".into()),
+            options: Box::new(CodeChunkOptions {
+                execution_dependencies: Some(vec![ExecutionDependency::new(
+                    ExecutionDependencyRelation::Uses,
+                    ExecutionDependencyNode::Variable(Variable::new("shorelines".into())),
+                )]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let load_id = load.node_id().to_string();
+        let plot_id = plot.node_id().to_string();
+        let doc = art([
+            Block::CodeChunk(load),
+            p([t("Shorelines are retreating.")]),
+            Block::CodeChunk(plot),
+            cc("1 + 1", Some("python")),
+        ]);
+
+        let tool = QueryProvenance::from_node(&doc);
+
+        let overview: Value = serde_json::from_str(&tool.call(json!({})).await?)?;
+        assert_eq!(
+            overview["nodes"].as_array().map(|nodes| nodes.len()),
+            Some(5)
+        );
+        assert_eq!(overview["edges"], 3);
+
+        let result: Value = serde_json::from_str(&tool.call(json!({ "node": "fig. 3" })).await?)?;
+        assert_eq!(result["node"]["id"], plot_id);
+        assert_eq!(result["node"]["label"], "Figure 3");
+        assert_eq!(
+            result["edges"],
+            json!([
+                {"from": plot_id, "relation": "uses", "to": "variable:shorelines", "depth": 1},
+                {"from": "variable:shorelines", "relation": "assignedBy", "to": load_id, "depth": 2},
+                {"from": load_id, "relation": "reads", "to": "file:transects.csv", "depth": 3},
+            ])
+        );
+        assert_eq!(result["related"][0]["execution"]["status"], "Succeeded");
+
+        let result: Value = serde_json::from_str(
+            &tool
+                .call(json!({ "node": "transects.csv", "direction": "downstream", "depth": 1 }))
+                .await?,
+        )?;
+        assert_eq!(result["related"][0]["id"], load_id);
+        assert_eq!(
+            result["related"].as_array().map(|nodes| nodes.len()),
+            Some(1)
+        );
+
+        assert!(tool.call(json!({ "node": "Figure 4" })).await.is_err());
+        assert!(
+            tool.call(json!({ "node": "Figure 3", "direction": "sideways" }))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf, absolute},
+};
+
+use model::{
+    ToolDefinition, ToolHandler,
+    common::{
+        async_trait::async_trait,
+        eyre::{Result, bail, eyre},
+        serde_json::{Value, json},
+        tokio::task::spawn_blocking,
+    },
+};
+
+/// The default maximum number of bytes read from a file
+const DEFAULT_MAX_BYTES: u64 = 256 * 1024;
+
+/// A tool for reading local text files
+///
+/// Lets models pull in files (e.g. the CSV and GeoJSON outputs of an analysis)
+/// on demand rather than all files being attached to the task upfront. Reading
+/// is sandboxed: only files within the allowed directories can be read (symlinks
+/// and `..` segments are resolved before checking) and at most `max_bytes` of
+/// each file are returned.
+#[derive(Debug, Clone)]
+pub struct ReadFile {
+    /// The directories files can be read from
+    roots: Vec<PathBuf>,
+
+    /// The maximum number of bytes returned for a file
+    max_bytes: u64,
+}
+
+impl ReadFile {
+    /// Create a tool which can read files within the directories
+    ///
+    /// Relative paths passed by the model are resolved against each directory, in order.
+    pub fn new<P: Into<PathBuf>>(roots: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            roots: roots.into_iter().map(Into::into).collect(),
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// Set the maximum number of bytes returned for a file
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Resolve a path passed by the model, checking that it is within an allowed directory
+    ///
+    /// Containment is checked on the lexically normalized path before the file
+    /// system is accessed, so that the same error is returned for paths outside of
+    /// the allowed directories whether or not they exist. It is checked again after
+    /// symlinks are resolved.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let path = Path::new(path);
+        let outside = || {
+            eyre!(
+                "path `{}` is outside of the allowed directories",
+                path.display()
+            )
+        };
+
+        let roots: Vec<PathBuf> = self
+            .roots
+            .iter()
+            .flat_map(|root| {
+                let lexical = absolute(root).ok().map(|root| normalize(&root));
+                let canonical = root.canonicalize().ok();
+                lexical.into_iter().chain(canonical)
+            })
+            .collect();
+        let is_allowed = |path: &Path| roots.iter().any(|root| path.starts_with(root));
+
+        let candidates: Vec<PathBuf> = if path.is_absolute() {
+            vec![path.to_path_buf()]
+        } else {
+            self.roots.iter().map(|root| root.join(path)).collect()
+        };
+        let candidates: Vec<PathBuf> = candidates
+            .into_iter()
+            .filter_map(|candidate| absolute(candidate).ok().map(|path| normalize(&path)))
+            .filter(|candidate| is_allowed(candidate))
+            .collect();
+        if candidates.is_empty() {
+            return Err(outside());
+        }
+
+        for candidate in candidates {
+            let Ok(resolved) = candidate.canonicalize() else {
+                continue;
+            };
+
+            if !is_allowed(&resolved) {
+                return Err(outside());
+            }
+
+            if !resolved.is_file() {
+                bail!("path `{}` is not a file", path.display())
+            }
+
+            return Ok(resolved);
+        }
+
+        bail!("file `{}` does not exist", path.display())
+    }
+
+    /// Read a file, returning at most `max_bytes`
+    fn read(&self, path: &str) -> Result<String> {
+        let path = self.resolve(path)?;
+
+        let size = path.metadata()?.len();
+        let mut bytes = Vec::new();
+        File::open(&path)?
+            .take(self.max_bytes)
+            .read_to_end(&mut bytes)?;
+
+        let mut content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            // The limit may have split a multi-byte character so only error if
+            // the invalid bytes are not at the very end
+            Err(error) if size > self.max_bytes && error.utf8_error().error_len().is_none() => {
+                let valid = error.utf8_error().valid_up_to();
+                let mut bytes = error.into_bytes();
+                bytes.truncate(valid);
+                String::from_utf8(bytes)?
+            }
+            Err(..) => bail!("file is not a UTF-8 text file"),
+        };
+
+        if size > self.max_bytes {
+            content.push_str(&format!(
+                "\n\n[Truncated: file is {size} bytes, only the first {} bytes were read]",
+                self.max_bytes
+            ));
+        }
+
+        Ok(content)
+    }
+}
+
+/// Lexically normalize a path, resolving `.` and `..` segments without accessing the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[async_trait]
+impl ToolHandler for ReadFile {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::new(
+            "read_file",
+            "Read the contents of a local text file (e.g. a CSV or JSON output of an analysis). Paths are relative to the project directory.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "The path of the file to read"
+                    }
+                },
+                "required": ["path"],
+                "additionalProperties": false
+            }),
+        )
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String> {
+        let Some(path) = arguments["path"].as_str().map(String::from) else {
+            bail!("argument `path` is required")
+        };
+
+        let tool = self.clone();
+        spawn_blocking(move || tool.read(&path)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use model::common::{tempfile::tempdir, tokio};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_allowed_files() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("outputs");
+        create_dir(&root)?;
+        write(root.join("transects.csv"), "id,rate\nnarra_0001,-0.4\n")?;
+        write(root.join("big.txt"), "é".repeat(10))?;
+        write(root.join("binary.bin"), [0xff, 0xfe, 0x00])?;
+        write(dir.path().join("secret.txt"), "secret")?;
+
+        let tool = ReadFile::new([&root]).max_bytes(5);
+        let read = |path: &str| tool.call(json!({ "path": path }));
+
+        assert_eq!(
+            read("transects.csv").await?,
+            "id,ra\n\n[Truncated: file is 24 bytes, only the first 5 bytes were read]"
+        );
+        assert!(read("big.txt").await?.starts_with("éé\n\n[Truncated"));
+        assert!(read("binary.bin").await.is_err());
+        assert!(read("../secret.txt").await.is_err());
+        assert!(
+            read(&dir.path().join("secret.txt").to_string_lossy())
+                .await
+                .is_err()
+        );
+
+        // Paths outside of the allowed directories get the same error whether or not they exist
+        for (existing, missing) in [
+            ("../secret.txt", "../missing.txt"),
+            ("outputs/../../secret.txt", "outputs/../../missing.txt"),
+        ] {
+            let (Err(existing), Err(missing)) = (read(existing).await, read(missing).await) else {
+                bail!("expected errors")
+            };
+            assert!(
+                existing
+                    .to_string()
+                    .contains("outside of the allowed directories")
+            );
+            assert_eq!(
+                existing.to_string().replace("secret", "missing"),
+                missing.to_string()
+            );
+        }
+        assert!(read("missing.csv").await.is_err());
+        assert!(read(".").await.is_err());
+        assert!(tool.call(json!({})).await.is_err());
+
+        let tool = ReadFile::new([&root]);
+        assert_eq!(
+            tool.call(json!({ "path": "transects.csv" })).await?,
+            "id,rate\nnarra_0001,-0.4\n"
+        );
+
+        Ok(())
+    }
+}
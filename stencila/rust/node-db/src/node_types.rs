@@ -828,7 +828,15 @@ impl DatabaseNode for InstructionAttachment {
     
     fn node_table(&self) -> Vec<(NodeProperty, LogicalType, Value)> {
         vec![
-            (NodeProperty::Alias, self.alias.to_kuzu_type(), self.alias.to_kuzu_value())
+            (NodeProperty::Alias, self.alias.to_kuzu_type(), self.alias.to_kuzu_value()),
+            (NodeProperty::Description, self.options.description.to_kuzu_type(), self.options.description.to_kuzu_value()),
+            (NodeProperty::Pages, self.options.pages.to_kuzu_type(), self.options.pages.to_kuzu_value()),
+            (NodeProperty::ByteRange, self.options.byte_range.to_kuzu_type(), self.options.byte_range.to_kuzu_value()),
+            (NodeProperty::Sheet, self.options.sheet.to_kuzu_type(), self.options.sheet.to_kuzu_value()),
+            (NodeProperty::Checksum, self.options.checksum.to_kuzu_type(), self.options.checksum.to_kuzu_value()),
+            (NodeProperty::SizeBytes, self.options.size_bytes.to_kuzu_type(), self.options.size_bytes.to_kuzu_value()),
+            (NodeProperty::SourceUrl, self.options.source_url.to_kuzu_type(), self.options.source_url.to_kuzu_value()),
+            (NodeProperty::GeneratedBy, self.options.generated_by.to_kuzu_type(), self.options.generated_by.to_kuzu_value())
         ]
     }
 
@@ -1663,6 +1671,34 @@ impl DatabaseNode for ThematicBreak {
     }
 }
 
+impl DatabaseNode for ToolResult {
+    fn node_type(&self) -> NodeType {
+        NodeType::ToolResult
+    }
+
+    fn node_id(&self) -> NodeId {
+        ToolResult::node_id(self)
+    }
+    
+    fn primary_key(&self) -> Value {
+        self.node_id().to_kuzu_value()
+    }
+    
+    fn node_table(&self) -> Vec<(NodeProperty, LogicalType, Value)> {
+        vec![
+            (NodeProperty::CallId, self.call_id.to_kuzu_type(), self.call_id.to_kuzu_value()),
+            (NodeProperty::Name, self.name.to_kuzu_type(), self.name.to_kuzu_value()),
+            (NodeProperty::Content, self.content.to_kuzu_type(), self.content.to_kuzu_value())
+        ]
+    }
+
+    fn rel_tables(&self) -> Vec<(NodeProperty, Vec<(NodeType, Value)>)> {
+        vec![
+            
+        ]
+    }
+}
+
 impl DatabaseNode for Variable {
     fn node_type(&self) -> NodeType {
         NodeType::Variable
@@ -1793,6 +1829,7 @@ impl DatabaseNode for Node {
             Node::TableCell(node) => node.node_type(),
             Node::TableRow(node) => node.node_type(),
             Node::ThematicBreak(node) => node.node_type(),
+            Node::ToolResult(node) => node.node_type(),
             Node::Variable(node) => node.node_type(),
             Node::VideoObject(node) => node.node_type(),
             _ => NodeType::Unknown
@@ -1852,6 +1889,7 @@ impl DatabaseNode for Node {
             Node::TableCell(node) => node.node_id(),
             Node::TableRow(node) => node.node_id(),
             Node::ThematicBreak(node) => node.node_id(),
+            Node::ToolResult(node) => node.node_id(),
             Node::Variable(node) => node.node_id(),
             Node::VideoObject(node) => node.node_id(),
             _ => NodeId::null()
@@ -1911,6 +1949,7 @@ impl DatabaseNode for Node {
             Node::TableCell(node) => node.primary_key(),
             Node::TableRow(node) => node.primary_key(),
             Node::ThematicBreak(node) => node.primary_key(),
+            Node::ToolResult(node) => node.primary_key(),
             Node::Variable(node) => node.primary_key(),
             Node::VideoObject(node) => node.primary_key(),
             _ => Value::Null(LogicalType::Any)
@@ -1970,6 +2009,7 @@ impl DatabaseNode for Node {
             Node::TableCell(node) => node.node_table(),
             Node::TableRow(node) => node.node_table(),
             Node::ThematicBreak(node) => node.node_table(),
+            Node::ToolResult(node) => node.node_table(),
             Node::Variable(node) => node.node_table(),
             Node::VideoObject(node) => node.node_table(),
             _ => Vec::new()
@@ -2029,6 +2069,7 @@ impl DatabaseNode for Node {
             Node::TableCell(node) => node.rel_tables(),
             Node::TableRow(node) => node.rel_tables(),
             Node::ThematicBreak(node) => node.rel_tables(),
+            Node::ToolResult(node) => node.rel_tables(),
             Node::Variable(node) => node.rel_tables(),
             Node::VideoObject(node) => node.rel_tables(),
             _ => Vec::new()
@@ -371,6 +371,14 @@ CREATE NODE TABLE IF NOT EXISTS `IncludeBlock` (
 
 CREATE NODE TABLE IF NOT EXISTS `InstructionAttachment` (
   `alias` STRING,
+  `description` STRING,
+  `pages` STRING,
+  `byteRange` STRING,
+  `sheet` STRING,
+  `checksum` STRING,
+  `sizeBytes` UINT64,
+  `sourceUrl` STRING,
+  `generatedBy` STRING,
   `docId` STRING,
   `nodeId` STRING PRIMARY KEY,
   `nodePath` STRING,
@@ -712,6 +720,17 @@ CREATE NODE TABLE IF NOT EXISTS `ThematicBreak` (
   `position` UINT32
 );
 
+CREATE NODE TABLE IF NOT EXISTS `ToolResult` (
+  `callId` STRING,
+  `name` STRING,
+  `content` STRING,
+  `docId` STRING,
+  `nodeId` STRING PRIMARY KEY,
+  `nodePath` STRING,
+  `nodeAncestors` STRING,
+  `position` UINT32
+);
+
 CREATE NODE TABLE IF NOT EXISTS `Variable` (
   `name` STRING,
   `programmingLanguage` STRING,
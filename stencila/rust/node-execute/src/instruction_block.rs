@@ -11,7 +11,8 @@ use common::{
 };
 use schema::{
     Author, AuthorRole, AuthorRoleAuthor, AuthorRoleName, CompilationDigest, ExecutionMessage,
-    InstructionAttachment, InstructionBlock, MessageLevel, SoftwareApplication,
+    InstructionAttachment, InstructionBlock, InstructionMessage, MessageLevel, MessagePart,
+    SoftwareApplication,
 };
 
 use crate::{ExecuteOptions, interrupt_impl, message_utils, model_utils, prelude::*, state_digest};
@@ -143,6 +144,9 @@ impl Executable for InstructionBlock {
 
         resolve_instruction_attachments(&mut self.options.attachments, executor, &mut messages)
             .await;
+        let selected_attachments =
+            select_instruction_attachments(&self.options.attachments, &mut messages).await;
+        resolve_message_files(&mut self.message, executor, &mut messages).await;
 
         // Determine the types of nodes in the content of the instruction
         // TODO: reinstate use of node_types
@@ -210,7 +214,7 @@ impl Executable for InstructionBlock {
             let mut instruction = self.clone();
             // Use the rendered message with variables resolved
             instruction.message = rendered_message.clone();
-            if let Some(attachments) = instruction.options.attachments.as_ref() {
+            if let Some(attachments) = selected_attachments.as_ref() {
                 let mut parts = model_utils::attachments_to_message_parts(attachments);
                 instruction.message.parts.append(&mut parts);
             }
@@ -297,6 +301,22 @@ async fn resolve_instruction_attachments(
     messages: &mut Vec<ExecutionMessage>,
 ) {
     if let Some(attachments) = attachments {
+        // Expand attachments pointing at directories or globs into one per file
+        let base = executor.directory_stack.last().cloned();
+        let mut expanded = Vec::with_capacity(attachments.len());
+        for attachment in attachments.drain(..) {
+            match models::expand_attachment(&attachment, base.as_deref()) {
+                Ok(Some(files)) => expanded.extend(files),
+                Ok(None) => expanded.push(attachment),
+                Err(error) => {
+                    let error = error.to_string();
+                    tracing::warn!("{error}");
+                    messages.push(ExecutionMessage::new(MessageLevel::Warning, error));
+                }
+            }
+        }
+        *attachments = expanded;
+
         for attachment in attachments.iter_mut() {
             if attachment.file.content.is_some() {
                 continue;
@@ -310,6 +330,63 @@ async fn resolve_instruction_attachments(
     }
 }
 
+/// Load the content of the files in an instruction message (e.g. `![](data.csv)`)
+///
+/// Files are resolved in the same way as attachments, so relative paths are
+/// relative to the directory of the document rather than the current working
+/// directory, and remote files are reported as unsupported.
+async fn resolve_message_files(
+    message: &mut InstructionMessage,
+    executor: &Executor,
+    messages: &mut Vec<ExecutionMessage>,
+) {
+    for part in message.parts.iter_mut() {
+        let MessagePart::File(file) = part else {
+            continue;
+        };
+        if file.content.is_some() {
+            continue;
+        }
+
+        let mut attachment = InstructionAttachment::new(file.name.clone(), file.clone());
+        match resolve_instruction_attachment(&mut attachment, executor).await {
+            Ok(()) => *file = attachment.file,
+            Err(error) => {
+                tracing::warn!("{error}");
+                messages.push(ExecutionMessage::new(MessageLevel::Warning, error));
+            }
+        }
+    }
+}
+
+/// Apply the selectors (e.g. `pages` or `sheet`) of resolved attachments
+///
+/// Used for the attachments added to the instruction message. The attachments
+/// of the instruction itself are left unselected because the selectors are applied
+/// when the model task is performed.
+async fn select_instruction_attachments(
+    attachments: &Option<Vec<InstructionAttachment>>,
+    messages: &mut Vec<ExecutionMessage>,
+) -> Option<Vec<InstructionAttachment>> {
+    let attachments = attachments.as_ref()?;
+
+    let mut selected = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        match models::select_attachment(attachment).await {
+            Ok(Some(attachment)) => selected.push(attachment),
+            Ok(None) => selected.push(attachment.clone()),
+            Err(error) => {
+                let error = error.to_string();
+                tracing::warn!("{error}");
+                messages.push(ExecutionMessage::new(MessageLevel::Warning, error));
+                selected.push(attachment.clone());
+            }
+        }
+    }
+
+    Some(selected)
+}
+
 async fn resolve_instruction_attachment(
     attachment: &mut InstructionAttachment,
     executor: &Executor,
@@ -4,7 +4,7 @@ use common::{
     eyre::{Result, bail},
     tracing,
 };
-use models::{ModelOutput, ModelOutputKind, ModelTask};
+use models::{ModelOutput, ModelOutputKind, ModelTask, attachment_preamble, citations_block};
 use schema::{
     Article, AudioObject, AuthorRole, AuthorRoleAuthor, AuthorRoleName, Block, File, ImageObject,
     Inline, InstructionAttachment, InstructionMessage, Link, MessagePart, MessageRole, Node, Text,
@@ -77,6 +77,9 @@ pub(super) fn file_to_message_part(file: &File) -> Option<MessagePart> {
         };
 
         Some(object)
+    } else if file.options.transfer_encoding.as_deref() == Some("base64") {
+        // Binary files (e.g. PDFs) are passed on as files for models that accept them
+        Some(MessagePart::File(file.clone()))
     } else {
         file.content
             .as_ref()
@@ -95,10 +98,7 @@ pub(super) fn attachments_to_message_parts(
 }
 
 fn attachment_to_message_parts(attachment: &InstructionAttachment) -> Vec<MessagePart> {
-    let descriptor = format!(
-        "Attachment `{}` ({})",
-        attachment.alias, attachment.file.name
-    );
+    let descriptor = attachment_preamble(attachment, None);
 
     match file_to_message_part(&attachment.file) {
         Some(MessagePart::Text(text)) => {
@@ -130,6 +130,9 @@ pub(super) async fn model_task_to_blocks_and_authors(
         kind,
         format,
         content,
+        image_generation,
+        citations,
+        ..
     } = models::perform_task(task).await?;
 
     if let Some(prompt) = prompt_text.as_ref() {
@@ -164,10 +167,13 @@ pub(super) async fn model_task_to_blocks_and_authors(
             )
             .await?;
 
-            let Node::Article(Article { content, .. }) = node else {
+            let Node::Article(Article { mut content, .. }) = node else {
                 bail!("Expected content to be decoded to an article")
             };
 
+            // Record the sources cited by the model as structured citations
+            content.extend(citations_block(&citations));
+
             content
         }
         ModelOutputKind::Url => {
@@ -181,11 +187,15 @@ pub(super) async fn model_task_to_blocks_and_authors(
                     ..Default::default()
                 })
             } else if format.is_image() {
-                Inline::ImageObject(ImageObject {
+                let mut image = ImageObject {
                     content_url,
                     media_type,
                     ..Default::default()
-                })
+                };
+                if let Some(generation) = &image_generation {
+                    generation.apply_to(&mut image);
+                }
+                Inline::ImageObject(image)
             } else if format.is_video() {
                 Inline::VideoObject(VideoObject {
                     content_url,
@@ -28,12 +28,16 @@ pub enum NodeProperty {
     AvailableLanguages,
     Bitrate,
     Brands,
+    ByteRange,
+    CachedTokens,
+    CallId,
     Caption,
     CellType,
     Cells,
     CharacterCount,
     CharacterPercent,
     Chars,
+    Checksum,
     CitationIntent,
     CitationMode,
     CitationPrefix,
@@ -59,6 +63,7 @@ pub enum NodeProperty {
     ContentSize,
     ContentUrl,
     Contributors,
+    Cost,
     CostWeight,
     Css,
     Date,
@@ -91,6 +96,7 @@ pub enum NodeProperty {
     EmbedUrl,
     EndColumn,
     EndLine,
+    EndTime,
     ErrorType,
     ExclusiveMaximum,
     ExclusiveMinimum,
@@ -121,6 +127,7 @@ pub enum NodeProperty {
     FundedBy,
     FundedItems,
     Funders,
+    GeneratedBy,
     Genre,
     Ghost,
     GivenNames,
@@ -187,8 +194,11 @@ pub enum NodeProperty {
     MinLength,
     Minimum,
     MinimumScore,
+    ModelId,
     ModelIds,
+    ModelInvocation,
     ModelParameters,
+    ModelVersion,
     Models,
     MultipleOf,
     Name,
@@ -207,9 +217,12 @@ pub enum NodeProperty {
     Order,
     Otherwise,
     Output,
+    OutputHash,
+    OutputTokens,
     Outputs,
     PageEnd,
     PageStart,
+    Pages,
     Pagination,
     Parameters,
     ParentItem,
@@ -223,9 +236,11 @@ pub enum NodeProperty {
     ProductId,
     ProgrammingLanguage,
     Prompt,
+    PromptTokens,
     PropertyId,
     Provenance,
     ProvenanceCategory,
+    Provider,
     Publish,
     Publisher,
     QualityWeight,
@@ -251,16 +266,20 @@ pub enum NodeProperty {
     SectionType,
     Select,
     SemanticDigest,
+    Sheet,
     Size,
+    SizeBytes,
     Slug,
     SoftwareRequirements,
     SoftwareVersion,
     Source,
+    SourceUrl,
     SpeedWeight,
     Sponsors,
     StackTrace,
     StartColumn,
     StartLine,
+    StartTime,
     State,
     StateDigest,
     Steps,
@@ -273,6 +292,7 @@ pub enum NodeProperty {
     Target,
     TargetNodes,
     TargetProducts,
+    TaskFingerprint,
     TelephoneNumbers,
     Temperature,
     TermCode,
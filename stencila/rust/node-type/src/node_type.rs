@@ -94,6 +94,7 @@ pub enum NodeType {
     MathBlock,
     MathInline,
     MediaObject,
+    ModelInvocation,
     ModelParameters,
     MonetaryGrant,
     Note,
@@ -141,6 +142,7 @@ pub enum NodeType {
     TimeValidator,
     Timestamp,
     TimestampValidator,
+    ToolResult,
     TupleValidator,
     Underline,
     Unknown,
@@ -267,6 +269,7 @@ impl TryFrom<&NodeId> for NodeType {
             "mtb" => MathBlock,
             "mti" => MathInline,
             "med" => MediaObject,
+            "mdi" => ModelInvocation,
             "mdp" => ModelParameters,
             "mon" => MonetaryGrant,
             "not" => Note,
@@ -318,6 +321,7 @@ impl TryFrom<&NodeId> for NodeType {
             "tmv" => TimeValidator,
             "tst" => Timestamp,
             "tsv" => TimestampValidator,
+            "tlr" => ToolResult,
             "tuv" => TupleValidator,
             "und" => Underline,
             "unk" => Unknown,
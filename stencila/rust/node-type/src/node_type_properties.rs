@@ -68,10 +68,10 @@ pub(crate) fn node_type_properties(node_type: &NodeType) -> Vec<NodeProperty> {
         NodeType::ImageObject => vec![NodeProperty::Id, NodeProperty::AlternateNames, NodeProperty::Description, NodeProperty::Identifiers, NodeProperty::Images, NodeProperty::Name, NodeProperty::Url, NodeProperty::WorkType, NodeProperty::Doi, NodeProperty::About, NodeProperty::Abstract, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::Contributors, NodeProperty::Editors, NodeProperty::Maintainers, NodeProperty::Comments, NodeProperty::DateCreated, NodeProperty::DateReceived, NodeProperty::DateAccepted, NodeProperty::DateModified, NodeProperty::DatePublished, NodeProperty::Funders, NodeProperty::FundedBy, NodeProperty::Genre, NodeProperty::Keywords, NodeProperty::IsPartOf, NodeProperty::Licenses, NodeProperty::Parts, NodeProperty::Publisher, NodeProperty::References, NodeProperty::Text, NodeProperty::Title, NodeProperty::Repository, NodeProperty::Path, NodeProperty::Commit, NodeProperty::Version, NodeProperty::Bitrate, NodeProperty::ContentSize, NodeProperty::ContentUrl, NodeProperty::EmbedUrl, NodeProperty::MediaType, NodeProperty::Caption, NodeProperty::Thumbnail],
         NodeType::IncludeBlock => vec![NodeProperty::Id, NodeProperty::ExecutionMode, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::ExecutionDigest, NodeProperty::ExecutionDependencies, NodeProperty::ExecutionDependants, NodeProperty::ExecutionTags, NodeProperty::ExecutionCount, NodeProperty::ExecutionRequired, NodeProperty::ExecutionStatus, NodeProperty::ExecutionInstance, NodeProperty::ExecutionEnded, NodeProperty::ExecutionDuration, NodeProperty::ExecutionMessages, NodeProperty::Source, NodeProperty::MediaType, NodeProperty::Select, NodeProperty::Content],
         NodeType::InlinesBlock => vec![NodeProperty::Id, NodeProperty::Content],
-        NodeType::InstructionAttachment => vec![NodeProperty::Id, NodeProperty::Alias, NodeProperty::File],
+        NodeType::InstructionAttachment => vec![NodeProperty::Id, NodeProperty::Alias, NodeProperty::File, NodeProperty::Description, NodeProperty::Pages, NodeProperty::ByteRange, NodeProperty::Sheet, NodeProperty::Checksum, NodeProperty::SizeBytes, NodeProperty::SourceUrl, NodeProperty::GeneratedBy],
         NodeType::InstructionBlock => vec![NodeProperty::Id, NodeProperty::ExecutionMode, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::ExecutionDigest, NodeProperty::ExecutionDependencies, NodeProperty::ExecutionDependants, NodeProperty::ExecutionTags, NodeProperty::ExecutionCount, NodeProperty::ExecutionRequired, NodeProperty::ExecutionStatus, NodeProperty::ExecutionInstance, NodeProperty::ExecutionEnded, NodeProperty::ExecutionDuration, NodeProperty::ExecutionMessages, NodeProperty::InstructionType, NodeProperty::Prompt, NodeProperty::Message, NodeProperty::ModelParameters, NodeProperty::ActiveSuggestion, NodeProperty::Content, NodeProperty::Suggestions, NodeProperty::Attachments],
         NodeType::InstructionInline => vec![NodeProperty::Id, NodeProperty::ExecutionMode, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::ExecutionDigest, NodeProperty::ExecutionDependencies, NodeProperty::ExecutionDependants, NodeProperty::ExecutionTags, NodeProperty::ExecutionCount, NodeProperty::ExecutionRequired, NodeProperty::ExecutionStatus, NodeProperty::ExecutionInstance, NodeProperty::ExecutionEnded, NodeProperty::ExecutionDuration, NodeProperty::ExecutionMessages, NodeProperty::InstructionType, NodeProperty::Prompt, NodeProperty::Message, NodeProperty::ModelParameters, NodeProperty::ActiveSuggestion, NodeProperty::Content, NodeProperty::Suggestions],
-        NodeType::InstructionMessage => vec![NodeProperty::Id, NodeProperty::Role, NodeProperty::Parts, NodeProperty::Authors, NodeProperty::Provenance],
+        NodeType::InstructionMessage => vec![NodeProperty::Id, NodeProperty::Role, NodeProperty::Name, NodeProperty::Parts, NodeProperty::Authors, NodeProperty::Provenance],
         NodeType::IntegerValidator => vec![NodeProperty::Id, NodeProperty::Minimum, NodeProperty::ExclusiveMinimum, NodeProperty::Maximum, NodeProperty::ExclusiveMaximum, NodeProperty::MultipleOf],
         NodeType::Island => vec![NodeProperty::Id, NodeProperty::Content, NodeProperty::IsAutomatic, NodeProperty::LabelType, NodeProperty::Label, NodeProperty::LabelAutomatically, NodeProperty::Style],
         NodeType::Link => vec![NodeProperty::Id, NodeProperty::Content, NodeProperty::Target, NodeProperty::Title, NodeProperty::Rel, NodeProperty::LabelOnly, NodeProperty::CompilationMessages],
@@ -80,6 +80,7 @@ pub(crate) fn node_type_properties(node_type: &NodeType) -> Vec<NodeProperty> {
         NodeType::MathBlock => vec![NodeProperty::Id, NodeProperty::Code, NodeProperty::MathLanguage, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::Mathml, NodeProperty::Images, NodeProperty::Label, NodeProperty::LabelAutomatically],
         NodeType::MathInline => vec![NodeProperty::Id, NodeProperty::Code, NodeProperty::MathLanguage, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::Mathml, NodeProperty::Images],
         NodeType::MediaObject => vec![NodeProperty::Id, NodeProperty::AlternateNames, NodeProperty::Description, NodeProperty::Identifiers, NodeProperty::Images, NodeProperty::Name, NodeProperty::Url, NodeProperty::WorkType, NodeProperty::Doi, NodeProperty::About, NodeProperty::Abstract, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::Contributors, NodeProperty::Editors, NodeProperty::Maintainers, NodeProperty::Comments, NodeProperty::DateCreated, NodeProperty::DateReceived, NodeProperty::DateAccepted, NodeProperty::DateModified, NodeProperty::DatePublished, NodeProperty::Funders, NodeProperty::FundedBy, NodeProperty::Genre, NodeProperty::Keywords, NodeProperty::IsPartOf, NodeProperty::Licenses, NodeProperty::Parts, NodeProperty::Publisher, NodeProperty::References, NodeProperty::Text, NodeProperty::Title, NodeProperty::Repository, NodeProperty::Path, NodeProperty::Commit, NodeProperty::Version, NodeProperty::Bitrate, NodeProperty::ContentSize, NodeProperty::ContentUrl, NodeProperty::EmbedUrl, NodeProperty::MediaType],
+        NodeType::ModelInvocation => vec![NodeProperty::Id, NodeProperty::ModelId, NodeProperty::ModelVersion, NodeProperty::Provider, NodeProperty::ModelParameters, NodeProperty::PromptTokens, NodeProperty::OutputTokens, NodeProperty::CachedTokens, NodeProperty::Cost, NodeProperty::StartTime, NodeProperty::EndTime, NodeProperty::TaskFingerprint, NodeProperty::OutputHash],
         NodeType::ModelParameters => vec![NodeProperty::Id, NodeProperty::ModelIds, NodeProperty::Replicates, NodeProperty::QualityWeight, NodeProperty::CostWeight, NodeProperty::SpeedWeight, NodeProperty::MinimumScore, NodeProperty::Temperature, NodeProperty::RandomSeed, NodeProperty::ExecuteContent, NodeProperty::ExecutionBounds, NodeProperty::MaximumRetries],
         NodeType::MonetaryGrant => vec![NodeProperty::Id, NodeProperty::AlternateNames, NodeProperty::Description, NodeProperty::Identifiers, NodeProperty::Images, NodeProperty::Name, NodeProperty::Url, NodeProperty::FundedItems, NodeProperty::Sponsors, NodeProperty::Amounts, NodeProperty::Funders],
         NodeType::Note => vec![NodeProperty::Id, NodeProperty::NoteType, NodeProperty::Content],
@@ -114,8 +115,8 @@ pub(crate) fn node_type_properties(node_type: &NodeType) -> Vec<NodeProperty> {
         NodeType::StyledBlock => vec![NodeProperty::Id, NodeProperty::Code, NodeProperty::StyleLanguage, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::Css, NodeProperty::ClassList, NodeProperty::Content],
         NodeType::StyledInline => vec![NodeProperty::Id, NodeProperty::Code, NodeProperty::StyleLanguage, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::CompilationDigest, NodeProperty::CompilationMessages, NodeProperty::Css, NodeProperty::ClassList, NodeProperty::Content],
         NodeType::Subscript => vec![NodeProperty::Id, NodeProperty::Content],
-        NodeType::SuggestionBlock => vec![NodeProperty::Id, NodeProperty::SuggestionStatus, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::ExecutionDuration, NodeProperty::ExecutionEnded, NodeProperty::Feedback, NodeProperty::Content],
-        NodeType::SuggestionInline => vec![NodeProperty::Id, NodeProperty::SuggestionStatus, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::ExecutionDuration, NodeProperty::ExecutionEnded, NodeProperty::Feedback, NodeProperty::Content],
+        NodeType::SuggestionBlock => vec![NodeProperty::Id, NodeProperty::SuggestionStatus, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::ExecutionDuration, NodeProperty::ExecutionEnded, NodeProperty::ModelInvocation, NodeProperty::Feedback, NodeProperty::Content],
+        NodeType::SuggestionInline => vec![NodeProperty::Id, NodeProperty::SuggestionStatus, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::ExecutionDuration, NodeProperty::ExecutionEnded, NodeProperty::ModelInvocation, NodeProperty::Feedback, NodeProperty::Content],
         NodeType::Superscript => vec![NodeProperty::Id, NodeProperty::Content],
         NodeType::Table => vec![NodeProperty::Id, NodeProperty::AlternateNames, NodeProperty::Description, NodeProperty::Identifiers, NodeProperty::Images, NodeProperty::Name, NodeProperty::Url, NodeProperty::WorkType, NodeProperty::Doi, NodeProperty::About, NodeProperty::Abstract, NodeProperty::Authors, NodeProperty::Provenance, NodeProperty::Contributors, NodeProperty::Editors, NodeProperty::Maintainers, NodeProperty::Comments, NodeProperty::DateCreated, NodeProperty::DateReceived, NodeProperty::DateAccepted, NodeProperty::DateModified, NodeProperty::DatePublished, NodeProperty::Funders, NodeProperty::FundedBy, NodeProperty::Genre, NodeProperty::Keywords, NodeProperty::IsPartOf, NodeProperty::Licenses, NodeProperty::Parts, NodeProperty::Publisher, NodeProperty::References, NodeProperty::Text, NodeProperty::Title, NodeProperty::Repository, NodeProperty::Path, NodeProperty::Commit, NodeProperty::Version, NodeProperty::Label, NodeProperty::LabelAutomatically, NodeProperty::Caption, NodeProperty::Rows, NodeProperty::Notes],
         NodeType::TableCell => vec![NodeProperty::Id, NodeProperty::CellType, NodeProperty::Name, NodeProperty::ColumnSpan, NodeProperty::RowSpan, NodeProperty::HorizontalAlignment, NodeProperty::HorizontalAlignmentCharacter, NodeProperty::VerticalAlignment, NodeProperty::Content],
@@ -127,6 +128,7 @@ pub(crate) fn node_type_properties(node_type: &NodeType) -> Vec<NodeProperty> {
         NodeType::TimeValidator => vec![NodeProperty::Id, NodeProperty::Minimum, NodeProperty::Maximum],
         NodeType::Timestamp => vec![NodeProperty::Id, NodeProperty::Value, NodeProperty::TimeUnit],
         NodeType::TimestampValidator => vec![NodeProperty::Id, NodeProperty::TimeUnits, NodeProperty::Minimum, NodeProperty::Maximum],
+        NodeType::ToolResult => vec![NodeProperty::Id, NodeProperty::CallId, NodeProperty::Name, NodeProperty::Content],
         NodeType::TupleValidator => vec![NodeProperty::Id, NodeProperty::Items],
         NodeType::Underline => vec![NodeProperty::Id, NodeProperty::Content],
         NodeType::Unknown => vec![NodeProperty::Id],
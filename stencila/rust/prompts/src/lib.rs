@@ -37,7 +37,7 @@ use images::ensure_http_or_data_uri;
 use rust_embed::RustEmbed;
 
 use model::{
-    ModelOutput, ModelOutputKind, ModelTask,
+    ModelOutput, ModelOutputKind, ModelTask, citations_block,
     schema::{
         Article, AudioObject, Author, AuthorRole, AuthorRoleAuthor, AuthorRoleName, Block,
         CompilationMessage, ExecutionMessage, ImageObject, Inline, InstructionBlock,
@@ -719,6 +719,10 @@ pub async fn execute_instruction_block(
         kind,
         format,
         content,
+        image_generation,
+        invocation,
+        citations,
+        ..
     } = models::perform_task(task).await?;
     if let Some(prompt) = prompt_text.as_ref() {
         annotate_generator_authors(&mut authors, prompt);
@@ -740,10 +744,13 @@ pub async fn execute_instruction_block(
             )
             .await?;
 
-            let Node::Article(Article { content, .. }) = node else {
+            let Node::Article(Article { mut content, .. }) = node else {
                 bail!("Expected content to be decoded to an article")
             };
 
+            // Record the sources cited by the model as structured citations
+            content.extend(citations_block(&citations));
+
             content
         }
         ModelOutputKind::Url => {
@@ -757,11 +764,15 @@ pub async fn execute_instruction_block(
                     ..Default::default()
                 })
             } else if format.is_image() {
-                Inline::ImageObject(ImageObject {
+                let mut image = ImageObject {
                     content_url,
                     media_type,
                     ..Default::default()
-                })
+                };
+                if let Some(generation) = &image_generation {
+                    generation.apply_to(&mut image);
+                }
+                Inline::ImageObject(image)
             } else if format.is_video() {
                 Inline::VideoObject(VideoObject {
                     content_url,
@@ -789,6 +800,7 @@ pub async fn execute_instruction_block(
         .expect("should use compatible timestamps");
     suggestion.execution_duration = Some(duration);
     suggestion.execution_ended = Some(ended);
+    suggestion.model_invocation = invocation;
 
     // Apply authorship to the suggestion.
     authors.append(&mut instructors);
@@ -192,6 +192,7 @@ impl Schemas {
             "InstructionInline",
             "InstructionMessage",
             "Island",
+            "ModelInvocation",
             "ModelParameters",
             "MonetaryGrant",
             "PostalAddress",
@@ -10,6 +10,7 @@ impl MarkdownCodec for ChatMessage {
                 match self.role {
                     MessageRole::User => " msg/user",
                     MessageRole::System => " msg/system",
+                    MessageRole::Developer => " msg/developer",
                     MessageRole::Model => " msg/model",
                 },
             );
@@ -92,6 +92,7 @@ impl Node {
             MathBlock,
             MathInline,
             MediaObject,
+            ModelInvocation,
             ModelParameters,
             MonetaryGrant,
             Note,
@@ -143,6 +144,7 @@ impl Node {
             Timestamp,
             TimestampValidator,
             TimeValidator,
+            ToolResult,
             TupleValidator,
             Underline,
             Unknown,
@@ -249,6 +251,7 @@ impl Node {
             MathBlock,
             MathInline,
             MediaObject,
+            ModelInvocation,
             ModelParameters,
             MonetaryGrant,
             Note,
@@ -296,6 +299,7 @@ impl Node {
             Timestamp,
             TimestampValidator,
             TimeValidator,
+            ToolResult,
             TupleValidator,
             Underline,
             Unknown,
@@ -445,6 +449,7 @@ impl ReadNode for Node {
             MathBlock,
             MathInline,
             MediaObject,
+            ModelInvocation,
             ModelParameters,
             MonetaryGrant,
             Note,
@@ -492,6 +497,7 @@ impl ReadNode for Node {
             Timestamp,
             TimestampValidator,
             TimeValidator,
+            ToolResult,
             TupleValidator,
             Underline,
             Unknown,
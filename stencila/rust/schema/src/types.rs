@@ -119,6 +119,7 @@ mod media_object;
 mod message_level;
 mod message_part;
 mod message_role;
+mod model_invocation;
 mod model_parameters;
 mod monetary_grant;
 mod node;
@@ -186,6 +187,7 @@ mod time_unit;
 mod time_validator;
 mod timestamp;
 mod timestamp_validator;
+mod tool_result;
 mod tuple_validator;
 mod underline;
 mod unknown;
@@ -318,6 +320,7 @@ pub use media_object::*;
 pub use message_level::*;
 pub use message_part::*;
 pub use message_role::*;
+pub use model_invocation::*;
 pub use model_parameters::*;
 pub use monetary_grant::*;
 pub use node::*;
@@ -385,6 +388,7 @@ pub use time_unit::*;
 pub use time_validator::*;
 pub use timestamp::*;
 pub use timestamp_validator::*;
+pub use tool_result::*;
 pub use tuple_validator::*;
 pub use underline::*;
 pub use unknown::*;
@@ -4,6 +4,7 @@ use crate::prelude::*;
 
 use super::file::File;
 use super::string::String;
+use super::unsigned_integer::UnsignedInteger;
 
 /// An attachment that can be provided to an instruction for model context.
 #[skip_serializing_none]
@@ -28,11 +29,51 @@ pub struct InstructionAttachment {
     #[walk]
     pub file: File,
 
+    /// Non-core optional fields
+    #[serde(flatten)]
+    #[html(flatten)]
+    #[jats(flatten)]
+    pub options: Box<InstructionAttachmentOptions>,
+
     /// A unique identifier for a node within a document
     #[serde(skip)]
     pub uid: NodeUid
 }
 
+#[skip_serializing_none]
+#[serde_as]
+#[derive(Debug, SmartDefault, Clone, PartialEq, Serialize, Deserialize, ProbeNode, StripNode, WalkNode, WriteNode, ReadNode, PatchNode, DomCodec, HtmlCodec, JatsCodec, LatexCodec, MarkdownCodec, TextCodec)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+pub struct InstructionAttachmentOptions {
+    /// A description of the attachment, provided by the user, to help the model understand it.
+    pub description: Option<String>,
+
+    /// The pages of a PDF attachment to include e.g. `3-5,9`.
+    pub pages: Option<String>,
+
+    /// The range of bytes of the attachment to include e.g. `0-4095`.
+    #[serde(alias = "byte-range", alias = "byte_range")]
+    pub byte_range: Option<String>,
+
+    /// The sheet of a spreadsheet attachment to include, by name or number.
+    pub sheet: Option<String>,
+
+    /// The SHA-256 hash of the content of the attachment provided to the model, hex encoded.
+    pub checksum: Option<String>,
+
+    /// The size, in bytes, of the content of the attachment provided to the model.
+    #[serde(alias = "size-bytes", alias = "size_bytes")]
+    pub size_bytes: Option<UnsignedInteger>,
+
+    /// The URL that the file of the attachment was retrieved from.
+    #[serde(alias = "source-url", alias = "source_url")]
+    pub source_url: Option<String>,
+
+    /// The workflow, or step of a workflow, which generated the file of the attachment.
+    #[serde(alias = "generated-by", alias = "generated_by")]
+    pub generated_by: Option<String>,
+}
+
 impl InstructionAttachment {
     const NICK: [u8; 3] = *b"iat";
     
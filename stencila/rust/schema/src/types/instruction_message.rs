@@ -28,6 +28,9 @@ pub struct InstructionMessage {
     /// The role of the message in the conversation.
     pub role: Option<MessageRole>,
 
+    /// The name of the participant who authored the message.
+    pub name: Option<String>,
+
     /// Parts of the message.
     #[serde(alias = "part")]
     #[serde(default)]
@@ -3,8 +3,10 @@
 use crate::prelude::*;
 
 use super::audio_object::AudioObject;
+use super::file::File;
 use super::image_object::ImageObject;
 use super::text::Text;
+use super::tool_result::ToolResult;
 use super::video_object::VideoObject;
 
 /// A union type for a part of a message.
@@ -19,4 +21,8 @@ pub enum MessagePart {
     AudioObject(AudioObject),
 
     VideoObject(VideoObject),
+
+    ToolResult(ToolResult),
+
+    File(File),
 }
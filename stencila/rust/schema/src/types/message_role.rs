@@ -10,6 +10,9 @@ pub enum MessageRole {
     /// A system message
     System,
 
+    /// A message from the developer of the application, providing platform guidance
+    Developer,
+
     /// A user message
     #[default]
     User,
@@ -0,0 +1,95 @@
+// Generated file; do not edit. See `schema-gen` crate.
+
+use crate::prelude::*;
+
+use super::model_parameters::ModelParameters;
+use super::number::Number;
+use super::string::String;
+use super::timestamp::Timestamp;
+use super::unsigned_integer::UnsignedInteger;
+
+/// A record of the invocation of a generative model to create content.
+#[skip_serializing_none]
+#[serde_as]
+#[derive(Debug, SmartDefault, Clone, PartialEq, Serialize, Deserialize, ProbeNode, StripNode, WalkNode, WriteNode, ReadNode, PatchNode, DomCodec, HtmlCodec, JatsCodec, LatexCodec, MarkdownCodec, TextCodec)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+#[derive(derive_more::Display)]
+#[display("ModelInvocation")]
+pub struct ModelInvocation {
+    /// The type of this item.
+    pub r#type: MustBe!("ModelInvocation"),
+
+    /// The identifier for this item.
+    #[strip(metadata)]
+    #[html(attr = "id")]
+    pub id: Option<String>,
+
+    /// The id of the model that was invoked e.g. `openai/gpt-4o`.
+    #[serde(alias = "model-id", alias = "model_id")]
+    pub model_id: String,
+
+    /// The version of the model reported by the provider e.g. `gpt-4o-2024-08-06`.
+    #[serde(alias = "model-version", alias = "model_version")]
+    pub model_version: Option<String>,
+
+    /// The provider of the model e.g. `OpenAI`.
+    pub provider: Option<String>,
+
+    /// The parameters the model was invoked with.
+    #[serde(alias = "model-parameters", alias = "model_parameters")]
+    pub model_parameters: Option<ModelParameters>,
+
+    /// The number of tokens in the prompt sent to the model.
+    #[serde(alias = "prompt-tokens", alias = "prompt_tokens")]
+    pub prompt_tokens: Option<UnsignedInteger>,
+
+    /// The number of tokens in the output generated by the model.
+    #[serde(alias = "output-tokens", alias = "output_tokens")]
+    pub output_tokens: Option<UnsignedInteger>,
+
+    /// The number of prompt tokens read from the provider's prompt cache.
+    #[serde(alias = "cached-tokens", alias = "cached_tokens")]
+    pub cached_tokens: Option<UnsignedInteger>,
+
+    /// The estimated cost of the invocation in US dollars.
+    pub cost: Option<Number>,
+
+    /// The time the invocation started.
+    #[serde(alias = "start-time", alias = "start_time")]
+    pub start_time: Option<Timestamp>,
+
+    /// The time the invocation ended.
+    #[serde(alias = "end-time", alias = "end_time")]
+    pub end_time: Option<Timestamp>,
+
+    /// The fingerprint of the task the model was invoked for.
+    #[serde(alias = "task-fingerprint", alias = "task_fingerprint")]
+    pub task_fingerprint: Option<String>,
+
+    /// The SHA-256 hash of the content generated by the model.
+    #[serde(alias = "output-hash", alias = "output_hash")]
+    pub output_hash: Option<String>,
+
+    /// A unique identifier for a node within a document
+    #[serde(skip)]
+    pub uid: NodeUid
+}
+
+impl ModelInvocation {
+    const NICK: [u8; 3] = *b"mdi";
+    
+    pub fn node_type(&self) -> NodeType {
+        NodeType::ModelInvocation
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId::new(&Self::NICK, &self.uid)
+    }
+    
+    pub fn new(model_id: String) -> Self {
+        Self {
+            model_id,
+            ..Default::default()
+        }
+    }
+}
@@ -81,6 +81,7 @@ use super::list_item::ListItem;
 use super::math_block::MathBlock;
 use super::math_inline::MathInline;
 use super::media_object::MediaObject;
+use super::model_invocation::ModelInvocation;
 use super::model_parameters::ModelParameters;
 use super::monetary_grant::MonetaryGrant;
 use super::note::Note;
@@ -132,6 +133,7 @@ use super::time::Time;
 use super::time_validator::TimeValidator;
 use super::timestamp::Timestamp;
 use super::timestamp_validator::TimestampValidator;
+use super::tool_result::ToolResult;
 use super::tuple_validator::TupleValidator;
 use super::underline::Underline;
 use super::unknown::Unknown;
@@ -310,6 +312,8 @@ pub enum Node {
 
     MediaObject(MediaObject),
 
+    ModelInvocation(ModelInvocation),
+
     ModelParameters(ModelParameters),
 
     MonetaryGrant(MonetaryGrant),
@@ -404,6 +408,8 @@ pub enum Node {
 
     TimestampValidator(TimestampValidator),
 
+    ToolResult(ToolResult),
+
     TupleValidator(TupleValidator),
 
     Underline(Underline),
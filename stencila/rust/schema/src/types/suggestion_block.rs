@@ -5,6 +5,7 @@ use crate::prelude::*;
 use super::author::Author;
 use super::block::Block;
 use super::duration::Duration;
+use super::model_invocation::ModelInvocation;
 use super::provenance_count::ProvenanceCount;
 use super::string::String;
 use super::suggestion_status::SuggestionStatus;
@@ -58,6 +59,11 @@ pub struct SuggestionBlock {
     #[dom(with = "Timestamp::to_dom_attr")]
     pub execution_ended: Option<Timestamp>,
 
+    /// The invocation of the model which generated the suggestion.
+    #[serde(alias = "model-invocation", alias = "model_invocation")]
+    #[strip(provenance)]
+    pub model_invocation: Option<ModelInvocation>,
+
     /// Feedback on the suggestion
     #[patch(format = "md", format = "smd", format = "myst", format = "ipynb", format = "qmd")]
     pub feedback: Option<String>,
@@ -0,0 +1,58 @@
+// Generated file; do not edit. See `schema-gen` crate.
+
+use crate::prelude::*;
+
+use super::string::String;
+
+/// The result of a call to a tool made by a model.
+#[skip_serializing_none]
+#[serde_as]
+#[derive(Debug, SmartDefault, Clone, PartialEq, Serialize, Deserialize, ProbeNode, StripNode, WalkNode, WriteNode, ReadNode, PatchNode, DomCodec, HtmlCodec, JatsCodec, LatexCodec, MarkdownCodec, TextCodec)]
+#[serde(rename_all = "camelCase", crate = "common::serde")]
+#[derive(derive_more::Display)]
+#[display("ToolResult")]
+pub struct ToolResult {
+    /// The type of this item.
+    pub r#type: MustBe!("ToolResult"),
+
+    /// The identifier for this item.
+    #[strip(metadata)]
+    #[html(attr = "id")]
+    pub id: Option<String>,
+
+    /// The identifier of the tool call that this is the result of.
+    #[serde(alias = "call-id", alias = "call_id")]
+    pub call_id: String,
+
+    /// The name of the tool that was called.
+    pub name: Option<String>,
+
+    /// The content of the result.
+    #[walk]
+    #[patch(format = "all")]
+    pub content: String,
+
+    /// A unique identifier for a node within a document
+    #[serde(skip)]
+    pub uid: NodeUid
+}
+
+impl ToolResult {
+    const NICK: [u8; 3] = *b"tlr";
+    
+    pub fn node_type(&self) -> NodeType {
+        NodeType::ToolResult
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId::new(&Self::NICK, &self.uid)
+    }
+    
+    pub fn new(call_id: String, content: String) -> Self {
+        Self {
+            call_id,
+            content,
+            ..Default::default()
+        }
+    }
+}
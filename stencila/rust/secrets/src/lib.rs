@@ -67,6 +67,13 @@ pub const GOOGLE_AI_API_KEY: &str = "GOOGLE_AI_API_KEY";
 pub const GITHUB_TOKEN: &str = "GITHUB_TOKEN";
 pub const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
 pub const MISTRAL_API_KEY: &str = "MISTRAL_API_KEY";
+pub const OPENROUTER_API_KEY: &str = "OPENROUTER_API_KEY";
+pub const AWS_ACCESS_KEY_ID: &str = "AWS_ACCESS_KEY_ID";
+pub const AWS_SECRET_ACCESS_KEY: &str = "AWS_SECRET_ACCESS_KEY";
+pub const HF_TOKEN: &str = "HF_TOKEN";
+pub const XAI_API_KEY: &str = "XAI_API_KEY";
+pub const DEEPSEEK_API_KEY: &str = "DEEPSEEK_API_KEY";
+pub const TOGETHER_API_KEY: &str = "TOGETHER_API_KEY";
 pub const GHOST_ADMIN_API_KEY: &str = "GHOST_ADMIN_API_KEY";
 
 /// A list of secrets used by Stencila
@@ -108,6 +115,48 @@ static SECRETS: Lazy<Vec<Secret>> = Lazy::new(|| {
             "Mistral API Key",
             "Used to access the Mistral API",
         ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            OPENROUTER_API_KEY,
+            "OpenRouter API Key",
+            "Used to access models from many providers via the OpenRouter API",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            AWS_ACCESS_KEY_ID,
+            "AWS Access Key Id",
+            "Used, with the secret access key, to access models on AWS Bedrock",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            AWS_SECRET_ACCESS_KEY,
+            "AWS Secret Access Key",
+            "Used, with the access key id, to access models on AWS Bedrock",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            HF_TOKEN,
+            "Hugging Face Access Token",
+            "Used to access the Hugging Face Inference API",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            XAI_API_KEY,
+            "xAI API Key",
+            "Used to access Grok models via the xAI API",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            DEEPSEEK_API_KEY,
+            "DeepSeek API Key",
+            "Used to access the DeepSeek API",
+        ),
+        Secret::new(
+            SecretCategory::AiApiKey,
+            TOGETHER_API_KEY,
+            "Together AI API Key",
+            "Used to access open models hosted by Together AI",
+        ),
         Secret::new(
             SecretCategory::ReadWriteApiKey,
             GHOST_ADMIN_API_KEY,
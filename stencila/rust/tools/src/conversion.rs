@@ -31,6 +31,34 @@ impl Tool for Agg {
     }
 }
 
+pub struct Ffmpeg;
+
+impl Tool for Ffmpeg {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn url(&self) -> &'static str {
+        "https://ffmpeg.org/"
+    }
+
+    fn description(&self) -> &'static str {
+        "A tool for converting audio and video, and extracting frames from video"
+    }
+
+    fn r#type(&self) -> ToolType {
+        ToolType::Conversion
+    }
+
+    fn version_command(&self) -> Vec<&'static str> {
+        vec!["-version"]
+    }
+
+    fn installation_tools(&self) -> Vec<Box<dyn Tool>> {
+        vec![Box::new(Mise), Box::new(Devbox), Box::new(Apt)]
+    }
+}
+
 pub struct MarkerPdf;
 
 impl Tool for MarkerPdf {
@@ -122,6 +150,30 @@ impl Tool for Pandoc {
     }
 }
 
+pub struct Qpdf;
+
+impl Tool for Qpdf {
+    fn name(&self) -> &'static str {
+        "qpdf"
+    }
+
+    fn url(&self) -> &'static str {
+        "https://qpdf.readthedocs.io/"
+    }
+
+    fn description(&self) -> &'static str {
+        "A tool for transforming PDF files, including selecting pages from them"
+    }
+
+    fn r#type(&self) -> ToolType {
+        ToolType::Conversion
+    }
+
+    fn installation_tools(&self) -> Vec<Box<dyn Tool>> {
+        vec![Box::new(Devbox), Box::new(Apt)]
+    }
+}
+
 pub struct Xelatex;
 
 impl Tool for Xelatex {
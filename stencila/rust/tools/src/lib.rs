@@ -58,9 +58,11 @@ pub fn list() -> Vec<Box<dyn Tool>> {
         Box::new(LintR),
         // Conversion
         Box::new(Agg),
+        Box::new(Ffmpeg),
         Box::new(MarkerPdf),
         Box::new(MinerU),
         Box::new(Pandoc),
+        Box::new(Qpdf),
         Box::new(Xelatex),
         // Collaboration
         Box::new(Git),
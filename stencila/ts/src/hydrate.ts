@@ -209,6 +209,8 @@ export function hydrate(value: types.Node): types.Node {
       return Object.setPrototypeOf(value, types.MathInline.prototype);
     case "MediaObject":
       return Object.setPrototypeOf(value, types.MediaObject.prototype);
+    case "ModelInvocation":
+      return Object.setPrototypeOf(value, types.ModelInvocation.prototype);
     case "ModelParameters":
       return Object.setPrototypeOf(value, types.ModelParameters.prototype);
     case "MonetaryGrant":
@@ -309,6 +311,8 @@ export function hydrate(value: types.Node): types.Node {
       return Object.setPrototypeOf(value, types.Timestamp.prototype);
     case "TimestampValidator":
       return Object.setPrototypeOf(value, types.TimestampValidator.prototype);
+    case "ToolResult":
+      return Object.setPrototypeOf(value, types.ToolResult.prototype);
     case "TupleValidator":
       return Object.setPrototypeOf(value, types.TupleValidator.prototype);
     case "Underline":
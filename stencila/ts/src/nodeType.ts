@@ -83,6 +83,7 @@ export type NodeType =
   | "MathBlock"
   | "MathInline"
   | "MediaObject"
+  | "ModelInvocation"
   | "ModelParameters"
   | "MonetaryGrant"
   | "Note"
@@ -130,6 +131,7 @@ export type NodeType =
   | "TimeValidator"
   | "Timestamp"
   | "TimestampValidator"
+  | "ToolResult"
   | "TupleValidator"
   | "Underline"
   | "Unknown"
@@ -223,6 +225,7 @@ export const NodeTypeList = [
   "MathBlock",
   "MathInline",
   "MediaObject",
+  "ModelInvocation",
   "ModelParameters",
   "MonetaryGrant",
   "Note",
@@ -270,6 +273,7 @@ export const NodeTypeList = [
   "TimeValidator",
   "Timestamp",
   "TimestampValidator",
+  "ToolResult",
   "TupleValidator",
   "Underline",
   "Unknown",
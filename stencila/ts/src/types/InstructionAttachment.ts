@@ -2,6 +2,7 @@
 
 import { Entity } from "./Entity.js";
 import { File } from "./File.js";
+import { UnsignedInteger } from "./UnsignedInteger.js";
 
 /**
  * An attachment that can be provided to an instruction for model context.
@@ -20,6 +21,46 @@ export class InstructionAttachment extends Entity {
    */
   file: File;
 
+  /**
+   * A description of the attachment, provided by the user, to help the model understand it.
+   */
+  description?: string;
+
+  /**
+   * The pages of a PDF attachment to include e.g. `3-5,9`.
+   */
+  pages?: string;
+
+  /**
+   * The range of bytes of the attachment to include e.g. `0-4095`.
+   */
+  byteRange?: string;
+
+  /**
+   * The sheet of a spreadsheet attachment to include, by name or number.
+   */
+  sheet?: string;
+
+  /**
+   * The SHA-256 hash of the content of the attachment provided to the model, hex encoded.
+   */
+  checksum?: string;
+
+  /**
+   * The size, in bytes, of the content of the attachment provided to the model.
+   */
+  sizeBytes?: UnsignedInteger;
+
+  /**
+   * The URL that the file of the attachment was retrieved from.
+   */
+  sourceUrl?: string;
+
+  /**
+   * The workflow, or step of a workflow, which generated the file of the attachment.
+   */
+  generatedBy?: string;
+
   constructor(alias: string, file: File, options?: Partial<InstructionAttachment>) {
     super();
     this.type = "InstructionAttachment";
@@ -18,6 +18,11 @@ export class InstructionMessage extends Entity {
    */
   role?: MessageRole;
 
+  /**
+   * The name of the participant who authored the message.
+   */
+  name?: string;
+
   /**
    * Parts of the message.
    */
@@ -3,8 +3,10 @@
 import { hydrate } from "../hydrate.js";
 
 import { type AudioObject } from "./AudioObject.js";
+import { type File } from "./File.js";
 import { type ImageObject } from "./ImageObject.js";
 import { type Text } from "./Text.js";
+import { type ToolResult } from "./ToolResult.js";
 import { type VideoObject } from "./VideoObject.js";
 
 /**
@@ -14,7 +16,9 @@ export type MessagePart =
   Text |
   ImageObject |
   AudioObject |
-  VideoObject;
+  VideoObject |
+  ToolResult |
+  File;
 
 /**
  * Create a `MessagePart` from an object
@@ -25,6 +29,8 @@ export function messagePart(other: MessagePart): MessagePart {
     case "ImageObject":
     case "AudioObject":
     case "VideoObject":
+    case "ToolResult":
+    case "File":
       return hydrate(other) as MessagePart
     default:
       // @ts-expect-error that this can never happen because this function may be used in weakly-typed JavaScript
@@ -4,6 +4,7 @@
  */
 export type MessageRole =
   'System' |
+  'Developer' |
   'User' |
   'Model';
 
@@ -0,0 +1,88 @@
+// Generated file; do not edit. See https://github.com/stencila/stencila/tree/main/rust/schema-gen
+
+import { Entity } from "./Entity.js";
+import { ModelParameters } from "./ModelParameters.js";
+import { Timestamp } from "./Timestamp.js";
+import { UnsignedInteger } from "./UnsignedInteger.js";
+
+/**
+ * A record of the invocation of a generative model to create content.
+ */
+export class ModelInvocation extends Entity {
+  // @ts-expect-error 'not assignable to the same property in base type'
+  type: "ModelInvocation";
+
+  /**
+   * The id of the model that was invoked e.g. `openai/gpt-4o`.
+   */
+  modelId: string;
+
+  /**
+   * The version of the model reported by the provider e.g. `gpt-4o-2024-08-06`.
+   */
+  modelVersion?: string;
+
+  /**
+   * The provider of the model e.g. `OpenAI`.
+   */
+  provider?: string;
+
+  /**
+   * The parameters the model was invoked with.
+   */
+  modelParameters?: ModelParameters;
+
+  /**
+   * The number of tokens in the prompt sent to the model.
+   */
+  promptTokens?: UnsignedInteger;
+
+  /**
+   * The number of tokens in the output generated by the model.
+   */
+  outputTokens?: UnsignedInteger;
+
+  /**
+   * The number of prompt tokens read from the provider's prompt cache.
+   */
+  cachedTokens?: UnsignedInteger;
+
+  /**
+   * The estimated cost of the invocation in US dollars.
+   */
+  cost?: number;
+
+  /**
+   * The time the invocation started.
+   */
+  startTime?: Timestamp;
+
+  /**
+   * The time the invocation ended.
+   */
+  endTime?: Timestamp;
+
+  /**
+   * The fingerprint of the task the model was invoked for.
+   */
+  taskFingerprint?: string;
+
+  /**
+   * The SHA-256 hash of the content generated by the model.
+   */
+  outputHash?: string;
+
+  constructor(modelId: string, options?: Partial<ModelInvocation>) {
+    super();
+    this.type = "ModelInvocation";
+    if (options) Object.assign(this, options);
+    this.modelId = modelId;
+  }
+}
+
+/**
+* Create a new `ModelInvocation`
+*/
+export function modelInvocation(modelId: string, options?: Partial<ModelInvocation>): ModelInvocation {
+  return new ModelInvocation(modelId, options);
+}
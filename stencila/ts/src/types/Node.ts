@@ -80,6 +80,7 @@ import { type ListItem } from "./ListItem.js";
 import { type MathBlock } from "./MathBlock.js";
 import { type MathInline } from "./MathInline.js";
 import { type MediaObject } from "./MediaObject.js";
+import { type ModelInvocation } from "./ModelInvocation.js";
 import { type ModelParameters } from "./ModelParameters.js";
 import { type MonetaryGrant } from "./MonetaryGrant.js";
 import { type Note } from "./Note.js";
@@ -128,6 +129,7 @@ import { type Time } from "./Time.js";
 import { type TimeValidator } from "./TimeValidator.js";
 import { type Timestamp } from "./Timestamp.js";
 import { type TimestampValidator } from "./TimestampValidator.js";
+import { type ToolResult } from "./ToolResult.js";
 import { type TupleValidator } from "./TupleValidator.js";
 import { type Underline } from "./Underline.js";
 import { type Unknown } from "./Unknown.js";
@@ -223,6 +225,7 @@ export type Node =
   MathBlock |
   MathInline |
   MediaObject |
+  ModelInvocation |
   ModelParameters |
   MonetaryGrant |
   Note |
@@ -270,6 +273,7 @@ export type Node =
   TimeValidator |
   Timestamp |
   TimestampValidator |
+  ToolResult |
   TupleValidator |
   Underline |
   Unknown |
@@ -363,6 +367,7 @@ export function node(other: Node): Node {
     case "MathBlock":
     case "MathInline":
     case "MediaObject":
+    case "ModelInvocation":
     case "ModelParameters":
     case "MonetaryGrant":
     case "Note":
@@ -410,6 +415,7 @@ export function node(other: Node): Node {
     case "TimeValidator":
     case "Timestamp":
     case "TimestampValidator":
+    case "ToolResult":
     case "TupleValidator":
     case "Underline":
     case "Unknown":
@@ -3,6 +3,7 @@
 import { Author } from "./Author.js";
 import { Duration } from "./Duration.js";
 import { Entity } from "./Entity.js";
+import { ModelInvocation } from "./ModelInvocation.js";
 import { ProvenanceCount } from "./ProvenanceCount.js";
 import { SuggestionStatus } from "./SuggestionStatus.js";
 import { Timestamp } from "./Timestamp.js";
@@ -39,6 +40,11 @@ export class Suggestion extends Entity {
    */
   executionEnded?: Timestamp;
 
+  /**
+   * The invocation of the model which generated the suggestion.
+   */
+  modelInvocation?: ModelInvocation;
+
   /**
    * Feedback on the suggestion
    */
@@ -0,0 +1,41 @@
+// Generated file; do not edit. See https://github.com/stencila/stencila/tree/main/rust/schema-gen
+
+import { Entity } from "./Entity.js";
+
+/**
+ * The result of a call to a tool made by a model.
+ */
+export class ToolResult extends Entity {
+  // @ts-expect-error 'not assignable to the same property in base type'
+  type: "ToolResult";
+
+  /**
+   * The identifier of the tool call that this is the result of.
+   */
+  callId: string;
+
+  /**
+   * The name of the tool that was called.
+   */
+  name?: string;
+
+  /**
+   * The content of the result.
+   */
+  content: string;
+
+  constructor(callId: string, content: string, options?: Partial<ToolResult>) {
+    super();
+    this.type = "ToolResult";
+    if (options) Object.assign(this, options);
+    this.callId = callId;
+    this.content = content;
+  }
+}
+
+/**
+* Create a new `ToolResult`
+*/
+export function toolResult(callId: string, content: string, options?: Partial<ToolResult>): ToolResult {
+  return new ToolResult(callId, content, options);
+}
@@ -126,6 +126,7 @@ export * from "./MediaObject.js";
 export * from "./MessageLevel.js";
 export * from "./MessagePart.js";
 export * from "./MessageRole.js";
+export * from "./ModelInvocation.js";
 export * from "./ModelParameters.js";
 export * from "./MonetaryGrant.js";
 export * from "./Node.js";
@@ -193,6 +194,7 @@ export * from "./TimeUnit.js";
 export * from "./TimeValidator.js";
 export * from "./Timestamp.js";
 export * from "./TimestampValidator.js";
+export * from "./ToolResult.js";
 export * from "./TupleValidator.js";
 export * from "./Underline.js";
 export * from "./Unknown.js";
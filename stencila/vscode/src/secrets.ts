@@ -30,6 +30,13 @@ const SECRET_NAMES = [
   'GOOGLE_AI_API_KEY',
   'OPENAI_API_KEY',
   'MISTRAL_API_KEY',
+  'OPENROUTER_API_KEY',
+  'AWS_ACCESS_KEY_ID',
+  'AWS_SECRET_ACCESS_KEY',
+  'HF_TOKEN',
+  'XAI_API_KEY',
+  'DEEPSEEK_API_KEY',
+  'TOGETHER_API_KEY',
 ]
 
 export async function collectSecrets(